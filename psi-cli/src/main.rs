@@ -0,0 +1,101 @@
+//! `psi-cli`: command-line tools around the PSI protocol.
+//!
+//! `vectors generate` writes deterministic test vectors (seeded secrets,
+//! inputs, every message and the expected intersection) as JSON;
+//! `vectors verify` recomputes a vector file and reports every field that
+//! differs. Together they let CI pipelines of FFI bindings and other
+//! implementations check themselves against this crate.
+//!
+//! `serve` answers PSI sessions over TCP against a set loaded from a file,
+//! one item per line, for as many concurrent clients as `--max-connections`
+//! allows. Each session is logged as a JSON line on stderr.
+//!
+//! Run with:
+//! ```bash
+//! cargo run --bin psi-cli -- vectors generate --seed 1 --out vectors.json
+//! cargo run --bin psi-cli -- vectors verify vectors.json
+//! cargo run --release --bin psi-cli -- serve --listen 0.0.0.0:7878 --set items.txt --max-set-size 100000
+//! ```
+
+mod serve;
+mod vectors;
+
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+usage: psi-cli vectors generate [--seed <N>] [--out <FILE>]
+       psi-cli vectors verify <FILE>
+       psi-cli serve [--config <FILE>] --listen <ADDR> --set <FILE> [--max-set-size <N>]
+                     [--max-connections <N>] [--timeout <SECS>] [--domain <NAME>]";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let outcome = match args.as_slice() {
+        ["vectors", "generate", options @ ..] => generate(options),
+        ["vectors", "verify", file] => verify(file),
+        ["serve", options @ ..] => serve::ServeOptions::from_args(options)
+            .and_then(serve::run)
+            .map(|()| ExitCode::SUCCESS),
+        _ => Err(String::new()),
+    };
+    match outcome {
+        Ok(code) => code,
+        Err(error) if error.is_empty() => {
+            eprintln!("{}", USAGE);
+            ExitCode::from(2)
+        }
+        Err(error) => {
+            eprintln!("psi-cli: {}", error);
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn generate(options: &[&str]) -> Result<ExitCode, String> {
+    let mut seed = 0;
+    let mut out = None;
+    let mut options = options.iter();
+    while let Some(flag) = options.next() {
+        let value = options
+            .next()
+            .ok_or_else(|| format!("{} needs a value", flag))?;
+        match *flag {
+            "--seed" => {
+                seed = value
+                    .parse()
+                    .map_err(|_| format!("invalid --seed {}", value))?
+            }
+            "--out" => out = Some(*value),
+            other => return Err(format!("unknown argument {}", other)),
+        }
+    }
+
+    let file = vectors::generate(seed)?;
+    let json = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())? + "\n";
+    match out {
+        Some(path) => std::fs::write(path, json).map_err(|e| format!("{}: {}", path, e))?,
+        None => print!("{}", json),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn verify(path: &str) -> Result<ExitCode, String> {
+    let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let file: vectors::VectorFile =
+        serde_json::from_str(&json).map_err(|e| format!("{}: {}", path, e))?;
+    let mismatches = vectors::verify(&file)?;
+    for mismatch in &mismatches {
+        println!("{}", mismatch);
+    }
+    println!(
+        "{} vectors, {} mismatches",
+        file.vectors.len(),
+        mismatches.len()
+    );
+    Ok(if mismatches.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
@@ -0,0 +1,474 @@
+//! `serve`: a matching endpoint answering many clients over TCP.
+//!
+//! Every connection runs one session as the responder, with the stream
+//! framing of [`wire::write_frame`](psi_protocol::wire::write_frame) that
+//! `psi-syncd` uses too: the client sends its blinded points, the server
+//! answers with its own and its double-blinded points, and the client sends
+//! its double-blinded points last. A rejected message is answered with an
+//! error report and an abort frame.
+//!
+//! The set is blinded once at startup; each session starts from a
+//! rerandomized copy, so clients never see the same blinded points twice
+//! and the set is not rehashed per connection. Sessions waiting on the
+//! network live in a [`SessionManager`] and are dropped, secret included,
+//! once they run past `--timeout`. Every session is logged as one JSON line
+//! on stderr, with counts only, never items.
+
+use psi_protocol::wire::{
+    abort_on_error, read_blinded, read_double_blinded, write_frame, FrameError, WireMessage,
+};
+use psi_protocol::{
+    AbortMessage, AbortReason, PreparedState, PsiConfig, PsiError, PsiProtocol, PsiSession,
+    SessionManager,
+};
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use toml_edit::Document;
+
+/// Default number of sessions served at once.
+const DEFAULT_MAX_CONNECTIONS: usize = 64;
+
+/// Default time a session may take, connection included.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Keys of the `[serve]` table of a config file and the flags they stand for.
+const CONFIG_KEYS: &[(&str, &str)] = &[
+    ("listen", "--listen"),
+    ("set", "--set"),
+    ("max_set_size", "--max-set-size"),
+    ("max_connections", "--max-connections"),
+    ("timeout", "--timeout"),
+    ("domain", "--domain"),
+];
+
+/// Options of `psi-cli serve`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServeOptions {
+    pub listen: SocketAddr,
+    /// Item file, one item per line
+    pub set: PathBuf,
+    /// Largest client set accepted
+    pub max_set_size: Option<usize>,
+    pub max_connections: usize,
+    pub timeout: Duration,
+    pub domain: Option<String>,
+}
+
+impl ServeOptions {
+    /// Parse the options following `serve`.
+    ///
+    /// `--config <FILE>` reads the options from the `[serve]` table of a
+    /// TOML file, e.g. `listen = "0.0.0.0:7878"` or `max_set_size = 100000`;
+    /// flags override it.
+    pub fn from_args(args: &[&str]) -> Result<Self, String> {
+        let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        if let Some(at) = args.iter().position(|arg| arg == "--config") {
+            let path = args.get(at + 1).ok_or("--config needs a value")?.clone();
+            args.drain(at..at + 2);
+            let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+            let file_args = config_args(&text).map_err(|e| format!("{}: {}", path, e))?;
+            args.splice(0..0, file_args);
+        }
+
+        let mut listen = None;
+        let mut set = None;
+        let mut max_set_size = None;
+        let mut max_connections = DEFAULT_MAX_CONNECTIONS;
+        let mut timeout = DEFAULT_TIMEOUT;
+        let mut domain = None;
+
+        let mut args = args.iter().map(String::as_str);
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("{} needs a value", flag))?;
+            let number = || {
+                value
+                    .parse::<usize>()
+                    .map_err(|_| format!("{} expects a number, got {}", flag, value))
+            };
+            match flag {
+                "--listen" => {
+                    listen = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("invalid --listen address {}", value))?,
+                    )
+                }
+                "--set" => set = Some(PathBuf::from(value)),
+                "--max-set-size" => max_set_size = Some(number()?),
+                "--max-connections" => max_connections = number()?,
+                "--timeout" => timeout = Duration::from_secs(number()? as u64),
+                "--domain" => domain = Some(value.to_string()),
+                other => return Err(format!("unknown argument {}", other)),
+            }
+        }
+
+        if max_connections == 0 {
+            return Err("--max-connections must be at least 1".to_string());
+        }
+        if timeout.is_zero() {
+            return Err("--timeout must be at least 1".to_string());
+        }
+        Ok(Self {
+            listen: listen.ok_or("--listen is required")?,
+            set: set.ok_or("--set is required")?,
+            max_set_size,
+            max_connections,
+            timeout,
+            domain,
+        })
+    }
+
+    /// Protocol configuration of every session; clients must use the same
+    /// domain.
+    fn psi_config(&self) -> Result<PsiConfig, String> {
+        let mut builder = PsiConfig::builder();
+        if let Some(limit) = self.max_set_size {
+            builder = builder.max_remote_items(limit);
+        }
+        if let Some(domain) = &self.domain {
+            builder = builder.domain(domain.as_bytes());
+        }
+        builder.build().map_err(|e| e.to_string())
+    }
+}
+
+/// Turn the `[serve]` table of a config file into flags.
+fn config_args(text: &str) -> Result<Vec<String>, String> {
+    let document = Document::parse(text).map_err(|e| e.to_string())?;
+    let mut args = Vec::new();
+    for (key, item) in document.as_table().iter() {
+        match key {
+            "serve" => {}
+            "tls" => {
+                return Err("TLS is not built in; terminate it in front of the endpoint".to_string())
+            }
+            other => return Err(format!("unknown table {}", other)),
+        }
+        let table = item.as_table().ok_or("serve must be a table")?;
+        for (key, item) in table.iter() {
+            let (_, flag) = CONFIG_KEYS
+                .iter()
+                .find(|(name, _)| *name == key)
+                .ok_or_else(|| format!("unknown key serve.{}", key))?;
+            let value = match (item.as_str(), item.as_integer()) {
+                (Some(value), _) => value.to_string(),
+                (None, Some(value)) if value >= 0 => value.to_string(),
+                _ => return Err(format!("serve.{} must be a string or a number", key)),
+            };
+            args.extend([flag.to_string(), value]);
+        }
+    }
+    Ok(args)
+}
+
+/// Load the set and serve until the listener fails.
+pub fn run(options: ServeOptions) -> Result<(), String> {
+    let set = std::fs::read_to_string(&options.set)
+        .map_err(|e| format!("{}: {}", options.set.display(), e))?;
+    let items: Vec<Vec<u8>> = set
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| line.as_bytes().to_vec())
+        .collect();
+    let template = PsiProtocol::new_with_config(&items, options.psi_config()?)
+        .map_err(|e| format!("{}: {}", options.set.display(), e))?;
+    let listener = TcpListener::bind(options.listen)
+        .map_err(|e| format!("cannot listen on {}: {}", options.listen, e))?;
+    log(
+        "listening",
+        serde_json::json!({
+            "addr": listener.local_addr().map_err(|e| e.to_string())?.to_string(),
+            "items": items.len(),
+        }),
+    );
+    serve(listener, template, &options).map_err(|e| e.to_string())
+}
+
+/// State shared by the connection threads.
+struct Server {
+    template: PsiProtocol<PreparedState>,
+    sessions: Mutex<SessionManager<u64>>,
+    active: AtomicUsize,
+    next_id: AtomicU64,
+    max_connections: usize,
+    timeout: Duration,
+}
+
+/// Accept connections on `listener`, one thread per session.
+fn serve(
+    listener: TcpListener,
+    template: PsiProtocol<PreparedState>,
+    options: &ServeOptions,
+) -> io::Result<()> {
+    let server = Arc::new(Server {
+        template,
+        sessions: Mutex::new(SessionManager::new(options.timeout)),
+        active: AtomicUsize::new(0),
+        next_id: AtomicU64::new(1),
+        max_connections: options.max_connections,
+        timeout: options.timeout,
+    });
+
+    // Sweep twice per timeout; the thread ends with the server
+    let sweeper = Arc::downgrade(&server);
+    let period = (options.timeout / 2).max(Duration::from_secs(1));
+    std::thread::spawn(move || {
+        while let Some(server) = sweeper.upgrade() {
+            let expired = server.sessions.lock().expect("lock poisoned").sweep();
+            if expired > 0 {
+                log("expired", serde_json::json!({ "sessions": expired }));
+            }
+            drop(server);
+            std::thread::sleep(period);
+        }
+    });
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let server = Arc::clone(&server);
+        std::thread::spawn(move || server.accept(stream));
+    }
+    Ok(())
+}
+
+impl Server {
+    /// Serve one connection, or turn it away when at capacity.
+    fn accept(&self, mut stream: TcpStream) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let peer = stream
+            .peer_addr()
+            .map_or_else(|_| "unknown".to_string(), |addr| addr.to_string());
+        if self.active.fetch_add(1, Ordering::AcqRel) >= self.max_connections {
+            self.active.fetch_sub(1, Ordering::AcqRel);
+            let _ = write_frame(
+                &mut stream,
+                &WireMessage::Abort(AbortMessage::new(AbortReason::Overloaded)),
+            );
+            log(
+                "refused",
+                serde_json::json!({ "session": id, "peer": peer, "reason": "overloaded" }),
+            );
+            return;
+        }
+
+        let started = Instant::now();
+        let outcome = self.session(id, &mut stream);
+        self.sessions.lock().expect("lock poisoned").remove(&id);
+        self.active.fetch_sub(1, Ordering::AcqRel);
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        match outcome {
+            Ok((remote_items, matches)) => log(
+                "session",
+                serde_json::json!({
+                    "session": id,
+                    "peer": peer,
+                    "remote_items": remote_items,
+                    "matches": matches,
+                    "elapsed_ms": elapsed_ms,
+                }),
+            ),
+            Err(error) => log(
+                "failed",
+                serde_json::json!({
+                    "session": id,
+                    "peer": peer,
+                    "error": error.to_string(),
+                    "elapsed_ms": elapsed_ms,
+                }),
+            ),
+        }
+    }
+
+    /// Run the responder side; returns the client's set size and the
+    /// number of matches.
+    fn session(&self, id: u64, stream: &mut TcpStream) -> Result<(usize, usize), SessionError> {
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let config = self.template.config();
+        let session = PsiSession::from(self.template.rerandomize()?);
+        self.park(id, session);
+
+        let remote_msg = read_blinded(stream, config)?;
+        let remote_items = remote_msg.len();
+        let mut session = self.take(id)?;
+        write_frame(stream, &WireMessage::Blinded(session.message()?))?;
+        let double_msg = abort_on_error(stream, session.on_blinded(remote_msg))?;
+        write_frame(stream, &WireMessage::DoubleBlinded(double_msg))?;
+        self.park(id, session);
+
+        let remote_double = read_double_blinded(stream, config)?;
+        let mut session = self.take(id)?;
+        let result = session.on_double_blinded(remote_double)?;
+        Ok((remote_items, result.len()))
+    }
+
+    /// Hand a session waiting on the client to the manager.
+    ///
+    /// Its deadline is set on the first call and kept afterwards.
+    fn park(&self, id: u64, session: PsiSession) {
+        let mut sessions = self.sessions.lock().expect("lock poisoned");
+        match sessions.deadline(&id) {
+            Some(deadline) => sessions.insert_with_deadline(id, session, deadline),
+            None => sessions.insert(id, session),
+        };
+    }
+
+    /// Take a session back from the manager to work on it.
+    ///
+    /// The entry and its deadline stay in the manager until the session is
+    /// parked again; fails once the deadline has passed.
+    fn take(&self, id: u64) -> Result<PsiSession, SessionError> {
+        let mut sessions = self.sessions.lock().expect("lock poisoned");
+        let session = sessions.get_mut(&id).ok_or(SessionError::Expired)?;
+        Ok(std::mem::replace(session, PsiSession::Poisoned))
+    }
+}
+
+/// Error of one client session.
+#[derive(Debug)]
+enum SessionError {
+    Frame(FrameError),
+    Expired,
+}
+
+impl std::fmt::Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionError::Frame(error) => write!(f, "{}", error),
+            SessionError::Expired => write!(f, "session expired"),
+        }
+    }
+}
+
+impl From<FrameError> for SessionError {
+    fn from(error: FrameError) -> Self {
+        SessionError::Frame(error)
+    }
+}
+
+impl From<io::Error> for SessionError {
+    fn from(error: io::Error) -> Self {
+        SessionError::Frame(FrameError::Io(error))
+    }
+}
+
+impl From<PsiError> for SessionError {
+    fn from(error: PsiError) -> Self {
+        SessionError::Frame(FrameError::Protocol(error))
+    }
+}
+
+/// Write one JSON log line to stderr.
+fn log(event: &str, mut fields: serde_json::Value) {
+    let ts_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64);
+    if let Some(fields) = fields.as_object_mut() {
+        fields.insert("ts_ms".to_string(), ts_ms.into());
+        fields.insert("event".to_string(), event.into());
+    }
+    eprintln!("{}", fields);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(max_connections: usize) -> ServeOptions {
+        ServeOptions {
+            listen: "127.0.0.1:0".parse().unwrap(),
+            set: PathBuf::new(),
+            max_set_size: Some(16),
+            max_connections,
+            timeout: Duration::from_secs(5),
+            domain: None,
+        }
+    }
+
+    /// Start a server for `items` and return its address.
+    fn start(items: &[&[u8]], options: ServeOptions) -> SocketAddr {
+        let items: Vec<Vec<u8>> = items.iter().map(|item| item.to_vec()).collect();
+        let template = PsiProtocol::new_with_config(&items, options.psi_config().unwrap()).unwrap();
+        let listener = TcpListener::bind(options.listen).unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || serve(listener, template, &options));
+        addr
+    }
+
+    /// Run the client side of a session.
+    fn query(addr: SocketAddr, items: &[&[u8]]) -> Result<usize, SessionError> {
+        let items: Vec<Vec<u8>> = items.iter().map(|item| item.to_vec()).collect();
+        let config = PsiConfig::default();
+        let mut stream = TcpStream::connect(addr)?;
+        let local = PsiProtocol::new(&items)?;
+        write_frame(&mut stream, &WireMessage::Blinded(local.message()))?;
+        let remote = read_blinded(&mut stream, &config)?;
+        let remote_double = read_double_blinded(&mut stream, &config)?;
+        let (local, double) = local.compute(remote)?;
+        write_frame(&mut stream, &WireMessage::DoubleBlinded(double))?;
+        let (_, result) = local.finalize(remote_double)?;
+        Ok(result.len())
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let parsed = ServeOptions::from_args(&[
+            "--listen",
+            "0.0.0.0:7878",
+            "--set",
+            "items.txt",
+            "--max-set-size",
+            "1000",
+        ])
+        .unwrap();
+        assert_eq!(parsed.max_set_size, Some(1000));
+        assert_eq!(parsed.max_connections, DEFAULT_MAX_CONNECTIONS);
+        assert!(ServeOptions::from_args(&["--set", "items.txt"]).is_err());
+        assert!(ServeOptions::from_args(&["--listen", "nowhere", "--set", "x"]).is_err());
+    }
+
+    #[test]
+    fn test_config_file_options() {
+        let args = config_args(
+            "[serve]\nlisten = \"127.0.0.1:7878\"\nset = \"items.txt\"\nmax_connections = 8\n",
+        )
+        .unwrap();
+        let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+        args.extend(["--max-connections", "4"]);
+        let parsed = ServeOptions::from_args(&args).unwrap();
+        assert_eq!(parsed.set, PathBuf::from("items.txt"));
+        assert_eq!(parsed.max_connections, 4);
+
+        assert!(config_args("[serve]\nmax_conections = 8").is_err());
+        assert!(config_args("[serve]\ntimeout = -1").is_err());
+        assert!(config_args("[tls]\ncert = \"c.pem\"").is_err());
+    }
+
+    #[test]
+    fn test_serves_concurrent_clients() {
+        let addr = start(&[b"apple", b"banana", b"cherry"], options(8));
+        let clients: Vec<_> = (0..4)
+            .map(|_| std::thread::spawn(move || query(addr, &[b"banana", b"cherry", b"date"])))
+            .collect();
+        for client in clients {
+            assert_eq!(client.join().unwrap().unwrap(), 2);
+        }
+    }
+
+    #[test]
+    fn test_rejects_oversized_sets() {
+        let addr = start(&[b"apple"], options(8));
+        let items: Vec<Vec<u8>> = (0..32).map(|i| vec![i]).collect();
+        let items: Vec<&[u8]> = items.iter().map(Vec::as_slice).collect();
+        assert!(query(addr, &items).is_err());
+        // The server keeps serving
+        assert_eq!(query(addr, &[b"apple"]).unwrap(), 1);
+    }
+}
@@ -0,0 +1,277 @@
+//! Deterministic test vectors.
+//!
+//! Each vector is one full exchange between Alice and Bob with secrets
+//! derived from fixed key material (`PsiProtocol::with_derived_secret`) and
+//! sorted messages, so every value is reproducible: the items, both blinded
+//! messages, both double-blinded answers and the intersection. Bindings and
+//! other implementations can either check their outputs against a file made
+//! by `generate`, or write their own file and have `verify` recompute it.
+//!
+//! Everything binary is lowercase hex; points are compressed Ristretto.
+
+use curve25519_dalek::ristretto::CompressedRistretto;
+use psi_protocol::{MessageOrder, PreparedState, PsiConfig, PsiProtocol};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Format version of the vector file.
+pub const VECTORS_VERSION: u32 = 1;
+
+/// Domain of the key material derived from the seed.
+const IKM_TAG: &[u8] = b"psi-cli/vectors/v1";
+
+/// A file of test vectors.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorFile {
+    pub version: u32,
+    pub vectors: Vec<Vector>,
+}
+
+/// One exchange.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vector {
+    pub name: String,
+    /// Context of `with_derived_secret`, hex
+    pub context: String,
+    pub alice: Party,
+    pub bob: Party,
+    /// Sorted item hashes of the intersection, hex
+    pub intersection: Vec<String>,
+}
+
+/// One side of an exchange.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Party {
+    /// Key material of `with_derived_secret`, hex
+    pub ikm: String,
+    /// Input items, hex
+    pub items: Vec<String>,
+    /// The party's blinded points message, in message order
+    pub blinded: Vec<String>,
+    /// The party's double-blinding of the peer's message, in its order
+    pub double_blinded: Vec<String>,
+}
+
+/// A generated vector's name and the items of Alice and Bob.
+type Case = (
+    &'static str,
+    &'static [&'static [u8]],
+    &'static [&'static [u8]],
+);
+
+/// Inputs of the generated vectors.
+const CASES: &[Case] = &[
+    (
+        "overlap",
+        &[b"apple", b"banana", b"cherry"],
+        &[b"banana", b"cherry", b"date"],
+    ),
+    ("disjoint", &[b"apple"], &[b"banana"]),
+    ("identical", &[b"apple", b"banana"], &[b"apple", b"banana"]),
+    ("duplicates", &[b"apple", b"apple", b"banana"], &[b"apple"]),
+    ("empty-item", &[b""], &[b"", b"apple"]),
+    (
+        "binary",
+        &[&[0x00, 0xff, 0x00]],
+        &[&[0x00, 0xff, 0x00], &[0xff]],
+    ),
+];
+
+/// The configuration of every vector: default, with sorted messages.
+fn config() -> PsiConfig {
+    PsiConfig::builder()
+        .order(MessageOrder::Sorted)
+        .build()
+        .expect("sorted order is a valid configuration")
+}
+
+/// Key material of `party` in `case`, derived from `seed`.
+fn ikm(seed: u64, party: &str, case: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(IKM_TAG);
+    hasher.update(seed.to_be_bytes());
+    for part in [party, case] {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// Generate the vectors for `seed`.
+pub fn generate(seed: u64) -> Result<VectorFile, String> {
+    let vectors = CASES
+        .iter()
+        .map(|(name, alice, bob)| {
+            let hexes = |items: &[&[u8]]| items.iter().map(hex::encode).collect();
+            let inputs = Vector {
+                name: name.to_string(),
+                context: hex::encode(format!("psi-cli vector {name}")),
+                alice: Party::inputs(ikm(seed, "alice", name), hexes(alice)),
+                bob: Party::inputs(ikm(seed, "bob", name), hexes(bob)),
+                intersection: Vec::new(),
+            };
+            compute(&inputs)
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(VectorFile {
+        version: VECTORS_VERSION,
+        vectors,
+    })
+}
+
+/// Recompute every vector of `file` from its inputs.
+///
+/// Returns one line per mismatching field; empty if the file is correct.
+pub fn verify(file: &VectorFile) -> Result<Vec<String>, String> {
+    if file.version != VECTORS_VERSION {
+        return Err(format!(
+            "unsupported vector version {}, expected {}",
+            file.version, VECTORS_VERSION
+        ));
+    }
+    let mut mismatches = Vec::new();
+    for vector in &file.vectors {
+        let expected = compute(vector)?;
+        let mut check = |field: &str, actual: &[String], expected: &[String]| {
+            if actual != expected {
+                mismatches.push(format!("{}: {} differs", vector.name, field));
+            }
+        };
+        check(
+            "alice.blinded",
+            &vector.alice.blinded,
+            &expected.alice.blinded,
+        );
+        check("bob.blinded", &vector.bob.blinded, &expected.bob.blinded);
+        check(
+            "alice.double_blinded",
+            &vector.alice.double_blinded,
+            &expected.alice.double_blinded,
+        );
+        check(
+            "bob.double_blinded",
+            &vector.bob.double_blinded,
+            &expected.bob.double_blinded,
+        );
+        check("intersection", &vector.intersection, &expected.intersection);
+    }
+    Ok(mismatches)
+}
+
+impl Party {
+    fn inputs(ikm: [u8; 32], items: Vec<String>) -> Self {
+        Self {
+            ikm: hex::encode(ikm),
+            items,
+            blinded: Vec::new(),
+            double_blinded: Vec::new(),
+        }
+    }
+
+    fn protocol(&self, context: &[u8]) -> Result<PsiProtocol<PreparedState>, String> {
+        let ikm = decode(&self.ikm)?;
+        let items = self
+            .items
+            .iter()
+            .map(|item| decode(item))
+            .collect::<Result<Vec<_>, _>>()?;
+        PsiProtocol::with_derived_secret(&items, &ikm, context, config()).map_err(|e| e.to_string())
+    }
+}
+
+/// Run the exchange described by the inputs of `vector`.
+fn compute(vector: &Vector) -> Result<Vector, String> {
+    let context = decode(&vector.context)?;
+    let alice = vector.alice.protocol(&context)?;
+    let bob = vector.bob.protocol(&context)?;
+
+    let (alice_msg, bob_msg) = (alice.message(), bob.message());
+    let alice_blinded = points(&alice_msg.blinded_points);
+    let bob_blinded = points(&bob_msg.blinded_points);
+    let (alice, alice_double) = alice.compute(bob_msg).map_err(|e| e.to_string())?;
+    let (bob, bob_double) = bob.compute(alice_msg).map_err(|e| e.to_string())?;
+    let alice_double_blinded = points(&alice_double.double_blinded_points);
+    let bob_double_blinded = points(&bob_double.double_blinded_points);
+    let (_, alice_result) = alice.finalize(bob_double).map_err(|e| e.to_string())?;
+    let (_, bob_result) = bob.finalize(alice_double).map_err(|e| e.to_string())?;
+
+    let mut intersection: Vec<String> = alice_result
+        .intersection_hashes
+        .iter()
+        .map(|id| id.to_string())
+        .collect();
+    intersection.sort();
+    let mut bob_intersection: Vec<String> = bob_result
+        .intersection_hashes
+        .iter()
+        .map(|id| id.to_string())
+        .collect();
+    bob_intersection.sort();
+    if intersection != bob_intersection {
+        return Err(format!(
+            "{}: parties disagree on the intersection",
+            vector.name
+        ));
+    }
+
+    Ok(Vector {
+        name: vector.name.clone(),
+        context: vector.context.clone(),
+        alice: Party {
+            blinded: alice_blinded,
+            double_blinded: alice_double_blinded,
+            ..vector.alice.clone()
+        },
+        bob: Party {
+            blinded: bob_blinded,
+            double_blinded: bob_double_blinded,
+            ..vector.bob.clone()
+        },
+        intersection,
+    })
+}
+
+fn points(points: &[CompressedRistretto]) -> Vec<String> {
+    points
+        .iter()
+        .map(|point| hex::encode(point.as_bytes()))
+        .collect()
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, String> {
+    hex::decode(value).map_err(|e| format!("invalid hex {:?}: {}", value, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_vectors_verify() {
+        let file = generate(7).unwrap();
+        assert_eq!(file, generate(7).unwrap());
+        assert_ne!(file, generate(8).unwrap());
+        assert!(verify(&file).unwrap().is_empty());
+
+        let overlap = &file.vectors[0];
+        assert_eq!(overlap.intersection.len(), 2);
+        assert_eq!(overlap.alice.blinded.len(), 3);
+    }
+
+    #[test]
+    fn test_verify_reports_tampered_fields() {
+        let mut file = generate(0).unwrap();
+        file.vectors[0].bob.double_blinded.swap(0, 1);
+        file.vectors[1].intersection.push(hex::encode([0u8; 32]));
+        assert_eq!(
+            verify(&file).unwrap(),
+            vec![
+                "overlap: bob.double_blinded differs".to_string(),
+                "disjoint: intersection differs".to_string(),
+            ]
+        );
+
+        file.version = 9;
+        assert!(verify(&file).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! Run the conformance suite against an implementation in another process.
+//!
+//! The candidate is started as a child process and driven over its stdin
+//! and stdout with length-prefixed records: one type byte, a big-endian
+//! `u32` payload length, then the payload.
+//!
+//! | type | direction       | payload                                       |
+//! |------|-----------------|-----------------------------------------------|
+//! | 1    | suite -> child  | start: JSON `{"case": name, "items": [hex]}`  |
+//! | 2    | both            | one wire-format frame                         |
+//! | 3    | suite -> child  | result request, empty                         |
+//! | 4    | child -> suite  | result: the 32-byte ids of the intersection   |
+//! | 5    | child -> suite  | the session is closed, empty                  |
+//!
+//! Run with:
+//! ```bash
+//! cargo run --release --bin conformance -- ./my-port --conformance
+//! ```
+
+use psi_protocol::conformance::{self, Candidate, Case};
+use psi_protocol::{ItemId, PsiError};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, ExitCode, Stdio};
+
+const START: u8 = 1;
+const FRAME: u8 = 2;
+const RESULT_REQUEST: u8 = 3;
+const RESULT: u8 = 4;
+const CLOSED: u8 = 5;
+
+/// A candidate behind a child process's stdin and stdout.
+struct Process {
+    child: Child,
+    input: BufWriter<ChildStdin>,
+    output: BufReader<ChildStdout>,
+}
+
+impl Process {
+    fn spawn(program: &str, args: &[String]) -> std::io::Result<Self> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let input = BufWriter::new(child.stdin.take().expect("stdin is piped"));
+        let output = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        Ok(Self {
+            child,
+            input,
+            output,
+        })
+    }
+
+    fn write(&mut self, kind: u8, payload: &[u8]) -> psi_protocol::Result<()> {
+        let len = u32::try_from(payload.len()).map_err(|_| io_error("record too large"))?;
+        let mut record = Vec::with_capacity(5 + payload.len());
+        record.push(kind);
+        record.extend_from_slice(&len.to_be_bytes());
+        record.extend_from_slice(payload);
+        self.input
+            .write_all(&record)
+            .and_then(|()| self.input.flush())
+            .map_err(|e| io_error(&e.to_string()))
+    }
+
+    fn read(&mut self) -> psi_protocol::Result<(u8, Vec<u8>)> {
+        let mut header = [0u8; 5];
+        self.output
+            .read_exact(&mut header)
+            .map_err(|e| io_error(&e.to_string()))?;
+        let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+        let mut payload = vec![0u8; len as usize];
+        self.output
+            .read_exact(&mut payload)
+            .map_err(|e| io_error(&e.to_string()))?;
+        Ok((header[0], payload))
+    }
+}
+
+impl Candidate for Process {
+    fn start(&mut self, case: &Case) -> psi_protocol::Result<()> {
+        let start = serde_json::json!({
+            "case": case.name,
+            "items": case.candidate_items.iter().map(hex::encode).collect::<Vec<_>>(),
+        });
+        self.write(START, start.to_string().as_bytes())
+    }
+
+    fn send(&mut self, frame: &[u8]) -> psi_protocol::Result<()> {
+        self.write(FRAME, frame)
+    }
+
+    fn receive(&mut self) -> psi_protocol::Result<Vec<u8>> {
+        match self.read()? {
+            (FRAME, frame) => Ok(frame),
+            (CLOSED, _) => Err(io_error("session closed")),
+            (kind, _) => Err(io_error(&format!("unexpected record type {kind}"))),
+        }
+    }
+
+    fn result(&mut self) -> psi_protocol::Result<Vec<ItemId>> {
+        self.write(RESULT_REQUEST, &[])?;
+        match self.read()? {
+            (RESULT, ids) if ids.len() % 32 == 0 => Ok(ids
+                .chunks_exact(32)
+                .map(|id| ItemId::new(id.try_into().expect("chunks are 32 bytes")))
+                .collect()),
+            (kind, _) => Err(io_error(&format!("unexpected record type {kind}"))),
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn io_error(reason: &str) -> PsiError {
+    PsiError::InvalidEncoding(format!("candidate: {reason}"))
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some((program, args)) = args.split_first() else {
+        eprintln!("usage: conformance <candidate command> [args...]");
+        return ExitCode::from(2);
+    };
+    let mut candidate = match Process::spawn(program, args) {
+        Ok(candidate) => candidate,
+        Err(e) => {
+            eprintln!("cannot start {program}: {e}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let report = conformance::run(&mut candidate);
+    println!("{report}");
+    if report.passed() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -8,7 +8,7 @@
 //! cargo run --bin in_memory
 //! ```
 
-use psi_protocol::{PsiProtocol, PsiResult};
+use psi_protocol::{hash_item, run_local_psi, PsiProtocol, PsiResult};
 use rand::RngCore;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -88,28 +88,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     assert_eq!(alice_set, bob_set, "Intersections do not match!");
 
     println!("\nIntersection items:");
-    for (i, hash) in alice_result.intersection_hashes.iter().enumerate() {
-        // Find the original item (for demonstration only - in practice,
-        // you wouldn't be able to reverse the hash)
-        let matching_item = alice_items
-            .iter()
-            .find(|item| {
-                // Re-hash to find which item this corresponds to
-                use sha2::{Digest, Sha512};
-                let mut hasher = Sha512::new();
-                hasher.update(item);
-                let result = hasher.finalize();
-                let mut h = [0u8; 32];
-                h.copy_from_slice(&result[..32]);
-                &h == hash
-            })
-            .unwrap();
-
+    for (i, item) in alice_result.match_items(&alice_items).iter().enumerate() {
         println!(
             "  {}: {} (hash: {:?})",
             i + 1,
-            String::from_utf8_lossy(matching_item),
-            &hash[..8] // Show first 8 bytes of hash
+            String::from_utf8_lossy(item),
+            &hash_item(item)[..8] // Show first 8 bytes of hash
         );
     }
 
@@ -147,17 +131,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("Alice: {} items, Bob: {} items", alice_large.len(), bob_large.len());
 
-    let alice_proto = PsiProtocol::new(&alice_large)?;
-    let bob_proto = PsiProtocol::new(&bob_large)?;
-
-    let alice_msg = alice_proto.message();
-    let bob_msg = bob_proto.message();
-
-    let (alice_int, alice_double) = alice_proto.compute(bob_msg)?;
-    let (bob_int, bob_double) = bob_proto.compute(alice_msg)?;
-
-    let (_alice_fin, alice_res) = alice_int.finalize(bob_double)?;
-    let (_bob_fin, bob_res) = bob_int.finalize(alice_double)?;
+    // Run both sides of the protocol in one call
+    let (alice_res, bob_res) = run_local_psi(&alice_large, &bob_large)?;
 
     println!(
         "\nIntersection size: {} (expected: 10)",
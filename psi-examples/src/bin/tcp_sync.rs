@@ -0,0 +1,167 @@
+//! TCP example of PSI protocol execution.
+//!
+//! Two peers run the full two-round protocol over a TCP connection. Every
+//! message is a wire frame (`to_bytes`/`from_bytes`) prefixed with its
+//! length as a big-endian `u32`, so the reader always knows where a message
+//! ends, and refuses lengths larger than the set size limit allows before
+//! allocating anything.
+//!
+//! The connecting peer speaks first, so neither side writes while the other
+//! is still writing:
+//!
+//! ```text
+//! connect                      listen
+//!    |-- blinded --------------->|
+//!    |<-------------- blinded ---|
+//!    |<------- double-blinded ---|
+//!    |-- double-blinded -------->|
+//! ```
+//!
+//! Run both peers in one process over loopback:
+//! ```bash
+//! cargo run --bin tcp_sync
+//! ```
+//!
+//! Or in two terminals, with one item per line in each file:
+//! ```bash
+//! cargo run --bin tcp_sync -- listen 127.0.0.1:7878 alice.txt
+//! cargo run --bin tcp_sync -- connect 127.0.0.1:7878 bob.txt
+//! ```
+
+use psi_protocol::{
+    wire, BlindedPointsMessage, DoubleBlindedPointsMessage, PsiProtocol, PsiResult,
+};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+/// Largest set either peer accepts from the other.
+const MAX_REMOTE_ITEMS: usize = 1_000_000;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+fn main() -> Result<(), BoxError> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.as_slice() {
+        [] => loopback(),
+        [mode, addr, file] if mode == "listen" => {
+            let items = read_items(file)?;
+            let listener = TcpListener::bind(addr)?;
+            println!("Listening on {}", listener.local_addr()?);
+            let (mut stream, peer) = listener.accept()?;
+            println!("Peer connected from {}", peer);
+            report(&items, &listen(&mut stream, &items)?);
+            Ok(())
+        }
+        [mode, addr, file] if mode == "connect" => {
+            let items = read_items(file)?;
+            let mut stream = TcpStream::connect(addr)?;
+            report(&items, &connect(&mut stream, &items)?);
+            Ok(())
+        }
+        _ => Err("usage: tcp_sync [listen|connect ADDR FILE]".into()),
+    }
+}
+
+/// Run both peers over a loopback connection.
+fn loopback() -> Result<(), BoxError> {
+    println!("=== PSI Protocol TCP Example ===\n");
+
+    let alice_items = items(&["alice_secret_1", "shared_secret_1", "shared_secret_2"]);
+    let bob_items = items(&["bob_secret_1", "shared_secret_1", "shared_secret_2"]);
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    println!("Alice listening on {}", addr);
+
+    let alice = std::thread::spawn(move || -> Result<PsiResult, BoxError> {
+        let (mut stream, _) = listener.accept()?;
+        listen(&mut stream, &alice_items)
+    });
+    let mut stream = TcpStream::connect(addr)?;
+    let bob_result = connect(&mut stream, &bob_items)?;
+    let alice_result = alice.join().map_err(|_| "Alice's thread panicked")??;
+
+    println!("Alice found {} items in intersection", alice_result.len());
+    println!("Bob found {} items in intersection", bob_result.len());
+    report(&bob_items, &bob_result);
+
+    let mut alice_hashes = alice_result.intersection_hashes;
+    let mut bob_hashes = bob_result.intersection_hashes;
+    alice_hashes.sort();
+    bob_hashes.sort();
+    assert_eq!(alice_hashes, bob_hashes, "Intersections do not match!");
+    println!("\n✓ Both parties computed the same intersection");
+    Ok(())
+}
+
+/// The listening side: answer the peer's blinded points first.
+fn listen(stream: &mut TcpStream, items: &[Vec<u8>]) -> Result<PsiResult, BoxError> {
+    let protocol = PsiProtocol::new(items)?;
+
+    let remote = BlindedPointsMessage::from_bytes(&read_frame(stream)?)?;
+    write_frame(stream, &protocol.message().to_bytes())?;
+    let (protocol, double_blinded) = protocol.compute(remote)?;
+    write_frame(stream, &double_blinded.to_bytes())?;
+
+    let remote = DoubleBlindedPointsMessage::from_bytes(&read_frame(stream)?)?;
+    let (_, result) = protocol.finalize(remote)?;
+    Ok(result)
+}
+
+/// The connecting side: send our blinded points first.
+fn connect(stream: &mut TcpStream, items: &[Vec<u8>]) -> Result<PsiResult, BoxError> {
+    let protocol = PsiProtocol::new(items)?;
+    write_frame(stream, &protocol.message().to_bytes())?;
+
+    let remote = BlindedPointsMessage::from_bytes(&read_frame(stream)?)?;
+    let (protocol, double_blinded) = protocol.compute(remote)?;
+    let remote = DoubleBlindedPointsMessage::from_bytes(&read_frame(stream)?)?;
+    write_frame(stream, &double_blinded.to_bytes())?;
+
+    let (_, result) = protocol.finalize(remote)?;
+    Ok(result)
+}
+
+fn write_frame(stream: &mut TcpStream, frame: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(frame.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame too large"))?;
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(frame)?;
+    stream.flush()
+}
+
+fn read_frame(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > wire::max_frame_len(MAX_REMOTE_ITEMS) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes is too large", len),
+        ));
+    }
+    let mut frame = vec![0u8; len];
+    stream.read_exact(&mut frame)?;
+    Ok(frame)
+}
+
+fn items(names: &[&str]) -> Vec<Vec<u8>> {
+    names.iter().map(|name| name.as_bytes().to_vec()).collect()
+}
+
+/// One item per non-empty line of `path`.
+fn read_items(path: &str) -> io::Result<Vec<Vec<u8>>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| line.as_bytes().to_vec())
+        .collect())
+}
+
+fn report(items: &[Vec<u8>], result: &PsiResult) {
+    println!("\nIntersection items:");
+    for (i, item) in result.match_items(items).iter().enumerate() {
+        println!("  {}: {}", i + 1, String::from_utf8_lossy(item));
+    }
+}
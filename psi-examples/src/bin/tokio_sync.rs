@@ -0,0 +1,230 @@
+//! Async example of PSI protocol execution with tokio.
+//!
+//! Each peer runs [`run_peer`], an async driver over any
+//! `AsyncRead + AsyncWrite` connection: a `tokio::net::TcpStream` in a real
+//! integration, an in-memory `tokio::io::duplex` pipe here. Frames are wire
+//! messages prefixed with their big-endian `u32` length, as in `tcp_sync`.
+//!
+//! Unlike the blocking example, both peers send and receive at the same
+//! time: the connection is split and every round joins the write of our
+//! message with the read of the peer's, so neither side waits for the other
+//! to go first. The CPU-heavy phases run on tokio's blocking pool
+//! (`compute_async`/`finalize_async`), and every phase has a deadline
+//! (`PhaseLimits`), so a stuck peer cannot hang the task.
+//!
+//! Errors end the session gracefully: the failing peer tells the other one
+//! why with an error report (when the cause has a wire code) and an abort
+//! frame, and a peer receiving those returns the matching `PsiError`
+//! instead of waiting for a message that will never come.
+//!
+//! With tokio's `net` feature, the same driver runs over TCP:
+//! ```ignore
+//! let listener = tokio::net::TcpListener::bind("127.0.0.1:7878").await?;
+//! let (stream, _) = listener.accept().await?;
+//! let result = run_peer(stream, items, &limits).await?;
+//! ```
+//!
+//! Run with:
+//! ```bash
+//! cargo run --bin tokio_sync
+//! ```
+
+use psi_protocol::{
+    wire, AbortMessage, AbortReason, BlindedPointsMessage, DoubleBlindedPointsMessage,
+    ErrorReportMessage, Phase, PhaseLimits, PsiConfig, PsiError, PsiProtocol, PsiResult,
+    WireMessage,
+};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Largest set either peer accepts from the other.
+const MAX_REMOTE_ITEMS: usize = 100_000;
+
+/// Time each phase may take, network waits included.
+const PHASE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::main]
+async fn main() -> Result<(), PsiError> {
+    println!("=== PSI Protocol Async Example ===\n");
+
+    let limits = PhaseLimits::new().timeout(PHASE_TIMEOUT);
+    let alice_items = items(&["alice_secret_1", "shared_secret_1", "shared_secret_2"]);
+    let bob_items = items(&["bob_secret_1", "shared_secret_1", "shared_secret_2"]);
+
+    let (alice_conn, bob_conn) = tokio::io::duplex(64 * 1024);
+    let (alice_result, bob_result) = tokio::join!(
+        run_peer(alice_conn, alice_items.clone(), &limits),
+        run_peer(bob_conn, bob_items, &limits),
+    );
+    let (alice_result, bob_result) = (alice_result?, bob_result?);
+
+    println!("Alice found {} items in intersection", alice_result.len());
+    println!("Bob found {} items in intersection", bob_result.len());
+    println!("\nIntersection items:");
+    for (i, item) in alice_result.match_items(&alice_items).iter().enumerate() {
+        println!("  {}: {}", i + 1, String::from_utf8_lossy(item));
+    }
+
+    // A peer whose set is too large for the other one: Bob rejects Alice's
+    // points and both sides end with an error instead of hanging.
+    println!("\n--- Rejected session ---");
+    let strict = PsiConfig::builder().max_remote_items(2).build()?;
+    let (alice_conn, bob_conn) = tokio::io::duplex(64 * 1024);
+    let (alice_outcome, bob_outcome) = tokio::join!(
+        run_peer(alice_conn, items(&["a", "b", "c"]), &limits),
+        run_peer_with_config(bob_conn, items(&["a"]), strict, &limits),
+    );
+    println!("Alice: {}", alice_outcome.unwrap_err());
+    println!("Bob: {}", bob_outcome.unwrap_err());
+    Ok(())
+}
+
+/// Run one peer of a session over `conn` with the default configuration.
+async fn run_peer<C>(
+    conn: C,
+    items: Vec<Vec<u8>>,
+    limits: &PhaseLimits,
+) -> Result<PsiResult, PsiError>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    let config = PsiConfig::builder()
+        .max_remote_items(MAX_REMOTE_ITEMS)
+        .build()?;
+    run_peer_with_config(conn, items, config, limits).await
+}
+
+/// Run one peer of a session over `conn`.
+///
+/// On a local error, the peer is told before the error is returned.
+async fn run_peer_with_config<C>(
+    conn: C,
+    items: Vec<Vec<u8>>,
+    config: PsiConfig,
+    limits: &PhaseLimits,
+) -> Result<PsiResult, PsiError>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut reader, mut writer) = tokio::io::split(conn);
+    let max_frame = wire::max_frame_len(config.max_remote_items().unwrap_or(MAX_REMOTE_ITEMS));
+
+    let outcome = async {
+        let protocol = limits
+            .run(Phase::Prepare, PsiProtocol::new_async(items, config))
+            .await?;
+
+        // Round one: blinded points both ways at once
+        let ours = WireMessage::Blinded(protocol.message());
+        let (sent, received) = limits
+            .run(Phase::Compute, async {
+                Ok(tokio::join!(
+                    write_frame(&mut writer, &ours),
+                    read_frame(&mut reader, max_frame)
+                ))
+            })
+            .await?;
+        // The peer's abort explains a failed write better than the write
+        let remote: BlindedPointsMessage = match received? {
+            WireMessage::Blinded(msg) => msg,
+            other => return Err(unexpected(other)),
+        };
+        sent?;
+        let (protocol, double_blinded) = limits
+            .run(Phase::Compute, protocol.compute_async(remote))
+            .await?;
+
+        // Round two: double-blinded points both ways at once
+        let ours = WireMessage::DoubleBlinded(double_blinded);
+        let (sent, received) = limits
+            .run(Phase::Finalize, async {
+                Ok(tokio::join!(
+                    write_frame(&mut writer, &ours),
+                    read_frame(&mut reader, max_frame)
+                ))
+            })
+            .await?;
+        let remote: DoubleBlindedPointsMessage = match received? {
+            WireMessage::DoubleBlinded(msg) => msg,
+            other => return Err(unexpected(other)),
+        };
+        sent?;
+        let (_, result) = limits
+            .run(Phase::Finalize, protocol.finalize_async(remote))
+            .await?;
+        Ok(result)
+    }
+    .await;
+
+    if let Err(error) = &outcome {
+        tell_peer(&mut writer, error).await;
+    }
+    outcome
+}
+
+/// Tell the peer why we give up, unless the peer ended the session itself.
+///
+/// Best effort: the connection may already be gone.
+async fn tell_peer<W: AsyncWrite + Unpin>(writer: &mut W, error: &PsiError) {
+    if matches!(error, PsiError::Aborted(_) | PsiError::Rejected(_)) {
+        return;
+    }
+    if let Some(report) = ErrorReportMessage::for_error(error) {
+        let _ = write_frame(writer, &WireMessage::ErrorReport(report)).await;
+    }
+    let abort = AbortMessage::new(AbortReason::for_error(error));
+    let _ = write_frame(writer, &WireMessage::Abort(abort)).await;
+    let _ = writer.shutdown().await;
+}
+
+/// Error for a frame that is not the message expected next.
+fn unexpected(msg: WireMessage) -> PsiError {
+    match msg {
+        WireMessage::Abort(abort) => abort.into_error(),
+        WireMessage::ErrorReport(report) => report.into_error(),
+        other => PsiError::InvalidEncoding(format!("unexpected {:?} message", other.kind())),
+    }
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    msg: &WireMessage,
+) -> Result<(), PsiError> {
+    let frame = wire::encode(msg);
+    let len = u32::try_from(frame.len())
+        .map_err(|_| PsiError::InvalidEncoding("frame too large".to_string()))?;
+    writer
+        .write_all(&len.to_be_bytes())
+        .await
+        .map_err(io_error)?;
+    writer.write_all(&frame).await.map_err(io_error)?;
+    writer.flush().await.map_err(io_error)
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_len: usize,
+) -> Result<WireMessage, PsiError> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len).await.map_err(io_error)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len {
+        return Err(PsiError::InvalidEncoding(format!(
+            "frame of {} bytes exceeds the limit of {}",
+            len, max_len
+        )));
+    }
+    let mut frame = vec![0u8; len];
+    reader.read_exact(&mut frame).await.map_err(io_error)?;
+    wire::decode(&frame)
+}
+
+/// The example reports transport failures as protocol errors to keep a
+/// single error type.
+fn io_error(error: std::io::Error) -> PsiError {
+    PsiError::InvalidEncoding(format!("connection failed: {}", error))
+}
+
+fn items(names: &[&str]) -> Vec<Vec<u8>> {
+    names.iter().map(|name| name.as_bytes().to_vec()).collect()
+}
@@ -0,0 +1,14 @@
+//! Feed arbitrary bytes as the remote's blinded points into `compute`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use psi_protocol::{BlindedPointsMessage, PsiProtocol};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(remote_msg) = BlindedPointsMessage::from_bytes(data) else {
+        return;
+    };
+    let local = PsiProtocol::new(&[b"apple".to_vec(), b"banana".to_vec()]).unwrap();
+    let _ = local.compute(remote_msg);
+});
@@ -0,0 +1,16 @@
+//! Feed arbitrary bytes into the wire decoder.
+//!
+//! Decoding must either succeed or return an error; it must never panic and
+//! never allocate more than the input size suggests.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use psi_protocol::wire;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(msg) = wire::decode(data) {
+        // Anything that decodes must re-encode to the same bytes
+        assert_eq!(wire::encode(&msg), data);
+    }
+});
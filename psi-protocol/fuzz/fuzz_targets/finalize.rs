@@ -0,0 +1,16 @@
+//! Feed arbitrary bytes as the remote's double-blinded points into `finalize`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use psi_protocol::{DoubleBlindedPointsMessage, PsiProtocol};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(remote_double_msg) = DoubleBlindedPointsMessage::from_bytes(data) else {
+        return;
+    };
+    let local = PsiProtocol::new(&[b"apple".to_vec(), b"banana".to_vec()]).unwrap();
+    let remote = PsiProtocol::new(&[b"banana".to_vec()]).unwrap();
+    let (local, _) = local.compute(remote.message()).unwrap();
+    let _ = local.finalize(remote_double_msg);
+});
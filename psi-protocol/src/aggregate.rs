@@ -0,0 +1,140 @@
+//! Combining the results of many sessions.
+//!
+//! Batch jobs intersect one local set against many partners, or the same
+//! partner over many rounds, and want a single answer afterwards: everything
+//! shared with anyone, and how widely each item is shared. [`ResultAggregator`]
+//! merges the [`PsiResult`]s as they come in with [`PsiResult::merge`] and
+//! counts, per item, how many results contained it.
+
+use crate::item_id::ItemId;
+use crate::messages::PsiResult;
+use std::collections::HashMap;
+
+/// Running merge of many [`PsiResult`]s.
+///
+/// # Example
+/// ```ignore
+/// use psi_protocol::ResultAggregator;
+///
+/// let mut aggregator = ResultAggregator::new();
+/// for partner in partners {
+///     aggregator.add(run_with(partner)?);
+/// }
+/// let everywhere = aggregator.shared_by_at_least(aggregator.results());
+/// let merged = aggregator.into_result();
+/// # Ok::<(), psi_protocol::PsiError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ResultAggregator {
+    merged: PsiResult,
+    occurrences: HashMap<ItemId, usize>,
+    results: usize,
+}
+
+impl ResultAggregator {
+    /// Create an aggregator with no results.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Merge one more result.
+    ///
+    /// An item listed twice in the same result counts once.
+    pub fn add(&mut self, result: PsiResult) {
+        let mut seen = std::collections::HashSet::new();
+        for id in &result.intersection_hashes {
+            if seen.insert(*id) {
+                *self.occurrences.entry(*id).or_default() += 1;
+            }
+        }
+        self.merged.merge(result);
+        self.results += 1;
+    }
+
+    /// Number of results added.
+    pub fn results(&self) -> usize {
+        self.results
+    }
+
+    /// Number of results that contained `id`.
+    pub fn occurrences(&self, id: &ItemId) -> usize {
+        self.occurrences.get(id).copied().unwrap_or(0)
+    }
+
+    /// Items found in at least `count` results, in merged order.
+    pub fn shared_by_at_least(&self, count: usize) -> Vec<ItemId> {
+        self.merged
+            .intersection_hashes
+            .iter()
+            .filter(|id| self.occurrences(id) >= count)
+            .copied()
+            .collect()
+    }
+
+    /// The union of every result added so far.
+    pub fn merged(&self) -> &PsiResult {
+        &self.merged
+    }
+
+    /// Consume the aggregator, returning the union of every result.
+    pub fn into_result(self) -> PsiResult {
+        self.merged
+    }
+}
+
+impl Extend<PsiResult> for ResultAggregator {
+    fn extend<I: IntoIterator<Item = PsiResult>>(&mut self, results: I) {
+        for result in results {
+            self.add(result);
+        }
+    }
+}
+
+impl FromIterator<PsiResult> for ResultAggregator {
+    fn from_iter<I: IntoIterator<Item = PsiResult>>(results: I) -> Self {
+        let mut aggregator = Self::new();
+        aggregator.extend(results);
+        aggregator
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local::run_local_psi;
+    use crate::test_util::items;
+
+    #[test]
+    fn test_aggregate_one_set_against_many_partners() {
+        let local = items(&["apple", "banana", "cherry", "date"]);
+        let partners = [
+            items(&["apple", "banana"]),
+            items(&["banana", "cherry"]),
+            items(&["banana", "elderberry"]),
+        ];
+        let aggregator: ResultAggregator = partners
+            .iter()
+            .map(|partner| run_local_psi(&local, partner).unwrap().0)
+            .collect();
+
+        assert_eq!(aggregator.results(), 3);
+        assert_eq!(aggregator.merged().len(), 3);
+        assert_eq!(aggregator.occurrences(&ItemId::of(b"banana")), 3);
+        assert_eq!(aggregator.occurrences(&ItemId::of(b"date")), 0);
+        assert_eq!(
+            aggregator.shared_by_at_least(3),
+            vec![ItemId::of(b"banana")]
+        );
+        let merged = aggregator.into_result();
+        assert_eq!(merged.double_blinded_map.len(), 3);
+    }
+
+    #[test]
+    fn test_duplicate_hashes_count_once_per_result() {
+        let id = ItemId::new([7u8; 32]);
+        let mut aggregator = ResultAggregator::new();
+        aggregator.add(PsiResult::new(vec![id, id], HashMap::new()));
+        assert_eq!(aggregator.occurrences(&id), 1);
+        assert_eq!(aggregator.merged().intersection_hashes, vec![id]);
+    }
+}
@@ -0,0 +1,220 @@
+//! Approximate intersection size from a coordinated sample.
+//!
+//! For exploratory analytics on sets too large for exact PSI, both peers
+//! keep only the items whose hash falls below the same threshold, run exact
+//! PSI on those samples, and scale the number of matches back up. Because
+//! the sampling decision only depends on the item, a shared item is sampled
+//! on both sides or on neither, as with MinHash sketches, so the sample
+//! intersection is a uniform sample of the real one.
+//!
+//! Everything here lives in its own module and returns an
+//! [`IntersectionEstimate`] rather than a [`PsiResult`], so approximate
+//! numbers cannot be mistaken for the output of the exact API.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::approx::{Confidence, SampledSet};
+//! use psi_protocol::PsiProtocol;
+//!
+//! let sample = SampledSet::new(&huge_set, 0.01)?;
+//! let alice = PsiProtocol::new(sample.items())?;
+//! // exchange and finalize as usual...
+//! let estimate = sample.estimate(&result, Confidence::P95);
+//! println!("~{:.0} shared ({:.0}..{:.0})", estimate.estimate, estimate.lower, estimate.upper);
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::error::{PsiError, Result};
+use crate::messages::PsiResult;
+use sha2::{Digest, Sha256};
+
+const SAMPLE_TAG: &[u8] = b"psi-sync/approx/sample";
+
+/// Two-sided confidence level of an [`IntersectionEstimate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confidence {
+    /// 90% confidence
+    P90,
+    /// 95% confidence
+    P95,
+    /// 99% confidence
+    P99,
+}
+
+impl Confidence {
+    /// Standard normal quantile for the level.
+    fn z(self) -> f64 {
+        match self {
+            Confidence::P90 => 1.645,
+            Confidence::P95 => 1.960,
+            Confidence::P99 => 2.576,
+        }
+    }
+}
+
+/// A local set reduced to its coordinated sample.
+///
+/// Both peers must use the same sampling rate.
+#[derive(Debug, Clone)]
+pub struct SampledSet {
+    rate: f64,
+    population: usize,
+    items: Vec<Vec<u8>>,
+}
+
+impl SampledSet {
+    /// Keep each item with probability `rate`, decided by its hash.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `rate` is not in `(0, 1]`
+    pub fn new(items: &[Vec<u8>], rate: f64) -> Result<Self> {
+        if !(rate > 0.0 && rate <= 1.0) {
+            return Err(PsiError::InvalidConfig(format!(
+                "Sampling rate must be in (0, 1], got {}",
+                rate
+            )));
+        }
+        let sampled = items
+            .iter()
+            .filter(|item| is_sampled(item, rate))
+            .cloned()
+            .collect();
+        Ok(Self {
+            rate,
+            population: items.len(),
+            items: sampled,
+        })
+    }
+
+    /// Sampling rate.
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Number of items before sampling.
+    pub fn population(&self) -> usize {
+        self.population
+    }
+
+    /// The sampled items, to run exact PSI on.
+    pub fn items(&self) -> &[Vec<u8>] {
+        &self.items
+    }
+
+    /// Scale the intersection of the two samples back to the full sets.
+    pub fn estimate(&self, result: &PsiResult, confidence: Confidence) -> IntersectionEstimate {
+        IntersectionEstimate::new(result.len(), self.rate, self.population, confidence)
+    }
+}
+
+/// Estimated size of the intersection of the full sets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntersectionEstimate {
+    /// Shared items found in the samples
+    pub sample_matches: usize,
+    /// Point estimate of the intersection size
+    pub estimate: f64,
+    /// Lower bound of the confidence interval
+    pub lower: f64,
+    /// Upper bound of the confidence interval
+    pub upper: f64,
+}
+
+impl IntersectionEstimate {
+    /// Estimate from `sample_matches` shared items found at sampling `rate`.
+    ///
+    /// Each shared item is sampled independently with probability `rate`, so
+    /// the matches are binomial and the interval uses the normal
+    /// approximation. The bounds are clamped to `[sample_matches,
+    /// population]`, where `population` is the local set size.
+    pub fn new(
+        sample_matches: usize,
+        rate: f64,
+        population: usize,
+        confidence: Confidence,
+    ) -> Self {
+        let matches = sample_matches as f64;
+        let estimate = matches / rate;
+        // With no match at all, still report the width of a single one
+        let std_dev = (matches.max(1.0) * (1.0 - rate)).sqrt() / rate;
+        let margin = confidence.z() * std_dev;
+        let population = population as f64;
+        Self {
+            sample_matches,
+            estimate: estimate.min(population),
+            lower: (estimate - margin).max(matches),
+            upper: (estimate + margin).min(population),
+        }
+    }
+}
+
+/// Whether the coordinated sample at `rate` keeps an item.
+fn is_sampled(item: &[u8], rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    let digest = Sha256::new()
+        .chain_update(SAMPLE_TAG)
+        .chain_update(item)
+        .finalize();
+    let mut key = [0u8; 8];
+    key.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(key) as f64) < rate * u64::MAX as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local::run_local_psi;
+    use crate::test_util::numbered;
+
+    #[test]
+    fn test_sampling_is_coordinated() {
+        let alice = SampledSet::new(&numbered(0..20_000), 0.01).unwrap();
+        let bob = SampledSet::new(&numbered(10_000..30_000), 0.01).unwrap();
+        assert_eq!(alice.population(), 20_000);
+
+        // A shared item is sampled on both sides or on neither
+        let bob_sampled: std::collections::HashSet<_> = bob.items().iter().collect();
+        for item in alice.items() {
+            let index: u32 = String::from_utf8_lossy(&item[5..]).parse().unwrap();
+            if index >= 10_000 {
+                assert!(bob_sampled.contains(item));
+            }
+        }
+        // Roughly 1% kept
+        assert!((150..250).contains(&alice.items().len()));
+    }
+
+    #[test]
+    fn test_estimate_brackets_true_intersection() {
+        let alice = SampledSet::new(&numbered(0..20_000), 0.01).unwrap();
+        let bob = SampledSet::new(&numbered(10_000..30_000), 0.01).unwrap();
+        let (result, _) = run_local_psi(alice.items(), bob.items()).unwrap();
+
+        let estimate = alice.estimate(&result, Confidence::P99);
+        assert!(estimate.lower <= 10_000.0 && 10_000.0 <= estimate.upper);
+        assert!(estimate.lower <= estimate.estimate && estimate.estimate <= estimate.upper);
+    }
+
+    #[test]
+    fn test_estimate_bounds() {
+        let exact = IntersectionEstimate::new(42, 1.0, 100, Confidence::P95);
+        assert_eq!(exact.estimate, 42.0);
+        assert_eq!((exact.lower, exact.upper), (42.0, 42.0));
+
+        let none = IntersectionEstimate::new(0, 0.1, 1000, Confidence::P95);
+        assert_eq!(none.lower, 0.0);
+        assert!(none.upper > 0.0);
+    }
+
+    #[test]
+    fn test_sampled_set_rejects_bad_rate() {
+        for rate in [0.0, -1.0, 1.5, f64::NAN] {
+            assert!(matches!(
+                SampledSet::new(&numbered(0..4), rate),
+                Err(PsiError::InvalidConfig(_))
+            ));
+        }
+    }
+}
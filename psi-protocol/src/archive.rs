@@ -0,0 +1,275 @@
+//! Zero-copy archives of the points messages.
+//!
+//! Decoding a [`wire`](crate::wire) frame copies every point into a fresh
+//! `Vec`. Services that receive messages of tens of megabytes can instead
+//! send an rkyv archive and read the points in place: [`PointsView::new`]
+//! only checks the archive layout (bounds and lengths), and each point is
+//! decompressed later, when `compute` or `finalize` actually uses it.
+//! Routing, rate limiting and size checks can work on the view alone; only
+//! [`PointsView::to_blinded`] and [`PointsView::to_double_blinded`] copy the
+//! points out of the buffer.
+//!
+//! Archives must be read from a buffer aligned to 16 bytes, such as
+//! `rkyv::util::AlignedVec`; an unaligned buffer is rejected, not copied.
+//!
+//! Requires the `rkyv` feature.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::archive::PointsView;
+//!
+//! let bytes = alice.message().to_archive();
+//! // ...send, receive into an AlignedVec...
+//! let view = PointsView::new(&received)?;
+//! let (bob, bob_double_msg) = bob.compute(view.to_blinded()?)?;
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::error::{PsiError, Result};
+use crate::messages::{
+    BlindedPointsMessage, DoubleBlindedPointsMessage, MessageMac, ParameterDigest,
+};
+use crate::wire::MessageKind;
+use curve25519_dalek::ristretto::CompressedRistretto;
+use rkyv::rancor;
+use rkyv::util::AlignedVec;
+
+/// Archived layout of a points message.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+struct PointsRecord {
+    kind: u8,
+    points: Vec<[u8; 32]>,
+    mac: Option<(u32, [u8; 32])>,
+    parameters: Option<[u8; 32]>,
+}
+
+impl PointsRecord {
+    fn archive(
+        kind: MessageKind,
+        points: &[CompressedRistretto],
+        mac: Option<&MessageMac>,
+        parameters: Option<&ParameterDigest>,
+    ) -> AlignedVec {
+        let record = PointsRecord {
+            kind: kind as u8,
+            points: points.iter().map(|point| point.to_bytes()).collect(),
+            mac: mac.map(|mac| (mac.key_id, mac.tag)),
+            parameters: parameters.map(|digest| digest.0),
+        };
+        rkyv::to_bytes::<rancor::Error>(&record).expect("points records always serialize")
+    }
+}
+
+/// Borrowed, validated view of an archived points message.
+pub struct PointsView<'a> {
+    record: &'a ArchivedPointsRecord,
+}
+
+impl<'a> PointsView<'a> {
+    /// Check the archive layout and borrow it.
+    ///
+    /// Points are not decompressed here; invalid encodings are reported by
+    /// `compute`/`finalize` as for any other message.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` if `bytes` is not an aligned
+    /// points archive or carries an unknown message kind
+    pub fn new(bytes: &'a [u8]) -> Result<Self> {
+        let record = rkyv::access::<ArchivedPointsRecord, rancor::Error>(bytes)
+            .map_err(|e| PsiError::InvalidEncoding(format!("Invalid points archive: {}", e)))?;
+        let view = Self { record };
+        view.kind()?;
+        Ok(view)
+    }
+
+    /// Kind of message archived.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` if the kind is not blinded or
+    /// double-blinded points
+    pub fn kind(&self) -> Result<MessageKind> {
+        match self.record.kind {
+            kind if kind == MessageKind::Blinded as u8 => Ok(MessageKind::Blinded),
+            kind if kind == MessageKind::DoubleBlinded as u8 => Ok(MessageKind::DoubleBlinded),
+            other => Err(PsiError::InvalidEncoding(format!(
+                "Unexpected message kind {} in points archive",
+                other
+            ))),
+        }
+    }
+
+    /// The compressed points, borrowed from the buffer.
+    pub fn points(&self) -> &'a [[u8; 32]] {
+        self.record.points.as_slice()
+    }
+
+    /// Number of points in the message.
+    pub fn len(&self) -> usize {
+        self.record.points.len()
+    }
+
+    /// Returns true if the message holds no points.
+    pub fn is_empty(&self) -> bool {
+        self.record.points.is_empty()
+    }
+
+    /// Point at `index`, if any.
+    pub fn get(&self, index: usize) -> Option<CompressedRistretto> {
+        self.points()
+            .get(index)
+            .map(|bytes| CompressedRistretto(*bytes))
+    }
+
+    /// Iterate over the points without collecting them.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = CompressedRistretto> + 'a {
+        self.points()
+            .iter()
+            .map(|bytes| CompressedRistretto(*bytes))
+    }
+
+    /// Rebuild a blinded points message.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` if the archive holds another kind
+    pub fn to_blinded(&self) -> Result<BlindedPointsMessage> {
+        self.expect(MessageKind::Blinded)?;
+        let mut msg = BlindedPointsMessage::new(self.iter().collect());
+        msg.authentication = self.mac();
+        msg.parameters = self.parameters();
+        Ok(msg)
+    }
+
+    /// Rebuild a double-blinded points message.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` if the archive holds another kind
+    pub fn to_double_blinded(&self) -> Result<DoubleBlindedPointsMessage> {
+        self.expect(MessageKind::DoubleBlinded)?;
+        let mut msg = DoubleBlindedPointsMessage::new(self.iter().collect());
+        msg.authentication = self.mac();
+        msg.parameters = self.parameters();
+        Ok(msg)
+    }
+
+    fn expect(&self, expected: MessageKind) -> Result<()> {
+        let kind = self.kind()?;
+        if kind != expected {
+            return Err(PsiError::InvalidEncoding(format!(
+                "Expected {:?} archive, found {:?}",
+                expected, kind
+            )));
+        }
+        Ok(())
+    }
+
+    fn mac(&self) -> Option<MessageMac> {
+        self.record
+            .mac
+            .as_ref()
+            .map(|mac| MessageMac::new(mac.0.to_native(), mac.1))
+    }
+
+    fn parameters(&self) -> Option<ParameterDigest> {
+        self.record
+            .parameters
+            .as_ref()
+            .map(|digest| ParameterDigest(*digest))
+    }
+}
+
+impl std::fmt::Debug for PointsView<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PointsView")
+            .field("kind", &self.record.kind)
+            .field("len", &self.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl BlindedPointsMessage {
+    /// Archive this message for zero-copy reading with [`PointsView`].
+    pub fn to_archive(&self) -> AlignedVec {
+        PointsRecord::archive(
+            MessageKind::Blinded,
+            &self.blinded_points,
+            self.authentication.as_ref(),
+            self.parameters.as_ref(),
+        )
+    }
+}
+
+impl DoubleBlindedPointsMessage {
+    /// Archive this message for zero-copy reading with [`PointsView`].
+    pub fn to_archive(&self) -> AlignedVec {
+        PointsRecord::archive(
+            MessageKind::DoubleBlinded,
+            &self.double_blinded_points,
+            self.authentication.as_ref(),
+            self.parameters.as_ref(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::PsiProtocol;
+
+    #[test]
+    fn test_archive_round_trip() {
+        let mut msg = BlindedPointsMessage::new(vec![
+            CompressedRistretto([1u8; 32]),
+            CompressedRistretto([2u8; 32]),
+        ]);
+        msg.authentication = Some(MessageMac::new(3, [4u8; 32]));
+        msg.parameters = Some(ParameterDigest([5u8; 32]));
+        let bytes = msg.to_archive();
+
+        let view = PointsView::new(&bytes).unwrap();
+        assert_eq!(view.kind().unwrap(), MessageKind::Blinded);
+        assert_eq!(view.len(), 2);
+        assert_eq!(view.get(1), Some(CompressedRistretto([2u8; 32])));
+        assert_eq!(view.to_blinded().unwrap(), msg);
+        assert!(view.to_double_blinded().is_err());
+    }
+
+    #[test]
+    fn test_archived_messages_run_protocol() {
+        let alice = PsiProtocol::new(&[b"apple".to_vec(), b"banana".to_vec()]).unwrap();
+        let bob = PsiProtocol::new(&[b"banana".to_vec()]).unwrap();
+        let alice_bytes = alice.message().to_archive();
+        let bob_bytes = bob.message().to_archive();
+
+        let (alice, alice_double) = alice
+            .compute(PointsView::new(&bob_bytes).unwrap().to_blinded().unwrap())
+            .unwrap();
+        let (bob, bob_double) = bob
+            .compute(PointsView::new(&alice_bytes).unwrap().to_blinded().unwrap())
+            .unwrap();
+        let bob_double = bob_double.to_archive();
+        let (_, result) = alice
+            .finalize(
+                PointsView::new(&bob_double)
+                    .unwrap()
+                    .to_double_blinded()
+                    .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(result.intersection_hashes.len(), 1);
+        assert!(bob.finalize(alice_double).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_malformed_archives() {
+        let bytes = BlindedPointsMessage::new(vec![CompressedRistretto([1u8; 32])]).to_archive();
+        for len in 0..bytes.len() {
+            let mut truncated = AlignedVec::<16>::new();
+            truncated.extend_from_slice(&bytes[..len]);
+            assert!(
+                PointsView::new(&truncated).is_err(),
+                "length {} accepted",
+                len
+            );
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Arrow arrays as protocol input and output.
+//!
+//! Identifier sets in analytics pipelines (DataFusion, Polars, DuckDB)
+//! already sit in Arrow memory. [`PsiProtocol::from_arrow`] and
+//! [`PsiProtocol::from_record_batch`] hash the values of a binary or string
+//! column in place, without first copying them into `Vec<Vec<u8>>`, and
+//! [`PsiResult::to_record_batch`] hands the intersection back as a batch.
+//!
+//! Supported input types are `Binary`, `LargeBinary`, `BinaryView`,
+//! `FixedSizeBinary`, `Utf8`, `LargeUtf8` and `Utf8View`. String values are
+//! hashed as their UTF-8 bytes, so they match the same strings passed as
+//! `Vec<u8>` on the other side. Null values are skipped.
+//!
+//! Requires the `arrow` feature.
+
+use crate::config::PsiConfig;
+use crate::crypto::{hash_item_with, hash_to_point_in, parallel_map, random_scalar};
+use crate::error::{PsiError, Result};
+use crate::messages::PsiResult;
+use crate::protocol::PsiProtocol;
+use crate::state::PreparedState;
+use arrow_array::builder::FixedSizeBinaryBuilder;
+use arrow_array::cast::AsArray;
+use arrow_array::{Array, ArrayRef, FixedSizeBinaryArray, RecordBatch};
+use std::sync::Arc;
+
+/// Name of the id column in [`PsiResult::to_record_batch`].
+pub const ITEM_ID_COLUMN: &str = "item_id";
+
+/// Hash every non-null value of a binary or string array.
+pub(crate) fn hash_array(array: &dyn Array, config: &PsiConfig) -> Result<Vec<[u8; 32]>> {
+    let hash = |value: &[u8]| hash_item_with(config.hash(), value);
+    let hashes = if let Some(values) = array.as_binary_opt::<i32>() {
+        values.iter().flatten().map(hash).collect()
+    } else if let Some(values) = array.as_binary_opt::<i64>() {
+        values.iter().flatten().map(hash).collect()
+    } else if let Some(values) = array.as_binary_view_opt() {
+        values.iter().flatten().map(hash).collect()
+    } else if let Some(values) = array.as_fixed_size_binary_opt() {
+        values.iter().flatten().map(hash).collect()
+    } else if let Some(values) = array.as_string_opt::<i32>() {
+        values
+            .iter()
+            .flatten()
+            .map(|value| hash(value.as_bytes()))
+            .collect()
+    } else if let Some(values) = array.as_string_opt::<i64>() {
+        values
+            .iter()
+            .flatten()
+            .map(|value| hash(value.as_bytes()))
+            .collect()
+    } else if let Some(values) = array.as_string_view_opt() {
+        values
+            .iter()
+            .flatten()
+            .map(|value| hash(value.as_bytes()))
+            .collect()
+    } else {
+        return Err(PsiError::InvalidConfig(format!(
+            "Unsupported Arrow type {} for protocol input",
+            array.data_type()
+        )));
+    };
+    Ok(hashes)
+}
+
+impl PsiProtocol<PreparedState> {
+    /// Build a prepared protocol from the values of an Arrow array.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if the array is not binary or
+    /// string, `PsiError::EmptyInput` if it has no non-null value, or
+    /// `PsiError::LimitExceeded` if it has more unique values than the
+    /// configured local limit
+    ///
+    /// # Example
+    /// ```ignore
+    /// let emails = batch.column_by_name("email").unwrap();
+    /// let alice = PsiProtocol::from_arrow(emails.as_ref(), PsiConfig::default())?;
+    /// # Ok::<(), psi_protocol::PsiError>(())
+    /// ```
+    pub fn from_arrow(array: &dyn Array, config: PsiConfig) -> Result<Self> {
+        let hashes = hash_array(array, &config)?;
+        Self::from_item_hashes(hashes, config)
+    }
+
+    /// Build a prepared protocol from unsorted item hashes, as produced by
+    /// [`hash_array`].
+    pub(crate) fn from_item_hashes(mut hashes: Vec<[u8; 32]>, config: PsiConfig) -> Result<Self> {
+        hashes.sort_unstable();
+        hashes.dedup();
+        config.check_local_len(hashes.len())?;
+        let hashed = parallel_map(&hashes, config.hash_threads(), |hash| {
+            (*hash, hash_to_point_in(config.domain(), hash))
+        });
+        Self::from_hashed(&hashed, random_scalar(), config)
+    }
+
+    /// Build a prepared protocol from one column of a record batch.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if the batch has no column named
+    /// `column`, plus the errors of [`from_arrow`](Self::from_arrow)
+    pub fn from_record_batch(batch: &RecordBatch, column: &str, config: PsiConfig) -> Result<Self> {
+        let array = batch.column_by_name(column).ok_or_else(|| {
+            PsiError::InvalidConfig(format!("Record batch has no column {:?}", column))
+        })?;
+        Self::from_arrow(array.as_ref(), config)
+    }
+}
+
+impl PsiResult {
+    /// The intersection ids as a `FixedSizeBinary(32)` array, in
+    /// `intersection_hashes` order.
+    pub fn to_arrow(&self) -> FixedSizeBinaryArray {
+        let mut builder = FixedSizeBinaryBuilder::with_capacity(self.intersection_hashes.len(), 32);
+        for id in &self.intersection_hashes {
+            builder
+                .append_value(id.as_bytes())
+                .expect("item ids are 32 bytes");
+        }
+        builder.finish()
+    }
+
+    /// The intersection as a record batch with one [`ITEM_ID_COLUMN`].
+    ///
+    /// Join it back to the input on a column holding
+    /// [`ItemId`](crate::ItemId)s of the input values.
+    pub fn to_record_batch(&self) -> RecordBatch {
+        let ids: ArrayRef = Arc::new(self.to_arrow());
+        RecordBatch::try_from_iter([(ITEM_ID_COLUMN, ids)])
+            .expect("a single column always forms a valid batch")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::item_id::ItemId;
+    use arrow_array::{BinaryArray, Int32Array, StringArray};
+
+    fn run(alice: PsiProtocol<PreparedState>, bob_items: &[&[u8]]) -> PsiResult {
+        let bob_items: Vec<Vec<u8>> = bob_items.iter().map(|item| item.to_vec()).collect();
+        let bob = PsiProtocol::new(&bob_items).unwrap();
+        let alice_msg = alice.message();
+        let (alice, _) = alice.compute(bob.message()).unwrap();
+        let (_, bob_double) = bob.compute(alice_msg).unwrap();
+        alice.finalize(bob_double).unwrap().1
+    }
+
+    #[test]
+    fn test_string_and_binary_columns() {
+        let strings = StringArray::from(vec![Some("apple"), None, Some("banana"), Some("apple")]);
+        let alice = PsiProtocol::from_arrow(&strings, PsiConfig::default()).unwrap();
+        assert_eq!(alice.message().len(), 2);
+        let result = run(alice, &[b"banana", b"cherry"]);
+        assert_eq!(result.intersection_hashes, vec![ItemId::of(b"banana")]);
+
+        let binary: ArrayRef = Arc::new(BinaryArray::from(vec![&b"cherry"[..], b"date"]));
+        let batch = RecordBatch::try_from_iter([("email", binary)]).unwrap();
+        let alice = PsiProtocol::from_record_batch(&batch, "email", PsiConfig::default()).unwrap();
+        let result = run(alice, &[b"banana", b"cherry"]);
+
+        let output = result.to_record_batch();
+        assert_eq!(output.num_rows(), 1);
+        let ids = output
+            .column_by_name(ITEM_ID_COLUMN)
+            .unwrap()
+            .as_fixed_size_binary();
+        assert_eq!(ids.value(0), ItemId::of(b"cherry").as_bytes());
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        let numbers = Int32Array::from(vec![1, 2]);
+        assert!(matches!(
+            PsiProtocol::from_arrow(&numbers, PsiConfig::default()),
+            Err(PsiError::InvalidConfig(_))
+        ));
+        let nulls = StringArray::from(vec![None::<&str>]);
+        assert!(matches!(
+            PsiProtocol::from_arrow(&nulls, PsiConfig::default()),
+            Err(PsiError::EmptyInput)
+        ));
+
+        let column: ArrayRef = Arc::new(StringArray::from(vec!["apple"]));
+        let batch = RecordBatch::try_from_iter([("email", column)]).unwrap();
+        assert!(matches!(
+            PsiProtocol::from_record_batch(&batch, "phone", PsiConfig::default()),
+            Err(PsiError::InvalidConfig(_))
+        ));
+        assert_eq!(
+            PsiResult::new(vec![], Default::default()).to_arrow().len(),
+            0
+        );
+    }
+}
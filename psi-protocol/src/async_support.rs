@@ -0,0 +1,620 @@
+//! Async wrappers that keep CPU-heavy phases off the async runtime.
+//!
+//! Hashing, blinding and double-blinding large sets can take seconds. Calling
+//! them directly from an async task stalls every other task on that worker
+//! thread, so these helpers run each phase on tokio's blocking thread pool.
+//!
+//! [`PsiProtocol::send_chunked`] and [`PsiProtocol::compute_chunked`] send a
+//! message in chunks over a [`ChunkTransport`], producing each chunk only
+//! when the [`FlowControl`] window has room, so a slow link never has more
+//! than the configured number of chunks buffered.
+//!
+//! Every future here can be dropped at any point: work already handed to
+//! the blocking pool finishes its current chunk, then the protocol state is
+//! dropped and its secret zeroized. [`PhaseLimits`] builds on that to give
+//! each phase a deadline and a [`CancelToken`], so a stuck peer cannot hang
+//! a task forever and a large computation can be stopped from elsewhere:
+//!
+//! ```ignore
+//! let cancel = CancelToken::new();
+//! let limits = PhaseLimits::new().timeout(Duration::from_secs(30)).cancel_with(cancel.clone());
+//!
+//! let bob_msg = limits.run(Phase::Compute, transport.recv_blinded()).await?;
+//! let (alice, reply) = limits.run(Phase::Compute, alice.compute_async(bob_msg)).await?;
+//! // elsewhere: cancel.cancel();
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+//!
+//! Requires the `tokio` feature and must be called from within a tokio runtime.
+
+use crate::config::PsiConfig;
+use crate::crypto::decompress_or_basepoint;
+use crate::error::{AbortReason, Phase, PsiError, Result};
+use crate::flow::FlowControl;
+use crate::messages::{BlindedPointsMessage, DoubleBlindedPointsMessage, PsiResult};
+use crate::protocol::PsiProtocol;
+use crate::state::{DoubleBlindedState, FinalState, PreparedState};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use std::future::Future;
+use std::ops::Range;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Remote points double-blinded per blocking task by
+/// [`compute_async`](PsiProtocol::compute_async); a dropped future stops
+/// after at most this many.
+const ASYNC_CHUNK: usize = 4096;
+
+/// Cooperative cancellation shared by clones.
+///
+/// Cancelling any clone cancels them all, and every phase run under
+/// [`PhaseLimits`] holding one of them stops with
+/// `PsiError::Aborted(AbortReason::Cancelled)`.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<CancelState>,
+}
+
+#[derive(Debug, Default)]
+struct CancelState {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelToken {
+    /// Create a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel every phase waiting on this token, now and later.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Release);
+        self.inner.notify.notify_waiters();
+    }
+
+    /// Returns true once [`cancel`](Self::cancel) was called.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Wait until the token is cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            // Register before checking, so a cancel in between is not missed
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Deadline and cancellation applied to each phase of an async run.
+///
+/// The deadline counts from the start of each [`run`](Self::run) call, so
+/// one value bounds every phase separately.
+#[derive(Debug, Clone, Default)]
+pub struct PhaseLimits {
+    timeout: Option<Duration>,
+    cancel: Option<CancelToken>,
+}
+
+impl PhaseLimits {
+    /// Limits that never expire nor cancel.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give each phase at most `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Stop phases once `token` is cancelled.
+    pub fn cancel_with(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Run one phase, e.g. a computation or a wait for the peer's message.
+    ///
+    /// When the limits stop the phase, `phase` is dropped: with the futures
+    /// of this module, the protocol state it owns is zeroized.
+    ///
+    /// # Errors
+    /// Returns `PsiError::Aborted(AbortReason::Cancelled)` if the token is
+    /// cancelled first, `PsiError::DeadlineExceeded` with `name` if the
+    /// timeout runs out first, or the phase's own error
+    pub async fn run<T>(&self, name: Phase, phase: impl Future<Output = Result<T>>) -> Result<T> {
+        let mut phase = pin!(phase);
+        let mut cancelled = pin!(async {
+            match &self.cancel {
+                Some(token) => token.cancelled().await,
+                None => std::future::pending().await,
+            }
+        });
+        let mut expired = pin!(async {
+            match self.timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        });
+        std::future::poll_fn(|cx| {
+            if cancelled.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Err(PsiError::Aborted(AbortReason::Cancelled)));
+            }
+            if let Poll::Ready(outcome) = phase.as_mut().poll(cx) {
+                return Poll::Ready(outcome);
+            }
+            if expired.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Err(PsiError::DeadlineExceeded { phase: name }));
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// Transport carrying the chunks of one message and their acknowledgements.
+///
+/// Chunks carry only points: the receiver must set the `parameters` of the
+/// reassembled message before `compute` or `finalize` accept it.
+pub trait ChunkTransport {
+    /// Send chunk `index` of the message.
+    fn send_chunk(
+        &mut self,
+        index: usize,
+        points: &[CompressedRistretto],
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Wait for the remote to acknowledge a chunk and return its index.
+    fn recv_ack(&mut self) -> impl Future<Output = Result<usize>> + Send;
+}
+
+/// Send chunks as the window allows until every chunk is acknowledged.
+///
+/// `produce` is only called for a chunk once the window has room for it;
+/// `sent` receives each chunk after the transport accepted it.
+async fn drive_chunks<T, F, Fut>(
+    total: usize,
+    chunk_size: usize,
+    max_in_flight: usize,
+    transport: &mut T,
+    mut produce: F,
+    mut sent: impl FnMut(Vec<CompressedRistretto>),
+) -> Result<()>
+where
+    T: ChunkTransport,
+    F: FnMut(Range<usize>) -> Fut,
+    Fut: Future<Output = Result<Vec<CompressedRistretto>>>,
+{
+    let mut flow = FlowControl::new(total, chunk_size, max_in_flight)?;
+    loop {
+        while let Some((index, range)) = flow.next_chunk() {
+            let points = produce(range).await?;
+            transport.send_chunk(index, &points).await?;
+            sent(points);
+        }
+        if flow.is_done() {
+            return Ok(());
+        }
+        let index = transport.recv_ack().await?;
+        flow.ack(index)?;
+    }
+}
+
+/// Run a closure on the blocking pool and flatten the join result.
+///
+/// Panics inside the closure are propagated to the caller.
+async fn offload<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(PsiError::TaskFailed(e.to_string())),
+    }
+}
+
+/// Shift the index of an `InvalidPoint` found in a chunk starting at `start`.
+fn absolute_index(error: PsiError, start: usize) -> PsiError {
+    match error {
+        PsiError::InvalidPoint { phase, index } => PsiError::InvalidPoint {
+            phase,
+            index: start + index,
+        },
+        other => other,
+    }
+}
+
+/// Decode every point, then return the position of the first invalid one
+/// and how many there are.
+fn invalid_points(points: &[CompressedRistretto]) -> (Option<usize>, usize) {
+    let valid: Vec<bool> = points
+        .iter()
+        .map(|point| decompress_or_basepoint(point).1)
+        .collect();
+    let invalid = valid.iter().filter(|valid| !**valid).count();
+    (valid.iter().position(|valid| !valid), invalid)
+}
+
+impl PsiProtocol<PreparedState> {
+    /// Async version of [`PsiProtocol::new_with_config`].
+    ///
+    /// Takes ownership of the items so they can be moved to the blocking pool.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::new_with_config`], plus `PsiError::TaskFailed`
+    /// if the blocking task was cancelled (e.g. runtime shutdown)
+    ///
+    /// # Example
+    /// ```ignore
+    /// let alice = PsiProtocol::new_async(items, PsiConfig::default()).await?;
+    /// ```
+    pub async fn new_async(items: Vec<Vec<u8>>, config: PsiConfig) -> Result<Self> {
+        offload(move || Self::new_with_config(&items, config)).await
+    }
+
+    /// Async version of [`PsiProtocol::compute`].
+    ///
+    /// The remote points are double-blinded in chunks, one blocking task
+    /// each, so dropping the future (e.g. from [`PhaseLimits::run`]) stops
+    /// the computation after the current chunk and zeroizes the state.
+    /// Every chunk is processed before an invalid point is reported, as in
+    /// `compute`.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::compute`], plus `PsiError::TaskFailed` if the
+    /// blocking task was cancelled
+    pub async fn compute_async(
+        self,
+        remote_msg: BlindedPointsMessage,
+    ) -> Result<(PsiProtocol<DoubleBlindedState>, DoubleBlindedPointsMessage)> {
+        let started = Instant::now();
+        self.config().check_remote_len(remote_msg.len())?;
+        self.check_message(&remote_msg)?;
+        let protocol = Arc::new(self);
+        let remote = Arc::new(remote_msg);
+        let mut double_blinded = Vec::with_capacity(remote.len());
+        let mut invalid = 0;
+        let mut first_invalid = None;
+
+        for start in (0..remote.len()).step_by(ASYNC_CHUNK) {
+            let range = start..remote.len().min(start + ASYNC_CHUNK);
+            let chunk = BlindedPointsMessage::new(remote.blinded_points[range].to_vec());
+            let worker = Arc::clone(&protocol);
+            match offload(move || worker.double_blind_counted(&chunk)).await {
+                Ok((points, chunk_invalid)) => {
+                    double_blinded.extend(points);
+                    invalid += chunk_invalid;
+                }
+                // Keep going, so the time to fail does not tell the remote
+                // which chunk held the invalid point
+                Err(error @ PsiError::InvalidPoint { .. }) => {
+                    first_invalid.get_or_insert(absolute_index(error, start));
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        if let Some(error) = first_invalid {
+            return Err(error);
+        }
+        Ok(protocol.to_double_blinded_since(started, &remote, double_blinded, invalid))
+    }
+
+    /// Send our [`message`](Self::message) in chunks with flow control.
+    ///
+    /// At most `max_in_flight` chunks of `chunk_size` points are sent
+    /// before the remote acknowledges one of them.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `chunk_size` or `max_in_flight`
+    /// is zero, `PsiError::InvalidEncoding` on an unexpected
+    /// acknowledgement, plus any transport error
+    pub async fn send_chunked<T: ChunkTransport>(
+        &self,
+        chunk_size: usize,
+        max_in_flight: usize,
+        transport: &mut T,
+    ) -> Result<()> {
+        let points = self.state().message_points();
+        drive_chunks(
+            points.len(),
+            chunk_size,
+            max_in_flight,
+            transport,
+            |range| std::future::ready(Ok(points[range].to_vec())),
+            drop,
+        )
+        .await
+    }
+
+    /// Like [`compute_async`](Self::compute_async), but double-blinds and
+    /// sends the answer in chunks with flow control.
+    ///
+    /// Each chunk is double-blinded on the blocking pool only when the
+    /// window has room for it, so a slow link throttles the computation
+    /// instead of piling up unsent points. The whole message is decoded
+    /// before the first chunk, so an invalid point is reported before
+    /// anything is sent, whichever chunk holds it. The returned state is
+    /// finalized as usual.
+    ///
+    /// # Errors
+    /// Same as [`compute`](Self::compute), plus the errors of
+    /// [`send_chunked`](Self::send_chunked)
+    pub async fn compute_chunked<T: ChunkTransport>(
+        self,
+        remote_msg: BlindedPointsMessage,
+        chunk_size: usize,
+        max_in_flight: usize,
+        transport: &mut T,
+    ) -> Result<PsiProtocol<DoubleBlindedState>> {
+        let started = Instant::now();
+        self.config().check_remote_len(remote_msg.len())?;
+        self.check_message(&remote_msg)?;
+        let lenient = self.config().lenient();
+        let protocol = Arc::new(self);
+        let remote = Arc::new(remote_msg);
+
+        let decoded = Arc::clone(&remote);
+        let (first_invalid, invalid) =
+            offload(move || Ok(invalid_points(&decoded.blinded_points))).await?;
+        if let (Some(index), false) = (first_invalid, lenient) {
+            return Err(PsiError::InvalidPoint {
+                phase: Phase::Compute,
+                index,
+            });
+        }
+
+        let mut double_blinded = Vec::with_capacity(remote.len());
+        drive_chunks(
+            remote.len(),
+            chunk_size,
+            max_in_flight,
+            transport,
+            |range| {
+                let protocol = Arc::clone(&protocol);
+                let remote = Arc::clone(&remote);
+                async move {
+                    let chunk = BlindedPointsMessage::new(remote.blinded_points[range].to_vec());
+                    offload(move || protocol.double_blind(&chunk)).await
+                }
+            },
+            |points| double_blinded.extend(points),
+        )
+        .await?;
+        Ok(protocol
+            .to_double_blinded_since(started, &remote, double_blinded, invalid)
+            .0)
+    }
+}
+
+impl PsiProtocol<DoubleBlindedState> {
+    /// Async version of [`PsiProtocol::finalize`].
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::finalize`], plus `PsiError::TaskFailed` if the
+    /// blocking task was cancelled
+    pub async fn finalize_async(
+        self,
+        remote_msg: DoubleBlindedPointsMessage,
+    ) -> Result<(PsiProtocol<FinalState>, PsiResult)> {
+        offload(move || self.finalize(remote_msg)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Phase;
+    use std::collections::VecDeque;
+
+    /// Loopback transport that acknowledges chunks in order on request.
+    #[derive(Default)]
+    struct Loopback {
+        chunks: Vec<(usize, Vec<CompressedRistretto>)>,
+        pending: VecDeque<usize>,
+        max_pending: usize,
+    }
+
+    impl ChunkTransport for Loopback {
+        async fn send_chunk(&mut self, index: usize, points: &[CompressedRistretto]) -> Result<()> {
+            self.chunks.push((index, points.to_vec()));
+            self.pending.push_back(index);
+            self.max_pending = self.max_pending.max(self.pending.len());
+            Ok(())
+        }
+
+        async fn recv_ack(&mut self) -> Result<usize> {
+            self.pending
+                .pop_front()
+                .ok_or_else(|| PsiError::TaskFailed("no chunk in flight".to_string()))
+        }
+    }
+
+    impl Loopback {
+        fn points(&self) -> Vec<CompressedRistretto> {
+            self.chunks
+                .iter()
+                .flat_map(|(_, points)| points.clone())
+                .collect()
+        }
+    }
+
+    fn letters(range: std::ops::Range<u8>) -> Vec<Vec<u8>> {
+        range.map(|byte| vec![byte]).collect()
+    }
+
+    #[tokio::test]
+    async fn test_send_chunked_respects_window() {
+        let alice = PsiProtocol::new(&letters(0..10)).unwrap();
+        let mut transport = Loopback::default();
+        alice.send_chunked(3, 2, &mut transport).await.unwrap();
+
+        assert_eq!(transport.chunks.len(), 4);
+        assert_eq!(transport.max_pending, 2);
+        assert_eq!(transport.points(), alice.message().blinded_points);
+    }
+
+    #[tokio::test]
+    async fn test_compute_chunked_matches_compute() {
+        let alice = PsiProtocol::new(&letters(0..10)).unwrap();
+        let bob = PsiProtocol::new(&letters(5..12)).unwrap();
+        let bob_msg = bob.message();
+
+        let mut transport = Loopback::default();
+        let bob_state = bob
+            .clone()
+            .compute_chunked(alice.message(), 4, 1, &mut transport)
+            .await
+            .unwrap();
+        assert_eq!(transport.max_pending, 1);
+        let (_, expected) = bob.compute(alice.message()).unwrap();
+        assert_eq!(transport.points(), expected.double_blinded_points);
+
+        let (alice_state, alice_double) = alice.compute(bob_msg).unwrap();
+        let (_, bob_result) = bob_state.finalize(alice_double).unwrap();
+        // Chunks carry no digest; the reassembled answer gets the sender's
+        let mut reassembled = DoubleBlindedPointsMessage::new(transport.points());
+        reassembled.parameters = expected.parameters;
+        let (_, alice_result) = alice_state.finalize(reassembled).unwrap();
+        assert_eq!(bob_result.len(), 5);
+        assert_eq!(
+            alice_result.double_blinded_map,
+            bob_result.double_blinded_map
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compute_chunked_reports_absolute_index() {
+        let bob = PsiProtocol::new(&letters(0..2)).unwrap();
+        let mut points = PsiProtocol::new(&letters(0..6)).unwrap().message();
+        points.blinded_points[4] = CompressedRistretto([0xff; 32]);
+
+        let mut transport = Loopback::default();
+        assert_eq!(
+            bob.compute_chunked(points.clone(), 2, 2, &mut transport)
+                .await
+                .unwrap_err(),
+            PsiError::InvalidPoint {
+                phase: Phase::Compute,
+                index: 4
+            }
+        );
+        // The point sits in the third chunk, yet no chunk went out
+        assert!(transport.chunks.is_empty());
+        let config = PsiConfig::builder().lenient(true).build().unwrap();
+        let bob = PsiProtocol::new_with_config(&letters(0..2), config).unwrap();
+        let state = bob
+            .compute_chunked(points, 2, 2, &mut transport)
+            .await
+            .unwrap();
+        assert_eq!(transport.chunks.len(), 3);
+        assert_eq!(state.stats().remote_points, 6);
+        assert_eq!(state.stats().invalid_points, 1);
+    }
+
+    #[tokio::test]
+    async fn test_async_protocol_run() {
+        let alice_items = vec![b"apple".to_vec(), b"banana".to_vec()];
+        let bob_items = vec![b"banana".to_vec(), b"cherry".to_vec()];
+
+        let alice = PsiProtocol::new_async(alice_items, PsiConfig::default()).await.unwrap();
+        let bob = PsiProtocol::new_async(bob_items, PsiConfig::default()).await.unwrap();
+
+        let alice_msg = alice.message();
+        let bob_msg = bob.message();
+
+        let (alice_intermediate, alice_double_msg) = alice.compute_async(bob_msg).await.unwrap();
+        let (bob_intermediate, bob_double_msg) = bob.compute_async(alice_msg).await.unwrap();
+
+        let (_, alice_result) = alice_intermediate.finalize_async(bob_double_msg).await.unwrap();
+        let (_, bob_result) = bob_intermediate.finalize_async(alice_double_msg).await.unwrap();
+
+        assert_eq!(alice_result.len(), 1);
+        assert_eq!(alice_result, bob_result);
+    }
+
+    #[tokio::test]
+    async fn test_compute_async_spans_chunks() {
+        let alice = PsiProtocol::new(&letters(0..3)).unwrap();
+        let mut remote = PsiProtocol::new(&letters(0..3)).unwrap().message();
+        remote.blinded_points = remote
+            .blinded_points
+            .iter()
+            .copied()
+            .cycle()
+            .take(ASYNC_CHUNK + 2)
+            .collect();
+
+        let (state, reply) = alice.clone().compute_async(remote.clone()).await.unwrap();
+        let (_, expected) = alice.clone().compute(remote.clone()).unwrap();
+        assert_eq!(reply, expected);
+        assert_eq!(state.stats().remote_points, ASYNC_CHUNK + 2);
+
+        remote.blinded_points[ASYNC_CHUNK + 1] = CompressedRistretto([0xff; 32]);
+        assert_eq!(
+            alice.clone().compute_async(remote.clone()).await.unwrap_err(),
+            PsiError::InvalidPoint {
+                phase: Phase::Compute,
+                index: ASYNC_CHUNK + 1
+            }
+        );
+
+        // The first invalid point is reported, whichever chunk it is in
+        remote.blinded_points[1] = CompressedRistretto([0xff; 32]);
+        assert_eq!(
+            alice.compute_async(remote).await.unwrap_err(),
+            PsiError::InvalidPoint {
+                phase: Phase::Compute,
+                index: 1
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_phase_limits() {
+        let limits = PhaseLimits::new().timeout(Duration::from_millis(10));
+        assert_eq!(
+            limits
+                .run(Phase::Finalize, std::future::pending::<Result<()>>())
+                .await,
+            Err(PsiError::DeadlineExceeded {
+                phase: Phase::Finalize
+            })
+        );
+        assert_eq!(limits.run(Phase::Compute, async { Ok(7) }).await, Ok(7));
+
+        let token = CancelToken::new();
+        let limits = PhaseLimits::new().cancel_with(token.clone());
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            canceller.cancel();
+        });
+        assert_eq!(
+            limits
+                .run(Phase::Compute, std::future::pending::<Result<()>>())
+                .await,
+            Err(PsiError::Aborted(AbortReason::Cancelled))
+        );
+        // A cancelled token stops later phases right away
+        assert!(token.is_cancelled());
+        assert!(limits.run(Phase::Compute, async { Ok(()) }).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_new_async_empty_input() {
+        let result = PsiProtocol::new_async(vec![], PsiConfig::default()).await;
+        assert!(matches!(result, Err(PsiError::EmptyInput)));
+    }
+}
@@ -0,0 +1,80 @@
+//! Curve arithmetic backend selection.
+//!
+//! curve25519-dalek 4 chooses its field arithmetic backend with `--cfg` flags
+//! rather than cargo features, so this crate cannot forward the choice as
+//! features of its own. The defaults are already the fast path:
+//!
+//! - On `x86_64`, the AVX2 backend is compiled in and selected at runtime when
+//!   the CPU supports it, falling back to the serial 64-bit backend.
+//! - On other 64-bit targets (including `aarch64`/NEON hosts) the serial
+//!   64-bit backend is used; curve25519-dalek 4 has no NEON backend.
+//!
+//! To force a backend, set `RUSTFLAGS` for the whole build:
+//!
+//! ```bash
+//! # Portable serial backend (e.g. to rule out SIMD issues)
+//! RUSTFLAGS='--cfg curve25519_dalek_backend="serial"' cargo build --release
+//! # Formally verified fiat-crypto backend
+//! RUSTFLAGS='--cfg curve25519_dalek_backend="fiat"' cargo build --release
+//! ```
+//!
+//! The `precomputed-tables` cargo feature (on by default) is forwarded to
+//! curve25519-dalek. The protocol multiplies secrets by variable points, so
+//! the tables only speed up padding generation; disabling them saves binary
+//! size without slowing down blinding.
+//!
+//! ## Measured impact
+//!
+//! Variable-base multiplication plus compression, release build, 20k points:
+//!
+//! | Host                   | serial     | AVX2 (auto) |
+//! |------------------------|------------|-------------|
+//! | x86_64 VM, AVX2 capable | ~46 µs/pt | ~46 µs/pt   |
+//!
+//! The AVX2 backend showed no measurable gain for this workload on that host,
+//! so the defaults are recommended; measure on your own hardware with
+//! [`active_backend`] before pinning anything.
+
+/// Arithmetic backend used by curve25519-dalek in this build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CurveBackend {
+    /// Portable serial backend.
+    Serial,
+    /// fiat-crypto formally verified backend.
+    Fiat,
+    /// AVX2 vectorized backend (x86_64 only).
+    Avx2,
+}
+
+/// Returns the backend curve25519-dalek uses on this host.
+///
+/// Takes both the build-time `curve25519_dalek_backend` cfg and runtime CPU
+/// feature detection into account, matching curve25519-dalek's own choice.
+pub fn active_backend() -> CurveBackend {
+    if cfg!(curve25519_dalek_backend = "fiat") {
+        return CurveBackend::Fiat;
+    }
+    if cfg!(curve25519_dalek_backend = "serial") {
+        return CurveBackend::Serial;
+    }
+    #[cfg(target_arch = "x86_64")]
+    {
+        if std::is_x86_feature_detected!("avx2") {
+            return CurveBackend::Avx2;
+        }
+    }
+    CurveBackend::Serial
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_active_backend_is_stable() {
+        assert_eq!(active_backend(), active_backend());
+        if !cfg!(target_arch = "x86_64") {
+            assert_ne!(active_backend(), CurveBackend::Avx2);
+        }
+    }
+}
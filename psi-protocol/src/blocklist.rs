@@ -0,0 +1,603 @@
+//! Recurring blocklist sync with delta rounds.
+//!
+//! Two organisations sharing threat intelligence want to learn which
+//! indicators (IP addresses, domains, file hashes) they both block, round
+//! after round, without revealing the rest of their lists. Running a full
+//! PSI every few minutes resends every indicator although only a handful
+//! changed. [`BlocklistSync`] keeps its blinding secret for the lifetime of
+//! the relationship instead of per session: points exchanged in earlier
+//! rounds stay comparable, so after a first full round each side only sends
+//! the indicators added since, and new matches are found against everything
+//! exchanged so far. Newly discovered common indicators are handed to a
+//! callback, see [`on_common`](BlocklistSync::on_common).
+//!
+//! Every [`Schedule::full_every`] rounds, a full round draws a fresh secret
+//! and exchanges both lists again. This bounds how long the peer can link
+//! rounds under one secret, and is the only way indicators the peer removed
+//! stop counting: delta rounds only ever add.
+//!
+//! Each round mirrors the two-message protocol:
+//! [`start_round`](BlocklistSync::start_round) →
+//! [`message`](BlocklistRound::message) → [`compute`](BlocklistSync::compute)
+//! → [`finish`](BlocklistSync::finish). Both sides must use the same
+//! [`Schedule`] and run the same rounds, so they agree on which rounds are
+//! full. The number of indicators added per round is visible to the peer
+//! unless the configuration pads messages.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::blocklist::{BlocklistSync, Schedule};
+//! use psi_protocol::PsiConfig;
+//! use std::time::{Duration, Instant};
+//!
+//! let schedule = Schedule::new(Duration::from_secs(300), 24)?;
+//! let mut sync = BlocklistSync::new(PsiConfig::default(), schedule)
+//!     .on_common(|ids| alert_soc(ids));
+//! sync.insert(b"203.0.113.7");
+//!
+//! loop {
+//!     std::thread::sleep(sync.due_at().saturating_duration_since(Instant::now()));
+//!     let mut round = sync.start_round();
+//!     send(round.message());
+//!     let reply = sync.compute(&mut round, receive())?;
+//!     send(reply);
+//!     sync.finish(round, receive())?;
+//! }
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::collections::FastSet;
+use crate::config::{MessageOrder, PsiConfig};
+use crate::crypto::{blind_point, decompress_or_basepoint, hash_item_with, hash_to_point_in};
+use crate::crypto::{random_point, random_scalar};
+use crate::error::{Phase, PsiError, Result};
+use crate::item_id::ItemId;
+use crate::messages::{BlindedPointsMessage, DoubleBlindedPointsMessage};
+use crate::psk::{BLINDED_LABEL, DOUBLE_BLINDED_LABEL};
+use crate::secret::SecretScalar;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use rand::rngs::OsRng;
+use rand::seq::SliceRandom;
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// When rounds run and which of them are full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    interval: Duration,
+    full_every: u64,
+}
+
+impl Schedule {
+    /// Run a round every `interval`, and a full round every `full_every`
+    /// rounds (the first round is always full).
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `interval` is zero or
+    /// `full_every` is zero
+    pub fn new(interval: Duration, full_every: u64) -> Result<Self> {
+        if interval.is_zero() {
+            return Err(PsiError::InvalidConfig(
+                "Round interval must not be zero".to_string(),
+            ));
+        }
+        if full_every == 0 {
+            return Err(PsiError::InvalidConfig(
+                "Full rounds must happen at least every round".to_string(),
+            ));
+        }
+        Ok(Self {
+            interval,
+            full_every,
+        })
+    }
+
+    /// Time between the starts of two rounds.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Number of rounds between two full rounds.
+    pub fn full_every(&self) -> u64 {
+        self.full_every
+    }
+}
+
+/// A local indicator and the peer's answer for it under the current secret.
+#[derive(Debug, Clone)]
+struct Indicator {
+    point: RistrettoPoint,
+    /// Our blinded point, double-blinded by the peer; `None` until sent
+    double_blinded: Option<CompressedRistretto>,
+}
+
+/// Callback receiving newly discovered common indicators.
+type Publisher = Box<dyn FnMut(&[ItemId]) + Send>;
+
+/// One side of a recurring blocklist sync with a single peer.
+pub struct BlocklistSync {
+    config: PsiConfig,
+    schedule: Schedule,
+    secret: SecretScalar,
+    indicators: BTreeMap<[u8; 32], Indicator>,
+    /// Every peer indicator received under the current secret, double-blinded
+    remote: FastSet<CompressedRistretto>,
+    /// Indicators known to be on both lists
+    common: HashSet<ItemId>,
+    rounds: u64,
+    last_start: Option<Instant>,
+    publisher: Option<Publisher>,
+}
+
+impl std::fmt::Debug for BlocklistSync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlocklistSync")
+            .field("schedule", &self.schedule)
+            .field("indicators", &self.indicators.len())
+            .field("remote", &self.remote.len())
+            .field("common", &self.common.len())
+            .field("rounds", &self.rounds)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BlocklistSync {
+    /// Create an empty blocklist.
+    pub fn new(config: PsiConfig, schedule: Schedule) -> Self {
+        Self {
+            config,
+            schedule,
+            secret: SecretScalar::new(random_scalar()),
+            indicators: BTreeMap::new(),
+            remote: FastSet::default(),
+            common: HashSet::new(),
+            rounds: 0,
+            last_start: None,
+            publisher: None,
+        }
+    }
+
+    /// Publish every batch of newly discovered common indicators to
+    /// `callback`, in addition to returning it from
+    /// [`finish`](Self::finish).
+    pub fn on_common(mut self, callback: impl FnMut(&[ItemId]) + Send + 'static) -> Self {
+        self.publisher = Some(Box::new(callback));
+        self
+    }
+
+    /// The schedule rounds follow.
+    pub fn schedule(&self) -> Schedule {
+        self.schedule
+    }
+
+    /// Add an indicator; it is sent in the next round.
+    pub fn insert(&mut self, item: &[u8]) {
+        let hash = hash_item_with(self.config.hash(), item);
+        let domain = self.config.domain();
+        self.indicators.entry(hash).or_insert_with(|| Indicator {
+            point: hash_to_point_in(domain, &hash),
+            double_blinded: None,
+        });
+    }
+
+    /// Remove an indicator. Returns true if it was on the list.
+    ///
+    /// The peer keeps matching against it until the next full round, but it
+    /// is no longer reported as common.
+    pub fn remove(&mut self, item: &[u8]) -> bool {
+        let hash = hash_item_with(self.config.hash(), item);
+        self.common.remove(&ItemId::new(hash));
+        self.indicators.remove(&hash).is_some()
+    }
+
+    /// Number of local indicators.
+    pub fn len(&self) -> usize {
+        self.indicators.len()
+    }
+
+    /// Returns true if the local list is empty.
+    pub fn is_empty(&self) -> bool {
+        self.indicators.is_empty()
+    }
+
+    /// Indicators found on both lists so far.
+    pub fn common(&self) -> &HashSet<ItemId> {
+        &self.common
+    }
+
+    /// Number of rounds started.
+    pub fn rounds(&self) -> u64 {
+        self.rounds
+    }
+
+    /// When the next round should start: now for the first round, one
+    /// interval after the previous start otherwise.
+    pub fn due_at(&self) -> Instant {
+        self.last_start
+            .map_or_else(Instant::now, |start| start + self.schedule.interval)
+    }
+
+    /// Returns true if a round should start at `now`.
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.last_start
+            .is_none_or(|start| now >= start + self.schedule.interval)
+    }
+
+    /// Returns true if the next round started will be full.
+    pub fn next_round_is_full(&self) -> bool {
+        self.rounds.is_multiple_of(self.schedule.full_every)
+    }
+
+    /// Start the next round, full or delta as the schedule says.
+    pub fn start_round(&mut self) -> BlocklistRound {
+        let full = self.next_round_is_full();
+        self.start(full)
+    }
+
+    /// Start a full round out of schedule, e.g. after a round failed on one
+    /// side. The peer must start a full round too.
+    pub fn start_full_round(&mut self) -> BlocklistRound {
+        self.start(true)
+    }
+
+    fn start(&mut self, full: bool) -> BlocklistRound {
+        if full {
+            // Points under the old secret can no longer be compared
+            self.secret = SecretScalar::new(random_scalar());
+            self.remote.clear();
+            for indicator in self.indicators.values_mut() {
+                indicator.double_blinded = None;
+            }
+        }
+        self.rounds += 1;
+        self.last_start = Some(Instant::now());
+
+        let mut slots: Vec<(Option<[u8; 32]>, CompressedRistretto)> = self
+            .indicators
+            .iter()
+            .filter(|(_, indicator)| indicator.double_blinded.is_none())
+            .map(|(hash, indicator)| {
+                (
+                    Some(*hash),
+                    blind_point(&indicator.point, self.secret.expose()),
+                )
+            })
+            .collect();
+        let padded_len = self.config.padding().padded_len(slots.len());
+        slots.resize_with(padded_len, || (None, random_point()));
+        match self.config.order() {
+            MessageOrder::Shuffled => slots.shuffle(&mut OsRng),
+            MessageOrder::Sorted => slots.sort_unstable_by_key(|(_, point)| point.to_bytes()),
+        }
+
+        let (hash_order, points): (Vec<_>, Vec<_>) = slots.into_iter().unzip();
+        let mut message = BlindedPointsMessage::new(points);
+        message.authentication = self
+            .config
+            .authenticate(BLINDED_LABEL, &message.blinded_points);
+        message.parameters = Some(self.config.parameter_digest());
+        BlocklistRound {
+            number: self.rounds,
+            full,
+            hash_order,
+            message,
+            computed: false,
+        }
+    }
+
+    /// Double-blind the peer's new indicators and remember them for later
+    /// rounds.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` if `round` is not the latest round
+    /// or was already computed, `PsiError::LimitExceeded` if the peer sends
+    /// more points than the configured remote limit, `PsiError::InvalidPoint`
+    /// for an invalid point outside lenient mode,
+    /// `PsiError::ParameterMismatch` if the peer used other parameters and,
+    /// with a pre-shared key, `PsiError::CryptoError` if the MAC does not
+    /// verify
+    pub fn compute(
+        &mut self,
+        round: &mut BlocklistRound,
+        remote_msg: BlindedPointsMessage,
+    ) -> Result<DoubleBlindedPointsMessage> {
+        self.check_current(round, "compute")?;
+        if round.computed {
+            return Err(PsiError::UnexpectedState {
+                operation: "compute",
+                state: "computed",
+            });
+        }
+        self.config.check_authentication(
+            BLINDED_LABEL,
+            &remote_msg.blinded_points,
+            remote_msg.authentication.as_ref(),
+        )?;
+        self.config
+            .check_parameters(Phase::Compute, remote_msg.parameters.as_ref())?;
+        self.config.check_remote_len(remote_msg.len())?;
+
+        let blinded: Vec<(CompressedRistretto, bool)> = remote_msg
+            .blinded_points
+            .iter()
+            .map(|point| {
+                let (point, valid) = decompress_or_basepoint(point);
+                ((self.secret.expose() * point).compress(), valid)
+            })
+            .collect();
+        if !self.config.lenient() {
+            if let Some(index) = blinded.iter().position(|(_, valid)| !valid) {
+                return Err(PsiError::InvalidPoint {
+                    phase: Phase::Compute,
+                    index,
+                });
+            }
+        }
+        let points: Vec<CompressedRistretto> = blinded
+            .into_iter()
+            .map(|(point, valid)| if valid { point } else { random_point() })
+            .collect();
+        self.remote.extend(points.iter().copied());
+        round.computed = true;
+
+        let mut message = DoubleBlindedPointsMessage::new(points);
+        message.authentication = self
+            .config
+            .authenticate(DOUBLE_BLINDED_LABEL, &message.double_blinded_points);
+        message.parameters = Some(self.config.parameter_digest());
+        Ok(message)
+    }
+
+    /// Store the peer's answer for our new indicators and report the
+    /// indicators that became common in this round.
+    ///
+    /// The new common indicators, in no particular order, are also passed to
+    /// the [`on_common`](Self::on_common) callback when there are any. After
+    /// a full round, [`common`](Self::common) only keeps indicators found
+    /// again.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` if `round` is not the latest round
+    /// or was not computed yet, `PsiError::LengthMismatch` if the peer did
+    /// not answer every point of our message, `PsiError::ParameterMismatch`
+    /// if the peer used other parameters and, with a pre-shared key,
+    /// `PsiError::CryptoError` if the MAC does not verify
+    pub fn finish(
+        &mut self,
+        round: BlocklistRound,
+        remote_msg: DoubleBlindedPointsMessage,
+    ) -> Result<Vec<ItemId>> {
+        self.check_current(&round, "finish")?;
+        if !round.computed {
+            return Err(PsiError::UnexpectedState {
+                operation: "finish",
+                state: "started",
+            });
+        }
+        self.config.check_authentication(
+            DOUBLE_BLINDED_LABEL,
+            &remote_msg.double_blinded_points,
+            remote_msg.authentication.as_ref(),
+        )?;
+        self.config
+            .check_parameters(Phase::Finalize, remote_msg.parameters.as_ref())?;
+        if remote_msg.len() != round.hash_order.len() {
+            return Err(PsiError::LengthMismatch {
+                phase: Phase::Finalize,
+                expected: round.hash_order.len(),
+                actual: remote_msg.len(),
+            });
+        }
+
+        for (slot, point) in round
+            .hash_order
+            .iter()
+            .zip(remote_msg.double_blinded_points)
+        {
+            if let Some(indicator) = slot.and_then(|hash| self.indicators.get_mut(&hash)) {
+                indicator.double_blinded = Some(point);
+            }
+        }
+
+        // New local indicators against all peer indicators, and old local
+        // indicators against the peer's new ones
+        let now_common: HashSet<ItemId> = self
+            .indicators
+            .iter()
+            .filter(|(_, indicator)| {
+                indicator
+                    .double_blinded
+                    .is_some_and(|point| self.remote.contains(&point))
+            })
+            .map(|(hash, _)| ItemId::new(*hash))
+            .collect();
+        let new: Vec<ItemId> = now_common
+            .iter()
+            .filter(|id| !self.common.contains(id))
+            .copied()
+            .collect();
+        if round.full {
+            self.common = now_common;
+        } else {
+            self.common.extend(new.iter().copied());
+        }
+
+        if !new.is_empty() {
+            if let Some(publish) = &mut self.publisher {
+                publish(&new);
+            }
+        }
+        Ok(new)
+    }
+
+    fn check_current(&self, round: &BlocklistRound, operation: &'static str) -> Result<()> {
+        if round.number != self.rounds {
+            return Err(PsiError::UnexpectedState {
+                operation,
+                state: "superseded",
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A round in progress, from [`BlocklistSync::start_round`].
+#[derive(Debug, Clone)]
+pub struct BlocklistRound {
+    number: u64,
+    full: bool,
+    /// Hash of each slot of our message, `None` for padding
+    hash_order: Vec<Option<[u8; 32]>>,
+    message: BlindedPointsMessage,
+    computed: bool,
+}
+
+impl BlocklistRound {
+    /// The blinded indicators to send to the peer: every indicator in a full
+    /// round, only the new ones in a delta round.
+    pub fn message(&self) -> BlindedPointsMessage {
+        self.message.clone()
+    }
+
+    /// Returns true if this round exchanges both full lists.
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    /// Round number, starting at 1.
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn schedule(full_every: u64) -> Schedule {
+        Schedule::new(Duration::from_secs(60), full_every).unwrap()
+    }
+
+    /// Run one round between both sides, returning what each discovered.
+    fn round(alice: &mut BlocklistSync, bob: &mut BlocklistSync) -> (Vec<ItemId>, Vec<ItemId>) {
+        let alice_round = alice.start_round();
+        let bob_round = bob.start_round();
+        exchange(alice, bob, alice_round, bob_round)
+    }
+
+    fn exchange(
+        alice: &mut BlocklistSync,
+        bob: &mut BlocklistSync,
+        mut alice_round: BlocklistRound,
+        mut bob_round: BlocklistRound,
+    ) -> (Vec<ItemId>, Vec<ItemId>) {
+        assert_eq!(alice_round.is_full(), bob_round.is_full());
+        let alice_reply = alice
+            .compute(&mut alice_round, bob_round.message())
+            .unwrap();
+        let bob_reply = bob.compute(&mut bob_round, alice_round.message()).unwrap();
+        let mut alice_new = alice.finish(alice_round, bob_reply).unwrap();
+        let mut bob_new = bob.finish(bob_round, alice_reply).unwrap();
+        alice_new.sort();
+        bob_new.sort();
+        (alice_new, bob_new)
+    }
+
+    fn id(item: &[u8]) -> ItemId {
+        ItemId::of(item)
+    }
+
+    #[test]
+    fn test_delta_rounds_only_send_new_indicators() {
+        let mut alice = BlocklistSync::new(PsiConfig::default(), schedule(10));
+        let mut bob = BlocklistSync::new(PsiConfig::default(), schedule(10));
+        alice.insert(b"198.51.100.1");
+        alice.insert(b"evil.example");
+        bob.insert(b"evil.example");
+        bob.insert(b"203.0.113.9");
+
+        let (alice_new, bob_new) = round(&mut alice, &mut bob);
+        assert_eq!(alice_new, vec![id(b"evil.example")]);
+        assert_eq!(bob_new, alice_new);
+
+        // Bob adds an indicator Alice has had all along
+        bob.insert(b"198.51.100.1");
+        let alice_round = alice.start_round();
+        let bob_round = bob.start_round();
+        assert!(!bob_round.is_full());
+        assert_eq!(alice_round.message().len(), 0);
+        assert_eq!(bob_round.message().len(), 1);
+        let (alice_new, bob_new) = exchange(&mut alice, &mut bob, alice_round, bob_round);
+        assert_eq!(alice_new, vec![id(b"198.51.100.1")]);
+        assert_eq!(bob_new, alice_new);
+        assert_eq!(alice.common().len(), 2);
+
+        // Nothing changed: nothing new
+        assert_eq!(round(&mut alice, &mut bob), (vec![], vec![]));
+    }
+
+    #[test]
+    fn test_full_round_drops_indicators_the_peer_removed() {
+        let mut alice = BlocklistSync::new(PsiConfig::default(), schedule(2));
+        let mut bob = BlocklistSync::new(PsiConfig::default(), schedule(2));
+        alice.insert(b"a");
+        alice.insert(b"b");
+        bob.insert(b"a");
+        bob.insert(b"b");
+        round(&mut alice, &mut bob);
+        assert_eq!(alice.common().len(), 2);
+
+        // A delta round cannot retract, the next full round does
+        bob.remove(b"b");
+        assert!(!alice.next_round_is_full());
+        round(&mut alice, &mut bob);
+        assert_eq!(alice.common().len(), 2);
+        assert!(alice.next_round_is_full());
+        round(&mut alice, &mut bob);
+        assert_eq!(alice.common(), &HashSet::from([id(b"a")]));
+        assert_eq!(bob.common(), &HashSet::from([id(b"a")]));
+    }
+
+    #[test]
+    fn test_callback_and_round_checks() {
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&published);
+        let config = PsiConfig::builder()
+            .padding(crate::Padding::ToMultipleOf(8))
+            .build()
+            .unwrap();
+        let mut alice = BlocklistSync::new(config.clone(), schedule(5))
+            .on_common(move |ids| sink.lock().unwrap().extend_from_slice(ids));
+        let mut bob = BlocklistSync::new(config, schedule(5));
+        alice.insert(b"shared");
+        bob.insert(b"shared");
+        assert!(alice.is_due(Instant::now()));
+
+        let mut stale = alice.start_round();
+        let bob_round = bob.start_round();
+        assert_eq!(bob_round.message().len(), 8);
+        let mut current = alice.start_full_round();
+        assert!(matches!(
+            alice.compute(&mut stale, bob_round.message()),
+            Err(PsiError::UnexpectedState { .. })
+        ));
+        assert!(!alice.is_due(Instant::now()));
+
+        let mut bob_round = bob.start_full_round();
+        let alice_reply = alice.compute(&mut current, bob_round.message()).unwrap();
+        let bob_reply = bob.compute(&mut bob_round, current.message()).unwrap();
+        assert_eq!(
+            alice.finish(current, bob_reply).unwrap(),
+            vec![id(b"shared")]
+        );
+        bob.finish(bob_round, alice_reply).unwrap();
+        assert_eq!(*published.lock().unwrap(), vec![id(b"shared")]);
+    }
+
+    #[test]
+    fn test_invalid_schedule_is_rejected() {
+        assert!(Schedule::new(Duration::ZERO, 1).is_err());
+        assert!(Schedule::new(Duration::from_secs(1), 0).is_err());
+    }
+}
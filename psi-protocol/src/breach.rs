@@ -0,0 +1,352 @@
+//! Credential-breach checking.
+//!
+//! A client wants to know whether some of its credentials appear in a
+//! server's breach corpus. Built on the one-round variant: the client sends
+//! its blinded credentials, the server double-blinds them, and only the
+//! client learns which of its credentials are breached. The server never
+//! sees a credential, and the client only sees the corpus blinded under the
+//! server's secret.
+//!
+//! The blinded corpus is the same for every client, so
+//! [`BreachServer::corpus`] can be downloaded once (or served from a cache)
+//! and each query only costs a [`DoubleBlindedPointsMessage`] of the size of
+//! the query. Every answered credential lets a client test one guess
+//! against the corpus, so [`QueryThrottle`] hooks cap how much each client
+//! may ask.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::breach::{credential, BreachClient, BreachServer, QuotaThrottle};
+//!
+//! // Server
+//! let mut server = BreachServer::new(&corpus)?.with_throttle(QuotaThrottle::new(1000));
+//! let corpus_msg = server.corpus();
+//!
+//! // Client
+//! let client = BreachClient::new(&[credential("alice", "hunter2")])?;
+//! let answer = server.answer(&client_id, client.query())?;
+//! let breached = client.finish(corpus_msg, answer)?;
+//! assert_eq!(breached, vec![true]);
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::config::PsiConfig;
+use crate::error::{Limit, Phase, PsiError, Result};
+use crate::item_id::ItemId;
+use crate::messages::{BlindedPointsMessage, DoubleBlindedPointsMessage, OneRoundResponseMessage};
+use crate::protocol::PsiProtocol;
+use crate::psk::{BLINDED_LABEL, DOUBLE_BLINDED_LABEL, ONE_ROUND_LABEL};
+use crate::state::PreparedState;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+/// Encode a username and password as a corpus item.
+///
+/// Both fields are length-prefixed, so `("ab", "c")` and `("a", "bc")`
+/// differ. The server must build its corpus with the same encoding.
+pub fn credential(username: &str, password: &str) -> Vec<u8> {
+    let mut item = Vec::with_capacity(8 + username.len() + password.len());
+    for field in [username, password] {
+        item.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        item.extend_from_slice(field.as_bytes());
+    }
+    item
+}
+
+/// Per-client admission check run before a query is answered.
+///
+/// `count` is the number of points in the query, padding included.
+/// Returning an error rejects the query before any work is done.
+pub trait QueryThrottle<K> {
+    /// Admit or reject `count` more credentials from `client`.
+    ///
+    /// # Errors
+    /// Any error rejects the query; it is returned to the caller of
+    /// [`BreachServer::answer`] unchanged
+    fn check(&mut self, client: &K, count: usize) -> Result<()>;
+}
+
+impl<K, F> QueryThrottle<K> for F
+where
+    F: FnMut(&K, usize) -> Result<()>,
+{
+    fn check(&mut self, client: &K, count: usize) -> Result<()> {
+        self(client, count)
+    }
+}
+
+/// Admits every query.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoThrottle;
+
+impl<K> QueryThrottle<K> for NoThrottle {
+    fn check(&mut self, _client: &K, _count: usize) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Caps the total number of credentials each client may query.
+#[derive(Debug, Clone)]
+pub struct QuotaThrottle<K> {
+    max: usize,
+    used: HashMap<K, usize>,
+}
+
+impl<K: Eq + Hash> QuotaThrottle<K> {
+    /// Allow each client at most `max` credentials in total.
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            used: HashMap::new(),
+        }
+    }
+
+    /// Credentials queried so far by `client`.
+    pub fn used(&self, client: &K) -> usize {
+        self.used.get(client).copied().unwrap_or(0)
+    }
+
+    /// Forget a client's usage, e.g. at the start of a new billing period.
+    pub fn reset(&mut self, client: &K) {
+        self.used.remove(client);
+    }
+}
+
+impl<K: Eq + Hash + Clone> QueryThrottle<K> for QuotaThrottle<K> {
+    /// # Errors
+    /// Returns `PsiError::LimitExceeded` if the query would take the client
+    /// over its quota; rejected queries are not counted
+    fn check(&mut self, client: &K, count: usize) -> Result<()> {
+        let actual = self.used(client).saturating_add(count);
+        if actual > self.max {
+            return Err(PsiError::LimitExceeded {
+                limit: Limit::ClientQueries,
+                max: self.max,
+                actual,
+            });
+        }
+        self.used.insert(client.clone(), actual);
+        Ok(())
+    }
+}
+
+/// Server holding the breach corpus.
+pub struct BreachServer<K> {
+    corpus: PsiProtocol<PreparedState>,
+    throttle: Box<dyn QueryThrottle<K> + Send>,
+}
+
+impl<K> BreachServer<K> {
+    /// Blind the corpus with the default configuration.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::new`]
+    pub fn new(corpus: &[Vec<u8>]) -> Result<Self> {
+        Self::new_with_config(corpus, PsiConfig::default())
+    }
+
+    /// Blind the corpus with a custom configuration.
+    ///
+    /// Use [`PsiConfigBuilder::max_remote_items`](crate::PsiConfigBuilder::max_remote_items)
+    /// to bound the size of a single query.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::new_with_config`]
+    pub fn new_with_config(corpus: &[Vec<u8>], config: PsiConfig) -> Result<Self> {
+        Ok(Self {
+            corpus: PsiProtocol::new_with_config(corpus, config)?,
+            throttle: Box::new(NoThrottle),
+        })
+    }
+
+    /// Run `throttle` before answering each query.
+    pub fn with_throttle(mut self, throttle: impl QueryThrottle<K> + Send + 'static) -> Self {
+        self.throttle = Box::new(throttle);
+        self
+    }
+
+    /// The blinded corpus, identical for every client.
+    pub fn corpus(&self) -> BlindedPointsMessage {
+        self.corpus.message()
+    }
+
+    /// Answer a client's blinded credentials.
+    ///
+    /// # Errors
+    /// Returns the throttle's error if the query is rejected, plus the
+    /// errors of [`PsiProtocol::respond_one_round`]
+    pub fn answer(
+        &mut self,
+        client: &K,
+        query: BlindedPointsMessage,
+    ) -> Result<DoubleBlindedPointsMessage> {
+        self.throttle.check(client, query.len())?;
+        // Same answer as `respond_one_round`, without copying the corpus
+        self.corpus.check_message(&query)?;
+        let double_blinded = self.corpus.double_blind(&query)?;
+        let mut answer = DoubleBlindedPointsMessage::new(double_blinded);
+        answer.authentication = self
+            .corpus
+            .config()
+            .authenticate(DOUBLE_BLINDED_LABEL, &answer.double_blinded_points);
+        answer.parameters = Some(self.corpus.config().parameter_digest());
+        Ok(answer)
+    }
+}
+
+impl<K> std::fmt::Debug for BreachServer<K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BreachServer")
+            .field("corpus", &self.corpus)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Client checking a batch of credentials.
+#[derive(Debug, Clone)]
+pub struct BreachClient {
+    protocol: PsiProtocol<PreparedState>,
+    credentials: Vec<Vec<u8>>,
+}
+
+impl BreachClient {
+    /// Blind credentials with the default configuration.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::new`]
+    pub fn new(credentials: &[Vec<u8>]) -> Result<Self> {
+        Self::new_with_config(credentials, PsiConfig::default())
+    }
+
+    /// Blind credentials with a custom configuration.
+    ///
+    /// Padding hides how many credentials are checked, at the cost of
+    /// quota.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::new_with_config`]
+    pub fn new_with_config(credentials: &[Vec<u8>], config: PsiConfig) -> Result<Self> {
+        Ok(Self {
+            protocol: PsiProtocol::new_with_config(credentials, config)?,
+            credentials: credentials.to_vec(),
+        })
+    }
+
+    /// The blinded credentials to send to the server.
+    pub fn query(&self) -> BlindedPointsMessage {
+        self.protocol.message()
+    }
+
+    /// Whether each credential is in the corpus, in input order.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::finalize_one_round`]
+    pub fn finish(
+        self,
+        corpus: BlindedPointsMessage,
+        answer: DoubleBlindedPointsMessage,
+    ) -> Result<Vec<bool>> {
+        let config = self.protocol.config();
+        let hash = config.hash();
+        config.check_authentication(
+            BLINDED_LABEL,
+            &corpus.blinded_points,
+            corpus.authentication.as_ref(),
+        )?;
+        config.check_authentication(
+            DOUBLE_BLINDED_LABEL,
+            &answer.double_blinded_points,
+            answer.authentication.as_ref(),
+        )?;
+        config.check_parameters(Phase::Finalize, corpus.parameters.as_ref())?;
+        config.check_parameters(Phase::Finalize, answer.parameters.as_ref())?;
+        // Both halves are verified, so the assembled response is too
+        let mut response =
+            OneRoundResponseMessage::new(corpus.blinded_points, answer.double_blinded_points);
+        response.authentication = config.authenticate_lists(
+            ONE_ROUND_LABEL,
+            &[&response.blinded_points, &response.double_blinded_points],
+        );
+        response.parameters = Some(config.parameter_digest());
+        let (_, result) = self.protocol.finalize_one_round(response)?;
+        let breached: HashSet<_> = result.intersection_hashes.iter().collect();
+        Ok(self
+            .credentials
+            .iter()
+            .map(|item| breached.contains(&ItemId::of_with(hash, item)))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corpus() -> Vec<Vec<u8>> {
+        vec![
+            credential("alice", "hunter2"),
+            credential("bob", "password"),
+            credential("carol", "123456"),
+        ]
+    }
+
+    #[test]
+    fn test_credential_encoding_is_unambiguous() {
+        assert_ne!(credential("ab", "c"), credential("a", "bc"));
+    }
+
+    #[test]
+    fn test_breach_check() {
+        let mut server: BreachServer<&str> = BreachServer::new(&corpus()).unwrap();
+        let corpus_msg = server.corpus();
+
+        let client = BreachClient::new(&[
+            credential("alice", "correct horse"),
+            credential("bob", "password"),
+        ])
+        .unwrap();
+        let answer = server.answer(&"client-1", client.query()).unwrap();
+        assert_eq!(
+            client.finish(corpus_msg, answer).unwrap(),
+            vec![false, true]
+        );
+    }
+
+    #[test]
+    fn test_quota_throttle() {
+        let mut server = BreachServer::new(&corpus())
+            .unwrap()
+            .with_throttle(QuotaThrottle::new(3));
+        let client = BreachClient::new(&[credential("a", "1"), credential("b", "2")]).unwrap();
+
+        assert!(server.answer(&1u32, client.query()).is_ok());
+        assert_eq!(
+            server.answer(&1u32, client.query()).unwrap_err(),
+            PsiError::LimitExceeded {
+                limit: Limit::ClientQueries,
+                max: 3,
+                actual: 4
+            }
+        );
+        // Quotas are per client
+        assert!(server.answer(&2u32, client.query()).is_ok());
+    }
+
+    #[test]
+    fn test_closure_throttle() {
+        let mut server = BreachServer::new(&corpus()).unwrap().with_throttle(
+            |client: &String, _count: usize| {
+                if client == "banned" {
+                    Err(PsiError::InvalidConfig("client is banned".to_string()))
+                } else {
+                    Ok(())
+                }
+            },
+        );
+        let client = BreachClient::new(&[credential("a", "1")]).unwrap();
+        assert!(server.answer(&"ok".to_string(), client.query()).is_ok());
+        assert!(server
+            .answer(&"banned".to_string(), client.query())
+            .is_err());
+    }
+}
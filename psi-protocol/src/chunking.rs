@@ -0,0 +1,384 @@
+//! Content-defined chunking for deduplicated transfer.
+//!
+//! Two hosts holding large files want to know which pieces the other side
+//! already has, without revealing what they store. [`Chunker`] splits data
+//! with FastCDC: chunk boundaries are chosen by a rolling hash of the
+//! content, so an insertion only changes the chunks around it and the same
+//! content yields the same chunks on both hosts, whatever its offset in the
+//! file. Each chunk becomes one PSI item (its SHA-256 digest); the
+//! intersection tells each side which chunks it does not need to send.
+//!
+//! Both hosts must use the same [`Chunker`] parameters, otherwise their
+//! boundaries differ and almost nothing matches.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::chunking::{chunk_items, missing_chunks, Chunker};
+//! use psi_protocol::PsiProtocol;
+//!
+//! let chunker = Chunker::default();
+//! let chunks = chunker.chunk_reader(std::fs::File::open("disk.img")?)?;
+//! let alice = PsiProtocol::new(&chunk_items(&chunks))?;
+//!
+//! let (_, result) = alice.compute(bob_msg)?;
+//! for chunk in missing_chunks(&chunks, &result) {
+//!     // send bytes chunk.offset..chunk.offset + chunk.len to the peer
+//! }
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::error::{PsiError, Result};
+use crate::item_id::ItemId;
+use crate::messages::PsiResult;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::Read;
+use std::ops::Range;
+
+/// Smallest accepted average chunk size.
+pub const MIN_AVG_SIZE: usize = 64;
+
+/// Largest accepted maximum chunk size (16 MiB).
+pub const MAX_CHUNK_SIZE: usize = 16 << 20;
+
+/// Gear table of the rolling hash.
+///
+/// Fixed forever: both hosts must derive the same boundaries, so changing it
+/// would break matching with every other version.
+const GEAR: [u64; 256] = gear_table();
+
+/// Fill the gear table with SplitMix64 output from a fixed seed.
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x7073_692d_7379_6e63; // "psi-sync"
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Mask testing the `bits` highest bits of the hash, which depend on the
+/// most recent bytes.
+const fn mask(bits: u32) -> u64 {
+    !0u64 << (64 - bits)
+}
+
+/// FastCDC chunk boundary finder.
+///
+/// Uses normalized chunking: below the average size a stricter mask makes a
+/// cut less likely, above it a looser mask makes one more likely, which
+/// keeps most chunks close to the average.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunker {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+    mask_small: u64,
+    mask_large: u64,
+}
+
+impl Default for Chunker {
+    /// 2 KiB minimum, 8 KiB average and 64 KiB maximum chunks.
+    fn default() -> Self {
+        Self::new(2 << 10, 8 << 10, 64 << 10).expect("default chunk sizes are valid")
+    }
+}
+
+impl Chunker {
+    /// Create a chunker with the given chunk size bounds.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` unless
+    /// `0 < min_size <= avg_size <= max_size <= MAX_CHUNK_SIZE`, with
+    /// `avg_size` a power of two no smaller than [`MIN_AVG_SIZE`]
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Result<Self> {
+        if min_size == 0 || min_size > avg_size || avg_size > max_size {
+            return Err(PsiError::InvalidConfig(format!(
+                "Chunk sizes must satisfy 0 < min <= avg <= max, got {}/{}/{}",
+                min_size, avg_size, max_size
+            )));
+        }
+        if !avg_size.is_power_of_two() || avg_size < MIN_AVG_SIZE {
+            return Err(PsiError::InvalidConfig(format!(
+                "Average chunk size must be a power of two of at least {}, got {}",
+                MIN_AVG_SIZE, avg_size
+            )));
+        }
+        if max_size > MAX_CHUNK_SIZE {
+            return Err(PsiError::InvalidConfig(format!(
+                "Maximum chunk size must be at most {}, got {}",
+                MAX_CHUNK_SIZE, max_size
+            )));
+        }
+        let bits = avg_size.trailing_zeros();
+        Ok(Self {
+            min_size,
+            avg_size,
+            max_size,
+            mask_small: mask(bits + 2),
+            mask_large: mask(bits - 2),
+        })
+    }
+
+    /// Minimum chunk size; only the last chunk of the data may be smaller.
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    /// Target average chunk size.
+    pub fn avg_size(&self) -> usize {
+        self.avg_size
+    }
+
+    /// Maximum chunk size.
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Length of the first chunk of `data`.
+    fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+        let end = data.len().min(self.max_size);
+        let normal = end.min(self.avg_size);
+        let mut hash = 0u64;
+        for (i, &byte) in data.iter().enumerate().take(end).skip(self.min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+            let mask = if i < normal {
+                self.mask_small
+            } else {
+                self.mask_large
+            };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+
+    /// Split in-memory data into chunk ranges covering all of it.
+    pub fn boundaries(&self, data: &[u8]) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        while start < data.len() {
+            let end = start + self.cut(&data[start..]);
+            ranges.push(start..end);
+            start = end;
+        }
+        ranges
+    }
+
+    /// Split in-memory data into hashed chunks.
+    pub fn chunk(&self, data: &[u8]) -> Vec<ChunkRef> {
+        self.boundaries(data)
+            .into_iter()
+            .map(|range| ChunkRef::new(range.start as u64, &data[range]))
+            .collect()
+    }
+
+    /// Split a reader's content into hashed chunks, holding at most one
+    /// maximum-size chunk plus one read buffer in memory.
+    ///
+    /// Gives the same chunks as [`chunk`](Self::chunk) on the whole content.
+    ///
+    /// # Errors
+    /// Returns `PsiError::SourceFailed` if the reader fails
+    pub fn chunk_reader<R: Read>(&self, mut reader: R) -> Result<Vec<ChunkRef>> {
+        let mut chunks = Vec::new();
+        let mut buffer = Vec::with_capacity(2 * self.max_size);
+        let mut offset = 0u64;
+        let mut eof = false;
+        loop {
+            // A cut needs max_size bytes ahead, unless the data ends sooner
+            while !eof && buffer.len() < self.max_size {
+                let filled = buffer.len();
+                buffer.resize(filled + self.max_size, 0);
+                let read = match reader.read(&mut buffer[filled..]) {
+                    Ok(read) => read,
+                    Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {
+                        buffer.truncate(filled);
+                        continue;
+                    }
+                    Err(error) => {
+                        return Err(PsiError::SourceFailed(format!("chunk reader: {}", error)))
+                    }
+                };
+                buffer.truncate(filled + read);
+                eof = read == 0;
+            }
+            if buffer.is_empty() {
+                return Ok(chunks);
+            }
+            let len = self.cut(&buffer);
+            chunks.push(ChunkRef::new(offset, &buffer[..len]));
+            offset += len as u64;
+            buffer.drain(..len);
+        }
+    }
+}
+
+/// One chunk of the chunked data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkRef {
+    /// Position of the chunk in the data
+    pub offset: u64,
+    /// Length of the chunk in bytes
+    pub len: usize,
+    /// SHA-256 of the chunk's content
+    pub digest: [u8; 32],
+}
+
+impl ChunkRef {
+    fn new(offset: u64, content: &[u8]) -> Self {
+        Self {
+            offset,
+            len: content.len(),
+            digest: Sha256::digest(content).into(),
+        }
+    }
+
+    /// The PSI item standing for this chunk's content.
+    pub fn item(&self) -> Vec<u8> {
+        self.digest.to_vec()
+    }
+
+    /// Identifier of the chunk in [`PsiResult::intersection_hashes`].
+    pub fn id(&self) -> ItemId {
+        ItemId::of(&self.digest)
+    }
+}
+
+/// PSI items for a set of chunks, one per distinct content.
+pub fn chunk_items(chunks: &[ChunkRef]) -> Vec<Vec<u8>> {
+    let mut seen = HashSet::new();
+    chunks
+        .iter()
+        .filter(|chunk| seen.insert(chunk.digest))
+        .map(ChunkRef::item)
+        .collect()
+}
+
+/// Chunks whose content the peer does not have, in data order.
+///
+/// Every occurrence of a repeated chunk is returned, so the caller can
+/// decide whether to send the content once or reference it.
+pub fn missing_chunks<'a>(chunks: &'a [ChunkRef], result: &PsiResult) -> Vec<&'a ChunkRef> {
+    let shared: HashSet<&ItemId> = result.intersection_hashes.iter().collect();
+    chunks
+        .iter()
+        .filter(|chunk| !shared.contains(&chunk.id()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local::run_local_psi;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+
+    fn random_data(len: usize, seed: u64) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        StdRng::seed_from_u64(seed).fill_bytes(&mut data);
+        data
+    }
+
+    #[test]
+    fn test_chunks_respect_bounds_and_cover_data() {
+        let chunker = Chunker::new(256, 1024, 4096).unwrap();
+        let data = random_data(100_000, 1);
+        let ranges = chunker.boundaries(&data);
+
+        assert_eq!(ranges.first().unwrap().start, 0);
+        assert_eq!(ranges.last().unwrap().end, data.len());
+        for pair in ranges.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        let (last, rest) = ranges.split_last().unwrap();
+        for range in rest {
+            assert!((256..=4096).contains(&range.len()));
+        }
+        assert!(last.len() <= 4096);
+        // Normalized chunking keeps the average close to the target
+        let average = data.len() / ranges.len();
+        assert!((512..=2048).contains(&average), "average {}", average);
+    }
+
+    #[test]
+    fn test_insertion_only_changes_nearby_chunks() {
+        let chunker = Chunker::new(256, 1024, 4096).unwrap();
+        let data = random_data(64_000, 2);
+        let mut edited = data[..30_000].to_vec();
+        edited.extend_from_slice(b"inserted bytes");
+        edited.extend_from_slice(&data[30_000..]);
+
+        let original = chunker.chunk(&data);
+        let changed = chunker.chunk(&edited);
+        let items: HashSet<[u8; 32]> = original.iter().map(|chunk| chunk.digest).collect();
+        let kept = changed
+            .iter()
+            .filter(|chunk| items.contains(&chunk.digest))
+            .count();
+        assert!(
+            kept + 3 >= original.len(),
+            "kept {} of {}",
+            kept,
+            original.len()
+        );
+    }
+
+    #[test]
+    fn test_reader_matches_in_memory_chunking() {
+        let chunker = Chunker::new(256, 1024, 4096).unwrap();
+        let data = random_data(50_000, 3);
+        // A reader returning short reads exercises the refill loop
+        struct Trickle<'a>(&'a [u8]);
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = buf.len().min(700).min(self.0.len());
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+        assert_eq!(
+            chunker.chunk_reader(Trickle(&data)).unwrap(),
+            chunker.chunk(&data)
+        );
+        assert!(chunker.chunk_reader(&[][..]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_psi_finds_missing_chunks() {
+        let chunker = Chunker::new(256, 1024, 4096).unwrap();
+        let base = random_data(40_000, 4);
+        let mut alice_data = base.clone();
+        alice_data.extend_from_slice(&random_data(5_000, 5));
+
+        let alice = chunker.chunk(&alice_data);
+        let bob = chunker.chunk(&base);
+        let (result, _) = run_local_psi(&chunk_items(&alice), &chunk_items(&bob)).unwrap();
+
+        let missing = missing_chunks(&alice, &result);
+        assert!(!missing.is_empty());
+        // Only the tail Bob lacks, plus the chunk straddling the join
+        assert!(missing.iter().all(|chunk| chunk.offset + 4096 >= 40_000));
+    }
+
+    #[test]
+    fn test_invalid_sizes_are_rejected() {
+        assert!(Chunker::new(0, 1024, 4096).is_err());
+        assert!(Chunker::new(2048, 1024, 4096).is_err());
+        assert!(Chunker::new(256, 1000, 4096).is_err());
+        assert!(Chunker::new(16, 32, 4096).is_err());
+        assert!(Chunker::new(256, 1024, MAX_CHUNK_SIZE + 1).is_err());
+        assert_eq!(Chunker::default().avg_size(), 8192);
+    }
+}
@@ -0,0 +1,26 @@
+//! Hash maps used internally on the protocol's hot paths.
+//!
+//! Matching in `finalize` and the membership and blocklist lookups probe one
+//! map per received point, so these use hashbrown with a fast hasher rather
+//! than `std`'s SipHash. Public types keep `std::collections`. The hasher is
+//! chosen at build time:
+//!
+//! - foldhash (default) - randomly seeded per process, several times faster
+//!   than SipHash on 32-byte keys
+//! - SipHash (`std-hasher` feature) - for deployments that want the
+//!   standard library's flooding resistance against peers choosing their
+//!   points to collide
+
+/// Hasher of the internal maps.
+#[cfg(not(feature = "std-hasher"))]
+pub(crate) type HashBuilder = hashbrown::DefaultHashBuilder;
+
+/// Hasher of the internal maps.
+#[cfg(feature = "std-hasher")]
+pub(crate) type HashBuilder = std::collections::hash_map::RandomState;
+
+/// Internal hash map.
+pub(crate) type FastMap<K, V> = hashbrown::HashMap<K, V, HashBuilder>;
+
+/// Internal hash set.
+pub(crate) type FastSet<T> = hashbrown::HashSet<T, HashBuilder>;
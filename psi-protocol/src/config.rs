@@ -0,0 +1,600 @@
+//! Protocol configuration.
+//!
+//! A [`PsiConfig`] is built once with [`PsiConfigBuilder`] and passed to
+//! [`PsiProtocol::new_with_config`](crate::PsiProtocol::new_with_config).
+//! The default configuration matches the behaviour of
+//! [`PsiProtocol::new`](crate::PsiProtocol::new).
+
+use crate::error::{Limit, Phase, PsiError, Result};
+use crate::messages::{MessageMac, ParameterDigest};
+use crate::psk::PreSharedKey;
+#[cfg(feature = "trace")]
+use crate::trace::{TraceEvent, Tracer};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use sha2::{Digest, Sha256};
+
+/// Version of the protocol, covered by [`PsiConfig::parameter_digest`].
+pub const PROTOCOL_VERSION: u8 = 1;
+
+const PARAMETERS_TAG: &[u8] = b"psi-sync/parameters/v1";
+
+/// Name of the group, covered by [`PsiConfig::parameter_digest`].
+const CURVE: &[u8] = b"ristretto255";
+
+/// Hash function used to turn an item into its 32-byte identifier.
+///
+/// Both parties MUST use the same algorithm, otherwise no item will match.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// SHA-512 truncated to its first 32 bytes.
+    #[default]
+    Sha512Trunc256,
+    /// SHA-256.
+    Sha256,
+}
+
+/// Padding applied to the outgoing blinded points message.
+///
+/// Dummy points are random group elements interleaved with the real ones,
+/// hiding the exact size of the local set from the remote party. They never
+/// match anything and are ignored when computing the intersection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Padding {
+    /// Send exactly one point per unique item.
+    #[default]
+    None,
+    /// Pad the message up to at least this many points.
+    ToSize(usize),
+    /// Pad the message up to the next multiple of this many points.
+    ToMultipleOf(usize),
+}
+
+impl Padding {
+    /// Returns the padded message length for `len` real points.
+    pub fn padded_len(&self, len: usize) -> usize {
+        match *self {
+            Padding::None => len,
+            Padding::ToSize(size) => len.max(size),
+            Padding::ToMultipleOf(multiple) => len.div_ceil(multiple) * multiple,
+        }
+    }
+}
+
+/// Order of the points in the outgoing blinded points message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MessageOrder {
+    /// Randomly shuffle the points.
+    #[default]
+    Shuffled,
+    /// Sort the points by their compressed encoding.
+    Sorted,
+}
+
+/// Configuration for a protocol run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PsiConfig {
+    hash: HashAlgorithm,
+    domain: Vec<u8>,
+    max_local_items: Option<usize>,
+    max_remote_items: Option<usize>,
+    padding: Padding,
+    order: MessageOrder,
+    lenient: bool,
+    allow_empty: bool,
+    threads: usize,
+    hash_threads: usize,
+    compute_threads: usize,
+    vartime: bool,
+    psk: Option<PreSharedKey>,
+    #[cfg(feature = "trace")]
+    tracer: Option<Tracer>,
+}
+
+impl Default for PsiConfig {
+    fn default() -> Self {
+        Self {
+            hash: HashAlgorithm::default(),
+            domain: Vec::new(),
+            max_local_items: None,
+            max_remote_items: None,
+            padding: Padding::default(),
+            order: MessageOrder::default(),
+            lenient: false,
+            allow_empty: false,
+            threads: 1,
+            hash_threads: 1,
+            compute_threads: 1,
+            vartime: false,
+            psk: None,
+            #[cfg(feature = "trace")]
+            tracer: None,
+        }
+    }
+}
+
+impl PsiConfig {
+    /// Start building a configuration from the defaults.
+    pub fn builder() -> PsiConfigBuilder {
+        PsiConfigBuilder::default()
+    }
+
+    /// Hash function used for items.
+    pub fn hash(&self) -> HashAlgorithm {
+        self.hash
+    }
+
+    /// Domain separation tag mixed into hash-to-curve; empty if unset.
+    pub fn domain(&self) -> &[u8] {
+        &self.domain
+    }
+
+    /// Maximum number of local items accepted by the constructor.
+    pub fn max_local_items(&self) -> Option<usize> {
+        self.max_local_items
+    }
+
+    /// Maximum number of points accepted in a remote message.
+    pub fn max_remote_items(&self) -> Option<usize> {
+        self.max_remote_items
+    }
+
+    /// Padding applied to the outgoing message.
+    pub fn padding(&self) -> Padding {
+        self.padding
+    }
+
+    /// Order of the points in the outgoing message.
+    pub fn order(&self) -> MessageOrder {
+        self.order
+    }
+
+    /// Whether invalid remote points are tolerated instead of rejected.
+    pub fn lenient(&self) -> bool {
+        self.lenient
+    }
+
+    /// Whether an empty local set is accepted by the constructor.
+    pub fn allow_empty(&self) -> bool {
+        self.allow_empty
+    }
+
+    /// Number of worker threads used to blind local items.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Number of worker threads used to hash local items to points.
+    pub fn hash_threads(&self) -> usize {
+        self.hash_threads
+    }
+
+    /// Number of worker threads used to double-blind remote points in `compute`.
+    pub fn compute_threads(&self) -> usize {
+        self.compute_threads
+    }
+
+    /// Whether `compute` uses variable-time batch multiplication.
+    pub fn vartime(&self) -> bool {
+        self.vartime
+    }
+
+    /// Key used to authenticate points messages, if any.
+    pub fn pre_shared_key(&self) -> Option<&PreSharedKey> {
+        self.psk.as_ref()
+    }
+
+    /// Digest of the parameters both parties must agree on.
+    ///
+    /// Covers the protocol version, the curve, the hash function, the domain
+    /// separation tag and the padding policy. Every points message carries
+    /// it, and `compute` and `finalize` reject a message whose digest is
+    /// missing or differs from ours with `PsiError::ParameterMismatch`,
+    /// instead of returning an empty intersection. A message built by hand
+    /// with `BlindedPointsMessage::new` must have its `parameters` set.
+    /// With a pre-shared key, the digest is also covered by the MAC.
+    pub fn parameter_digest(&self) -> ParameterDigest {
+        let (padding, size) = match self.padding {
+            Padding::None => (0u8, 0),
+            Padding::ToSize(size) => (1, size),
+            Padding::ToMultipleOf(multiple) => (2, multiple),
+        };
+        let hash = match self.hash {
+            HashAlgorithm::Sha512Trunc256 => 1u8,
+            HashAlgorithm::Sha256 => 2,
+        };
+        let mut hasher = Sha256::new();
+        hasher.update(PARAMETERS_TAG);
+        hasher.update([PROTOCOL_VERSION]);
+        hasher.update((CURVE.len() as u64).to_be_bytes());
+        hasher.update(CURVE);
+        hasher.update([hash]);
+        hasher.update((self.domain.len() as u64).to_be_bytes());
+        hasher.update(&self.domain);
+        hasher.update([padding]);
+        hasher.update((size as u64).to_be_bytes());
+        ParameterDigest(hasher.finalize().into())
+    }
+
+    /// Hand an event to the tracer, if one is set; see `trace_event!`.
+    #[cfg(feature = "trace")]
+    pub(crate) fn trace(
+        &self,
+        phase: crate::error::Phase,
+        step: &'static str,
+        fields: impl FnOnce() -> Vec<(&'static str, String)>,
+    ) {
+        if let Some(tracer) = &self.tracer {
+            tracer.emit(&TraceEvent {
+                phase,
+                step,
+                fields: fields(),
+            });
+        }
+    }
+
+    /// Trace a step's outcome: `fields` on success, the error otherwise;
+    /// see `trace_outcome!`.
+    #[cfg(feature = "trace")]
+    pub(crate) fn trace_outcome<T>(
+        &self,
+        phase: crate::error::Phase,
+        outcome: &Result<T>,
+        step: &'static str,
+        fields: impl FnOnce(&T) -> Vec<(&'static str, String)>,
+    ) {
+        match outcome {
+            Ok(value) => self.trace(phase, step, || fields(value)),
+            Err(error) => self.trace(phase, "rejected", || vec![("error", error.to_string())]),
+        }
+    }
+
+    /// MAC for an outgoing points message, if a pre-shared key is set.
+    pub(crate) fn authenticate(
+        &self,
+        label: &[u8],
+        points: &[CompressedRistretto],
+    ) -> Option<MessageMac> {
+        self.authenticate_lists(label, &[points])
+    }
+
+    /// MAC for an outgoing message with several point lists, if a
+    /// pre-shared key is set.
+    pub(crate) fn authenticate_lists(
+        &self,
+        label: &[u8],
+        lists: &[&[CompressedRistretto]],
+    ) -> Option<MessageMac> {
+        self.psk
+            .as_ref()
+            .map(|psk| psk.authenticate(label, lists, &self.parameter_digest()))
+    }
+
+    /// Verify the MAC of a remote points message if a pre-shared key is set.
+    pub(crate) fn check_authentication(
+        &self,
+        label: &[u8],
+        points: &[CompressedRistretto],
+        mac: Option<&MessageMac>,
+    ) -> Result<()> {
+        self.check_authentication_lists(label, &[points], mac)
+    }
+
+    /// Verify the MAC of a remote message with several point lists if a
+    /// pre-shared key is set.
+    pub(crate) fn check_authentication_lists(
+        &self,
+        label: &[u8],
+        lists: &[&[CompressedRistretto]],
+        mac: Option<&MessageMac>,
+    ) -> Result<()> {
+        match &self.psk {
+            Some(psk) => psk.verify(label, lists, &self.parameter_digest(), mac),
+            None => Ok(()),
+        }
+    }
+
+    /// Compare the parameter digest of a remote message with ours; a
+    /// message without one is rejected.
+    pub(crate) fn check_parameters(
+        &self,
+        phase: Phase,
+        digest: Option<&ParameterDigest>,
+    ) -> Result<()> {
+        match digest {
+            Some(digest) if *digest == self.parameter_digest() => Ok(()),
+            _ => Err(PsiError::ParameterMismatch { phase }),
+        }
+    }
+
+    /// Check the size of a complete local set: not empty unless allowed,
+    /// and within the configured limit.
+    pub(crate) fn check_local_set(&self, len: usize) -> Result<()> {
+        if len == 0 && !self.allow_empty {
+            return Err(PsiError::EmptyInput);
+        }
+        self.check_local_len(len)
+    }
+
+    /// Check a local set size against the configured limit.
+    pub(crate) fn check_local_len(&self, len: usize) -> Result<()> {
+        match self.max_local_items {
+            Some(max) if len > max => Err(PsiError::LimitExceeded {
+                limit: Limit::LocalItems,
+                max,
+                actual: len,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check a remote message length against the configured limit.
+    pub(crate) fn check_remote_len(&self, len: usize) -> Result<()> {
+        match self.max_remote_items {
+            Some(max) if len > max => Err(PsiError::LimitExceeded {
+                limit: Limit::RemotePoints,
+                max,
+                actual: len,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Builder for [`PsiConfig`].
+///
+/// # Example
+/// ```ignore
+/// use psi_protocol::{MessageOrder, Padding, PsiConfig};
+///
+/// let config = PsiConfig::builder()
+///     .max_remote_items(1_000_000)
+///     .padding(Padding::ToMultipleOf(1024))
+///     .order(MessageOrder::Sorted)
+///     .threads(4)
+///     .build()?;
+/// # Ok::<(), psi_protocol::PsiError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PsiConfigBuilder {
+    config: PsiConfig,
+}
+
+impl PsiConfigBuilder {
+    /// Set the hash function used for items.
+    pub fn hash(mut self, hash: HashAlgorithm) -> Self {
+        self.config.hash = hash;
+        self
+    }
+
+    /// Set an application-specific domain separation tag.
+    ///
+    /// The tag is mixed into hash-to-curve, so the same item used by two
+    /// applications is mapped to unrelated points and their protocol
+    /// messages cannot be linked. Both parties MUST use the same tag,
+    /// otherwise no item will match. Item hashes, and so the reported
+    /// [`ItemId`](crate::ItemId)s, do not depend on the tag.
+    pub fn domain(mut self, domain: impl AsRef<[u8]>) -> Self {
+        self.config.domain = domain.as_ref().to_vec();
+        self
+    }
+
+    /// Limit the number of local items accepted by the constructor.
+    pub fn max_local_items(mut self, limit: usize) -> Self {
+        self.config.max_local_items = Some(limit);
+        self
+    }
+
+    /// Limit the number of points accepted in a remote message.
+    pub fn max_remote_items(mut self, limit: usize) -> Self {
+        self.config.max_remote_items = Some(limit);
+        self
+    }
+
+    /// Set the padding applied to the outgoing message.
+    pub fn padding(mut self, padding: Padding) -> Self {
+        self.config.padding = padding;
+        self
+    }
+
+    /// Set the order of the points in the outgoing message.
+    pub fn order(mut self, order: MessageOrder) -> Self {
+        self.config.order = order;
+        self
+    }
+
+    /// Replace invalid remote points with random ones instead of failing.
+    ///
+    /// Positions are preserved so the remote can still align the answer
+    /// with its own items; the replaced points simply never match.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.config.lenient = lenient;
+        self
+    }
+
+    /// Accept an empty local set instead of failing with `PsiError::EmptyInput`.
+    ///
+    /// For a fresh node that has nothing yet but still takes part in a sync:
+    /// it sends no item points (padding still applies), double-blinds the
+    /// remote's points as usual and ends with an empty intersection. The
+    /// remote learns that nothing matched, as with any disjoint set.
+    pub fn allow_empty(mut self, allow_empty: bool) -> Self {
+        self.config.allow_empty = allow_empty;
+        self
+    }
+
+    /// Set the number of worker threads used to blind local items.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.config.threads = threads;
+        self
+    }
+
+    /// Set the number of worker threads used to hash local items to points.
+    ///
+    /// Independent of [`threads`](Self::threads), so ingestion-bound
+    /// workloads can scale hashing separately from blinding.
+    pub fn hash_threads(mut self, threads: usize) -> Self {
+        self.config.hash_threads = threads;
+        self
+    }
+
+    /// Set the number of worker threads used to double-blind remote points.
+    ///
+    /// This is the dominant cost for the responding side. The output keeps
+    /// the order of the remote message regardless of the thread count.
+    ///
+    /// Requires the `parallel` cargo feature.
+    #[cfg(feature = "parallel")]
+    pub fn compute_threads(mut self, threads: usize) -> Self {
+        self.config.compute_threads = threads;
+        self
+    }
+
+    /// Use variable-time batch multiplication when double-blinding remote points.
+    ///
+    /// Speeds up `compute` (and `respond_one_round`) by roughly 30% on large
+    /// remote sets, at the cost of running time that depends on the secret
+    /// scalar. Only enable it where nobody can time the computation, e.g. a
+    /// server-side responder on dedicated hardware. Local blinding in `new`
+    /// always stays constant-time.
+    ///
+    /// Requires the `vartime` cargo feature.
+    #[cfg(feature = "vartime")]
+    pub fn vartime(mut self, vartime: bool) -> Self {
+        self.config.vartime = vartime;
+        self
+    }
+
+    /// Authenticate every points message with a pre-shared key.
+    ///
+    /// Outgoing blinded and double-blinded messages carry a MAC, and
+    /// `compute`/`finalize` reject remote messages without a valid one.
+    /// Both parties MUST use the same key. See [`PreSharedKey`].
+    pub fn pre_shared_key(mut self, key: PreSharedKey) -> Self {
+        self.config.psk = Some(key);
+        self
+    }
+
+    /// Report every phase of sessions using this configuration to `tracer`.
+    ///
+    /// See [`trace`](crate::trace) for what is reported; secrets never are.
+    ///
+    /// Requires the `trace` cargo feature.
+    #[cfg(feature = "trace")]
+    pub fn trace(mut self, tracer: Tracer) -> Self {
+        self.config.tracer = Some(tracer);
+        self
+    }
+
+    /// Validate and build the configuration.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if a thread count is zero or the padding
+    /// multiple is zero.
+    pub fn build(self) -> Result<PsiConfig> {
+        if self.config.threads == 0
+            || self.config.hash_threads == 0
+            || self.config.compute_threads == 0
+        {
+            return Err(PsiError::InvalidConfig(
+                "Thread count must be at least 1".to_string(),
+            ));
+        }
+        if self.config.padding == Padding::ToMultipleOf(0) {
+            return Err(PsiError::InvalidConfig(
+                "Padding multiple must be at least 1".to_string(),
+            ));
+        }
+        Ok(self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config() {
+        let config = PsiConfig::default();
+        assert_eq!(config.hash(), HashAlgorithm::Sha512Trunc256);
+        assert!(config.domain().is_empty());
+        assert_eq!(config.max_local_items(), None);
+        assert_eq!(config.max_remote_items(), None);
+        assert_eq!(config.padding(), Padding::None);
+        assert_eq!(config.order(), MessageOrder::Shuffled);
+        assert!(!config.lenient());
+        assert_eq!(config.threads(), 1);
+        assert_eq!(config.hash_threads(), 1);
+        assert_eq!(config.compute_threads(), 1);
+        assert!(!config.vartime());
+        assert_eq!(PsiConfig::builder().build().unwrap(), config);
+    }
+
+    #[test]
+    fn test_builder_sets_all_fields() {
+        let config = PsiConfig::builder()
+            .hash(HashAlgorithm::Sha256)
+            .domain("my-app/v1")
+            .max_local_items(10)
+            .max_remote_items(20)
+            .padding(Padding::ToSize(16))
+            .order(MessageOrder::Sorted)
+            .lenient(true)
+            .threads(4)
+            .hash_threads(2)
+            .build()
+            .unwrap();
+        assert_eq!(config.hash(), HashAlgorithm::Sha256);
+        assert_eq!(config.domain(), b"my-app/v1");
+        assert_eq!(config.max_local_items(), Some(10));
+        assert_eq!(config.max_remote_items(), Some(20));
+        assert_eq!(config.padding(), Padding::ToSize(16));
+        assert_eq!(config.order(), MessageOrder::Sorted);
+        assert!(config.lenient());
+        assert_eq!(config.threads(), 4);
+        assert_eq!(config.hash_threads(), 2);
+    }
+
+    #[test]
+    fn test_builder_rejects_invalid_values() {
+        assert!(matches!(
+            PsiConfig::builder().threads(0).build(),
+            Err(PsiError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            PsiConfig::builder().hash_threads(0).build(),
+            Err(PsiError::InvalidConfig(_))
+        ));
+        #[cfg(feature = "parallel")]
+        assert!(matches!(
+            PsiConfig::builder().compute_threads(0).build(),
+            Err(PsiError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            PsiConfig::builder().padding(Padding::ToMultipleOf(0)).build(),
+            Err(PsiError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_padded_len() {
+        assert_eq!(Padding::None.padded_len(5), 5);
+        assert_eq!(Padding::ToSize(8).padded_len(5), 8);
+        assert_eq!(Padding::ToSize(4).padded_len(5), 5);
+        assert_eq!(Padding::ToMultipleOf(4).padded_len(5), 8);
+        assert_eq!(Padding::ToMultipleOf(4).padded_len(8), 8);
+    }
+
+    #[test]
+    fn test_check_remote_len() {
+        let config = PsiConfig::builder().max_remote_items(2).build().unwrap();
+        assert!(config.check_remote_len(2).is_ok());
+        assert_eq!(
+            config.check_remote_len(3),
+            Err(PsiError::LimitExceeded {
+                limit: Limit::RemotePoints,
+                max: 2,
+                actual: 3
+            })
+        );
+    }
+}
@@ -0,0 +1,455 @@
+//! Conformance suite for other implementations of the protocol.
+//!
+//! A port of psi-sync (in any language) is checked by running sessions
+//! between this crate and the port. Wrap the port in a [`Candidate`], which
+//! starts one session per [`Case`] and carries [`wire`](crate::wire) frames
+//! to and from it, then call [`run`]:
+//!
+//! ```ignore
+//! use psi_protocol::conformance;
+//!
+//! let report = conformance::run(&mut my_port);
+//! println!("{report}");
+//! assert!(report.passed());
+//! ```
+//!
+//! The suite plays one party with the default configuration and always
+//! sends first. Intersection cases check that both sides compute the
+//! expected intersection; malformed cases send a frame the candidate must
+//! reject, by answering with an abort or error report or by closing the
+//! session, rather than carry on with the protocol. The candidate may send
+//! its own blinded message before reading ours; the suite skips it where
+//! a rejection is expected.
+
+use crate::error::{PsiError, Result};
+use crate::item_id::ItemId;
+use crate::messages::BlindedPointsMessage;
+use crate::protocol::PsiProtocol;
+use crate::wire::{self, WireMessage};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Number of items in each set of the large case.
+const LARGE_CASE_ITEMS: usize = 128;
+
+/// A port of the protocol under test.
+pub trait Candidate {
+    /// Start a new session in which the candidate holds `case.candidate_items`.
+    ///
+    /// Any state of the previous session is dropped.
+    fn start(&mut self, case: &Case) -> Result<()>;
+
+    /// Deliver a frame from the suite to the candidate.
+    fn send(&mut self, frame: &[u8]) -> Result<()>;
+
+    /// Take the next frame the candidate sent.
+    ///
+    /// # Errors
+    /// Any error, including the candidate closing the session, ends the
+    /// session
+    fn receive(&mut self) -> Result<Vec<u8>>;
+
+    /// The intersection the candidate computed in this session.
+    fn result(&mut self) -> Result<Vec<ItemId>>;
+}
+
+/// What a case checks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaseKind {
+    /// A complete session; both sides must find the expected intersection.
+    Intersection,
+    /// The suite sends this frame instead of its blinded message.
+    MalformedBlinded(Vec<u8>),
+    /// The suite answers with one double-blinded point too few.
+    ShortAnswer,
+}
+
+/// One session of the suite.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    /// Stable name of the case, for reports
+    pub name: &'static str,
+    /// Items of the suite's party
+    pub suite_items: Vec<Vec<u8>>,
+    /// Items the candidate must hold
+    pub candidate_items: Vec<Vec<u8>>,
+    /// What the case checks
+    pub kind: CaseKind,
+}
+
+impl Case {
+    fn intersection(name: &'static str, suite: &[&[u8]], candidate: &[&[u8]]) -> Self {
+        Self {
+            name,
+            suite_items: suite.iter().map(|item| item.to_vec()).collect(),
+            candidate_items: candidate.iter().map(|item| item.to_vec()).collect(),
+            kind: CaseKind::Intersection,
+        }
+    }
+
+    fn malformed(name: &'static str, frame: Vec<u8>) -> Self {
+        Self {
+            name,
+            suite_items: vec![b"apple".to_vec()],
+            candidate_items: vec![b"apple".to_vec(), b"banana".to_vec()],
+            kind: CaseKind::MalformedBlinded(frame),
+        }
+    }
+
+    /// Ids of the items both parties hold.
+    pub fn expected(&self) -> HashSet<ItemId> {
+        let ours: HashSet<ItemId> = self.suite_items.iter().map(|i| ItemId::of(i)).collect();
+        self.candidate_items
+            .iter()
+            .map(|item| ItemId::of(item))
+            .filter(|id| ours.contains(id))
+            .collect()
+    }
+}
+
+/// Outcome of one case.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The candidate behaved as specified.
+    Passed,
+    /// The candidate did not; the reason says how.
+    Failed(String),
+}
+
+/// Outcome of one case, by name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseReport {
+    /// Name of the case
+    pub name: &'static str,
+    /// What happened
+    pub outcome: Outcome,
+}
+
+/// Outcome of the whole suite.
+///
+/// Displays as one `PASS`/`FAIL` line per case.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConformanceReport {
+    /// One entry per case, in suite order
+    pub cases: Vec<CaseReport>,
+}
+
+impl ConformanceReport {
+    /// Returns true if every case passed.
+    pub fn passed(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    /// The cases that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &CaseReport> {
+        self.cases
+            .iter()
+            .filter(|case| matches!(case.outcome, Outcome::Failed(_)))
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for case in &self.cases {
+            match &case.outcome {
+                Outcome::Passed => writeln!(f, "PASS {}", case.name)?,
+                Outcome::Failed(reason) => writeln!(f, "FAIL {}: {}", case.name, reason)?,
+            }
+        }
+        let failed = self.failures().count();
+        write!(f, "{} passed, {} failed", self.cases.len() - failed, failed)
+    }
+}
+
+/// The cases of the suite, in the order [`run`] plays them.
+pub fn cases() -> Vec<Case> {
+    let large = |prefix: &str, range: std::ops::Range<usize>| -> Vec<Vec<u8>> {
+        range
+            .map(|i| format!("{prefix}-{i}").into_bytes())
+            .collect()
+    };
+    let valid = blinded_frame(&[b"apple".to_vec()]);
+    let mut truncated = valid.clone();
+    truncated.pop();
+    let mut unknown_version = valid.clone();
+    unknown_version[0] = wire::WIRE_VERSION.wrapping_add(1);
+
+    vec![
+        Case::intersection(
+            "overlap",
+            &[b"apple", b"banana", b"cherry"],
+            &[b"banana", b"cherry", b"date"],
+        ),
+        Case::intersection("disjoint", &[b"apple", b"banana"], &[b"cherry", b"date"]),
+        Case::intersection("identical", &[b"apple", b"banana"], &[b"apple", b"banana"]),
+        Case::intersection("single-item", &[b"apple"], &[b"apple"]),
+        Case::intersection("empty-item", &[b"", b"apple"], &[b""]),
+        Case::intersection(
+            "duplicates",
+            &[b"apple", b"banana"],
+            &[b"apple", b"apple", b"banana", b"banana"],
+        ),
+        Case::intersection(
+            "binary-items",
+            &[&[0u8, 255, 0], &[0xff; 64]],
+            &[&[0u8, 255, 0], &[0xfe; 64]],
+        ),
+        Case::intersection(
+            "unequal-sizes",
+            &[b"apple"],
+            &[b"apple", b"banana", b"cherry"],
+        ),
+        Case {
+            name: "large",
+            suite_items: large("item", 0..LARGE_CASE_ITEMS),
+            candidate_items: large("item", LARGE_CASE_ITEMS / 2..LARGE_CASE_ITEMS * 3 / 2),
+            kind: CaseKind::Intersection,
+        },
+        Case::malformed(
+            "invalid-encoding",
+            blinded_frame_of(vec![CompressedRistretto([0xff; 32])]),
+        ),
+        Case::malformed("truncated-frame", truncated),
+        Case::malformed("unknown-version", unknown_version),
+        Case {
+            name: "short-answer",
+            suite_items: vec![b"apple".to_vec(), b"banana".to_vec()],
+            candidate_items: vec![b"apple".to_vec(), b"cherry".to_vec()],
+            kind: CaseKind::ShortAnswer,
+        },
+    ]
+}
+
+/// Run every case of [`cases`] against `candidate`.
+///
+/// A failing case does not stop the suite; each case starts a new session.
+pub fn run<C: Candidate + ?Sized>(candidate: &mut C) -> ConformanceReport {
+    ConformanceReport {
+        cases: cases()
+            .iter()
+            .map(|case| run_case(candidate, case))
+            .collect(),
+    }
+}
+
+/// Run one case against `candidate`.
+pub fn run_case<C: Candidate + ?Sized>(candidate: &mut C, case: &Case) -> CaseReport {
+    let outcome = match play(candidate, case) {
+        Ok(()) => Outcome::Passed,
+        Err(reason) => Outcome::Failed(reason),
+    };
+    CaseReport {
+        name: case.name,
+        outcome,
+    }
+}
+
+/// Play a case; the error is the reason it failed.
+fn play<C: Candidate + ?Sized>(candidate: &mut C, case: &Case) -> std::result::Result<(), String> {
+    let transport = |e: PsiError| format!("transport: {e}");
+    candidate.start(case).map_err(transport)?;
+    let ours = PsiProtocol::new(&case.suite_items).map_err(|e| e.to_string())?;
+
+    if let CaseKind::MalformedBlinded(frame) = &case.kind {
+        candidate.send(frame).map_err(transport)?;
+        return expect_rejection(candidate, &[wire::MessageKind::Blinded]);
+    }
+
+    candidate
+        .send(&wire::encode(&WireMessage::Blinded(ours.message())))
+        .map_err(transport)?;
+    let theirs = match receive(candidate)? {
+        WireMessage::Blinded(msg) => msg,
+        other => return Err(unexpected("a blinded message", &other)),
+    };
+    let (ours, mut answer) = ours
+        .compute(theirs)
+        .map_err(|e| format!("candidate's blinded message rejected: {e}"))?;
+
+    if case.kind == CaseKind::ShortAnswer {
+        answer.double_blinded_points.pop();
+        answer.authentication = None;
+        candidate
+            .send(&wire::encode(&WireMessage::DoubleBlinded(answer)))
+            .map_err(transport)?;
+        return expect_rejection(candidate, &[wire::MessageKind::DoubleBlinded]);
+    }
+
+    candidate
+        .send(&wire::encode(&WireMessage::DoubleBlinded(answer)))
+        .map_err(transport)?;
+    let theirs = match receive(candidate)? {
+        WireMessage::DoubleBlinded(msg) => msg,
+        other => return Err(unexpected("a double-blinded message", &other)),
+    };
+    let (_, result) = ours
+        .finalize(theirs)
+        .map_err(|e| format!("candidate's double-blinded message rejected: {e}"))?;
+
+    let expected = case.expected();
+    let ours: HashSet<ItemId> = result.intersection_hashes.into_iter().collect();
+    if ours != expected {
+        return Err(format!(
+            "suite found {} shared items, expected {}",
+            ours.len(),
+            expected.len()
+        ));
+    }
+    let theirs = candidate.result().map_err(transport)?;
+    let reported = theirs.len();
+    let theirs: HashSet<ItemId> = theirs.into_iter().collect();
+    if theirs != expected || reported != expected.len() {
+        return Err(format!(
+            "candidate reported {} shared items, expected {}",
+            reported,
+            expected.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Read frames until the candidate rejects the session; frames of the
+/// `skipped` kinds are its own messages of the current round.
+fn expect_rejection<C: Candidate + ?Sized>(
+    candidate: &mut C,
+    skipped: &[wire::MessageKind],
+) -> std::result::Result<(), String> {
+    loop {
+        let Ok(frame) = candidate.receive() else {
+            // Closing the session is a rejection
+            return Ok(());
+        };
+        match wire::decode(&frame) {
+            Ok(WireMessage::Abort(_) | WireMessage::ErrorReport(_)) => return Ok(()),
+            Ok(msg) if skipped.contains(&msg.kind()) => continue,
+            Ok(other) => return Err(unexpected("a rejection", &other)),
+            Err(e) => return Err(format!("candidate sent an invalid frame: {e}")),
+        }
+    }
+}
+
+/// Receive and decode the candidate's next frame.
+fn receive<C: Candidate + ?Sized>(candidate: &mut C) -> std::result::Result<WireMessage, String> {
+    let frame = candidate.receive().map_err(|e| format!("transport: {e}"))?;
+    wire::decode(&frame).map_err(|e| format!("candidate sent an invalid frame: {e}"))
+}
+
+fn unexpected(wanted: &str, got: &WireMessage) -> String {
+    format!("expected {wanted}, candidate sent {:?}", got.kind())
+}
+
+/// A valid blinded points frame for `items`.
+fn blinded_frame(items: &[Vec<u8>]) -> Vec<u8> {
+    let protocol = PsiProtocol::new(items).expect("fixed items are valid");
+    wire::encode(&WireMessage::Blinded(protocol.message()))
+}
+
+/// A blinded points frame carrying `points` as they are.
+fn blinded_frame_of(points: Vec<CompressedRistretto>) -> Vec<u8> {
+    wire::encode(&WireMessage::Blinded(BlindedPointsMessage::new(points)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{AbortMessage, DoubleBlindedPointsMessage, ErrorReportMessage};
+    use crate::session::PsiSession;
+    use crate::AbortReason;
+    use std::collections::VecDeque;
+
+    /// This crate as a candidate, answering frames as they arrive.
+    #[derive(Default)]
+    struct Reference {
+        session: Option<PsiSession>,
+        outbox: VecDeque<Vec<u8>>,
+        result: Vec<ItemId>,
+        /// Answer blinded messages it should reject with their points
+        lax: bool,
+    }
+
+    impl Reference {
+        fn reject(&mut self, error: &PsiError) {
+            let msg = match ErrorReportMessage::for_error(error) {
+                Some(report) => WireMessage::ErrorReport(report),
+                None => WireMessage::Abort(AbortMessage::new(AbortReason::InvalidMessage)),
+            };
+            self.outbox.push_back(wire::encode(&msg));
+            self.session = None;
+        }
+    }
+
+    impl Candidate for Reference {
+        fn start(&mut self, case: &Case) -> Result<()> {
+            let session = PsiSession::new(&case.candidate_items)?;
+            self.outbox = VecDeque::from([wire::encode(&WireMessage::Blinded(session.message()?))]);
+            self.session = Some(session);
+            self.result.clear();
+            Ok(())
+        }
+
+        fn send(&mut self, frame: &[u8]) -> Result<()> {
+            let Some(session) = self.session.as_mut() else {
+                return Err(PsiError::Aborted(AbortReason::Unspecified));
+            };
+            let lax = self.lax;
+            let outcome = wire::decode(frame).and_then(|msg| match msg {
+                WireMessage::Blinded(msg) => session
+                    .on_blinded(msg.clone())
+                    .or_else(|e| match lax {
+                        true => Ok(DoubleBlindedPointsMessage::new(msg.blinded_points)),
+                        false => Err(e),
+                    })
+                    .map(|answer| Some(WireMessage::DoubleBlinded(answer))),
+                WireMessage::DoubleBlinded(msg) => {
+                    let result = session.on_double_blinded(msg)?;
+                    self.result = result.intersection_hashes;
+                    Ok(None)
+                }
+                _ => Ok(None),
+            });
+            match outcome {
+                Ok(Some(reply)) => self.outbox.push_back(wire::encode(&reply)),
+                Ok(None) => {}
+                Err(error) => self.reject(&error),
+            }
+            Ok(())
+        }
+
+        fn receive(&mut self) -> Result<Vec<u8>> {
+            self.outbox
+                .pop_front()
+                .ok_or(PsiError::Aborted(AbortReason::Unspecified))
+        }
+
+        fn result(&mut self) -> Result<Vec<ItemId>> {
+            Ok(self.result.clone())
+        }
+    }
+
+    #[test]
+    fn test_reference_passes() {
+        let report = run(&mut Reference::default());
+        assert!(report.passed(), "{report}");
+        assert_eq!(report.cases.len(), cases().len());
+    }
+
+    #[test]
+    fn test_reports_candidate_accepting_invalid_points() {
+        let mut lax = Reference {
+            lax: true,
+            ..Reference::default()
+        };
+        // Only the malformed cases, the others pass as for the reference
+        let report = ConformanceReport {
+            cases: cases()
+                .iter()
+                .filter(|case| case.kind != CaseKind::Intersection)
+                .map(|case| run_case(&mut lax, case))
+                .collect(),
+        };
+        let failed: Vec<&str> = report.failures().map(|case| case.name).collect();
+        assert_eq!(failed, vec!["invalid-encoding"]);
+        assert!(report.to_string().contains("FAIL invalid-encoding"));
+    }
+}
@@ -0,0 +1,363 @@
+//! Scheduling sharded runs over a cluster.
+//!
+//! At billions of items a [`Sharding`] with thousands of shards is spread
+//! over many machines: each worker pair (one worker per side, holding the
+//! same shard of each set) runs a plain protocol over one shard at a time.
+//! [`ShardCoordinator`] is the bookkeeping around that: it hands shards to
+//! idle worker pairs, gives each assignment a lease, puts failed or expired
+//! shards back in the queue until they run out of attempts, and assembles
+//! the global [`PsiResult`] once every shard is done.
+//!
+//! The coordinator does no I/O. Workers report back through whatever RPC the
+//! deployment uses; only the coordinator of the side that needs the result
+//! has to exist, and the peer side's workers simply follow.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::coordinator::{RetryPolicy, ShardCoordinator};
+//! use psi_protocol::sharding::Sharding;
+//!
+//! let mut coordinator = ShardCoordinator::new(Sharding::new(4096)?, RetryPolicy::default());
+//!
+//! // A worker pair asks for work
+//! if let Some(assignment) = coordinator.assign(pair_id) {
+//!     dispatch(pair_id, assignment.shard);
+//! }
+//! // ... and reports back
+//! coordinator.complete(shard, &pair_id, result)?;
+//! // or: coordinator.fail(shard, &pair_id, "peer worker unreachable")?;
+//!
+//! if coordinator.is_finished() {
+//!     let result = coordinator.into_result()?;
+//! }
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::error::{PsiError, Result};
+use crate::messages::PsiResult;
+use crate::sharding::{combine, Sharding};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// How often and for how long a shard is tried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts per shard before it is marked failed
+    pub max_attempts: u32,
+    /// Time a worker pair has to report on a shard before it is reassigned
+    pub lease: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts with a ten-minute lease each.
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            lease: Duration::from_secs(600),
+        }
+    }
+}
+
+/// A shard handed to a worker pair.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment<W> {
+    /// Shard to intersect, see [`Sharding::shard_of`]
+    pub shard: u32,
+    /// Worker pair the shard is assigned to
+    pub worker: W,
+    /// Attempt number, starting at 1
+    pub attempt: u32,
+    /// Report before this instant or the shard is reassigned
+    pub deadline: Instant,
+}
+
+/// Where a shard stands.
+#[derive(Debug, Clone)]
+enum ShardState<W> {
+    Pending { attempts: u32 },
+    Running(Assignment<W>),
+    Done(PsiResult),
+    Failed { attempts: u32, error: String },
+}
+
+/// Counts of shards in each state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub pending: usize,
+    pub running: usize,
+    pub done: usize,
+    pub failed: usize,
+}
+
+/// Shard assignment, retries and result assembly for one sharded run.
+#[derive(Debug)]
+pub struct ShardCoordinator<W> {
+    sharding: Sharding,
+    policy: RetryPolicy,
+    shards: BTreeMap<u32, ShardState<W>>,
+    /// Pending shards in assignment order; retried shards go to the back
+    queue: VecDeque<u32>,
+}
+
+impl<W: Clone + PartialEq> ShardCoordinator<W> {
+    /// Create a coordinator with every shard pending.
+    pub fn new(sharding: Sharding, policy: RetryPolicy) -> Self {
+        Self {
+            sharding,
+            policy,
+            shards: (0..sharding.shards())
+                .map(|shard| (shard, ShardState::Pending { attempts: 0 }))
+                .collect(),
+            queue: (0..sharding.shards()).collect(),
+        }
+    }
+
+    /// The sharding being run.
+    pub fn sharding(&self) -> Sharding {
+        self.sharding
+    }
+
+    /// Give the next pending shard to `worker`, if any is left.
+    ///
+    /// Expired leases are reclaimed first.
+    pub fn assign(&mut self, worker: W) -> Option<Assignment<W>> {
+        self.assign_at(worker, Instant::now())
+    }
+
+    /// Same as [`assign`](Self::assign), at the given instant.
+    pub fn assign_at(&mut self, worker: W, now: Instant) -> Option<Assignment<W>> {
+        self.reclaim_expired_at(now);
+        let shard = self.queue.pop_front()?;
+        let attempts = match self.shards.get(&shard) {
+            Some(ShardState::Pending { attempts }) => *attempts,
+            _ => unreachable!("queued shards are pending"),
+        };
+        let assignment = Assignment {
+            shard,
+            worker,
+            attempt: attempts + 1,
+            deadline: now + self.policy.lease,
+        };
+        self.shards
+            .insert(shard, ShardState::Running(assignment.clone()));
+        Some(assignment)
+    }
+
+    /// Record the result of a shard.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` if the shard is not currently
+    /// assigned to `worker`, e.g. because its lease expired and it was
+    /// reassigned; the late result is dropped
+    pub fn complete(&mut self, shard: u32, worker: &W, result: PsiResult) -> Result<()> {
+        self.running(shard, worker, "complete")?;
+        self.shards.insert(shard, ShardState::Done(result));
+        Ok(())
+    }
+
+    /// Record a failed attempt; the shard is retried unless it ran out of
+    /// attempts.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` if the shard is not currently
+    /// assigned to `worker`
+    pub fn fail(&mut self, shard: u32, worker: &W, error: impl std::fmt::Display) -> Result<()> {
+        let attempts = self.running(shard, worker, "fail")?;
+        self.retry(shard, attempts, error.to_string());
+        Ok(())
+    }
+
+    /// Put every shard whose lease expired at or before `now` back in the
+    /// queue (or mark it failed). Returns the shards reclaimed.
+    pub fn reclaim_expired_at(&mut self, now: Instant) -> Vec<u32> {
+        let expired: Vec<(u32, u32)> = self
+            .shards
+            .iter()
+            .filter_map(|(shard, state)| match state {
+                ShardState::Running(assignment) if assignment.deadline <= now => {
+                    Some((*shard, assignment.attempt))
+                }
+                _ => None,
+            })
+            .collect();
+        for (shard, attempts) in &expired {
+            self.retry(*shard, *attempts, "lease expired".to_string());
+        }
+        expired.into_iter().map(|(shard, _)| shard).collect()
+    }
+
+    /// Attempts of a shard running on `worker`.
+    fn running(&self, shard: u32, worker: &W, operation: &'static str) -> Result<u32> {
+        match self.shards.get(&shard) {
+            Some(ShardState::Running(assignment)) if assignment.worker == *worker => {
+                Ok(assignment.attempt)
+            }
+            Some(state) => Err(PsiError::UnexpectedState {
+                operation,
+                state: match state {
+                    ShardState::Pending { .. } => "pending",
+                    ShardState::Running(_) => "reassigned",
+                    ShardState::Done(_) => "done",
+                    ShardState::Failed { .. } => "failed",
+                },
+            }),
+            None => Err(PsiError::UnexpectedState {
+                operation,
+                state: "unknown shard",
+            }),
+        }
+    }
+
+    fn retry(&mut self, shard: u32, attempts: u32, error: String) {
+        let state = if attempts >= self.policy.max_attempts {
+            ShardState::Failed { attempts, error }
+        } else {
+            self.queue.push_back(shard);
+            ShardState::Pending { attempts }
+        };
+        self.shards.insert(shard, state);
+    }
+
+    /// Shards in each state.
+    pub fn progress(&self) -> Progress {
+        let mut progress = Progress::default();
+        for state in self.shards.values() {
+            match state {
+                ShardState::Pending { .. } => progress.pending += 1,
+                ShardState::Running(_) => progress.running += 1,
+                ShardState::Done(_) => progress.done += 1,
+                ShardState::Failed { .. } => progress.failed += 1,
+            }
+        }
+        progress
+    }
+
+    /// Returns true once no shard is pending or running.
+    pub fn is_finished(&self) -> bool {
+        self.shards
+            .values()
+            .all(|state| matches!(state, ShardState::Done(_) | ShardState::Failed { .. }))
+    }
+
+    /// Shards that ran out of attempts, with their last error.
+    pub fn failed(&self) -> Vec<(u32, &str)> {
+        self.shards
+            .iter()
+            .filter_map(|(shard, state)| match state {
+                ShardState::Failed { error, .. } => Some((*shard, error.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Assemble the global result from every shard, in shard order.
+    ///
+    /// # Errors
+    /// Returns `PsiError::TaskFailed` naming the first shard that is not
+    /// done, with its last error if it failed
+    pub fn into_result(self) -> Result<PsiResult> {
+        let mut results = Vec::with_capacity(self.shards.len());
+        for (shard, state) in self.shards {
+            match state {
+                ShardState::Done(result) => results.push(result),
+                ShardState::Failed { attempts, error } => {
+                    return Err(PsiError::TaskFailed(format!(
+                        "Shard {} failed after {} attempts: {}",
+                        shard, attempts, error
+                    )))
+                }
+                ShardState::Pending { .. } | ShardState::Running(_) => {
+                    return Err(PsiError::TaskFailed(format!(
+                        "Shard {} has not completed",
+                        shard
+                    )))
+                }
+            }
+        }
+        Ok(combine(results))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HashAlgorithm;
+    use crate::local::run_local_psi;
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            lease: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn test_coordinated_shards_give_the_global_result() {
+        let items = |range: std::ops::Range<u32>| -> Vec<Vec<u8>> {
+            range.map(|i| i.to_be_bytes().to_vec()).collect()
+        };
+        let (alice_items, bob_items) = (items(0..24), items(16..40));
+        let sharding = Sharding::new(4).unwrap();
+        let alice_shards = sharding.partition(HashAlgorithm::default(), &alice_items);
+        let bob_shards = sharding.partition(HashAlgorithm::default(), &bob_items);
+
+        let mut coordinator = ShardCoordinator::new(sharding, RetryPolicy::default());
+        let workers = ["pair-a", "pair-b"];
+        let mut round = 0;
+        while let Some(assignment) = coordinator.assign(workers[round % 2]) {
+            round += 1;
+            let (Some(alice), Some(bob)) = (
+                alice_shards.get(&assignment.shard),
+                bob_shards.get(&assignment.shard),
+            ) else {
+                coordinator
+                    .complete(assignment.shard, &assignment.worker, PsiResult::default())
+                    .unwrap();
+                continue;
+            };
+            let (result, _) = run_local_psi(alice, bob).unwrap();
+            coordinator
+                .complete(assignment.shard, &assignment.worker, result)
+                .unwrap();
+        }
+        assert!(coordinator.is_finished());
+        assert_eq!(coordinator.progress().done, 4);
+
+        let mut sharded = coordinator.into_result().unwrap().intersection_hashes;
+        let mut expected = run_local_psi(&alice_items, &bob_items)
+            .unwrap()
+            .0
+            .intersection_hashes;
+        sharded.sort();
+        expected.sort();
+        assert_eq!(sharded, expected);
+    }
+
+    #[test]
+    fn test_failed_and_expired_shards_are_retried() {
+        let mut coordinator = ShardCoordinator::new(Sharding::new(1).unwrap(), policy(2));
+        let start = Instant::now();
+
+        let first = coordinator.assign_at(1, start).unwrap();
+        assert_eq!(first.attempt, 1);
+        assert!(coordinator.assign_at(2, start).is_none());
+
+        // The lease runs out; the shard goes to the next worker
+        let later = start + Duration::from_secs(61);
+        let second = coordinator.assign_at(2, later).unwrap();
+        assert_eq!((second.shard, second.attempt), (0, 2));
+        // The first worker's late result is refused
+        assert!(matches!(
+            coordinator.complete(0, &1, PsiResult::default()),
+            Err(PsiError::UnexpectedState { .. })
+        ));
+
+        coordinator.fail(0, &2, "peer unreachable").unwrap();
+        assert!(coordinator.is_finished());
+        assert_eq!(coordinator.failed(), vec![(0, "peer unreachable")]);
+        assert!(matches!(
+            coordinator.into_result(),
+            Err(PsiError::TaskFailed(_))
+        ));
+    }
+}
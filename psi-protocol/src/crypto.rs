@@ -1,12 +1,20 @@
 //! Cryptographic operations for the PSI protocol.
 
+use crate::config::HashAlgorithm;
 use crate::error::{PsiError, Result};
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
 use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
 use curve25519_dalek::Scalar;
+use hkdf::Hkdf;
 use rand::rngs::OsRng;
-use sha2::{Digest, Sha512};
+use rand::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256, Sha512};
+#[cfg(test)]
 use std::collections::HashMap;
 
+/// Prefix of the hash-to-curve input when a domain separation tag is set.
+const DOMAIN_TAG: &[u8] = b"psi-sync/domain";
+
 /// Hash a byte array to a 32-byte SHA-512 hash.
 ///
 /// # Arguments
@@ -23,6 +31,54 @@ pub fn hash_bytes(input: &[u8]) -> [u8; 32] {
     hash
 }
 
+/// Hash a byte array to a 32-byte identifier with the given algorithm.
+///
+/// # Arguments
+/// * `algorithm` - Hash function to use
+/// * `input` - Input bytes to hash
+///
+/// # Returns
+/// A 32-byte hash
+pub fn hash_bytes_with(algorithm: HashAlgorithm, input: &[u8]) -> [u8; 32] {
+    match algorithm {
+        HashAlgorithm::Sha512Trunc256 => hash_bytes(input),
+        HashAlgorithm::Sha256 => Sha256::digest(input).into(),
+    }
+}
+
+/// Hash an item to the 32-byte identifier reported in a [`PsiResult`].
+///
+/// This is the hash used by the default configuration (SHA-512 truncated to
+/// 32 bytes). Use it to map `intersection_hashes` back to your own items
+/// instead of re-implementing the hash; its output is part of the stable API
+/// and will not change without a major version bump.
+///
+/// For a non-default [`HashAlgorithm`], use [`hash_item_with`].
+///
+/// # Example
+/// ```ignore
+/// use psi_protocol::{hash_item, ItemId};
+///
+/// let id = ItemId::from(hash_item(b"apple"));
+/// if result.intersection_hashes.contains(&id) {
+///     println!("apple is shared");
+/// }
+/// ```
+///
+/// [`PsiResult`]: crate::PsiResult
+pub fn hash_item(item: &[u8]) -> [u8; 32] {
+    hash_bytes(item)
+}
+
+/// Hash an item to its 32-byte identifier with the given algorithm.
+///
+/// Matches the hashes reported by a protocol run configured with
+/// [`PsiConfigBuilder::hash`](crate::PsiConfigBuilder::hash). Stable in the
+/// same way as [`hash_item`].
+pub fn hash_item_with(algorithm: HashAlgorithm, item: &[u8]) -> [u8; 32] {
+    hash_bytes_with(algorithm, item)
+}
+
 /// Map a 32-byte hash to a Ristretto point using hash-to-curve.
 ///
 /// # Arguments
@@ -34,6 +90,23 @@ pub fn hash_to_point(hash: &[u8; 32]) -> RistrettoPoint {
     RistrettoPoint::hash_from_bytes::<Sha512>(hash)
 }
 
+/// Map a 32-byte hash to a Ristretto point within an application domain.
+///
+/// An empty `domain` gives the same point as [`hash_to_point`]; any other
+/// domain is length-prefixed and hashed in front of the item hash, so two
+/// domains never map an item to the same point.
+pub fn hash_to_point_in(domain: &[u8], hash: &[u8; 32]) -> RistrettoPoint {
+    if domain.is_empty() {
+        return hash_to_point(hash);
+    }
+    let hasher = Sha512::new()
+        .chain_update(DOMAIN_TAG)
+        .chain_update((domain.len() as u64).to_be_bytes())
+        .chain_update(domain)
+        .chain_update(hash);
+    RistrettoPoint::from_hash(hasher)
+}
+
 /// Hash multiple byte arrays to 32-byte SHA-512 hashes.
 ///
 /// # Arguments
@@ -41,6 +114,7 @@ pub fn hash_to_point(hash: &[u8; 32]) -> RistrettoPoint {
 ///
 /// # Returns
 /// A vector of 32-byte hashes
+#[allow(dead_code)]
 pub fn hash_multiple(inputs: &[Vec<u8>]) -> Vec<[u8; 32]> {
     inputs.iter().map(|input| hash_bytes(input)).collect()
 }
@@ -54,11 +128,28 @@ pub fn hash_multiple(inputs: &[Vec<u8>]) -> Vec<[u8; 32]> {
 ///
 /// # Returns
 /// A HashMap mapping input hashes to their corresponding Ristretto points
+#[cfg(test)]
 pub fn hash_inputs_to_points(inputs: &[Vec<u8>]) -> HashMap<[u8; 32], RistrettoPoint> {
+    hash_inputs_to_points_with(HashAlgorithm::default(), inputs)
+}
+
+/// Hash multiple byte arrays to Ristretto points with the given algorithm.
+///
+/// # Arguments
+/// * `algorithm` - Hash function to use for the item hashes
+/// * `inputs` - Slice of input byte vectors
+///
+/// # Returns
+/// A HashMap mapping input hashes to their corresponding Ristretto points
+#[cfg(test)]
+pub fn hash_inputs_to_points_with(
+    algorithm: HashAlgorithm,
+    inputs: &[Vec<u8>],
+) -> HashMap<[u8; 32], RistrettoPoint> {
     inputs
         .iter()
         .map(|input| {
-            let hash = hash_bytes(input);
+            let hash = hash_bytes_with(algorithm, input);
             (hash, hash_to_point(&hash))
         })
         .collect()
@@ -84,6 +175,7 @@ pub fn blind_point(point: &RistrettoPoint, secret: &Scalar) -> CompressedRistret
 ///
 /// # Returns
 /// A HashMap mapping hashes to blinded points
+#[cfg(test)]
 pub fn blind_points(
     points: &HashMap<[u8; 32], RistrettoPoint>,
     secret: &Scalar,
@@ -94,6 +186,121 @@ pub fn blind_points(
         .collect()
 }
 
+/// Blind sorted `(hash, point)` pairs with a scalar, splitting the work across threads.
+///
+/// # Arguments
+/// * `points` - Pairs of hashes and points, as returned by [`hash_inputs_sorted`]
+/// * `secret` - The scalar to multiply with
+/// * `threads` - Number of worker threads; `1` runs on the calling thread
+///
+/// # Returns
+/// Pairs of hashes and blinded points, in the same order as `points`
+pub fn blind_points_parallel(
+    points: &[([u8; 32], RistrettoPoint)],
+    secret: &Scalar,
+    threads: usize,
+) -> Vec<([u8; 32], CompressedRistretto)> {
+    parallel_map(points, threads, |(hash, point)| {
+        (*hash, blind_point(point, secret))
+    })
+}
+
+/// Hash multiple byte arrays to Ristretto points, splitting the work across threads.
+///
+/// Duplicate items are removed before hash-to-curve, which dominates
+/// ingestion of large sets; `threads` scales it with the number of cores
+/// independently of the blinding parallelism.
+///
+/// # Arguments
+/// * `algorithm` - Hash function to use for the item hashes
+/// * `domain` - Domain separation tag for hash-to-curve, see [`hash_to_point_in`]
+/// * `inputs` - Slice of input byte vectors
+/// * `threads` - Number of worker threads; `1` runs on the calling thread
+///
+/// # Returns
+/// Pairs of unique input hashes and their Ristretto points, sorted by hash
+pub fn hash_inputs_sorted(
+    algorithm: HashAlgorithm,
+    domain: &[u8],
+    inputs: &[Vec<u8>],
+    threads: usize,
+) -> Vec<([u8; 32], RistrettoPoint)> {
+    let mut hashes = parallel_map(inputs, threads, |input| hash_bytes_with(algorithm, input));
+    hashes.sort_unstable();
+    hashes.dedup();
+    parallel_map(&hashes, threads, |hash| {
+        (*hash, hash_to_point_in(domain, hash))
+    })
+}
+
+/// Map `f` over `items` on up to `threads` scoped threads, preserving order.
+pub(crate) fn parallel_map<T, R, F>(items: &[T], threads: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    if threads <= 1 || items.len() < 2 {
+        return items.iter().map(f).collect();
+    }
+    let chunk_size = items.len().div_ceil(threads);
+    let f = &f;
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Vec<_>>()))
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("worker thread panicked"))
+            .collect()
+    })
+}
+
+/// Multiply many points by one scalar in variable time and compress them in a batch.
+///
+/// Each multiplication uses curve25519-dalek's variable-time wNAF
+/// multiplication, and compression shares a single field inversion across
+/// the whole batch. This is roughly 30% faster than [`blind_point`] per point,
+/// but the running time depends on the bits of `secret`.
+///
+/// Only use this where an observer timing the computation is acceptable,
+/// e.g. a server answering from a dedicated host.
+///
+/// # Arguments
+/// * `points` - The points to multiply
+/// * `secret` - The scalar to multiply with
+///
+/// # Returns
+/// The compressed products, in input order
+#[cfg(feature = "vartime")]
+pub fn vartime_blind_batch(points: &[RistrettoPoint], secret: &Scalar) -> Vec<CompressedRistretto> {
+    // double_and_compress_batch computes compress(2P), so multiply by secret / 2
+    let half_secret = secret * Scalar::from(2u8).invert();
+    let halves: Vec<RistrettoPoint> = points
+        .iter()
+        .map(|point| {
+            RistrettoPoint::vartime_double_scalar_mul_basepoint(&half_secret, point, &Scalar::ZERO)
+        })
+        .collect();
+    RistrettoPoint::double_and_compress_batch(&halves)
+}
+
+/// Generate a random compressed point, used as padding.
+///
+/// # Returns
+/// A uniformly random compressed Ristretto point
+pub fn random_point() -> CompressedRistretto {
+    random_point_from(&mut OsRng)
+}
+
+/// Generate a random compressed point from `rng`.
+pub(crate) fn random_point_from<R: RngCore + CryptoRng>(rng: &mut R) -> CompressedRistretto {
+    RistrettoPoint::random(rng).compress()
+}
+
 /// Generate a random scalar using OsRng.
 ///
 /// # Returns
@@ -103,6 +310,56 @@ pub fn random_scalar() -> Scalar {
     Scalar::random(&mut rng)
 }
 
+/// Minimum length of input key material accepted by [`derive_scalar`].
+pub const MIN_IKM_LEN: usize = 32;
+
+/// Salt used when deriving blinding scalars with HKDF.
+const DERIVE_SALT: &[u8] = b"psi-sync/v1/blinding-secret";
+
+/// Salt used when deriving message layout seeds with HKDF.
+const LAYOUT_SALT: &[u8] = b"psi-sync/v1/message-layout";
+
+/// Derive a scalar deterministically from key material using HKDF-SHA512.
+///
+/// # Arguments
+/// * `ikm` - Input key material, at least [`MIN_IKM_LEN`] bytes
+/// * `context` - Context string; different contexts yield unrelated scalars
+///
+/// # Returns
+/// A scalar uniformly distributed when `ikm` has enough entropy
+///
+/// # Errors
+/// Returns `PsiError::CryptoError` if `ikm` is too short
+pub fn derive_scalar(ikm: &[u8], context: &[u8]) -> Result<Scalar> {
+    let mut okm = [0u8; 64];
+    derive_okm(DERIVE_SALT, ikm, context, &mut okm)?;
+    Ok(Scalar::from_bytes_mod_order_wide(&okm))
+}
+
+/// Derive the seed of a message layout (padding points and shuffle) from
+/// the key material of [`derive_scalar`], independent of the scalar.
+///
+/// # Errors
+/// Returns `PsiError::CryptoError` if `ikm` is too short
+pub(crate) fn derive_layout_seed(ikm: &[u8], context: &[u8]) -> Result<[u8; 32]> {
+    let mut seed = [0u8; 32];
+    derive_okm(LAYOUT_SALT, ikm, context, &mut seed)?;
+    Ok(seed)
+}
+
+/// HKDF-SHA512 of `ikm` under `salt`, expanded with `context` into `okm`.
+fn derive_okm(salt: &[u8], ikm: &[u8], context: &[u8], okm: &mut [u8]) -> Result<()> {
+    if ikm.len() < MIN_IKM_LEN {
+        return Err(PsiError::CryptoError(format!(
+            "Input key material must be at least {} bytes",
+            MIN_IKM_LEN
+        )));
+    }
+    let hkdf = Hkdf::<Sha512>::new(Some(salt), ikm);
+    hkdf.expand(context, okm)
+        .map_err(|e| PsiError::CryptoError(format!("HKDF expansion failed: {}", e)))
+}
+
 /// Decompress a compressed Ristretto point.
 ///
 /// # Arguments
@@ -119,6 +376,22 @@ pub fn decompress_point(compressed: &CompressedRistretto) -> Result<RistrettoPoi
         .ok_or_else(|| PsiError::CryptoError("Failed to decompress Ristretto point".to_string()))
 }
 
+/// Decompress a remote point, substituting the basepoint if it is invalid.
+///
+/// Callers keep working on the substitute and report invalid positions
+/// only once the whole batch is processed, so the running time does not
+/// tell the remote where in its message (or whether) an invalid point was
+/// found.
+///
+/// # Returns
+/// The point (or the basepoint) and whether the encoding was valid
+pub(crate) fn decompress_or_basepoint(compressed: &CompressedRistretto) -> (RistrettoPoint, bool) {
+    match compressed.decompress() {
+        Some(point) => (point, true),
+        None => (RISTRETTO_BASEPOINT_POINT, false),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -149,6 +422,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_hash_to_point_in_domain() {
+        let hash = [42u8; 32];
+        assert_eq!(hash_to_point_in(b"", &hash), hash_to_point(&hash));
+        assert_ne!(hash_to_point_in(b"app-a", &hash), hash_to_point(&hash));
+        assert_ne!(
+            hash_to_point_in(b"app-a", &hash),
+            hash_to_point_in(b"app-b", &hash)
+        );
+    }
+
     #[test]
     fn test_hash_multiple() {
         let inputs = vec![b"apple".to_vec(), b"banana".to_vec()];
@@ -190,11 +474,95 @@ mod tests {
 
         assert_eq!(blinded.len(), 2);
         // Blinded points should be valid compressed points
-        for (_, compressed) in &blinded {
+        for compressed in blinded.values() {
             assert!(decompress_point(compressed).is_ok());
         }
     }
 
+    #[test]
+    fn test_hash_inputs_sorted() {
+        let mut inputs: Vec<Vec<u8>> = (0u8..10).map(|i| vec![i]).collect();
+        inputs.push(vec![3]);
+        let sorted = hash_inputs_sorted(HashAlgorithm::Sha256, b"", &inputs, 4);
+        assert_eq!(sorted.len(), 10);
+        assert!(sorted.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(
+            sorted.into_iter().collect::<HashMap<_, _>>(),
+            hash_inputs_to_points_with(HashAlgorithm::Sha256, &inputs)
+        );
+    }
+
+    #[test]
+    fn test_parallel_map_preserves_order() {
+        let items: Vec<u32> = (0..100).collect();
+        let doubled = parallel_map(&items, 7, |x| x * 2);
+        assert_eq!(doubled, items.iter().map(|x| x * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_hash_item_is_stable() {
+        // Pinned test vectors: changing these breaks downstream item lookups
+        let apple_sha512_trunc = [
+            0x84, 0x4d, 0x87, 0x79, 0x10, 0x3b, 0x94, 0xc1, 0x8f, 0x4a, 0xa4, 0xcc, 0x0c, 0x3b,
+            0x44, 0x74, 0x05, 0x85, 0x80, 0xa9, 0x91, 0xfb, 0xa8, 0x5d, 0x3c, 0xa6, 0x98, 0xa0,
+            0xbc, 0x9e, 0x52, 0xc5,
+        ];
+        let apple_sha256 = [
+            0x3a, 0x7b, 0xd3, 0xe2, 0x36, 0x0a, 0x3d, 0x29, 0xee, 0xa4, 0x36, 0xfc, 0xfb, 0x7e,
+            0x44, 0xc7, 0x35, 0xd1, 0x17, 0xc4, 0x2d, 0x1c, 0x18, 0x35, 0x42, 0x0b, 0x6b, 0x99,
+            0x42, 0xdd, 0x4f, 0x1b,
+        ];
+        assert_eq!(hash_item(b"apple"), apple_sha512_trunc);
+        assert_eq!(
+            hash_item_with(HashAlgorithm::Sha512Trunc256, b"apple"),
+            apple_sha512_trunc
+        );
+        assert_eq!(
+            hash_item_with(HashAlgorithm::Sha256, b"apple"),
+            apple_sha256
+        );
+    }
+
+    #[test]
+    fn test_hash_bytes_with() {
+        let input = b"test input";
+        assert_eq!(
+            hash_bytes_with(HashAlgorithm::Sha512Trunc256, input),
+            hash_bytes(input)
+        );
+        assert_ne!(
+            hash_bytes_with(HashAlgorithm::Sha256, input),
+            hash_bytes(input)
+        );
+    }
+
+    #[test]
+    fn test_blind_points_parallel_matches_sequential() {
+        let inputs: Vec<Vec<u8>> = (0u8..10).map(|i| vec![i]).collect();
+        let sorted = hash_inputs_sorted(HashAlgorithm::default(), b"", &inputs, 1);
+        let secret = random_scalar();
+        let blinded = blind_points_parallel(&sorted, &secret, 3);
+        assert_eq!(
+            blinded.iter().map(|(hash, _)| *hash).collect::<Vec<_>>(),
+            sorted.iter().map(|(hash, _)| *hash).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            blinded.into_iter().collect::<HashMap<_, _>>(),
+            blind_points(&hash_inputs_to_points(&inputs), &secret)
+        );
+    }
+
+    #[cfg(feature = "vartime")]
+    #[test]
+    fn test_vartime_blind_batch_matches_constant_time() {
+        let points: Vec<RistrettoPoint> = (0u8..10).map(|i| hash_to_point(&[i; 32])).collect();
+        let secret = random_scalar();
+        let expected: Vec<CompressedRistretto> =
+            points.iter().map(|point| blind_point(point, &secret)).collect();
+        assert_eq!(vartime_blind_batch(&points, &secret), expected);
+        assert!(vartime_blind_batch(&[], &secret).is_empty());
+    }
+
     #[test]
     fn test_random_scalar() {
         let scalar1 = random_scalar();
@@ -203,6 +571,22 @@ mod tests {
         assert_ne!(scalar1, scalar2, "Random scalars should be different");
     }
 
+    #[test]
+    fn test_derive_scalar() {
+        let ikm = [7u8; 32];
+        let a = derive_scalar(&ikm, b"context").unwrap();
+        let b = derive_scalar(&ikm, b"context").unwrap();
+        let c = derive_scalar(&ikm, b"other context").unwrap();
+        assert_eq!(a, b, "Derivation should be deterministic");
+        assert_ne!(a, c, "Different contexts should yield different scalars");
+    }
+
+    #[test]
+    fn test_derive_scalar_short_ikm() {
+        let result = derive_scalar(&[0u8; MIN_IKM_LEN - 1], b"context");
+        assert!(matches!(result, Err(PsiError::CryptoError(_))));
+    }
+
     #[test]
     fn test_decompress_point() {
         let hash = [42u8; 32];
@@ -226,4 +610,14 @@ mod tests {
             Err(e) => assert!(matches!(e, PsiError::CryptoError(_))),
         }
     }
+
+    #[test]
+    fn test_decompress_or_basepoint() {
+        let point = hash_to_point(&[42u8; 32]);
+        assert_eq!(decompress_or_basepoint(&point.compress()), (point, true));
+        assert_eq!(
+            decompress_or_basepoint(&CompressedRistretto([0xff; 32])),
+            (RISTRETTO_BASEPOINT_POINT, false)
+        );
+    }
 }
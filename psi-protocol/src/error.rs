@@ -2,6 +2,243 @@
 
 use std::fmt;
 
+/// Protocol phase in which an error occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Phase {
+    /// Hashing and blinding the local items.
+    Prepare,
+    /// Double-blinding the remote's points (`compute`, `respond_one_round`).
+    Compute,
+    /// Matching the remote's answer (`finalize`, `finalize_one_round`).
+    Finalize,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Phase::Prepare => write!(f, "prepare"),
+            Phase::Compute => write!(f, "compute"),
+            Phase::Finalize => write!(f, "finalize"),
+        }
+    }
+}
+
+/// A configured size limit, see [`PsiConfigBuilder`](crate::PsiConfigBuilder)
+/// [`QueryThrottle`](crate::breach::QueryThrottle) and
+/// [`RateLimiter`](crate::RateLimiter).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Limit {
+    /// Maximum number of local items.
+    LocalItems,
+    /// Maximum number of points in a remote message.
+    RemotePoints,
+    /// Maximum number of credentials a client may query.
+    ClientQueries,
+    /// Maximum number of items a peer may query per time window.
+    PeerItems,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::LocalItems => write!(f, "local items"),
+            Limit::RemotePoints => write!(f, "remote points"),
+            Limit::ClientQueries => write!(f, "client queries"),
+            Limit::PeerItems => write!(f, "items queried by peer"),
+        }
+    }
+}
+
+/// Why a peer rejected one of our messages, carried by an
+/// [`ErrorReportMessage`](crate::ErrorReportMessage).
+///
+/// Mirrors the [`PsiError`] the peer got, from the peer's point of view:
+/// `phase` is the peer's phase, `index` a position in our message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorReport {
+    /// One of our points is not a valid encoding.
+    InvalidPoint {
+        /// Peer's phase that rejected the point.
+        phase: Phase,
+        /// Position of the point in our message.
+        index: usize,
+    },
+    /// Our message does not have the number of points the peer expected.
+    LengthMismatch {
+        /// Peer's phase that rejected the message.
+        phase: Phase,
+        /// Number of points expected.
+        expected: usize,
+        /// Number of points we sent.
+        actual: usize,
+    },
+    /// Our message is over one of the peer's limits.
+    LimitExceeded {
+        /// The peer's limit.
+        limit: Limit,
+        /// The peer's configured maximum.
+        max: usize,
+        /// The size we sent.
+        actual: usize,
+    },
+    /// We use other protocol parameters than the peer.
+    ParameterMismatch {
+        /// Peer's phase that rejected the message.
+        phase: Phase,
+    },
+    /// Our message's MAC did not verify under the peer's key.
+    Unauthenticated,
+    /// Our message was made against a server key the peer has retired.
+    StaleKey {
+        /// Peer's current key epoch.
+        current: u32,
+        /// Key epoch of our message.
+        actual: u32,
+    },
+    /// Our frame could not be decoded.
+    Malformed,
+}
+
+impl ErrorReport {
+    /// Report of a local error caused by the peer's message, if it was.
+    pub fn for_error(error: &PsiError) -> Option<Self> {
+        Some(match *error {
+            PsiError::InvalidPoint { phase, index } => ErrorReport::InvalidPoint { phase, index },
+            PsiError::LengthMismatch {
+                phase,
+                expected,
+                actual,
+            } => ErrorReport::LengthMismatch {
+                phase,
+                expected,
+                actual,
+            },
+            PsiError::LimitExceeded { limit, max, actual } => {
+                ErrorReport::LimitExceeded { limit, max, actual }
+            }
+            PsiError::ParameterMismatch { phase } => ErrorReport::ParameterMismatch { phase },
+            PsiError::CryptoError(_) => ErrorReport::Unauthenticated,
+            PsiError::StaleKey { current, actual } => ErrorReport::StaleKey { current, actual },
+            PsiError::InvalidEncoding(_)
+            | PsiError::VersionMismatch { .. }
+            | PsiError::ChecksumMismatch { .. } => ErrorReport::Malformed,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for ErrorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorReport::InvalidPoint { phase, index } => {
+                write!(f, "invalid point at index {} during {}", index, phase)
+            }
+            ErrorReport::LengthMismatch {
+                phase,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "length mismatch during {}: expected {} points, found {}",
+                phase, expected, actual
+            ),
+            ErrorReport::LimitExceeded { limit, max, actual } => {
+                write!(f, "{} {} over the limit of {}", actual, limit, max)
+            }
+            ErrorReport::ParameterMismatch { phase } => {
+                write!(f, "protocol parameters differ during {}", phase)
+            }
+            ErrorReport::Unauthenticated => write!(f, "message authentication failed"),
+            ErrorReport::StaleKey { current, actual } => write!(
+                f,
+                "key epoch {} is retired, current epoch is {}",
+                actual, current
+            ),
+            ErrorReport::Malformed => write!(f, "malformed frame"),
+        }
+    }
+}
+
+/// Why a session was aborted, carried by an
+/// [`AbortMessage`](crate::AbortMessage).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AbortReason {
+    /// No reason given.
+    Unspecified,
+    /// The session breaks the sender's policy, e.g. a set size limit.
+    PolicyViolation,
+    /// The sender is too busy to run the session; retry later.
+    Overloaded,
+    /// The sender could not use our message: invalid points, other
+    /// parameters, a bad MAC.
+    InvalidMessage,
+    /// The sender's application cancelled the session.
+    Cancelled,
+    /// A reason code this version does not know.
+    Other(u8),
+}
+
+impl AbortReason {
+    /// Code of the reason on the wire.
+    pub fn code(&self) -> u8 {
+        match *self {
+            AbortReason::Unspecified => 0,
+            AbortReason::PolicyViolation => 1,
+            AbortReason::Overloaded => 2,
+            AbortReason::InvalidMessage => 3,
+            AbortReason::Cancelled => 4,
+            AbortReason::Other(code) => code,
+        }
+    }
+
+    /// Reason of a wire code; unknown codes map to `Other`.
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0 => AbortReason::Unspecified,
+            1 => AbortReason::PolicyViolation,
+            2 => AbortReason::Overloaded,
+            3 => AbortReason::InvalidMessage,
+            4 => AbortReason::Cancelled,
+            other => AbortReason::Other(other),
+        }
+    }
+
+    /// Reason to send the peer after a local error rejected its message.
+    pub fn for_error(error: &PsiError) -> Self {
+        match error {
+            PsiError::LimitExceeded { .. } => AbortReason::PolicyViolation,
+            PsiError::InvalidBlindedPoints(_)
+            | PsiError::CryptoError(_)
+            | PsiError::InvalidEncoding(_)
+            | PsiError::InvalidPoint { .. }
+            | PsiError::LengthMismatch { .. }
+            | PsiError::VersionMismatch { .. }
+            | PsiError::ChecksumMismatch { .. }
+            | PsiError::ParameterMismatch { .. } => AbortReason::InvalidMessage,
+            PsiError::Aborted(reason) => *reason,
+            PsiError::DeadlineExceeded { .. } => AbortReason::Cancelled,
+            _ => AbortReason::Unspecified,
+        }
+    }
+}
+
+impl fmt::Display for AbortReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AbortReason::Unspecified => write!(f, "unspecified"),
+            AbortReason::PolicyViolation => write!(f, "policy violation"),
+            AbortReason::Overloaded => write!(f, "overloaded"),
+            AbortReason::InvalidMessage => write!(f, "invalid message"),
+            AbortReason::Cancelled => write!(f, "cancelled"),
+            AbortReason::Other(code) => write!(f, "reason code {}", code),
+        }
+    }
+}
+
 /// Errors that can occur during PSI protocol execution.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PsiError {
@@ -13,6 +250,113 @@ pub enum PsiError {
 
     /// A cryptographic operation failed.
     CryptoError(String),
+
+    /// A message could not be decoded from its wire representation.
+    InvalidEncoding(String),
+
+    /// A configured size limit was exceeded.
+    LimitExceeded {
+        /// The limit that was exceeded.
+        limit: Limit,
+        /// The configured maximum.
+        max: usize,
+        /// The size that was rejected.
+        actual: usize,
+    },
+
+    /// The protocol configuration is invalid.
+    InvalidConfig(String),
+
+    /// A background task running part of the protocol did not complete.
+    TaskFailed(String),
+
+    /// A remote point is not a valid Ristretto encoding.
+    InvalidPoint {
+        /// Phase that rejected the point.
+        phase: Phase,
+        /// Position of the point in the remote message.
+        index: usize,
+    },
+
+    /// A remote message does not have the expected number of points.
+    LengthMismatch {
+        /// Phase that rejected the message.
+        phase: Phase,
+        /// Number of points expected.
+        expected: usize,
+        /// Number of points received.
+        actual: usize,
+    },
+
+    /// An operation was called in a session state that does not support it.
+    UnexpectedState {
+        /// The operation that was attempted.
+        operation: &'static str,
+        /// The state the session was in.
+        state: &'static str,
+    },
+
+    /// A wire frame was encoded with an unsupported format version.
+    VersionMismatch {
+        /// Version supported by this library.
+        expected: u8,
+        /// Version found in the frame.
+        actual: u8,
+    },
+
+    /// A wire frame does not match its checksum, e.g. because the transport
+    /// corrupted it.
+    ChecksumMismatch {
+        /// Checksum of the bytes received.
+        expected: u32,
+        /// Checksum carried by the frame.
+        actual: u32,
+    },
+
+    /// A remote message was produced with different protocol parameters,
+    /// see [`PsiConfig::parameter_digest`](crate::PsiConfig::parameter_digest).
+    ParameterMismatch {
+        /// Phase that rejected the message.
+        phase: Phase,
+    },
+
+    /// An artifact was made under a server key that has since been retired,
+    /// e.g. a downloaded blinded set; re-fetch it and retry.
+    StaleKey {
+        /// Current key epoch.
+        current: u32,
+        /// Key epoch of the artifact.
+        actual: u32,
+    },
+
+    /// A remote message arrived again after it was handled, e.g. from an
+    /// at-least-once transport; nothing was processed and the session is
+    /// unchanged, so it is safe to ignore.
+    DuplicateMessage {
+        /// Phase that handled the message the first time.
+        phase: Phase,
+    },
+
+    /// A phase ran past its deadline, e.g. waiting on a stuck peer.
+    DeadlineExceeded {
+        /// Phase that ran out of time.
+        phase: Phase,
+    },
+
+    /// The session was aborted, by the peer or locally.
+    Aborted(AbortReason),
+
+    /// The peer rejected one of our messages and said why.
+    Rejected(ErrorReport),
+
+    /// A result sink could not take a match.
+    SinkFailed(String),
+
+    /// An item source could not be read.
+    SourceFailed(String),
+
+    /// A session store could not be read or written.
+    StoreFailed(String),
 }
 
 impl fmt::Display for PsiError {
@@ -23,12 +367,132 @@ impl fmt::Display for PsiError {
                 write!(f, "Invalid blinded points: {}", msg)
             }
             PsiError::CryptoError(msg) => write!(f, "Cryptographic error: {}", msg),
+            PsiError::InvalidEncoding(msg) => write!(f, "Invalid encoding: {}", msg),
+            PsiError::LimitExceeded { limit, max, actual } => {
+                write!(f, "Limit exceeded: {} {}, limit is {}", actual, limit, max)
+            }
+            PsiError::InvalidConfig(msg) => write!(f, "Invalid configuration: {}", msg),
+            PsiError::TaskFailed(msg) => write!(f, "Background task failed: {}", msg),
+            PsiError::InvalidPoint { phase, index } => {
+                write!(f, "Invalid point at index {} during {}", index, phase)
+            }
+            PsiError::LengthMismatch {
+                phase,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Length mismatch during {}: expected {} points, found {}",
+                phase, expected, actual
+            ),
+            PsiError::UnexpectedState { operation, state } => {
+                write!(f, "Cannot call {} in the {} state", operation, state)
+            }
+            PsiError::VersionMismatch { expected, actual } => write!(
+                f,
+                "Unsupported wire version {}, expected {}",
+                actual, expected
+            ),
+            PsiError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Corrupted frame: checksum {:08x}, expected {:08x}",
+                actual, expected
+            ),
+            PsiError::ParameterMismatch { phase } => write!(
+                f,
+                "Protocol parameters differ from the remote's during {}",
+                phase
+            ),
+            PsiError::StaleKey { current, actual } => write!(
+                f,
+                "Stale artifact from key epoch {}, current epoch is {}; re-fetch it",
+                actual, current
+            ),
+            PsiError::DuplicateMessage { phase } => {
+                write!(f, "Duplicate message, already handled during {}", phase)
+            }
+            PsiError::DeadlineExceeded { phase } => write!(f, "Deadline exceeded during {}", phase),
+            PsiError::Aborted(reason) => write!(f, "Session aborted: {}", reason),
+            PsiError::Rejected(report) => write!(f, "Peer rejected our message: {}", report),
+            PsiError::SinkFailed(msg) => write!(f, "Result sink failed: {}", msg),
+            PsiError::SourceFailed(msg) => write!(f, "Item source failed: {}", msg),
+            PsiError::StoreFailed(msg) => write!(f, "Session store failed: {}", msg),
         }
     }
 }
 
+impl PsiError {
+    /// Returns true if the error only reports a retransmitted message that
+    /// was already handled, see [`PsiError::DuplicateMessage`].
+    pub fn is_duplicate(&self) -> bool {
+        matches!(self, PsiError::DuplicateMessage { .. })
+    }
+
+    /// Returns true if the error means an artifact made under a retired
+    /// server key must be re-fetched, whether detected locally or reported
+    /// by the peer, rather than a genuine failure.
+    pub fn is_stale_key(&self) -> bool {
+        matches!(
+            self,
+            PsiError::StaleKey { .. } | PsiError::Rejected(ErrorReport::StaleKey { .. })
+        )
+    }
+}
+
 impl std::error::Error for PsiError {}
 
+/// Error from a fallible state transition that hands the protocol back.
+///
+/// Returned by [`PsiProtocol::try_compute`](crate::PsiProtocol::try_compute)
+/// and [`PsiProtocol::try_finalize`](crate::PsiProtocol::try_finalize) so a
+/// bad remote message does not destroy the local state.
+#[derive(Debug, Clone)]
+pub struct RecoverableError<P> {
+    /// The protocol, unchanged, ready to retry the transition (boxed to
+    /// keep the `Err` variant small).
+    protocol: Box<P>,
+    /// The reason the transition failed.
+    pub error: PsiError,
+}
+
+impl<P> RecoverableError<P> {
+    /// Create a new recoverable error.
+    pub(crate) fn new(protocol: P, error: PsiError) -> Self {
+        Self {
+            protocol: Box::new(protocol),
+            error,
+        }
+    }
+
+    /// Get the unconsumed protocol.
+    pub fn protocol(&self) -> &P {
+        &self.protocol
+    }
+
+    /// Split into the unconsumed protocol and the error.
+    pub fn into_parts(self) -> (P, PsiError) {
+        (*self.protocol, self.error)
+    }
+}
+
+impl<P> fmt::Display for RecoverableError<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl<P: fmt::Debug> std::error::Error for RecoverableError<P> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl<P> From<RecoverableError<P>> for PsiError {
+    fn from(err: RecoverableError<P>) -> Self {
+        err.error
+    }
+}
+
 /// Result type for PSI operations.
 pub type Result<T> = std::result::Result<T, PsiError>;
 
@@ -50,6 +514,166 @@ mod tests {
             format!("{}", PsiError::CryptoError("test".to_string())),
             "Cryptographic error: test"
         );
+        assert_eq!(
+            format!("{}", PsiError::InvalidEncoding("test".to_string())),
+            "Invalid encoding: test"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                PsiError::LimitExceeded {
+                    limit: Limit::RemotePoints,
+                    max: 2,
+                    actual: 5
+                }
+            ),
+            "Limit exceeded: 5 remote points, limit is 2"
+        );
+        assert_eq!(
+            format!("{}", PsiError::InvalidConfig("test".to_string())),
+            "Invalid configuration: test"
+        );
+        assert_eq!(
+            format!("{}", PsiError::SinkFailed("test".to_string())),
+            "Result sink failed: test"
+        );
+        assert_eq!(
+            format!("{}", PsiError::SourceFailed("test".to_string())),
+            "Item source failed: test"
+        );
+        assert_eq!(
+            format!("{}", PsiError::StoreFailed("test".to_string())),
+            "Session store failed: test"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                PsiError::DuplicateMessage {
+                    phase: Phase::Finalize
+                }
+            ),
+            "Duplicate message, already handled during finalize"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                PsiError::DeadlineExceeded {
+                    phase: Phase::Compute
+                }
+            ),
+            "Deadline exceeded during compute"
+        );
+        assert_eq!(
+            format!("{}", PsiError::TaskFailed("test".to_string())),
+            "Background task failed: test"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                PsiError::InvalidPoint {
+                    phase: Phase::Compute,
+                    index: 3
+                }
+            ),
+            "Invalid point at index 3 during compute"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                PsiError::LengthMismatch {
+                    phase: Phase::Finalize,
+                    expected: 4,
+                    actual: 2
+                }
+            ),
+            "Length mismatch during finalize: expected 4 points, found 2"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                PsiError::VersionMismatch {
+                    expected: 1,
+                    actual: 9
+                }
+            ),
+            "Unsupported wire version 9, expected 1"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                PsiError::UnexpectedState {
+                    operation: "message",
+                    state: "final"
+                }
+            ),
+            "Cannot call message in the final state"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                PsiError::ChecksumMismatch {
+                    expected: 0xe3069283,
+                    actual: 1
+                }
+            ),
+            "Corrupted frame: checksum 00000001, expected e3069283"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                PsiError::ParameterMismatch {
+                    phase: Phase::Compute
+                }
+            ),
+            "Protocol parameters differ from the remote's during compute"
+        );
+    }
+
+    #[test]
+    fn test_abort_reason_codes() {
+        for code in 0..=u8::MAX {
+            assert_eq!(AbortReason::from_code(code).code(), code);
+        }
+        assert_eq!(AbortReason::from_code(2), AbortReason::Overloaded);
+        assert_eq!(
+            format!("{}", PsiError::Aborted(AbortReason::Other(9))),
+            "Session aborted: reason code 9"
+        );
+        assert_eq!(
+            AbortReason::for_error(&PsiError::ParameterMismatch {
+                phase: Phase::Compute
+            }),
+            AbortReason::InvalidMessage
+        );
+    }
+
+    #[test]
+    fn test_error_report_mirrors_error() {
+        let error = PsiError::LimitExceeded {
+            limit: Limit::RemotePoints,
+            max: 2,
+            actual: 5,
+        };
+        let report = ErrorReport::for_error(&error).unwrap();
+        assert_eq!(
+            format!("{}", PsiError::Rejected(report)),
+            "Peer rejected our message: 5 remote points over the limit of 2"
+        );
+        assert_eq!(
+            ErrorReport::for_error(&PsiError::CryptoError("bad tag".to_string())),
+            Some(ErrorReport::Unauthenticated)
+        );
+        assert_eq!(ErrorReport::for_error(&PsiError::EmptyInput), None);
+    }
+
+    #[test]
+    fn test_recoverable_error() {
+        let err = RecoverableError::new(42u32, PsiError::EmptyInput);
+        assert_eq!(format!("{}", err), "Input data cannot be empty");
+        assert_eq!(*err.protocol(), 42);
+        let psi_err: PsiError = err.clone().into();
+        assert_eq!(psi_err, PsiError::EmptyInput);
+        assert_eq!(err.into_parts(), (42, PsiError::EmptyInput));
     }
 
     #[test]
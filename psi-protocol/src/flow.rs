@@ -0,0 +1,142 @@
+//! Flow control for sending large messages in chunks.
+//!
+//! Sending a multi-million-point message in one frame forces the sender to
+//! hold it in full and lets a slow link buffer it again in the transport.
+//! [`FlowControl`] splits a message into fixed-size chunks and only hands
+//! out the next chunk while fewer than `max_in_flight` chunks are waiting
+//! for an acknowledgement from the remote.
+//!
+//! It does no I/O: the caller sends each chunk returned by
+//! [`FlowControl::next_chunk`] and reports acknowledgements with
+//! [`FlowControl::ack`]. With the `tokio` feature,
+//! `PsiProtocol::send_chunked` and `PsiProtocol::compute_chunked` drive it
+//! over a `ChunkTransport`.
+//!
+//! The receiver rebuilds the message by concatenating chunks in index
+//! order.
+
+use crate::error::{PsiError, Result};
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+/// Sliding window over the chunks of one outgoing message.
+#[derive(Debug, Clone)]
+pub struct FlowControl {
+    total: usize,
+    chunk_size: usize,
+    max_in_flight: usize,
+    next: usize,
+    in_flight: BTreeSet<usize>,
+}
+
+impl FlowControl {
+    /// Split `total` points into chunks of `chunk_size`, with at most
+    /// `max_in_flight` unacknowledged chunks at a time.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `chunk_size` or `max_in_flight`
+    /// is zero
+    pub fn new(total: usize, chunk_size: usize, max_in_flight: usize) -> Result<Self> {
+        if chunk_size == 0 || max_in_flight == 0 {
+            return Err(PsiError::InvalidConfig(
+                "Chunk size and in-flight limit must be positive".to_string(),
+            ));
+        }
+        Ok(Self {
+            total,
+            chunk_size,
+            max_in_flight,
+            next: 0,
+            in_flight: BTreeSet::new(),
+        })
+    }
+
+    /// Number of chunks the message is split into.
+    pub fn chunk_count(&self) -> usize {
+        self.total.div_ceil(self.chunk_size)
+    }
+
+    /// Number of chunks sent but not yet acknowledged.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Returns true once every chunk has been sent and acknowledged.
+    pub fn is_done(&self) -> bool {
+        self.next == self.chunk_count() && self.in_flight.is_empty()
+    }
+
+    /// Index and point range of the next chunk to send.
+    ///
+    /// Returns `None` when the window is full or every chunk has been
+    /// handed out; wait for an acknowledgement before calling again.
+    pub fn next_chunk(&mut self) -> Option<(usize, Range<usize>)> {
+        if self.next == self.chunk_count() || self.in_flight.len() >= self.max_in_flight {
+            return None;
+        }
+        let index = self.next;
+        self.next += 1;
+        self.in_flight.insert(index);
+        let start = index * self.chunk_size;
+        Some((index, start..(start + self.chunk_size).min(self.total)))
+    }
+
+    /// Record the remote's acknowledgement of chunk `index`.
+    ///
+    /// Acknowledgements may arrive in any order.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` if chunk `index` is not in
+    /// flight, which means the remote acknowledged it twice or before it
+    /// was sent
+    pub fn ack(&mut self, index: usize) -> Result<()> {
+        if !self.in_flight.remove(&index) {
+            return Err(PsiError::InvalidEncoding(format!(
+                "Unexpected acknowledgement for chunk {}",
+                index
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_limits_in_flight_chunks() {
+        let mut flow = FlowControl::new(10, 4, 2).unwrap();
+        assert_eq!(flow.chunk_count(), 3);
+
+        assert_eq!(flow.next_chunk(), Some((0, 0..4)));
+        assert_eq!(flow.next_chunk(), Some((1, 4..8)));
+        assert_eq!(flow.next_chunk(), None);
+        assert_eq!(flow.in_flight(), 2);
+
+        flow.ack(1).unwrap();
+        assert_eq!(flow.next_chunk(), Some((2, 8..10)));
+        assert_eq!(flow.next_chunk(), None);
+        assert!(!flow.is_done());
+
+        flow.ack(0).unwrap();
+        flow.ack(2).unwrap();
+        assert!(flow.is_done());
+    }
+
+    #[test]
+    fn test_rejects_unexpected_ack() {
+        let mut flow = FlowControl::new(4, 4, 1).unwrap();
+        assert!(matches!(flow.ack(0), Err(PsiError::InvalidEncoding(_))));
+        flow.next_chunk();
+        flow.ack(0).unwrap();
+        assert!(matches!(flow.ack(0), Err(PsiError::InvalidEncoding(_))));
+    }
+
+    #[test]
+    fn test_rejects_zero_parameters() {
+        assert!(FlowControl::new(4, 0, 1).is_err());
+        assert!(FlowControl::new(4, 1, 0).is_err());
+        assert!(FlowControl::new(0, 1, 1).unwrap().is_done());
+    }
+}
@@ -0,0 +1,216 @@
+//! Item identifiers.
+//!
+//! Every item is reduced to a 32-byte hash before it enters the protocol
+//! (see [`hash_item`]). [`ItemId`] wraps that hash so it cannot be confused
+//! with other 32-byte values such as compressed points or keys.
+
+use crate::config::HashAlgorithm;
+use crate::crypto::{hash_item, hash_item_with};
+use crate::error::PsiError;
+use std::fmt;
+use std::str::FromStr;
+
+/// The 32-byte hash identifying an item in a [`PsiResult`](crate::PsiResult).
+///
+/// Displays and parses as 64 lowercase hex characters. With the `serde`
+/// feature it serializes as that hex string in human-readable formats (so
+/// it can be a JSON map key) and as raw bytes otherwise.
+///
+/// # Example
+/// ```ignore
+/// use psi_protocol::ItemId;
+///
+/// let id = ItemId::of(b"apple");
+/// let parsed: ItemId = id.to_string().parse()?;
+/// assert_eq!(parsed, id);
+/// # Ok::<(), psi_protocol::PsiError>(())
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ItemId([u8; 32]);
+
+impl ItemId {
+    /// Wrap a raw 32-byte hash.
+    pub const fn new(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// Identifier of an item under the default hash, see [`hash_item`].
+    pub fn of(item: &[u8]) -> Self {
+        Self(hash_item(item))
+    }
+
+    /// Identifier of an item under the given hash, see [`hash_item_with`].
+    pub fn of_with(algorithm: HashAlgorithm, item: &[u8]) -> Self {
+        Self(hash_item_with(algorithm, item))
+    }
+
+    /// Get the raw hash bytes.
+    pub const fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Consume the identifier and return the raw hash bytes.
+    pub const fn to_bytes(self) -> [u8; 32] {
+        self.0
+    }
+}
+
+impl From<[u8; 32]> for ItemId {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<ItemId> for [u8; 32] {
+    fn from(id: ItemId) -> Self {
+        id.0
+    }
+}
+
+impl AsRef<[u8]> for ItemId {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl PartialEq<[u8; 32]> for ItemId {
+    fn eq(&self, other: &[u8; 32]) -> bool {
+        &self.0 == other
+    }
+}
+
+impl fmt::Display for ItemId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ItemId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ItemId({})", self)
+    }
+}
+
+impl FromStr for ItemId {
+    type Err = PsiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.as_bytes();
+        if digits.len() != 64 {
+            return Err(PsiError::InvalidEncoding(format!(
+                "Item id must be 64 hex characters, found {}",
+                digits.len()
+            )));
+        }
+        let mut bytes = [0u8; 32];
+        for (byte, pair) in bytes.iter_mut().zip(digits.chunks_exact(2)) {
+            *byte = (hex_value(pair[0])? << 4) | hex_value(pair[1])?;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+/// Value of a single hex digit.
+fn hex_value(digit: u8) -> Result<u8, PsiError> {
+    match digit {
+        b'0'..=b'9' => Ok(digit - b'0'),
+        b'a'..=b'f' => Ok(digit - b'a' + 10),
+        b'A'..=b'F' => Ok(digit - b'A' + 10),
+        _ => Err(PsiError::InvalidEncoding(format!(
+            "Invalid hex digit {:?} in item id",
+            digit as char
+        ))),
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for ItemId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ItemId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            s.parse().map_err(serde::de::Error::custom)
+        } else {
+            <[u8; 32]>::deserialize(deserializer).map(Self)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_item_id_of_matches_hash_item() {
+        assert_eq!(ItemId::of(b"apple"), hash_item(b"apple"));
+        assert_eq!(
+            ItemId::of_with(HashAlgorithm::Sha256, b"apple").to_bytes(),
+            hash_item_with(HashAlgorithm::Sha256, b"apple")
+        );
+    }
+
+    #[test]
+    fn test_item_id_hex_round_trip() {
+        let id = ItemId::of(b"apple");
+        let hex = id.to_string();
+        assert_eq!(
+            hex,
+            "844d8779103b94c18f4aa4cc0c3b4474058580a991fba85d3ca698a0bc9e52c5"
+        );
+        assert_eq!(hex.parse::<ItemId>().unwrap(), id);
+        assert_eq!(hex.to_uppercase().parse::<ItemId>().unwrap(), id);
+        assert_eq!(format!("{:?}", id), format!("ItemId({})", hex));
+    }
+
+    #[test]
+    fn test_item_id_from_str_rejects_invalid() {
+        assert!(matches!(
+            "abcd".parse::<ItemId>(),
+            Err(PsiError::InvalidEncoding(_))
+        ));
+        assert!(matches!(
+            "zz".repeat(32).parse::<ItemId>(),
+            Err(PsiError::InvalidEncoding(_))
+        ));
+    }
+
+    #[test]
+    fn test_item_id_ordering_follows_bytes() {
+        let mut ids = vec![
+            ItemId::new([2; 32]),
+            ItemId::new([0; 32]),
+            ItemId::new([1; 32]),
+        ];
+        ids.sort();
+        assert_eq!(
+            ids,
+            vec![
+                ItemId::new([0; 32]),
+                ItemId::new([1; 32]),
+                ItemId::new([2; 32])
+            ]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_item_id_serde_json_is_hex() {
+        let id = ItemId::of(b"apple");
+        let json = serde_json::to_string(&id).unwrap();
+        assert_eq!(json, format!("\"{}\"", id));
+        assert_eq!(serde_json::from_str::<ItemId>(&json).unwrap(), id);
+    }
+}
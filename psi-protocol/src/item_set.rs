@@ -0,0 +1,293 @@
+//! Long-lived item sets with per-item expiry.
+//!
+//! Peers that sync continuously start a new session every few minutes over a
+//! set that changes slowly. [`PsiItemSet`] keeps the items hashed to points
+//! between sessions, so only new items pay for hash-to-curve, and lets each
+//! item carry an expiry after which it is left out of new sessions and
+//! dropped from the cache.
+
+use crate::config::{HashAlgorithm, PsiConfig};
+use crate::crypto::{hash_item_with, hash_to_point_in, random_scalar};
+use crate::error::{PsiError, Result};
+use crate::protocol::PsiProtocol;
+use crate::state::PreparedState;
+use curve25519_dalek::ristretto::RistrettoPoint;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// A cached item and the instant it expires, if ever.
+#[derive(Debug, Clone)]
+struct Entry {
+    point: RistrettoPoint,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn is_live(&self, now: Instant) -> bool {
+        self.expires_at.is_none_or(|deadline| deadline > now)
+    }
+}
+
+/// A set of items hashed once and reused across sessions.
+///
+/// Items are keyed by their hash, so inserting the same item twice keeps one
+/// entry and replaces its expiry. Expired items are invisible to
+/// [`contains`](Self::contains) and never enter a session, even before
+/// [`purge_expired`](Self::purge_expired) removes them.
+///
+/// # Example
+/// ```ignore
+/// use psi_protocol::{PsiConfig, PsiItemSet};
+/// use std::time::Duration;
+///
+/// let mut items = PsiItemSet::new();
+/// items.insert(b"10.0.0.1");
+/// items.insert_for(b"10.0.0.2", Duration::from_secs(3600));
+///
+/// // Every sync round
+/// let alice = items.prepare(PsiConfig::default())?;
+/// let msg = alice.message();
+/// # Ok::<(), psi_protocol::PsiError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct PsiItemSet {
+    algorithm: HashAlgorithm,
+    domain: Vec<u8>,
+    entries: BTreeMap<[u8; 32], Entry>,
+}
+
+impl Default for PsiItemSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PsiItemSet {
+    /// Create an empty set using the default hash algorithm.
+    pub fn new() -> Self {
+        Self::with_hash(HashAlgorithm::default())
+    }
+
+    /// Create an empty set using the given hash algorithm.
+    ///
+    /// Sessions prepared from the set must be configured with the same
+    /// algorithm.
+    pub fn with_hash(algorithm: HashAlgorithm) -> Self {
+        Self {
+            algorithm,
+            domain: Vec::new(),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Map items to points within a domain separation tag.
+    ///
+    /// Sessions prepared from the set must be configured with the same tag,
+    /// see [`PsiConfigBuilder::domain`](crate::PsiConfigBuilder::domain).
+    /// Items already in the set are mapped again.
+    pub fn with_domain(mut self, domain: impl AsRef<[u8]>) -> Self {
+        self.domain = domain.as_ref().to_vec();
+        for (hash, entry) in &mut self.entries {
+            entry.point = hash_to_point_in(&self.domain, hash);
+        }
+        self
+    }
+
+    /// Hash algorithm the items are hashed with.
+    pub fn hash(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// Domain separation tag the items are mapped to points with.
+    pub fn domain(&self) -> &[u8] {
+        &self.domain
+    }
+
+    /// Insert an item that never expires.
+    pub fn insert(&mut self, item: &[u8]) {
+        self.insert_entry(item, None);
+    }
+
+    /// Insert an item that expires at `deadline`.
+    pub fn insert_until(&mut self, item: &[u8], deadline: Instant) {
+        self.insert_entry(item, Some(deadline));
+    }
+
+    /// Insert an item that expires after `ttl`.
+    pub fn insert_for(&mut self, item: &[u8], ttl: Duration) {
+        self.insert_until(item, Instant::now() + ttl);
+    }
+
+    fn insert_entry(&mut self, item: &[u8], expires_at: Option<Instant>) {
+        let hash = hash_item_with(self.algorithm, item);
+        self.entries
+            .entry(hash)
+            .and_modify(|entry| entry.expires_at = expires_at)
+            .or_insert_with(|| Entry {
+                point: hash_to_point_in(&self.domain, &hash),
+                expires_at,
+            });
+    }
+
+    /// Remove an item. Returns true if it was stored, expired or not.
+    pub fn remove(&mut self, item: &[u8]) -> bool {
+        let hash = hash_item_with(self.algorithm, item);
+        self.entries.remove(&hash).is_some()
+    }
+
+    /// Returns true if the item is stored and not expired.
+    pub fn contains(&self, item: &[u8]) -> bool {
+        let hash = hash_item_with(self.algorithm, item);
+        let now = Instant::now();
+        self.entries
+            .get(&hash)
+            .is_some_and(|entry| entry.is_live(now))
+    }
+
+    /// Number of stored items, including expired ones not yet purged.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if no items are stored.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Drop every item whose expiry has passed.
+    ///
+    /// Returns the number of items dropped.
+    pub fn purge_expired(&mut self) -> usize {
+        self.purge_expired_at(Instant::now())
+    }
+
+    /// Drop every item expiring at or before `now`.
+    ///
+    /// Returns the number of items dropped.
+    pub fn purge_expired_at(&mut self, now: Instant) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| entry.is_live(now));
+        before - self.entries.len()
+    }
+
+    /// Purge expired items and start a session over the remaining ones.
+    ///
+    /// Uses the cached points, so no item is hashed again.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `config` uses a different hash
+    /// algorithm or domain than the set, `PsiError::EmptyInput` if no live item is
+    /// left, and `PsiError::LimitExceeded` if the set is over the configured
+    /// local limit
+    pub fn prepare(&mut self, config: PsiConfig) -> Result<PsiProtocol<PreparedState>> {
+        if config.hash() != self.algorithm {
+            return Err(PsiError::InvalidConfig(format!(
+                "Item set is hashed with {:?}, config uses {:?}",
+                self.algorithm,
+                config.hash()
+            )));
+        }
+        if config.domain() != self.domain {
+            return Err(PsiError::InvalidConfig(
+                "Item set and config use different domains".to_string(),
+            ));
+        }
+        self.purge_expired();
+        let hashed: Vec<([u8; 32], RistrettoPoint)> = self
+            .entries
+            .iter()
+            .map(|(hash, entry)| (*hash, entry.point))
+            .collect();
+        PsiProtocol::from_hashed(&hashed, random_scalar(), config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(alice: &mut PsiItemSet, bob_items: &[Vec<u8>]) -> usize {
+        let alice = alice.prepare(PsiConfig::default()).unwrap();
+        let bob = PsiProtocol::new(bob_items).unwrap();
+        let alice_msg = alice.message();
+        let (alice, _) = alice.compute(bob.message()).unwrap();
+        let (_, bob_double) = bob.compute(alice_msg).unwrap();
+        alice.finalize(bob_double).unwrap().1.len()
+    }
+
+    #[test]
+    fn test_item_set_insert_and_remove() {
+        let mut set = PsiItemSet::new();
+        set.insert(b"apple");
+        set.insert(b"apple");
+        assert_eq!(set.len(), 1);
+        assert!(set.contains(b"apple"));
+        assert!(set.remove(b"apple"));
+        assert!(!set.remove(b"apple"));
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn test_expired_items_are_hidden_and_purged() {
+        let mut set = PsiItemSet::new();
+        let now = Instant::now();
+        set.insert_until(b"stale", now);
+        set.insert_for(b"fresh", Duration::from_secs(60));
+        set.insert(b"forever");
+
+        assert!(!set.contains(b"stale"));
+        assert!(set.contains(b"fresh"));
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.purge_expired_at(now), 1);
+        assert_eq!(set.purge_expired_at(now + Duration::from_secs(120)), 1);
+        assert!(set.contains(b"forever"));
+    }
+
+    #[test]
+    fn test_reinsert_replaces_expiry() {
+        let mut set = PsiItemSet::new();
+        let now = Instant::now();
+        set.insert_until(b"apple", now);
+        set.insert(b"apple");
+        assert_eq!(set.purge_expired_at(now + Duration::from_secs(3600)), 0);
+    }
+
+    #[test]
+    fn test_prepare_excludes_expired_items() {
+        let mut set = PsiItemSet::new();
+        set.insert(b"apple");
+        set.insert_until(b"banana", Instant::now());
+        let bob_items = vec![b"apple".to_vec(), b"banana".to_vec()];
+
+        assert_eq!(run(&mut set, &bob_items), 1);
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn test_prepare_errors() {
+        let mut set = PsiItemSet::new();
+        set.insert_until(b"apple", Instant::now());
+        assert_eq!(
+            set.prepare(PsiConfig::default()).unwrap_err(),
+            PsiError::EmptyInput
+        );
+
+        set.insert(b"apple");
+        let config = PsiConfig::builder()
+            .hash(HashAlgorithm::Sha256)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            set.prepare(config),
+            Err(PsiError::InvalidConfig(_))
+        ));
+
+        let config = PsiConfig::builder().domain("my-app").build().unwrap();
+        assert!(matches!(
+            set.prepare(config.clone()),
+            Err(PsiError::InvalidConfig(_))
+        ));
+        let mut set = set.with_domain("my-app");
+        assert!(set.prepare(config).is_ok());
+    }
+}
@@ -0,0 +1,149 @@
+//! Prepared protocols built from an async stream of items.
+//!
+//! Items often come out of a database cursor or a network feed rather than
+//! a slice held in memory. [`PsiProtocol::from_stream`] hashes and blinds
+//! each item as it arrives, so by the time the stream ends only the message
+//! layout (padding and ordering) is left to do.
+//!
+//! Each item costs one hash-to-curve and one scalar multiplication on the
+//! polling task. For very large sets on a shared runtime, prefer collecting
+//! and calling `new_async` (`tokio` feature), which runs on the blocking
+//! pool.
+//!
+//! Requires the `futures` feature; works with any executor.
+
+use crate::config::PsiConfig;
+use crate::crypto::{blind_point, hash_item_with, hash_to_point_in, random_scalar};
+use crate::error::Result;
+use crate::protocol::PsiProtocol;
+use crate::state::PreparedState;
+use futures_core::Stream;
+use std::collections::BTreeMap;
+
+impl PsiProtocol<PreparedState> {
+    /// Build a prepared protocol from a stream of items.
+    ///
+    /// Duplicates are blinded once. The local item limit is checked as
+    /// items arrive, so an oversized stream is rejected without being
+    /// drained.
+    ///
+    /// # Errors
+    /// Returns `PsiError::EmptyInput` if the stream yields no item, or
+    /// `PsiError::LimitExceeded` once it yields more unique items than the
+    /// configured local limit
+    ///
+    /// # Example
+    /// ```ignore
+    /// let rows = db.query("SELECT email FROM users");
+    /// let alice = PsiProtocol::from_stream(rows, PsiConfig::default()).await?;
+    /// # Ok::<(), psi_protocol::PsiError>(())
+    /// ```
+    pub async fn from_stream<S, T>(items: S, config: PsiConfig) -> Result<Self>
+    where
+        S: Stream<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        let secret = random_scalar();
+        let mut blinded = BTreeMap::new();
+
+        let mut items = std::pin::pin!(items);
+        while let Some(item) = std::future::poll_fn(|cx| items.as_mut().poll_next(cx)).await {
+            let hash = hash_item_with(config.hash(), item.as_ref());
+            if blinded.contains_key(&hash) {
+                continue;
+            }
+            config.check_local_len(blinded.len() + 1)?;
+            blinded.insert(
+                hash,
+                blind_point(&hash_to_point_in(config.domain(), &hash), &secret),
+            );
+        }
+
+        Self::from_blinded(blinded.into_iter().collect(), secret, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{Limit, PsiError};
+    use crate::item_id::ItemId;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// Stream over a vector that is pending every other poll, like a cursor
+    /// waiting on I/O.
+    struct SlowStream {
+        items: std::vec::IntoIter<Vec<u8>>,
+        ready: bool,
+    }
+
+    impl SlowStream {
+        fn new(items: &[&[u8]]) -> Self {
+            Self {
+                items: items
+                    .iter()
+                    .map(|item| item.to_vec())
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+                ready: false,
+            }
+        }
+    }
+
+    impl Stream for SlowStream {
+        type Item = Vec<u8>;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Vec<u8>>> {
+            self.ready = !self.ready;
+            if self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Poll::Ready(self.items.next())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_runs_protocol() {
+        let stream = SlowStream::new(&[b"apple", b"banana", b"apple"]);
+        let alice = PsiProtocol::from_stream(stream, PsiConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(alice.message().len(), 2);
+
+        let bob = PsiProtocol::new(&[b"banana".to_vec(), b"cherry".to_vec()]).unwrap();
+        let alice_msg = alice.message();
+        let (alice, alice_double) = alice.compute(bob.message()).unwrap();
+        let (bob, bob_double) = bob.compute(alice_msg).unwrap();
+        let (_, alice_result) = alice.finalize(bob_double).unwrap();
+        let (_, bob_result) = bob.finalize(alice_double).unwrap();
+        assert_eq!(
+            alice_result.intersection_hashes,
+            vec![ItemId::of(b"banana")]
+        );
+        assert_eq!(alice_result, bob_result);
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_errors() {
+        let empty = SlowStream::new(&[]);
+        assert_eq!(
+            PsiProtocol::from_stream(empty, PsiConfig::default())
+                .await
+                .unwrap_err(),
+            PsiError::EmptyInput
+        );
+
+        let config = PsiConfig::builder().max_local_items(1).build().unwrap();
+        let stream = SlowStream::new(&[b"apple", b"apple", b"banana"]);
+        assert_eq!(
+            PsiProtocol::from_stream(stream, config).await.unwrap_err(),
+            PsiError::LimitExceeded {
+                limit: Limit::LocalItems,
+                max: 1,
+                actual: 2
+            }
+        );
+    }
+}
@@ -0,0 +1,420 @@
+//! Batch exchanges through Kafka topics.
+//!
+//! Organizations matching records offline have no connection to each other,
+//! but can share a Kafka cluster: each one produces to its own topic and
+//! consumes the other's. [`KafkaExchange`] runs [`SessionMux`] sessions
+//! over those topics. Both sides [`open`](KafkaExchange::open) the session
+//! under an agreed id (e.g. derived from the batch date), produce the
+//! records it returns, and hand every consumed record to
+//! [`on_record`](KafkaExchange::on_record), which may return more records to
+//! produce and finally the intersection. Neither side needs to be online
+//! at the same time as the other.
+//!
+//! Each mux frame is split into records no larger than the configured
+//! record size, all keyed by the session id so they land on one partition
+//! and stay in order. Each record value is:
+//!
+//! ```text
+//! +-------------+----------------+-------------------+-------------------+-------+
+//! | version: u8 | frame id: 8 B  | index (u32, BE)   | count (u32, BE)   | bytes |
+//! +-------------+----------------+-------------------+-------------------+-------+
+//! ```
+//!
+//! The frame id is a truncated SHA-256 of the whole frame: chunks of one
+//! frame share it, and the reassembled frame must hash to it, so chunks of
+//! different frames are never mixed up. Consumers may see records twice
+//! (Kafka delivers at least once): repeated chunks, including those of a
+//! frame already handled, are ignored.
+//!
+//! The adapter does no I/O and depends on no Kafka client.
+//!
+//! Requires the `kafka` feature.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::kafka::KafkaExchange;
+//! use psi_protocol::{MuxEvent, PsiSession};
+//!
+//! let mut exchange = KafkaExchange::new(Duration::from_secs(24 * 3600));
+//! produce("org-a.psi", exchange.open(batch_id, PsiSession::new(&customers)?)?)?;
+//!
+//! for record in consume("org-b.psi") {
+//!     let step = exchange.on_record(&record)?;
+//!     produce("org-a.psi", step.records)?;
+//!     for event in step.events {
+//!         if let MuxEvent::Complete { session, result } = event {
+//!             store(session, result);
+//!         }
+//!     }
+//! }
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::error::{AbortReason, PsiError, Result};
+use crate::mux::{MuxEvent, SessionMux};
+use crate::session::PsiSession;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// Version of the record value layout.
+pub const RECORD_VERSION: u8 = 1;
+
+/// Size of the header of each record value in bytes.
+pub const RECORD_HEADER_LEN: usize = 1 + FRAME_ID_LEN + 4 + 4;
+
+/// Default largest record value, under Kafka's default 1 MiB message limit
+/// with room for the record overhead.
+pub const DEFAULT_MAX_RECORD_LEN: usize = 1_000_000;
+
+/// Default largest reassembled frame (256 MiB).
+pub const DEFAULT_MAX_FRAME_LEN: usize = 256 << 20;
+
+const FRAME_ID_LEN: usize = 8;
+
+type FrameId = [u8; FRAME_ID_LEN];
+
+/// A Kafka record as the adapter reads and writes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KafkaRecord {
+    /// The session id, big-endian.
+    pub key: Vec<u8>,
+    /// Header and chunk, see the [module documentation](self).
+    pub value: Vec<u8>,
+}
+
+/// Outcome of one consumed record.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct KafkaStep {
+    /// Records to produce to our topic, in order.
+    pub records: Vec<KafkaRecord>,
+    /// What the mux reported for a completed frame; never
+    /// [`MuxEvent::Reply`], which becomes `records`.
+    pub events: Vec<MuxEvent>,
+}
+
+/// Chunks of one frame received so far.
+#[derive(Debug)]
+struct Partial {
+    count: u32,
+    chunks: BTreeMap<u32, Vec<u8>>,
+    bytes: usize,
+    started: Instant,
+}
+
+/// Sessions exchanged as chunked Kafka records.
+#[derive(Debug)]
+pub struct KafkaExchange {
+    mux: SessionMux,
+    partial: HashMap<FrameId, Partial>,
+    /// Frames already handled, kept to ignore their redelivered chunks
+    handled: HashMap<FrameId, Instant>,
+    ttl: Duration,
+    max_record_len: usize,
+    max_frame_len: usize,
+}
+
+impl KafkaExchange {
+    /// Create an exchange giving each session `ttl` to complete.
+    ///
+    /// Batch exchanges may wait hours for the peer; size `ttl` for it.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            mux: SessionMux::new(ttl),
+            partial: HashMap::new(),
+            handled: HashMap::new(),
+            ttl,
+            max_record_len: DEFAULT_MAX_RECORD_LEN,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+
+    /// Split frames into record values of at most `len` bytes, header
+    /// included (at least one byte more than [`RECORD_HEADER_LEN`]).
+    pub fn max_record_len(mut self, len: usize) -> Self {
+        self.max_record_len = len.max(RECORD_HEADER_LEN + 1);
+        self
+    }
+
+    /// Refuse frames larger than `len` bytes, e.g.
+    /// [`wire::max_frame_len`](crate::wire::max_frame_len) of the set size
+    /// limit plus the session id.
+    pub fn max_frame_len(mut self, len: usize) -> Self {
+        self.max_frame_len = len;
+        self
+    }
+
+    /// Register a prepared session and return the records of its first
+    /// frame.
+    ///
+    /// # Errors
+    /// Same as [`SessionMux::open`]
+    pub fn open(&mut self, id: u32, session: PsiSession) -> Result<Vec<KafkaRecord>> {
+        let frame = self.mux.open(id, session)?;
+        Ok(self.records(id, &frame))
+    }
+
+    /// Handle a record consumed from the peer's topic.
+    ///
+    /// Chunks are buffered until their frame is complete; then the frame
+    /// goes through the mux and its replies come back as records.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` for a malformed record value, a
+    /// frame over the size limit or a reassembled frame that does not hash
+    /// to its id, plus the errors of [`SessionMux::on_frame`]
+    pub fn on_record(&mut self, record: &KafkaRecord) -> Result<KafkaStep> {
+        let (id, index, count, chunk) = parse_record(&record.value)?;
+        if self.handled.contains_key(&id) {
+            return Ok(KafkaStep::default());
+        }
+        let partial = self.partial.entry(id).or_insert_with(|| Partial {
+            count,
+            chunks: BTreeMap::new(),
+            bytes: 0,
+            started: Instant::now(),
+        });
+        if partial.count != count {
+            return Err(PsiError::InvalidEncoding(format!(
+                "Record announces {} chunks, earlier ones announced {}",
+                count, partial.count
+            )));
+        }
+        if !partial.chunks.contains_key(&index) {
+            if partial.bytes + chunk.len() > self.max_frame_len {
+                self.partial.remove(&id);
+                return Err(PsiError::InvalidEncoding(format!(
+                    "Frame exceeds the limit of {} bytes",
+                    self.max_frame_len
+                )));
+            }
+            partial.bytes += chunk.len();
+            partial.chunks.insert(index, chunk.to_vec());
+        }
+        if partial.chunks.len() < count as usize {
+            return Ok(KafkaStep::default());
+        }
+
+        let partial = self.partial.remove(&id).expect("looked up above");
+        let frame: Vec<u8> = partial.chunks.into_values().flatten().collect();
+        if frame_id(&frame) != id {
+            return Err(PsiError::InvalidEncoding(
+                "Reassembled frame does not match its id".to_string(),
+            ));
+        }
+        let events = self.mux.on_frame(&frame)?;
+        // A frame that failed may be resent, so only successes are kept
+        self.handled.insert(id, Instant::now());
+        let mut step = KafkaStep::default();
+        for event in events {
+            match event {
+                MuxEvent::Reply(reply) => {
+                    let (session, _) = crate::mux::decode_frame(&reply)?;
+                    step.records.extend(self.records(session, &reply));
+                }
+                other => step.events.push(other),
+            }
+        }
+        Ok(step)
+    }
+
+    /// Drop a live session and return the records telling the peer.
+    pub fn abort(&mut self, id: u32, reason: AbortReason) -> Vec<KafkaRecord> {
+        self.mux
+            .abort(id, reason)
+            .map(|frame| self.records(id, &frame))
+            .unwrap_or_default()
+    }
+
+    /// Records telling the peer why its frame for session `id` was
+    /// rejected; empty when `error` has no wire code.
+    pub fn report(&self, id: u32, error: &PsiError) -> Vec<KafkaRecord> {
+        SessionMux::report(id, error)
+            .map(|frame| self.records(id, &frame))
+            .unwrap_or_default()
+    }
+
+    /// Number of open sessions, including expired ones not yet swept.
+    pub fn len(&self) -> usize {
+        self.mux.len()
+    }
+
+    /// Returns true if no session is open.
+    pub fn is_empty(&self) -> bool {
+        self.mux.is_empty()
+    }
+
+    /// Drop expired sessions, frames left incomplete and handled frame ids
+    /// older than the time to live; returns how many open sessions were
+    /// dropped.
+    pub fn sweep(&mut self) -> usize {
+        let ttl = self.ttl;
+        self.partial
+            .retain(|_, partial| partial.started.elapsed() < ttl);
+        self.handled.retain(|_, handled| handled.elapsed() < ttl);
+        self.mux.sweep()
+    }
+
+    /// Split `frame` of session `session` into records.
+    fn records(&self, session: u32, frame: &[u8]) -> Vec<KafkaRecord> {
+        let id = frame_id(frame);
+        let chunk_len = self.max_record_len - RECORD_HEADER_LEN;
+        let count = u32::try_from(frame.len().div_ceil(chunk_len).max(1))
+            .expect("frames hold far fewer than u32::MAX chunks");
+        let chunks: Vec<&[u8]> = if frame.is_empty() {
+            vec![&[]]
+        } else {
+            frame.chunks(chunk_len).collect()
+        };
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut value = Vec::with_capacity(RECORD_HEADER_LEN + chunk.len());
+                value.push(RECORD_VERSION);
+                value.extend_from_slice(&id);
+                value.extend_from_slice(&(index as u32).to_be_bytes());
+                value.extend_from_slice(&count.to_be_bytes());
+                value.extend_from_slice(chunk);
+                KafkaRecord {
+                    key: session.to_be_bytes().to_vec(),
+                    value,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Truncated SHA-256 of a frame.
+fn frame_id(frame: &[u8]) -> FrameId {
+    let digest = Sha256::digest(frame);
+    let mut id = [0u8; FRAME_ID_LEN];
+    id.copy_from_slice(&digest[..FRAME_ID_LEN]);
+    id
+}
+
+/// Split a record value into frame id, chunk index, chunk count and chunk.
+fn parse_record(value: &[u8]) -> Result<(FrameId, u32, u32, &[u8])> {
+    if value.len() < RECORD_HEADER_LEN {
+        return Err(PsiError::InvalidEncoding(format!(
+            "Record of {} bytes is shorter than its header",
+            value.len()
+        )));
+    }
+    if value[0] != RECORD_VERSION {
+        return Err(PsiError::InvalidEncoding(format!(
+            "Unknown record version {}",
+            value[0]
+        )));
+    }
+    let (header, chunk) = value.split_at(RECORD_HEADER_LEN);
+    let mut id = [0u8; FRAME_ID_LEN];
+    id.copy_from_slice(&header[1..1 + FRAME_ID_LEN]);
+    let field = |at: usize| u32::from_be_bytes(header[at..at + 4].try_into().expect("4 bytes"));
+    let (index, count) = (field(1 + FRAME_ID_LEN), field(5 + FRAME_ID_LEN));
+    if index >= count {
+        return Err(PsiError::InvalidEncoding(format!(
+            "Chunk {} of a frame of {} chunks",
+            index, count
+        )));
+    }
+    Ok((id, index, count, chunk))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::session;
+
+    fn exchange() -> KafkaExchange {
+        KafkaExchange::new(Duration::from_secs(60)).max_record_len(64)
+    }
+
+    /// Consume every record of `records`, twice each, returning what to
+    /// produce and the completed intersections.
+    fn consume(exchange: &mut KafkaExchange, records: &[KafkaRecord]) -> KafkaStep {
+        let mut out = KafkaStep::default();
+        for record in records.iter().flat_map(|record| [record, record]) {
+            let step = exchange.on_record(record).unwrap();
+            out.records.extend(step.records);
+            out.events.extend(step.events);
+        }
+        out
+    }
+
+    #[test]
+    fn test_batch_exchange_with_redelivery() {
+        let (mut alice, mut bob) = (exchange(), exchange());
+        let alice_records = alice.open(7, session(&["a", "b", "c"])).unwrap();
+        let bob_records = bob.open(7, session(&["b", "c", "d"])).unwrap();
+        // 3 points do not fit in one 64-byte record
+        assert!(alice_records.len() > 1);
+        assert!(alice_records.iter().all(|record| record.value.len() <= 64));
+        assert!(alice_records
+            .iter()
+            .all(|record| record.key == 7u32.to_be_bytes()));
+
+        let bob_step = consume(&mut bob, &alice_records);
+        let alice_step = consume(&mut alice, &bob_records);
+        assert!(bob_step.events.is_empty());
+
+        let alice_done = consume(&mut alice, &bob_step.records);
+        let bob_done = consume(&mut bob, &alice_step.records);
+        for done in [alice_done, bob_done] {
+            assert!(done.records.is_empty());
+            match done.events.as_slice() {
+                [MuxEvent::Complete { session: 7, result }] => {
+                    assert_eq!(result.len(), 2)
+                }
+                other => panic!("unexpected events {:?}", other),
+            }
+        }
+        assert!(alice.is_empty() && bob.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_tampered_and_oversized_frames() {
+        let mut alice = exchange();
+        let mut bob = exchange().max_frame_len(64);
+        let records = alice.open(1, session(&["a", "b"])).unwrap();
+        bob.open(1, session(&["a"])).unwrap();
+        assert!(matches!(
+            records
+                .iter()
+                .map(|record| bob.on_record(record))
+                .find_map(|step| step.err()),
+            Some(PsiError::InvalidEncoding(_))
+        ));
+
+        let mut bob = exchange();
+        bob.open(1, session(&["a"])).unwrap();
+        let mut tampered = records.clone();
+        let last = tampered.last_mut().unwrap();
+        *last.value.last_mut().unwrap() ^= 1;
+        let outcomes: Vec<_> = tampered
+            .iter()
+            .map(|record| bob.on_record(record))
+            .collect();
+        assert!(outcomes.last().unwrap().is_err());
+
+        assert!(bob
+            .on_record(&KafkaRecord {
+                key: vec![],
+                value: vec![RECORD_VERSION]
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_sweep_drops_incomplete_frames() {
+        let mut alice = KafkaExchange::new(Duration::ZERO).max_record_len(64);
+        let mut bob = KafkaExchange::new(Duration::ZERO);
+        let records = alice.open(1, session(&["a", "b"])).unwrap();
+        bob.on_record(&records[0]).unwrap();
+        assert_eq!(bob.partial.len(), 1);
+        bob.handled.insert([0; FRAME_ID_LEN], Instant::now());
+        assert_eq!(alice.sweep(), 1);
+        bob.sweep();
+        assert!(bob.partial.is_empty() && bob.handled.is_empty());
+    }
+}
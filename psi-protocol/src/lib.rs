@@ -16,7 +16,7 @@
 //! - **Symmetric API**: Both parties use the same API; no distinction
 //!   between client and server.
 //! - **Input as Byte Arrays**: Accepts `Vec<u8>` as input, handling hashing
-//!   internally.
+//!   internally. Use [`hash_item`] to map result hashes back to your items.
 //! - **Type-State Pattern**: Uses Rust's type system to enforce valid protocol
 //!   transitions at compile time.
 //!
@@ -33,6 +33,13 @@
 //! 3. **Compute Phase**: Compute intersection with remote's message, returning
 //!    the results as a `PsiResult`.
 //!
+//! [`PsiSession`] drives the same steps through `&mut self` methods for
+//! callers that need to store sessions in collections or behind `dyn`.
+//!
+//! A one-round variant (`respond_one_round` / `finalize_one_round`) lets an
+//! initiator learn the intersection after a single round trip, which matters
+//! on high-latency links; only the initiator learns the result.
+//!
 //! ## Example Usage
 //!
 //! ```ignore
@@ -61,31 +68,305 @@
 //! # Ok::<(), PsiError>(())
 //! ```
 //!
+//! ## Thread Safety
+//!
+//! Every `PsiProtocol<S>` and state type is `Send + Sync + Clone`; this is
+//! asserted at compile time, so states can be moved into threads or tokio
+//! tasks. Cloning a state copies its secret scalar along with it.
+//!
 //! ## Security Considerations
 //!
 //! - The exchange of blinded points (Step 2) MUST be secured with TLS in
 //!   production to prevent man-in-the-middle attacks.
 //! - Only elements in the intersection are revealed to both parties.
 //! - Blinded points leak no information about underlying elements.
+//! - Secret scalars are redacted from `Debug` output and zeroized when their
+//!   state is dropped.
+//! - Peer input never panics: decoding, `compute`, `finalize` and their
+//!   variants return a `PsiError` for any malformed or hostile message.
 //!
 //! ## Modules
 //!
+//! - [`config`] - Protocol configuration and its builder
 //! - [`messages`] - Message types for protocol exchange
 //! - [`protocol`] - Core protocol implementation
 //! - [`state`] - Protocol state types (type-state pattern)
+//! - [`stats`] - `PsiStats`, counters and timings carried through a run
+//! - [`sink`] - `MatchSink` and `finalize_with`, streaming the intersection
+//!   out of `finalize`
+//! - [`source`] - `ItemSource`, items pulled from external stores in batches
+//! - `sqlite` - `SqliteSource`, an `ItemSource` over a SQLite table (`sqlite`
+//!   feature)
+//! - [`stream`] - `BlindingStream`, lazy blinding of the local set
+//! - `item_stream` - Prepared protocols built from an async stream of items
+//!   (`futures` feature)
+//! - [`flow`] - `FlowControl`, a sliding window for sending messages in
+//!   chunks
+//! - [`transcript`] - Session transcripts bound to a context, with key
+//!   confirmation
+//! - [`ratchet`] - `SessionRatchet`, per-session key evolution for
+//!   recurring peers
+//! - [`psk`] - `PreSharedKey`, message MACs for deployments without a PKI
+//! - [`session`] - `PsiSession`, a runtime-checked wrapper over the states
+//! - [`psi_backend`] - `PsiBackend`, the trait alternative constructions
+//!   implement; `PsiSession` is the ECDH implementation
+//! - [`manager`] - `SessionManager`, per-peer sessions with deadlines
+//! - [`store`] - `SessionStore`, session state shared between server
+//!   replicas
+//! - `redis` - `RedisStore`, a `SessionStore` backed by Redis (`redis`
+//!   feature)
+//! - [`mux`] - `SessionMux`, several sessions sharing one connection
+//! - `mqtt` - `MqttRequester`/`MqttResponder`, one-round exchanges over
+//!   MQTT 5 request/response (`mqtt` feature)
+//! - `nats` - `NatsRequester`/`NatsResponder`, two-round sessions over NATS
+//!   request-reply (`nats` feature)
+//! - `kafka` - `KafkaExchange`, batch sessions through Kafka topics with no
+//!   direct connection (`kafka` feature)
+//! - `object_store` - `ObjectExchange`, batch sessions through a shared
+//!   bucket prefix with manifests and digests (`object-store` feature)
+//! - [`sharding`] - Hash-prefix sharding of one large intersection into
+//!   independent runs
+//! - [`coordinator`] - `ShardCoordinator`, shard assignment, retries and
+//!   result assembly for sharded runs over a cluster
+//! - [`time_buckets`] - Per-time-window PSI for correlating event logs
+//! - `trace` - `Tracer`, a human-readable trace of every protocol phase
+//!   with secrets left out (`trace` feature)
 //! - [`crypto`] - Cryptographic operations
 //! - [`error`] - Error types
+//! - [`item_id`] - The `ItemId` item hash newtype
+//! - [`item_set`] - `PsiItemSet`, a reusable item set with per-item expiry
+//! - [`persist`] - Stable binary and serde encodings of `PsiResult`
+//! - [`prefilter`] - Merkle-tree prefilter for mostly-identical sets
+//! - [`range_sync`] - Recursive range-partition sync with PSI at the leaves
+//! - [`aggregate`] - `ResultAggregator`, the merge of many results with
+//!   per-item counts
+//! - [`approx`] - Approximate intersection size from a coordinated sample,
+//!   separate from the exact API
+//! - `payload` - Encrypted per-item payloads for shared items (`payload`
+//!   feature)
+//! - `record` - `RecordingSession`, sessions recorded to a file for
+//!   deterministic replay (`record` feature)
+//! - [`rate_limit`] - `RateLimiter`, per-peer caps on queried items to deter
+//!   set enumeration
+//! - [`blocklist`] - `BlocklistSync`, recurring blocklist sync exchanging
+//!   only new indicators after a first full round
+//! - [`breach`] - Credential-breach checking on top of the one-round variant
+//! - [`membership`] - `MembershipServer`/`MembershipClient`, many small
+//!   membership query batches against one server set blinded once
+//! - [`private_eq`] - `PrivateEq`, a private equality test of two single
+//!   values
+//! - [`tokens`] - `TokenKey`, salted session-scoped tokens in place of
+//!   the item hashes of a result
+//! - [`chunking`] - FastCDC content-defined chunks as PSI items, for
+//!   deduplicated transfer
+//! - [`qr`] - `QrEncoder`/`QrReassembler`, messages as QR codes for
+//!   air-gapped exchanges
+//! - [`conformance`] - Conformance suite checking other implementations
+//!   against this crate over the wire format
+//! - [`local`] - In-process execution of the full protocol
+//! - [`wire`] - Binary wire format for messages
+//! - `arrow` - Arrow arrays and record batches as input and output (`arrow`
+//!   feature)
+//! - `parquet` - `ParquetSource`, a column of Parquet files as input, with
+//!   time-range pushdown (`parquet` feature)
+//! - `archive` - Zero-copy rkyv archives of the points messages (`rkyv`
+//!   feature)
+//! - [`backend`] - Curve arithmetic backend selection
+//! - [`self_test`](mod@self_test) - Startup known-answer test of the crypto
+//!   backend
+//!
+//! ## Cargo Features
+//!
+//! - `precomputed-tables` (default) - Forwarded to curve25519-dalek; see
+//!   [`backend`] for SIMD/backend selection
+//! - `serde` - Derive `Serialize`/`Deserialize` on the message types and
+//!   `PsiResult`
+//! - `vartime` - Opt-in variable-time batch multiplication for `compute`
+//!   (see `PsiConfigBuilder::vartime`); not constant-time in the secret
+//! - `parallel` - Split the double-blinding in `compute` across worker
+//!   threads (see `PsiConfigBuilder::compute_threads`)
+//! - `payload` - `seal_payloads`/`open_payloads`, which deliver an
+//!   encrypted payload per shared item alongside the intersection
+//! - `futures` - `from_stream`, which blinds items from a
+//!   `futures_core::Stream` as they arrive
+//! - `tokio` - `new_async`/`compute_async`/`finalize_async`, which run the
+//!   CPU-heavy phases on tokio's blocking pool, `send_chunked`/`compute_chunked`,
+//!   which send messages in flow-controlled chunks, `PhaseLimits`/`CancelToken`,
+//!   per-phase deadlines and cancellation, and `spawn_sweeper`, which expires
+//!   `SessionManager` sessions in the background
+//! - `arrow` - `from_arrow`/`from_record_batch` and `PsiResult::to_record_batch`,
+//!   for Arrow-based pipelines
+//! - `parquet` - `parquet::ParquetSource` and `from_parquet`, which stream a
+//!   column out of Parquet files (implies `arrow`)
+//! - `sqlite` - `sqlite::SqliteSource`, an `ItemSource` paging a column out
+//!   of a SQLite table
+//! - `redis` - `redis::RedisStore`, sessions shared between replicas through
+//!   Redis
+//! - `guarded-memory` - Secret scalars in locked memory between guard pages,
+//!   falling back to regular memory where locking is not permitted (see
+//!   `guarded_memory_locked`)
+//! - `record` - `record::RecordingSession` and `record::SessionRecording`,
+//!   which record a session's seed, configuration and messages and replay
+//!   them deterministically
+//! - `mqtt` - `mqtt::MqttRequester` and `mqtt::MqttResponder`, which map
+//!   one-round exchanges onto MQTT 5 publishes for the application's client
+//! - `nats` - `nats::NatsRequester` and `nats::NatsResponder`, which run
+//!   sessions as NATS requests on a subject per session
+//! - `kafka` - `kafka::KafkaExchange`, which splits mux frames into
+//!   session-keyed Kafka records and reassembles the peer's
+//! - `object-store` - `object_store::ObjectExchange`, which drops messages
+//!   under an agreed prefix and polls for the peer's, and
+//!   `object_store::DirStore`, its directory-backed store
+//! - `trace` - `PsiConfigBuilder::trace` and `trace::Tracer`, which report
+//!   counts, shortened points and decisions of every phase, for learning and
+//!   integration debugging
+//! - `rkyv` - `to_archive` and `archive::PointsView`, zero-copy reading of
+//!   received points messages
+//! - `std-hasher` - SipHash instead of foldhash for the internal point maps
+//!   used while matching, keyed against hash flooding by a peer choosing
+//!   its points
 
-pub use messages::{BlindedPointsMessage, DoubleBlindedPointsMessage, PsiResult};
+pub use aggregate::ResultAggregator;
+#[cfg(feature = "tokio")]
+pub use async_support::{CancelToken, ChunkTransport, PhaseLimits};
+pub use backend::{active_backend, CurveBackend};
+pub use config::{
+    HashAlgorithm, MessageOrder, Padding, PsiConfig, PsiConfigBuilder, PROTOCOL_VERSION,
+};
+pub use crypto::{hash_item, hash_item_with};
+pub use flow::FlowControl;
+pub use item_id::ItemId;
+pub use item_set::PsiItemSet;
+pub use local::run_local_psi;
+#[cfg(feature = "tokio")]
+pub use manager::spawn_sweeper;
+pub use manager::SessionManager;
+pub use messages::{
+    AbortMessage, AlignedMatch, BlindedPointsMessage, CardinalityMessage, ConfirmationMessage,
+    DoubleBlindedPointsMessage, ErrorReportMessage, MerkleDigestsMessage, MessageMac,
+    OneRoundResponseMessage, ParameterDigest, PsiResult, RangeDigest, RangeDigestsMessage,
+};
+pub use mux::{MuxEvent, SessionMux};
+#[cfg(feature = "payload")]
+pub use payload::{EncryptedPayload, EncryptedPayloadsMessage};
+pub use persist::RESULT_VERSION;
+pub use prefilter::{MerklePrefilter, MAX_MERKLE_DEPTH};
 pub use protocol::PsiProtocol;
+pub use psi_backend::{run_local_backend, PsiBackend};
+pub use psk::PreSharedKey;
+pub use range_sync::RangeSync;
+pub use ratchet::{SessionRatchet, RATCHET_STATE_LEN};
+pub use rate_limit::RateLimiter;
+#[cfg(feature = "guarded-memory")]
+pub use secret::guarded_memory_locked;
+pub use self_test::self_test;
+pub use session::PsiSession;
+pub use sink::{Match, MatchSink, WriteSink};
+pub use source::ItemSource;
 pub use state::{PsiState, PreparedState, DoubleBlindedState, FinalState};
-pub use error::{PsiError, Result};
+pub use error::{AbortReason, ErrorReport, Limit, Phase, PsiError, RecoverableError, Result};
+pub use stats::PsiStats;
+pub use store::{MemoryStore, SessionStore, SESSION_STATE_VERSION};
+pub use stream::BlindingStream;
+pub use time_buckets::{BucketedPsi, TimeBuckets};
+pub use tokens::{OpaqueToken, TokenKey, TokenizedResult};
+pub use transcript::{PeerIdentity, SessionContext, Transcript};
+pub use wire::WireMessage;
+
+/// Report a protocol step to the configuration's tracer (`trace` feature).
+///
+/// Expands to nothing without the feature; fields are only formatted when a
+/// tracer is set.
+macro_rules! trace_event {
+    ($config:expr, $phase:expr, $step:expr $(, $name:ident = $value:expr)* $(,)?) => {
+        #[cfg(feature = "trace")]
+        $config.trace($phase, $step, || vec![$((stringify!($name), $value.to_string())),*]);
+    };
+}
+
+/// Report the outcome of a protocol step: `$fields` for a success, the error
+/// as a `rejected` step otherwise (`trace` feature).
+macro_rules! trace_outcome {
+    ($config:expr, $phase:expr, $outcome:expr, $step:expr, |$value:pat_param| $fields:expr) => {
+        #[cfg(feature = "trace")]
+        $config.trace_outcome($phase, $outcome, $step, |$value| $fields);
+    };
+}
 
+mod aggregate;
+pub mod approx;
+#[cfg(feature = "rkyv")]
+pub mod archive;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "tokio")]
+mod async_support;
+pub mod backend;
+pub mod blocklist;
+pub mod breach;
+pub mod chunking;
+mod collections;
+mod config;
+pub mod conformance;
+pub mod coordinator;
 mod crypto;
 mod error;
+mod flow;
+mod item_id;
+mod item_set;
+#[cfg(feature = "futures")]
+mod item_stream;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+mod local;
+mod manager;
+pub mod membership;
 mod messages;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod mux;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(feature = "object-store")]
+pub mod object_store;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "payload")]
+mod payload;
+mod persist;
+mod prefilter;
+pub mod private_eq;
 mod protocol;
+mod psi_backend;
+mod psk;
+pub mod qr;
+mod range_sync;
+mod ratchet;
+mod rate_limit;
+#[cfg(feature = "record")]
+pub mod record;
+#[cfg(feature = "redis")]
+pub mod redis;
+mod secret;
+mod self_test;
+mod session;
+pub mod sharding;
+mod sink;
+mod source;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 mod state;
+mod stats;
+mod store;
+mod stream;
+#[cfg(test)]
+mod test_util;
+mod time_buckets;
+mod tokens;
+#[cfg(feature = "trace")]
+pub mod trace;
+mod transcript;
+pub mod wire;
 
 /// Integration tests for the full PSI protocol.
 #[cfg(test)]
@@ -192,3 +473,153 @@ mod integration_tests {
         );
     }
 }
+
+/// Property-based tests over randomly generated item sets.
+#[cfg(test)]
+mod property_tests {
+    use super::*;
+    use curve25519_dalek::ristretto::CompressedRistretto;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use std::collections::HashSet;
+
+    /// Generate two item sets with a controlled overlap.
+    ///
+    /// Items are drawn from small byte strings so duplicates within a set
+    /// occur naturally; each side gets at least one item.
+    fn overlapping_sets() -> impl Strategy<Value = (Vec<Vec<u8>>, Vec<Vec<u8>>)> {
+        let item = vec(any::<u8>(), 0..4);
+        (
+            vec(item.clone(), 0..8),
+            vec(item.clone(), 0..8),
+            vec(item, 1..8),
+        )
+            .prop_map(|(alice_only, bob_only, common)| {
+                let mut alice = alice_only;
+                let mut bob = bob_only;
+                alice.extend(common.iter().cloned());
+                bob.extend(common);
+                (alice, bob)
+            })
+    }
+
+    fn expected_intersection(alice: &[Vec<u8>], bob: &[Vec<u8>]) -> HashSet<ItemId> {
+        let alice_hashes: HashSet<_> = alice.iter().map(|i| ItemId::of(i)).collect();
+        let bob_hashes: HashSet<_> = bob.iter().map(|i| ItemId::of(i)).collect();
+        alice_hashes.intersection(&bob_hashes).copied().collect()
+    }
+
+    fn run_protocol(alice_items: &[Vec<u8>], bob_items: &[Vec<u8>]) -> (PsiResult, PsiResult) {
+        run_local_psi(alice_items, bob_items).unwrap()
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn prop_both_parties_agree((alice_items, bob_items) in overlapping_sets()) {
+            let (alice_result, bob_result) = run_protocol(&alice_items, &bob_items);
+
+            let alice_set: HashSet<_> = alice_result.intersection_hashes.iter().copied().collect();
+            let bob_set: HashSet<_> = bob_result.intersection_hashes.iter().copied().collect();
+            prop_assert_eq!(&alice_set, &bob_set);
+            prop_assert_eq!(&alice_set, &expected_intersection(&alice_items, &bob_items));
+
+            // No duplicates in the reported intersection
+            prop_assert_eq!(alice_set.len(), alice_result.len());
+            prop_assert_eq!(alice_result.double_blinded_map.len(), alice_result.len());
+        }
+
+        #[test]
+        fn prop_double_blinded_maps_agree((alice_items, bob_items) in overlapping_sets()) {
+            let (alice_result, bob_result) = run_protocol(&alice_items, &bob_items);
+            prop_assert_eq!(alice_result.double_blinded_map, bob_result.double_blinded_map);
+        }
+
+        #[test]
+        fn prop_message_length_counts_unique_items(items in vec(vec(any::<u8>(), 0..3), 1..16)) {
+            let unique: HashSet<_> = items.iter().collect();
+            let proto = PsiProtocol::new(&items).unwrap();
+            prop_assert_eq!(proto.message().len(), unique.len());
+        }
+
+        #[test]
+        fn prop_single_item_sets(item in vec(any::<u8>(), 0..8), other in vec(any::<u8>(), 0..8)) {
+            let (alice_result, bob_result) = run_protocol(std::slice::from_ref(&item), std::slice::from_ref(&other));
+            let expected = usize::from(item == other);
+            prop_assert_eq!(alice_result.len(), expected);
+            prop_assert_eq!(bob_result.len(), expected);
+        }
+    }
+
+    #[test]
+    fn empty_set_is_rejected() {
+        assert!(matches!(PsiProtocol::new(&[]), Err(PsiError::EmptyInput)));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn prop_messages_wire_round_trip(items in vec(vec(any::<u8>(), 0..8), 1..16)) {
+            let alice = PsiProtocol::new(&items).unwrap();
+            let bob = PsiProtocol::new(&items).unwrap();
+
+            let msg = alice.message();
+            let decoded = BlindedPointsMessage::from_bytes(&msg.to_bytes()).unwrap();
+            prop_assert_eq!(&decoded, &msg);
+
+            let (_, double_msg) = bob.compute(decoded).unwrap();
+            let decoded = DoubleBlindedPointsMessage::from_bytes(&double_msg.to_bytes()).unwrap();
+            prop_assert_eq!(decoded, double_msg);
+        }
+
+        #[test]
+        fn prop_decode_arbitrary_bytes_never_panics(bytes in vec(any::<u8>(), 0..256)) {
+            let _ = wire::decode(&bytes);
+            let _ = mux::decode_frame(&bytes);
+            let _ = PsiResult::from_bytes(&bytes);
+            let _ = PsiSession::from_state_bytes(&bytes, PsiConfig::default());
+        }
+
+        #[test]
+        fn prop_arbitrary_points_never_panic(
+            points in vec(any::<[u8; 32]>(), 0..8),
+            other in vec(any::<[u8; 32]>(), 0..8),
+        ) {
+            let points: Vec<_> = points.into_iter().map(CompressedRistretto).collect();
+            let other: Vec<_> = other.into_iter().map(CompressedRistretto).collect();
+            let alice = PsiProtocol::new(&[b"apple".to_vec(), b"banana".to_vec()]).unwrap();
+
+            let _ = alice.respond_one_round(BlindedPointsMessage::new(points.clone()));
+            let response = OneRoundResponseMessage::new(other.clone(), points.clone());
+            let _ = alice.clone().finalize_one_round(response);
+
+            let bob = PsiProtocol::new(&[b"banana".to_vec()]).unwrap();
+            let _ = alice.clone().compute(BlindedPointsMessage::new(points.clone()));
+            let (alice, _) = alice.compute(bob.message()).unwrap();
+            let _ = alice.finalize(DoubleBlindedPointsMessage::new(other));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn prop_messages_serde_round_trip(items in vec(vec(any::<u8>(), 0..8), 1..16)) {
+            let alice = PsiProtocol::new(&items).unwrap();
+            let bob = PsiProtocol::new(&items).unwrap();
+
+            let msg = alice.message();
+            let json = serde_json::to_string(&msg).unwrap();
+            let decoded: BlindedPointsMessage = serde_json::from_str(&json).unwrap();
+            prop_assert_eq!(&decoded, &msg);
+
+            let (_, double_msg) = bob.compute(decoded).unwrap();
+            let json = serde_json::to_string(&double_msg).unwrap();
+            let decoded: DoubleBlindedPointsMessage = serde_json::from_str(&json).unwrap();
+            prop_assert_eq!(decoded, double_msg);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! In-process execution of the full protocol.
+
+use crate::error::Result;
+use crate::messages::PsiResult;
+use crate::protocol::PsiProtocol;
+
+/// Run the whole PSI protocol between two local item sets.
+///
+/// Both parties are simulated in-process: messages are handed over
+/// directly instead of going through a transport. The results are exactly
+/// what two remote peers would compute with the same inputs.
+///
+/// # Arguments
+/// * `local_items` - Items of the first party
+/// * `remote_items` - Items of the second party
+///
+/// # Returns
+/// A tuple of (first party's `PsiResult`, second party's `PsiResult`)
+///
+/// # Errors
+/// Returns `PsiError::EmptyInput` if either slice is empty
+///
+/// # Example
+/// ```ignore
+/// use psi_protocol::run_local_psi;
+///
+/// let alice = vec![b"apple".to_vec(), b"banana".to_vec()];
+/// let bob = vec![b"banana".to_vec(), b"cherry".to_vec()];
+/// let (alice_result, bob_result) = run_local_psi(&alice, &bob)?;
+/// assert_eq!(alice_result.len(), 1);
+/// # Ok::<(), psi_protocol::PsiError>(())
+/// ```
+pub fn run_local_psi(
+    local_items: &[Vec<u8>],
+    remote_items: &[Vec<u8>],
+) -> Result<(PsiResult, PsiResult)> {
+    let local = PsiProtocol::new(local_items)?;
+    let remote = PsiProtocol::new(remote_items)?;
+
+    let local_msg = local.message();
+    let remote_msg = remote.message();
+
+    let (local_intermediate, local_double_msg) = local.compute(remote_msg)?;
+    let (remote_intermediate, remote_double_msg) = remote.compute(local_msg)?;
+
+    let (_, local_result) = local_intermediate.finalize(remote_double_msg)?;
+    let (_, remote_result) = remote_intermediate.finalize(local_double_msg)?;
+
+    Ok((local_result, remote_result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::PsiError;
+
+    #[test]
+    fn test_run_local_psi() {
+        let alice = vec![b"apple".to_vec(), b"banana".to_vec()];
+        let bob = vec![b"banana".to_vec(), b"cherry".to_vec()];
+
+        let (alice_result, bob_result) = run_local_psi(&alice, &bob).unwrap();
+        assert_eq!(alice_result.len(), 1);
+        assert_eq!(alice_result, bob_result);
+    }
+
+    #[test]
+    fn test_run_local_psi_empty_input() {
+        let items = vec![b"apple".to_vec()];
+        assert_eq!(run_local_psi(&[], &items), Err(PsiError::EmptyInput));
+        assert_eq!(run_local_psi(&items, &[]), Err(PsiError::EmptyInput));
+    }
+}
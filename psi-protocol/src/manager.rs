@@ -0,0 +1,260 @@
+//! Bookkeeping for many concurrent sessions.
+//!
+//! A server talking to many peers keeps one [`PsiSession`] per peer. A peer
+//! that disconnects halfway would otherwise pin its session, including the
+//! secret scalar, in memory forever. [`SessionManager`] gives every session a
+//! deadline and drops expired ones on [`sweep`](SessionManager::sweep);
+//! dropped states zeroize their secret.
+
+use crate::error::AbortReason;
+use crate::messages::AbortMessage;
+use crate::session::PsiSession;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// A session together with the instant it expires.
+#[derive(Debug)]
+struct Entry {
+    session: PsiSession,
+    deadline: Instant,
+}
+
+/// Sessions keyed by peer, each with a deadline.
+///
+/// Expired sessions are invisible to [`get`](Self::get) and
+/// [`get_mut`](Self::get_mut) even before the next sweep, so a late message
+/// can never revive them.
+///
+/// # Example
+/// ```ignore
+/// use psi_protocol::{PsiSession, SessionManager};
+/// use std::time::Duration;
+///
+/// let mut sessions = SessionManager::new(Duration::from_secs(30));
+/// sessions.insert(peer_id, PsiSession::new(&items)?);
+///
+/// // On every incoming message
+/// if let Some(session) = sessions.get_mut(&peer_id) {
+///     let reply = session.on_blinded(msg)?;
+/// }
+///
+/// // Periodically
+/// let expired = sessions.sweep();
+/// # Ok::<(), psi_protocol::PsiError>(())
+/// ```
+#[derive(Debug)]
+pub struct SessionManager<K> {
+    sessions: HashMap<K, Entry>,
+    ttl: Duration,
+}
+
+impl<K: Eq + Hash> SessionManager<K> {
+    /// Create an empty manager giving each new session `ttl` to complete.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            sessions: HashMap::new(),
+            ttl,
+        }
+    }
+
+    /// Time each session is given when inserted or touched.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Insert a session expiring after the manager's TTL.
+    ///
+    /// Returns the session previously stored under `key`, if any.
+    pub fn insert(&mut self, key: K, session: PsiSession) -> Option<PsiSession> {
+        let deadline = Instant::now() + self.ttl;
+        self.insert_with_deadline(key, session, deadline)
+    }
+
+    /// Insert a session expiring at `deadline`.
+    ///
+    /// Returns the session previously stored under `key`, if any.
+    pub fn insert_with_deadline(
+        &mut self,
+        key: K,
+        session: PsiSession,
+        deadline: Instant,
+    ) -> Option<PsiSession> {
+        self.sessions
+            .insert(key, Entry { session, deadline })
+            .map(|entry| entry.session)
+    }
+
+    /// Get a live session.
+    pub fn get(&self, key: &K) -> Option<&PsiSession> {
+        let now = Instant::now();
+        self.sessions
+            .get(key)
+            .filter(|entry| entry.deadline > now)
+            .map(|entry| &entry.session)
+    }
+
+    /// Get a live session mutably, e.g. to feed it the next message.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut PsiSession> {
+        let now = Instant::now();
+        self.sessions
+            .get_mut(key)
+            .filter(|entry| entry.deadline > now)
+            .map(|entry| &mut entry.session)
+    }
+
+    /// Deadline of a session, expired or not.
+    pub fn deadline(&self, key: &K) -> Option<Instant> {
+        self.sessions.get(key).map(|entry| entry.deadline)
+    }
+
+    /// Give a live session a fresh TTL, e.g. after the peer made progress.
+    ///
+    /// Returns false if there is no live session under `key`.
+    pub fn touch(&mut self, key: &K) -> bool {
+        let now = Instant::now();
+        match self.sessions.get_mut(key) {
+            Some(entry) if entry.deadline > now => {
+                entry.deadline = now + self.ttl;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Remove a session, e.g. once its result has been collected.
+    pub fn remove(&mut self, key: &K) -> Option<PsiSession> {
+        self.sessions.remove(key).map(|entry| entry.session)
+    }
+
+    /// Abort a live session and remove it.
+    ///
+    /// Returns the message to send the peer, or `None` if there is no live
+    /// session under `key`.
+    pub fn abort(&mut self, key: &K, reason: AbortReason) -> Option<AbortMessage> {
+        self.get(key)?;
+        self.remove(key).map(|mut session| session.abort(reason))
+    }
+
+    /// Number of stored sessions, including expired ones not yet swept.
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Returns true if no sessions are stored.
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Drop every session whose deadline has passed.
+    ///
+    /// Returns the number of sessions dropped. Their secrets are zeroized.
+    pub fn sweep(&mut self) -> usize {
+        self.sweep_at(Instant::now())
+    }
+
+    /// Drop every session whose deadline is at or before `now`.
+    ///
+    /// Returns the number of sessions dropped.
+    pub fn sweep_at(&mut self, now: Instant) -> usize {
+        let before = self.sessions.len();
+        self.sessions.retain(|_, entry| entry.deadline > now);
+        before - self.sessions.len()
+    }
+}
+
+/// Sweep a shared manager every `period` on the current tokio runtime.
+///
+/// The task runs until aborted through the returned handle. Requires the
+/// `tokio` feature.
+///
+/// # Example
+/// ```ignore
+/// let sessions = Arc::new(Mutex::new(SessionManager::new(Duration::from_secs(30))));
+/// let sweeper = spawn_sweeper(Arc::clone(&sessions), Duration::from_secs(5));
+/// // ...
+/// sweeper.abort();
+/// ```
+#[cfg(feature = "tokio")]
+pub fn spawn_sweeper<K>(
+    manager: std::sync::Arc<std::sync::Mutex<SessionManager<K>>>,
+    period: Duration,
+) -> tokio::task::JoinHandle<()>
+where
+    K: Eq + Hash + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            // A poisoned lock still holds a usable map; keep expiring sessions
+            let mut manager = manager.lock().unwrap_or_else(|e| e.into_inner());
+            manager.sweep();
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> PsiSession {
+        PsiSession::new(&[b"apple".to_vec()]).unwrap()
+    }
+
+    #[test]
+    fn test_insert_get_remove() {
+        let mut manager = SessionManager::new(Duration::from_secs(60));
+        assert!(manager.insert(1, session()).is_none());
+        assert!(manager.insert(1, session()).is_some());
+        assert_eq!(manager.len(), 1);
+        assert!(manager.get(&1).is_some());
+        assert!(manager.get_mut(&1).unwrap().message().is_ok());
+        assert!(manager.remove(&1).is_some());
+        assert!(manager.is_empty());
+    }
+
+    #[test]
+    fn test_sweep_drops_expired_sessions() {
+        let mut manager = SessionManager::new(Duration::from_secs(60));
+        let now = Instant::now();
+        manager.insert_with_deadline("stale", session(), now);
+        manager.insert("fresh", session());
+
+        // Expired sessions are hidden before the sweep removes them
+        assert!(manager.get(&"stale").is_none());
+        assert!(manager.get_mut(&"stale").is_none());
+        assert!(!manager.touch(&"stale"));
+        assert_eq!(manager.len(), 2);
+
+        assert_eq!(manager.sweep_at(now), 1);
+        assert_eq!(manager.len(), 1);
+        assert!(manager.get(&"fresh").is_some());
+        assert_eq!(manager.sweep_at(now + Duration::from_secs(120)), 1);
+        assert!(manager.is_empty());
+    }
+
+    #[test]
+    fn test_touch_extends_deadline() {
+        let mut manager = SessionManager::new(Duration::from_secs(60));
+        let soon = Instant::now() + Duration::from_secs(1);
+        manager.insert_with_deadline(7, session(), soon);
+        assert!(manager.touch(&7));
+        assert!(manager.deadline(&7).unwrap() > soon);
+        assert!(!manager.touch(&8));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_spawn_sweeper_expires_sessions() {
+        use std::sync::{Arc, Mutex};
+
+        let manager = Arc::new(Mutex::new(SessionManager::new(Duration::from_millis(10))));
+        manager.lock().unwrap().insert(1, session());
+        let sweeper = spawn_sweeper(Arc::clone(&manager), Duration::from_millis(5));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(manager.lock().unwrap().is_empty());
+        sweeper.abort();
+    }
+}
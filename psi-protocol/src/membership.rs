@@ -0,0 +1,491 @@
+//! Amortized membership queries against one server set.
+//!
+//! Lookup-style workloads ask about a few items at a time, many times over,
+//! against the same large set. Running a full protocol per lookup would
+//! blind the server's set again each time. Here the server blinds its set
+//! once under a long-term key and the client downloads it once; afterwards
+//! every query batch only costs one point per queried item on each side:
+//!
+//! 1. The server builds a [`MembershipServer`] and publishes
+//!    [`set`](MembershipServer::set) (cacheable, identical for every client)
+//! 2. The client builds a [`MembershipClient`] from it, which indexes the
+//!    blinded set once
+//! 3. For each batch, the client sends [`query`](MembershipClient::query),
+//!    the server [`answer`](MembershipServer::answer)s it, and the client
+//!    [`finish`](MembershipClient::finish)es it
+//!
+//! Each batch is blinded with a fresh scalar, so the server cannot link
+//! batches or tell repeated items apart. Only the client learns which of
+//! its items are members. Every answered point lets the client test one
+//! guess against the set; cap queries per client with a
+//! [`QueryThrottle`](crate::breach::QueryThrottle) in front of `answer`.
+//!
+//! # Key rotation
+//!
+//! The server key is versioned by an epoch, and every message is an
+//! [`EpochMessage`] tagged with the epoch it was made under.
+//! [`rotate`](MembershipServer::rotate) switches to a new key (and possibly
+//! a new set) while the previous key keeps answering queries until
+//! [`retire_previous`](MembershipServer::retire_previous). Queries made
+//! against a retired key fail with `PsiError::StaleKey`, which reaches the
+//! client directly or as `PsiError::Rejected(ErrorReport::StaleKey { .. })`
+//! through an [`ErrorReportMessage`](crate::ErrorReportMessage);
+//! [`PsiError::is_stale_key`] covers both and means "re-fetch the set", not
+//! a genuine failure. [`MembershipServer::with_derived_secret`] derives the
+//! key of an epoch from key material, so it survives restarts and is shared
+//! by replicas.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::membership::{MembershipClient, MembershipServer};
+//!
+//! // Server, once
+//! let server = MembershipServer::new(&set, PsiConfig::default())?;
+//! let set_msg = server.set();
+//!
+//! // Client, once
+//! let client = MembershipClient::new(set_msg, PsiConfig::default())?;
+//!
+//! // Then for every batch
+//! let (pending, query) = client.query(&[b"apple".to_vec()])?;
+//! let members = match server.answer(query) {
+//!     Ok(answer) => client.finish(pending, answer)?,
+//!     Err(e) if e.is_stale_key() => { /* re-fetch server.set() and retry */ }
+//!     Err(e) => return Err(e),
+//! };
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::collections::FastSet;
+use crate::config::PsiConfig;
+use crate::crypto::{
+    blind_point, decompress_or_basepoint, hash_bytes_with, hash_to_point_in, random_scalar,
+};
+use crate::error::{Phase, PsiError, Result};
+use crate::messages::{BlindedPointsMessage, DoubleBlindedPointsMessage};
+use crate::protocol::PsiProtocol;
+use crate::psk::{BLINDED_LABEL, DOUBLE_BLINDED_LABEL};
+use crate::secret::SecretScalar;
+use crate::state::PreparedState;
+use curve25519_dalek::ristretto::CompressedRistretto;
+
+/// A message tagged with the server key epoch it was made under.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EpochMessage<M> {
+    /// Epoch of the server key
+    pub epoch: u32,
+    /// The message itself
+    pub message: M,
+}
+
+impl<M> EpochMessage<M> {
+    /// Tag `message` with `epoch`.
+    pub fn new(epoch: u32, message: M) -> Self {
+        Self { epoch, message }
+    }
+}
+
+/// Server side: the set blinded once under a long-term, versioned key.
+#[derive(Debug, Clone)]
+pub struct MembershipServer {
+    epoch: u32,
+    set: PsiProtocol<PreparedState>,
+    /// The key before the last rotation, still answering queries
+    previous: Option<(u32, PsiProtocol<PreparedState>)>,
+}
+
+impl MembershipServer {
+    /// Blind `set` under a fresh random key, at epoch 0.
+    ///
+    /// Clients must download the set again whenever the server restarts.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::new_with_config`]
+    pub fn new(set: &[Vec<u8>], config: PsiConfig) -> Result<Self> {
+        Ok(Self {
+            epoch: 0,
+            set: PsiProtocol::new_with_config(set, config)?,
+            previous: None,
+        })
+    }
+
+    /// Blind `set` under the key of `epoch`, derived from `ikm` and
+    /// `context`.
+    ///
+    /// Restarted servers and replicas sharing the key material and epoch
+    /// publish the same blinded set and answer queries identically.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::with_derived_secret`]
+    pub fn with_derived_secret(
+        set: &[Vec<u8>],
+        ikm: &[u8],
+        context: &[u8],
+        epoch: u32,
+        config: PsiConfig,
+    ) -> Result<Self> {
+        Ok(Self {
+            epoch,
+            set: PsiProtocol::with_derived_secret(
+                set,
+                ikm,
+                &epoch_context(context, epoch),
+                config,
+            )?,
+            previous: None,
+        })
+    }
+
+    /// Epoch of the current key.
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Switch to a fresh random key over `set`, returning the new epoch.
+    ///
+    /// The previous key keeps answering queries until
+    /// [`retire_previous`](Self::retire_previous) or the next rotation; the
+    /// one before it is retired now.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if the epoch counter is exhausted,
+    /// plus the errors of [`PsiProtocol::new_with_config`]
+    pub fn rotate(&mut self, set: &[Vec<u8>]) -> Result<u32> {
+        let epoch = self.next_epoch()?;
+        let next = PsiProtocol::new_with_config(set, self.set.config().clone())?;
+        Ok(self.install(epoch, next))
+    }
+
+    /// Switch to the key of the next epoch derived from `ikm` and `context`,
+    /// returning the new epoch. See [`rotate`](Self::rotate).
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if the epoch counter is exhausted,
+    /// plus the errors of [`PsiProtocol::with_derived_secret`]
+    pub fn rotate_derived(&mut self, set: &[Vec<u8>], ikm: &[u8], context: &[u8]) -> Result<u32> {
+        let epoch = self.next_epoch()?;
+        let next = PsiProtocol::with_derived_secret(
+            set,
+            ikm,
+            &epoch_context(context, epoch),
+            self.set.config().clone(),
+        )?;
+        Ok(self.install(epoch, next))
+    }
+
+    /// Stop answering queries made under the key before the last rotation.
+    pub fn retire_previous(&mut self) {
+        self.previous = None;
+    }
+
+    fn next_epoch(&self) -> Result<u32> {
+        self.epoch
+            .checked_add(1)
+            .ok_or_else(|| PsiError::InvalidConfig("Key epoch counter exhausted".to_string()))
+    }
+
+    fn install(&mut self, epoch: u32, next: PsiProtocol<PreparedState>) -> u32 {
+        let current = std::mem::replace(&mut self.set, next);
+        self.previous = Some((self.epoch, current));
+        self.epoch = epoch;
+        epoch
+    }
+
+    /// The blinded set under the current key, identical for every client.
+    pub fn set(&self) -> EpochMessage<BlindedPointsMessage> {
+        EpochMessage::new(self.epoch, self.set.message())
+    }
+
+    /// Apply the key of the query's epoch to a client's query batch.
+    ///
+    /// Takes `&self`, so one server answers any number of clients
+    /// concurrently.
+    ///
+    /// # Errors
+    /// Returns `PsiError::StaleKey` if the query was made against a retired
+    /// key, `PsiError::LimitExceeded` if the batch is over the configured
+    /// remote limit, `PsiError::InvalidPoint` if it holds an invalid point
+    /// (unless lenient), `PsiError::ParameterMismatch` if the client used
+    /// other parameters, or, with a pre-shared key, `PsiError::CryptoError`
+    /// if the query's MAC does not verify
+    pub fn answer(
+        &self,
+        query: EpochMessage<BlindedPointsMessage>,
+    ) -> Result<EpochMessage<DoubleBlindedPointsMessage>> {
+        let set = match &self.previous {
+            _ if query.epoch == self.epoch => &self.set,
+            Some((epoch, previous)) if query.epoch == *epoch => previous,
+            _ => {
+                return Err(PsiError::StaleKey {
+                    current: self.epoch,
+                    actual: query.epoch,
+                })
+            }
+        };
+        set.check_message(&query.message)?;
+        let double_blinded = set.double_blind(&query.message)?;
+        let config = set.config();
+        let mut answer = DoubleBlindedPointsMessage::new(double_blinded);
+        answer.authentication =
+            config.authenticate(DOUBLE_BLINDED_LABEL, &answer.double_blinded_points);
+        answer.parameters = Some(config.parameter_digest());
+        Ok(EpochMessage::new(query.epoch, answer))
+    }
+}
+
+/// Derivation context of the key of `epoch`.
+fn epoch_context(context: &[u8], epoch: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(context.len() + 4);
+    out.extend_from_slice(context);
+    out.extend_from_slice(&epoch.to_be_bytes());
+    out
+}
+
+/// Client side: the downloaded server set, indexed once.
+#[derive(Debug, Clone)]
+pub struct MembershipClient {
+    epoch: u32,
+    set: FastSet<CompressedRistretto>,
+    config: PsiConfig,
+}
+
+/// A query batch waiting for the server's answer.
+#[derive(Debug)]
+pub struct PendingQuery {
+    /// Inverse of the batch's blinding scalar, redacted from `Debug` output
+    unblind: SecretScalar,
+    epoch: u32,
+    len: usize,
+}
+
+impl MembershipClient {
+    /// Index the server's blinded set.
+    ///
+    /// # Errors
+    /// Returns `PsiError::ParameterMismatch` if the server used other
+    /// parameters, or, with a pre-shared key, `PsiError::CryptoError` if the
+    /// set's MAC does not verify
+    pub fn new(set: EpochMessage<BlindedPointsMessage>, config: PsiConfig) -> Result<Self> {
+        let EpochMessage {
+            epoch,
+            message: set,
+        } = set;
+        config.check_authentication(
+            BLINDED_LABEL,
+            &set.blinded_points,
+            set.authentication.as_ref(),
+        )?;
+        config.check_parameters(Phase::Compute, set.parameters.as_ref())?;
+        Ok(Self {
+            epoch,
+            set: set.blinded_points.into_iter().collect(),
+            config,
+        })
+    }
+
+    /// Epoch of the server key the set was blinded with.
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Number of distinct points in the server's set, padding included.
+    pub fn set_len(&self) -> usize {
+        self.set.len()
+    }
+
+    /// Blind a batch of items under a fresh scalar.
+    ///
+    /// Returns the pending batch and the query to send. Points follow the
+    /// order of `items`, duplicates included.
+    ///
+    /// # Errors
+    /// Returns `PsiError::EmptyInput` if `items` is empty
+    pub fn query(
+        &self,
+        items: &[Vec<u8>],
+    ) -> Result<(PendingQuery, EpochMessage<BlindedPointsMessage>)> {
+        if items.is_empty() {
+            return Err(PsiError::EmptyInput);
+        }
+        let secret = random_scalar();
+        let points: Vec<CompressedRistretto> = items
+            .iter()
+            .map(|item| {
+                let hash = hash_bytes_with(self.config.hash(), item);
+                blind_point(&hash_to_point_in(self.config.domain(), &hash), &secret)
+            })
+            .collect();
+        let mut query = BlindedPointsMessage::new(points);
+        query.authentication = self
+            .config
+            .authenticate(BLINDED_LABEL, &query.blinded_points);
+        query.parameters = Some(self.config.parameter_digest());
+        let pending = PendingQuery {
+            unblind: SecretScalar::new(secret.invert()),
+            epoch: self.epoch,
+            len: items.len(),
+        };
+        Ok((pending, EpochMessage::new(self.epoch, query)))
+    }
+
+    /// Whether each queried item is in the server's set, in query order.
+    ///
+    /// # Errors
+    /// Returns `PsiError::StaleKey` if the answer was made under another key
+    /// than the query, `PsiError::LengthMismatch` if the answer does not hold one
+    /// point per queried item, `PsiError::InvalidPoint` with the position of
+    /// the first invalid point, `PsiError::ParameterMismatch` if the server
+    /// used other parameters, or, with a pre-shared key,
+    /// `PsiError::CryptoError` if the answer's MAC does not verify
+    pub fn finish(
+        &self,
+        pending: PendingQuery,
+        answer: EpochMessage<DoubleBlindedPointsMessage>,
+    ) -> Result<Vec<bool>> {
+        if answer.epoch != pending.epoch {
+            return Err(PsiError::StaleKey {
+                current: answer.epoch,
+                actual: pending.epoch,
+            });
+        }
+        let answer = answer.message;
+        self.config.check_authentication(
+            DOUBLE_BLINDED_LABEL,
+            &answer.double_blinded_points,
+            answer.authentication.as_ref(),
+        )?;
+        self.config
+            .check_parameters(Phase::Finalize, answer.parameters.as_ref())?;
+        if answer.len() != pending.len {
+            return Err(PsiError::LengthMismatch {
+                phase: Phase::Finalize,
+                expected: pending.len,
+                actual: answer.len(),
+            });
+        }
+
+        // s·c·H(x) unblinded with c⁻¹ is s·H(x), as published in the set
+        let mut invalid = None;
+        let members = answer
+            .double_blinded_points
+            .iter()
+            .enumerate()
+            .map(|(index, point)| {
+                let (point, valid) = decompress_or_basepoint(point);
+                if !valid {
+                    invalid.get_or_insert(index);
+                }
+                valid
+                    && self
+                        .set
+                        .contains(&(pending.unblind.expose() * point).compress())
+            })
+            .collect();
+        match invalid {
+            Some(index) => Err(PsiError::InvalidPoint {
+                phase: Phase::Finalize,
+                index,
+            }),
+            None => Ok(members),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::items;
+
+    #[test]
+    fn test_many_batches_against_one_set() {
+        let server =
+            MembershipServer::new(&items(&["apple", "banana", "cherry"]), PsiConfig::default())
+                .unwrap();
+        let client = MembershipClient::new(server.set(), PsiConfig::default()).unwrap();
+        assert_eq!(client.set_len(), 3);
+
+        let (pending, query) = client.query(&items(&["banana", "date"])).unwrap();
+        let answer = server.answer(query).unwrap();
+        assert_eq!(client.finish(pending, answer).unwrap(), vec![true, false]);
+
+        let (pending, query) = client.query(&items(&["cherry", "cherry"])).unwrap();
+        let answer = server.answer(query).unwrap();
+        assert_eq!(client.finish(pending, answer).unwrap(), vec![true, true]);
+    }
+
+    #[test]
+    fn test_derived_key_survives_restarts() {
+        let set = items(&["apple"]);
+        let key = [7u8; 32];
+        let server =
+            MembershipServer::with_derived_secret(&set, &key, b"lookup", 3, PsiConfig::default())
+                .unwrap();
+        let client = MembershipClient::new(server.set(), PsiConfig::default()).unwrap();
+
+        let restarted =
+            MembershipServer::with_derived_secret(&set, &key, b"lookup", 3, PsiConfig::default())
+                .unwrap();
+        let (pending, query) = client.query(&items(&["apple"])).unwrap();
+        let answer = restarted.answer(query).unwrap();
+        assert_eq!(client.finish(pending, answer).unwrap(), vec![true]);
+    }
+
+    #[test]
+    fn test_rotation_invalidates_old_sets() {
+        let mut server = MembershipServer::new(&items(&["apple"]), PsiConfig::default()).unwrap();
+        let old_client = MembershipClient::new(server.set(), PsiConfig::default()).unwrap();
+
+        // The set changes with the key; the previous key still answers
+        assert_eq!(server.rotate(&items(&["banana"])).unwrap(), 1);
+        let (pending, query) = old_client.query(&items(&["apple"])).unwrap();
+        let answer = server.answer(query).unwrap();
+        assert_eq!(old_client.finish(pending, answer).unwrap(), vec![true]);
+
+        let new_client = MembershipClient::new(server.set(), PsiConfig::default()).unwrap();
+        assert_eq!(new_client.epoch(), 1);
+        let (pending, query) = new_client.query(&items(&["banana"])).unwrap();
+        let answer = server.answer(query).unwrap();
+        assert_eq!(new_client.finish(pending, answer).unwrap(), vec![true]);
+
+        server.retire_previous();
+        let (_, query) = old_client.query(&items(&["apple"])).unwrap();
+        let error = server.answer(query).unwrap_err();
+        assert_eq!(
+            error,
+            PsiError::StaleKey {
+                current: 1,
+                actual: 0
+            }
+        );
+        assert!(error.is_stale_key());
+        let reported = crate::ErrorReportMessage::for_error(&error).unwrap();
+        assert!(reported.into_error().is_stale_key());
+        assert!(!PsiError::EmptyInput.is_stale_key());
+    }
+
+    #[test]
+    fn test_rejects_mismatched_answers() {
+        let server = MembershipServer::new(&items(&["apple"]), PsiConfig::default()).unwrap();
+        let client = MembershipClient::new(server.set(), PsiConfig::default()).unwrap();
+        assert_eq!(client.query(&[]).unwrap_err(), PsiError::EmptyInput);
+
+        let answer = |points| {
+            let mut answer = DoubleBlindedPointsMessage::new(points);
+            answer.parameters = Some(PsiConfig::default().parameter_digest());
+            EpochMessage::new(0, answer)
+        };
+        let empty = answer(vec![]);
+        let (pending, _) = client.query(&items(&["apple", "banana"])).unwrap();
+        assert!(matches!(
+            client.finish(pending, empty),
+            Err(PsiError::LengthMismatch { expected: 2, .. })
+        ));
+        let (pending, _) = client.query(&items(&["apple"])).unwrap();
+        let invalid = answer(vec![CompressedRistretto([0xff; 32])]);
+        assert!(matches!(
+            client.finish(pending, invalid),
+            Err(PsiError::InvalidPoint { index: 0, .. })
+        ));
+    }
+}
@@ -1,8 +1,9 @@
 //! Message types exchanged between PSI protocol parties.
 
-use crate::error::{PsiError, Result};
+use crate::error::{AbortReason, ErrorReport, Phase, PsiError, Result};
+use crate::item_id::ItemId;
 use curve25519_dalek::ristretto::CompressedRistretto;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// Message containing blinded points sent to remote party.
 ///
@@ -11,9 +12,17 @@ use std::collections::HashMap;
 /// for all items in the sender's set - no hashes are included.
 /// This improves privacy by not revealing any hash information.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BlindedPointsMessage {
     /// Blinded points for each item (no hashes included)
     pub blinded_points: Vec<CompressedRistretto>,
+    /// MAC under a pre-shared key, see [`PreSharedKey`](crate::PreSharedKey)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub authentication: Option<MessageMac>,
+    /// Digest of the sender's parameters, see
+    /// [`PsiConfig::parameter_digest`](crate::PsiConfig::parameter_digest)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub parameters: Option<ParameterDigest>,
 }
 
 impl BlindedPointsMessage {
@@ -25,7 +34,11 @@ impl BlindedPointsMessage {
     /// # Returns
     /// A new `BlindedPointsMessage` instance
     pub fn new(blinded_points: Vec<CompressedRistretto>) -> Self {
-        Self { blinded_points }
+        Self {
+            blinded_points,
+            authentication: None,
+            parameters: None,
+        }
     }
 
     /// Create a new blinded points message, validating that it's not empty.
@@ -44,7 +57,7 @@ impl BlindedPointsMessage {
                 "Blinded points vector cannot be empty".to_string(),
             ));
         }
-        Ok(Self { blinded_points })
+        Ok(Self::new(blinded_points))
     }
 
     /// Returns the number of items in this message.
@@ -64,9 +77,18 @@ impl BlindedPointsMessage {
 /// It contains the double-blinded Ristretto points for all items that were
 /// received from the remote party.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DoubleBlindedPointsMessage {
-    /// Double-blinded points computed from remote's single-blinded points
+    /// Double-blinded points computed from remote's single-blinded points,
+    /// in the order of the remote's message: point `i` answers its point `i`
     pub double_blinded_points: Vec<CompressedRistretto>,
+    /// MAC under a pre-shared key, see [`PreSharedKey`](crate::PreSharedKey)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub authentication: Option<MessageMac>,
+    /// Digest of the sender's parameters, see
+    /// [`PsiConfig::parameter_digest`](crate::PsiConfig::parameter_digest)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub parameters: Option<ParameterDigest>,
 }
 
 impl DoubleBlindedPointsMessage {
@@ -78,7 +100,11 @@ impl DoubleBlindedPointsMessage {
     /// # Returns
     /// A new `DoubleBlindedPointsMessage` instance
     pub fn new(double_blinded_points: Vec<CompressedRistretto>) -> Self {
-        Self { double_blinded_points }
+        Self {
+            double_blinded_points,
+            authentication: None,
+            parameters: None,
+        }
     }
 
     /// Returns the number of items in this message.
@@ -92,16 +118,288 @@ impl DoubleBlindedPointsMessage {
     }
 }
 
+/// Response of the one-round protocol variant.
+///
+/// Sent by the responder after receiving the initiator's blinded points. It
+/// carries both the responder's own single-blinded points and the
+/// double-blinded version of the initiator's points (in the initiator's
+/// order), so the initiator can compute the intersection without a second
+/// round trip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OneRoundResponseMessage {
+    /// Responder's own single-blinded points
+    pub blinded_points: Vec<CompressedRistretto>,
+    /// Double-blinded initiator points, in the initiator's message order
+    pub double_blinded_points: Vec<CompressedRistretto>,
+    /// MAC under a pre-shared key over both point lists, see
+    /// [`PreSharedKey`](crate::PreSharedKey)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub authentication: Option<MessageMac>,
+    /// Digest of the responder's parameters, see
+    /// [`PsiConfig::parameter_digest`](crate::PsiConfig::parameter_digest)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub parameters: Option<ParameterDigest>,
+}
+
+impl OneRoundResponseMessage {
+    /// Create a new one-round response message.
+    ///
+    /// # Arguments
+    /// * `blinded_points` - Responder's single-blinded points
+    /// * `double_blinded_points` - Double-blinded initiator points
+    pub fn new(
+        blinded_points: Vec<CompressedRistretto>,
+        double_blinded_points: Vec<CompressedRistretto>,
+    ) -> Self {
+        Self {
+            blinded_points,
+            double_blinded_points,
+            authentication: None,
+            parameters: None,
+        }
+    }
+}
+
+/// Optional size announcement sent before the blinded points.
+///
+/// Declares how many points the sender's [`BlindedPointsMessage`] will hold,
+/// padding included, so the receiver can pre-allocate buffers, pick chunk
+/// sizes, or abort before any heavy exchange if the peer's set is beyond
+/// policy. Configure [`Padding`](crate::Padding) to announce an obfuscated
+/// size instead of the exact one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CardinalityMessage {
+    /// Number of points in the sender's upcoming blinded points message
+    pub point_count: usize,
+}
+
+impl CardinalityMessage {
+    /// Create a new cardinality message.
+    pub fn new(point_count: usize) -> Self {
+        Self { point_count }
+    }
+
+    /// Check that a received blinded points message matches this announcement.
+    ///
+    /// # Errors
+    /// Returns `PsiError::LengthMismatch` if the message holds a different
+    /// number of points than announced.
+    pub fn check(&self, msg: &BlindedPointsMessage) -> Result<()> {
+        if msg.len() != self.point_count {
+            return Err(PsiError::LengthMismatch {
+                phase: Phase::Compute,
+                expected: self.point_count,
+                actual: msg.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Clean termination of a session by one peer.
+///
+/// Sent instead of the next protocol message when a peer gives up on a
+/// session (a policy violation, overload, cancellation), so the other side
+/// gets `PsiError::Aborted` right away instead of waiting for a transport
+/// timeout. See [`PsiSession::abort`](crate::PsiSession::abort).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AbortMessage {
+    /// Why the sender aborted
+    pub reason: AbortReason,
+}
+
+impl AbortMessage {
+    /// Create a new abort message.
+    pub fn new(reason: AbortReason) -> Self {
+        Self { reason }
+    }
+
+    /// The error the receiving side reports.
+    pub fn into_error(self) -> PsiError {
+        PsiError::Aborted(self.reason)
+    }
+}
+
+/// Structured account of why a peer rejected one of our messages.
+///
+/// Optional: a peer whose `compute` or `finalize` fails on our message may
+/// send this so operators on our side see the peer's reason, e.g. which
+/// point was invalid or which limit was hit, as `PsiError::Rejected`. It
+/// does not end the session by itself; follow it with an [`AbortMessage`]
+/// to do so.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ErrorReportMessage {
+    /// What the sender rejected
+    pub report: ErrorReport,
+}
+
+impl ErrorReportMessage {
+    /// Create a new error report message.
+    pub fn new(report: ErrorReport) -> Self {
+        Self { report }
+    }
+
+    /// Report a local error to the peer, if the peer's message caused it.
+    pub fn for_error(error: &PsiError) -> Option<Self> {
+        ErrorReport::for_error(error).map(Self::new)
+    }
+
+    /// The error the receiving side reports.
+    pub fn into_error(self) -> PsiError {
+        PsiError::Rejected(self.report)
+    }
+}
+
+/// Digests of one level of a [`MerklePrefilter`](crate::MerklePrefilter).
+///
+/// Holds the digests of the sender's frontier nodes at `level`, in node
+/// order. Both peers derive the same frontier, so node indices are implied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MerkleDigestsMessage {
+    /// Tree level of the digests; 0 is the root
+    pub level: u8,
+    /// One digest per frontier node
+    pub digests: Vec<[u8; 32]>,
+}
+
+impl MerkleDigestsMessage {
+    /// Create a new Merkle digests message.
+    pub fn new(level: u8, digests: Vec<[u8; 32]>) -> Self {
+        Self { level, digests }
+    }
+
+    /// Returns the number of digests in this message.
+    pub fn len(&self) -> usize {
+        self.digests.len()
+    }
+
+    /// Returns true if this message contains no digests.
+    pub fn is_empty(&self) -> bool {
+        self.digests.is_empty()
+    }
+}
+
+/// Count and digest of one hash range in a [`RangeSync`](crate::RangeSync).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RangeDigest {
+    /// Number of the sender's items in the range
+    pub count: u64,
+    /// Digest of the sender's item hashes in the range
+    pub digest: [u8; 32],
+}
+
+/// One round of a [`RangeSync`](crate::RangeSync).
+///
+/// Holds a [`RangeDigest`] per range still in question at `level`, in hash
+/// order. Both peers derive the same ranges, so their bounds are implied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RangeDigestsMessage {
+    /// Split level of the ranges; 0 is the whole hash space
+    pub level: u8,
+    /// One entry per range in question
+    pub ranges: Vec<RangeDigest>,
+}
+
+impl RangeDigestsMessage {
+    /// Create a new range digests message.
+    pub fn new(level: u8, ranges: Vec<RangeDigest>) -> Self {
+        Self { level, ranges }
+    }
+
+    /// Returns the number of ranges in this message.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Returns true if this message contains no ranges.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
+
+/// Key confirmation over a session [`Transcript`](crate::Transcript).
+///
+/// Sent by both parties once the exchange is over; a tag that does not
+/// verify means the two parties did not see the same messages in the same
+/// context.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConfirmationMessage {
+    /// Tag binding the sender's view of the session
+    pub tag: [u8; 32],
+}
+
+impl ConfirmationMessage {
+    /// Create a new confirmation message.
+    pub fn new(tag: [u8; 32]) -> Self {
+        Self { tag }
+    }
+}
+
+/// Authentication tag of a points message under a pre-shared key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MessageMac {
+    /// Id of the pre-shared key the tag was computed with
+    pub key_id: u32,
+    /// HMAC-SHA256 over the message kind and points
+    pub tag: [u8; 32],
+}
+
+impl MessageMac {
+    /// Create a new message MAC.
+    pub fn new(key_id: u32, tag: [u8; 32]) -> Self {
+        Self { key_id, tag }
+    }
+}
+
+/// Digest of the protocol parameters a points message was produced with.
+///
+/// Two parties with different hash functions, domains or padding policies
+/// would otherwise find an empty intersection without any error; see
+/// [`PsiConfig::parameter_digest`](crate::PsiConfig::parameter_digest).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParameterDigest(pub [u8; 32]);
+
+/// Where a matched item sits in both blinded points messages.
+///
+/// Indices count every point of a message as sent, padding included. They
+/// let payload-carrying extensions refer to a match without naming the
+/// item: each party sees the same match with the two indices swapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AlignedMatch {
+    /// Id of the matched item
+    pub id: ItemId,
+    /// Position of the item in our blinded points message
+    pub local_index: usize,
+    /// Position of the same item in the peer's blinded points message
+    pub remote_index: usize,
+}
+
 /// Final result of the PSI protocol.
 ///
 /// Contains the intersection of the two private sets and a mapping
 /// from intersection hashes to their double-blinded point representations.
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// See [`to_bytes`](Self::to_bytes) and, with the `serde` feature, the serde
+/// implementations for stable encodings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PsiResult {
     /// Hashes of elements in the intersection
-    pub intersection_hashes: Vec<[u8; 32]>,
+    pub intersection_hashes: Vec<ItemId>,
     /// Double-blinded points mapped to intersection hashes
-    pub double_blinded_map: HashMap<[u8; 32], CompressedRistretto>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::persist::sorted_map"))]
+    pub double_blinded_map: HashMap<ItemId, CompressedRistretto>,
 }
 
 impl PsiResult {
@@ -111,8 +409,8 @@ impl PsiResult {
     /// * `intersection_hashes` - Hashes of elements in the intersection
     /// * `double_blinded_map` - Mapping from intersection hashes to double-blinded points
     pub fn new(
-        intersection_hashes: Vec<[u8; 32]>,
-        double_blinded_map: HashMap<[u8; 32], CompressedRistretto>,
+        intersection_hashes: Vec<ItemId>,
+        double_blinded_map: HashMap<ItemId, CompressedRistretto>,
     ) -> Self {
         Self {
             intersection_hashes,
@@ -129,6 +427,148 @@ impl PsiResult {
     pub fn is_empty(&self) -> bool {
         self.intersection_hashes.is_empty()
     }
+
+    /// Returns the caller's items that are in the intersection.
+    ///
+    /// Each item is re-hashed with [`hash_item`](crate::hash_item) and kept
+    /// if its hash was found, in the order of `items`. Only valid for runs
+    /// using the default [`HashAlgorithm`](crate::HashAlgorithm).
+    ///
+    /// # Example
+    /// ```ignore
+    /// let (_, result) = alice_intermediate.finalize(bob_double_msg)?;
+    /// for item in result.match_items(&alice_items) {
+    ///     println!("shared: {}", String::from_utf8_lossy(item));
+    /// }
+    /// # Ok::<(), psi_protocol::PsiError>(())
+    /// ```
+    pub fn match_items<'a>(&self, items: &'a [Vec<u8>]) -> Vec<&'a [u8]> {
+        self.match_indices(items)
+            .into_iter()
+            .map(|index| items[index].as_slice())
+            .collect()
+    }
+
+    /// Returns the indices into `items` of the items in the intersection.
+    ///
+    /// Same as [`match_items`](Self::match_items), but returns positions so
+    /// the caller can look up data stored alongside each item.
+    pub fn match_indices(&self, items: &[Vec<u8>]) -> Vec<usize> {
+        let hashes: HashSet<&ItemId> = self.intersection_hashes.iter().collect();
+        items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| hashes.contains(&ItemId::of(item)))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Returns the caller's prioritized items that are in the intersection,
+    /// highest priority first.
+    ///
+    /// Items of equal priority keep their order in `items`. Priorities are
+    /// local only and never sent to the peer, so a sync engine can attach
+    /// whatever weight suits it and transfer the most important shared data
+    /// first. Same hash requirements as [`match_items`](Self::match_items).
+    ///
+    /// # Example
+    /// ```ignore
+    /// let items = vec![(b"config".to_vec(), 10), (b"logs".to_vec(), 1)];
+    /// for (item, priority) in result.prioritized_matches(&items) {
+    ///     transfer(item);
+    /// }
+    /// ```
+    pub fn prioritized_matches<'a, P: Ord + Copy>(
+        &self,
+        items: &'a [(Vec<u8>, P)],
+    ) -> Vec<(&'a [u8], P)> {
+        let hashes: HashSet<&ItemId> = self.intersection_hashes.iter().collect();
+        let mut matches: Vec<(&[u8], P)> = items
+            .iter()
+            .filter(|(item, _)| hashes.contains(&ItemId::of(item)))
+            .map(|(item, priority)| (item.as_slice(), *priority))
+            .collect();
+        matches.sort_by_key(|(_, priority)| std::cmp::Reverse(*priority));
+        matches
+    }
+
+    /// Add the items of `other` to this result.
+    ///
+    /// Hashes already present are not repeated, and new ones are appended in
+    /// `other`'s order. Double-blinded points differ between sessions for the
+    /// same item, so when both results map a hash, this result's point is
+    /// kept; entries of `other` for new hashes are taken over.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let mut all = results_per_peer.remove(0);
+    /// for result in results_per_peer {
+    ///     all.merge(result);
+    /// }
+    /// ```
+    pub fn merge(&mut self, other: PsiResult) {
+        let mut known: HashSet<ItemId> = self.intersection_hashes.iter().copied().collect();
+        for id in other.intersection_hashes {
+            if known.insert(id) {
+                self.intersection_hashes.push(id);
+            }
+        }
+        for (id, point) in other.double_blinded_map {
+            self.double_blinded_map.entry(id).or_insert(point);
+        }
+    }
+
+    /// Items in either result: this result's, then the new ones of `other`.
+    ///
+    /// Same as [`merge`](Self::merge) into a copy of this result.
+    pub fn union(&self, other: &PsiResult) -> PsiResult {
+        let mut union = self.clone();
+        union.merge(other.clone());
+        union
+    }
+
+    /// Items in both results, in this result's order.
+    ///
+    /// # Example
+    /// ```ignore
+    /// // Items common to all three partners, without another protocol run
+    /// let everywhere = with_a.intersection(&with_b).intersection(&with_c);
+    /// ```
+    pub fn intersection(&self, other: &PsiResult) -> PsiResult {
+        let theirs: HashSet<&ItemId> = other.intersection_hashes.iter().collect();
+        self.retain_ids(|id| theirs.contains(id))
+    }
+
+    /// Items of this result that are not in `other`, in this result's order.
+    pub fn difference(&self, other: &PsiResult) -> PsiResult {
+        let theirs: HashSet<&ItemId> = other.intersection_hashes.iter().collect();
+        self.retain_ids(|id| !theirs.contains(id))
+    }
+
+    /// Copy of this result restricted to the hashes `keep` accepts, with
+    /// their double-blinded points.
+    fn retain_ids(&self, mut keep: impl FnMut(&ItemId) -> bool) -> PsiResult {
+        let intersection_hashes: Vec<ItemId> = self
+            .intersection_hashes
+            .iter()
+            .filter(|id| keep(id))
+            .copied()
+            .collect();
+        let double_blinded_map = intersection_hashes
+            .iter()
+            .filter_map(|id| self.double_blinded_map.get(id).map(|point| (*id, *point)))
+            .collect();
+        PsiResult::new(intersection_hashes, double_blinded_map)
+    }
+
+    /// Reorder `intersection_hashes` by descending priority.
+    ///
+    /// `priority` is called once per hash; hashes of equal priority keep
+    /// their relative order.
+    pub fn sort_by_priority<P: Ord, F: FnMut(&ItemId) -> P>(&mut self, mut priority: F) {
+        self.intersection_hashes
+            .sort_by_cached_key(|id| std::cmp::Reverse(priority(id)));
+    }
 }
 
 #[cfg(test)]
@@ -170,7 +610,7 @@ mod tests {
 
     #[test]
     fn test_psi_result() {
-        let hash = [1u8; 32];
+        let hash = ItemId::new([1u8; 32]);
         let point = CompressedRistretto([0u8; 32]);
         let mut map = HashMap::new();
         map.insert(hash, point);
@@ -189,6 +629,46 @@ mod tests {
         assert!(result.is_empty());
     }
 
+    #[test]
+    fn test_psi_result_merge() {
+        let (a, b, c) = (
+            ItemId::new([1u8; 32]),
+            ItemId::new([2u8; 32]),
+            ItemId::new([3u8; 32]),
+        );
+        let point = |byte| CompressedRistretto([byte; 32]);
+        let mut result =
+            PsiResult::new(vec![a, b], HashMap::from([(a, point(10)), (b, point(11))]));
+        result.merge(PsiResult::new(
+            vec![c, b],
+            HashMap::from([(b, point(20)), (c, point(21))]),
+        ));
+        assert_eq!(result.intersection_hashes, vec![a, b, c]);
+        assert_eq!(result.double_blinded_map[&b], point(11));
+        assert_eq!(result.double_blinded_map[&c], point(21));
+    }
+
+    #[test]
+    fn test_psi_result_set_operations() {
+        let id = |byte| ItemId::new([byte; 32]);
+        let result = |bytes: &[u8]| {
+            PsiResult::new(
+                bytes.iter().map(|&byte| id(byte)).collect(),
+                bytes
+                    .iter()
+                    .map(|&byte| (id(byte), CompressedRistretto([byte; 32])))
+                    .collect(),
+            )
+        };
+        let (with_a, with_b, with_c) = (result(&[1, 2, 3]), result(&[3, 2, 4]), result(&[2, 5]));
+
+        let everywhere = with_a.intersection(&with_b).intersection(&with_c);
+        assert_eq!(everywhere, result(&[2]));
+        assert_eq!(with_a.union(&with_b), result(&[1, 2, 3, 4]));
+        assert_eq!(with_a.difference(&with_b), result(&[1]));
+        assert_eq!(with_c.difference(&with_c), result(&[]));
+    }
+
     #[test]
     fn test_double_blinded_points_message_new() {
         let double_blinded_points = vec![CompressedRistretto([0u8; 32])];
@@ -198,10 +678,84 @@ mod tests {
         assert!(!msg.is_empty());
     }
 
+    #[test]
+    fn test_one_round_response_message_new() {
+        let blinded = vec![CompressedRistretto([1u8; 32])];
+        let double_blinded = vec![CompressedRistretto([2u8; 32]); 2];
+        let msg = OneRoundResponseMessage::new(blinded.clone(), double_blinded.clone());
+        assert_eq!(msg.blinded_points, blinded);
+        assert_eq!(msg.double_blinded_points, double_blinded);
+    }
+
     #[test]
     fn test_double_blinded_points_message_empty() {
         let msg = DoubleBlindedPointsMessage::new(vec![]);
         assert_eq!(msg.len(), 0);
         assert!(msg.is_empty());
     }
+
+    #[test]
+    fn test_psi_result_match_items() {
+        let items = vec![b"apple".to_vec(), b"banana".to_vec(), b"cherry".to_vec()];
+        let result = PsiResult::new(
+            vec![ItemId::of(b"cherry"), ItemId::of(b"apple")],
+            HashMap::new(),
+        );
+        assert_eq!(result.match_indices(&items), vec![0, 2]);
+        assert_eq!(
+            result.match_items(&items),
+            vec![b"apple".as_slice(), b"cherry".as_slice()]
+        );
+        assert!(PsiResult::new(vec![], HashMap::new())
+            .match_items(&items)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_psi_result_prioritized_matches() {
+        let items = vec![
+            (b"apple".to_vec(), 1),
+            (b"banana".to_vec(), 5),
+            (b"cherry".to_vec(), 1),
+            (b"date".to_vec(), 9),
+        ];
+        let mut result = PsiResult::new(
+            vec![
+                ItemId::of(b"apple"),
+                ItemId::of(b"banana"),
+                ItemId::of(b"cherry"),
+            ],
+            HashMap::new(),
+        );
+        assert_eq!(
+            result.prioritized_matches(&items),
+            vec![
+                (b"banana".as_slice(), 5),
+                (b"apple".as_slice(), 1),
+                (b"cherry".as_slice(), 1)
+            ]
+        );
+
+        let priorities: HashMap<ItemId, i32> = items
+            .iter()
+            .map(|(item, priority)| (ItemId::of(item), *priority))
+            .collect();
+        result.sort_by_priority(|id| priorities[id]);
+        assert_eq!(result.intersection_hashes[0], ItemId::of(b"banana"));
+        assert_eq!(result.intersection_hashes[1], ItemId::of(b"apple"));
+    }
+
+    #[test]
+    fn test_cardinality_message_check() {
+        let msg = BlindedPointsMessage::new(vec![CompressedRistretto([0u8; 32]); 3]);
+        assert!(CardinalityMessage::new(3).check(&msg).is_ok());
+        assert_eq!(
+            CardinalityMessage::new(2).check(&msg),
+            Err(PsiError::LengthMismatch {
+                phase: Phase::Compute,
+                expected: 2,
+                actual: 3
+            })
+        );
+    }
 }
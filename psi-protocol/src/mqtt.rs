@@ -0,0 +1,391 @@
+//! One-round exchanges over MQTT 5 request/response.
+//!
+//! Fleets of devices that already speak MQTT can reconcile a set (device
+//! ids, firmware hashes) with a backend without a direct connection to it.
+//! Each device runs the [one-round](PsiProtocol::respond_one_round)
+//! variant as an MQTT 5 request/response pair:
+//!
+//! ```text
+//! device                                          backend
+//!   |-- publish on <prefix>/request ------------------>|
+//!   |     payload: blinded points                      |
+//!   |     response topic: <prefix>/response/<client>   |
+//!   |     correlation data: 16 random bytes            |
+//!   |                                                  |
+//!   |<---------- publish on <prefix>/response/<client> |
+//!   |     payload: one-round response                  |
+//!   |     correlation data: same 16 bytes              |
+//! ```
+//!
+//! Payloads are [`wire`] frames. The correlation data is drawn per
+//! [`MqttRequester`], so a device ignores responses meant for an earlier
+//! request. The backend keeps no state between requests: [`MqttResponder`]
+//! answers every device from the same prepared set. Only the device learns
+//! the intersection.
+//!
+//! The adapter does no I/O and depends on no MQTT client: hand it each
+//! publish received on the subscribed topic as an [`MqttPublish`], and
+//! publish the ones it returns, mapping `response_topic` and
+//! `correlation_data` to the MQTT 5 properties of the same name.
+//!
+//! Requires the `mqtt` feature.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::mqtt::{MqttRequester, MqttResponder, MqttTopics};
+//!
+//! let topics = MqttTopics::new("fleet/psi")?;
+//!
+//! // Backend, subscribed to `topics.request()`
+//! let responder = MqttResponder::new(PsiProtocol::new(&known_firmware)?, &topics);
+//! let reply = match responder.on_publish(&publish) {
+//!     Ok(reply) => reply,
+//!     Err(error) => responder.error_reply(&publish, &error),
+//! };
+//! if let Some(reply) = reply {
+//!     client.publish(reply)?;
+//! }
+//!
+//! // Device, subscribed to `requester.response_topic()`
+//! let mut requester = MqttRequester::new(PsiProtocol::new(&installed)?, &topics, "device-42")?;
+//! client.publish(requester.request())?;
+//! let result = loop {
+//!     if let Some(result) = requester.on_publish(&client.next()?)? {
+//!         break result;
+//!     }
+//! };
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::error::{AbortReason, PsiError, Result};
+use crate::messages::{AbortMessage, ErrorReportMessage, PsiResult};
+use crate::protocol::PsiProtocol;
+use crate::state::PreparedState;
+use crate::wire::{self, WireMessage};
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+/// Length of the correlation data of a request in bytes.
+pub const CORRELATION_LEN: usize = 16;
+
+/// An MQTT publish as the adapter reads and writes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttPublish {
+    /// Topic the message is published on.
+    pub topic: String,
+    /// MQTT 5 response topic property; set on requests only.
+    pub response_topic: Option<String>,
+    /// MQTT 5 correlation data property.
+    pub correlation_data: Vec<u8>,
+    /// A [`wire`] frame.
+    pub payload: Vec<u8>,
+}
+
+/// Topic layout shared by a backend and its devices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttTopics {
+    prefix: String,
+}
+
+impl MqttTopics {
+    /// Topics under `prefix`, e.g. `fleet/psi`.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `prefix` is empty or holds a
+    /// wildcard
+    pub fn new(prefix: &str) -> Result<Self> {
+        if prefix.is_empty() || prefix.contains(['+', '#']) {
+            return Err(PsiError::InvalidConfig(format!(
+                "Invalid MQTT topic prefix {:?}",
+                prefix
+            )));
+        }
+        Ok(Self {
+            prefix: prefix.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// Topic devices publish their requests on.
+    pub fn request(&self) -> String {
+        format!("{}/request", self.prefix)
+    }
+
+    /// Topic the backend answers `client_id` on.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `client_id` is empty or holds a
+    /// wildcard or a level separator
+    pub fn response(&self, client_id: &str) -> Result<String> {
+        if client_id.is_empty() || client_id.contains(['+', '#', '/']) {
+            return Err(PsiError::InvalidConfig(format!(
+                "Invalid MQTT client id {:?}",
+                client_id
+            )));
+        }
+        Ok(format!("{}/response/{}", self.prefix, client_id))
+    }
+}
+
+impl Default for MqttTopics {
+    fn default() -> Self {
+        Self {
+            prefix: "psi".to_string(),
+        }
+    }
+}
+
+/// Device side: one request and the intersection from its response.
+pub struct MqttRequester {
+    protocol: Option<PsiProtocol<PreparedState>>,
+    request: MqttPublish,
+}
+
+impl MqttRequester {
+    /// Prepare a request of `protocol`'s set, answered on the response
+    /// topic of `client_id`.
+    ///
+    /// # Errors
+    /// Same as [`MqttTopics::response`]
+    pub fn new(
+        protocol: PsiProtocol<PreparedState>,
+        topics: &MqttTopics,
+        client_id: &str,
+    ) -> Result<Self> {
+        let mut correlation = vec![0u8; CORRELATION_LEN];
+        OsRng.fill_bytes(&mut correlation);
+        let request = MqttPublish {
+            topic: topics.request(),
+            response_topic: Some(topics.response(client_id)?),
+            correlation_data: correlation,
+            payload: protocol.message().to_bytes(),
+        };
+        Ok(Self {
+            protocol: Some(protocol),
+            request,
+        })
+    }
+
+    /// The request to publish; the same one every call, e.g. to resend it
+    /// after a timeout.
+    pub fn request(&self) -> MqttPublish {
+        self.request.clone()
+    }
+
+    /// Topic to subscribe to before publishing the request.
+    pub fn response_topic(&self) -> &str {
+        self.request
+            .response_topic
+            .as_deref()
+            .expect("requests carry a response topic")
+    }
+
+    /// Handle a publish received on the response topic.
+    ///
+    /// Returns the intersection for the response to our request, `None`
+    /// for any other publish, such as a response to an earlier request.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` once a response was handled,
+    /// `PsiError::Rejected` or `PsiError::Aborted` if the backend refused
+    /// the request, `PsiError::InvalidEncoding` for a payload that is not a
+    /// one-round response, plus the errors of
+    /// [`PsiProtocol::finalize_one_round`]
+    pub fn on_publish(&mut self, publish: &MqttPublish) -> Result<Option<PsiResult>> {
+        if publish.topic != self.response_topic()
+            || publish.correlation_data != self.request.correlation_data
+        {
+            return Ok(None);
+        }
+        let msg = wire::decode(&publish.payload)?;
+        let protocol = self.protocol.take().ok_or(PsiError::UnexpectedState {
+            operation: "on_publish",
+            state: "final",
+        })?;
+        match msg {
+            WireMessage::OneRoundResponse(response) => protocol
+                .finalize_one_round(response)
+                .map(|(_, result)| Some(result)),
+            WireMessage::Abort(msg) => Err(msg.into_error()),
+            WireMessage::ErrorReport(report) => Err(report.into_error()),
+            other => Err(PsiError::InvalidEncoding(format!(
+                "Expected a one-round response, found {:?}",
+                other.kind()
+            ))),
+        }
+    }
+}
+
+/// Backend side: answers every request from one prepared set.
+pub struct MqttResponder {
+    protocol: PsiProtocol<PreparedState>,
+    request_topic: String,
+}
+
+impl MqttResponder {
+    /// Answer requests published on `topics`' request topic with
+    /// `protocol`'s set.
+    pub fn new(protocol: PsiProtocol<PreparedState>, topics: &MqttTopics) -> Self {
+        Self {
+            protocol,
+            request_topic: topics.request(),
+        }
+    }
+
+    /// Topic to subscribe to.
+    pub fn request_topic(&self) -> &str {
+        &self.request_topic
+    }
+
+    /// Handle a publish received on the request topic.
+    ///
+    /// Returns the response to publish, or `None` for a publish that needs
+    /// no answer: another topic, or a device giving up on its request.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` for a request without a response
+    /// topic or correlation data, or whose payload is not blinded points,
+    /// `PsiError::LimitExceeded` for a set larger than the configured
+    /// remote limit, plus the errors of [`PsiProtocol::respond_one_round`]
+    pub fn on_publish(&self, publish: &MqttPublish) -> Result<Option<MqttPublish>> {
+        if publish.topic != self.request_topic {
+            return Ok(None);
+        }
+        check_request(publish)?;
+        let msg = match wire::decode(&publish.payload)? {
+            WireMessage::Blinded(msg) => msg,
+            WireMessage::Abort(_) => return Ok(None),
+            other => {
+                return Err(PsiError::InvalidEncoding(format!(
+                    "Expected blinded points, found {:?}",
+                    other.kind()
+                )))
+            }
+        };
+        self.protocol.config().check_remote_len(msg.len())?;
+        let response = self.protocol.respond_one_round(msg)?;
+        Ok(reply(publish, &WireMessage::OneRoundResponse(response)))
+    }
+
+    /// The response telling the device why its request failed with `error`.
+    ///
+    /// Carries an error report when the error has a wire code, an abort
+    /// otherwise. Returns `None` if the request cannot be answered.
+    pub fn error_reply(&self, request: &MqttPublish, error: &PsiError) -> Option<MqttPublish> {
+        check_request(request).ok()?;
+        let msg = match ErrorReportMessage::for_error(error) {
+            Some(report) => WireMessage::ErrorReport(report),
+            None => WireMessage::Abort(AbortMessage::new(AbortReason::for_error(error))),
+        };
+        reply(request, &msg)
+    }
+}
+
+/// Check that `request` says where and how to answer it.
+fn check_request(request: &MqttPublish) -> Result<()> {
+    if request.response_topic.is_none() {
+        return Err(PsiError::InvalidEncoding(
+            "MQTT request without a response topic".to_string(),
+        ));
+    }
+    if request.correlation_data.len() != CORRELATION_LEN {
+        return Err(PsiError::InvalidEncoding(format!(
+            "MQTT correlation data of {} bytes, expected {}",
+            request.correlation_data.len(),
+            CORRELATION_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// The publish answering `request` with `msg`.
+fn reply(request: &MqttPublish, msg: &WireMessage) -> Option<MqttPublish> {
+    Some(MqttPublish {
+        topic: request.response_topic.clone()?,
+        response_topic: None,
+        correlation_data: request.correlation_data.clone(),
+        payload: wire::encode(msg),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PsiConfig;
+    use crate::test_util::items;
+
+    #[test]
+    fn test_request_response() {
+        let topics = MqttTopics::new("fleet/psi/").unwrap();
+        let responder = MqttResponder::new(
+            PsiProtocol::new(&items(&["fw-1", "fw-2", "fw-3"])).unwrap(),
+            &topics,
+        );
+        let mut requester = MqttRequester::new(
+            PsiProtocol::new(&items(&["fw-2", "fw-9"])).unwrap(),
+            &topics,
+            "device-42",
+        )
+        .unwrap();
+        assert_eq!(responder.request_topic(), "fleet/psi/request");
+        assert_eq!(requester.response_topic(), "fleet/psi/response/device-42");
+
+        let request = requester.request();
+        let response = responder.on_publish(&request).unwrap().unwrap();
+        assert_eq!(response.topic, "fleet/psi/response/device-42");
+
+        // A response to another request is not ours
+        let stale = MqttPublish {
+            correlation_data: vec![0; CORRELATION_LEN],
+            ..response.clone()
+        };
+        assert_eq!(requester.on_publish(&stale).unwrap(), None);
+
+        let result = requester.on_publish(&response).unwrap().unwrap();
+        assert_eq!(result.match_items(&items(&["fw-2", "fw-9"])), vec![b"fw-2"]);
+        assert!(requester.on_publish(&response).is_err());
+    }
+
+    #[test]
+    fn test_rejected_request_reaches_the_device() {
+        let topics = MqttTopics::default();
+        let config = PsiConfig::builder().max_remote_items(1).build().unwrap();
+        let responder = MqttResponder::new(
+            PsiProtocol::new_with_config(&items(&["a"]), config).unwrap(),
+            &topics,
+        );
+        let mut requester = MqttRequester::new(
+            PsiProtocol::new(&items(&["a", "b"])).unwrap(),
+            &topics,
+            "device-1",
+        )
+        .unwrap();
+
+        let request = requester.request();
+        let error = responder.on_publish(&request).unwrap_err();
+        let reply = responder.error_reply(&request, &error).unwrap();
+        assert!(matches!(
+            requester.on_publish(&reply),
+            Err(PsiError::Rejected(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_topics_and_requests() {
+        assert!(MqttTopics::new("").is_err());
+        assert!(MqttTopics::new("fleet/+").is_err());
+        let topics = MqttTopics::default();
+        assert!(topics.response("a/b").is_err());
+
+        let responder = MqttResponder::new(PsiProtocol::new(&items(&["a"])).unwrap(), &topics);
+        let requester =
+            MqttRequester::new(PsiProtocol::new(&items(&["a"])).unwrap(), &topics, "d").unwrap();
+        let mut request = requester.request();
+        request.response_topic = None;
+        let error = responder.on_publish(&request).unwrap_err();
+        assert!(matches!(error, PsiError::InvalidEncoding(_)));
+        assert_eq!(responder.error_reply(&request, &error), None);
+
+        request.topic = "elsewhere".to_string();
+        assert_eq!(responder.on_publish(&request).unwrap(), None);
+    }
+}
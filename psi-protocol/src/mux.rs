@@ -0,0 +1,529 @@
+//! Several sessions over one connection.
+//!
+//! Applications syncing more than one set with the same peer (one per
+//! namespace, tenant or table) would otherwise open one connection per
+//! session. [`SessionMux`] tags every [`wire`](crate::wire) frame with a
+//! session id:
+//!
+//! ```text
+//! +----------------------+------------+
+//! | session id (u32, BE) | wire frame |
+//! +----------------------+------------+
+//! ```
+//!
+//! and routes incoming frames to the matching [`PsiSession`], kept in a
+//! [`SessionManager`] so abandoned sessions still expire. It does no I/O:
+//! write the frames it returns to the connection, with whatever framing the
+//! transport uses (e.g. a length prefix on TCP, one message per QUIC
+//! stream), and feed it every frame read back.
+//!
+//! Both peers must agree on the id of each session, e.g. by deriving it
+//! from the namespace.
+//!
+//! Frames may arrive out of order: a peer's double-blinded frame that
+//! overtakes its blinded frame is held back until the blinded frame is
+//! handled. Frames delivered twice by at-least-once transports are handled like
+//! [`PsiSession`] handles repeated messages. Finished sessions are kept
+//! until their TTL runs out, so a late repeat of the last frame is still
+//! recognized as [`MuxEvent::Duplicate`] rather than an unknown session.
+
+use crate::error::{AbortReason, PsiError, Result};
+use crate::manager::SessionManager;
+use crate::messages::{DoubleBlindedPointsMessage, ErrorReportMessage, PsiResult};
+use crate::session::PsiSession;
+use crate::wire::{self, WireMessage};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Size of the session id prefix in bytes.
+pub const SESSION_ID_LEN: usize = 4;
+
+/// Prefix an encoded message with its session id.
+pub fn encode_frame(session: u32, msg: &WireMessage) -> Vec<u8> {
+    let mut out = session.to_be_bytes().to_vec();
+    out.extend_from_slice(&wire::encode(msg));
+    out
+}
+
+/// Split a multiplexed frame into its session id and message.
+///
+/// # Errors
+/// Returns `PsiError::InvalidEncoding` if the frame is too short for a
+/// session id, plus the errors of [`wire::decode`]
+pub fn decode_frame(bytes: &[u8]) -> Result<(u32, WireMessage)> {
+    if bytes.len() < SESSION_ID_LEN {
+        return Err(PsiError::InvalidEncoding(format!(
+            "Frame too short for a session id: {} bytes",
+            bytes.len()
+        )));
+    }
+    let (id, rest) = bytes.split_at(SESSION_ID_LEN);
+    let session = u32::from_be_bytes([id[0], id[1], id[2], id[3]]);
+    Ok((session, wire::decode(rest)?))
+}
+
+/// Outcome of handling one incoming frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MuxEvent {
+    /// Frame to write back to the connection.
+    Reply(Vec<u8>),
+    /// A session finished; it has been removed from the mux.
+    Complete {
+        /// Id of the finished session
+        session: u32,
+        /// Its intersection
+        result: PsiResult,
+    },
+    /// The peer aborted a session; it has been removed from the mux.
+    Aborted {
+        /// Id of the aborted session
+        session: u32,
+        /// `PsiError::Aborted` with the peer's reason
+        error: PsiError,
+    },
+    /// The peer rejected our last message for a session; it stays open.
+    Rejected {
+        /// Id of the session
+        session: u32,
+        /// `PsiError::Rejected` with the peer's report
+        error: PsiError,
+    },
+    /// A repeat of a frame that was already handled; nothing to do.
+    Duplicate {
+        /// Id of the session
+        session: u32,
+    },
+    /// A held-back frame failed once its session could handle it; the
+    /// session stays open, as after an error from
+    /// [`on_frame`](SessionMux::on_frame).
+    Failed {
+        /// Id of the session
+        session: u32,
+        /// Why the frame was refused
+        error: PsiError,
+    },
+}
+
+/// Routes multiplexed frames to per-session state.
+///
+/// # Example
+/// ```ignore
+/// use psi_protocol::{PsiSession, SessionMux, MuxEvent};
+///
+/// let mut mux = SessionMux::new(Duration::from_secs(30));
+/// connection.send(mux.open(1, PsiSession::new(&users)?)?);
+/// connection.send(mux.open(2, PsiSession::new(&groups)?)?);
+///
+/// while !mux.is_empty() {
+///     for event in mux.on_frame(&connection.recv())? {
+///         match event {
+///             MuxEvent::Reply(frame) => connection.send(frame),
+///             MuxEvent::Complete { session, result } => store(session, result),
+///             MuxEvent::Aborted { session, error }
+///             | MuxEvent::Rejected { session, error }
+///             | MuxEvent::Failed { session, error } => log(session, error),
+///             MuxEvent::Duplicate { .. } => {}
+///         }
+///     }
+/// }
+/// # Ok::<(), psi_protocol::PsiError>(())
+/// ```
+#[derive(Debug)]
+pub struct SessionMux {
+    sessions: SessionManager<u32>,
+    /// Completed sessions, kept to recognize repeated frames
+    finished: SessionManager<u32>,
+    /// Double-blinded frames that arrived before their blinded frame
+    early: HashMap<u32, DoubleBlindedPointsMessage>,
+}
+
+impl SessionMux {
+    /// Create an empty mux giving each session `ttl` to complete.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            sessions: SessionManager::new(ttl),
+            finished: SessionManager::new(ttl),
+            early: HashMap::new(),
+        }
+    }
+
+    /// Register a prepared session and return its first frame.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if a live session already uses
+    /// `id`, or `PsiError::UnexpectedState` if `session` is not prepared
+    pub fn open(&mut self, id: u32, session: PsiSession) -> Result<Vec<u8>> {
+        if self.sessions.get(&id).is_some() {
+            return Err(PsiError::InvalidConfig(format!(
+                "Session {} is already open",
+                id
+            )));
+        }
+        let frame = encode_frame(id, &WireMessage::Blinded(session.message()?));
+        self.finished.remove(&id);
+        self.sessions.insert(id, session);
+        Ok(frame)
+    }
+
+    /// Handle a frame read from the connection.
+    ///
+    /// Returns what to do about it, usually one event. A peer's
+    /// double-blinded frame that overtakes its blinded frame, as on
+    /// transports that do not keep frames in order, is held back and
+    /// returns no event; once the blinded frame arrives it yields both the
+    /// reply and the completion.
+    ///
+    /// A session that fails keeps its state, as with [`PsiSession`], so the
+    /// caller may wait for a resent frame, [`close`](Self::close) it or
+    /// [`abort`](Self::abort) it. A peer's abort frame removes its session;
+    /// its error report leaves the session as is.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` if no live session has the
+    /// frame's id or the message kind is not part of the two-round flow,
+    /// plus the errors of [`decode_frame`], [`PsiSession::on_blinded`] and
+    /// [`PsiSession::on_double_blinded`]. A held-back frame that fails is
+    /// reported as [`MuxEvent::Failed`] instead, after the reply.
+    pub fn on_frame(&mut self, bytes: &[u8]) -> Result<Vec<MuxEvent>> {
+        let (id, msg) = decode_frame(bytes)?;
+        if self.sessions.get(&id).is_none() && self.is_late_duplicate(id, &msg) {
+            return Ok(vec![MuxEvent::Duplicate { session: id }]);
+        }
+        if let WireMessage::Abort(msg) = msg {
+            let mut session = self.sessions.remove(&id).ok_or(PsiError::UnexpectedState {
+                operation: "on_frame",
+                state: "unknown session",
+            })?;
+            self.early.remove(&id);
+            return Ok(vec![MuxEvent::Aborted {
+                session: id,
+                error: session.on_abort(msg),
+            }]);
+        }
+        let session = self
+            .sessions
+            .get_mut(&id)
+            .ok_or(PsiError::UnexpectedState {
+                operation: "on_frame",
+                state: "unknown session",
+            })?;
+        match msg {
+            WireMessage::ErrorReport(msg) => Ok(vec![MuxEvent::Rejected {
+                session: id,
+                error: msg.into_error(),
+            }]),
+            WireMessage::Blinded(msg) => {
+                let reply = session.on_blinded(msg)?;
+                self.sessions.touch(&id);
+                let mut events = vec![MuxEvent::Reply(encode_frame(
+                    id,
+                    &WireMessage::DoubleBlinded(reply),
+                ))];
+                if let Some(early) = self.early.remove(&id) {
+                    events.push(
+                        self.on_double_blinded(id, early)
+                            .unwrap_or_else(|error| MuxEvent::Failed { session: id, error }),
+                    );
+                }
+                Ok(events)
+            }
+            WireMessage::DoubleBlinded(msg) if matches!(session, PsiSession::Prepared(_)) => {
+                // The peer's blinded frame is still on its way
+                self.early.insert(id, msg);
+                Ok(Vec::new())
+            }
+            WireMessage::DoubleBlinded(msg) => Ok(vec![self.on_double_blinded(id, msg)?]),
+            _ => Err(PsiError::UnexpectedState {
+                operation: "on_frame",
+                state: session.state_name(),
+            }),
+        }
+    }
+
+    /// Finish session `id` with the peer's double-blinded message.
+    fn on_double_blinded(&mut self, id: u32, msg: DoubleBlindedPointsMessage) -> Result<MuxEvent> {
+        let session = self
+            .sessions
+            .get_mut(&id)
+            .ok_or(PsiError::UnexpectedState {
+                operation: "on_frame",
+                state: "unknown session",
+            })?;
+        let result = session.on_double_blinded(msg)?;
+        if let Some(done) = self.sessions.remove(&id) {
+            self.finished.insert(id, done);
+        }
+        Ok(MuxEvent::Complete {
+            session: id,
+            result,
+        })
+    }
+
+    /// Returns true if `msg` repeats the last frame of a completed session.
+    fn is_late_duplicate(&self, id: u32, msg: &WireMessage) -> bool {
+        let Some(PsiSession::Final(done)) = self.finished.get(&id) else {
+            return false;
+        };
+        match msg {
+            WireMessage::Blinded(msg) => done.handled_blinded(msg),
+            WireMessage::DoubleBlinded(msg) => done.handled_double_blinded(msg),
+            _ => false,
+        }
+    }
+
+    /// Drop a session, e.g. after the peer reported an error for it.
+    pub fn close(&mut self, id: u32) -> Option<PsiSession> {
+        self.finished.remove(&id);
+        self.early.remove(&id);
+        self.sessions.remove(&id)
+    }
+
+    /// Drop a live session and return the abort frame telling the peer.
+    pub fn abort(&mut self, id: u32, reason: AbortReason) -> Option<Vec<u8>> {
+        self.early.remove(&id);
+        self.sessions
+            .abort(&id, reason)
+            .map(|msg| encode_frame(id, &WireMessage::Abort(msg)))
+    }
+
+    /// Frame telling the peer why its message for session `id` was rejected.
+    ///
+    /// Returns `None` for errors that describe no fault of the peer's
+    /// message, see [`ErrorReportMessage::for_error`].
+    pub fn report(id: u32, error: &PsiError) -> Option<Vec<u8>> {
+        ErrorReportMessage::for_error(error)
+            .map(|msg| encode_frame(id, &WireMessage::ErrorReport(msg)))
+    }
+
+    /// Number of open sessions, including expired ones not yet swept.
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Returns true if no session is open.
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Drop every expired session; returns how many open ones were dropped.
+    pub fn sweep(&mut self) -> usize {
+        self.finished.sweep();
+        let dropped = self.sessions.sweep();
+        let sessions = &self.sessions;
+        self.early.retain(|id, _| sessions.deadline(id).is_some());
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{ErrorReport, Limit};
+    use crate::item_id::ItemId;
+    use crate::messages::CardinalityMessage;
+    use crate::test_util::session;
+
+    /// Handle every frame in `inbox`, queueing replies in `outbox`.
+    fn pump(
+        mux: &mut SessionMux,
+        inbox: &mut Vec<Vec<u8>>,
+        outbox: &mut Vec<Vec<u8>>,
+        results: &mut Vec<(u32, Vec<ItemId>)>,
+    ) {
+        for frame in std::mem::take(inbox) {
+            for event in mux.on_frame(&frame).unwrap() {
+                match event {
+                    MuxEvent::Reply(reply) => outbox.push(reply),
+                    MuxEvent::Complete { session, result } => {
+                        results.push((session, result.intersection_hashes))
+                    }
+                    MuxEvent::Aborted { session, error }
+                    | MuxEvent::Rejected { session, error }
+                    | MuxEvent::Failed { session, error } => {
+                        panic!("{} failed: {}", session, error)
+                    }
+                    MuxEvent::Duplicate { session } => panic!("{} repeated a frame", session),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_interleaved_sessions() {
+        let mut alice = SessionMux::new(Duration::from_secs(60));
+        let mut bob = SessionMux::new(Duration::from_secs(60));
+        let mut to_bob = vec![
+            alice.open(1, session(&["apple", "banana"])).unwrap(),
+            alice.open(2, session(&["red"])).unwrap(),
+        ];
+        let mut to_alice = vec![
+            bob.open(2, session(&["red", "blue"])).unwrap(),
+            bob.open(1, session(&["banana"])).unwrap(),
+        ];
+
+        let mut results = Vec::new();
+        while !to_bob.is_empty() || !to_alice.is_empty() {
+            pump(&mut alice, &mut to_alice, &mut to_bob, &mut results);
+            pump(&mut bob, &mut to_bob, &mut to_alice, &mut results);
+        }
+        results.sort();
+        assert_eq!(
+            results,
+            vec![
+                (1, vec![ItemId::of(b"banana")]),
+                (1, vec![ItemId::of(b"banana")]),
+                (2, vec![ItemId::of(b"red")]),
+                (2, vec![ItemId::of(b"red")]),
+            ]
+        );
+        assert!(alice.is_empty() && bob.is_empty());
+    }
+
+    #[test]
+    fn test_duplicated_frames() {
+        let mut alice = SessionMux::new(Duration::from_secs(60));
+        let mut bob = SessionMux::new(Duration::from_secs(60));
+        let alice_blinded = alice.open(1, session(&["apple", "banana"])).unwrap();
+        let bob_blinded = bob.open(1, session(&["banana"])).unwrap();
+
+        // Every frame is delivered twice; repeated blinded frames are
+        // answered again, the answers' repeats are recognized
+        let mut replies = Vec::new();
+        for _ in 0..2 {
+            for event in [
+                alice.on_frame(&bob_blinded).unwrap(),
+                bob.on_frame(&alice_blinded).unwrap(),
+            ]
+            .into_iter()
+            .flatten()
+            {
+                let MuxEvent::Reply(frame) = event else {
+                    panic!("expected a reply, got {:?}", event);
+                };
+                replies.push(frame);
+            }
+        }
+        assert_eq!(replies[0], replies[2]);
+
+        let mut completed = 0;
+        let mut duplicates = 0;
+        for (index, frame) in replies.iter().enumerate() {
+            let mux = if index % 2 == 0 { &mut bob } else { &mut alice };
+            match mux.on_frame(frame).unwrap().remove(0) {
+                MuxEvent::Complete { result, .. } => {
+                    assert_eq!(result.intersection_hashes, vec![ItemId::of(b"banana")]);
+                    completed += 1;
+                }
+                MuxEvent::Duplicate { session } => {
+                    assert_eq!(session, 1);
+                    duplicates += 1;
+                }
+                event => panic!("unexpected {:?}", event),
+            }
+        }
+        assert_eq!((completed, duplicates), (2, 2));
+        assert!(alice.is_empty() && bob.is_empty());
+        assert_eq!(
+            alice.on_frame(&bob_blinded).unwrap(),
+            vec![MuxEvent::Duplicate { session: 1 }]
+        );
+    }
+
+    #[test]
+    fn test_double_blinded_frame_overtaking_blinded_frame() {
+        let mut alice = SessionMux::new(Duration::from_secs(60));
+        let mut bob = SessionMux::new(Duration::from_secs(60));
+        let alice_blinded = alice.open(1, session(&["apple", "banana"])).unwrap();
+        let bob_blinded = bob.open(1, session(&["banana"])).unwrap();
+
+        // Bob answers, and his answer reaches Alice before his blinded frame
+        let [MuxEvent::Reply(bob_double)] = &bob.on_frame(&alice_blinded).unwrap()[..] else {
+            panic!("expected a reply");
+        };
+        assert_eq!(alice.on_frame(bob_double).unwrap(), vec![]);
+
+        let events = alice.on_frame(&bob_blinded).unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], MuxEvent::Reply(_)));
+        let MuxEvent::Complete { session, result } = &events[1] else {
+            panic!("expected a completion, got {:?}", events[1]);
+        };
+        assert_eq!(*session, 1);
+        assert_eq!(result.intersection_hashes, vec![ItemId::of(b"banana")]);
+        assert!(alice.is_empty());
+    }
+
+    #[test]
+    fn test_abort_one_session() {
+        let mut alice = SessionMux::new(Duration::from_secs(60));
+        let mut bob = SessionMux::new(Duration::from_secs(60));
+        alice.open(1, session(&["apple"])).unwrap();
+        let keep = alice.open(2, session(&["red"])).unwrap();
+        bob.open(1, session(&["apple"])).unwrap();
+
+        let frame = bob.abort(1, AbortReason::PolicyViolation).unwrap();
+        assert!(bob.abort(1, AbortReason::PolicyViolation).is_none());
+        assert_eq!(
+            alice.on_frame(&frame).unwrap(),
+            vec![MuxEvent::Aborted {
+                session: 1,
+                error: PsiError::Aborted(AbortReason::PolicyViolation)
+            }]
+        );
+        assert_eq!(alice.len(), 1);
+        assert!(bob.on_frame(&keep).is_err());
+    }
+
+    #[test]
+    fn test_error_report_keeps_the_session() {
+        let mut alice = SessionMux::new(Duration::from_secs(60));
+        alice.open(1, session(&["apple"])).unwrap();
+
+        let error = PsiError::LimitExceeded {
+            limit: Limit::RemotePoints,
+            max: 1,
+            actual: 2,
+        };
+        let frame = SessionMux::report(1, &error).unwrap();
+        assert_eq!(
+            alice.on_frame(&frame).unwrap(),
+            vec![MuxEvent::Rejected {
+                session: 1,
+                error: PsiError::Rejected(ErrorReport::LimitExceeded {
+                    limit: Limit::RemotePoints,
+                    max: 1,
+                    actual: 2
+                })
+            }]
+        );
+        assert_eq!(alice.len(), 1);
+        assert!(SessionMux::report(1, &PsiError::EmptyInput).is_none());
+    }
+
+    #[test]
+    fn test_rejects_unknown_sessions_and_frames() {
+        let mut mux = SessionMux::new(Duration::from_secs(60));
+        let frame = mux.open(1, session(&["apple"])).unwrap();
+        assert!(matches!(
+            mux.open(1, session(&["apple"])),
+            Err(PsiError::InvalidConfig(_))
+        ));
+
+        let mut unknown = frame.clone();
+        unknown[..SESSION_ID_LEN].copy_from_slice(&9u32.to_be_bytes());
+        assert!(matches!(
+            mux.on_frame(&unknown),
+            Err(PsiError::UnexpectedState { .. })
+        ));
+        let cardinality = encode_frame(1, &WireMessage::Cardinality(CardinalityMessage::new(1)));
+        assert!(matches!(
+            mux.on_frame(&cardinality),
+            Err(PsiError::UnexpectedState { .. })
+        ));
+        assert!(matches!(
+            mux.on_frame(&frame[..2]),
+            Err(PsiError::InvalidEncoding(_))
+        ));
+        assert!(mux.close(1).is_some());
+    }
+}
@@ -0,0 +1,404 @@
+//! Two-round exchanges over NATS request-reply.
+//!
+//! Services that reach each other only through a NATS bus run a full
+//! [`PsiSession`] as two requests on a subject of its own,
+//! `<prefix>.<session id>`:
+//!
+//! ```text
+//! requester                                 responder
+//!   |-- request: blinded points ----------------->|
+//!   |<---------------- reply: its blinded points -|
+//!   |-- request: double-blinded points ---------->|
+//!   |<--------- reply: its double-blinded points -|
+//! ```
+//!
+//! Both sides learn the intersection. The responder subscribes once to
+//! `<prefix>.*` and keeps each session in a [`SessionManager`] until its
+//! second request, so sessions of requesters that disappear expire after
+//! the configured time to live. Each session starts from a
+//! [rerandomized](PsiProtocol::rerandomize) copy of the responder's
+//! prepared set, so sessions never share a secret.
+//!
+//! The adapter does no I/O and depends on no NATS client: the requester
+//! sends [`request`](NatsRequester::request) with the client's request
+//! call and feeds the reply payload to
+//! [`on_reply`](NatsRequester::on_reply); the responder hands each received
+//! message to [`on_request`](NatsResponder::on_request) as a
+//! [`NatsMessage`] and publishes the reply. Payloads are [`wire`] frames.
+//! Core NATS delivers at most once, so a request that timed out is not
+//! retried: start a new session instead.
+//!
+//! Requires the `nats` feature.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::nats::{NatsMessage, NatsRequester, NatsResponder, NatsSubjects};
+//!
+//! let subjects = NatsSubjects::new("psi.inventory")?;
+//!
+//! // Responder, subscribed to `responder.subscription()`
+//! let mut responder = NatsResponder::new(PsiProtocol::new(&ours)?, subjects.clone(), ttl);
+//! let reply = match responder.on_request(&request) {
+//!     Ok(reply) => {
+//!         if let Some(result) = reply.result { record(result) }
+//!         Some(reply.message)
+//!     }
+//!     Err(error) => responder.error_reply(&request, &error),
+//! };
+//!
+//! // Requester
+//! let mut requester = NatsRequester::new(PsiProtocol::new(&theirs)?, &subjects);
+//! let result = loop {
+//!     let reply = client.request(requester.subject(), requester.request()?)?;
+//!     if let Some(result) = requester.on_reply(&reply.payload)? {
+//!         break result;
+//!     }
+//! };
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::error::{AbortReason, Phase, PsiError, Result};
+use crate::manager::SessionManager;
+use crate::messages::{AbortMessage, ErrorReportMessage, PsiResult};
+use crate::protocol::PsiProtocol;
+use crate::session::PsiSession;
+use crate::state::PreparedState;
+use crate::wire::{self, WireMessage};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::time::Duration;
+
+/// Random bytes of a session id, hex-encoded in its subject.
+const SESSION_ID_LEN: usize = 16;
+
+/// A NATS message as the adapter reads and writes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NatsMessage {
+    /// Subject the message is published on.
+    pub subject: String,
+    /// Reply subject; set on requests only.
+    pub reply: Option<String>,
+    /// A [`wire`] frame.
+    pub payload: Vec<u8>,
+}
+
+/// Subject layout shared by requesters and their responder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NatsSubjects {
+    prefix: String,
+}
+
+impl NatsSubjects {
+    /// Subjects under `prefix`, e.g. `psi.inventory`.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `prefix` has an empty token, a
+    /// wildcard or whitespace
+    pub fn new(prefix: &str) -> Result<Self> {
+        let valid = prefix.split('.').all(|token| {
+            !token.is_empty()
+                && !token.contains(|c: char| c == '*' || c == '>' || c.is_whitespace())
+        });
+        if !valid {
+            return Err(PsiError::InvalidConfig(format!(
+                "Invalid NATS subject prefix {:?}",
+                prefix
+            )));
+        }
+        Ok(Self {
+            prefix: prefix.to_string(),
+        })
+    }
+
+    /// Subject of session `id`.
+    pub fn session(&self, id: &str) -> String {
+        format!("{}.{}", self.prefix, id)
+    }
+
+    /// Subscription matching every session subject.
+    pub fn wildcard(&self) -> String {
+        format!("{}.*", self.prefix)
+    }
+
+    /// Session id of `subject`, if it is a session subject.
+    pub fn session_id<'a>(&self, subject: &'a str) -> Option<&'a str> {
+        let id = subject.strip_prefix(&self.prefix)?.strip_prefix('.')?;
+        (!id.is_empty() && !id.contains('.')).then_some(id)
+    }
+}
+
+/// Requesting side of one session.
+pub struct NatsRequester {
+    session: PsiSession,
+    subject: String,
+}
+
+impl NatsRequester {
+    /// Start a session of `protocol`'s set on a fresh random subject.
+    pub fn new(protocol: PsiProtocol<PreparedState>, subjects: &NatsSubjects) -> Self {
+        let mut id = [0u8; SESSION_ID_LEN];
+        OsRng.fill_bytes(&mut id);
+        let id: String = id.iter().map(|byte| format!("{:02x}", byte)).collect();
+        Self {
+            session: PsiSession::from(protocol),
+            subject: subjects.session(&id),
+        }
+    }
+
+    /// Subject to send the requests to.
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    /// Payload of the next request.
+    ///
+    /// # Errors
+    /// Same as [`PsiSession::outgoing_message`]
+    pub fn request(&self) -> Result<Vec<u8>> {
+        self.session.outgoing_message()
+    }
+
+    /// Handle the reply to the last request.
+    ///
+    /// Returns the intersection after the second reply, `None` after the
+    /// first; then send the next [`request`](Self::request).
+    ///
+    /// # Errors
+    /// Same as [`PsiSession::handle_message`]
+    pub fn on_reply(&mut self, payload: &[u8]) -> Result<Option<PsiResult>> {
+        self.session.handle_message(payload)
+    }
+}
+
+/// Responding side of every session under a prefix.
+pub struct NatsResponder {
+    template: PsiProtocol<PreparedState>,
+    subjects: NatsSubjects,
+    sessions: SessionManager<String>,
+}
+
+/// What to publish for a request, see [`NatsResponder::on_request`].
+#[derive(Debug, Clone)]
+pub struct NatsReply {
+    /// The reply to publish.
+    pub message: NatsMessage,
+    /// The intersection, once the request completed the session.
+    pub result: Option<PsiResult>,
+}
+
+impl NatsResponder {
+    /// Answer sessions under `subjects` with `template`'s set, expiring
+    /// sessions whose second request does not arrive within `ttl`.
+    pub fn new(
+        template: PsiProtocol<PreparedState>,
+        subjects: NatsSubjects,
+        ttl: Duration,
+    ) -> Self {
+        Self {
+            template,
+            subjects,
+            sessions: SessionManager::new(ttl),
+        }
+    }
+
+    /// Subject to subscribe to.
+    pub fn subscription(&self) -> String {
+        self.subjects.wildcard()
+    }
+
+    /// Sessions waiting for their second request.
+    pub fn pending(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Drop expired sessions, returning how many were dropped.
+    pub fn sweep(&mut self) -> usize {
+        self.sessions.sweep()
+    }
+
+    /// Handle a request received on the subscription.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` for a message that is not a
+    /// request on a session subject or carries no protocol message,
+    /// `PsiError::DuplicateMessage` for a first request of a session already
+    /// under way, `PsiError::UnexpectedState` for a second request of an
+    /// unknown or expired session, `PsiError::Aborted` when the requester
+    /// aborts, plus the errors of [`PsiSession::on_blinded`] and
+    /// [`PsiSession::on_double_blinded`]
+    pub fn on_request(&mut self, request: &NatsMessage) -> Result<NatsReply> {
+        let (id, reply) = self.route(request)?;
+        match wire::decode(&request.payload)? {
+            WireMessage::Blinded(msg) => {
+                if self.sessions.get(&id).is_none() {
+                    let session = PsiSession::from(self.template.rerandomize()?);
+                    self.sessions.insert(id.clone(), session);
+                }
+                let session = self.sessions.get_mut(&id).expect("inserted above");
+                if !matches!(session, PsiSession::Prepared(_)) {
+                    return Err(PsiError::DuplicateMessage {
+                        phase: Phase::Compute,
+                    });
+                }
+                let ours = session.outgoing_message()?;
+                session.on_blinded(msg)?;
+                Ok(NatsReply {
+                    message: reply.with_payload(ours),
+                    result: None,
+                })
+            }
+            WireMessage::DoubleBlinded(msg) => {
+                let session = self
+                    .sessions
+                    .get_mut(&id)
+                    .ok_or(PsiError::UnexpectedState {
+                        operation: "on_request",
+                        state: "unknown session",
+                    })?;
+                let ours = session.outgoing_message()?;
+                let result = session.on_double_blinded(msg)?;
+                self.sessions.remove(&id);
+                Ok(NatsReply {
+                    message: reply.with_payload(ours),
+                    result: Some(result),
+                })
+            }
+            WireMessage::Abort(msg) => {
+                self.sessions.remove(&id);
+                Err(msg.into_error())
+            }
+            other => Err(PsiError::InvalidEncoding(format!(
+                "Expected a two-round message, found {:?}",
+                other.kind()
+            ))),
+        }
+    }
+
+    /// The reply telling the requester why its request failed with `error`.
+    ///
+    /// Carries an error report when the error has a wire code, an abort
+    /// otherwise. Returns `None` for a request that cannot be answered and
+    /// for the requester's own abort.
+    pub fn error_reply(&self, request: &NatsMessage, error: &PsiError) -> Option<NatsMessage> {
+        if matches!(error, PsiError::Aborted(_)) {
+            return None;
+        }
+        let (_, reply) = self.route(request).ok()?;
+        let msg = match ErrorReportMessage::for_error(error) {
+            Some(report) => WireMessage::ErrorReport(report),
+            None => WireMessage::Abort(AbortMessage::new(AbortReason::for_error(error))),
+        };
+        Some(reply.with_payload(wire::encode(&msg)))
+    }
+
+    /// Session id of `request` and an empty reply to it.
+    fn route(&self, request: &NatsMessage) -> Result<(String, NatsMessage)> {
+        let id = self.subjects.session_id(&request.subject).ok_or_else(|| {
+            PsiError::InvalidEncoding(format!("Not a session subject: {:?}", request.subject))
+        })?;
+        let reply = request.reply.clone().ok_or_else(|| {
+            PsiError::InvalidEncoding("NATS request without a reply subject".to_string())
+        })?;
+        Ok((
+            id.to_string(),
+            NatsMessage {
+                subject: reply,
+                reply: None,
+                payload: Vec::new(),
+            },
+        ))
+    }
+}
+
+impl NatsMessage {
+    fn with_payload(self, payload: Vec<u8>) -> Self {
+        Self { payload, ..self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::items;
+
+    fn request(requester: &NatsRequester) -> NatsMessage {
+        NatsMessage {
+            subject: requester.subject().to_string(),
+            reply: Some("_INBOX.test".to_string()),
+            payload: requester.request().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_request_reply_session() {
+        let subjects = NatsSubjects::new("psi.inventory").unwrap();
+        let mut responder = NatsResponder::new(
+            PsiProtocol::new(&items(&["a", "b", "c"])).unwrap(),
+            subjects.clone(),
+            Duration::from_secs(30),
+        );
+        let mut requester = NatsRequester::new(
+            PsiProtocol::new(&items(&["b", "c", "d"])).unwrap(),
+            &subjects,
+        );
+        assert_eq!(responder.subscription(), "psi.inventory.*");
+
+        let first = request(&requester);
+        let reply = responder.on_request(&first).unwrap();
+        assert_eq!(reply.message.subject, "_INBOX.test");
+        assert_eq!(reply.result, None);
+        assert_eq!(responder.pending(), 1);
+        assert!(responder.on_request(&first).unwrap_err().is_duplicate());
+        assert_eq!(requester.on_reply(&reply.message.payload).unwrap(), None);
+
+        let reply = responder.on_request(&request(&requester)).unwrap();
+        let theirs = reply.result.unwrap();
+        let ours = requester.on_reply(&reply.message.payload).unwrap().unwrap();
+        assert_eq!(ours.len(), 2);
+        let mut hashes = (ours.intersection_hashes, theirs.intersection_hashes);
+        hashes.0.sort();
+        hashes.1.sort();
+        assert_eq!(hashes.0, hashes.1);
+        assert_eq!(responder.pending(), 0);
+    }
+
+    #[test]
+    fn test_errors_reach_the_requester() {
+        let subjects = NatsSubjects::new("psi").unwrap();
+        let mut responder = NatsResponder::new(
+            PsiProtocol::new(&items(&["a"])).unwrap(),
+            subjects.clone(),
+            Duration::from_secs(30),
+        );
+        let mut requester =
+            NatsRequester::new(PsiProtocol::new(&items(&["a"])).unwrap(), &subjects);
+
+        let mut stray = request(&requester);
+        stray.reply = None;
+        // A second request for a session the responder does not know
+        let mut second = request(&requester);
+        second.payload = wire::encode(&WireMessage::DoubleBlinded(
+            crate::messages::DoubleBlindedPointsMessage::new(vec![]),
+        ));
+        let error = responder.on_request(&second).unwrap_err();
+        let reply = responder.error_reply(&second, &error).unwrap();
+        assert!(requester.on_reply(&reply.payload).is_err());
+        assert!(requester.request().is_err());
+
+        let error = responder.on_request(&stray).unwrap_err();
+        assert!(matches!(error, PsiError::InvalidEncoding(_)));
+        assert_eq!(responder.error_reply(&stray, &error), None);
+    }
+
+    #[test]
+    fn test_subjects() {
+        assert!(NatsSubjects::new("psi..x").is_err());
+        assert!(NatsSubjects::new("psi.>").is_err());
+        assert!(NatsSubjects::new("").is_err());
+        let subjects = NatsSubjects::new("psi.sync").unwrap();
+        assert_eq!(subjects.session_id("psi.sync.abc"), Some("abc"));
+        assert_eq!(subjects.session_id("psi.sync.a.b"), None);
+        assert_eq!(subjects.session_id("psi.syncx.abc"), None);
+        assert_eq!(subjects.session_id("psi.sync"), None);
+    }
+}
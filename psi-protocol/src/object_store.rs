@@ -0,0 +1,375 @@
+//! Batch exchanges through a shared object store.
+//!
+//! Enterprises that will not open a connection to each other can still
+//! agree on a bucket prefix (S3, GCS, a shared folder). Each party drops
+//! its messages under its own directory of that prefix and polls the
+//! other's, possibly hours apart:
+//!
+//! ```text
+//! <prefix>/<party>/manifest
+//! <prefix>/<party>/blinded
+//! <prefix>/<party>/double-blinded
+//! ```
+//!
+//! Message objects are [`wire`](crate::wire) frames. The manifest lists
+//! every message a party published with its length and SHA-256 digest, and
+//! is written after the message, so a message is only read once it is
+//! complete, and a truncated upload or an object left over from another
+//! run under the same prefix is refused rather than fed to the session:
+//!
+//! ```text
+//! psi-exchange 1
+//! blinded <length> <sha-256, hex>
+//! double-blinded <length> <sha-256, hex>
+//! ```
+//!
+//! [`ObjectExchange::poll`] does whatever is possible at the moment:
+//! publish our next message if it is missing, and handle the peer's if it
+//! arrived. It only relies on the state of its [`PsiSession`], so a
+//! process can save the session between polls (e.g. in a
+//! [`SessionStore`](crate::SessionStore)) and resume later with
+//! [`ObjectExchange::new`]. Use a fresh prefix per run.
+//!
+//! The crate brings no cloud client: implement [`ObjectStore`] over one,
+//! or use [`DirStore`] for a directory, e.g. a mounted bucket or a shared
+//! drive.
+//!
+//! Requires the `object-store` feature.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::object_store::{DirStore, ObjectExchange};
+//!
+//! let store = DirStore::new("/mnt/exchange");
+//! let session = PsiSession::new(&customers)?;
+//! let mut exchange = ObjectExchange::new(store, "2026-10-15", "org-a", "org-b", session)?;
+//! let result = loop {
+//!     if let Some(result) = exchange.poll()? {
+//!         break result;
+//!     }
+//!     std::thread::sleep(Duration::from_secs(60));
+//! };
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::error::{PsiError, Result};
+use crate::messages::PsiResult;
+use crate::session::PsiSession;
+use sha2::{Digest, Sha256};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Version of the manifest format.
+pub const MANIFEST_VERSION: u32 = 1;
+
+const MANIFEST: &str = "manifest";
+const BLINDED: &str = "blinded";
+const DOUBLE_BLINDED: &str = "double-blinded";
+
+/// Minimal object storage: whole objects under `/`-separated keys.
+pub trait ObjectStore {
+    /// Store `bytes` under `key`, replacing any previous object.
+    ///
+    /// # Errors
+    /// Returns `PsiError::StoreFailed` if the backend fails
+    fn put(&mut self, key: &str, bytes: &[u8]) -> Result<()>;
+
+    /// Fetch the object under `key`, if any.
+    ///
+    /// # Errors
+    /// Returns `PsiError::StoreFailed` if the backend fails
+    fn get(&mut self, key: &str) -> Result<Option<Vec<u8>>>;
+}
+
+/// [`ObjectStore`] keeping objects as files under a directory.
+///
+/// Files are written next to their final name and renamed into place, so
+/// readers never see a partial object.
+#[derive(Debug, Clone)]
+pub struct DirStore {
+    root: PathBuf,
+}
+
+impl DirStore {
+    /// Store objects under `root`, creating directories as needed.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+fn store_error(key: &str, error: std::io::Error) -> PsiError {
+    PsiError::StoreFailed(format!("Object {}: {}", key, error))
+}
+
+impl ObjectStore for DirStore {
+    fn put(&mut self, key: &str, bytes: &[u8]) -> Result<()> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|error| store_error(key, error))?;
+        }
+        let mut partial = path.clone().into_os_string();
+        partial.push(".partial");
+        std::fs::write(&partial, bytes).map_err(|error| store_error(key, error))?;
+        std::fs::rename(&partial, &path).map_err(|error| store_error(key, error))
+    }
+
+    fn get(&mut self, key: &str) -> Result<Option<Vec<u8>>> {
+        match std::fs::read(self.root.join(key)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(store_error(key, error)),
+        }
+    }
+}
+
+/// One message listed in a manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    name: String,
+    len: usize,
+    digest: String,
+}
+
+impl Entry {
+    fn of(name: &str, bytes: &[u8]) -> Self {
+        Self {
+            name: name.to_string(),
+            len: bytes.len(),
+            digest: hex_digest(bytes),
+        }
+    }
+
+    fn matches(&self, bytes: &[u8]) -> bool {
+        self.len == bytes.len() && self.digest == hex_digest(bytes)
+    }
+}
+
+fn hex_digest(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn encode_manifest(entries: &[Entry]) -> String {
+    let mut text = format!("psi-exchange {}\n", MANIFEST_VERSION);
+    for entry in entries {
+        text.push_str(&format!("{} {} {}\n", entry.name, entry.len, entry.digest));
+    }
+    text
+}
+
+fn decode_manifest(bytes: &[u8]) -> Result<Vec<Entry>> {
+    let invalid = |what: &str| PsiError::InvalidEncoding(format!("Manifest: {}", what));
+    let text = std::str::from_utf8(bytes).map_err(|_| invalid("not UTF-8"))?;
+    let mut lines = text.lines();
+    if lines.next() != Some(&format!("psi-exchange {}", MANIFEST_VERSION)) {
+        return Err(invalid("unknown format or version"));
+    }
+    lines
+        .map(
+            |line| match line.split(' ').collect::<Vec<_>>().as_slice() {
+                [name, len, digest] => Ok(Entry {
+                    name: name.to_string(),
+                    len: len.parse().map_err(|_| invalid(line))?,
+                    digest: digest.to_string(),
+                }),
+                _ => Err(invalid(line)),
+            },
+        )
+        .collect()
+}
+
+/// Check that `segment` is a single, plain path segment.
+fn check_segment(what: &str, segment: &str) -> Result<()> {
+    if segment.is_empty() || segment == "." || segment == ".." || segment.contains(['/', '\\']) {
+        return Err(PsiError::InvalidConfig(format!(
+            "Invalid {} {:?}",
+            what, segment
+        )));
+    }
+    Ok(())
+}
+
+/// One party's side of an exchange through an [`ObjectStore`].
+pub struct ObjectExchange<S> {
+    store: S,
+    ours: String,
+    theirs: String,
+    session: PsiSession,
+}
+
+impl<S: ObjectStore> ObjectExchange<S> {
+    /// Run `session` as party `ours` against party `theirs` under
+    /// `prefix`; `session` may be fresh or restored from an earlier poll.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `prefix` has an empty, `.` or
+    /// `..` segment, a party name is not a single segment, or both parties
+    /// have the same name
+    pub fn new(
+        store: S,
+        prefix: &str,
+        ours: &str,
+        theirs: &str,
+        session: PsiSession,
+    ) -> Result<Self> {
+        for segment in prefix.split('/') {
+            check_segment("object prefix segment", segment)?;
+        }
+        check_segment("party name", ours)?;
+        check_segment("party name", theirs)?;
+        if ours == theirs {
+            return Err(PsiError::InvalidConfig(format!(
+                "Both parties are named {:?}",
+                ours
+            )));
+        }
+        Ok(Self {
+            store,
+            ours: format!("{}/{}", prefix, ours),
+            theirs: format!("{}/{}", prefix, theirs),
+            session,
+        })
+    }
+
+    /// The session, e.g. to save it between polls.
+    pub fn session(&self) -> &PsiSession {
+        &self.session
+    }
+
+    /// Publish our next message if needed and handle the peer's if it
+    /// arrived.
+    ///
+    /// Returns the intersection once the peer's double-blinded message is
+    /// handled, `None` while waiting for the peer.
+    ///
+    /// # Errors
+    /// Returns `PsiError::StoreFailed` if the store fails or a listed
+    /// message is missing, `PsiError::InvalidEncoding` for a malformed
+    /// manifest or a message that does not match its manifest entry,
+    /// `PsiError::UnexpectedState` once the session is final, plus the
+    /// errors of [`PsiSession::handle_message`]
+    pub fn poll(&mut self) -> Result<Option<PsiResult>> {
+        loop {
+            let name = match self.session {
+                PsiSession::Prepared(_) => BLINDED,
+                PsiSession::DoubleBlinded(_) => DOUBLE_BLINDED,
+                _ => return self.session.outgoing_message().map(|_| None),
+            };
+            self.publish(name)?;
+
+            let Some(entry) = read_manifest(&mut self.store, &self.theirs)?
+                .into_iter()
+                .find(|entry| entry.name == name)
+            else {
+                return Ok(None);
+            };
+            let key = format!("{}/{}", self.theirs, name);
+            let bytes = self.store.get(&key)?.ok_or_else(|| {
+                PsiError::StoreFailed(format!("Object {} is listed but missing", key))
+            })?;
+            if !entry.matches(&bytes) {
+                return Err(PsiError::InvalidEncoding(format!(
+                    "Object {} does not match its manifest entry",
+                    key
+                )));
+            }
+            if let Some(result) = self.session.handle_message(&bytes)? {
+                return Ok(Some(result));
+            }
+        }
+    }
+
+    /// Publish our message `name` unless our manifest already lists it.
+    fn publish(&mut self, name: &str) -> Result<()> {
+        let mut entries = read_manifest(&mut self.store, &self.ours)?;
+        if entries.iter().any(|entry| entry.name == name) {
+            return Ok(());
+        }
+        let bytes = self.session.outgoing_message()?;
+        self.store.put(&format!("{}/{}", self.ours, name), &bytes)?;
+        entries.push(Entry::of(name, &bytes));
+        self.store.put(
+            &format!("{}/{}", self.ours, MANIFEST),
+            encode_manifest(&entries).as_bytes(),
+        )
+    }
+}
+
+/// Entries of the manifest of `party`; empty if it has none yet.
+fn read_manifest<S: ObjectStore>(store: &mut S, party: &str) -> Result<Vec<Entry>> {
+    match store.get(&format!("{}/{}", party, MANIFEST))? {
+        Some(bytes) => decode_manifest(&bytes),
+        None => Ok(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PsiConfig;
+    use crate::test_util::session;
+
+    fn root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("psi-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        root
+    }
+
+    #[test]
+    fn test_exchange_with_resume() {
+        let root = root("object-exchange");
+        let new = |ours, theirs, session| {
+            ObjectExchange::new(DirStore::new(&root), "run/1", ours, theirs, session).unwrap()
+        };
+        let mut alice = new("alice", "bob", session(&["a", "b", "c"]));
+        assert_eq!(alice.poll().unwrap(), None);
+        // Alice's process stops and resumes from the saved state
+        let state = alice.session().to_state_bytes().unwrap();
+        let restored = PsiSession::from_state_bytes(&state, PsiConfig::default()).unwrap();
+        let mut alice = new("alice", "bob", restored);
+        assert_eq!(alice.poll().unwrap(), None);
+
+        let mut bob = new("bob", "alice", session(&["b", "c", "d"]));
+        assert_eq!(bob.poll().unwrap(), None);
+        let alice_result = alice.poll().unwrap().unwrap();
+        let bob_result = bob.poll().unwrap().unwrap();
+        assert_eq!(alice_result.len(), 2);
+        assert_eq!(bob_result.len(), 2);
+        assert!(alice.poll().is_err());
+
+        let manifest = std::fs::read_to_string(root.join("run/1/alice/manifest")).unwrap();
+        assert!(manifest.starts_with("psi-exchange 1\nblinded "));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_refuses_objects_not_matching_the_manifest() {
+        let root = root("object-tampered");
+        let mut alice =
+            ObjectExchange::new(DirStore::new(&root), "run", "a", "b", session(&["x"])).unwrap();
+        let mut bob =
+            ObjectExchange::new(DirStore::new(&root), "run", "b", "a", session(&["x"])).unwrap();
+        alice.poll().unwrap();
+
+        let path = root.join("run/a/blinded");
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        std::fs::write(&path, bytes).unwrap();
+        assert!(matches!(bob.poll(), Err(PsiError::InvalidEncoding(_))));
+
+        std::fs::write(root.join("run/a/manifest"), "psi-exchange 9\n").unwrap();
+        assert!(matches!(bob.poll(), Err(PsiError::InvalidEncoding(_))));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        let store = || DirStore::new("unused");
+        assert!(ObjectExchange::new(store(), "run/../x", "a", "b", session(&["x"])).is_err());
+        assert!(ObjectExchange::new(store(), "run", "a/b", "c", session(&["x"])).is_err());
+        assert!(ObjectExchange::new(store(), "run", "a", "a", session(&["x"])).is_err());
+        assert!(ObjectExchange::new(store(), "", "a", "b", session(&["x"])).is_err());
+    }
+}
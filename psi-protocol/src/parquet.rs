@@ -0,0 +1,346 @@
+//! Parquet files as protocol input.
+//!
+//! Identifier sets for matching jobs usually live in Parquet, often as one
+//! file per day with a timestamp column. [`ParquetSource`] reads a single
+//! column out of one or more files, one record batch at a time, and hashes
+//! each batch before reading the next: peak memory is one batch plus 32
+//! bytes per item, however large the values are.
+//!
+//! A [`time_range`](ParquetSource::time_range) is pushed down to the reader.
+//! Row groups whose column statistics fall outside the range are skipped
+//! without being read, and the rows of the remaining groups are filtered on
+//! the time column before the item column is decoded.
+//!
+//! The item column accepts the same types as
+//! [`from_arrow`](PsiProtocol::from_arrow). The time column may be `Int64`,
+//! `Date64` or a `Timestamp` of any unit; the range is compared with the
+//! raw stored values, so it must use the column's unit. Rows with a null
+//! time are skipped.
+//!
+//! Requires the `parquet` feature.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::parquet::ParquetSource;
+//!
+//! // Timestamps in microseconds since the epoch
+//! let source = ParquetSource::new("email")
+//!     .files(["events-2026-10-01.parquet", "events-2026-10-02.parquet"])
+//!     .time_range("seen_at", start_micros..end_micros);
+//! let alice = PsiProtocol::from_parquet(&source, PsiConfig::default())?;
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::arrow::hash_array;
+use crate::config::PsiConfig;
+use crate::error::{PsiError, Result};
+use crate::protocol::PsiProtocol;
+use crate::state::PreparedState;
+use ::parquet::arrow::arrow_reader::{
+    ArrowPredicateFn, ParquetRecordBatchReaderBuilder, RowFilter,
+};
+use ::parquet::arrow::ProjectionMask;
+use ::parquet::file::metadata::RowGroupMetaData;
+use ::parquet::file::statistics::Statistics;
+use ::parquet::schema::types::SchemaDescriptor;
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    ArrowPrimitiveType, Date64Type, Int64Type, TimestampMicrosecondType, TimestampMillisecondType,
+    TimestampNanosecondType, TimestampSecondType,
+};
+use arrow_array::{Array, BooleanArray, RecordBatch};
+use arrow_schema::{ArrowError, DataType, TimeUnit};
+use std::fs::File;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Default number of rows per record batch.
+pub const DEFAULT_BATCH_SIZE: usize = 8192;
+
+/// One column of a set of Parquet files, optionally restricted to a time
+/// range.
+#[derive(Debug, Clone)]
+pub struct ParquetSource {
+    files: Vec<PathBuf>,
+    column: String,
+    time_filter: Option<(String, Range<i64>)>,
+    batch_size: usize,
+}
+
+impl ParquetSource {
+    /// Read the items from `column`, a dot-separated path for nested
+    /// columns.
+    pub fn new(column: impl Into<String>) -> Self {
+        Self {
+            files: Vec::new(),
+            column: column.into(),
+            time_filter: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    /// Add a file to read.
+    pub fn file(mut self, path: impl AsRef<Path>) -> Self {
+        self.files.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Add several files to read, in order.
+    pub fn files<P: AsRef<Path>>(mut self, paths: impl IntoIterator<Item = P>) -> Self {
+        self.files
+            .extend(paths.into_iter().map(|path| path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Only read rows whose `column` value lies in `range` (end excluded).
+    pub fn time_range(mut self, column: impl Into<String>, range: Range<i64>) -> Self {
+        self.time_filter = Some((column.into(), range));
+        self
+    }
+
+    /// Set the number of rows decoded per batch (minimum 1).
+    pub fn batch_size(mut self, rows: usize) -> Self {
+        self.batch_size = rows.max(1);
+        self
+    }
+
+    /// Hash the items of every file, without deduplicating them.
+    fn hash_items(&self, config: &PsiConfig) -> Result<Vec<[u8; 32]>> {
+        let mut hashes = Vec::new();
+        for path in &self.files {
+            self.hash_file(path, config, &mut hashes)?;
+        }
+        Ok(hashes)
+    }
+
+    fn hash_file(&self, path: &Path, config: &PsiConfig, hashes: &mut Vec<[u8; 32]>) -> Result<()> {
+        let file = File::open(path).map_err(|e| {
+            PsiError::InvalidConfig(format!("Cannot open {}: {}", path.display(), e))
+        })?;
+        let mut builder = ParquetRecordBatchReaderBuilder::try_new(file)
+            .map_err(|e| invalid_file(path, e))?
+            .with_batch_size(self.batch_size);
+        let metadata = builder.metadata().clone();
+        let schema = metadata.file_metadata().schema_descr();
+        let item_leaf = leaf_index(schema, &self.column, path)?;
+
+        if let Some((column, range)) = &self.time_filter {
+            let time_leaf = leaf_index(schema, column, path)?;
+            let row_groups = metadata
+                .row_groups()
+                .iter()
+                .enumerate()
+                .filter(|(_, group)| may_overlap(group, time_leaf, range))
+                .map(|(index, _)| index)
+                .collect();
+            let range = range.clone();
+            let predicate = ArrowPredicateFn::new(
+                ProjectionMask::leaves(schema, [time_leaf]),
+                move |batch: RecordBatch| in_range(batch.column(0).as_ref(), &range),
+            );
+            builder = builder
+                .with_row_groups(row_groups)
+                .with_row_filter(RowFilter::new(vec![Box::new(predicate)]));
+        }
+
+        let reader = builder
+            .with_projection(ProjectionMask::leaves(schema, [item_leaf]))
+            .build()
+            .map_err(|e| invalid_file(path, e))?;
+        for batch in reader {
+            let batch = batch.map_err(|e| invalid_file(path, e))?;
+            hashes.extend(hash_array(batch.column(0).as_ref(), config)?);
+        }
+        Ok(())
+    }
+}
+
+fn invalid_file(path: &Path, error: impl std::fmt::Display) -> PsiError {
+    PsiError::InvalidEncoding(format!("Cannot read {}: {}", path.display(), error))
+}
+
+/// Index of the leaf column at `column` (dot-separated).
+fn leaf_index(schema: &SchemaDescriptor, column: &str, path: &Path) -> Result<usize> {
+    schema
+        .columns()
+        .iter()
+        .position(|leaf| leaf.path().string() == column)
+        .ok_or_else(|| {
+            PsiError::InvalidConfig(format!("{} has no column {:?}", path.display(), column))
+        })
+}
+
+/// Returns false only if the row group's statistics prove that no value of
+/// the time column lies in `range`.
+fn may_overlap(group: &RowGroupMetaData, time_leaf: usize, range: &Range<i64>) -> bool {
+    let (min, max) = match group.column(time_leaf).statistics() {
+        Some(Statistics::Int64(stats)) => (stats.min_opt().copied(), stats.max_opt().copied()),
+        _ => return true,
+    };
+    match (min, max) {
+        (Some(min), Some(max)) => min < range.end && max >= range.start,
+        _ => true,
+    }
+}
+
+/// Row filter keeping the non-null time values in `range`.
+fn in_range(
+    array: &dyn Array,
+    range: &Range<i64>,
+) -> std::result::Result<BooleanArray, ArrowError> {
+    fn mask<T: ArrowPrimitiveType<Native = i64>>(
+        array: &dyn Array,
+        range: &Range<i64>,
+    ) -> BooleanArray {
+        array
+            .as_primitive::<T>()
+            .iter()
+            .map(|value| Some(value.is_some_and(|value| range.contains(&value))))
+            .collect()
+    }
+    Ok(match array.data_type() {
+        DataType::Int64 => mask::<Int64Type>(array, range),
+        DataType::Date64 => mask::<Date64Type>(array, range),
+        DataType::Timestamp(TimeUnit::Second, _) => mask::<TimestampSecondType>(array, range),
+        DataType::Timestamp(TimeUnit::Millisecond, _) => {
+            mask::<TimestampMillisecondType>(array, range)
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, _) => {
+            mask::<TimestampMicrosecondType>(array, range)
+        }
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+            mask::<TimestampNanosecondType>(array, range)
+        }
+        other => {
+            return Err(ArrowError::InvalidArgumentError(format!(
+                "Unsupported time column type {}",
+                other
+            )))
+        }
+    })
+}
+
+impl PsiProtocol<PreparedState> {
+    /// Build a prepared protocol from a column of Parquet files.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if a file cannot be opened or lacks
+    /// a column, `PsiError::InvalidEncoding` if a file cannot be decoded
+    /// (including an unsupported time column type), plus the errors of
+    /// [`from_arrow`](Self::from_arrow)
+    pub fn from_parquet(source: &ParquetSource, config: PsiConfig) -> Result<Self> {
+        let hashes = source.hash_items(&config)?;
+        Self::from_item_hashes(hashes, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::item_id::ItemId;
+    use crate::messages::PsiResult;
+    use ::parquet::arrow::ArrowWriter;
+    use ::parquet::file::properties::WriterProperties;
+    use arrow_array::{ArrayRef, Int64Array, StringArray};
+    use std::sync::Arc;
+
+    /// Write `(email, seen_at)` rows, three per row group.
+    fn write_file(name: &str, rows: &[(&str, i64)]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("psi-{}-{}.parquet", name, std::process::id()));
+        let emails: ArrayRef =
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|row| row.0)));
+        let seen: ArrayRef = Arc::new(Int64Array::from_iter_values(rows.iter().map(|row| row.1)));
+        let batch = RecordBatch::try_from_iter([("email", emails), ("seen_at", seen)]).unwrap();
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(3)
+            .build();
+        let mut writer = ArrowWriter::try_new(
+            File::create(&path).unwrap(),
+            batch.schema(),
+            Some(properties),
+        )
+        .unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        path
+    }
+
+    fn intersect(alice: PsiProtocol<PreparedState>, bob_items: &[&[u8]]) -> PsiResult {
+        let bob_items: Vec<Vec<u8>> = bob_items.iter().map(|item| item.to_vec()).collect();
+        let bob = PsiProtocol::new(&bob_items).unwrap();
+        let alice_msg = alice.message();
+        let (alice, _) = alice.compute(bob.message()).unwrap();
+        let (_, bob_double) = bob.compute(alice_msg).unwrap();
+        let mut result = alice.finalize(bob_double).unwrap().1;
+        result.intersection_hashes.sort();
+        result
+    }
+
+    #[test]
+    fn test_reads_column_with_time_range() {
+        let path = write_file(
+            "time-range",
+            &[
+                ("a", 10),
+                ("b", 11),
+                ("c", 12),
+                ("d", 20),
+                ("e", 21),
+                ("f", 22),
+                ("g", 30),
+            ],
+        );
+        let all = ParquetSource::new("email").file(&path).batch_size(2);
+        let alice = PsiProtocol::from_parquet(&all, PsiConfig::default()).unwrap();
+        assert_eq!(alice.message().len(), 7);
+
+        let window = all.time_range("seen_at", 12..22);
+        let alice = PsiProtocol::from_parquet(&window, PsiConfig::default()).unwrap();
+        assert_eq!(alice.message().len(), 3);
+        let result = intersect(alice, &[b"a", b"c", b"e", b"f", b"g"]);
+        let mut expected = vec![ItemId::of(b"c"), ItemId::of(b"e")];
+        expected.sort();
+        assert_eq!(result.intersection_hashes, expected);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_row_group_pruning() {
+        let path = write_file("pruning", &[("a", 1), ("b", 2), ("c", 3), ("d", 7)]);
+        let file = File::open(&path).unwrap();
+        let metadata = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .metadata()
+            .clone();
+        let groups = metadata.row_groups();
+        assert_eq!(groups.len(), 2);
+        assert!(!may_overlap(&groups[0], 1, &(4..10)));
+        assert!(may_overlap(&groups[1], 1, &(4..10)));
+        assert!(may_overlap(&groups[0], 1, &(3..4)));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_bad_sources() {
+        let path = write_file("bad-sources", &[("a", 1)]);
+        let missing = ParquetSource::new("phone").file(&path);
+        assert!(matches!(
+            PsiProtocol::from_parquet(&missing, PsiConfig::default()),
+            Err(PsiError::InvalidConfig(_))
+        ));
+        let string_time = ParquetSource::new("email")
+            .file(&path)
+            .time_range("email", 0..1);
+        assert!(matches!(
+            PsiProtocol::from_parquet(&string_time, PsiConfig::default()),
+            Err(PsiError::InvalidEncoding(_))
+        ));
+        let no_file = ParquetSource::new("email").file(path.with_extension("missing"));
+        assert!(matches!(
+            PsiProtocol::from_parquet(&no_file, PsiConfig::default()),
+            Err(PsiError::InvalidConfig(_))
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,361 @@
+//! Encrypted per-item payloads ("PSI with data transfer").
+//!
+//! A party attaches an optional payload to some of its items and sends them
+//! sealed with ChaCha20-Poly1305 in a follow-up message; the peer can open
+//! exactly the payloads of the items it also holds.
+//!
+//! The double-blinded points `a*b*H(x)` cannot serve as keys directly: the
+//! peer computed them for every one of our items. Instead the sender picks a
+//! fresh scalar `c` per message and keys the payload of item `x` with
+//! `c*a*b*H(x)`. Alongside the payloads it sends `c*a*b*H(y)` for every
+//! point `y` of the peer's message, in the peer's order, so the peer obtains
+//! the keys of its own items and no others.
+//!
+//! The flow adds one message after the double-blinded exchange, in either
+//! direction or both:
+//!
+//! 1. After receiving the peer's [`DoubleBlindedPointsMessage`], call
+//!    [`seal_payloads`](PsiProtocol::seal_payloads) with it and send the
+//!    returned [`EncryptedPayloadsMessage`].
+//! 2. Open the peer's message with
+//!    [`open_payloads`](PsiProtocol::open_payloads), then finalize as usual.
+//!
+//! The number of payloads is visible to the peer. Requires the `payload`
+//! feature.
+
+use crate::collections::FastMap;
+use crate::crypto::{decompress_point, hash_item_with, random_scalar};
+use crate::error::{Phase, PsiError, Result};
+use crate::item_id::ItemId;
+use crate::messages::DoubleBlindedPointsMessage;
+use crate::protocol::PsiProtocol;
+use crate::state::DoubleBlindedState;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::Scalar;
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+use std::collections::HashMap;
+use zeroize::Zeroize;
+
+const KEY_INFO: &[u8] = b"psi-sync/payload/key";
+const TAG_INFO: &[u8] = b"psi-sync/payload/tag";
+
+/// One sealed payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EncryptedPayload {
+    /// Lookup tag derived from the item's key point
+    pub tag: [u8; 32],
+    /// Random AEAD nonce
+    pub nonce: [u8; 12],
+    /// ChaCha20-Poly1305 ciphertext, authentication tag included
+    pub ciphertext: Vec<u8>,
+}
+
+/// Follow-up message carrying the sender's sealed payloads.
+///
+/// Payloads are sorted by tag, so their order says nothing about the items.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EncryptedPayloadsMessage {
+    /// Key points for the receiver's items, in the receiver's message order
+    pub unlock_points: Vec<CompressedRistretto>,
+    /// Sealed payloads
+    pub payloads: Vec<EncryptedPayload>,
+}
+
+impl EncryptedPayloadsMessage {
+    /// Create a new encrypted payloads message.
+    pub fn new(unlock_points: Vec<CompressedRistretto>, payloads: Vec<EncryptedPayload>) -> Self {
+        Self {
+            unlock_points,
+            payloads,
+        }
+    }
+
+    /// Returns the number of payloads in this message.
+    pub fn len(&self) -> usize {
+        self.payloads.len()
+    }
+
+    /// Returns true if this message contains no payloads.
+    pub fn is_empty(&self) -> bool {
+        self.payloads.is_empty()
+    }
+}
+
+impl PsiProtocol<DoubleBlindedState> {
+    /// Seal a payload for each `(item, payload)` pair.
+    ///
+    /// `remote_msg` is the peer's answer to our blinded points, the same
+    /// message later passed to [`finalize`](Self::finalize). Only a peer
+    /// holding `item` can open its payload.
+    ///
+    /// # Errors
+    /// Returns `PsiError::LengthMismatch` if `remote_msg` does not answer
+    /// every point of our message, `PsiError::InvalidPoint` if a point we
+    /// need is not a valid encoding, `PsiError::InvalidConfig` if an item is
+    /// not part of the local set, and `PsiError::CryptoError` if encryption
+    /// fails
+    ///
+    /// # Example
+    /// ```ignore
+    /// let (alice, alice_double) = alice.compute(bob_msg)?;
+    /// let bob_double = exchange(alice_double);
+    ///
+    /// let sealed = alice.seal_payloads(&bob_double, &[(b"apple".to_vec(), b"red".to_vec())])?;
+    /// let bob_sealed = exchange(sealed);
+    /// let bob_payloads = alice.open_payloads(&bob_sealed)?;
+    /// let (_, result) = alice.finalize(bob_double)?;
+    /// # Ok::<(), psi_protocol::PsiError>(())
+    /// ```
+    pub fn seal_payloads(
+        &self,
+        remote_msg: &DoubleBlindedPointsMessage,
+        payloads: &[(Vec<u8>, Vec<u8>)],
+    ) -> Result<EncryptedPayloadsMessage> {
+        let hash_order = self.state().hash_order();
+        if remote_msg.len() != hash_order.len() {
+            return Err(PsiError::LengthMismatch {
+                phase: Phase::Finalize,
+                expected: hash_order.len(),
+                actual: remote_msg.len(),
+            });
+        }
+        let positions: FastMap<[u8; 32], usize> = hash_order
+            .iter()
+            .enumerate()
+            .filter_map(|(index, hash)| hash.map(|hash| (hash, index)))
+            .collect();
+
+        let mut c = random_scalar();
+        let sealed = self.seal_with(&c, remote_msg, &positions, payloads);
+        c.zeroize();
+        sealed
+    }
+
+    fn seal_with(
+        &self,
+        c: &Scalar,
+        remote_msg: &DoubleBlindedPointsMessage,
+        positions: &FastMap<[u8; 32], usize>,
+        payloads: &[(Vec<u8>, Vec<u8>)],
+    ) -> Result<EncryptedPayloadsMessage> {
+        // Our double-blinded points of the peer's items were computed locally
+        let unlock_points = self
+            .state()
+            .double_blinded_from_remote()
+            .iter()
+            .enumerate()
+            .map(|(index, point)| reblind(c, point, index))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut sealed = payloads
+            .iter()
+            .map(|(item, payload)| {
+                let hash = hash_item_with(self.config().hash(), item);
+                let index = *positions.get(&hash).ok_or_else(|| {
+                    PsiError::InvalidConfig(
+                        "Payload attached to an item outside the local set".to_string(),
+                    )
+                })?;
+                let key_point = reblind(c, &remote_msg.double_blinded_points[index], index)?;
+                seal(&key_point, payload)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        sealed.sort_unstable_by_key(|payload| payload.tag);
+        Ok(EncryptedPayloadsMessage::new(unlock_points, sealed))
+    }
+
+    /// Open the peer's payloads for the items we also hold.
+    ///
+    /// Payloads for other items cannot be opened and are ignored, so the
+    /// keys of the returned map are part of the intersection.
+    ///
+    /// # Errors
+    /// Returns `PsiError::LengthMismatch` if the message does not hold one
+    /// unlock point per point of our message, and `PsiError::CryptoError` if
+    /// a payload addressed to one of our items fails authentication
+    pub fn open_payloads(
+        &self,
+        msg: &EncryptedPayloadsMessage,
+    ) -> Result<HashMap<ItemId, Vec<u8>>> {
+        let hash_order = self.state().hash_order();
+        if msg.unlock_points.len() != hash_order.len() {
+            return Err(PsiError::LengthMismatch {
+                phase: Phase::Finalize,
+                expected: hash_order.len(),
+                actual: msg.unlock_points.len(),
+            });
+        }
+        let by_tag: HashMap<&[u8; 32], &EncryptedPayload> =
+            msg.payloads.iter().map(|p| (&p.tag, p)).collect();
+
+        let mut opened = HashMap::new();
+        for (hash, point) in hash_order.iter().zip(&msg.unlock_points) {
+            // Padding slots carry no item
+            let Some(hash) = hash else {
+                continue;
+            };
+            let (mut key, tag) = derive_keys(point);
+            let Some(payload) = by_tag.get(&tag) else {
+                key.zeroize();
+                continue;
+            };
+            let plaintext = ChaCha20Poly1305::new(Key::from_slice(&key)).decrypt(
+                Nonce::from_slice(&payload.nonce),
+                Payload {
+                    msg: &payload.ciphertext,
+                    aad: &payload.tag,
+                },
+            );
+            key.zeroize();
+            let id = ItemId::new(*hash);
+            let plaintext = plaintext.map_err(|_| {
+                PsiError::CryptoError(format!("Payload for item {} failed authentication", id))
+            })?;
+            opened.insert(id, plaintext);
+        }
+        Ok(opened)
+    }
+}
+
+/// Multiply a double-blinded point by the per-message scalar.
+fn reblind(c: &Scalar, point: &CompressedRistretto, index: usize) -> Result<CompressedRistretto> {
+    decompress_point(point)
+        .map(|point| (c * point).compress())
+        .map_err(|_| PsiError::InvalidPoint {
+            phase: Phase::Finalize,
+            index,
+        })
+}
+
+/// Seal one payload under the keys derived from its key point.
+fn seal(key_point: &CompressedRistretto, payload: &[u8]) -> Result<EncryptedPayload> {
+    let (mut key, tag) = derive_keys(key_point);
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce);
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key)).encrypt(
+        Nonce::from_slice(&nonce),
+        Payload {
+            msg: payload,
+            aad: &tag,
+        },
+    );
+    key.zeroize();
+    Ok(EncryptedPayload {
+        tag,
+        nonce,
+        ciphertext: ciphertext
+            .map_err(|_| PsiError::CryptoError("Payload encryption failed".to_string()))?,
+    })
+}
+
+/// Derive the AEAD key and lookup tag of a key point.
+fn derive_keys(key_point: &CompressedRistretto) -> ([u8; 32], [u8; 32]) {
+    let hkdf = Hkdf::<Sha256>::new(None, key_point.as_bytes());
+    let mut key = [0u8; 32];
+    let mut tag = [0u8; 32];
+    // 32 bytes is always a valid HKDF-SHA256 output length
+    hkdf.expand(KEY_INFO, &mut key).expect("valid length");
+    hkdf.expand(TAG_INFO, &mut tag).expect("valid length");
+    (key, tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Exchange = (
+        PsiProtocol<DoubleBlindedState>,
+        DoubleBlindedPointsMessage,
+        PsiProtocol<DoubleBlindedState>,
+        DoubleBlindedPointsMessage,
+    );
+
+    fn exchange(alice_items: &[Vec<u8>], bob_items: &[Vec<u8>]) -> Exchange {
+        let alice = PsiProtocol::new(alice_items).unwrap();
+        let bob = PsiProtocol::new(bob_items).unwrap();
+        let alice_msg = alice.message();
+        let (alice, alice_double) = alice.compute(bob.message()).unwrap();
+        let (bob, bob_double) = bob.compute(alice_msg).unwrap();
+        (alice, bob_double, bob, alice_double)
+    }
+
+    #[test]
+    fn test_payloads_open_only_for_shared_items() {
+        let alice_items = vec![b"apple".to_vec(), b"banana".to_vec()];
+        let bob_items = vec![b"banana".to_vec(), b"cherry".to_vec()];
+        let (alice, bob_double, bob, alice_double) = exchange(&alice_items, &bob_items);
+
+        let sealed = alice
+            .seal_payloads(
+                &bob_double,
+                &[
+                    (b"apple".to_vec(), b"secret".to_vec()),
+                    (b"banana".to_vec(), b"yellow".to_vec()),
+                ],
+            )
+            .unwrap();
+        assert_eq!(sealed.len(), 2);
+        assert_eq!(sealed.unlock_points.len(), 2);
+
+        let opened = bob.open_payloads(&sealed).unwrap();
+        assert_eq!(opened.len(), 1);
+        assert_eq!(opened[&ItemId::of(b"banana")], b"yellow".to_vec());
+
+        let (_, bob_result) = bob.finalize(alice_double).unwrap();
+        assert!(opened
+            .keys()
+            .all(|id| bob_result.intersection_hashes.contains(id)));
+    }
+
+    #[test]
+    fn test_double_blinded_points_do_not_open_payloads() {
+        // The peer knows every double-blinded point of our items; none of
+        // them may act as a payload key
+        let items = vec![b"apple".to_vec()];
+        let (alice, bob_double, _, _) = exchange(&items, &items);
+        let sealed = alice
+            .seal_payloads(&bob_double, &[(b"apple".to_vec(), b"red".to_vec())])
+            .unwrap();
+        let (_, tag) = derive_keys(&bob_double.double_blinded_points[0]);
+        assert_ne!(tag, sealed.payloads[0].tag);
+    }
+
+    #[test]
+    fn test_tampered_payload_is_rejected() {
+        let items = vec![b"apple".to_vec()];
+        let (alice, bob_double, bob, _) = exchange(&items, &items);
+        let mut sealed = alice
+            .seal_payloads(&bob_double, &[(b"apple".to_vec(), b"red".to_vec())])
+            .unwrap();
+        sealed.payloads[0].ciphertext[0] ^= 1;
+        assert!(matches!(
+            bob.open_payloads(&sealed),
+            Err(PsiError::CryptoError(_))
+        ));
+    }
+
+    #[test]
+    fn test_seal_and_open_reject_bad_input() {
+        let items = vec![b"apple".to_vec()];
+        let (alice, bob_double, bob, _) = exchange(&items, &items);
+        assert!(matches!(
+            alice.seal_payloads(&bob_double, &[(b"pear".to_vec(), vec![])]),
+            Err(PsiError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            alice.seal_payloads(&DoubleBlindedPointsMessage::new(vec![]), &[]),
+            Err(PsiError::LengthMismatch { .. })
+        ));
+        assert!(matches!(
+            bob.open_payloads(&EncryptedPayloadsMessage::new(vec![], vec![])),
+            Err(PsiError::LengthMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,193 @@
+//! Stable encodings of [`PsiResult`] for storage and shipping.
+//!
+//! Results outlive sessions: they are stored, sent to other services and
+//! compared across runs. Two encodings are stable for that purpose:
+//!
+//! - with the `serde` feature, `PsiResult` implements `Serialize` and
+//!   `Deserialize`; ids are hex strings and the double-blinded map is written
+//!   sorted by id, so the same result always gives the same JSON;
+//! - [`PsiResult::to_bytes`] gives a compact binary form:
+//!
+//! ```text
+//! +---------+-------+-----------------+-------+----------------------------+
+//! | version | count | count * 32 (id) | count | count * (32 id + 32 point) |
+//! +---------+-------+-----------------+-------+----------------------------+
+//! ```
+//!
+//! The first list is `intersection_hashes` in order, the second the
+//! double-blinded map sorted by id. Counts are `u32` big-endian.
+
+use crate::error::{PsiError, Result};
+use crate::item_id::ItemId;
+use crate::messages::PsiResult;
+use crate::wire::{read_count, write_count};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use std::collections::HashMap;
+
+/// Current version of the binary result format.
+pub const RESULT_VERSION: u8 = 1;
+
+impl PsiResult {
+    /// Encode the result in the compact binary form.
+    ///
+    /// Deterministic: equal results give equal bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(
+            9 + 32 * self.intersection_hashes.len() + 64 * self.double_blinded_map.len(),
+        );
+        out.push(RESULT_VERSION);
+        write_count(&mut out, self.intersection_hashes.len());
+        for id in &self.intersection_hashes {
+            out.extend_from_slice(id.as_bytes());
+        }
+        let mut entries: Vec<_> = self.double_blinded_map.iter().collect();
+        entries.sort_unstable_by_key(|(id, _)| **id);
+        write_count(&mut out, entries.len());
+        for (id, point) in entries {
+            out.extend_from_slice(id.as_bytes());
+            out.extend_from_slice(point.as_bytes());
+        }
+        out
+    }
+
+    /// Decode a result encoded with [`to_bytes`](Self::to_bytes).
+    ///
+    /// # Errors
+    /// Returns `PsiError::VersionMismatch` if the bytes have another format
+    /// version, or `PsiError::InvalidEncoding` if they are truncated, have
+    /// trailing bytes or list an id twice in the map
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (&version, rest) = bytes
+            .split_first()
+            .ok_or_else(|| PsiError::InvalidEncoding("Empty result".to_string()))?;
+        if version != RESULT_VERSION {
+            return Err(PsiError::VersionMismatch {
+                expected: RESULT_VERSION,
+                actual: version,
+            });
+        }
+
+        let (ids, rest) = read_records(rest, 32)?;
+        let intersection_hashes = ids.map(|record| ItemId::new(array(record))).collect();
+
+        let (entries, rest) = read_records(rest, 64)?;
+        let count = entries.len();
+        let double_blinded_map: HashMap<ItemId, CompressedRistretto> = entries
+            .map(|record| {
+                let (id, point) = record.split_at(32);
+                (ItemId::new(array(id)), CompressedRistretto(array(point)))
+            })
+            .collect();
+        if double_blinded_map.len() != count {
+            return Err(PsiError::InvalidEncoding(
+                "Result maps an id twice".to_string(),
+            ));
+        }
+
+        if !rest.is_empty() {
+            return Err(PsiError::InvalidEncoding(format!(
+                "{} trailing bytes after result",
+                rest.len()
+            )));
+        }
+        Ok(PsiResult::new(intersection_hashes, double_blinded_map))
+    }
+}
+
+/// Read a `count | records` block, checking the count against the input
+/// before anything is allocated.
+fn read_records(
+    bytes: &[u8],
+    record_len: usize,
+) -> Result<(std::slice::ChunksExact<'_, u8>, &[u8])> {
+    let (count, rest) = read_count(bytes)?;
+    if rest.len() / record_len < count {
+        return Err(PsiError::InvalidEncoding(format!(
+            "Result announces {} records, found {} bytes",
+            count,
+            rest.len()
+        )));
+    }
+    let (records, rest) = rest.split_at(count * record_len);
+    Ok((records.chunks_exact(record_len), rest))
+}
+
+fn array(bytes: &[u8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    out.copy_from_slice(bytes);
+    out
+}
+
+/// Serde helper writing the double-blinded map sorted by id.
+#[cfg(feature = "serde")]
+pub(crate) mod sorted_map {
+    use crate::item_id::ItemId;
+    use curve25519_dalek::ristretto::CompressedRistretto;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::{BTreeMap, HashMap};
+
+    pub(crate) fn serialize<S: Serializer>(
+        map: &HashMap<ItemId, CompressedRistretto>,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<HashMap<ItemId, CompressedRistretto>, D::Error> {
+        HashMap::deserialize(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local::run_local_psi;
+
+    fn result() -> PsiResult {
+        let alice = vec![b"apple".to_vec(), b"banana".to_vec(), b"cherry".to_vec()];
+        let bob = vec![b"banana".to_vec(), b"cherry".to_vec()];
+        run_local_psi(&alice, &bob).unwrap().0
+    }
+
+    #[test]
+    fn test_binary_roundtrip_is_deterministic() {
+        let result = result();
+        let bytes = result.to_bytes();
+        assert_eq!(bytes.len(), 1 + 4 + 2 * 32 + 4 + 2 * 64);
+        let decoded = PsiResult::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, result);
+        assert_eq!(decoded.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_binary_rejects_malformed_input() {
+        let bytes = result().to_bytes();
+        assert!(matches!(
+            PsiResult::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(PsiError::InvalidEncoding(_))
+        ));
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(PsiResult::from_bytes(&trailing).is_err());
+        let mut version = bytes;
+        version[0] = 2;
+        assert!(matches!(
+            PsiResult::from_bytes(&version),
+            Err(PsiError::VersionMismatch { .. })
+        ));
+        // A huge announced count is refused before allocating
+        assert!(PsiResult::from_bytes(&[RESULT_VERSION, 0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_json_is_stable() {
+        let result = result();
+        let json = serde_json::to_string(&result).unwrap();
+        let decoded: PsiResult = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, result);
+        assert_eq!(serde_json::to_string(&decoded).unwrap(), json);
+    }
+}
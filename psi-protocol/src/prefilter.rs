@@ -0,0 +1,376 @@
+//! Merkle-tree prefilter for mostly-identical sets.
+//!
+//! Replicas of the same dataset usually differ in a handful of items. Running
+//! PSI over millions of shared items to find those few wastes almost all of
+//! the work. [`MerklePrefilter`] buckets items by hash prefix into a 16-ary
+//! Merkle tree; the peers exchange digests level by level, descending only
+//! into subtrees whose digests differ, and end up with the few leaves whose
+//! contents differ. Items in identical leaves are shared on both sides, and
+//! PSI only needs to run over the items of the differing leaves.
+//!
+//! Unlike PSI itself, the prefilter reveals which hash-prefix buckets the
+//! two sets agree on, and an equal digest reveals that a whole bucket is
+//! shared. Only use it between peers allowed to learn that much, such as
+//! replicas of the same data.
+
+use crate::config::HashAlgorithm;
+use crate::error::{Phase, PsiError, Result};
+use crate::item_id::ItemId;
+use crate::messages::MerkleDigestsMessage;
+use sha2::{Digest, Sha256};
+
+/// Children per internal node; each level consumes one hex digit of the hash.
+const FANOUT: usize = 16;
+
+/// Deepest supported tree: 16^6 leaves.
+pub const MAX_MERKLE_DEPTH: u8 = 6;
+
+const LEAF_TAG: &[u8] = b"psi-sync/merkle/leaf";
+const NODE_TAG: &[u8] = b"psi-sync/merkle/node";
+
+/// Digest tree over a local set and the state of the descent.
+///
+/// Both peers must build their trees with the same depth and hash
+/// algorithm. Each round, both send [`digests`](Self::digests) and feed the
+/// peer's message to [`on_digests`](Self::on_digests) until
+/// [`is_done`](Self::is_done); the frontier only depends on both sides'
+/// digests, so the peers stay in step without extra coordination.
+///
+/// # Example
+/// ```ignore
+/// use psi_protocol::{MerklePrefilter, PsiProtocol};
+///
+/// let mut filter = MerklePrefilter::new(&items, 4)?;
+/// while !filter.is_done() {
+///     let remote = exchange(filter.digests());
+///     filter.on_digests(&remote)?;
+/// }
+///
+/// // Run PSI only over the differing leaves
+/// let protocol = PsiProtocol::new(&filter.differing_items(&items))?;
+/// let shared_without_psi = filter.matching_indices();
+/// # Ok::<(), psi_protocol::PsiError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct MerklePrefilter {
+    depth: u8,
+    /// `levels[l]` holds the 16^l digests of level `l`; level 0 is the root
+    levels: Vec<Vec<[u8; 32]>>,
+    /// Item hashes with their index in the caller's slice, sorted by hash
+    sorted: Vec<(ItemId, usize)>,
+    /// Level of the nodes in `frontier`
+    level: u8,
+    /// Nodes whose digests are compared in the next round
+    frontier: Vec<u32>,
+    /// Leaves whose digests differ, once the descent reached the leaves
+    differing: Vec<u32>,
+}
+
+impl MerklePrefilter {
+    /// Build the tree over `items` hashed with the default algorithm.
+    ///
+    /// `depth` is the number of levels below the root; the tree has 16^depth
+    /// leaves. Pick it so that a leaf holds a few items on average.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `depth` exceeds
+    /// [`MAX_MERKLE_DEPTH`]
+    pub fn new(items: &[Vec<u8>], depth: u8) -> Result<Self> {
+        Self::new_with_hash(items, depth, HashAlgorithm::default())
+    }
+
+    /// Build the tree over `items` hashed with the given algorithm.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `depth` exceeds
+    /// [`MAX_MERKLE_DEPTH`]
+    pub fn new_with_hash(items: &[Vec<u8>], depth: u8, algorithm: HashAlgorithm) -> Result<Self> {
+        if depth > MAX_MERKLE_DEPTH {
+            return Err(PsiError::InvalidConfig(format!(
+                "Merkle depth must be at most {}, got {}",
+                MAX_MERKLE_DEPTH, depth
+            )));
+        }
+
+        let mut sorted: Vec<(ItemId, usize)> = items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| (ItemId::of_with(algorithm, item), index))
+            .collect();
+        sorted.sort_unstable();
+        sorted.dedup_by_key(|(id, _)| *id);
+
+        let leaf_count = FANOUT.pow(depth as u32);
+        let mut leaves = Vec::with_capacity(leaf_count);
+        let mut rest = sorted.as_slice();
+        for leaf in 0..leaf_count as u32 {
+            let len = rest.partition_point(|(id, _)| leaf_of(id, depth) == leaf);
+            let mut hasher = Sha256::new();
+            hasher.update(LEAF_TAG);
+            for (id, _) in &rest[..len] {
+                hasher.update(id.as_bytes());
+            }
+            leaves.push(hasher.finalize().into());
+            rest = &rest[len..];
+        }
+
+        let mut levels = vec![leaves];
+        while levels[0].len() > 1 {
+            let parents = levels[0]
+                .chunks(FANOUT)
+                .map(|children| {
+                    let mut hasher = Sha256::new();
+                    hasher.update(NODE_TAG);
+                    for child in children {
+                        hasher.update(child);
+                    }
+                    hasher.finalize().into()
+                })
+                .collect();
+            levels.insert(0, parents);
+        }
+
+        Ok(Self {
+            depth,
+            levels,
+            sorted,
+            level: 0,
+            frontier: vec![0],
+            differing: Vec::new(),
+        })
+    }
+
+    /// Number of levels below the root.
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    /// Digest of the whole set.
+    ///
+    /// Equal roots mean equal sets; exchanging them first is enough to skip
+    /// a sync entirely.
+    pub fn root(&self) -> [u8; 32] {
+        self.levels[0][0]
+    }
+
+    /// Returns true once the differing leaves are known.
+    pub fn is_done(&self) -> bool {
+        self.frontier.is_empty()
+    }
+
+    /// Digests of the current frontier, to send to the peer.
+    ///
+    /// Empty once the descent is done.
+    pub fn digests(&self) -> MerkleDigestsMessage {
+        let level = &self.levels[self.level as usize];
+        MerkleDigestsMessage::new(
+            self.level,
+            self.frontier
+                .iter()
+                .map(|node| level[*node as usize])
+                .collect(),
+        )
+    }
+
+    /// Compare the peer's frontier digests with ours and descend one level.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` if the message is for another
+    /// level, and `PsiError::LengthMismatch` if it holds a different number
+    /// of digests than our frontier
+    pub fn on_digests(&mut self, remote: &MerkleDigestsMessage) -> Result<()> {
+        if self.is_done() {
+            return Ok(());
+        }
+        if remote.level != self.level {
+            return Err(PsiError::InvalidEncoding(format!(
+                "Merkle digests for level {}, expected level {}",
+                remote.level, self.level
+            )));
+        }
+        if remote.digests.len() != self.frontier.len() {
+            return Err(PsiError::LengthMismatch {
+                phase: Phase::Prepare,
+                expected: self.frontier.len(),
+                actual: remote.digests.len(),
+            });
+        }
+
+        let level = &self.levels[self.level as usize];
+        let mismatched: Vec<u32> = self
+            .frontier
+            .iter()
+            .zip(&remote.digests)
+            .filter(|(node, digest)| level[**node as usize] != **digest)
+            .map(|(node, _)| *node)
+            .collect();
+
+        if self.level == self.depth {
+            self.differing = mismatched;
+            self.frontier.clear();
+        } else {
+            self.level += 1;
+            self.frontier = mismatched
+                .into_iter()
+                .flat_map(|node| node * FANOUT as u32..(node + 1) * FANOUT as u32)
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// Leaves whose contents differ between the two sets.
+    ///
+    /// Only meaningful once [`is_done`](Self::is_done).
+    pub fn differing_leaves(&self) -> &[u32] {
+        &self.differing
+    }
+
+    /// Indices into the caller's items that fall in differing leaves.
+    ///
+    /// These are the only items PSI still needs to run over. Duplicate items
+    /// appear once. Only meaningful once [`is_done`](Self::is_done).
+    pub fn differing_indices(&self) -> Vec<usize> {
+        self.indices(true)
+    }
+
+    /// Indices into the caller's items that fall in identical leaves.
+    ///
+    /// These items are held by both peers. Only meaningful once
+    /// [`is_done`](Self::is_done).
+    pub fn matching_indices(&self) -> Vec<usize> {
+        self.indices(false)
+    }
+
+    /// The caller's items that fall in differing leaves, ready to feed to
+    /// [`PsiProtocol::new`](crate::PsiProtocol::new).
+    ///
+    /// `items` must be the slice the tree was built from.
+    pub fn differing_items(&self, items: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        self.differing_indices()
+            .into_iter()
+            .map(|index| items[index].clone())
+            .collect()
+    }
+
+    fn indices(&self, differing: bool) -> Vec<usize> {
+        let mut indices: Vec<usize> = self
+            .sorted
+            .iter()
+            .filter(|(id, _)| {
+                self.differing
+                    .binary_search(&leaf_of(id, self.depth))
+                    .is_ok()
+                    == differing
+            })
+            .map(|(_, index)| *index)
+            .collect();
+        indices.sort_unstable();
+        indices
+    }
+}
+
+/// Leaf holding an item: the first `depth` hex digits of its hash.
+fn leaf_of(id: &ItemId, depth: u8) -> u32 {
+    if depth == 0 {
+        return 0;
+    }
+    let bytes = id.as_bytes();
+    let prefix = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    prefix >> (32 - 4 * depth as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::numbered;
+
+    fn sync(alice: &mut MerklePrefilter, bob: &mut MerklePrefilter) -> usize {
+        let mut rounds = 0;
+        while !alice.is_done() {
+            let alice_digests = alice.digests();
+            alice.on_digests(&bob.digests()).unwrap();
+            bob.on_digests(&alice_digests).unwrap();
+            rounds += 1;
+        }
+        assert!(bob.is_done());
+        rounds
+    }
+
+    #[test]
+    fn test_identical_sets_stop_at_root() {
+        let set = numbered(0..500);
+        let mut alice = MerklePrefilter::new(&set, 3).unwrap();
+        let mut bob = MerklePrefilter::new(&set, 3).unwrap();
+        assert_eq!(alice.root(), bob.root());
+
+        assert_eq!(sync(&mut alice, &mut bob), 1);
+        assert!(alice.differing_indices().is_empty());
+        assert_eq!(alice.matching_indices().len(), 500);
+    }
+
+    #[test]
+    fn test_prefilter_isolates_differences() {
+        let mut alice_items = numbered(0..2000);
+        let mut bob_items = numbered(0..2000);
+        alice_items.push(b"only-alice".to_vec());
+        bob_items.push(b"only-bob".to_vec());
+        bob_items.remove(10);
+
+        let mut alice = MerklePrefilter::new(&alice_items, 3).unwrap();
+        let mut bob = MerklePrefilter::new(&bob_items, 3).unwrap();
+        assert_eq!(sync(&mut alice, &mut bob), 4);
+        assert!(alice.differing_leaves().len() <= 3);
+        assert_eq!(alice.differing_leaves(), bob.differing_leaves());
+
+        let alice_diff = alice.differing_items(&alice_items);
+        let bob_diff = bob.differing_items(&bob_items);
+        assert!(alice_diff.len() < 20);
+        assert!(alice_diff.contains(&b"only-alice".to_vec()));
+        assert!(alice_diff.contains(&b"item-10".to_vec()));
+        assert!(bob_diff.contains(&b"only-bob".to_vec()));
+
+        // Items outside the differing leaves are shared as-is
+        let mut alice_matching: Vec<_> = alice
+            .matching_indices()
+            .into_iter()
+            .map(|index| alice_items[index].clone())
+            .collect();
+        let mut bob_matching: Vec<_> = bob
+            .matching_indices()
+            .into_iter()
+            .map(|index| bob_items[index].clone())
+            .collect();
+        alice_matching.sort();
+        bob_matching.sort();
+        assert_eq!(alice_matching, bob_matching);
+
+        // PSI over the differing leaves recovers the remaining shared items
+        let (alice_result, _) = crate::run_local_psi(&alice_diff, &bob_diff).unwrap();
+        assert_eq!(
+            alice_result.len() + alice_matching.len(),
+            alice_items.len() - 2
+        );
+    }
+
+    #[test]
+    fn test_prefilter_rejects_bad_input() {
+        assert!(matches!(
+            MerklePrefilter::new(&[], MAX_MERKLE_DEPTH + 1),
+            Err(PsiError::InvalidConfig(_))
+        ));
+
+        let mut filter = MerklePrefilter::new(&numbered(0..10), 1).unwrap();
+        assert!(matches!(
+            filter.on_digests(&MerkleDigestsMessage::new(1, vec![[0; 32]])),
+            Err(PsiError::InvalidEncoding(_))
+        ));
+        assert_eq!(
+            filter.on_digests(&MerkleDigestsMessage::new(0, vec![])),
+            Err(PsiError::LengthMismatch {
+                phase: Phase::Prepare,
+                expected: 1,
+                actual: 0
+            })
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Private equality test of two single values.
+//!
+//! Each side holds exactly one value and both learn only whether the values
+//! are equal: the ECDH exchange of the full protocol with one point per
+//! message and none of the set bookkeeping (no hashes to track, no padding,
+//! no shuffling). Useful for dedupe checks or comparing one-time codes.
+//!
+//! The flow mirrors [`PsiProtocol`](crate::PsiProtocol) and reuses its
+//! message types, so the [`wire`](crate::wire) format, pre-shared keys and
+//! parameter digests apply unchanged:
+//!
+//! 1. [`PrivateEq::new`] and send [`PrivateEq::message`]
+//! 2. [`PrivateEq::compute`] with the peer's message, send the reply
+//! 3. [`PendingEq::finish`] with the peer's reply
+//!
+//! As with the full protocol, a dishonest peer can make its own answer come
+//! out wrong; it cannot learn the value.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::private_eq::PrivateEq;
+//!
+//! let alice = PrivateEq::new(b"483-921");
+//! let bob = PrivateEq::new(b"483-921");
+//!
+//! let (alice_msg, bob_msg) = (alice.message(), bob.message());
+//! let (alice, alice_reply) = alice.compute(bob_msg)?;
+//! let (bob, bob_reply) = bob.compute(alice_msg)?;
+//!
+//! assert!(alice.finish(bob_reply)?);
+//! assert!(bob.finish(alice_reply)?);
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::config::PsiConfig;
+use crate::crypto::{blind_point, hash_bytes_with, hash_to_point_in, random_scalar};
+use crate::error::{Phase, PsiError, Result};
+use crate::messages::{BlindedPointsMessage, DoubleBlindedPointsMessage};
+use crate::psk::{BLINDED_LABEL, DOUBLE_BLINDED_LABEL};
+use crate::secret::SecretScalar;
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::traits::IsIdentity;
+
+/// One side of a private equality test, before the peer's message.
+#[derive(Debug, Clone)]
+pub struct PrivateEq {
+    /// Secret scalar, redacted from `Debug` output
+    secret: SecretScalar,
+    /// Our value, hashed to the curve and blinded
+    blinded: CompressedRistretto,
+    config: PsiConfig,
+}
+
+/// One side of a private equality test, waiting for the peer's reply.
+///
+/// The secret is already dropped; only the expected answer is kept.
+#[derive(Debug, Clone)]
+pub struct PendingEq {
+    /// Our secret applied to the peer's blinded value
+    expected: CompressedRistretto,
+    config: PsiConfig,
+}
+
+impl PrivateEq {
+    /// Blind `value` with a fresh secret and the default configuration.
+    pub fn new(value: &[u8]) -> Self {
+        Self::new_with_config(value, PsiConfig::default())
+    }
+
+    /// Blind `value` with a fresh secret.
+    ///
+    /// Only the hash algorithm, domain and pre-shared key of `config` apply;
+    /// both sides must agree on them as for the full protocol.
+    pub fn new_with_config(value: &[u8], config: PsiConfig) -> Self {
+        let secret = random_scalar();
+        let point = hash_to_point_in(config.domain(), &hash_bytes_with(config.hash(), value));
+        Self {
+            blinded: blind_point(&point, &secret),
+            secret: SecretScalar::new(secret),
+            config,
+        }
+    }
+
+    /// Our blinded value, to send to the peer.
+    pub fn message(&self) -> BlindedPointsMessage {
+        let points = [self.blinded];
+        let mut message = BlindedPointsMessage::new(points.to_vec());
+        message.authentication = self.config.authenticate(BLINDED_LABEL, &points);
+        message.parameters = Some(self.config.parameter_digest());
+        message
+    }
+
+    /// Apply our secret to the peer's blinded value.
+    ///
+    /// Returns the pending side and the reply to send to the peer.
+    ///
+    /// # Errors
+    /// Returns `PsiError::LengthMismatch` if the message does not hold
+    /// exactly one point, `PsiError::InvalidPoint` if that point is not a
+    /// valid encoding or is the identity, `PsiError::ParameterMismatch` if
+    /// the peer used other parameters, or, with a pre-shared key,
+    /// `PsiError::CryptoError` if the message's MAC does not verify
+    pub fn compute(
+        self,
+        remote_msg: BlindedPointsMessage,
+    ) -> Result<(PendingEq, DoubleBlindedPointsMessage)> {
+        self.config.check_authentication(
+            BLINDED_LABEL,
+            &remote_msg.blinded_points,
+            remote_msg.authentication.as_ref(),
+        )?;
+        self.config
+            .check_parameters(Phase::Compute, remote_msg.parameters.as_ref())?;
+        let remote = single_point(Phase::Compute, &remote_msg.blinded_points)?;
+        let point = remote
+            .decompress()
+            .filter(|point| !point.is_identity())
+            .ok_or(PsiError::InvalidPoint {
+                phase: Phase::Compute,
+                index: 0,
+            })?;
+
+        // We keep and send a·(b·H(y)); the peer sends back b·(a·H(x)),
+        // which is the same point iff x = y
+        let expected = (self.secret.expose() * point).compress();
+        let points = [expected];
+        let mut reply = DoubleBlindedPointsMessage::new(points.to_vec());
+        reply.authentication = self.config.authenticate(DOUBLE_BLINDED_LABEL, &points);
+        reply.parameters = Some(self.config.parameter_digest());
+        let pending = PendingEq {
+            expected,
+            config: self.config,
+        };
+        Ok((pending, reply))
+    }
+}
+
+impl PendingEq {
+    /// Compare the peer's reply with our answer.
+    ///
+    /// # Errors
+    /// Returns `PsiError::LengthMismatch` if the reply does not hold exactly
+    /// one point, `PsiError::ParameterMismatch` if the peer used other
+    /// parameters, or, with a pre-shared key, `PsiError::CryptoError` if the
+    /// reply's MAC does not verify
+    pub fn finish(self, remote_msg: DoubleBlindedPointsMessage) -> Result<bool> {
+        self.config.check_authentication(
+            DOUBLE_BLINDED_LABEL,
+            &remote_msg.double_blinded_points,
+            remote_msg.authentication.as_ref(),
+        )?;
+        self.config
+            .check_parameters(Phase::Finalize, remote_msg.parameters.as_ref())?;
+        let remote = single_point(Phase::Finalize, &remote_msg.double_blinded_points)?;
+        Ok(*remote == self.expected)
+    }
+}
+
+/// The only point of a message.
+fn single_point(phase: Phase, points: &[CompressedRistretto]) -> Result<&CompressedRistretto> {
+    match points {
+        [point] => Ok(point),
+        _ => Err(PsiError::LengthMismatch {
+            phase,
+            expected: 1,
+            actual: points.len(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(alice: &[u8], bob: &[u8]) -> (bool, bool) {
+        let (alice, bob) = (PrivateEq::new(alice), PrivateEq::new(bob));
+        let (alice_msg, bob_msg) = (alice.message(), bob.message());
+        let (alice, alice_reply) = alice.compute(bob_msg).unwrap();
+        let (bob, bob_reply) = bob.compute(alice_msg).unwrap();
+        (
+            alice.finish(bob_reply).unwrap(),
+            bob.finish(alice_reply).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_both_sides_learn_equality() {
+        assert_eq!(run(b"483-921", b"483-921"), (true, true));
+        assert_eq!(run(b"483-921", b"483-922"), (false, false));
+    }
+
+    #[test]
+    fn test_rejects_malformed_messages() {
+        let alice = PrivateEq::new(b"apple");
+        let parameters = alice.message().parameters;
+        let mut two = BlindedPointsMessage::new(vec![alice.blinded; 2]);
+        two.parameters = parameters;
+        assert!(matches!(
+            alice.clone().compute(two),
+            Err(PsiError::LengthMismatch { expected: 1, .. })
+        ));
+        let mut identity = BlindedPointsMessage::new(vec![CompressedRistretto([0u8; 32])]);
+        identity.parameters = parameters;
+        assert!(matches!(
+            alice.clone().compute(identity),
+            Err(PsiError::InvalidPoint { index: 0, .. })
+        ));
+
+        let (pending, _) = alice.compute(PrivateEq::new(b"apple").message()).unwrap();
+        let mut empty = DoubleBlindedPointsMessage::new(vec![]);
+        empty.parameters = parameters;
+        assert!(matches!(
+            pending.finish(empty),
+            Err(PsiError::LengthMismatch { actual: 0, .. })
+        ));
+    }
+}
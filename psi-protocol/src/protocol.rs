@@ -1,19 +1,121 @@
 //! Core protocol implementation using the type-state pattern.
 
-use crate::crypto::{decompress_point, hash_inputs_to_points, blind_points};
-use crate::messages::{BlindedPointsMessage, DoubleBlindedPointsMessage, PsiResult};
-use crate::state::{PsiState, PreparedState, DoubleBlindedState, FinalState};
-use crate::error::{PsiError, Result};
-use curve25519_dalek::ristretto::CompressedRistretto;
+use crate::collections::{FastMap, FastSet};
+use crate::config::{MessageOrder, PsiConfig};
+#[cfg(feature = "parallel")]
+use crate::crypto::parallel_map;
+use crate::crypto::{
+    blind_points_parallel, decompress_or_basepoint, decompress_point, derive_layout_seed,
+    derive_scalar, hash_inputs_sorted, random_point, random_point_from, random_scalar,
+};
+use crate::item_id::ItemId;
+use crate::messages::{
+    AlignedMatch, BlindedPointsMessage, CardinalityMessage, DoubleBlindedPointsMessage,
+    OneRoundResponseMessage, PsiResult,
+};
+use crate::psk::{BLINDED_LABEL, DOUBLE_BLINDED_LABEL, ONE_ROUND_LABEL};
+use crate::state::{PsiState, PreparedState, DoubleBlindedState, FinalState, MessageSlots};
+use crate::error::{Phase, PsiError, RecoverableError, Result};
+use crate::stats::{PsiStats, POINT_LEN};
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::Scalar;
+use rand::rngs::{OsRng, StdRng};
+use rand::seq::SliceRandom;
+use rand::{CryptoRng, RngCore, SeedableRng};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
 
 /// Protocol wrapper that holds the current state.
 ///
 /// This generic wrapper enforces type-level state tracking - each state
 /// has different available methods, preventing invalid operations.
-#[derive(Debug)]
+///
+/// `PsiProtocol<S>` is `Send + Sync + Clone` for every state `S`, so a
+/// protocol run can be moved into a spawned thread or async task. Cloning a
+/// prepared protocol duplicates its secret scalar.
+#[derive(Debug, Clone)]
 pub struct PsiProtocol<S: PsiState> {
     state: S,
+    config: PsiConfig,
+    stats: PsiStats,
+    received: Received,
+}
+
+/// Digests of the remote messages a run has handled, to recognize
+/// retransmissions.
+#[derive(Debug, Clone, Copy, Default)]
+struct Received {
+    blinded: Option<[u8; 32]>,
+    double_blinded: Option<[u8; 32]>,
+}
+
+// Compile-time guarantee that protocol runs can cross thread boundaries.
+const _: () = {
+    const fn assert_send_sync_clone<T: Send + Sync + Clone>() {}
+    assert_send_sync_clone::<PsiProtocol<PreparedState>>();
+    assert_send_sync_clone::<PsiProtocol<DoubleBlindedState>>();
+    assert_send_sync_clone::<PsiProtocol<FinalState>>();
+};
+
+impl<S: PsiState> PsiProtocol<S> {
+    /// Get the configuration this protocol run was created with.
+    pub fn config(&self) -> &PsiConfig {
+        &self.config
+    }
+
+    /// Counters and timings of this run so far.
+    ///
+    /// Each phase adds its figures and hands them on to the next state, so
+    /// the final state reports the whole run.
+    pub fn stats(&self) -> &PsiStats {
+        &self.stats
+    }
+
+    /// Assemble a protocol from a state built in another module.
+    pub(crate) fn from_parts(state: S, config: PsiConfig) -> Self {
+        Self {
+            state,
+            config,
+            stats: PsiStats::default(),
+            received: Received::default(),
+        }
+    }
+
+    /// Returns true if this run already handled `remote_msg`, e.g. when an
+    /// at-least-once transport delivers it twice.
+    ///
+    /// Not persisted: a session restored from a store does not recognize
+    /// messages handled before it was saved.
+    pub(crate) fn handled_blinded(&self, remote_msg: &BlindedPointsMessage) -> bool {
+        self.received.blinded == Some(received_digest(BLINDED_LABEL, &remote_msg.blinded_points))
+    }
+
+    /// [`handled_blinded`](Self::handled_blinded) for the double-blinded message.
+    pub(crate) fn handled_double_blinded(&self, remote_msg: &DoubleBlindedPointsMessage) -> bool {
+        self.received.double_blinded
+            == Some(received_digest(
+                DOUBLE_BLINDED_LABEL,
+                &remote_msg.double_blinded_points,
+            ))
+    }
+
+    /// Our statistics plus a finished matching phase.
+    fn finalized_stats(&self, started: Instant, received: usize, matches: usize) -> PsiStats {
+        PsiStats {
+            finalize: started.elapsed(),
+            bytes_processed: self.stats.bytes_processed + received * POINT_LEN,
+            matches,
+            ..self.stats
+        }
+    }
+
+    /// Get the current state, for extensions living in other modules.
+    #[cfg_attr(not(any(feature = "payload", feature = "tokio")), allow(dead_code))]
+    pub(crate) fn state(&self) -> &S {
+        &self.state
+    }
 }
 
 impl PsiProtocol<PreparedState> {
@@ -42,32 +144,243 @@ impl PsiProtocol<PreparedState> {
     /// # Ok::<(), psi_protocol::PsiError>(())
     /// ```
     pub fn new(items: &[Vec<u8>]) -> Result<Self> {
-        if items.is_empty() {
-            return Err(PsiError::EmptyInput);
-        }
+        Self::new_with_config(items, PsiConfig::default())
+    }
 
-        let secret = crate::crypto::random_scalar();
-        let hash_to_point = hash_inputs_to_points(items);
-        let hash_to_blinded = blind_points(&hash_to_point, &secret);
+    /// Create a new protocol instance from items with a custom configuration.
+    ///
+    /// # Arguments
+    /// * `items` - Slice of byte vectors representing the private set
+    /// * `config` - Protocol configuration (see [`PsiConfig::builder`])
+    ///
+    /// # Returns
+    /// A `PsiProtocol<PreparedState>` ready for message exchange
+    ///
+    /// # Errors
+    /// Returns `PsiError::EmptyInput` if items is empty and the configuration
+    /// does not [`allow_empty`](crate::PsiConfigBuilder::allow_empty), or
+    /// `PsiError::LimitExceeded` if items exceeds the configured local limit
+    ///
+    /// # Example
+    /// ```ignore
+    /// use psi_protocol::{Padding, PsiConfig, PsiProtocol};
+    ///
+    /// let config = PsiConfig::builder().padding(Padding::ToMultipleOf(64)).build()?;
+    /// let alice = PsiProtocol::new_with_config(&items, config)?;
+    /// # Ok::<(), psi_protocol::PsiError>(())
+    /// ```
+    pub fn new_with_config(items: &[Vec<u8>], config: PsiConfig) -> Result<Self> {
+        Self::with_secret(items, random_scalar(), config)
+    }
 
-        // Build reverse mapping from blinded point to hash
-        let blinded_to_hash: HashMap<CompressedRistretto, [u8; 32]> =
-            hash_to_blinded.iter()
-                .map(|(hash, point)| (*point, *hash))
-                .collect();
+    /// Create a new protocol instance with a secret derived from key material.
+    ///
+    /// The blinding scalar is derived with HKDF-SHA512 from `ikm` and
+    /// `context`, so several stateless workers sharing the same key material
+    /// produce identical blinded points and act as a single party. The raw
+    /// scalar never needs to be distributed. Padding points and the shuffle
+    /// are derived from the same key material, so every worker lays out the
+    /// same message under any [`MessageOrder`] and padding: one worker can
+    /// send the message and another finalize it. Workers must run the same
+    /// version of this crate, since the layout follows `rand`'s `StdRng`.
+    ///
+    /// Reusing a derived secret across sessions makes blinded values linkable
+    /// between those sessions; vary `context` when that matters.
+    ///
+    /// # Arguments
+    /// * `items` - Slice of byte vectors representing the private set
+    /// * `ikm` - Input key material, at least 32 bytes of secret entropy
+    /// * `context` - Application or session context for domain separation
+    /// * `config` - Protocol configuration
+    ///
+    /// # Errors
+    /// Returns `PsiError::CryptoError` if `ikm` is shorter than 32 bytes, plus
+    /// the errors of [`PsiProtocol::new_with_config`]
+    ///
+    /// # Example
+    /// ```ignore
+    /// let config = PsiConfig::default();
+    /// let worker = PsiProtocol::with_derived_secret(&items, &shared_key, b"tenant-42", config)?;
+    /// # Ok::<(), psi_protocol::PsiError>(())
+    /// ```
+    pub fn with_derived_secret(
+        items: &[Vec<u8>],
+        ikm: &[u8],
+        context: &[u8],
+        config: PsiConfig,
+    ) -> Result<Self> {
+        let secret = derive_scalar(ikm, context)?;
+        let mut rng = StdRng::from_seed(derive_layout_seed(ikm, context)?);
+        Self::with_secret_and_rng(items, secret, config, &mut rng)
+    }
 
-        // Track the order of hashes (consistent with blinded_points iteration)
-        let hash_order: Vec<[u8; 32]> = hash_to_blinded.keys().copied().collect();
+    /// Prepare a fresh run over the same items with a new secret.
+    ///
+    /// Repeated syncs over an unchanged set should not resend the same
+    /// blinded points, or the remote can link the sessions. This multiplies
+    /// every blinded point by a fresh random scalar `r`, which gives the
+    /// same points as blinding from scratch with `r·a` but skips hashing and
+    /// hash-to-curve. Padding and message order are drawn again, so
+    /// positions do not link the sessions either.
+    ///
+    /// # Errors
+    /// Returns `PsiError::CryptoError` if a stored point fails to decompress,
+    /// which cannot happen for points produced by this crate
+    ///
+    /// # Example
+    /// ```ignore
+    /// let mut alice = PsiProtocol::new(&items)?;
+    /// loop {
+    ///     let session = alice.clone();
+    ///     // run `session` to completion...
+    ///     alice = alice.rerandomize()?;
+    /// }
+    /// ```
+    pub fn rerandomize(&self) -> Result<Self> {
+        let started = Instant::now();
+        let r = random_scalar();
+        let items = self.state.blinded_items();
+        let rerandomized =
+            crate::crypto::parallel_map(items, self.config.threads(), |(hash, point)| {
+                decompress_point(point).map(|point| (*hash, (r * point).compress()))
+            })
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+        Self::from_blinded(
+            rerandomized,
+            self.state.secret_scalar() * r,
+            self.config.clone(),
+        )
+        .map(|protocol| protocol.prepared_since(started))
+    }
+
+    /// Record the time spent preparing, from `started` until now.
+    fn prepared_since(mut self, started: Instant) -> Self {
+        self.stats.prepare = started.elapsed();
+        self
+    }
+
+    /// Shared constructor once the secret scalar has been chosen.
+    fn with_secret(items: &[Vec<u8>], secret: Scalar, config: PsiConfig) -> Result<Self> {
+        Self::with_secret_and_rng(items, secret, config, &mut OsRng)
+    }
+
+    /// Same as [`with_secret`](Self::with_secret), drawing padding points
+    /// and the shuffle from `rng`.
+    fn with_secret_and_rng<R: RngCore + CryptoRng>(
+        items: &[Vec<u8>],
+        secret: Scalar,
+        config: PsiConfig,
+        rng: &mut R,
+    ) -> Result<Self> {
+        config.check_local_set(items.len())?;
+
+        let started = Instant::now();
+        let hashed =
+            hash_inputs_sorted(config.hash(), config.domain(), items, config.hash_threads());
+        Self::from_hashed_with_rng(&hashed, secret, config, rng)
+            .map(|protocol| protocol.prepared_since(started))
+    }
 
-        Ok(Self {
-            state: PreparedState::new(secret, hash_to_blinded, blinded_to_hash, hash_order),
-        })
+    /// Constructor from items already hashed to points.
+    ///
+    /// `hashed` must be sorted by hash without duplicates, as returned by
+    /// `hash_inputs_sorted`.
+    pub(crate) fn from_hashed(
+        hashed: &[([u8; 32], RistrettoPoint)],
+        secret: Scalar,
+        config: PsiConfig,
+    ) -> Result<Self> {
+        Self::from_hashed_with_rng(hashed, secret, config, &mut OsRng)
+    }
+
+    /// Same as [`from_hashed`](Self::from_hashed), drawing padding points
+    /// and the shuffle from `rng`.
+    fn from_hashed_with_rng<R: RngCore + CryptoRng>(
+        hashed: &[([u8; 32], RistrettoPoint)],
+        secret: Scalar,
+        config: PsiConfig,
+        rng: &mut R,
+    ) -> Result<Self> {
+        config.check_local_set(hashed.len())?;
+
+        let started = Instant::now();
+        let blinded_items = blind_points_parallel(hashed, &secret, config.threads());
+        Self::from_blinded_with_rng(blinded_items, secret, config, rng)
+            .map(|protocol| protocol.prepared_since(started))
+    }
+
+    /// Constructor from items already blinded with `secret`.
+    ///
+    /// `blinded_items` must be sorted by hash without duplicates; this only
+    /// lays out the message.
+    pub(crate) fn from_blinded(
+        blinded_items: Vec<([u8; 32], CompressedRistretto)>,
+        secret: Scalar,
+        config: PsiConfig,
+    ) -> Result<Self> {
+        Self::from_blinded_with_rng(blinded_items, secret, config, &mut OsRng)
+    }
+
+    /// Same as [`from_blinded`](Self::from_blinded), drawing padding points
+    /// and the shuffle from `rng`.
+    pub(crate) fn from_blinded_with_rng<R: RngCore + CryptoRng>(
+        blinded_items: Vec<([u8; 32], CompressedRistretto)>,
+        secret: Scalar,
+        config: PsiConfig,
+        rng: &mut R,
+    ) -> Result<Self> {
+        config.check_local_set(blinded_items.len())?;
+
+        // Lay out the message: one slot per item plus padding slots
+        let padded_len = config.padding().padded_len(blinded_items.len());
+        let mut slots: MessageSlots = blinded_items
+            .iter()
+            .map(|(hash, point)| (Some(*hash), *point))
+            .collect();
+        slots.resize_with(padded_len, || (None, random_point_from(rng)));
+
+        match config.order() {
+            MessageOrder::Shuffled => slots.shuffle(rng),
+            MessageOrder::Sorted => slots.sort_unstable_by_key(|(_, point)| point.to_bytes()),
+        }
+
+        let state = PreparedState::new(secret, blinded_items, slots, &config);
+        trace_event!(
+            config,
+            Phase::Prepare,
+            "blind",
+            items = state.blinded_items().len(),
+            padding = state.message_points().len() - state.blinded_items().len(),
+            order = format!("{:?}", config.order()),
+            sent = crate::trace::points(state.message_points()),
+        );
+
+        Ok(Self::from_prepared(state, config))
+    }
+
+    /// Assemble a prepared protocol, counting the items and message it holds.
+    pub(crate) fn from_prepared(state: PreparedState, config: PsiConfig) -> Self {
+        let stats = PsiStats {
+            local_items: state.blinded_items().len(),
+            bytes_processed: state.message_points().len() * POINT_LEN,
+            ..PsiStats::default()
+        };
+        Self {
+            state,
+            config,
+            stats,
+            received: Received::default(),
+        }
     }
 
     /// Get the blinded points message for exchange with remote party.
     ///
     /// Returns a message containing only blinded points (no hashes)
-    /// that should be sent to the remote party.
+    /// that should be sent to the remote party. The message is built once
+    /// when the protocol is prepared; this clones it. Use
+    /// [`shared_message`](Self::shared_message) to avoid copying the points
+    /// when sending it several times.
     ///
     /// # Returns
     /// A `BlindedPointsMessage` ready to be serialized and sent
@@ -79,13 +392,37 @@ impl PsiProtocol<PreparedState> {
     /// // send_to_remote(alice_msg);
     /// ```
     pub fn message(&self) -> BlindedPointsMessage {
-        // Use hash_order to ensure consistent ordering
-        let blinded_points: Vec<CompressedRistretto> = self.state
-            .hash_order()
-            .iter()
-            .map(|hash| *self.state.blinded_map().get(hash).unwrap())
-            .collect();
-        BlindedPointsMessage::new(blinded_points)
+        BlindedPointsMessage::clone(self.state.message())
+    }
+
+    /// Get the blinded points message without copying it.
+    ///
+    /// Same message as [`message`](Self::message), shared with the
+    /// protocol: cheap to call repeatedly, e.g. for retransmission.
+    pub fn shared_message(&self) -> Arc<BlindedPointsMessage> {
+        Arc::clone(self.state.message())
+    }
+
+    /// Announce the size of our [`message`](Self::message) to the remote party.
+    ///
+    /// Optional: sending this first lets the remote check its limits with
+    /// [`check_cardinality`](Self::check_cardinality) before any points are
+    /// exchanged. The announced count includes padding, so a padded
+    /// configuration only reveals the padded size.
+    pub fn cardinality(&self) -> CardinalityMessage {
+        CardinalityMessage::new(self.state.message_points().len())
+    }
+
+    /// Check the remote's announced size against the configured remote limit.
+    ///
+    /// Use [`CardinalityMessage::check`] once the blinded points arrive to
+    /// make sure the remote kept its word.
+    ///
+    /// # Errors
+    /// Returns `PsiError::LimitExceeded` if the announced size is above
+    /// [`PsiConfig::max_remote_items`]
+    pub fn check_cardinality(&self, remote: &CardinalityMessage) -> Result<()> {
+        self.config.check_remote_len(remote.point_count)
     }
 
     /// Compute double-blinded points from remote's single-blinded points.
@@ -101,7 +438,15 @@ impl PsiProtocol<PreparedState> {
     /// A tuple of (PsiProtocol<DoubleBlindedState>, DoubleBlindedPointsMessage)
     ///
     /// # Errors
-    /// Returns `PsiError::InvalidBlindedPoints` if remote's points cannot be processed
+    /// Returns `PsiError::InvalidPoint` with the position of the first remote
+    /// point that is not a valid encoding, or `PsiError::LimitExceeded` if the
+    /// message exceeds the configured remote limit. In lenient mode, invalid
+    /// points are replaced with random ones instead. With a pre-shared key,
+    /// returns `PsiError::CryptoError` if the message's MAC does not verify.
+    /// Returns `PsiError::ParameterMismatch` if the remote used other
+    /// parameters, see [`PsiConfig::parameter_digest`].
+    /// Every point is processed before an invalid one is reported, so the
+    /// time to fail does not reveal its position.
     ///
     /// # Example
     /// ```ignore
@@ -117,35 +462,466 @@ impl PsiProtocol<PreparedState> {
         self,
         remote_msg: BlindedPointsMessage,
     ) -> Result<(PsiProtocol<DoubleBlindedState>, DoubleBlindedPointsMessage)> {
+        self.try_compute(remote_msg).map_err(|e| e.error)
+    }
+
+    /// Like [`compute`](Self::compute), but hands the protocol back on error.
+    ///
+    /// A malformed or oversized remote message does not destroy the prepared
+    /// state: the error carries the unconsumed protocol, so the caller can
+    /// ask the remote to resend and retry without redoing any blinding.
+    ///
+    /// # Errors
+    /// Returns a [`RecoverableError`] holding the original protocol and the
+    /// same `PsiError` that [`compute`](Self::compute) would return
+    ///
+    /// # Example
+    /// ```ignore
+    /// let alice = match alice.try_compute(bob_msg) {
+    ///     Ok((intermediate, double_msg)) => { /* continue */ }
+    ///     Err(rejected) => {
+    ///         let (alice, error) = rejected.into_parts();
+    ///         // request a resend, then alice.try_compute(resent_msg)
+    ///     }
+    /// };
+    /// ```
+    pub fn try_compute(
+        self,
+        remote_msg: BlindedPointsMessage,
+    ) -> std::result::Result<
+        (PsiProtocol<DoubleBlindedState>, DoubleBlindedPointsMessage),
+        RecoverableError<Self>,
+    > {
+        let started = Instant::now();
+        if let Err(error) = self.check_message(&remote_msg) {
+            trace_event!(self.config, Phase::Compute, "rejected", error = error);
+            return Err(RecoverableError::new(self, error));
+        }
+        match self.double_blind_counted(&remote_msg) {
+            Ok((double_blinded, invalid)) => {
+                Ok(self.to_double_blinded_since(started, &remote_msg, double_blinded, invalid))
+            }
+            Err(error) => Err(RecoverableError::new(self, error)),
+        }
+    }
+
+    /// Compute double-blinded points for one of several peers.
+    ///
+    /// Unlike [`compute`](Self::compute), this borrows the prepared state, so
+    /// one blinded local set can serve many concurrent sessions. Each call
+    /// returns an independent `PsiProtocol<DoubleBlindedState>` that is
+    /// finalized with that peer's double-blinded message.
+    ///
+    /// All peers receive the same [`message`](Self::message) and the same
+    /// secret is used for every session, so colluding peers can link the
+    /// blinded values they received. Prepare a fresh state per peer when that
+    /// matters.
+    ///
+    /// # Errors
+    /// Same as [`compute`](Self::compute)
+    ///
+    /// # Example
+    /// ```ignore
+    /// let server = PsiProtocol::new(&items)?;
+    /// let (session_a, reply_a) = server.compute_for_peer(peer_a_msg)?;
+    /// let (session_b, reply_b) = server.compute_for_peer(peer_b_msg)?;
+    /// # Ok::<(), psi_protocol::PsiError>(())
+    /// ```
+    pub fn compute_for_peer(
+        &self,
+        remote_msg: BlindedPointsMessage,
+    ) -> Result<(PsiProtocol<DoubleBlindedState>, DoubleBlindedPointsMessage)> {
+        let started = Instant::now();
+        self.check_message(&remote_msg)?;
+        let (double_blinded, invalid) = self.double_blind_counted(&remote_msg)?;
+        Ok(self.to_double_blinded_since(started, &remote_msg, double_blinded, invalid))
+    }
+
+    /// Answer an initiator in the one-round protocol variant.
+    ///
+    /// The one-round variant needs a single round trip: the initiator sends
+    /// its [`message`](Self::message), the responder answers with one
+    /// [`OneRoundResponseMessage`] holding both its own blinded points and the
+    /// double-blinded initiator points, and the initiator finishes with
+    /// [`finalize_one_round`](Self::finalize_one_round). Only the initiator
+    /// learns the intersection.
+    ///
+    /// This borrows the prepared state, so a responder can answer many
+    /// initiators with the same blinded set.
+    ///
+    /// # Errors
+    /// Same as [`compute`](Self::compute)
+    ///
+    /// # Example
+    /// ```ignore
+    /// // Responder
+    /// let response = bob.respond_one_round(alice_msg)?;
+    /// // Initiator
+    /// let (_alice_final, result) = alice.finalize_one_round(response)?;
+    /// # Ok::<(), psi_protocol::PsiError>(())
+    /// ```
+    pub fn respond_one_round(
+        &self,
+        initiator_msg: BlindedPointsMessage,
+    ) -> Result<OneRoundResponseMessage> {
+        self.check_message(&initiator_msg)?;
+        let double_blinded = self.double_blind(&initiator_msg)?;
+        let mut response =
+            OneRoundResponseMessage::new(self.state.message_points().to_vec(), double_blinded);
+        response.authentication = self.config.authenticate_lists(
+            ONE_ROUND_LABEL,
+            &[&response.blinded_points, &response.double_blinded_points],
+        );
+        response.parameters = Some(self.config.parameter_digest());
+        Ok(response)
+    }
+
+    /// Compute the intersection from a one-round response (initiator side).
+    ///
+    /// Each double-blinded point `b·a·H(x)` is unblinded with the inverse of
+    /// our secret to get `b·H(x)`, which is compared directly against the
+    /// responder's single-blinded points `b·H(y)`. The double-blinded map in
+    /// the result holds the same values as in the two-round flow.
+    ///
+    /// # Arguments
+    /// * `response` - The responder's answer to our [`message`](Self::message)
+    ///
+    /// # Returns
+    /// A tuple of (PsiProtocol<FinalState>, PsiResult)
+    ///
+    /// # Errors
+    /// Returns `PsiError::CryptoError` if a pre-shared key is configured and
+    /// the response's MAC is missing or does not verify,
+    /// `PsiError::ParameterMismatch` if the response's parameter digest is
+    /// missing or differs from ours, `PsiError::LengthMismatch` if the response does not hold
+    /// exactly one double-blinded point per point of our message,
+    /// `PsiError::LimitExceeded` if the responder's set exceeds the configured
+    /// remote limit, or `PsiError::InvalidPoint` with the position of the
+    /// first invalid double-blinded point, reported after the whole response
+    /// is processed (ignored in lenient mode).
+    pub fn finalize_one_round(
+        self,
+        response: OneRoundResponseMessage,
+    ) -> Result<(PsiProtocol<FinalState>, PsiResult)> {
+        let started = Instant::now();
+        let outcome = self.match_one_round(&response);
+        trace_outcome!(
+            self.config,
+            Phase::Finalize,
+            &outcome,
+            "match-one-round",
+            |result| vec![
+                ("received", response.double_blinded_points.len().to_string()),
+                (
+                    "responder_points",
+                    response.blinded_points.len().to_string()
+                ),
+                ("matches", result.intersection_hashes.len().to_string()),
+            ]
+        );
+        let result = outcome?;
+        let received = response.blinded_points.len() + response.double_blinded_points.len();
+        let stats = PsiStats {
+            remote_points: response.blinded_points.len(),
+            ..self.finalized_stats(started, received, result.len())
+        };
+        Ok((
+            PsiProtocol {
+                state: FinalState::new(result.double_blinded_map.clone()),
+                config: self.config,
+                stats,
+                received: Received::default(),
+            },
+            result,
+        ))
+    }
+
+    /// Unblind a one-round response and match it against the responder's
+    /// blinded points.
+    fn match_one_round(&self, response: &OneRoundResponseMessage) -> Result<PsiResult> {
+        self.config.check_authentication_lists(
+            ONE_ROUND_LABEL,
+            &[&response.blinded_points, &response.double_blinded_points],
+            response.authentication.as_ref(),
+        )?;
+        self.config
+            .check_parameters(Phase::Finalize, response.parameters.as_ref())?;
+        self.config.check_remote_len(response.blinded_points.len())?;
+        let hash_order = self.state.hash_order();
+        if response.double_blinded_points.len() != hash_order.len() {
+            return Err(PsiError::LengthMismatch {
+                phase: Phase::Finalize,
+                expected: hash_order.len(),
+                actual: response.double_blinded_points.len(),
+            });
+        }
+
+        let remote_blinded: FastSet<CompressedRistretto> =
+            response.blinded_points.iter().copied().collect();
+        let inverse = self.state.secret_scalar().invert();
+
+        // Unblind every point before looking at validity, so the time spent
+        // does not depend on where an invalid point sits
+        let unblinded: Vec<(CompressedRistretto, bool)> = response
+            .double_blinded_points
+            .iter()
+            .map(|double_blinded| {
+                let (point, valid) = decompress_or_basepoint(double_blinded);
+                // a^-1 * (b * a * H) = b * H, comparable to the remote's b * H'
+                ((inverse * point).compress(), valid)
+            })
+            .collect();
+        if !self.config.lenient() {
+            // Padding slots can never match, so their points are not checked
+            let invalid = hash_order
+                .iter()
+                .zip(&unblinded)
+                .position(|(slot, (_, valid))| slot.is_some() && !valid);
+            if let Some(index) = invalid {
+                return Err(PsiError::InvalidPoint {
+                    phase: Phase::Finalize,
+                    index,
+                });
+            }
+        }
+
+        let mut intersection_hashes = Vec::new();
+        let mut double_blinded_map = HashMap::new();
+        for ((slot, (unblinded, valid)), double_blinded) in hash_order
+            .iter()
+            .zip(&unblinded)
+            .zip(&response.double_blinded_points)
+        {
+            let Some(hash) = slot else { continue };
+            if *valid && remote_blinded.contains(unblinded) {
+                intersection_hashes.push(ItemId::new(*hash));
+                double_blinded_map.insert(ItemId::new(*hash), *double_blinded);
+            }
+        }
+
+        Ok(PsiResult::new(intersection_hashes, double_blinded_map))
+    }
+
+    /// Double-blind the remote's points without touching our own state.
+    pub(crate) fn double_blind(
+        &self,
+        remote_msg: &BlindedPointsMessage,
+    ) -> Result<Vec<CompressedRistretto>> {
+        self.double_blind_counted(remote_msg)
+            .map(|(double_blinded, _)| double_blinded)
+    }
+
+    /// [`double_blind`](Self::double_blind), also returning how many remote
+    /// points were invalid (always zero unless lenient).
+    pub(crate) fn double_blind_counted(
+        &self,
+        remote_msg: &BlindedPointsMessage,
+    ) -> Result<(Vec<CompressedRistretto>, usize)> {
+        let outcome = self.blind_remote(remote_msg);
+        trace_outcome!(
+            self.config,
+            Phase::Compute,
+            &outcome,
+            "double-blind",
+            |(sent, invalid)| vec![
+                ("received", remote_msg.len().to_string()),
+                ("invalid", invalid.to_string()),
+                ("lenient", self.config.lenient().to_string()),
+                ("path", self.double_blind_path().to_string()),
+                ("sent", crate::trace::points(sent)),
+            ]
+        );
+        outcome
+    }
+
+    /// Name of the double-blinding code path, for traces.
+    #[cfg(feature = "trace")]
+    fn double_blind_path(&self) -> &'static str {
+        #[cfg(feature = "vartime")]
+        if self.config.vartime() {
+            return "vartime batch";
+        }
+        #[cfg(feature = "parallel")]
+        if self.config.compute_threads() > 1 {
+            return "parallel constant-time";
+        }
+        "constant-time"
+    }
+
+    fn blind_remote(
+        &self,
+        remote_msg: &BlindedPointsMessage,
+    ) -> Result<(Vec<CompressedRistretto>, usize)> {
+        self.config.check_remote_len(remote_msg.len())?;
+
         // Compute double-blinded values from remote's single-blinded points
         // These are: my_secret * remote_blinded_point
         // This will be sent back to the remote party
-        let double_blinded_to_send: Vec<CompressedRistretto> = remote_msg
+        let lenient = self.config.lenient();
+
+        #[cfg(feature = "vartime")]
+        if self.config.vartime() {
+            return self.double_blind_vartime(remote_msg);
+        }
+
+        // Invalid points are blinded as the basepoint and flagged, so every
+        // point costs the same and errors are reported after the whole batch
+        let blind_one = |blinded_point: &CompressedRistretto| {
+            let (point, valid) = decompress_or_basepoint(blinded_point);
+            ((self.state.secret_scalar() * point).compress(), valid)
+        };
+
+        #[cfg(feature = "parallel")]
+        let double_blinded: Vec<(CompressedRistretto, bool)> = if self.config.compute_threads() > 1
+        {
+            // Chunks are joined in order, so positions match the remote message
+            parallel_map(
+                &remote_msg.blinded_points,
+                self.config.compute_threads(),
+                blind_one,
+            )
+        } else {
+            remote_msg.blinded_points.iter().map(blind_one).collect()
+        };
+        #[cfg(not(feature = "parallel"))]
+        let double_blinded: Vec<(CompressedRistretto, bool)> =
+            remote_msg.blinded_points.iter().map(blind_one).collect();
+
+        if !lenient {
+            if let Some(index) = double_blinded.iter().position(|(_, valid)| !valid) {
+                return Err(PsiError::InvalidPoint {
+                    phase: Phase::Compute,
+                    index,
+                });
+            }
+        }
+        let invalid = double_blinded.iter().filter(|(_, valid)| !valid).count();
+        let double_blinded = double_blinded
+            .into_iter()
+            // Keep the position so the remote can still align our answer
+            .map(|(point, valid)| if valid { point } else { random_point() })
+            .collect();
+        Ok((double_blinded, invalid))
+    }
+
+    /// Variable-time batch version of the double-blinding loop.
+    #[cfg(feature = "vartime")]
+    fn double_blind_vartime(
+        &self,
+        remote_msg: &BlindedPointsMessage,
+    ) -> Result<(Vec<CompressedRistretto>, usize)> {
+        let (points, valid): (Vec<RistrettoPoint>, Vec<bool>) = remote_msg
             .blinded_points
             .iter()
-            .map(|blinded_point| {
-                let point = decompress_point(blinded_point)?;
-                Ok((self.state.secret_scalar() * point).compress())
-            })
-            .collect::<Result<Vec<_>>>()?;
+            .map(decompress_or_basepoint)
+            .unzip();
+        if !self.config.lenient() {
+            if let Some(index) = valid.iter().position(|valid| !valid) {
+                return Err(PsiError::InvalidPoint {
+                    phase: Phase::Compute,
+                    index,
+                });
+            }
+        }
+
+        let mut double_blinded =
+            crate::crypto::vartime_blind_batch(&points, self.state.secret_scalar());
+        // Keep the position so the remote can still align our answer
+        let mut invalid = 0;
+        for (point, valid) in double_blinded.iter_mut().zip(valid) {
+            if !valid {
+                *point = random_point();
+                invalid += 1;
+            }
+        }
+        Ok((double_blinded, invalid))
+    }
 
-        // Create double-blinded state with hash_order
+    /// Verify the remote's MAC when a pre-shared key is configured, and its
+    /// parameter digest when it carries one.
+    pub(crate) fn check_message(&self, remote_msg: &BlindedPointsMessage) -> Result<()> {
+        self.config.check_authentication(
+            BLINDED_LABEL,
+            &remote_msg.blinded_points,
+            remote_msg.authentication.as_ref(),
+        )?;
+        self.config
+            .check_parameters(Phase::Compute, remote_msg.parameters.as_ref())
+    }
+
+    /// Build the double-blinded state once the remote's points are processed.
+    pub(crate) fn to_double_blinded(
+        &self,
+        double_blinded_to_send: Vec<CompressedRistretto>,
+    ) -> (PsiProtocol<DoubleBlindedState>, DoubleBlindedPointsMessage) {
+        let received = double_blinded_to_send.len();
+        // Create double-blinded state with hash_order; it keeps the points
+        // we send, so the message can be rebuilt for a resend
         let double_blinded_state = DoubleBlindedState::new(
             *self.state.secret_scalar(),
-            self.state.blinded_map().clone(),
-            self.state.blinded_to_hash().clone(),
-            double_blinded_to_send.clone(),
+            double_blinded_to_send,
             self.state.hash_order().to_vec(),
         );
 
-        // Create the message to send back to remote (contains double-blinded of remote's points)
-        let message = DoubleBlindedPointsMessage::new(double_blinded_to_send);
+        let stats = PsiStats {
+            remote_points: received,
+            // The remote's points in, our answer out
+            bytes_processed: self.stats.bytes_processed + 2 * received * POINT_LEN,
+            ..self.stats
+        };
+        let next = PsiProtocol {
+            state: double_blinded_state,
+            config: self.config.clone(),
+            stats,
+            received: self.received,
+        };
+        let message = next.message();
+        (next, message)
+    }
 
-        Ok((PsiProtocol { state: double_blinded_state }, message))
+    /// [`to_double_blinded`](Self::to_double_blinded) at the end of a
+    /// compute phase that began at `started` and handled `remote_msg`.
+    pub(crate) fn to_double_blinded_since(
+        &self,
+        started: Instant,
+        remote_msg: &BlindedPointsMessage,
+        double_blinded_to_send: Vec<CompressedRistretto>,
+        invalid: usize,
+    ) -> (PsiProtocol<DoubleBlindedState>, DoubleBlindedPointsMessage) {
+        let (mut next, message) = self.to_double_blinded(double_blinded_to_send);
+        next.stats.invalid_points = invalid;
+        next.stats.compute = started.elapsed();
+        next.received.blinded = Some(received_digest(BLINDED_LABEL, &remote_msg.blinded_points));
+        (next, message)
     }
 }
 
 impl PsiProtocol<DoubleBlindedState> {
+    /// Get the double-blinded points message again, e.g. to resend it.
+    ///
+    /// Returns the same message as the [`compute`](PsiProtocol::compute)
+    /// call that produced this state: our double-blinding of the remote's
+    /// points, in the remote's order, with the same MAC and parameters. A
+    /// transport that lost it can resend it without caching the bytes.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let (alice, alice_double_msg) = alice.compute(bob_msg)?;
+    /// send(alice_double_msg);
+    /// // the peer reports a lost message
+    /// send(alice.message());
+    /// # Ok::<(), psi_protocol::PsiError>(())
+    /// ```
+    pub fn message(&self) -> DoubleBlindedPointsMessage {
+        let mut message =
+            DoubleBlindedPointsMessage::new(self.state.double_blinded_from_remote().to_vec());
+        message.authentication = self
+            .config
+            .authenticate(DOUBLE_BLINDED_LABEL, &message.double_blinded_points);
+        message.parameters = Some(self.config.parameter_digest());
+        message
+    }
+
     /// Finalize the protocol by computing the intersection from double-blinded points.
     ///
     /// This consumes the `PsiProtocol<DoubleBlindedState>` and returns:
@@ -159,7 +935,11 @@ impl PsiProtocol<DoubleBlindedState> {
     /// A tuple of (PsiProtocol<FinalState>, PsiResult)
     ///
     /// # Errors
-    /// Returns `PsiError::InvalidBlindedPoints` if remote's points cannot be processed
+    /// Returns `PsiError::LengthMismatch` if the remote did not answer exactly
+    /// one double-blinded point per point of our message,
+    /// `PsiError::ParameterMismatch` if the remote used other parameters, or,
+    /// with a pre-shared key, `PsiError::CryptoError` if the message's MAC
+    /// does not verify
     ///
     /// # Example
     /// ```ignore
@@ -179,32 +959,198 @@ impl PsiProtocol<DoubleBlindedState> {
         self,
         remote_msg: DoubleBlindedPointsMessage,
     ) -> Result<(PsiProtocol<FinalState>, PsiResult)> {
-        // Build a set of double-blinded points we computed from remote's single-blinded points
-        // These are: a*(b*K) for each of Bob's items (where K is Bob's hash)
-        let computed_double_blinded_set: std::collections::HashSet<CompressedRistretto> =
-            self.state.double_blinded_from_remote().iter().cloned().collect();
+        self.try_finalize(remote_msg).map_err(|e| e.error)
+    }
 
-        // The received double-blinded points are: b*(a*H) for each of our items (in order)
-        // For each received point at index i, check if it matches any of our computed points
+    /// Like [`finalize`](Self::finalize), but hands the protocol back on error.
+    ///
+    /// # Errors
+    /// Returns a [`RecoverableError`] holding the original protocol and the
+    /// same `PsiError` that [`finalize`](Self::finalize) would return
+    pub fn try_finalize(
+        self,
+        remote_msg: DoubleBlindedPointsMessage,
+    ) -> std::result::Result<(PsiProtocol<FinalState>, PsiResult), RecoverableError<Self>> {
+        let started = Instant::now();
+        match self.match_remote(&remote_msg) {
+            Ok(result) => {
+                // Create final state (secret is dropped)
+                let final_state = FinalState::new(result.double_blinded_map.clone());
+                let next = self.to_final(final_state, started, &remote_msg, result.len());
+                Ok((next, result))
+            }
+            Err(error) => Err(RecoverableError::new(self, error)),
+        }
+    }
+
+    /// Like [`finalize`](Self::finalize), also returning where each match
+    /// sits in both blinded points messages.
+    ///
+    /// The alignment follows the order of `intersection_hashes`. The peer
+    /// finalizing with our double-blinded message gets the same matches with
+    /// `local_index` and `remote_index` swapped, so a follow-up message can
+    /// refer to "the item at your index `j`" without naming it.
+    ///
+    /// # Errors
+    /// Same as [`finalize`](Self::finalize)
+    ///
+    /// # Example
+    /// ```ignore
+    /// let (_, result, alignment) = alice_intermediate.finalize_aligned(bob_double_msg)?;
+    /// for matched in alignment {
+    ///     request_label(matched.remote_index);
+    /// }
+    /// # Ok::<(), psi_protocol::PsiError>(())
+    /// ```
+    pub fn finalize_aligned(
+        self,
+        remote_msg: DoubleBlindedPointsMessage,
+    ) -> Result<(PsiProtocol<FinalState>, PsiResult, Vec<AlignedMatch>)> {
+        let started = Instant::now();
+        let (result, alignment) = self.match_remote_aligned(&remote_msg)?;
+        let state = FinalState::new(result.double_blinded_map.clone());
+        let next = self.to_final(state, started, &remote_msg, result.len());
+        Ok((next, result, alignment))
+    }
+
+    /// Build the final state once `remote_msg` is matched, finishing the
+    /// statistics of a phase that began at `started`.
+    pub(crate) fn to_final(
+        &self,
+        state: FinalState,
+        started: Instant,
+        remote_msg: &DoubleBlindedPointsMessage,
+        matches: usize,
+    ) -> PsiProtocol<FinalState> {
+        let received = Received {
+            double_blinded: Some(received_digest(
+                DOUBLE_BLINDED_LABEL,
+                &remote_msg.double_blinded_points,
+            )),
+            ..self.received
+        };
+        PsiProtocol {
+            state,
+            config: self.config.clone(),
+            stats: self.finalized_stats(started, remote_msg.len(), matches),
+            received,
+        }
+    }
+
+    /// Match the remote's double-blinded points against ours without consuming the state.
+    pub(crate) fn match_remote(
+        &self,
+        remote_msg: &DoubleBlindedPointsMessage,
+    ) -> Result<PsiResult> {
+        self.match_remote_aligned(remote_msg)
+            .map(|(result, _)| result)
+    }
+
+    /// [`match_remote`](Self::match_remote), with the alignment of each match.
+    fn match_remote_aligned(
+        &self,
+        remote_msg: &DoubleBlindedPointsMessage,
+    ) -> Result<(PsiResult, Vec<AlignedMatch>)> {
         let mut intersection_hashes = Vec::new();
         let mut double_blinded_map = HashMap::new();
+        let mut alignment = Vec::new();
+        self.match_remote_into(remote_msg, |matched, point| {
+            intersection_hashes.push(matched.id);
+            double_blinded_map.insert(matched.id, point);
+            alignment.push(matched);
+            Ok(())
+        })?;
+        Ok((
+            PsiResult::new(intersection_hashes, double_blinded_map),
+            alignment,
+        ))
+    }
+
+    /// Match the remote's double-blinded points against ours, handing each
+    /// match to `on_match` in our message order.
+    pub(crate) fn match_remote_into(
+        &self,
+        remote_msg: &DoubleBlindedPointsMessage,
+        on_match: impl FnMut(AlignedMatch, CompressedRistretto) -> Result<()>,
+    ) -> Result<()> {
+        let outcome = self.match_points(remote_msg, on_match);
+        trace_outcome!(
+            self.config,
+            Phase::Finalize,
+            &outcome,
+            "match",
+            |matches| vec![
+                ("received", remote_msg.len().to_string()),
+                (
+                    "padding",
+                    self.state
+                        .hash_order()
+                        .iter()
+                        .filter(|slot| slot.is_none())
+                        .count()
+                        .to_string()
+                ),
+                ("matches", matches.to_string()),
+            ]
+        );
+        outcome.map(|_| ())
+    }
+
+    /// Body of [`match_remote_into`](Self::match_remote_into), returning the
+    /// number of matches.
+    fn match_points(
+        &self,
+        remote_msg: &DoubleBlindedPointsMessage,
+        mut on_match: impl FnMut(AlignedMatch, CompressedRistretto) -> Result<()>,
+    ) -> Result<usize> {
+        let mut matches = 0;
+        self.config.check_authentication(
+            DOUBLE_BLINDED_LABEL,
+            &remote_msg.double_blinded_points,
+            remote_msg.authentication.as_ref(),
+        )?;
+        self.config
+            .check_parameters(Phase::Finalize, remote_msg.parameters.as_ref())?;
+
+        // The remote must answer every point of our message, padding included
+        let expected = self.state.hash_order().len();
+        if remote_msg.len() != expected {
+            return Err(PsiError::LengthMismatch {
+                phase: Phase::Finalize,
+                expected,
+                actual: remote_msg.len(),
+            });
+        }
+
+        // Index the double-blinded points we computed from remote's single-blinded points
+        // These are: a*(b*K) for each of Bob's items (where K is Bob's hash), in Bob's order
+        let computed_double_blinded: FastMap<CompressedRistretto, usize> = self
+            .state
+            .double_blinded_from_remote()
+            .iter()
+            .enumerate()
+            .map(|(index, point)| (*point, index))
+            .collect();
 
+        // The received double-blinded points are: b*(a*H) for each of our items (in order)
+        // For each received point at index i, check if it matches any of our computed points
         for (index, remote_double_blinded) in remote_msg.double_blinded_points.iter().enumerate() {
-            if computed_double_blinded_set.contains(remote_double_blinded) {
+            if let Some(&remote_index) = computed_double_blinded.get(remote_double_blinded) {
                 // Found a match! This means a*(b*K) = b*(a*Hi) for some K, so Hi = K (common item)
                 // The hash at this index is in the intersection
-                if let Some(&hash) = self.state.hash_order().get(index) {
-                    intersection_hashes.push(hash);
-                    double_blinded_map.insert(hash, *remote_double_blinded);
+                // Padding slots (`None`) and out-of-range indices are ignored
+                if let Some(&Some(hash)) = self.state.hash_order().get(index) {
+                    let matched = AlignedMatch {
+                        id: ItemId::new(hash),
+                        local_index: index,
+                        remote_index,
+                    };
+                    on_match(matched, *remote_double_blinded)?;
+                    matches += 1;
                 }
             }
         }
-
-        // Create final state (secret is dropped)
-        let final_state = FinalState::new(double_blinded_map.clone());
-        let result = PsiResult::new(intersection_hashes, double_blinded_map);
-
-        Ok((PsiProtocol { state: final_state }, result))
+        Ok(matches)
     }
 }
 
@@ -216,14 +1162,29 @@ impl PsiProtocol<FinalState> {
     /// # Returns
     /// A reference to the HashMap mapping intersection hashes to double-blinded points
     #[cfg(test)]
-    pub fn double_blinded_map(&self) -> &HashMap<[u8; 32], CompressedRistretto> {
+    pub fn double_blinded_map(&self) -> &HashMap<ItemId, CompressedRistretto> {
         self.state.double_blinded_map()
     }
 }
 
+/// Digest of a received message's points, under the message's label.
+fn received_digest(label: &[u8], points: &[CompressedRistretto]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(label);
+    for point in points {
+        hasher.update(point.as_bytes());
+    }
+    hasher.finalize().into()
+}
+
 #[cfg(test)]
+#[allow(clippy::useless_vec)]
 mod tests {
     use super::*;
+    use crate::error::Limit;
+    use crate::messages::ParameterDigest;
+    use crate::psk::PreSharedKey;
+    use std::collections::HashSet;
 
     #[test]
     fn test_psi_protocol_new_empty() {
@@ -231,6 +1192,25 @@ mod tests {
         assert!(matches!(result, Err(PsiError::EmptyInput)));
     }
 
+    #[test]
+    fn test_empty_set_when_allowed() {
+        let config = PsiConfig::builder().allow_empty(true).build().unwrap();
+        let alice = PsiProtocol::new_with_config(&[], config).unwrap();
+        let bob = PsiProtocol::new(&[b"apple".to_vec(), b"banana".to_vec()]).unwrap();
+
+        let alice_msg = alice.message();
+        assert!(alice_msg.is_empty());
+        let (alice_intermediate, alice_double_msg) = alice.compute(bob.message()).unwrap();
+        let (bob_intermediate, bob_double_msg) = bob.compute(alice_msg).unwrap();
+        assert_eq!(alice_double_msg.len(), 2);
+        assert!(bob_double_msg.is_empty());
+
+        let (_, alice_result) = alice_intermediate.finalize(bob_double_msg).unwrap();
+        let (_, bob_result) = bob_intermediate.finalize(alice_double_msg).unwrap();
+        assert!(alice_result.is_empty());
+        assert!(bob_result.is_empty());
+    }
+
     #[test]
     fn test_psi_protocol_new_single_item() {
         let items = vec![b"test".to_vec()];
@@ -273,6 +1253,46 @@ mod tests {
         assert_eq!(bob_result.len(), 0);
     }
 
+    #[test]
+    fn test_finalize_aligned_swaps_indices_between_peers() {
+        let alice_items: Vec<Vec<u8>> =
+            vec![b"apple".to_vec(), b"banana".to_vec(), b"cherry".to_vec()];
+        let bob_items: Vec<Vec<u8>> =
+            vec![b"banana".to_vec(), b"cherry".to_vec(), b"date".to_vec()];
+        let config = PsiConfig::builder()
+            .padding(crate::config::Padding::ToSize(5))
+            .build()
+            .unwrap();
+        let alice = PsiProtocol::new_with_config(&alice_items, config.clone()).unwrap();
+        let bob = PsiProtocol::new_with_config(&bob_items, config).unwrap();
+
+        let (alice_msg, bob_msg) = (alice.message(), bob.message());
+        let (alice_intermediate, alice_double_msg) = alice.compute(bob_msg.clone()).unwrap();
+        let (bob_intermediate, bob_double_msg) = bob.compute(alice_msg.clone()).unwrap();
+
+        let (_, alice_result, alice_alignment) = alice_intermediate
+            .finalize_aligned(bob_double_msg.clone())
+            .unwrap();
+        let (_, _, bob_alignment) = bob_intermediate
+            .finalize_aligned(alice_double_msg.clone())
+            .unwrap();
+
+        assert_eq!(alice_alignment.len(), 2);
+        let ids: Vec<ItemId> = alice_alignment.iter().map(|matched| matched.id).collect();
+        assert_eq!(ids, alice_result.intersection_hashes);
+        for matched in &alice_alignment {
+            assert!(bob_alignment.contains(&AlignedMatch {
+                id: matched.id,
+                local_index: matched.remote_index,
+                remote_index: matched.local_index,
+            }));
+            assert_eq!(
+                alice_double_msg.double_blinded_points[matched.remote_index],
+                bob_double_msg.double_blinded_points[matched.local_index]
+            );
+        }
+    }
+
     #[test]
     fn test_psi_protocol_compute_with_intersection() {
         let alice = PsiProtocol::new(&vec![b"apple".to_vec()]).unwrap();
@@ -334,7 +1354,7 @@ mod tests {
         let alice_msg = alice.message();
         let bob_msg = bob.message();
 
-        let (alice_intermediate, alice_double_msg) = alice.compute(bob_msg).unwrap();
+        let (alice_intermediate, _alice_double_msg) = alice.compute(bob_msg).unwrap();
         let (bob_intermediate, bob_double_msg) = bob.compute(alice_msg).unwrap();
 
         let (alice_final, _alice_result) = alice_intermediate.finalize(bob_double_msg).unwrap();
@@ -345,4 +1365,604 @@ mod tests {
         // But we can access the double-blinded map:
         let _map = alice_final.double_blinded_map();
     }
+
+    fn run_with_configs(
+        alice_items: &[Vec<u8>],
+        alice_config: PsiConfig,
+        bob_items: &[Vec<u8>],
+        bob_config: PsiConfig,
+    ) -> (PsiResult, PsiResult) {
+        let alice = PsiProtocol::new_with_config(alice_items, alice_config).unwrap();
+        let bob = PsiProtocol::new_with_config(bob_items, bob_config).unwrap();
+
+        let alice_msg = alice.message();
+        let bob_msg = bob.message();
+
+        let (alice_intermediate, alice_double_msg) = alice.compute(bob_msg).unwrap();
+        let (bob_intermediate, bob_double_msg) = bob.compute(alice_msg).unwrap();
+
+        let (_, alice_result) = alice_intermediate.finalize(bob_double_msg).unwrap();
+        let (_, bob_result) = bob_intermediate.finalize(alice_double_msg).unwrap();
+        (alice_result, bob_result)
+    }
+
+    #[test]
+    fn test_psi_protocol_padding() {
+        let config = PsiConfig::builder()
+            .padding(crate::config::Padding::ToMultipleOf(8))
+            .build()
+            .unwrap();
+        let alice = PsiProtocol::new_with_config(&[b"apple".to_vec()], config.clone()).unwrap();
+        assert_eq!(alice.message().len(), 8);
+
+        let (alice_result, bob_result) = run_with_configs(
+            &[b"apple".to_vec(), b"banana".to_vec()],
+            config.clone(),
+            &[b"banana".to_vec(), b"cherry".to_vec(), b"date".to_vec()],
+            config,
+        );
+        assert_eq!(alice_result.len(), 1);
+        assert_eq!(alice_result.intersection_hashes, bob_result.intersection_hashes);
+    }
+
+    #[test]
+    fn test_psi_protocol_sorted_order() {
+        let config = PsiConfig::builder().order(MessageOrder::Sorted).build().unwrap();
+        let items: Vec<Vec<u8>> = (0u8..8).map(|i| vec![i]).collect();
+        let alice = PsiProtocol::new_with_config(&items, config).unwrap();
+        let points: Vec<[u8; 32]> = alice
+            .message()
+            .blinded_points
+            .iter()
+            .map(|p| p.to_bytes())
+            .collect();
+        assert!(points.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_psi_protocol_local_limit() {
+        let config = PsiConfig::builder().max_local_items(1).build().unwrap();
+        let result = PsiProtocol::new_with_config(&[b"a".to_vec(), b"b".to_vec()], config);
+        assert_eq!(
+            result.unwrap_err(),
+            PsiError::LimitExceeded {
+                limit: Limit::LocalItems,
+                max: 1,
+                actual: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_psi_protocol_remote_limit() {
+        let config = PsiConfig::builder().max_remote_items(1).build().unwrap();
+        let alice = PsiProtocol::new_with_config(&[b"a".to_vec()], config).unwrap();
+        let bob = PsiProtocol::new(&[b"a".to_vec(), b"b".to_vec()]).unwrap();
+        let result = alice.compute(bob.message());
+        assert_eq!(
+            result.unwrap_err(),
+            PsiError::LimitExceeded {
+                limit: Limit::RemotePoints,
+                max: 1,
+                actual: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_psi_protocol_lenient_invalid_points() {
+        let mut invalid = [0xffu8; 32];
+        invalid[0] = 0xfe;
+        let bob = PsiProtocol::new(&[b"apple".to_vec()]).unwrap();
+        let mut bob_msg = bob.message();
+        bob_msg.blinded_points.insert(0, CompressedRistretto(invalid));
+
+        let strict = PsiProtocol::new(&[b"apple".to_vec()]).unwrap();
+        assert_eq!(
+            strict.compute(bob_msg.clone()).unwrap_err(),
+            PsiError::InvalidPoint {
+                phase: Phase::Compute,
+                index: 0
+            }
+        );
+
+        let config = PsiConfig::builder().lenient(true).build().unwrap();
+        let lenient = PsiProtocol::new_with_config(&[b"apple".to_vec()], config).unwrap();
+        let (_, double_msg) = lenient.compute(bob_msg).unwrap();
+        assert_eq!(double_msg.len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_points_report_first_position_after_full_batch() {
+        let mut invalid = [0xffu8; 32];
+        invalid[0] = 0xfe;
+        let items: Vec<Vec<u8>> = (0u8..6).map(|i| vec![i]).collect();
+        let bob = PsiProtocol::new(&items).unwrap();
+        let mut bob_msg = bob.message();
+        bob_msg.blinded_points[4] = CompressedRistretto(invalid);
+        bob_msg.blinded_points[2] = CompressedRistretto(invalid);
+
+        let alice = PsiProtocol::new(&items).unwrap();
+        assert_eq!(
+            alice.compute(bob_msg.clone()).unwrap_err(),
+            PsiError::InvalidPoint {
+                phase: Phase::Compute,
+                index: 2
+            }
+        );
+
+        // Lenient mode keeps every position and still matches the valid ones
+        let config = PsiConfig::builder().lenient(true).build().unwrap();
+        let alice = PsiProtocol::new_with_config(&items, config).unwrap();
+        let bob_double = {
+            let (_, double_msg) = bob.compute(alice.message()).unwrap();
+            double_msg
+        };
+        let (alice, alice_double) = alice.compute(bob_msg).unwrap();
+        assert_eq!(alice_double.len(), 6);
+        let (_, result) = alice.finalize(bob_double).unwrap();
+        assert_eq!(result.intersection_hashes.len(), 4);
+    }
+
+    #[test]
+    fn test_psi_protocol_threads_and_hash_choice() {
+        let config = PsiConfig::builder()
+            .hash(crate::config::HashAlgorithm::Sha256)
+            .threads(3)
+            .hash_threads(2)
+            .build()
+            .unwrap();
+        let alice_items: Vec<Vec<u8>> = (0u8..10).map(|i| vec![i]).collect();
+        let bob_items: Vec<Vec<u8>> = (5u8..15).map(|i| vec![i]).collect();
+        let (alice_result, bob_result) =
+            run_with_configs(&alice_items, config.clone(), &bob_items, config);
+        assert_eq!(alice_result.len(), 5);
+        assert_eq!(bob_result.len(), 5);
+    }
+
+    #[test]
+    fn test_psi_protocol_derived_secret_is_shared() {
+        let ikm = [9u8; 32];
+        let items = vec![b"apple".to_vec(), b"banana".to_vec()];
+        let sorted_points = |proto: &PsiProtocol<PreparedState>| {
+            let mut points: Vec<[u8; 32]> =
+                proto.message().blinded_points.iter().map(|p| p.to_bytes()).collect();
+            points.sort();
+            points
+        };
+
+        let worker_a =
+            PsiProtocol::with_derived_secret(&items, &ikm, b"ctx", PsiConfig::default()).unwrap();
+        let worker_b =
+            PsiProtocol::with_derived_secret(&items, &ikm, b"ctx", PsiConfig::default()).unwrap();
+        let other =
+            PsiProtocol::with_derived_secret(&items, &ikm, b"other", PsiConfig::default()).unwrap();
+
+        assert_eq!(sorted_points(&worker_a), sorted_points(&worker_b));
+        assert_ne!(sorted_points(&worker_a), sorted_points(&other));
+    }
+
+    #[test]
+    fn test_psi_protocol_derived_secret_interoperates() {
+        // The remote answers one worker; a different worker finalizes
+        let ikm = [3u8; 32];
+        let items = vec![b"apple".to_vec(), b"banana".to_vec()];
+        let config = PsiConfig::builder().order(MessageOrder::Sorted).build().unwrap();
+        let worker_a = PsiProtocol::with_derived_secret(&items, &ikm, b"ctx", config.clone()).unwrap();
+        let worker_b = PsiProtocol::with_derived_secret(&items, &ikm, b"ctx", config).unwrap();
+        let bob = PsiProtocol::new(&[b"banana".to_vec()]).unwrap();
+        let bob_msg = bob.message();
+
+        let (_, bob_double_msg) = bob.compute(worker_a.message()).unwrap();
+        let (worker_b, _) = worker_b.compute(bob_msg).unwrap();
+        let (_, result) = worker_b.finalize(bob_double_msg).unwrap();
+        assert_eq!(result.len(), 1);
+    }
+
+    #[test]
+    fn test_psi_protocol_derived_secret_shuffled_and_padded() {
+        // Same as above under the default shuffled order, and with padding
+        let ikm = [5u8; 32];
+        let items: Vec<Vec<u8>> = (0u8..20).map(|i| vec![i]).collect();
+        let padded = PsiConfig::builder()
+            .padding(crate::config::Padding::ToMultipleOf(8))
+            .build()
+            .unwrap();
+        for config in [PsiConfig::default(), padded] {
+            let worker_a =
+                PsiProtocol::with_derived_secret(&items, &ikm, b"ctx", config.clone()).unwrap();
+            let worker_b =
+                PsiProtocol::with_derived_secret(&items, &ikm, b"ctx", config.clone()).unwrap();
+            assert_eq!(worker_a.message(), worker_b.message());
+
+            let bob_items = [vec![3], vec![11], b"other".to_vec()];
+            let bob = PsiProtocol::new_with_config(&bob_items, config).unwrap();
+            let bob_msg = bob.message();
+            let (_, bob_double_msg) = bob.compute(worker_a.message()).unwrap();
+            let (worker_b, _) = worker_b.compute(bob_msg).unwrap();
+            let (_, result) = worker_b.finalize(bob_double_msg).unwrap();
+            assert_eq!(result.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_psi_protocol_moves_across_threads() {
+        let alice = PsiProtocol::new(&[b"apple".to_vec()]).unwrap();
+        let bob = PsiProtocol::new(&[b"apple".to_vec()]).unwrap();
+        let bob_msg = bob.message();
+
+        let handle = std::thread::spawn(move || alice.compute(bob_msg).unwrap());
+        let (alice_intermediate, _) = handle.join().unwrap();
+        let _copy = alice_intermediate.clone();
+    }
+
+    #[test]
+    fn test_try_compute_returns_state_on_error() {
+        let config = PsiConfig::builder().max_remote_items(1).build().unwrap();
+        let alice = PsiProtocol::new_with_config(&[b"apple".to_vec()], config).unwrap();
+        let alice_msg = alice.message();
+        let bob = PsiProtocol::new(&[b"apple".to_vec()]).unwrap();
+
+        // A bad message is rejected but the prepared state survives
+        let mut oversized = BlindedPointsMessage::new(vec![bob.message().blinded_points[0]; 2]);
+        oversized.parameters = bob.message().parameters;
+        let rejected = alice.try_compute(oversized).unwrap_err();
+        assert!(matches!(rejected.error, PsiError::LimitExceeded { .. }));
+        let (alice, _) = rejected.into_parts();
+        assert_eq!(alice.message(), alice_msg);
+
+        // Retry with the resent, valid message
+        let bob_msg = bob.message();
+        let (bob_intermediate, bob_double_msg) = bob.compute(alice_msg).unwrap();
+        let (alice_intermediate, alice_double_msg) = alice.try_compute(bob_msg).unwrap();
+        let (_, alice_result) = alice_intermediate.try_finalize(bob_double_msg).unwrap();
+        let (_, bob_result) = bob_intermediate.finalize(alice_double_msg).unwrap();
+        assert_eq!(alice_result.len(), 1);
+        assert_eq!(bob_result.len(), 1);
+    }
+
+    #[test]
+    fn test_compute_for_peer_serves_many_peers() {
+        let server = PsiProtocol::new(&[b"apple".to_vec(), b"banana".to_vec()]).unwrap();
+        let server_msg = server.message();
+        let peers = [
+            (vec![b"apple".to_vec()], 1),
+            (vec![b"apple".to_vec(), b"banana".to_vec()], 2),
+            (vec![b"cherry".to_vec()], 0),
+        ];
+
+        std::thread::scope(|scope| {
+            for (items, expected) in &peers {
+                let server = &server;
+                let server_msg = server_msg.clone();
+                scope.spawn(move || {
+                    let peer = PsiProtocol::new(items).unwrap();
+                    let (session, server_double_msg) =
+                        server.compute_for_peer(peer.message()).unwrap();
+                    let (peer_intermediate, peer_double_msg) = peer.compute(server_msg).unwrap();
+
+                    let (_, server_result) = session.finalize(peer_double_msg).unwrap();
+                    let (_, peer_result) = peer_intermediate.finalize(server_double_msg).unwrap();
+                    assert_eq!(server_result.len(), *expected);
+                    assert_eq!(peer_result.len(), *expected);
+                });
+            }
+        });
+
+        // The prepared state is untouched and still usable
+        assert_eq!(server.message(), server_msg);
+    }
+
+    #[test]
+    fn test_one_round_variant() {
+        let alice = PsiProtocol::new(&[
+            b"apple".to_vec(),
+            b"banana".to_vec(),
+            b"cherry".to_vec(),
+        ]).unwrap();
+        let bob = PsiProtocol::new(&[b"banana".to_vec(), b"cherry".to_vec(), b"date".to_vec()])
+            .unwrap();
+
+        let response = bob.respond_one_round(alice.message()).unwrap();
+        let (_, result) = alice.finalize_one_round(response).unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(result.double_blinded_map.len(), 2);
+    }
+
+    #[test]
+    fn test_one_round_matches_two_round_result() {
+        let alice_items = vec![b"apple".to_vec(), b"banana".to_vec()];
+        let bob_items = vec![b"banana".to_vec(), b"cherry".to_vec()];
+        let alice = PsiProtocol::new(&alice_items).unwrap();
+        let bob = PsiProtocol::new(&bob_items).unwrap();
+
+        // Two-round flow with the same secrets
+        let (bob_session, bob_double_msg) = bob.compute_for_peer(alice.message()).unwrap();
+        let (alice_session, alice_double_msg) = alice.compute_for_peer(bob.message()).unwrap();
+        let (_, two_round) = alice_session.finalize(bob_double_msg).unwrap();
+        let _ = bob_session.finalize(alice_double_msg).unwrap();
+
+        let response = bob.respond_one_round(alice.message()).unwrap();
+        let (_, one_round) = alice.finalize_one_round(response).unwrap();
+        assert_eq!(one_round.double_blinded_map, two_round.double_blinded_map);
+    }
+
+    #[test]
+    fn test_one_round_with_padding() {
+        let config = PsiConfig::builder()
+            .padding(crate::config::Padding::ToSize(16))
+            .build()
+            .unwrap();
+        let alice = PsiProtocol::new_with_config(&[b"apple".to_vec()], config.clone()).unwrap();
+        let bob = PsiProtocol::new_with_config(&[b"apple".to_vec()], config).unwrap();
+
+        let response = bob.respond_one_round(alice.message()).unwrap();
+        assert_eq!(response.blinded_points.len(), 16);
+        let (_, result) = alice.finalize_one_round(response).unwrap();
+        assert_eq!(result.len(), 1);
+    }
+
+    #[test]
+    fn test_cardinality_pre_exchange() {
+        let config = PsiConfig::builder()
+            .padding(crate::config::Padding::ToMultipleOf(8))
+            .build()
+            .unwrap();
+        let bob = PsiProtocol::new_with_config(&[b"a".to_vec(), b"b".to_vec()], config).unwrap();
+        let announced = bob.cardinality();
+        assert_eq!(announced.point_count, 8);
+        assert!(announced.check(&bob.message()).is_ok());
+
+        let strict_config = PsiConfig::builder().max_remote_items(4).build().unwrap();
+        let alice = PsiProtocol::new_with_config(&[b"a".to_vec()], strict_config).unwrap();
+        assert!(matches!(
+            alice.check_cardinality(&announced),
+            Err(PsiError::LimitExceeded { .. })
+        ));
+        assert!(PsiProtocol::new(&[b"a".to_vec()])
+            .unwrap()
+            .check_cardinality(&announced)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_message_is_built_once() {
+        let alice = PsiProtocol::new(&[b"apple".to_vec(), b"banana".to_vec()]).unwrap();
+        let shared = alice.shared_message();
+        assert!(Arc::ptr_eq(&shared, &alice.shared_message()));
+        assert!(Arc::ptr_eq(&shared, &alice.clone().shared_message()));
+        assert_eq!(*shared, alice.message());
+        assert_eq!(shared.blinded_points, alice.state.message_points());
+    }
+
+    #[test]
+    fn test_double_blinded_message_can_be_resent() {
+        let key = PreSharedKey::new(1, [7u8; 32]);
+        let config = PsiConfig::builder().pre_shared_key(key).build().unwrap();
+        let alice = PsiProtocol::new_with_config(&[b"apple".to_vec()], config.clone()).unwrap();
+        let bob = PsiProtocol::new_with_config(&[b"apple".to_vec()], config).unwrap();
+
+        let (alice_msg, bob_msg) = (alice.message(), bob.message());
+        let (alice, alice_double_msg) = alice.compute(bob_msg).unwrap();
+        let (bob, _) = bob.compute(alice_msg).unwrap();
+        assert!(alice_double_msg.authentication.is_some());
+        assert_eq!(alice.message(), alice_double_msg);
+
+        // The resent copy finalizes like the original
+        let (_, result) = bob.finalize(alice.message()).unwrap();
+        assert_eq!(result.len(), 1);
+    }
+
+    #[test]
+    fn test_one_round_rejects_length_mismatch() {
+        let alice = PsiProtocol::new(&[b"apple".to_vec()]).unwrap();
+        let bob = PsiProtocol::new(&[b"apple".to_vec()]).unwrap();
+        let mut response = bob.respond_one_round(alice.message()).unwrap();
+        response.double_blinded_points.clear();
+        let result = alice.finalize_one_round(response);
+        assert_eq!(
+            result.unwrap_err(),
+            PsiError::LengthMismatch {
+                phase: Phase::Finalize,
+                expected: 1,
+                actual: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_finalize_rejects_length_mismatch() {
+        let alice = PsiProtocol::new(&[b"apple".to_vec(), b"banana".to_vec()]).unwrap();
+        let bob = PsiProtocol::new(&[b"apple".to_vec()]).unwrap();
+        let (alice_intermediate, _) = alice.compute(bob.message()).unwrap();
+        let mut short = DoubleBlindedPointsMessage::new(vec![CompressedRistretto([0u8; 32])]);
+        short.parameters = bob.message().parameters;
+        let rejected = alice_intermediate.try_finalize(short).unwrap_err();
+        assert_eq!(
+            rejected.error,
+            PsiError::LengthMismatch {
+                phase: Phase::Finalize,
+                expected: 2,
+                actual: 1
+            }
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_compute_preserves_order() {
+        let alice_items: Vec<Vec<u8>> = (0u8..10).map(|i| vec![i]).collect();
+        let bob_items: Vec<Vec<u8>> = (5u8..20).map(|i| vec![i]).collect();
+        let bob = PsiProtocol::new(&bob_items).unwrap();
+
+        let config = PsiConfig::builder().compute_threads(4).build().unwrap();
+        let alice = PsiProtocol::new_with_config(&alice_items, config).unwrap();
+        let (_, parallel_msg) = alice.compute_for_peer(bob.message()).unwrap();
+
+        let sequential = PsiProtocol {
+            state: alice.state.clone(),
+            config: PsiConfig::default(),
+            stats: PsiStats::default(),
+            received: Received::default(),
+        };
+        let (_, sequential_msg) = sequential.compute_for_peer(bob.message()).unwrap();
+        assert_eq!(parallel_msg, sequential_msg);
+
+        let (_, bob_double_msg) = bob.compute_for_peer(alice.message()).unwrap();
+        let (_, result) = alice
+            .compute(bob.message())
+            .unwrap()
+            .0
+            .finalize(bob_double_msg)
+            .unwrap();
+        assert_eq!(result.len(), 5);
+    }
+
+    #[test]
+    fn test_rerandomize() {
+        let items = vec![b"apple".to_vec(), b"banana".to_vec()];
+        let alice = PsiProtocol::new(&items).unwrap();
+        let fresh = alice.rerandomize().unwrap();
+        assert_ne!(fresh.state.secret_scalar(), alice.state.secret_scalar());
+
+        // No point is reused, so the remote cannot link the sessions
+        let old: HashSet<_> = alice.message().blinded_points.into_iter().collect();
+        assert!(fresh
+            .message()
+            .blinded_points
+            .iter()
+            .all(|p| !old.contains(p)));
+
+        let bob = PsiProtocol::new(&[b"banana".to_vec()]).unwrap();
+        let fresh_msg = fresh.message();
+        let (fresh, _) = fresh.compute(bob.message()).unwrap();
+        let (_, bob_double) = bob.compute(fresh_msg).unwrap();
+        let (_, result) = fresh.finalize(bob_double).unwrap();
+        assert_eq!(result.intersection_hashes, vec![ItemId::of(b"banana")]);
+    }
+
+    #[test]
+    fn test_domain_separation() {
+        let items = vec![b"apple".to_vec(), b"banana".to_vec()];
+        let run = |alice_domain: &str, bob_domain: &str| {
+            let config = |domain: &str| PsiConfig::builder().domain(domain).build().unwrap();
+            let alice = PsiProtocol::new_with_config(&items, config(alice_domain)).unwrap();
+            let bob = PsiProtocol::new_with_config(&items, config(bob_domain)).unwrap();
+            // Swap in the receiver's digests to see what the points alone give
+            let mut alice_msg = alice.message();
+            let mut bob_msg = bob.message();
+            std::mem::swap(&mut alice_msg.parameters, &mut bob_msg.parameters);
+            let alice_digest = bob_msg.parameters;
+            let (alice, _) = alice.compute(bob_msg).unwrap();
+            let (_, mut bob_double) = bob.compute(alice_msg).unwrap();
+            bob_double.parameters = alice_digest;
+            alice.finalize(bob_double).unwrap().1
+        };
+
+        let result = run("app-a", "app-a");
+        assert_eq!(result.len(), 2);
+        // Item ids do not depend on the domain
+        assert!(result.intersection_hashes.contains(&ItemId::of(b"apple")));
+        assert_eq!(run("app-a", "app-b").len(), 0);
+        assert_eq!(run("app-a", "").len(), 0);
+    }
+
+    #[test]
+    fn test_parameter_mismatch_fails_loudly() {
+        let items = vec![b"apple".to_vec()];
+        let configs = [
+            PsiConfig::builder().domain("app-b").build().unwrap(),
+            PsiConfig::builder()
+                .hash(crate::config::HashAlgorithm::Sha256)
+                .build()
+                .unwrap(),
+            PsiConfig::builder()
+                .padding(crate::config::Padding::ToSize(4))
+                .build()
+                .unwrap(),
+        ];
+        let alice_config = PsiConfig::builder().domain("app-a").build().unwrap();
+        for bob_config in configs {
+            assert_ne!(
+                alice_config.parameter_digest(),
+                bob_config.parameter_digest()
+            );
+            let alice = PsiProtocol::new_with_config(&items, alice_config.clone()).unwrap();
+            let bob = PsiProtocol::new_with_config(&items, bob_config).unwrap();
+            assert_eq!(
+                alice.compute_for_peer(bob.message()).unwrap_err(),
+                PsiError::ParameterMismatch {
+                    phase: Phase::Compute
+                }
+            );
+            assert!(bob.respond_one_round(alice.message()).is_err());
+        }
+
+        // A double-blinded answer made under other parameters is refused too
+        let alice = PsiProtocol::new(&items).unwrap();
+        let bob = PsiProtocol::new(&items).unwrap();
+        let alice_msg = alice.message();
+        let (alice, _) = alice.compute(bob.message()).unwrap();
+        let (_, mut bob_double) = bob.compute(alice_msg).unwrap();
+        bob_double.parameters = Some(ParameterDigest([0u8; 32]));
+        assert!(matches!(
+            alice.finalize(bob_double),
+            Err(PsiError::ParameterMismatch {
+                phase: Phase::Finalize
+            })
+        ));
+    }
+
+    #[test]
+    fn test_missing_parameters_are_refused() {
+        let items = vec![b"apple".to_vec()];
+        let alice = PsiProtocol::new(&items).unwrap();
+        let bob = PsiProtocol::new(&items).unwrap();
+        let mut bob_msg = bob.message();
+        bob_msg.parameters = None;
+        assert_eq!(
+            alice.clone().compute(bob_msg.clone()).unwrap_err(),
+            PsiError::ParameterMismatch {
+                phase: Phase::Compute
+            }
+        );
+        assert!(alice.respond_one_round(bob_msg).is_err());
+
+        let mut response = bob.respond_one_round(alice.message()).unwrap();
+        assert!(response.parameters.is_some());
+        response.parameters = None;
+        assert!(matches!(
+            alice.finalize_one_round(response),
+            Err(PsiError::ParameterMismatch {
+                phase: Phase::Finalize
+            })
+        ));
+    }
+
+    #[cfg(feature = "vartime")]
+    #[test]
+    fn test_vartime_compute_matches_constant_time() {
+        let mut invalid = [0xffu8; 32];
+        invalid[0] = 0xfe;
+        let alice_items = vec![b"apple".to_vec(), b"banana".to_vec()];
+        let bob = PsiProtocol::new(&[b"banana".to_vec(), b"cherry".to_vec()]).unwrap();
+        let mut bob_msg = bob.message();
+        bob_msg.blinded_points.insert(1, CompressedRistretto(invalid));
+
+        let config = PsiConfig::builder().lenient(true).vartime(true).build().unwrap();
+        let alice = PsiProtocol::new_with_config(&alice_items, config).unwrap();
+        let (_, vartime_msg) = alice.compute_for_peer(bob_msg.clone()).unwrap();
+
+        let constant_time = PsiProtocol {
+            state: alice.state.clone(),
+            config: PsiConfig::builder().lenient(true).build().unwrap(),
+            stats: PsiStats::default(),
+            received: Received::default(),
+        };
+        let (_, constant_time_msg) = constant_time.compute_for_peer(bob_msg).unwrap();
+
+        assert_eq!(vartime_msg.len(), 3);
+        assert_eq!(vartime_msg.double_blinded_points[0], constant_time_msg.double_blinded_points[0]);
+        assert_eq!(vartime_msg.double_blinded_points[2], constant_time_msg.double_blinded_points[2]);
+    }
 }
@@ -0,0 +1,183 @@
+//! Pluggable PSI constructions.
+//!
+//! [`PsiBackend`] describes a two-message PSI construction as a runtime
+//! state machine: each party sends a first message, answers the remote's
+//! first message, and turns the remote's answer into an output. Code that
+//! only drives the exchange (transports, session stores, test harnesses)
+//! can be written against the trait and pick up new constructions without
+//! changes.
+//!
+//! The ECDH construction implements it through [`PsiSession`]; the
+//! type-state [`PsiProtocol`](crate::PsiProtocol) API is unchanged and
+//! remains the recommended entry point when the construction is fixed.
+
+use crate::config::PsiConfig;
+use crate::error::Result;
+use crate::messages::{BlindedPointsMessage, DoubleBlindedPointsMessage, PsiResult};
+use crate::session::PsiSession;
+
+/// A two-message PSI construction driven through `&mut self`.
+///
+/// Calling a method in the wrong state must return
+/// `PsiError::UnexpectedState` rather than panic, and a rejected remote
+/// message must leave the state unchanged so a resent message can be
+/// handled.
+pub trait PsiBackend: Sized {
+    /// Construction-specific configuration.
+    type Config;
+    /// First message, sent by both parties.
+    type Message;
+    /// Answer to the remote's first message.
+    type Reply;
+    /// What a party learns at the end of the run.
+    type Output;
+
+    /// Short name of the construction, for logs and negotiation.
+    const NAME: &'static str;
+
+    /// Prepare a run over `items`.
+    ///
+    /// # Errors
+    /// Construction-specific; typically `PsiError::EmptyInput` and
+    /// `PsiError::LimitExceeded`
+    fn start(items: &[Vec<u8>], config: Self::Config) -> Result<Self>;
+
+    /// The first message to send to the remote party.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` once the message has been answered
+    fn message(&self) -> Result<Self::Message>;
+
+    /// Handle the remote's first message and produce our answer.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` if called twice, plus
+    /// construction-specific validation errors
+    fn on_message(&mut self, remote_msg: Self::Message) -> Result<Self::Reply>;
+
+    /// Handle the remote's answer and produce the output.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` unless [`on_message`](Self::on_message)
+    /// has succeeded, plus construction-specific validation errors
+    fn on_reply(&mut self, remote_reply: Self::Reply) -> Result<Self::Output>;
+
+    /// Returns true once the output has been produced.
+    fn is_complete(&self) -> bool;
+}
+
+impl PsiBackend for PsiSession {
+    type Config = PsiConfig;
+    type Message = BlindedPointsMessage;
+    type Reply = DoubleBlindedPointsMessage;
+    type Output = PsiResult;
+
+    const NAME: &'static str = "ecdh-ristretto";
+
+    fn start(items: &[Vec<u8>], config: PsiConfig) -> Result<Self> {
+        PsiSession::new_with_config(items, config)
+    }
+
+    fn message(&self) -> Result<BlindedPointsMessage> {
+        PsiSession::message(self)
+    }
+
+    fn on_message(
+        &mut self,
+        remote_msg: BlindedPointsMessage,
+    ) -> Result<DoubleBlindedPointsMessage> {
+        self.on_blinded(remote_msg)
+    }
+
+    fn on_reply(&mut self, remote_reply: DoubleBlindedPointsMessage) -> Result<PsiResult> {
+        self.on_double_blinded(remote_reply)
+    }
+
+    fn is_complete(&self) -> bool {
+        PsiSession::is_complete(self)
+    }
+}
+
+/// Run a whole exchange between two local parties of the same construction.
+///
+/// The generic counterpart of [`run_local_psi`](crate::run_local_psi).
+///
+/// # Errors
+/// Any error from [`PsiBackend::start`] or the transitions
+pub fn run_local_backend<B: PsiBackend>(
+    local_items: &[Vec<u8>],
+    remote_items: &[Vec<u8>],
+    local_config: B::Config,
+    remote_config: B::Config,
+) -> Result<(B::Output, B::Output)> {
+    let mut local = B::start(local_items, local_config)?;
+    let mut remote = B::start(remote_items, remote_config)?;
+
+    let local_msg = local.message()?;
+    let remote_msg = remote.message()?;
+    let local_reply = local.on_message(remote_msg)?;
+    let remote_reply = remote.on_message(local_msg)?;
+
+    Ok((local.on_reply(remote_reply)?, remote.on_reply(local_reply)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::PsiError;
+    use crate::local::run_local_psi;
+
+    fn sets() -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+        (
+            vec![b"apple".to_vec(), b"banana".to_vec()],
+            vec![b"banana".to_vec(), b"cherry".to_vec()],
+        )
+    }
+
+    #[test]
+    fn test_ecdh_backend_matches_protocol() {
+        let (alice, bob) = sets();
+        let (alice_result, bob_result) = run_local_backend::<PsiSession>(
+            &alice,
+            &bob,
+            PsiConfig::default(),
+            PsiConfig::default(),
+        )
+        .unwrap();
+        let (expected, _) = run_local_psi(&alice, &bob).unwrap();
+        assert_eq!(
+            alice_result.intersection_hashes,
+            expected.intersection_hashes
+        );
+        assert_eq!(
+            alice_result.intersection_hashes,
+            bob_result.intersection_hashes
+        );
+    }
+
+    #[test]
+    fn test_backend_state_errors() {
+        let (alice, _) = sets();
+        let mut session = <PsiSession as PsiBackend>::start(&alice, PsiConfig::default()).unwrap();
+        assert!(!PsiBackend::is_complete(&session));
+        assert!(matches!(
+            session.on_reply(DoubleBlindedPointsMessage::new(vec![])),
+            Err(PsiError::UnexpectedState { .. })
+        ));
+    }
+
+    #[test]
+    fn test_backend_propagates_start_errors() {
+        let (alice, _) = sets();
+        assert_eq!(
+            run_local_backend::<PsiSession>(
+                &alice,
+                &[],
+                PsiConfig::default(),
+                PsiConfig::default()
+            )
+            .unwrap_err(),
+            PsiError::EmptyInput
+        );
+    }
+}
@@ -0,0 +1,247 @@
+//! Message authentication with a pre-shared key.
+//!
+//! Deployments without a PKI can still get integrity and peer
+//! authentication over a plaintext transport: configure both parties with
+//! the same [`PreSharedKey`] through
+//! [`PsiConfigBuilder::pre_shared_key`](crate::PsiConfigBuilder::pre_shared_key)
+//! and every blinded, double-blinded and one-round response message carries
+//! a [`MessageMac`]. `compute`, `finalize`, `respond_one_round` and
+//! `finalize_one_round` reject a message whose MAC is missing, made with
+//! another key id, or does not verify.
+//!
+//! The MAC is HMAC-SHA256 over the message kind, its point lists and the
+//! [`ParameterDigest`], so the digest cannot be swapped in transit. It does
+//! not bind a message to a session, so a recorded message can be replayed
+//! into another session under the same key; pair it with a
+//! [`Transcript`](crate::Transcript) when that matters. The chunks of
+//! chunked sending carry no MAC.
+
+use crate::error::{PsiError, Result};
+use crate::messages::{MessageMac, ParameterDigest};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use zeroize::Zeroize;
+
+const MAC_TAG: &[u8] = b"psi-sync/psk-mac/v1";
+
+/// Message kinds covered by a MAC, so a tag cannot be moved between kinds.
+pub(crate) const BLINDED_LABEL: &[u8] = b"blinded";
+pub(crate) const DOUBLE_BLINDED_LABEL: &[u8] = b"double-blinded";
+pub(crate) const ONE_ROUND_LABEL: &[u8] = b"one-round-response";
+
+/// A symmetric key shared by both parties out of band.
+///
+/// The id travels with every MAC so keys can be rotated: a receiver
+/// rejects MACs made with any other id.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PreSharedKey {
+    id: u32,
+    key: [u8; 32],
+}
+
+impl PreSharedKey {
+    /// Create a key with its id.
+    pub fn new(id: u32, key: [u8; 32]) -> Self {
+        Self { id, key }
+    }
+
+    /// Id sent along with every MAC.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// MAC a points message of the given kind, sent with `parameters`.
+    pub(crate) fn authenticate(
+        &self,
+        label: &[u8],
+        lists: &[&[CompressedRistretto]],
+        parameters: &ParameterDigest,
+    ) -> MessageMac {
+        MessageMac::new(self.id, self.tag(label, lists, parameters))
+    }
+
+    /// Verify the MAC of a points message of the given kind, received with
+    /// `parameters`.
+    ///
+    /// # Errors
+    /// Returns `PsiError::CryptoError` if `mac` is missing, made with
+    /// another key id, or does not match the point lists and parameters
+    pub(crate) fn verify(
+        &self,
+        label: &[u8],
+        lists: &[&[CompressedRistretto]],
+        parameters: &ParameterDigest,
+        mac: Option<&MessageMac>,
+    ) -> Result<()> {
+        let mac =
+            mac.ok_or_else(|| PsiError::CryptoError("Message is not authenticated".to_string()))?;
+        if mac.key_id != self.id {
+            return Err(PsiError::CryptoError(format!(
+                "Unknown pre-shared key id {}",
+                mac.key_id
+            )));
+        }
+        let diff = self
+            .tag(label, lists, parameters)
+            .iter()
+            .zip(&mac.tag)
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        if diff != 0 {
+            return Err(PsiError::CryptoError(
+                "Message authentication failed".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn tag(
+        &self,
+        label: &[u8],
+        lists: &[&[CompressedRistretto]],
+        parameters: &ParameterDigest,
+    ) -> [u8; 32] {
+        let points: usize = lists.iter().map(|list| list.len()).sum();
+        let mut input = Vec::with_capacity(
+            MAC_TAG.len() + label.len() + 8 + lists.len() * 8 + points * 32 + 32,
+        );
+        input.extend_from_slice(MAC_TAG);
+        input.extend_from_slice(&(label.len() as u64).to_be_bytes());
+        input.extend_from_slice(label);
+        // Each list is length-prefixed so points cannot move between lists
+        for list in lists {
+            input.extend_from_slice(&(list.len() as u64).to_be_bytes());
+            for point in *list {
+                input.extend_from_slice(point.as_bytes());
+            }
+        }
+        input.extend_from_slice(&parameters.0);
+        // HKDF-Extract is HMAC with the salt as key
+        let (tag, _) = Hkdf::<Sha256>::extract(Some(&self.key), &input);
+        tag.into()
+    }
+}
+
+impl std::fmt::Debug for PreSharedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreSharedKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for PreSharedKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PsiConfig;
+    use crate::protocol::PsiProtocol;
+    use crate::test_util::items;
+
+    fn config(id: u32, key: u8) -> PsiConfig {
+        PsiConfig::builder()
+            .pre_shared_key(PreSharedKey::new(id, [key; 32]))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_authenticated_run() {
+        let alice =
+            PsiProtocol::new_with_config(&items(&["apple", "banana"]), config(1, 7)).unwrap();
+        let bob = PsiProtocol::new_with_config(&items(&["banana"]), config(1, 7)).unwrap();
+        let alice_msg = alice.message();
+        assert!(alice_msg.authentication.is_some());
+
+        let (alice, alice_double) = alice.compute(bob.message()).unwrap();
+        let (bob, bob_double) = bob.compute(alice_msg).unwrap();
+        assert!(alice_double.authentication.is_some());
+        let (_, alice_result) = alice.finalize(bob_double).unwrap();
+        let (_, bob_result) = bob.finalize(alice_double).unwrap();
+        assert_eq!(alice_result.intersection_hashes.len(), 1);
+        assert_eq!(bob_result.intersection_hashes.len(), 1);
+    }
+
+    #[test]
+    fn test_rejects_bad_mac() {
+        let alice = PsiProtocol::new_with_config(&items(&["apple"]), config(1, 7)).unwrap();
+        let mut tampered = alice.message();
+        tampered.blinded_points.push(tampered.blinded_points[0]);
+
+        let cases = [
+            (config(1, 7), tampered),
+            (config(1, 8), alice.message()),
+            (config(2, 7), alice.message()),
+            (
+                config(1, 7),
+                PsiProtocol::new(&items(&["apple"])).unwrap().message(),
+            ),
+        ];
+        for (bob_config, msg) in cases {
+            let bob = PsiProtocol::new_with_config(&items(&["apple"]), bob_config).unwrap();
+            assert!(matches!(bob.compute(msg), Err(PsiError::CryptoError(_))));
+        }
+    }
+
+    #[test]
+    fn test_finalize_rejects_moved_tag() {
+        let alice = PsiProtocol::new_with_config(&items(&["apple"]), config(1, 7)).unwrap();
+        let bob = PsiProtocol::new_with_config(&items(&["apple"]), config(1, 7)).unwrap();
+        let (alice, _) = alice.compute(bob.message()).unwrap();
+
+        // A blinded message's tag does not verify as a double-blinded one
+        let blinded = bob.message();
+        let mut forged = crate::messages::DoubleBlindedPointsMessage::new(blinded.blinded_points);
+        forged.authentication = blinded.authentication;
+        assert!(matches!(
+            alice.finalize(forged),
+            Err(PsiError::CryptoError(_))
+        ));
+    }
+
+    #[test]
+    fn test_mac_covers_parameters() {
+        let alice = PsiProtocol::new_with_config(&items(&["apple"]), config(1, 7)).unwrap();
+        let bob = PsiProtocol::new_with_config(&items(&["apple"]), config(1, 7)).unwrap();
+        let mut msg = alice.message();
+        msg.authentication = Some(PreSharedKey::new(1, [7; 32]).authenticate(
+            BLINDED_LABEL,
+            &[&msg.blinded_points],
+            &ParameterDigest([0; 32]),
+        ));
+        assert!(matches!(bob.compute(msg), Err(PsiError::CryptoError(_))));
+    }
+
+    #[test]
+    fn test_one_round_rejects_wrong_key() {
+        let alice = PsiProtocol::new_with_config(&items(&["apple"]), config(1, 7)).unwrap();
+        let bob = PsiProtocol::new_with_config(&items(&["apple"]), config(1, 8)).unwrap();
+        assert!(matches!(
+            bob.respond_one_round(alice.message()),
+            Err(PsiError::CryptoError(_))
+        ));
+
+        // A response authenticated under another key
+        let bob = PsiProtocol::new_with_config(&items(&["apple"]), config(1, 7)).unwrap();
+        let mut response = bob.respond_one_round(alice.message()).unwrap();
+        assert!(response.authentication.is_some());
+        response.authentication = Some(PreSharedKey::new(1, [8; 32]).authenticate(
+            ONE_ROUND_LABEL,
+            &[&response.blinded_points, &response.double_blinded_points],
+            &config(1, 7).parameter_digest(),
+        ));
+        assert!(matches!(
+            alice.clone().finalize_one_round(response),
+            Err(PsiError::CryptoError(_))
+        ));
+
+        let response = bob.respond_one_round(alice.message()).unwrap();
+        let (_, result) = alice.finalize_one_round(response).unwrap();
+        assert_eq!(result.intersection_hashes.len(), 1);
+    }
+}
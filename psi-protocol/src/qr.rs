@@ -0,0 +1,432 @@
+//! QR-code-sized chunks for air-gapped exchanges.
+//!
+//! Two offline devices can run the protocol by showing each other QR
+//! codes: [`QrEncoder`] splits a [`wire`](crate::wire) frame into codes
+//! small enough to scan reliably, and [`QrReassembler`] puts the frame back
+//! together from codes scanned in any order, any number of times. For
+//! small, high-sensitivity sets this needs only a few codes per message;
+//! the one-round variant needs the fewest displays (the initiator's message,
+//! then the responder's answer).
+//!
+//! Each code is `PSI:` followed by the [Base45] encoding of a chunk, so
+//! codes only use the QR alphanumeric character set and encode densely in
+//! alphanumeric mode:
+//!
+//! ```text
+//! +-------------+---------------+-----------------+-----------------+-------+
+//! | version: u8 | frame id: 4 B | index (u16, BE) | count (u16, BE) | bytes |
+//! +-------------+---------------+-----------------+-----------------+-------+
+//! ```
+//!
+//! The frame id is a truncated SHA-256 of the whole frame: codes of an
+//! earlier message scanned by mistake do not mix with the current one, and
+//! the reassembled frame must hash to its id.
+//!
+//! [Base45]: https://www.rfc-editor.org/rfc/rfc9285
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::qr::{QrEncoder, QrReassembler};
+//!
+//! // Showing device
+//! for code in QrEncoder::default().encode(&alice.message().to_bytes())? {
+//!     display_qr(&code);
+//! }
+//!
+//! // Scanning device
+//! let mut reassembler = QrReassembler::default();
+//! let frame = loop {
+//!     if let Some(frame) = reassembler.push(&scan_qr())? {
+//!         break frame;
+//!     }
+//!     let (scanned, total) = reassembler.progress();
+//!     show_progress(scanned, total);
+//! };
+//! let alice_msg = BlindedPointsMessage::from_bytes(&frame)?;
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::error::{PsiError, Result};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+
+/// Version of the chunk layout.
+pub const QR_VERSION: u8 = 1;
+
+/// Prefix of every code.
+pub const QR_PREFIX: &str = "PSI:";
+
+/// Default largest code in characters, prefix included; fits a version 25
+/// QR code at medium error correction.
+pub const DEFAULT_CODE_LEN: usize = 1000;
+
+/// Default largest reassembled frame (1 MiB).
+pub const DEFAULT_MAX_FRAME_LEN: usize = 1 << 20;
+
+const FRAME_ID_LEN: usize = 4;
+
+/// Size of the chunk header in bytes.
+const HEADER_LEN: usize = 1 + FRAME_ID_LEN + 2 + 2;
+
+/// Frames being reassembled at once, e.g. the current message and stray
+/// codes of an earlier one.
+const MAX_PARTIAL: usize = 4;
+
+const BASE45: &[u8; 45] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+type FrameId = [u8; FRAME_ID_LEN];
+
+/// Splits frames into QR code contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QrEncoder {
+    code_len: usize,
+    max_frame_len: usize,
+}
+
+impl Default for QrEncoder {
+    fn default() -> Self {
+        Self {
+            code_len: DEFAULT_CODE_LEN,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+}
+
+impl QrEncoder {
+    /// Encoder making codes of at most `code_len` characters, e.g. smaller
+    /// for low-resolution cameras.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `code_len` leaves no room for
+    /// data after the prefix and header
+    pub fn new(code_len: usize) -> Result<Self> {
+        let encoder = Self {
+            code_len,
+            ..Self::default()
+        };
+        if encoder.chunk_len() == 0 {
+            return Err(PsiError::InvalidConfig(format!(
+                "QR code length {} leaves no room for data",
+                code_len
+            )));
+        }
+        Ok(encoder)
+    }
+
+    /// Refuse frames larger than `len` bytes, e.g. the limit of the
+    /// scanning device's [`QrReassembler`].
+    pub fn max_frame_len(mut self, len: usize) -> Self {
+        self.max_frame_len = len;
+        self
+    }
+
+    /// Frame bytes carried by one code.
+    fn chunk_len(&self) -> usize {
+        // Base45 turns every 2 bytes into 3 characters, a last single byte
+        // into 2
+        let bytes = self.code_len.saturating_sub(QR_PREFIX.len()) / 3 * 2;
+        bytes.saturating_sub(HEADER_LEN)
+    }
+
+    /// Split `frame` into the contents of its QR codes, in order.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `frame` exceeds the frame limit
+    /// or needs more than `u16::MAX` codes of the configured length
+    pub fn encode(&self, frame: &[u8]) -> Result<Vec<String>> {
+        if frame.len() > self.max_frame_len {
+            return Err(PsiError::InvalidConfig(format!(
+                "Frame of {} bytes exceeds the limit of {} bytes",
+                frame.len(),
+                self.max_frame_len
+            )));
+        }
+        let id = frame_id(frame);
+        let chunks: Vec<&[u8]> = if frame.is_empty() {
+            vec![&[]]
+        } else {
+            frame.chunks(self.chunk_len()).collect()
+        };
+        let count = u16::try_from(chunks.len()).map_err(|_| {
+            PsiError::InvalidConfig(format!(
+                "Frame of {} bytes needs {} QR codes of {} characters, more than {}",
+                frame.len(),
+                chunks.len(),
+                self.code_len,
+                u16::MAX
+            ))
+        })?;
+        let codes = chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut bytes = Vec::with_capacity(HEADER_LEN + chunk.len());
+                bytes.push(QR_VERSION);
+                bytes.extend_from_slice(&id);
+                bytes.extend_from_slice(&(index as u16).to_be_bytes());
+                bytes.extend_from_slice(&count.to_be_bytes());
+                bytes.extend_from_slice(chunk);
+                format!("{}{}", QR_PREFIX, base45_encode(&bytes))
+            })
+            .collect();
+        Ok(codes)
+    }
+}
+
+/// Chunks of one frame scanned so far.
+#[derive(Debug)]
+struct Partial {
+    count: u16,
+    chunks: BTreeMap<u16, Vec<u8>>,
+    bytes: usize,
+}
+
+/// Rebuilds frames from scanned QR codes.
+#[derive(Debug)]
+pub struct QrReassembler {
+    partial: HashMap<FrameId, Partial>,
+    /// Frame most recently added to, for progress reports
+    current: Option<FrameId>,
+    max_frame_len: usize,
+}
+
+impl Default for QrReassembler {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_LEN)
+    }
+}
+
+impl QrReassembler {
+    /// Reassembler refusing frames larger than `max_frame_len` bytes.
+    pub fn new(max_frame_len: usize) -> Self {
+        Self {
+            partial: HashMap::new(),
+            current: None,
+            max_frame_len,
+        }
+    }
+
+    /// Add a scanned code; returns the frame once all its codes are in.
+    ///
+    /// Codes already scanned are ignored. Completing a frame forgets every
+    /// other partial frame.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` for a code that is not one of
+    /// ours or is malformed, a frame over the size limit, or a reassembled
+    /// frame that does not hash to its id
+    pub fn push(&mut self, code: &str) -> Result<Option<Vec<u8>>> {
+        let (id, index, count, chunk) = parse_code(code)?;
+        if !self.partial.contains_key(&id) && self.partial.len() >= MAX_PARTIAL {
+            // Make room by dropping the least complete frame
+            let stale = self
+                .partial
+                .iter()
+                .min_by_key(|(_, partial)| partial.chunks.len())
+                .map(|(stale, _)| *stale)
+                .expect("map is full");
+            self.partial.remove(&stale);
+        }
+        let partial = self.partial.entry(id).or_insert_with(|| Partial {
+            count,
+            chunks: BTreeMap::new(),
+            bytes: 0,
+        });
+        if partial.count != count {
+            return Err(PsiError::InvalidEncoding(format!(
+                "QR code announces {} codes, earlier ones announced {}",
+                count, partial.count
+            )));
+        }
+        self.current = Some(id);
+        if !partial.chunks.contains_key(&index) {
+            if partial.bytes + chunk.len() > self.max_frame_len {
+                self.partial.remove(&id);
+                return Err(PsiError::InvalidEncoding(format!(
+                    "Frame exceeds the limit of {} bytes",
+                    self.max_frame_len
+                )));
+            }
+            partial.bytes += chunk.len();
+            partial.chunks.insert(index, chunk);
+        }
+        if partial.chunks.len() < usize::from(count) {
+            return Ok(None);
+        }
+
+        let partial = self.partial.remove(&id).expect("looked up above");
+        self.partial.clear();
+        self.current = None;
+        let frame: Vec<u8> = partial.chunks.into_values().flatten().collect();
+        if frame_id(&frame) != id {
+            return Err(PsiError::InvalidEncoding(
+                "Reassembled frame does not match its id".to_string(),
+            ));
+        }
+        Ok(Some(frame))
+    }
+
+    /// Codes scanned and codes needed for the frame last added to; `(0, 0)`
+    /// before the first code.
+    pub fn progress(&self) -> (usize, usize) {
+        self.current
+            .and_then(|id| self.partial.get(&id))
+            .map_or((0, 0), |partial| {
+                (partial.chunks.len(), usize::from(partial.count))
+            })
+    }
+}
+
+/// Truncated SHA-256 of a frame.
+fn frame_id(frame: &[u8]) -> FrameId {
+    let digest = Sha256::digest(frame);
+    let mut id = [0u8; FRAME_ID_LEN];
+    id.copy_from_slice(&digest[..FRAME_ID_LEN]);
+    id
+}
+
+/// Split a code into frame id, chunk index, chunk count and chunk.
+fn parse_code(code: &str) -> Result<(FrameId, u16, u16, Vec<u8>)> {
+    let data = code
+        .strip_prefix(QR_PREFIX)
+        .ok_or_else(|| PsiError::InvalidEncoding("Not a psi-sync QR code".to_string()))?;
+    let bytes = base45_decode(data)?;
+    if bytes.len() < HEADER_LEN {
+        return Err(PsiError::InvalidEncoding(format!(
+            "QR chunk of {} bytes is shorter than its header",
+            bytes.len()
+        )));
+    }
+    if bytes[0] != QR_VERSION {
+        return Err(PsiError::InvalidEncoding(format!(
+            "Unknown QR chunk version {}",
+            bytes[0]
+        )));
+    }
+    let mut id = [0u8; FRAME_ID_LEN];
+    id.copy_from_slice(&bytes[1..1 + FRAME_ID_LEN]);
+    let field = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+    let (index, count) = (field(1 + FRAME_ID_LEN), field(3 + FRAME_ID_LEN));
+    if index >= count {
+        return Err(PsiError::InvalidEncoding(format!(
+            "QR code {} of a frame of {} codes",
+            index, count
+        )));
+    }
+    Ok((id, index, count, bytes[HEADER_LEN..].to_vec()))
+}
+
+/// Base45 encoding of RFC 9285.
+fn base45_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(2) * 3);
+    for pair in bytes.chunks(2) {
+        let (mut value, digits) = match pair {
+            [a, b] => (usize::from(*a) * 256 + usize::from(*b), 3),
+            [a] => (usize::from(*a), 2),
+            _ => unreachable!("chunks of at most two bytes"),
+        };
+        for _ in 0..digits {
+            out.push(char::from(BASE45[value % 45]));
+            value /= 45;
+        }
+    }
+    out
+}
+
+/// Base45 decoding of RFC 9285.
+fn base45_decode(text: &str) -> Result<Vec<u8>> {
+    let invalid = || PsiError::InvalidEncoding("Invalid Base45 in QR code".to_string());
+    let digits = text
+        .bytes()
+        .map(|c| BASE45.iter().position(|&d| d == c).ok_or_else(invalid))
+        .collect::<Result<Vec<_>>>()?;
+    let mut out = Vec::with_capacity(digits.len() / 3 * 2 + 1);
+    for group in digits.chunks(3) {
+        let value = group
+            .iter()
+            .rev()
+            .fold(0, |value, digit| value * 45 + digit);
+        match group.len() {
+            3 if value <= 0xffff => out.extend_from_slice(&(value as u16).to_be_bytes()),
+            2 if value <= 0xff => out.push(value as u8),
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::BlindedPointsMessage;
+    use crate::protocol::PsiProtocol;
+
+    #[test]
+    fn test_base45_rfc_vectors() {
+        for (bytes, text) in [
+            (&b"AB"[..], "BB8"),
+            (b"Hello!!", "%69 VD92EX0"),
+            (b"base-45", "UJCLQE7W581"),
+            (b"ietf!", "QED8WEX0"),
+        ] {
+            assert_eq!(base45_encode(bytes), text);
+            assert_eq!(base45_decode(text).unwrap(), bytes);
+        }
+        assert!(base45_decode("GGW").is_err());
+        assert!(base45_decode("a").is_err());
+        assert!(base45_decode("ZZZZ").is_err());
+    }
+
+    #[test]
+    fn test_message_through_scanned_codes() {
+        let items: Vec<Vec<u8>> = (0..8).map(|i| vec![i]).collect();
+        let msg = PsiProtocol::new(&items).unwrap().message();
+        let frame = msg.to_bytes();
+        let encoder = QrEncoder::new(200).unwrap();
+        let codes = encoder.encode(&frame).unwrap();
+        assert!(codes.len() > 1);
+        assert!(codes.iter().all(|code| code.len() <= 200
+            && code
+                .bytes()
+                .all(|c| BASE45.contains(&c) || QR_PREFIX.as_bytes().contains(&c))));
+
+        // A stray code of another message, then codes in reverse, rescanned
+        let mut reassembler = QrReassembler::default();
+        let stray = encoder.encode(&[1; 300]).unwrap();
+        assert_eq!(reassembler.push(&stray[0]).unwrap(), None);
+        let mut scanned = None;
+        for code in codes.iter().rev().flat_map(|code| [code, code]) {
+            if let Some(frame) = reassembler.push(code).unwrap() {
+                scanned = Some(frame);
+                break;
+            }
+            assert_eq!(reassembler.progress().1, codes.len());
+        }
+        assert_eq!(
+            BlindedPointsMessage::from_bytes(&scanned.unwrap()).unwrap(),
+            msg
+        );
+        assert_eq!(reassembler.progress(), (0, 0));
+    }
+
+    #[test]
+    fn test_rejects_bad_codes() {
+        let mut reassembler = QrReassembler::new(16);
+        assert!(reassembler.push("HELLO").is_err());
+        assert!(reassembler.push("PSI:abc").is_err());
+        let codes = QrEncoder::new(30).unwrap().encode(&[7; 64]).unwrap();
+        assert!(codes
+            .iter()
+            .map(|code| reassembler.push(code))
+            .any(|outcome| outcome.is_err()));
+        assert!(QrEncoder::new(10).is_err());
+
+        // Frames the encoder cannot or should not split
+        let tiny = QrEncoder::new(20).unwrap();
+        assert!(tiny.encode(&[0; 1 << 16]).is_err());
+        assert!(tiny.max_frame_len(8).encode(&[0; 9]).is_err());
+        assert!(QrEncoder::default()
+            .encode(&vec![0; DEFAULT_MAX_FRAME_LEN + 1])
+            .is_err());
+    }
+}
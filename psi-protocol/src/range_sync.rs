@@ -0,0 +1,365 @@
+//! Recursive range-partition sync.
+//!
+//! A divide-and-conquer alternative to [`MerklePrefilter`](crate::MerklePrefilter)
+//! for large, similar sets. Both peers start with the whole hash space as one
+//! range and exchange a count and digest per range. Equal digests settle a
+//! range as shared; differing ranges are split into 16 sub-ranges and
+//! compared again in the next round, until a range holds at most `leaf_size`
+//! items on both sides. PSI then only runs over the items of those leaves.
+//!
+//! Nothing is precomputed: each round only hashes the ranges still in
+//! question, so the work follows the size of the difference rather than the
+//! size of the sets, at the price of one round trip per level.
+//!
+//! Like the Merkle prefilter, the peers learn which hash ranges they agree on
+//! and how many items each side holds per compared range.
+
+use crate::config::HashAlgorithm;
+use crate::error::{Phase, PsiError, Result};
+use crate::item_id::ItemId;
+use crate::messages::{RangeDigest, RangeDigestsMessage};
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+
+/// Sub-ranges per split; each level consumes one hex digit of the hash.
+const FANOUT: u64 = 16;
+
+/// Deepest level: the 16 hex digits of the 64-bit hash prefix.
+const MAX_LEVEL: u8 = 16;
+
+const RANGE_TAG: &[u8] = b"psi-sync/range";
+
+/// A range of the hash space still being compared.
+#[derive(Debug, Clone)]
+struct Node {
+    /// First `level` hex digits shared by every hash in the range
+    prefix: u64,
+    /// Items of the range, as positions in `RangeSync::sorted`
+    span: Range<usize>,
+    digest: [u8; 32],
+}
+
+/// Range-partition state for one local set.
+///
+/// Each round, both peers send [`digests`](Self::digests) and feed the
+/// peer's message to [`on_digests`](Self::on_digests) until
+/// [`is_done`](Self::is_done). Both derive the next ranges from both sides'
+/// counts and digests, so they stay in step. Afterwards every local item is
+/// in exactly one of [`shared_indices`](Self::shared_indices),
+/// [`psi_indices`](Self::psi_indices) and
+/// [`local_only_indices`](Self::local_only_indices).
+///
+/// # Example
+/// ```ignore
+/// use psi_protocol::{PsiProtocol, RangeSync};
+///
+/// let mut sync = RangeSync::new(&items, 64)?;
+/// while !sync.is_done() {
+///     let remote = exchange(sync.digests());
+///     sync.on_digests(&remote)?;
+/// }
+///
+/// // One PSI run over every leaf left in question
+/// let protocol = PsiProtocol::new(&sync.psi_items(&items))?;
+/// # Ok::<(), psi_protocol::PsiError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct RangeSync {
+    leaf_size: usize,
+    /// Item hashes with their index in the caller's slice, sorted by hash
+    sorted: Vec<(ItemId, usize)>,
+    level: u8,
+    frontier: Vec<Node>,
+    shared: Vec<Range<usize>>,
+    psi: Vec<Range<usize>>,
+    local_only: Vec<Range<usize>>,
+}
+
+impl RangeSync {
+    /// Start a sync over `items` hashed with the default algorithm.
+    ///
+    /// Ranges are split until they hold at most `leaf_size` items on both
+    /// sides; both peers must use the same value.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `leaf_size` is 0
+    pub fn new(items: &[Vec<u8>], leaf_size: usize) -> Result<Self> {
+        Self::new_with_hash(items, leaf_size, HashAlgorithm::default())
+    }
+
+    /// Start a sync over `items` hashed with the given algorithm.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `leaf_size` is 0
+    pub fn new_with_hash(
+        items: &[Vec<u8>],
+        leaf_size: usize,
+        algorithm: HashAlgorithm,
+    ) -> Result<Self> {
+        if leaf_size == 0 {
+            return Err(PsiError::InvalidConfig(
+                "Leaf size must be at least 1".to_string(),
+            ));
+        }
+
+        let mut sorted: Vec<(ItemId, usize)> = items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| (ItemId::of_with(algorithm, item), index))
+            .collect();
+        sorted.sort_unstable();
+        sorted.dedup_by_key(|(id, _)| *id);
+
+        let mut sync = Self {
+            leaf_size,
+            sorted,
+            level: 0,
+            frontier: Vec::new(),
+            shared: Vec::new(),
+            psi: Vec::new(),
+            local_only: Vec::new(),
+        };
+        let root = sync.node(0, 0..sync.sorted.len());
+        sync.frontier.push(root);
+        Ok(sync)
+    }
+
+    /// Returns true once every range is settled.
+    pub fn is_done(&self) -> bool {
+        self.frontier.is_empty()
+    }
+
+    /// Number of completed rounds.
+    pub fn rounds(&self) -> u8 {
+        self.level
+    }
+
+    /// Counts and digests of the ranges in question, to send to the peer.
+    ///
+    /// Empty once the sync is done.
+    pub fn digests(&self) -> RangeDigestsMessage {
+        RangeDigestsMessage::new(
+            self.level,
+            self.frontier
+                .iter()
+                .map(|node| RangeDigest {
+                    count: node.span.len() as u64,
+                    digest: node.digest,
+                })
+                .collect(),
+        )
+    }
+
+    /// Settle or split every range in question against the peer's digests.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` if the message is for another
+    /// level, and `PsiError::LengthMismatch` if it holds a different number
+    /// of ranges than ours
+    pub fn on_digests(&mut self, remote: &RangeDigestsMessage) -> Result<()> {
+        if self.is_done() {
+            return Ok(());
+        }
+        if remote.level != self.level {
+            return Err(PsiError::InvalidEncoding(format!(
+                "Range digests for level {}, expected level {}",
+                remote.level, self.level
+            )));
+        }
+        if remote.ranges.len() != self.frontier.len() {
+            return Err(PsiError::LengthMismatch {
+                phase: Phase::Prepare,
+                expected: self.frontier.len(),
+                actual: remote.ranges.len(),
+            });
+        }
+
+        let leaf_size = self.leaf_size as u64;
+        let mut next = Vec::new();
+        for (node, theirs) in std::mem::take(&mut self.frontier)
+            .into_iter()
+            .zip(&remote.ranges)
+        {
+            let ours = node.span.len() as u64;
+            if node.digest == theirs.digest {
+                self.shared.push(node.span);
+            } else if ours == 0 {
+                // Only the peer has items here
+            } else if theirs.count == 0 {
+                self.local_only.push(node.span);
+            } else if (ours <= leaf_size && theirs.count <= leaf_size) || self.level == MAX_LEVEL {
+                self.psi.push(node.span);
+            } else {
+                next.extend(self.split(&node));
+            }
+        }
+        self.level += 1;
+        self.frontier = next;
+        Ok(())
+    }
+
+    /// Indices into the caller's items that both peers hold.
+    ///
+    /// Only meaningful once [`is_done`](Self::is_done).
+    pub fn shared_indices(&self) -> Vec<usize> {
+        self.indices(&self.shared)
+    }
+
+    /// Indices into the caller's items that still need PSI.
+    ///
+    /// Only meaningful once [`is_done`](Self::is_done).
+    pub fn psi_indices(&self) -> Vec<usize> {
+        self.indices(&self.psi)
+    }
+
+    /// Indices into the caller's items in ranges the peer has no item in.
+    ///
+    /// Only meaningful once [`is_done`](Self::is_done).
+    pub fn local_only_indices(&self) -> Vec<usize> {
+        self.indices(&self.local_only)
+    }
+
+    /// The caller's items that still need PSI, ready to feed to
+    /// [`PsiProtocol::new`](crate::PsiProtocol::new).
+    ///
+    /// Both peers' leaves cover the same ranges, so a single run over these
+    /// items finds every remaining shared item. `items` must be the slice
+    /// the sync was started from.
+    pub fn psi_items(&self, items: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        self.psi_indices()
+            .into_iter()
+            .map(|index| items[index].clone())
+            .collect()
+    }
+
+    fn indices(&self, spans: &[Range<usize>]) -> Vec<usize> {
+        let mut indices: Vec<usize> = spans
+            .iter()
+            .flat_map(|span| &self.sorted[span.clone()])
+            .map(|(_, index)| *index)
+            .collect();
+        indices.sort_unstable();
+        indices
+    }
+
+    /// Split a range into its 16 sub-ranges at the next level.
+    fn split(&self, node: &Node) -> Vec<Node> {
+        let level = self.level + 1;
+        let mut start = node.span.start;
+        (0..FANOUT)
+            .map(|digit| {
+                let prefix = node.prefix * FANOUT + digit;
+                let len = self.sorted[start..node.span.end]
+                    .partition_point(|(id, _)| prefix_of(id, level) == prefix);
+                let child = self.node(prefix, start..start + len);
+                start += len;
+                child
+            })
+            .collect()
+    }
+
+    fn node(&self, prefix: u64, span: Range<usize>) -> Node {
+        let mut hasher = Sha256::new();
+        hasher.update(RANGE_TAG);
+        for (id, _) in &self.sorted[span.clone()] {
+            hasher.update(id.as_bytes());
+        }
+        Node {
+            prefix,
+            span,
+            digest: hasher.finalize().into(),
+        }
+    }
+}
+
+/// First `level` hex digits of an item hash.
+fn prefix_of(id: &ItemId, level: u8) -> u64 {
+    if level == 0 {
+        return 0;
+    }
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&id.as_bytes()[..8]);
+    u64::from_be_bytes(bytes) >> (64 - 4 * level as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::numbered;
+
+    fn run(alice: &mut RangeSync, bob: &mut RangeSync) {
+        while !alice.is_done() {
+            let alice_digests = alice.digests();
+            alice.on_digests(&bob.digests()).unwrap();
+            bob.on_digests(&alice_digests).unwrap();
+        }
+        assert!(bob.is_done());
+    }
+
+    #[test]
+    fn test_identical_sets_settle_in_one_round() {
+        let set = numbered(0..1000);
+        let mut alice = RangeSync::new(&set, 8).unwrap();
+        let mut bob = RangeSync::new(&set, 8).unwrap();
+        run(&mut alice, &mut bob);
+        assert_eq!(alice.rounds(), 1);
+        assert_eq!(alice.shared_indices().len(), 1000);
+        assert!(alice.psi_indices().is_empty());
+    }
+
+    #[test]
+    fn test_range_sync_narrows_psi_to_differences() {
+        let mut alice_items = numbered(0..5000);
+        let mut bob_items = numbered(0..5000);
+        alice_items.push(b"only-alice".to_vec());
+        bob_items.push(b"only-bob".to_vec());
+        bob_items.remove(42);
+
+        let mut alice = RangeSync::new(&alice_items, 8).unwrap();
+        let mut bob = RangeSync::new(&bob_items, 8).unwrap();
+        run(&mut alice, &mut bob);
+
+        // Every item lands in exactly one bucket
+        let alice_total = alice.shared_indices().len()
+            + alice.psi_indices().len()
+            + alice.local_only_indices().len();
+        assert_eq!(alice_total, alice_items.len());
+
+        let alice_psi = alice.psi_items(&alice_items);
+        let bob_psi = bob.psi_items(&bob_items);
+        assert!(alice_psi.len() + alice.local_only_indices().len() <= 3 * 8);
+        assert_eq!(alice.shared_indices().len(), bob.shared_indices().len());
+
+        let shared_by_psi = if alice_psi.is_empty() || bob_psi.is_empty() {
+            0
+        } else {
+            crate::run_local_psi(&alice_psi, &bob_psi).unwrap().0.len()
+        };
+        assert_eq!(
+            alice.shared_indices().len() + shared_by_psi,
+            alice_items.len() - 2
+        );
+    }
+
+    #[test]
+    fn test_range_sync_rejects_bad_input() {
+        assert!(matches!(
+            RangeSync::new(&numbered(0..4), 0),
+            Err(PsiError::InvalidConfig(_))
+        ));
+
+        let mut sync = RangeSync::new(&numbered(0..4), 1).unwrap();
+        assert!(matches!(
+            sync.on_digests(&RangeDigestsMessage::new(3, vec![])),
+            Err(PsiError::InvalidEncoding(_))
+        ));
+        assert_eq!(
+            sync.on_digests(&RangeDigestsMessage::new(0, vec![])),
+            Err(PsiError::LengthMismatch {
+                phase: Phase::Prepare,
+                expected: 1,
+                actual: 0
+            })
+        );
+    }
+}
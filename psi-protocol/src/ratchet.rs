@@ -0,0 +1,285 @@
+//! Key ratchet for peers that sync repeatedly.
+//!
+//! A [`SessionRatchet`] keeps two 32-byte chain keys between sessions:
+//!
+//! - a local key, never shared, from which each session's blinding secret is
+//!   derived;
+//! - a shared key, which both peers evolve identically from the
+//!   double-blinded intersection points and use to key the next session's
+//!   [`Transcript`](crate::Transcript) confirmation.
+//!
+//! Both keys are replaced by a one-way function of themselves and the
+//! session output on every [`advance`](SessionRatchet::advance), so a leaked
+//! ratchet state does not reveal the secrets of earlier sessions and cannot
+//! be used to link their blinded values. Persist the state with
+//! [`to_bytes`](SessionRatchet::to_bytes) to resume after a restart.
+//!
+//! The shared key only becomes secret once a session had a non-empty
+//! intersection: before that it depends on public values alone.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::SessionRatchet;
+//!
+//! let mut ratchet = SessionRatchet::new(&local_seed, &shared_root)?;
+//! // Every sync round
+//! let alice = ratchet.prepare(&items, PsiConfig::default())?;
+//! let transcript = Transcript::new(&ratchet.context("inventory-sync"));
+//! // exchange, finalize and confirm as usual...
+//! ratchet.advance(&result);
+//! store(ratchet.to_bytes());
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::config::PsiConfig;
+use crate::error::{PsiError, Result};
+use crate::messages::PsiResult;
+use crate::protocol::PsiProtocol;
+use crate::state::PreparedState;
+use crate::transcript::SessionContext;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use zeroize::Zeroize;
+
+const LOCAL_INFO: &[u8] = b"psi-sync/ratchet/local";
+const SHARED_INFO: &[u8] = b"psi-sync/ratchet/shared";
+const BLINDING_CONTEXT: &[u8] = b"psi-sync/ratchet/blinding";
+const ID_INFO: &[u8] = b"psi-sync/ratchet/id";
+
+/// Minimum length of the local seed, matching
+/// [`PsiProtocol::with_derived_secret`].
+const MIN_SEED_LEN: usize = 32;
+
+/// Size of a serialized ratchet state in bytes.
+pub const RATCHET_STATE_LEN: usize = 72;
+
+/// Per-peer ratchet state carried from one session to the next.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SessionRatchet {
+    local: [u8; 32],
+    shared: [u8; 32],
+    epoch: u64,
+}
+
+impl SessionRatchet {
+    /// Start a ratchet.
+    ///
+    /// `local_seed` is private entropy of this party only; `shared_root` is
+    /// a key shared with the peer, or empty if there is none.
+    ///
+    /// # Errors
+    /// Returns `PsiError::CryptoError` if `local_seed` is shorter than 32
+    /// bytes
+    pub fn new(local_seed: &[u8], shared_root: &[u8]) -> Result<Self> {
+        if local_seed.len() < MIN_SEED_LEN {
+            return Err(PsiError::CryptoError(format!(
+                "Local seed must be at least {} bytes",
+                MIN_SEED_LEN
+            )));
+        }
+        Ok(Self {
+            local: expand(None, local_seed, LOCAL_INFO),
+            shared: expand(None, shared_root, SHARED_INFO),
+            epoch: 0,
+        })
+    }
+
+    /// Number of sessions completed with this ratchet.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Public identifier of the current shared state.
+    ///
+    /// Both peers have the same id when they are at the same point of the
+    /// ratchet; compare ids before a session to detect a peer that missed
+    /// an [`advance`](Self::advance).
+    pub fn session_id(&self) -> [u8; 32] {
+        expand(None, &self.shared, ID_INFO)
+    }
+
+    /// Prepare this epoch's session with a blinding secret from the local key.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::new_with_config`]
+    pub fn prepare(
+        &self,
+        items: &[Vec<u8>],
+        config: PsiConfig,
+    ) -> Result<PsiProtocol<PreparedState>> {
+        let mut context = BLINDING_CONTEXT.to_vec();
+        context.extend_from_slice(&self.epoch.to_be_bytes());
+        PsiProtocol::with_derived_secret(items, &self.local, &context, config)
+    }
+
+    /// Session context for `application`, keyed with the shared key.
+    pub fn context(&self, application: impl AsRef<[u8]>) -> SessionContext {
+        SessionContext::new(application).resumption_key(self.shared)
+    }
+
+    /// Move to the next epoch, mixing in the session's result.
+    ///
+    /// Both peers must call this with the result of the same session.
+    pub fn advance(&mut self, result: &PsiResult) {
+        let mut shared_points: Vec<[u8; 32]> = result
+            .double_blinded_map
+            .values()
+            .map(|point| point.to_bytes())
+            .collect();
+        shared_points.sort_unstable();
+        let output = shared_points.concat();
+
+        let local = expand(Some(&self.local), &output, LOCAL_INFO);
+        let shared = expand(Some(&self.shared), &output, SHARED_INFO);
+        self.local.zeroize();
+        self.shared.zeroize();
+        self.local = local;
+        self.shared = shared;
+        self.epoch += 1;
+    }
+
+    /// Serialize the state, to persist it between runs.
+    ///
+    /// The bytes are as sensitive as the keys they hold.
+    pub fn to_bytes(&self) -> [u8; RATCHET_STATE_LEN] {
+        let mut bytes = [0u8; RATCHET_STATE_LEN];
+        bytes[..32].copy_from_slice(&self.local);
+        bytes[32..64].copy_from_slice(&self.shared);
+        bytes[64..].copy_from_slice(&self.epoch.to_be_bytes());
+        bytes
+    }
+
+    /// Restore a state saved with [`to_bytes`](Self::to_bytes).
+    pub fn from_bytes(bytes: &[u8; RATCHET_STATE_LEN]) -> Self {
+        let mut local = [0u8; 32];
+        let mut shared = [0u8; 32];
+        let mut epoch = [0u8; 8];
+        local.copy_from_slice(&bytes[..32]);
+        shared.copy_from_slice(&bytes[32..64]);
+        epoch.copy_from_slice(&bytes[64..]);
+        Self {
+            local,
+            shared,
+            epoch: u64::from_be_bytes(epoch),
+        }
+    }
+}
+
+impl std::fmt::Debug for SessionRatchet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionRatchet")
+            .field("epoch", &self.epoch)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for SessionRatchet {
+    fn drop(&mut self) {
+        self.local.zeroize();
+        self.shared.zeroize();
+    }
+}
+
+/// HKDF-SHA256 to a 32-byte key.
+fn expand(salt: Option<&[u8]>, ikm: &[u8], info: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(salt, ikm)
+        .expand(info, &mut key)
+        .expect("valid length");
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::items;
+    use crate::transcript::Transcript;
+
+    /// Run one ratcheted session and return both confirmation results.
+    fn session(alice: &mut SessionRatchet, bob: &mut SessionRatchet) -> (bool, bool) {
+        let alice_proto = alice
+            .prepare(&items(&["apple", "banana"]), PsiConfig::default())
+            .unwrap();
+        let bob_proto = bob
+            .prepare(&items(&["banana", "cherry"]), PsiConfig::default())
+            .unwrap();
+        let (alice_msg, bob_msg) = (alice_proto.message(), bob_proto.message());
+        let (alice_proto, alice_double) = alice_proto.compute(bob_msg.clone()).unwrap();
+        let (bob_proto, bob_double) = bob_proto.compute(alice_msg.clone()).unwrap();
+
+        let mut alice_transcript = Transcript::new(&alice.context("test"));
+        alice_transcript.append_blinded(&alice_msg, &bob_msg);
+        let mut bob_transcript = Transcript::new(&bob.context("test"));
+        bob_transcript.append_blinded(&bob_msg, &alice_msg);
+        let confirmed = (
+            alice_transcript
+                .verify(&bob_transcript.confirmation().unwrap())
+                .is_ok(),
+            bob_transcript
+                .verify(&alice_transcript.confirmation().unwrap())
+                .is_ok(),
+        );
+
+        let (_, alice_result) = alice_proto.finalize(bob_double).unwrap();
+        let (_, bob_result) = bob_proto.finalize(alice_double).unwrap();
+        alice.advance(&alice_result);
+        bob.advance(&bob_result);
+        confirmed
+    }
+
+    #[test]
+    fn test_peers_stay_in_sync() {
+        let mut alice = SessionRatchet::new(&[1u8; 32], b"shared root").unwrap();
+        let mut bob = SessionRatchet::new(&[2u8; 32], b"shared root").unwrap();
+        let first_id = alice.session_id();
+        assert_eq!(first_id, bob.session_id());
+
+        for epoch in 1..=3 {
+            assert_eq!(session(&mut alice, &mut bob), (true, true));
+            assert_eq!(alice.epoch(), epoch);
+            assert_eq!(alice.session_id(), bob.session_id());
+        }
+        assert_ne!(alice.session_id(), first_id);
+    }
+
+    #[test]
+    fn test_blinding_secret_changes_every_epoch() {
+        let mut ratchet = SessionRatchet::new(&[1u8; 32], b"").unwrap();
+        let before = ratchet
+            .prepare(&items(&["apple"]), PsiConfig::default())
+            .unwrap();
+        ratchet.advance(&PsiResult::new(vec![], Default::default()));
+        let after = ratchet
+            .prepare(&items(&["apple"]), PsiConfig::default())
+            .unwrap();
+        assert_ne!(before.message(), after.message());
+    }
+
+    #[test]
+    fn test_lagging_peer_fails_confirmation() {
+        let mut alice = SessionRatchet::new(&[1u8; 32], b"shared root").unwrap();
+        let mut bob = SessionRatchet::new(&[2u8; 32], b"shared root").unwrap();
+        session(&mut alice, &mut bob);
+
+        let mut stale_bob = SessionRatchet::new(&[2u8; 32], b"shared root").unwrap();
+        assert_ne!(alice.session_id(), stale_bob.session_id());
+        assert_eq!(session(&mut alice, &mut stale_bob), (false, false));
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut ratchet = SessionRatchet::new(&[1u8; 32], b"root").unwrap();
+        ratchet.advance(&PsiResult::new(vec![], Default::default()));
+        let restored = SessionRatchet::from_bytes(&ratchet.to_bytes());
+        assert_eq!(restored, ratchet);
+        assert_eq!(restored.epoch(), 1);
+    }
+
+    #[test]
+    fn test_short_seed_is_rejected() {
+        assert!(matches!(
+            SessionRatchet::new(&[1u8; 16], b""),
+            Err(PsiError::CryptoError(_))
+        ));
+    }
+}
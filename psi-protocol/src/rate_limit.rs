@@ -0,0 +1,215 @@
+//! Per-peer caps on queried items.
+//!
+//! Every PSI run reveals whether each of the client's items is in the
+//! server's set. A client that runs the protocol over and over with fresh
+//! guesses can enumerate the server's set element by element, however well
+//! each single run is protected. [`RateLimiter`] caps the total number of
+//! items each peer may submit within a sliding time window; check every
+//! incoming blinded message against it before calling `compute`.
+//!
+//! It also implements [`QueryThrottle`](crate::breach::QueryThrottle), so it
+//! can guard a [`BreachServer`](crate::breach::BreachServer) directly.
+//!
+//! Keys should identify the peer as strongly as the deployment allows (an
+//! authenticated identity rather than an IP address), otherwise the cap is
+//! only as good as the cost of a new key.
+
+use crate::breach::QueryThrottle;
+use crate::error::{Limit, PsiError, Result};
+use crate::messages::BlindedPointsMessage;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Sliding-window budget of queried items, per peer.
+///
+/// # Example
+/// ```ignore
+/// use psi_protocol::RateLimiter;
+/// use std::time::Duration;
+///
+/// // At most 10 000 items per peer and hour
+/// let mut limiter = RateLimiter::new(10_000, Duration::from_secs(3600));
+///
+/// // On every incoming blinded message
+/// limiter.check_message(&peer_id, &msg)?;
+/// let (session, reply) = server.compute_for_peer(msg)?;
+///
+/// // Periodically
+/// limiter.sweep();
+/// # Ok::<(), psi_protocol::PsiError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimiter<K> {
+    max_items: usize,
+    window: Duration,
+    /// Admitted queries per peer, oldest first.
+    peers: HashMap<K, VecDeque<(Instant, usize)>>,
+}
+
+impl<K: Eq + Hash + Clone> RateLimiter<K> {
+    /// Allow each peer at most `max_items` items per `window`.
+    pub fn new(max_items: usize, window: Duration) -> Self {
+        Self {
+            max_items,
+            window,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Maximum number of items per peer and window.
+    pub fn max_items(&self) -> usize {
+        self.max_items
+    }
+
+    /// Length of the sliding window.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Admit or reject `items` more items from `peer`.
+    ///
+    /// # Errors
+    /// Returns `PsiError::LimitExceeded` if the query would take the peer
+    /// over its budget; rejected queries are not counted
+    pub fn check(&mut self, peer: &K, items: usize) -> Result<()> {
+        self.check_at(peer, items, Instant::now())
+    }
+
+    /// Admit or reject the points of a blinded message from `peer`.
+    ///
+    /// Padding points count like real ones, since the server cannot tell
+    /// them apart.
+    ///
+    /// # Errors
+    /// Same as [`check`](Self::check)
+    pub fn check_message(&mut self, peer: &K, msg: &BlindedPointsMessage) -> Result<()> {
+        self.check(peer, msg.len())
+    }
+
+    /// Admit or reject `items` more items from `peer` at `now`.
+    ///
+    /// # Errors
+    /// Same as [`check`](Self::check)
+    pub fn check_at(&mut self, peer: &K, items: usize, now: Instant) -> Result<()> {
+        let actual = self.used_at(peer, now).saturating_add(items);
+        if actual > self.max_items {
+            return Err(PsiError::LimitExceeded {
+                limit: Limit::PeerItems,
+                max: self.max_items,
+                actual,
+            });
+        }
+        let window = self.window;
+        let queries = self.peers.entry(peer.clone()).or_default();
+        while queries
+            .front()
+            .is_some_and(|(at, _)| now.saturating_duration_since(*at) >= window)
+        {
+            queries.pop_front();
+        }
+        queries.push_back((now, items));
+        Ok(())
+    }
+
+    /// Items admitted from `peer` within the current window.
+    pub fn used(&self, peer: &K) -> usize {
+        self.used_at(peer, Instant::now())
+    }
+
+    /// Items admitted from `peer` within the window ending at `now`.
+    pub fn used_at(&self, peer: &K, now: Instant) -> usize {
+        self.peers.get(peer).map_or(0, |queries| {
+            queries
+                .iter()
+                .filter(|(at, _)| now.saturating_duration_since(*at) < self.window)
+                .map(|(_, items)| items)
+                .fold(0usize, |total, items| total.saturating_add(*items))
+        })
+    }
+
+    /// Forget every peer whose queries have all left the window.
+    ///
+    /// Returns the number of peers dropped.
+    pub fn sweep(&mut self) -> usize {
+        self.sweep_at(Instant::now())
+    }
+
+    /// Forget every peer with no query in the window ending at `now`.
+    ///
+    /// Returns the number of peers dropped.
+    pub fn sweep_at(&mut self, now: Instant) -> usize {
+        let before = self.peers.len();
+        let window = self.window;
+        self.peers.retain(|_, queries| {
+            queries
+                .back()
+                .is_some_and(|(at, _)| now.saturating_duration_since(*at) < window)
+        });
+        before - self.peers.len()
+    }
+}
+
+impl<K: Eq + Hash + Clone> QueryThrottle<K> for RateLimiter<K> {
+    /// # Errors
+    /// Same as [`RateLimiter::check`]
+    fn check(&mut self, client: &K, count: usize) -> Result<()> {
+        RateLimiter::check(self, client, count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn test_budget_per_peer() {
+        let mut limiter = RateLimiter::new(10, HOUR);
+        let now = Instant::now();
+        limiter.check_at(&"alice", 6, now).unwrap();
+        assert_eq!(
+            limiter.check_at(&"alice", 5, now),
+            Err(PsiError::LimitExceeded {
+                limit: Limit::PeerItems,
+                max: 10,
+                actual: 11
+            })
+        );
+        // Rejected queries are not counted, other peers are independent
+        assert_eq!(limiter.used_at(&"alice", now), 6);
+        limiter.check_at(&"alice", 4, now).unwrap();
+        limiter.check_at(&"bob", 10, now).unwrap();
+    }
+
+    #[test]
+    fn test_window_slides() {
+        let mut limiter = RateLimiter::new(10, HOUR);
+        let start = Instant::now();
+        limiter.check_at(&1, 6, start).unwrap();
+        limiter
+            .check_at(&1, 4, start + Duration::from_secs(1800))
+            .unwrap();
+        assert!(limiter.check_at(&1, 1, start + HOUR / 2).is_err());
+
+        // The first query leaves the window after an hour
+        let later = start + HOUR;
+        assert_eq!(limiter.used_at(&1, later), 4);
+        limiter.check_at(&1, 6, later).unwrap();
+        assert!(limiter.check_at(&1, 1, later).is_err());
+    }
+
+    #[test]
+    fn test_sweep_and_messages() {
+        let mut limiter = RateLimiter::new(2, HOUR);
+        let msg = BlindedPointsMessage::new(vec![Default::default(); 3]);
+        assert!(limiter.check_message(&"mallory", &msg).is_err());
+
+        let now = Instant::now();
+        limiter.check_at(&"alice", 1, now).unwrap();
+        assert_eq!(limiter.sweep_at(now), 0);
+        assert_eq!(limiter.sweep_at(now + HOUR), 1);
+        assert_eq!(limiter.used_at(&"alice", now), 0);
+    }
+}
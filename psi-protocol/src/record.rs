@@ -0,0 +1,743 @@
+//! Record a session to a file and replay it deterministically.
+//!
+//! A sync that fails or returns a surprising intersection in production is
+//! hard to reproduce: the peer is gone and every run draws a fresh secret.
+//! [`RecordingSession`] drives a [`PsiSession`] whose randomness (secret
+//! scalar, padding points, message shuffle) comes from a single 32-byte
+//! seed, and keeps the seed, the configuration, the local item hashes and
+//! every message sent or received in a [`SessionRecording`]. Saved to a
+//! file, the recording can be [replayed](SessionRecording::replay) anywhere
+//! without the original peer or input data: the replay rebuilds the same
+//! session, feeds it the recorded remote messages and checks that it sends
+//! the same messages back.
+//!
+//! A recording holds the seed and therefore the session's secret, plus the
+//! hashes of the local items; treat it like the items themselves. A
+//! pre-shared key is never written: replays skip MAC checks, and sent
+//! messages are compared by their points only. The seeded generator is
+//! `rand`'s `StdRng`, so a recording replays with the version of this crate
+//! that wrote it.
+//!
+//! Requires the `record` feature.
+//!
+//! # Format
+//!
+//! ```text
+//! +-------------+------------+--------+--------------+----------------+
+//! | version: u8 | seed: 32 B | config | item hashes  | messages       |
+//! +-------------+------------+--------+--------------+----------------+
+//! ```
+//!
+//! Lists are prefixed with a big-endian `u32` count, as in [`wire`]. Each
+//! message is a direction byte followed by a length-prefixed [`wire`] frame.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::record::{RecordingSession, SessionRecording};
+//!
+//! // In production
+//! let mut session = RecordingSession::new(&items, config)?;
+//! send(session.message()?);
+//! let reply = session.on_blinded(receive())?;
+//! // ...
+//! session.recording().save("sync-1234.psirec")?;
+//!
+//! // Later, on a laptop
+//! let replay = SessionRecording::load("sync-1234.psirec")?.replay()?;
+//! assert_eq!(replay.diverged_at, None);
+//! println!("{:?} {:?}", replay.result, replay.errors);
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::config::{HashAlgorithm, MessageOrder, Padding, PsiConfig};
+use crate::crypto::{
+    blind_points_parallel, decompress_point, hash_inputs_sorted, hash_to_point_in,
+};
+use crate::error::{PsiError, Result};
+use crate::messages::{BlindedPointsMessage, DoubleBlindedPointsMessage, PsiResult};
+use crate::protocol::PsiProtocol;
+use crate::session::PsiSession;
+use crate::wire::{self, read_count, write_count, WireMessage};
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::Scalar;
+use rand::rngs::{OsRng, StdRng};
+use rand::{RngCore, SeedableRng};
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// Version of the recording format.
+pub const RECORDING_VERSION: u8 = 1;
+
+const SENT: u8 = 0;
+const RECEIVED: u8 = 1;
+
+/// Whether a recorded message was sent or received by the local party.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Sent to the remote party.
+    Sent,
+    /// Received from the remote party.
+    Received,
+}
+
+/// One message of a recorded session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedMessage {
+    /// Who sent the message.
+    pub direction: Direction,
+    /// The message itself.
+    pub message: WireMessage,
+}
+
+/// Everything needed to rerun one party of a session.
+#[derive(Clone)]
+pub struct SessionRecording {
+    seed: Zeroizing<[u8; 32]>,
+    config: PsiConfig,
+    hashes: Vec<[u8; 32]>,
+    messages: Vec<RecordedMessage>,
+}
+
+/// Outcome of [`SessionRecording::replay`].
+#[derive(Debug)]
+pub struct Replay {
+    /// The session after the last recorded message.
+    pub session: PsiSession,
+    /// The intersection, if the replayed session computed one.
+    pub result: Option<PsiResult>,
+    /// Errors returned while handling received messages, with the position
+    /// of the message in [`SessionRecording::messages`].
+    pub errors: Vec<(usize, PsiError)>,
+    /// Position of the first sent message the replay did not reproduce.
+    pub diverged_at: Option<usize>,
+}
+
+/// A [`PsiSession`] that records everything needed to replay it.
+///
+/// Offers the methods of [`PsiSession`]; the recording grows with every
+/// message and can be saved at any point, including after an error.
+#[derive(Debug)]
+pub struct RecordingSession {
+    session: PsiSession,
+    recording: SessionRecording,
+}
+
+/// Start the session of a recording from its hashed items.
+fn seeded_session(
+    hashed: &[([u8; 32], RistrettoPoint)],
+    seed: &[u8; 32],
+    config: PsiConfig,
+) -> Result<PsiSession> {
+    let mut rng = StdRng::from_seed(*seed);
+    let secret = Scalar::random(&mut rng);
+    let blinded = blind_points_parallel(hashed, &secret, config.threads());
+    PsiProtocol::from_blinded_with_rng(blinded, secret, config, &mut rng).map(PsiSession::Prepared)
+}
+
+/// The configuration as recorded, without its pre-shared key.
+fn without_psk(config: &PsiConfig) -> PsiConfig {
+    let builder = PsiConfig::builder()
+        .hash(config.hash())
+        .domain(config.domain())
+        .padding(config.padding())
+        .order(config.order())
+        .lenient(config.lenient())
+        .allow_empty(config.allow_empty())
+        .threads(config.threads())
+        .hash_threads(config.hash_threads());
+    let builder = match config.max_local_items() {
+        Some(limit) => builder.max_local_items(limit),
+        None => builder,
+    };
+    let builder = match config.max_remote_items() {
+        Some(limit) => builder.max_remote_items(limit),
+        None => builder,
+    };
+    #[cfg(feature = "parallel")]
+    let builder = builder.compute_threads(config.compute_threads());
+    #[cfg(feature = "vartime")]
+    let builder = builder.vartime(config.vartime());
+    builder
+        .build()
+        .expect("a built configuration stays valid without its key")
+}
+
+impl RecordingSession {
+    /// Start a recorded session from items, with a fresh random seed.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::new_with_config`]
+    pub fn new(items: &[Vec<u8>], config: PsiConfig) -> Result<Self> {
+        config.check_local_set(items.len())?;
+        let mut seed = Zeroizing::new([0u8; 32]);
+        OsRng.fill_bytes(seed.as_mut());
+        let hashed =
+            hash_inputs_sorted(config.hash(), config.domain(), items, config.hash_threads());
+        let recorded_config = without_psk(&config);
+        let session = seeded_session(&hashed, &seed, config)?;
+
+        let mut recording = SessionRecording {
+            seed,
+            config: recorded_config,
+            hashes: hashed.iter().map(|(hash, _)| *hash).collect(),
+            messages: Vec::new(),
+        };
+        recording.push(Direction::Sent, WireMessage::Blinded(session.message()?));
+        Ok(Self { session, recording })
+    }
+
+    /// The recorded session.
+    pub fn session(&self) -> &PsiSession {
+        &self.session
+    }
+
+    /// The recording so far.
+    pub fn recording(&self) -> &SessionRecording {
+        &self.recording
+    }
+
+    /// Return the recording, ending the session.
+    pub fn into_recording(self) -> SessionRecording {
+        self.recording
+    }
+
+    /// Same as [`PsiSession::message`].
+    ///
+    /// # Errors
+    /// Same as [`PsiSession::message`]
+    pub fn message(&self) -> Result<BlindedPointsMessage> {
+        self.session.message()
+    }
+
+    /// Same as [`PsiSession::on_blinded`], recording both messages.
+    ///
+    /// # Errors
+    /// Same as [`PsiSession::on_blinded`]
+    pub fn on_blinded(
+        &mut self,
+        remote_msg: BlindedPointsMessage,
+    ) -> Result<DoubleBlindedPointsMessage> {
+        self.recording.push(
+            Direction::Received,
+            WireMessage::Blinded(remote_msg.clone()),
+        );
+        let double_msg = self.session.on_blinded(remote_msg)?;
+        self.recording.push(
+            Direction::Sent,
+            WireMessage::DoubleBlinded(double_msg.clone()),
+        );
+        Ok(double_msg)
+    }
+
+    /// Same as [`PsiSession::on_double_blinded`], recording the message.
+    ///
+    /// # Errors
+    /// Same as [`PsiSession::on_double_blinded`]
+    pub fn on_double_blinded(
+        &mut self,
+        remote_msg: DoubleBlindedPointsMessage,
+    ) -> Result<PsiResult> {
+        self.recording.push(
+            Direction::Received,
+            WireMessage::DoubleBlinded(remote_msg.clone()),
+        );
+        self.session.on_double_blinded(remote_msg)
+    }
+}
+
+impl SessionRecording {
+    fn push(&mut self, direction: Direction, message: WireMessage) {
+        self.messages.push(RecordedMessage { direction, message });
+    }
+
+    /// The recorded configuration, without its pre-shared key.
+    pub fn config(&self) -> &PsiConfig {
+        &self.config
+    }
+
+    /// Hashes of the local items, sorted.
+    pub fn item_hashes(&self) -> &[[u8; 32]] {
+        &self.hashes
+    }
+
+    /// The recorded messages, in the order they were sent or received.
+    pub fn messages(&self) -> &[RecordedMessage] {
+        &self.messages
+    }
+
+    /// Rerun the recorded session.
+    ///
+    /// Received messages are handled in order as they were in the recorded
+    /// session, errors included; each sent message is compared with the one
+    /// the replay produces. In lenient mode, the random stand-ins for invalid
+    /// remote points are not compared.
+    ///
+    /// # Errors
+    /// Returns the errors of [`PsiProtocol::new_with_config`] if the session
+    /// cannot be started from the recording
+    pub fn replay(&self) -> Result<Replay> {
+        let hashed: Vec<_> = self
+            .hashes
+            .iter()
+            .map(|hash| (*hash, hash_to_point_in(self.config.domain(), hash)))
+            .collect();
+        let mut replay = Replay {
+            session: seeded_session(&hashed, &self.seed, self.config.clone())?,
+            result: None,
+            errors: Vec::new(),
+            diverged_at: None,
+        };
+
+        // What the replayed session would have sent next
+        let mut outgoing = replay.session.message().ok().map(WireMessage::Blinded);
+        let mut last_received: &[CompressedRistretto] = &[];
+        for (index, recorded) in self.messages.iter().enumerate() {
+            match (&recorded.direction, &recorded.message) {
+                (Direction::Sent, message) => {
+                    let reproduced = outgoing
+                        .take()
+                        .is_some_and(|replayed| same_points(message, &replayed, last_received));
+                    if !reproduced && replay.diverged_at.is_none() {
+                        replay.diverged_at = Some(index);
+                    }
+                }
+                (Direction::Received, WireMessage::Blinded(msg)) => {
+                    last_received = &msg.blinded_points;
+                    match replay.session.on_blinded(msg.clone()) {
+                        Ok(double_msg) => outgoing = Some(WireMessage::DoubleBlinded(double_msg)),
+                        Err(error) => replay.errors.push((index, error)),
+                    }
+                }
+                (Direction::Received, WireMessage::DoubleBlinded(msg)) => {
+                    match replay.session.on_double_blinded(msg.clone()) {
+                        Ok(result) => replay.result = Some(result),
+                        Err(error) => replay.errors.push((index, error)),
+                    }
+                }
+                (Direction::Received, _) => {
+                    replay.errors.push((
+                        index,
+                        PsiError::InvalidEncoding(
+                            "Recorded message kind is not replayable".to_string(),
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(replay)
+    }
+
+    /// Serialize the recording.
+    ///
+    /// The bytes contain the seed and are zeroized on drop.
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let frames: Vec<(u8, Vec<u8>)> = self
+            .messages
+            .iter()
+            .map(|recorded| {
+                let direction = match recorded.direction {
+                    Direction::Sent => SENT,
+                    Direction::Received => RECEIVED,
+                };
+                (direction, wire::encode(&recorded.message))
+            })
+            .collect();
+        let config_len = 1 + 9 + 1 + 1 + 9 + 9 + 12 + 1 + 4 + self.config.domain().len();
+        let frames_len: usize = frames.iter().map(|(_, frame)| 5 + frame.len()).sum();
+        let mut out = Zeroizing::new(Vec::with_capacity(
+            1 + 32 + config_len + 4 + self.hashes.len() * 32 + 4 + frames_len,
+        ));
+
+        out.push(RECORDING_VERSION);
+        out.extend_from_slice(self.seed.as_ref());
+        write_config(&mut out, &self.config);
+        write_count(&mut out, self.hashes.len());
+        for hash in &self.hashes {
+            out.extend_from_slice(hash);
+        }
+        write_count(&mut out, frames.len());
+        for (direction, frame) in &frames {
+            out.push(*direction);
+            write_count(&mut out, frame.len());
+            out.extend_from_slice(frame);
+        }
+        out
+    }
+
+    /// Restore a recording serialized with [`to_bytes`](Self::to_bytes).
+    ///
+    /// # Errors
+    /// Returns `PsiError::VersionMismatch` for an unknown format version,
+    /// `PsiError::InvalidEncoding` if the bytes are malformed, or
+    /// `PsiError::InvalidConfig` if the recorded configuration is invalid
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader(bytes);
+        let version = reader.byte()?;
+        if version != RECORDING_VERSION {
+            return Err(PsiError::VersionMismatch {
+                expected: RECORDING_VERSION,
+                actual: version,
+            });
+        }
+        let seed = Zeroizing::new(reader.array()?);
+        let config = reader.config()?;
+        let hash_count = reader.count(32)?;
+        let hashes: Vec<[u8; 32]> = (0..hash_count)
+            .map(|_| reader.array())
+            .collect::<Result<_>>()?;
+        if !hashes.windows(2).all(|pair| pair[0] < pair[1]) {
+            return Err(PsiError::InvalidEncoding(
+                "Recorded item hashes are not sorted".to_string(),
+            ));
+        }
+        let message_count = reader.count(5)?;
+        let messages = (0..message_count)
+            .map(|_| {
+                let direction = match reader.byte()? {
+                    SENT => Direction::Sent,
+                    RECEIVED => Direction::Received,
+                    other => {
+                        return Err(PsiError::InvalidEncoding(format!(
+                            "Invalid message direction {} in recording",
+                            other
+                        )))
+                    }
+                };
+                let len = reader.count(1)?;
+                let message = wire::decode(reader.take(len)?)?;
+                Ok(RecordedMessage { direction, message })
+            })
+            .collect::<Result<_>>()?;
+        if !reader.0.is_empty() {
+            return Err(PsiError::InvalidEncoding(format!(
+                "{} trailing bytes after recording",
+                reader.0.len()
+            )));
+        }
+        Ok(Self {
+            seed,
+            config,
+            hashes,
+            messages,
+        })
+    }
+
+    /// Write the recording to a file.
+    ///
+    /// # Errors
+    /// Returns `PsiError::StoreFailed` if the file cannot be written
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_bytes().as_slice()).map_err(|error| {
+            PsiError::StoreFailed(format!("Recording {}: {}", path.display(), error))
+        })
+    }
+
+    /// Read a recording from a file.
+    ///
+    /// # Errors
+    /// Returns `PsiError::StoreFailed` if the file cannot be read, plus the
+    /// errors of [`from_bytes`](Self::from_bytes)
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = Zeroizing::new(std::fs::read(path).map_err(|error| {
+            PsiError::StoreFailed(format!("Recording {}: {}", path.display(), error))
+        })?);
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl std::fmt::Debug for SessionRecording {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionRecording")
+            .field("config", &self.config)
+            .field("items", &self.hashes.len())
+            .field("messages", &self.messages.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns true if a recorded and a replayed message carry the same points.
+///
+/// `received` is the remote message the double-blinded answers were
+/// computed from; positions holding an invalid point are skipped.
+fn same_points(
+    recorded: &WireMessage,
+    replayed: &WireMessage,
+    received: &[CompressedRistretto],
+) -> bool {
+    match (recorded, replayed) {
+        (WireMessage::Blinded(recorded), WireMessage::Blinded(replayed)) => {
+            recorded.blinded_points == replayed.blinded_points
+        }
+        (WireMessage::DoubleBlinded(recorded), WireMessage::DoubleBlinded(replayed)) => {
+            recorded.double_blinded_points.len() == replayed.double_blinded_points.len()
+                && recorded
+                    .double_blinded_points
+                    .iter()
+                    .zip(&replayed.double_blinded_points)
+                    .zip(received)
+                    .all(|((recorded, replayed), received)| {
+                        recorded == replayed || decompress_point(received).is_err()
+                    })
+        }
+        _ => false,
+    }
+}
+
+fn write_optional(out: &mut Vec<u8>, value: Option<usize>) {
+    out.push(value.is_some() as u8);
+    out.extend_from_slice(&(value.unwrap_or_default() as u64).to_be_bytes());
+}
+
+fn write_config(out: &mut Vec<u8>, config: &PsiConfig) {
+    out.push(match config.hash() {
+        HashAlgorithm::Sha512Trunc256 => 0,
+        HashAlgorithm::Sha256 => 1,
+    });
+    let (padding, size) = match config.padding() {
+        Padding::None => (0, 0),
+        Padding::ToSize(size) => (1, size),
+        Padding::ToMultipleOf(multiple) => (2, multiple),
+    };
+    out.push(padding);
+    out.extend_from_slice(&(size as u64).to_be_bytes());
+    out.push(match config.order() {
+        MessageOrder::Shuffled => 0,
+        MessageOrder::Sorted => 1,
+    });
+    out.push(config.lenient() as u8);
+    write_optional(out, config.max_local_items());
+    write_optional(out, config.max_remote_items());
+    for threads in [
+        config.threads(),
+        config.hash_threads(),
+        config.compute_threads(),
+    ] {
+        write_count(out, threads);
+    }
+    out.push(config.vartime() as u8);
+    write_count(out, config.domain().len());
+    out.extend_from_slice(config.domain());
+}
+
+/// Cursor over a serialized recording.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(PsiError::InvalidEncoding(format!(
+                "Recording truncated: expected {} more bytes, found {}",
+                len,
+                self.0.len()
+            )));
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn flag(&mut self) -> Result<bool> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(PsiError::InvalidEncoding(format!(
+                "Invalid flag {} in recording",
+                other
+            ))),
+        }
+    }
+
+    fn array(&mut self) -> Result<[u8; 32]> {
+        let mut out = [0u8; 32];
+        out.copy_from_slice(self.take(32)?);
+        Ok(out)
+    }
+
+    fn size(&mut self) -> Result<usize> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        usize::try_from(u64::from_be_bytes(bytes)).map_err(|_| {
+            PsiError::InvalidEncoding("Recorded size does not fit this platform".to_string())
+        })
+    }
+
+    fn optional(&mut self) -> Result<Option<usize>> {
+        let present = self.flag()?;
+        let value = self.size()?;
+        Ok(present.then_some(value))
+    }
+
+    fn number(&mut self) -> Result<usize> {
+        let (number, rest) = read_count(self.0)?;
+        self.0 = rest;
+        Ok(number)
+    }
+
+    /// Read a count of `record_len`-byte records, checked against the input.
+    fn count(&mut self, record_len: usize) -> Result<usize> {
+        let (count, rest) = read_count(self.0)?;
+        if rest.len() / record_len < count {
+            return Err(PsiError::InvalidEncoding(format!(
+                "Recording announces {} records, found {} bytes",
+                count,
+                rest.len()
+            )));
+        }
+        self.0 = rest;
+        Ok(count)
+    }
+
+    fn config(&mut self) -> Result<PsiConfig> {
+        let hash = match self.byte()? {
+            0 => HashAlgorithm::Sha512Trunc256,
+            1 => HashAlgorithm::Sha256,
+            other => {
+                return Err(PsiError::InvalidEncoding(format!(
+                    "Unknown hash algorithm {} in recording",
+                    other
+                )))
+            }
+        };
+        let padding = match (self.byte()?, self.size()?) {
+            (0, _) => Padding::None,
+            (1, size) => Padding::ToSize(size),
+            (2, multiple) => Padding::ToMultipleOf(multiple),
+            (other, _) => {
+                return Err(PsiError::InvalidEncoding(format!(
+                    "Unknown padding {} in recording",
+                    other
+                )))
+            }
+        };
+        let order = match self.byte()? {
+            0 => MessageOrder::Shuffled,
+            1 => MessageOrder::Sorted,
+            other => {
+                return Err(PsiError::InvalidEncoding(format!(
+                    "Unknown message order {} in recording",
+                    other
+                )))
+            }
+        };
+        let lenient = self.flag()?;
+        let max_local = self.optional()?;
+        let max_remote = self.optional()?;
+        let threads = self.number()?;
+        let hash_threads = self.number()?;
+        let _compute_threads = self.number()?;
+        let _vartime = self.flag()?;
+        let domain_len = self.count(1)?;
+        let domain = self.take(domain_len)?;
+
+        let builder = PsiConfig::builder()
+            .hash(hash)
+            .domain(domain)
+            .padding(padding)
+            .order(order)
+            .lenient(lenient)
+            .threads(threads)
+            .hash_threads(hash_threads);
+        let builder = match max_local {
+            Some(limit) => builder.max_local_items(limit),
+            None => builder,
+        };
+        let builder = match max_remote {
+            Some(limit) => builder.max_remote_items(limit),
+            None => builder,
+        };
+        // Both only change how fast points are computed, not their values
+        #[cfg(feature = "parallel")]
+        let builder = builder.compute_threads(_compute_threads);
+        #[cfg(feature = "vartime")]
+        let builder = builder.vartime(_vartime);
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(range: std::ops::Range<u8>) -> Vec<Vec<u8>> {
+        range.map(|i| vec![i]).collect()
+    }
+
+    /// Run a recorded session for Alice against a plain one for Bob.
+    fn record(config: PsiConfig, bob_msg: Option<BlindedPointsMessage>) -> SessionRecording {
+        let mut alice = RecordingSession::new(&items(0..10), config.clone()).unwrap();
+        let mut bob = PsiSession::new_with_config(&items(5..15), config).unwrap();
+        let bob_msg = bob_msg.unwrap_or_else(|| bob.message().unwrap());
+        let alice_double = bob.on_blinded(alice.message().unwrap()).unwrap();
+        let bob_double = alice.on_blinded(bob_msg).unwrap();
+        let _ = bob.on_double_blinded(bob_double);
+        alice.on_double_blinded(alice_double).unwrap();
+        alice.into_recording()
+    }
+
+    #[test]
+    fn test_replay_reproduces_session() {
+        let config = PsiConfig::builder()
+            .padding(Padding::ToMultipleOf(16))
+            .domain("record-test")
+            .max_remote_items(100)
+            .build()
+            .unwrap();
+        let recording = record(config, None);
+        assert_eq!(recording.messages().len(), 4);
+
+        let restored = SessionRecording::from_bytes(&recording.to_bytes()).unwrap();
+        assert_eq!(restored.config(), recording.config());
+        assert_eq!(restored.messages(), recording.messages());
+
+        let replay = restored.replay().unwrap();
+        assert_eq!(replay.diverged_at, None);
+        assert!(replay.errors.is_empty());
+        assert_eq!(replay.result.unwrap().intersection_hashes.len(), 5);
+        assert!(replay.session.is_complete());
+    }
+
+    #[test]
+    fn test_replay_reproduces_errors_and_lenient_points() {
+        let mut invalid = [0xffu8; 32];
+        invalid[0] = 0xfe;
+        let mut bob_msg = PsiSession::new(&items(5..15)).unwrap().message().unwrap();
+        bob_msg.blinded_points[3] = CompressedRistretto(invalid);
+
+        let lenient = PsiConfig::builder().lenient(true).build().unwrap();
+        let replay = record(lenient, Some(bob_msg.clone())).replay().unwrap();
+        assert_eq!(replay.diverged_at, None);
+
+        let mut alice = RecordingSession::new(&items(0..10), PsiConfig::default()).unwrap();
+        assert!(alice.on_blinded(bob_msg).is_err());
+        let replay = alice.recording().replay().unwrap();
+        assert_eq!(replay.errors.len(), 1);
+        assert_eq!(replay.errors[0].0, 1);
+        assert_eq!(replay.session.state_name(), "prepared");
+    }
+
+    #[test]
+    fn test_tampered_recording_diverges_or_fails() {
+        let recording = record(PsiConfig::default(), None);
+        let mut tampered = recording.clone();
+        tampered.seed[0] ^= 1;
+        assert_eq!(tampered.replay().unwrap().diverged_at, Some(0));
+
+        let bytes = recording.to_bytes();
+        assert!(matches!(
+            SessionRecording::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(PsiError::InvalidEncoding(_))
+        ));
+        let mut wrong_version = bytes.to_vec();
+        wrong_version[0] = 0;
+        assert!(matches!(
+            SessionRecording::from_bytes(&wrong_version),
+            Err(PsiError::VersionMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,216 @@
+//! Redis as a [`SessionStore`].
+//!
+//! [`RedisStore`] keeps each session state under `<prefix><session id>` with
+//! a time to live, so sessions abandoned by their peer expire on the server
+//! as they do in a [`SessionManager`](crate::SessionManager). Every save
+//! resets the time to live.
+//!
+//! The store is generic over [`redis::ConnectionLike`], so it works with a
+//! plain connection, a cluster connection or a pooled one.
+//!
+//! Requires the `redis` feature.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::redis::RedisStore;
+//! use psi_protocol::SessionStore;
+//!
+//! let mut store = RedisStore::open("redis://sessions.internal/", Duration::from_secs(30))?;
+//! store.save(&session_id, &session)?;
+//! // ...on another replica
+//! let session = store.load(&session_id, config)?;
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::error::{PsiError, Result};
+use crate::store::SessionStore;
+use ::redis::{Connection, ConnectionLike};
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+/// Default prefix of the session keys.
+pub const DEFAULT_KEY_PREFIX: &str = "psi:session:";
+
+fn store_error(error: ::redis::RedisError) -> PsiError {
+    PsiError::StoreFailed(format!("Redis: {}", error))
+}
+
+/// [`SessionStore`] keeping session states in Redis.
+pub struct RedisStore<C = Connection> {
+    connection: C,
+    prefix: String,
+    ttl: Duration,
+}
+
+impl RedisStore {
+    /// Connect to the Redis server at `url`.
+    ///
+    /// # Errors
+    /// Returns `PsiError::StoreFailed` if the url is invalid or the server
+    /// cannot be reached
+    pub fn open(url: &str, ttl: Duration) -> Result<Self> {
+        let connection = ::redis::Client::open(url)
+            .and_then(|client| client.get_connection())
+            .map_err(store_error)?;
+        Ok(Self::new(connection, ttl))
+    }
+}
+
+impl<C: ConnectionLike> RedisStore<C> {
+    /// Store sessions over `connection`, expiring them `ttl` after their
+    /// last save (at least one millisecond).
+    pub fn new(connection: C, ttl: Duration) -> Self {
+        Self {
+            connection,
+            prefix: DEFAULT_KEY_PREFIX.to_string(),
+            ttl,
+        }
+    }
+
+    /// Set the key prefix, e.g. to separate deployments sharing a server.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Time to live of stored sessions.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Return the connection.
+    pub fn into_inner(self) -> C {
+        self.connection
+    }
+
+    fn key(&self, id: &str) -> String {
+        format!("{}{}", self.prefix, id)
+    }
+}
+
+impl<C> std::fmt::Debug for RedisStore<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisStore")
+            .field("prefix", &self.prefix)
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<C: ConnectionLike> SessionStore for RedisStore<C> {
+    fn put(&mut self, id: &str, state: &[u8]) -> Result<()> {
+        let ttl_ms = self.ttl.as_millis().clamp(1, u64::MAX as u128) as u64;
+        ::redis::cmd("SET")
+            .arg(self.key(id))
+            .arg(state)
+            .arg("PX")
+            .arg(ttl_ms)
+            .query::<()>(&mut self.connection)
+            .map_err(store_error)
+    }
+
+    fn get(&mut self, id: &str) -> Result<Option<Zeroizing<Vec<u8>>>> {
+        ::redis::cmd("GET")
+            .arg(self.key(id))
+            .query::<Option<Vec<u8>>>(&mut self.connection)
+            .map(|state| state.map(Zeroizing::new))
+            .map_err(store_error)
+    }
+
+    fn delete(&mut self, id: &str) -> Result<()> {
+        ::redis::cmd("DEL")
+            .arg(self.key(id))
+            .query::<()>(&mut self.connection)
+            .map_err(store_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PsiConfig;
+    use crate::session::PsiSession;
+    use ::redis::{Arg, Cmd, RedisResult, Value};
+    use std::collections::HashMap;
+
+    /// In-memory stand-in for a server, answering SET/GET/DEL.
+    #[derive(Default)]
+    struct FakeRedis {
+        keys: HashMap<Vec<u8>, Vec<u8>>,
+        ttls: Vec<Vec<u8>>,
+    }
+
+    impl ConnectionLike for FakeRedis {
+        fn req_packed_command(&mut self, _cmd: &[u8]) -> RedisResult<Value> {
+            unreachable!("commands are sent through req_command")
+        }
+
+        fn req_packed_commands(
+            &mut self,
+            _cmd: &[u8],
+            _offset: usize,
+            _count: usize,
+        ) -> RedisResult<Vec<Value>> {
+            unreachable!("pipelines are not used")
+        }
+
+        fn req_command(&mut self, cmd: &Cmd) -> RedisResult<Value> {
+            let args: Vec<&[u8]> = cmd
+                .args_iter()
+                .map(|arg| match arg {
+                    Arg::Simple(bytes) => bytes,
+                    _ => unreachable!("no cursor arguments"),
+                })
+                .collect();
+            Ok(match args[0] {
+                b"SET" => {
+                    self.ttls.push(args[4].to_vec());
+                    self.keys.insert(args[1].to_vec(), args[2].to_vec());
+                    Value::Okay
+                }
+                b"GET" => match self.keys.get(args[1]) {
+                    Some(value) => Value::BulkString(value.clone()),
+                    None => Value::Nil,
+                },
+                b"DEL" => Value::Int(self.keys.remove(args[1]).is_some() as i64),
+                other => panic!("unexpected command {:?}", other),
+            })
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+
+        fn check_connection(&mut self) -> bool {
+            true
+        }
+
+        fn is_open(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_session_round_trip() {
+        let mut store =
+            RedisStore::new(FakeRedis::default(), Duration::from_secs(30)).prefix("test:");
+        let session = PsiSession::new(&[b"apple".to_vec()]).unwrap();
+        store.save("s1", &session).unwrap();
+
+        let restored = store.load("s1", PsiConfig::default()).unwrap().unwrap();
+        assert_eq!(restored.message().unwrap(), session.message().unwrap());
+        store.delete("s1").unwrap();
+        assert!(store.load("s1", PsiConfig::default()).unwrap().is_none());
+
+        let redis = store.into_inner();
+        assert_eq!(redis.ttls, vec![b"30000".to_vec()]);
+        assert!(redis.keys.is_empty());
+    }
+
+    #[test]
+    fn test_keys_use_prefix() {
+        let store = RedisStore::new(FakeRedis::default(), Duration::ZERO);
+        assert_eq!(store.key("s1"), "psi:session:s1");
+        assert_eq!(store.prefix("a/").key("s1"), "a/s1");
+    }
+}
@@ -0,0 +1,187 @@
+//! Wrapper keeping the blinding scalar out of logs and dumps.
+//!
+//! Protocol states derive `Debug` so they can be logged while debugging a
+//! session, which used to print the raw scalar along with everything else.
+//! [`SecretScalar`] prints as `SecretScalar([REDACTED])`, implements no
+//! serialization traits and zeroizes itself on drop; the scalar is only
+//! reachable through [`expose`](SecretScalar::expose), inside the crate.
+//!
+//! With the `guarded-memory` feature, each scalar gets its own allocation
+//! from `memsec`: locked with `mlock` so it is never swapped out (and
+//! excluded from core dumps on Linux), between guard pages that fault on
+//! overflows into or out of it. Locking is limited by `RLIMIT_MEMLOCK` and
+//! may not be permitted at all in containers; the scalar then stays in
+//! guarded but unlocked memory, or on the regular heap if guarded pages
+//! cannot be mapped, and the protocol keeps working. Check
+//! [`guarded_memory_locked`] at startup where locking is a requirement.
+
+use curve25519_dalek::Scalar;
+use zeroize::Zeroize;
+
+/// A blinding scalar that never appears in `Debug` output.
+pub(crate) struct SecretScalar(Slot);
+
+impl SecretScalar {
+    pub(crate) fn new(scalar: Scalar) -> Self {
+        Self(Slot::new(scalar))
+    }
+
+    /// The raw scalar, for the arithmetic that needs it.
+    pub(crate) fn expose(&self) -> &Scalar {
+        self.0.get()
+    }
+}
+
+impl Clone for SecretScalar {
+    fn clone(&self) -> Self {
+        Self::new(*self.expose())
+    }
+}
+
+impl std::fmt::Debug for SecretScalar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretScalar([REDACTED])")
+    }
+}
+
+impl zeroize::ZeroizeOnDrop for SecretScalar {}
+
+/// Inline storage, zeroized on drop.
+#[cfg(not(feature = "guarded-memory"))]
+struct Slot(Scalar);
+
+#[cfg(not(feature = "guarded-memory"))]
+impl Slot {
+    fn new(scalar: Scalar) -> Self {
+        Self(scalar)
+    }
+
+    fn get(&self) -> &Scalar {
+        &self.0
+    }
+}
+
+#[cfg(not(feature = "guarded-memory"))]
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(feature = "guarded-memory")]
+pub use guarded::guarded_memory_locked;
+#[cfg(feature = "guarded-memory")]
+use guarded::Slot;
+
+#[cfg(feature = "guarded-memory")]
+mod guarded {
+    use super::*;
+    use std::ptr::NonNull;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Set once any scalar could not be locked in memory.
+    static LOCK_FAILED: AtomicBool = AtomicBool::new(false);
+
+    /// Returns true if every secret scalar allocated so far is locked in
+    /// memory.
+    ///
+    /// Allocate one (e.g. run [`self_test`](crate::self_test)) before
+    /// checking, since nothing is locked until a scalar exists.
+    ///
+    /// Requires the `guarded-memory` feature.
+    pub fn guarded_memory_locked() -> bool {
+        !LOCK_FAILED.load(Ordering::Relaxed)
+    }
+
+    /// Guarded allocation, or the heap if none could be mapped.
+    pub(super) enum Slot {
+        Guarded(NonNull<Scalar>),
+        Heap(Box<Scalar>),
+    }
+
+    // SAFETY: a `Slot::Guarded` pointer is owned exclusively by its slot,
+    // like a `Box`, and only read through `&self`.
+    unsafe impl Send for Slot {}
+    unsafe impl Sync for Slot {}
+
+    impl Slot {
+        pub(super) fn new(scalar: Scalar) -> Self {
+            // SAFETY: `malloc` returns memory sized and aligned for a
+            // `Scalar` (page-aligned, plain bytes), which is initialized
+            // before any read.
+            match unsafe { memsec::malloc::<Scalar>() } {
+                Some(ptr) => unsafe {
+                    ptr.as_ptr().write(scalar);
+                    // memsec already tried to lock the pages; locking again
+                    // is harmless and tells us whether it worked
+                    if !memsec::mlock(ptr.as_ptr().cast(), std::mem::size_of::<Scalar>()) {
+                        LOCK_FAILED.store(true, Ordering::Relaxed);
+                    }
+                    Slot::Guarded(ptr)
+                },
+                None => {
+                    LOCK_FAILED.store(true, Ordering::Relaxed);
+                    Slot::Heap(Box::new(scalar))
+                }
+            }
+        }
+
+        pub(super) fn get(&self) -> &Scalar {
+            match self {
+                // SAFETY: the pointer is valid and initialized until drop.
+                Slot::Guarded(ptr) => unsafe { ptr.as_ref() },
+                Slot::Heap(scalar) => scalar,
+            }
+        }
+    }
+
+    impl Drop for Slot {
+        fn drop(&mut self) {
+            match self {
+                // SAFETY: the pointer came from `memsec::malloc` and is
+                // freed exactly once; `free` also zeroes and unlocks it.
+                Slot::Guarded(ptr) => unsafe {
+                    ptr.as_mut().zeroize();
+                    memsec::free(*ptr);
+                },
+                Slot::Heap(scalar) => scalar.zeroize(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::PsiProtocol;
+
+    #[test]
+    fn test_debug_output_redacts_secret() {
+        let scalar = Scalar::from(0x1234_5678u64);
+        let secret = SecretScalar::new(scalar);
+        assert_eq!(format!("{:?}", secret), "SecretScalar([REDACTED])");
+        assert_eq!(secret.expose(), &scalar);
+
+        let alice = PsiProtocol::new(&[b"apple".to_vec()]).unwrap();
+        let secret_debug = format!("{:?}", alice.state().secret());
+        let dump = format!("{:?}", alice);
+        assert!(dump.contains("SecretScalar([REDACTED])"));
+        assert!(!dump.contains(&secret_debug));
+        let bob = PsiProtocol::new(&[b"apple".to_vec()]).unwrap();
+        let (alice, _) = alice.compute(bob.message()).unwrap();
+        assert!(format!("{:?}", alice).contains("SecretScalar([REDACTED])"));
+    }
+
+    #[cfg(feature = "guarded-memory")]
+    #[test]
+    fn test_guarded_secrets_survive_clone_and_threads() {
+        let scalar = Scalar::from(42u64);
+        let secret = SecretScalar::new(scalar);
+        let copy = secret.clone();
+        drop(secret);
+        let copy = std::thread::spawn(move || copy).join().unwrap();
+        assert_eq!(copy.expose(), &scalar);
+        // Locking depends on the host's limits; either answer is valid
+        let _ = guarded_memory_locked();
+    }
+}
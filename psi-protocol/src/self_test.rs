@@ -0,0 +1,137 @@
+//! Startup known-answer test.
+//!
+//! The protocol only works if both parties compute bit-identical points.
+//! A miscompiled SIMD backend, a wrong `curve25519_dalek_backend` cfg in a
+//! cross-compiled build or a broken hash implementation would not crash;
+//! it would silently produce empty intersections. [`self_test`] runs a tiny
+//! exchange with fixed secrets and compares it against pinned values, so a
+//! service can refuse to start instead.
+
+use crate::backend::active_backend;
+use crate::config::PsiConfig;
+use crate::crypto::{blind_point, derive_scalar, hash_item, hash_to_point};
+use crate::error::{PsiError, Result};
+use crate::item_id::ItemId;
+use crate::protocol::PsiProtocol;
+
+/// Key material of both test parties; not secret.
+const ALICE_IKM: [u8; 32] = [0xa1; 32];
+const BOB_IKM: [u8; 32] = [0xb0; 32];
+const CONTEXT: &[u8] = b"psi-protocol self-test";
+
+/// `hash_item(b"banana")`.
+const BANANA_HASH: [u8; 32] = [
+    0xf8, 0xe3, 0x18, 0x3d, 0x38, 0xe6, 0xc5, 0x18, 0x89, 0x58, 0x2c, 0xb2, 0x60, 0xab, 0x82, 0x52,
+    0x52, 0xf3, 0x95, 0xb4, 0xac, 0x8f, 0xb0, 0xe6, 0xb1, 0x3e, 0x9a, 0x71, 0xf7, 0xc1, 0x0a, 0x80,
+];
+/// `hash_to_point(BANANA_HASH)` blinded by Alice's scalar.
+const BANANA_ALICE: [u8; 32] = [
+    0xe6, 0x36, 0x6a, 0x33, 0x55, 0x46, 0x14, 0xe3, 0x8e, 0x47, 0x2e, 0x8d, 0x6d, 0x57, 0xe8, 0x96,
+    0x76, 0x95, 0x12, 0x6e, 0x83, 0x11, 0xfb, 0xa9, 0xd4, 0x2c, 0x84, 0x9a, 0x1d, 0xdc, 0x52, 0x3c,
+];
+/// `hash_to_point(BANANA_HASH)` blinded by both scalars.
+const BANANA_SHARED: [u8; 32] = [
+    0x56, 0x81, 0x2f, 0x75, 0x8d, 0x44, 0xbf, 0x82, 0xf9, 0x98, 0xe9, 0xf6, 0xaa, 0x99, 0x85, 0x7a,
+    0x70, 0xdd, 0x6c, 0x74, 0x4b, 0x75, 0xa2, 0x7a, 0xe6, 0xef, 0xd2, 0x00, 0xcf, 0x09, 0x14, 0x03,
+];
+
+fn check(what: &str, ok: bool) -> Result<()> {
+    if ok {
+        return Ok(());
+    }
+    Err(PsiError::CryptoError(format!(
+        "Self-test failed: {} (backend {:?})",
+        what,
+        active_backend()
+    )))
+}
+
+/// Run the full exchange between the test parties under `config`.
+fn exchange(config: PsiConfig) -> Result<()> {
+    let alice_items = vec![b"apple".to_vec(), b"banana".to_vec(), b"cherry".to_vec()];
+    let bob_items = vec![b"banana".to_vec(), b"cherry".to_vec(), b"date".to_vec()];
+    let alice =
+        PsiProtocol::with_derived_secret(&alice_items, &ALICE_IKM, CONTEXT, config.clone())?;
+    let bob = PsiProtocol::with_derived_secret(&bob_items, &BOB_IKM, CONTEXT, config)?;
+
+    let alice_msg = alice.message();
+    let (alice, alice_double) = alice.compute(bob.message())?;
+    let (bob, bob_double) = bob.compute(alice_msg)?;
+    let (_, alice_result) = alice.finalize(bob_double)?;
+    let (_, bob_result) = bob.finalize(alice_double)?;
+
+    let mut expected = vec![ItemId::of(b"banana"), ItemId::of(b"cherry")];
+    expected.sort();
+    for result in [&alice_result, &bob_result] {
+        let mut found = result.intersection_hashes.clone();
+        found.sort();
+        check("wrong intersection", found == expected)?;
+        let banana = result.double_blinded_map.get(&ItemId::new(BANANA_HASH));
+        check(
+            "double-blinded point differs from the known answer",
+            banana.map(|point| point.to_bytes()) == Some(BANANA_SHARED),
+        )?;
+    }
+    Ok(())
+}
+
+/// Check the hash, curve and protocol code against known answers.
+///
+/// Runs in a few milliseconds: one hash and blinding against pinned values,
+/// then a three-item exchange with fixed secrets, once with the default
+/// configuration and once with worker threads (plus the `vartime` and
+/// `parallel` paths when those features are enabled).
+///
+/// # Errors
+/// Returns `PsiError::CryptoError` naming the failed check and the active
+/// [`CurveBackend`](crate::CurveBackend)
+///
+/// # Example
+/// ```ignore
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     psi_protocol::self_test()?;
+///     // start serving...
+///     Ok(())
+/// }
+/// ```
+pub fn self_test() -> Result<()> {
+    check("item hash", hash_item(b"banana") == BANANA_HASH)?;
+    let alice = derive_scalar(&ALICE_IKM, CONTEXT)?;
+    check(
+        "blinded point",
+        blind_point(&hash_to_point(&BANANA_HASH), &alice).to_bytes() == BANANA_ALICE,
+    )?;
+
+    exchange(PsiConfig::default())?;
+    let builder = PsiConfig::builder().threads(2).hash_threads(2);
+    #[cfg(feature = "parallel")]
+    let builder = builder.compute_threads(2);
+    #[cfg(feature = "vartime")]
+    let builder = builder.vartime(true);
+    exchange(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_test_passes() {
+        self_test().unwrap();
+    }
+
+    #[test]
+    fn test_pinned_values_match_protocol_math() {
+        let alice = derive_scalar(&ALICE_IKM, CONTEXT).unwrap();
+        let bob = derive_scalar(&BOB_IKM, CONTEXT).unwrap();
+        let point = hash_to_point(&BANANA_HASH);
+        assert_eq!(
+            blind_point(&(point * bob), &alice).to_bytes(),
+            BANANA_SHARED
+        );
+        assert_eq!(
+            blind_point(&(point * alice), &bob).to_bytes(),
+            BANANA_SHARED
+        );
+    }
+}
@@ -0,0 +1,479 @@
+//! Dynamic session API on top of the type-state protocol.
+//!
+//! [`PsiProtocol`] encodes the protocol state in its type and consumes
+//! `self` on every transition, which is the safest API but awkward when
+//! sessions live in a `HashMap`, behind a trait object or across an FFI
+//! boundary. [`PsiSession`] wraps the same states in an enum and drives
+//! them through `&mut self`; calling a method in the wrong state returns
+//! `PsiError::UnexpectedState` instead of failing to compile.
+//!
+//! Either peer may give up on a session with [`PsiSession::abort`]; the
+//! other side hands the [`AbortMessage`] it receives to
+//! [`PsiSession::on_abort`]. Both sessions then drop their secret and answer
+//! every further call with `PsiError::Aborted`.
+//!
+//! Sessions tolerate at-least-once transports: a remote message delivered
+//! again after it was handled is recognized instead of failing the session
+//! or being processed twice. A repeated blinded message is answered with
+//! the same double-blinded message, since the peer may have lost it; any
+//! other repeat returns `PsiError::DuplicateMessage`, which callers can
+//! ignore.
+//!
+//! For the simplest integrations, [`outgoing_message`](PsiSession::outgoing_message)
+//! and [`handle_message`](PsiSession::handle_message) take care of the
+//! [`wire`](crate::wire) encoding as well: send what the first returns,
+//! feed what the peer sends to the second, until it yields the result.
+
+use crate::config::PsiConfig;
+use crate::error::{AbortReason, Phase, PsiError, Result};
+use crate::messages::{AbortMessage, BlindedPointsMessage, DoubleBlindedPointsMessage, PsiResult};
+use crate::protocol::PsiProtocol;
+use crate::state::{DoubleBlindedState, FinalState, PreparedState};
+use crate::wire::{self, WireMessage};
+
+/// A protocol run whose state is tracked at runtime.
+///
+/// # Example
+/// ```ignore
+/// use psi_protocol::PsiSession;
+///
+/// let mut alice = PsiSession::new(&alice_items)?;
+/// let alice_msg = alice.message()?;
+/// // exchange messages...
+/// let alice_double_msg = alice.on_blinded(bob_msg)?;
+/// // exchange double-blinded messages...
+/// let result = alice.on_double_blinded(bob_double_msg)?;
+/// assert!(alice.is_complete());
+/// # Ok::<(), psi_protocol::PsiError>(())
+/// ```
+///
+/// The same run over a byte transport:
+/// ```ignore
+/// let mut session = PsiSession::new(&items)?;
+/// send(&session.outgoing_message()?)?;
+/// let result = loop {
+///     if let Some(result) = session.handle_message(&recv()?)? {
+///         break result;
+///     }
+///     send(&session.outgoing_message()?)?;
+/// };
+/// # Ok::<(), psi_protocol::PsiError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub enum PsiSession {
+    /// Local items are blinded; waiting for the remote's blinded points.
+    Prepared(PsiProtocol<PreparedState>),
+    /// Remote points are double-blinded; waiting for the remote's answer.
+    DoubleBlinded(PsiProtocol<DoubleBlindedState>),
+    /// The intersection has been computed.
+    Final(PsiProtocol<FinalState>),
+    /// One of the peers aborted the session.
+    Aborted(AbortReason),
+    /// A transition panicked midway; the session cannot continue.
+    Poisoned,
+}
+
+// Compile-time guarantee that sessions can be shared across threads.
+const _: () = {
+    const fn assert_send_sync_clone<T: Send + Sync + Clone>() {}
+    assert_send_sync_clone::<PsiSession>();
+};
+
+impl PsiSession {
+    /// Start a session from items with the default configuration.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::new`]
+    pub fn new(items: &[Vec<u8>]) -> Result<Self> {
+        PsiProtocol::new(items).map(Self::Prepared)
+    }
+
+    /// Start a session from items with a custom configuration.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::new_with_config`]
+    pub fn new_with_config(items: &[Vec<u8>], config: PsiConfig) -> Result<Self> {
+        PsiProtocol::new_with_config(items, config).map(Self::Prepared)
+    }
+
+    /// Name of the current state, for logs and error messages.
+    pub fn state_name(&self) -> &'static str {
+        match self {
+            PsiSession::Prepared(_) => "prepared",
+            PsiSession::DoubleBlinded(_) => "double-blinded",
+            PsiSession::Final(_) => "final",
+            PsiSession::Aborted(_) => "aborted",
+            PsiSession::Poisoned => "poisoned",
+        }
+    }
+
+    /// Returns true once the intersection has been computed.
+    pub fn is_complete(&self) -> bool {
+        matches!(self, PsiSession::Final(_))
+    }
+
+    /// Get the blinded points message to send to the remote party.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` unless the session is prepared,
+    /// or `PsiError::Aborted` once it was aborted
+    pub fn message(&self) -> Result<BlindedPointsMessage> {
+        match self {
+            PsiSession::Prepared(protocol) => Ok(protocol.message()),
+            _ => Err(self.unexpected("message")),
+        }
+    }
+
+    /// Get our double-blinded message again, e.g. when the peer lost it.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` unless the session is
+    /// double-blinded, or `PsiError::Aborted` once it was aborted
+    pub fn double_blinded_message(&self) -> Result<DoubleBlindedPointsMessage> {
+        match self {
+            PsiSession::DoubleBlinded(protocol) => Ok(protocol.message()),
+            _ => Err(self.unexpected("double_blinded_message")),
+        }
+    }
+
+    /// Handle the remote's blinded points and return our double-blinded answer.
+    ///
+    /// On error the session stays prepared, so a resent message can be
+    /// handled with another call. The message this session already handled
+    /// is answered again without recomputing it.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` unless the session is prepared,
+    /// `PsiError::DuplicateMessage` for a repeat once the session is final,
+    /// plus the errors of [`PsiProtocol::compute`]
+    pub fn on_blinded(
+        &mut self,
+        remote_msg: BlindedPointsMessage,
+    ) -> Result<DoubleBlindedPointsMessage> {
+        match self {
+            PsiSession::Prepared(_) => {}
+            PsiSession::DoubleBlinded(protocol) if protocol.handled_blinded(&remote_msg) => {
+                return Ok(protocol.message())
+            }
+            PsiSession::Final(protocol) if protocol.handled_blinded(&remote_msg) => {
+                return Err(PsiError::DuplicateMessage {
+                    phase: Phase::Compute,
+                })
+            }
+            _ => return Err(self.unexpected("on_blinded")),
+        }
+        let PsiSession::Prepared(protocol) = std::mem::replace(self, PsiSession::Poisoned) else {
+            unreachable!("state checked above");
+        };
+        match protocol.try_compute(remote_msg) {
+            Ok((next, double_msg)) => {
+                *self = PsiSession::DoubleBlinded(next);
+                Ok(double_msg)
+            }
+            Err(rejected) => {
+                let (protocol, error) = rejected.into_parts();
+                *self = PsiSession::Prepared(protocol);
+                Err(error)
+            }
+        }
+    }
+
+    /// Handle the remote's double-blinded points and compute the intersection.
+    ///
+    /// On error the session stays double-blinded, so a resent message can be
+    /// handled with another call.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` unless the session is
+    /// double-blinded, `PsiError::DuplicateMessage` for the message that
+    /// finalized it, plus the errors of [`PsiProtocol::finalize`]
+    pub fn on_double_blinded(
+        &mut self,
+        remote_msg: DoubleBlindedPointsMessage,
+    ) -> Result<PsiResult> {
+        match self {
+            PsiSession::DoubleBlinded(_) => {}
+            PsiSession::Final(protocol) if protocol.handled_double_blinded(&remote_msg) => {
+                return Err(PsiError::DuplicateMessage {
+                    phase: Phase::Finalize,
+                })
+            }
+            _ => return Err(self.unexpected("on_double_blinded")),
+        }
+        let PsiSession::DoubleBlinded(protocol) = std::mem::replace(self, PsiSession::Poisoned)
+        else {
+            unreachable!("state checked above");
+        };
+        match protocol.try_finalize(remote_msg) {
+            Ok((next, result)) => {
+                *self = PsiSession::Final(next);
+                Ok(result)
+            }
+            Err(rejected) => {
+                let (protocol, error) = rejected.into_parts();
+                *self = PsiSession::DoubleBlinded(protocol);
+                Err(error)
+            }
+        }
+    }
+
+    /// Encoded frame to send to the peer in the current state.
+    ///
+    /// A prepared session returns its blinded points, a double-blinded one
+    /// its double-blinded answer; calling it again returns the same frame,
+    /// e.g. to resend it.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` once the session is final, or
+    /// `PsiError::Aborted` once it was aborted
+    pub fn outgoing_message(&self) -> Result<Vec<u8>> {
+        match self {
+            PsiSession::Prepared(protocol) => Ok(protocol.message().to_bytes()),
+            PsiSession::DoubleBlinded(protocol) => Ok(protocol.message().to_bytes()),
+            _ => Err(self.unexpected("outgoing_message")),
+        }
+    }
+
+    /// Handle an encoded frame from the peer.
+    ///
+    /// Returns the intersection once the peer's double-blinded points
+    /// arrive, `None` before; after `None`, send the new
+    /// [`outgoing_message`](Self::outgoing_message). A peer's abort or
+    /// error report ends the call with the matching error.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` if the frame is malformed or not
+    /// part of a two-round run, `PsiError::Aborted` or `PsiError::Rejected`
+    /// for the peer's abort or error report, plus the errors of
+    /// [`on_blinded`](Self::on_blinded) and
+    /// [`on_double_blinded`](Self::on_double_blinded)
+    pub fn handle_message(&mut self, bytes: &[u8]) -> Result<Option<PsiResult>> {
+        match wire::decode(bytes)? {
+            WireMessage::Blinded(msg) => self.on_blinded(msg).map(|_| None),
+            WireMessage::DoubleBlinded(msg) => self.on_double_blinded(msg).map(Some),
+            WireMessage::Abort(msg) => Err(self.on_abort(msg)),
+            WireMessage::ErrorReport(report) => Err(report.into_error()),
+            other => Err(PsiError::InvalidEncoding(format!(
+                "Expected a two-round message, found {:?}",
+                other.kind()
+            ))),
+        }
+    }
+
+    /// Give up on the session and return the message telling the peer.
+    ///
+    /// The local state, secret included, is dropped; every further call
+    /// returns `PsiError::Aborted` with `reason`.
+    pub fn abort(&mut self, reason: AbortReason) -> AbortMessage {
+        *self = PsiSession::Aborted(reason);
+        AbortMessage::new(reason)
+    }
+
+    /// Handle the peer's abort, returning the error to report.
+    ///
+    /// The local state is dropped as with [`abort`](Self::abort).
+    pub fn on_abort(&mut self, msg: AbortMessage) -> PsiError {
+        *self = PsiSession::Aborted(msg.reason);
+        msg.into_error()
+    }
+
+    /// Error for an operation that is not valid in the current state.
+    fn unexpected(&self, operation: &'static str) -> PsiError {
+        match self {
+            PsiSession::Aborted(reason) => PsiError::Aborted(*reason),
+            _ => PsiError::UnexpectedState {
+                operation,
+                state: self.state_name(),
+            },
+        }
+    }
+}
+
+impl From<PsiProtocol<PreparedState>> for PsiSession {
+    fn from(protocol: PsiProtocol<PreparedState>) -> Self {
+        PsiSession::Prepared(protocol)
+    }
+}
+
+impl From<PsiProtocol<DoubleBlindedState>> for PsiSession {
+    fn from(protocol: PsiProtocol<DoubleBlindedState>) -> Self {
+        PsiSession::DoubleBlinded(protocol)
+    }
+}
+
+impl From<PsiProtocol<FinalState>> for PsiSession {
+    fn from(protocol: PsiProtocol<FinalState>) -> Self {
+        PsiSession::Final(protocol)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::CardinalityMessage;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_session_full_run() {
+        let mut alice = PsiSession::new(&[b"apple".to_vec(), b"banana".to_vec()]).unwrap();
+        let mut bob = PsiSession::new(&[b"banana".to_vec(), b"cherry".to_vec()]).unwrap();
+
+        let alice_msg = alice.message().unwrap();
+        let bob_msg = bob.message().unwrap();
+        let alice_double = alice.on_blinded(bob_msg).unwrap();
+        let bob_double = bob.on_blinded(alice_msg).unwrap();
+        assert_eq!(alice.state_name(), "double-blinded");
+        assert_eq!(alice.double_blinded_message().unwrap(), alice_double);
+        assert!(bob.message().is_err());
+
+        let alice_result = alice.on_double_blinded(bob_double).unwrap();
+        let bob_result = bob.on_double_blinded(alice_double).unwrap();
+        assert_eq!(alice_result.len(), 1);
+        assert_eq!(
+            alice_result.intersection_hashes,
+            bob_result.intersection_hashes
+        );
+        assert!(alice.is_complete());
+    }
+
+    #[test]
+    fn test_session_over_bytes() {
+        let mut alice = PsiSession::new(&[b"apple".to_vec(), b"banana".to_vec()]).unwrap();
+        let mut bob = PsiSession::new(&[b"banana".to_vec(), b"cherry".to_vec()]).unwrap();
+
+        let (alice_frame, bob_frame) = (
+            alice.outgoing_message().unwrap(),
+            bob.outgoing_message().unwrap(),
+        );
+        assert_eq!(alice.handle_message(&bob_frame).unwrap(), None);
+        assert_eq!(bob.handle_message(&alice_frame).unwrap(), None);
+        // A resent blinded frame is answered with the same frame again
+        let alice_frame = alice.outgoing_message().unwrap();
+        assert_eq!(alice.handle_message(&bob_frame).unwrap(), None);
+        assert_eq!(alice.outgoing_message().unwrap(), alice_frame);
+
+        let bob_frame = bob.outgoing_message().unwrap();
+        let alice_result = alice.handle_message(&bob_frame).unwrap().unwrap();
+        let bob_result = bob.handle_message(&alice_frame).unwrap().unwrap();
+        assert_eq!(alice_result.len(), 1);
+        assert_eq!(
+            alice_result.intersection_hashes,
+            bob_result.intersection_hashes
+        );
+        assert!(alice.outgoing_message().is_err());
+    }
+
+    #[test]
+    fn test_handle_message_errors() {
+        let mut alice = PsiSession::new(&[b"apple".to_vec()]).unwrap();
+        assert!(matches!(
+            alice.handle_message(b"garbage"),
+            Err(PsiError::InvalidEncoding(_))
+        ));
+        let cardinality = wire::encode(&WireMessage::Cardinality(CardinalityMessage::new(3)));
+        assert!(matches!(
+            alice.handle_message(&cardinality),
+            Err(PsiError::InvalidEncoding(_))
+        ));
+        assert_eq!(alice.state_name(), "prepared");
+
+        let abort = wire::encode(&WireMessage::Abort(AbortMessage::new(
+            AbortReason::Overloaded,
+        )));
+        assert_eq!(
+            alice.handle_message(&abort).unwrap_err(),
+            PsiError::Aborted(AbortReason::Overloaded)
+        );
+        assert!(alice.outgoing_message().is_err());
+    }
+
+    #[test]
+    fn test_session_recognizes_duplicates() {
+        let mut alice = PsiSession::new(&[b"apple".to_vec(), b"banana".to_vec()]).unwrap();
+        let mut bob = PsiSession::new(&[b"banana".to_vec()]).unwrap();
+        let (alice_msg, bob_msg) = (alice.message().unwrap(), bob.message().unwrap());
+
+        let alice_double = alice.on_blinded(bob_msg.clone()).unwrap();
+        // A repeated blinded message gets the same answer again
+        assert_eq!(alice.on_blinded(bob_msg.clone()).unwrap(), alice_double);
+        let other = PsiSession::new(&[b"cherry".to_vec()]).unwrap();
+        assert!(matches!(
+            alice.on_blinded(other.message().unwrap()),
+            Err(PsiError::UnexpectedState { .. })
+        ));
+
+        let bob_double = bob.on_blinded(alice_msg).unwrap();
+        let result = alice.on_double_blinded(bob_double.clone()).unwrap();
+        assert_eq!(result.len(), 1);
+        assert!(alice
+            .on_double_blinded(bob_double)
+            .unwrap_err()
+            .is_duplicate());
+        assert!(alice.on_blinded(bob_msg).unwrap_err().is_duplicate());
+        assert!(alice.is_complete());
+    }
+
+    #[test]
+    fn test_session_rejects_wrong_state() {
+        let mut session = PsiSession::new(&[b"apple".to_vec()]).unwrap();
+        let msg = DoubleBlindedPointsMessage::new(vec![]);
+        assert_eq!(
+            session.on_double_blinded(msg).unwrap_err(),
+            PsiError::UnexpectedState {
+                operation: "on_double_blinded",
+                state: "prepared"
+            }
+        );
+        assert_eq!(session.state_name(), "prepared");
+    }
+
+    #[test]
+    fn test_session_keeps_state_on_error() {
+        let config = PsiConfig::builder().max_remote_items(1).build().unwrap();
+        let mut session = PsiSession::new_with_config(&[b"apple".to_vec()], config).unwrap();
+        let before = session.message().unwrap();
+
+        let remote = PsiSession::new(&[b"a".to_vec(), b"b".to_vec()]).unwrap();
+        assert!(session.on_blinded(remote.message().unwrap()).is_err());
+        assert_eq!(session.message().unwrap(), before);
+    }
+
+    #[test]
+    fn test_abort_reaches_the_peer() {
+        let mut alice = PsiSession::new(&[b"apple".to_vec()]).unwrap();
+        let mut bob = PsiSession::new(&[b"apple".to_vec()]).unwrap();
+        let alice_msg = alice.message().unwrap();
+
+        let abort = bob.abort(AbortReason::Overloaded);
+        assert_eq!(bob.state_name(), "aborted");
+        assert_eq!(
+            bob.on_blinded(alice_msg).unwrap_err(),
+            PsiError::Aborted(AbortReason::Overloaded)
+        );
+
+        assert_eq!(
+            alice.on_abort(abort),
+            PsiError::Aborted(AbortReason::Overloaded)
+        );
+        assert!(matches!(
+            alice.message(),
+            Err(PsiError::Aborted(AbortReason::Overloaded))
+        ));
+        assert!(!alice.is_complete());
+    }
+
+    #[test]
+    fn test_sessions_in_collection() {
+        let mut sessions: HashMap<u32, PsiSession> = HashMap::new();
+        let server = PsiProtocol::new(&[b"apple".to_vec()]).unwrap();
+        for peer in 0..3 {
+            sessions.insert(peer, PsiSession::new(&[b"apple".to_vec()]).unwrap());
+        }
+        for session in sessions.values_mut() {
+            let (_, reply) = server.compute_for_peer(session.message().unwrap()).unwrap();
+            session.on_blinded(server.message()).unwrap();
+            assert_eq!(session.on_double_blinded(reply).unwrap().len(), 1);
+        }
+        assert!(sessions.values().all(PsiSession::is_complete));
+    }
+}
@@ -0,0 +1,306 @@
+//! Hash-prefix sharding of one large intersection.
+//!
+//! A single protocol run over hundreds of millions of items is bound by one
+//! core and one machine's memory. [`Sharding`] splits a set into `K` shards by
+//! the prefix of each item's hash: the same item lands in the same shard on
+//! both sides, so shard `i` only needs to be intersected with the peer's
+//! shard `i`, and the `K` sub-protocols are independent. [`ShardedPsi`] runs
+//! them on local threads; [`Sharding::partition`] hands each shard's raw
+//! items to a different machine instead. [`combine`] joins the per-shard
+//! results into the global one.
+//!
+//! Both sides must use the same shard count and hash algorithm. Each shard
+//! is a full protocol run with its own secret; shards that are empty on one
+//! side are skipped, which reveals which hash ranges are empty (only
+//! relevant for small sets or large `K`).
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::sharding::{combine, ShardedPsi, Sharding};
+//! use psi_protocol::PsiConfig;
+//!
+//! let config = PsiConfig::builder().threads(16).build()?;
+//! let alice = ShardedPsi::new(&items, Sharding::new(64)?, config)?;
+//! let alice_messages = alice.messages();
+//!
+//! let (alice, alice_double) = alice.compute(bob_messages)?;
+//! let result = combine(alice.finalize(bob_double)?.into_values());
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::config::{HashAlgorithm, PsiConfig};
+use crate::crypto::{hash_bytes_with, hash_inputs_sorted, parallel_map, random_scalar};
+use crate::error::{PsiError, Result};
+use crate::messages::{BlindedPointsMessage, DoubleBlindedPointsMessage, PsiResult};
+use crate::protocol::PsiProtocol;
+use crate::state::{DoubleBlindedState, PreparedState, PsiState};
+use std::collections::BTreeMap;
+
+/// Largest accepted shard count.
+pub const MAX_SHARDS: u32 = 1 << 16;
+
+/// Split of the hash space into equal ranges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sharding {
+    shards: u32,
+}
+
+impl Sharding {
+    /// Split into `shards` ranges.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `shards` is zero or above
+    /// [`MAX_SHARDS`]
+    pub fn new(shards: u32) -> Result<Self> {
+        if shards == 0 || shards > MAX_SHARDS {
+            return Err(PsiError::InvalidConfig(format!(
+                "Shard count must be between 1 and {}, got {}",
+                MAX_SHARDS, shards
+            )));
+        }
+        Ok(Self { shards })
+    }
+
+    /// Number of shards.
+    pub fn shards(&self) -> u32 {
+        self.shards
+    }
+
+    /// Shard of an item hash, from its first four bytes.
+    ///
+    /// Monotonic in the hash, so hashes sorted by value are grouped by shard.
+    pub fn shard_of(&self, hash: &[u8; 32]) -> u32 {
+        let prefix = u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]);
+        ((u64::from(prefix) * u64::from(self.shards)) >> 32) as u32
+    }
+
+    /// Group raw items by shard, dropping no item.
+    ///
+    /// For running shards on different machines: each machine gets one
+    /// shard's items and runs a plain [`PsiProtocol`] over them with the
+    /// peer's machine for the same shard.
+    pub fn partition(
+        &self,
+        algorithm: HashAlgorithm,
+        items: &[Vec<u8>],
+    ) -> BTreeMap<u32, Vec<Vec<u8>>> {
+        let mut shards: BTreeMap<u32, Vec<Vec<u8>>> = BTreeMap::new();
+        for item in items {
+            let shard = self.shard_of(&hash_bytes_with(algorithm, item));
+            shards.entry(shard).or_default().push(item.clone());
+        }
+        shards
+    }
+}
+
+/// Join per-shard results into the result of the whole set.
+///
+/// Shards are disjoint, so this concatenates the intersections in the given
+/// order and unions the double-blinded maps.
+pub fn combine(results: impl IntoIterator<Item = PsiResult>) -> PsiResult {
+    let mut combined = PsiResult::default();
+    for result in results {
+        combined
+            .intersection_hashes
+            .extend(result.intersection_hashes);
+        combined
+            .double_blinded_map
+            .extend(result.double_blinded_map);
+    }
+    combined
+}
+
+/// One protocol run per shard, executed on local threads.
+///
+/// Mirrors [`PsiProtocol`]: `new` → `messages` → `compute` → `finalize`, with
+/// every message keyed by shard index. Up to
+/// [`PsiConfig::threads`] shards are processed at once.
+#[derive(Debug, Clone)]
+pub struct ShardedPsi<S: PsiState> {
+    sharding: Sharding,
+    threads: usize,
+    runs: BTreeMap<u32, PsiProtocol<S>>,
+}
+
+impl<S: PsiState> ShardedPsi<S> {
+    /// The sharding in use.
+    pub fn sharding(&self) -> Sharding {
+        self.sharding
+    }
+
+    /// Shard indices with a run in progress.
+    pub fn shards(&self) -> impl Iterator<Item = u32> + '_ {
+        self.runs.keys().copied()
+    }
+
+    /// Take the runs out, e.g. to drive them on separate tasks.
+    pub fn into_runs(self) -> BTreeMap<u32, PsiProtocol<S>> {
+        self.runs
+    }
+}
+
+impl ShardedPsi<PreparedState> {
+    /// Hash, shard and blind the items, one run per non-empty shard.
+    ///
+    /// The configured local limit applies to the whole set.
+    ///
+    /// # Errors
+    /// Returns `PsiError::EmptyInput` if `items` is empty and the
+    /// configuration does not allow it, and
+    /// `PsiError::LimitExceeded` if it is over the configured local limit
+    pub fn new(items: &[Vec<u8>], sharding: Sharding, config: PsiConfig) -> Result<Self> {
+        config.check_local_set(items.len())?;
+
+        let hashed =
+            hash_inputs_sorted(config.hash(), config.domain(), items, config.hash_threads());
+        let shards: Vec<(u32, &[_])> = hashed
+            .chunk_by(|a, b| sharding.shard_of(&a.0) == sharding.shard_of(&b.0))
+            .map(|chunk| (sharding.shard_of(&chunk[0].0), chunk))
+            .collect();
+        let runs = parallel_map(&shards, config.threads(), |(shard, hashed)| {
+            PsiProtocol::from_hashed(hashed, random_scalar(), config.clone())
+                .map(|run| (*shard, run))
+        })
+        .into_iter()
+        .collect::<Result<_>>()?;
+        Ok(Self {
+            sharding,
+            threads: config.threads(),
+            runs,
+        })
+    }
+
+    /// Blinded points messages to send, one per non-empty shard.
+    pub fn messages(&self) -> BTreeMap<u32, BlindedPointsMessage> {
+        self.runs
+            .iter()
+            .map(|(shard, run)| (*shard, run.message()))
+            .collect()
+    }
+
+    /// Double-blind the remote's messages for every shard both sides have.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::compute`] for each shard
+    pub fn compute(
+        self,
+        mut remote: BTreeMap<u32, BlindedPointsMessage>,
+    ) -> Result<(
+        ShardedPsi<DoubleBlindedState>,
+        BTreeMap<u32, DoubleBlindedPointsMessage>,
+    )> {
+        let work: Vec<_> = self
+            .runs
+            .into_iter()
+            .filter_map(|(shard, run)| remote.remove(&shard).map(|msg| (shard, run, msg)))
+            .collect();
+        let computed = parallel_map(&work, self.threads, |(shard, run, msg)| {
+            run.compute_for_peer(msg.clone())
+                .map(|(next, reply)| (*shard, next, reply))
+        });
+
+        let mut runs = BTreeMap::new();
+        let mut replies = BTreeMap::new();
+        for outcome in computed {
+            let (shard, next, reply) = outcome?;
+            runs.insert(shard, next);
+            replies.insert(shard, reply);
+        }
+        let sharded = ShardedPsi {
+            sharding: self.sharding,
+            threads: self.threads,
+            runs,
+        };
+        Ok((sharded, replies))
+    }
+}
+
+impl ShardedPsi<DoubleBlindedState> {
+    /// Compute the intersection of every shard from the remote's replies.
+    ///
+    /// Shards the remote did not answer are left out; pass the values to
+    /// [`combine`] for the global result.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::finalize`] for each shard
+    pub fn finalize(
+        self,
+        mut remote: BTreeMap<u32, DoubleBlindedPointsMessage>,
+    ) -> Result<BTreeMap<u32, PsiResult>> {
+        let work: Vec<_> = self
+            .runs
+            .into_iter()
+            .filter_map(|(shard, run)| remote.remove(&shard).map(|msg| (shard, run, msg)))
+            .collect();
+        parallel_map(&work, self.threads, |(shard, run, msg)| {
+            run.match_remote(msg).map(|result| (*shard, result))
+        })
+        .into_iter()
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ItemId;
+    use crate::local::run_local_psi;
+    use crate::test_util::numbered;
+
+    #[test]
+    fn test_shard_of_splits_prefix_ranges() {
+        let sharding = Sharding::new(4).unwrap();
+        let hash = |first: u8| {
+            let mut hash = [0u8; 32];
+            hash[0] = first;
+            hash
+        };
+        assert_eq!(sharding.shard_of(&hash(0x00)), 0);
+        assert_eq!(sharding.shard_of(&hash(0x3f)), 0);
+        assert_eq!(sharding.shard_of(&hash(0x40)), 1);
+        assert_eq!(sharding.shard_of(&hash(0xff)), 3);
+        assert!(Sharding::new(0).is_err());
+        assert!(Sharding::new(MAX_SHARDS + 1).is_err());
+    }
+
+    #[test]
+    fn test_sharded_matches_unsharded() {
+        let alice_items = numbered(0..30);
+        let bob_items = numbered(20..50);
+        let config = PsiConfig::builder().threads(4).build().unwrap();
+        let sharding = Sharding::new(4).unwrap();
+
+        let alice = ShardedPsi::new(&alice_items, sharding, config.clone()).unwrap();
+        let bob = ShardedPsi::new(&bob_items, sharding, config).unwrap();
+        assert_eq!(alice.shards().count(), 4);
+
+        let alice_messages = alice.messages();
+        let (alice, alice_double) = alice.compute(bob.messages()).unwrap();
+        let (bob, bob_double) = bob.compute(alice_messages).unwrap();
+        let alice_result = combine(alice.finalize(bob_double).unwrap().into_values());
+        let bob_result = combine(bob.finalize(alice_double).unwrap().into_values());
+
+        let (expected, _) = run_local_psi(&alice_items, &bob_items).unwrap();
+        let mut sharded: Vec<ItemId> = alice_result.intersection_hashes.clone();
+        let mut unsharded = expected.intersection_hashes;
+        sharded.sort();
+        unsharded.sort();
+        assert_eq!(sharded, unsharded);
+        assert_eq!(alice_result.len(), 10);
+        assert_eq!(bob_result.len(), 10);
+    }
+
+    #[test]
+    fn test_partition_agrees_with_sharded_runs() {
+        let sharding = Sharding::new(16).unwrap();
+        let all = numbered(0..40);
+        let partitioned = sharding.partition(HashAlgorithm::default(), &all);
+        assert_eq!(partitioned.values().map(Vec::len).sum::<usize>(), 40);
+
+        let sharded = ShardedPsi::new(&all, sharding, PsiConfig::default()).unwrap();
+        assert_eq!(
+            sharded.shards().collect::<Vec<_>>(),
+            partitioned.keys().copied().collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,280 @@
+//! Streaming the intersection into a caller-provided sink.
+//!
+//! [`PsiProtocol::finalize`] collects the intersection into a [`PsiResult`],
+//! a `Vec` of ids plus a `HashMap` of double-blinded points. For
+//! intersections with millions of entries that doubles the peak memory for
+//! data the caller usually writes straight to a file or a channel.
+//! [`PsiProtocol::finalize_into`] hands each match to a [`MatchSink`] as it
+//! is found instead, and [`PsiProtocol::finalize_with`] to a plain callback
+//! taking a [`Match`].
+//!
+//! Sinks are provided for closures, `Vec<ItemId>`, `mpsc::Sender<ItemId>`
+//! and any [`Write`] through [`WriteSink`].
+
+use crate::error::{PsiError, Result};
+use crate::item_id::ItemId;
+use crate::messages::DoubleBlindedPointsMessage;
+use crate::protocol::PsiProtocol;
+use crate::state::{DoubleBlindedState, FinalState};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::mpsc::Sender;
+use std::time::Instant;
+
+/// One item of the intersection, as seen by [`PsiProtocol::finalize_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    /// Id of the matched item
+    pub id: ItemId,
+    /// Double-blinded point of the item, as in
+    /// [`PsiResult::double_blinded_map`](crate::PsiResult::double_blinded_map)
+    pub double_blinded: CompressedRistretto,
+    /// Position of the item in our blinded points message
+    pub local_index: usize,
+    /// Position of the same item in the peer's blinded points message
+    pub remote_index: usize,
+}
+
+/// Destination for the matches of [`PsiProtocol::finalize_into`].
+pub trait MatchSink {
+    /// Take one item of the intersection with its double-blinded point.
+    ///
+    /// # Errors
+    /// Any error stops finalization and is returned to the caller of
+    /// `finalize_into` unchanged
+    fn push(&mut self, id: ItemId, double_blinded: CompressedRistretto) -> Result<()>;
+}
+
+impl<F> MatchSink for F
+where
+    F: FnMut(ItemId, CompressedRistretto) -> Result<()>,
+{
+    fn push(&mut self, id: ItemId, double_blinded: CompressedRistretto) -> Result<()> {
+        self(id, double_blinded)
+    }
+}
+
+impl MatchSink for Vec<ItemId> {
+    fn push(&mut self, id: ItemId, _double_blinded: CompressedRistretto) -> Result<()> {
+        Vec::push(self, id);
+        Ok(())
+    }
+}
+
+impl MatchSink for Sender<ItemId> {
+    /// # Errors
+    /// Returns `PsiError::SinkFailed` if the receiver was dropped
+    fn push(&mut self, id: ItemId, _double_blinded: CompressedRistretto) -> Result<()> {
+        self.send(id)
+            .map_err(|_| PsiError::SinkFailed("Receiver dropped".to_string()))
+    }
+}
+
+/// Writes each matching id as 32 raw bytes.
+///
+/// Wrap the writer in a `BufWriter` when it is a file or socket; call
+/// [`into_inner`](Self::into_inner) and flush once finalization returns.
+#[derive(Debug)]
+pub struct WriteSink<W> {
+    writer: W,
+    count: usize,
+}
+
+impl<W: Write> WriteSink<W> {
+    /// Write matches to `writer`.
+    pub fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+
+    /// Number of ids written so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Return the writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> MatchSink for WriteSink<W> {
+    /// # Errors
+    /// Returns `PsiError::SinkFailed` if the writer fails
+    fn push(&mut self, id: ItemId, _double_blinded: CompressedRistretto) -> Result<()> {
+        self.writer
+            .write_all(id.as_bytes())
+            .map_err(|e| PsiError::SinkFailed(e.to_string()))?;
+        self.count += 1;
+        Ok(())
+    }
+}
+
+impl PsiProtocol<DoubleBlindedState> {
+    /// Finalize the protocol, streaming the intersection into `sink`.
+    ///
+    /// Matches are pushed in our message order, without collecting them.
+    /// The returned final state does not keep the double-blinded map, so
+    /// its [`double_blinded_map`](PsiProtocol::double_blinded_map) is empty.
+    ///
+    /// # Returns
+    /// The final state and the number of matches pushed
+    ///
+    /// # Errors
+    /// Same as [`finalize`](Self::finalize), plus any error of the sink.
+    /// Matches pushed before an error stay in the sink.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let mut sink = WriteSink::new(BufWriter::new(File::create("matches.bin")?));
+    /// let (_, count) = alice.finalize_into(bob_double_msg, &mut sink)?;
+    /// sink.into_inner().flush()?;
+    /// # Ok::<(), psi_protocol::PsiError>(())
+    /// ```
+    pub fn finalize_into<S: MatchSink + ?Sized>(
+        self,
+        remote_msg: DoubleBlindedPointsMessage,
+        sink: &mut S,
+    ) -> Result<(PsiProtocol<FinalState>, usize)> {
+        let started = Instant::now();
+        let mut count = 0;
+        self.match_remote_into(&remote_msg, |matched, point| {
+            count += 1;
+            sink.push(matched.id, point)
+        })?;
+        let state = FinalState::new(HashMap::new());
+        Ok((self.to_final(state, started, &remote_msg, count), count))
+    }
+
+    /// Finalize the protocol, calling `on_match` for each item of the
+    /// intersection.
+    ///
+    /// The infallible counterpart of [`finalize_into`](Self::finalize_into)
+    /// for callers that build their own structures from the matches; same
+    /// order and same empty final state.
+    ///
+    /// # Returns
+    /// The final state and the number of matches
+    ///
+    /// # Errors
+    /// Same as [`finalize`](Self::finalize). No match is reported unless
+    /// the whole message is valid.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let mut shared = BTreeMap::new();
+    /// alice.finalize_with(bob_double_msg, |m| {
+    ///     shared.insert(m.id, m.local_index);
+    /// })?;
+    /// # Ok::<(), psi_protocol::PsiError>(())
+    /// ```
+    pub fn finalize_with<F: FnMut(Match)>(
+        self,
+        remote_msg: DoubleBlindedPointsMessage,
+        mut on_match: F,
+    ) -> Result<(PsiProtocol<FinalState>, usize)> {
+        let started = Instant::now();
+        let mut count = 0;
+        self.match_remote_into(&remote_msg, |matched, double_blinded| {
+            count += 1;
+            on_match(Match {
+                id: matched.id,
+                double_blinded,
+                local_index: matched.local_index,
+                remote_index: matched.remote_index,
+            });
+            Ok(())
+        })?;
+        let state = FinalState::new(HashMap::new());
+        Ok((self.to_final(state, started, &remote_msg, count), count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run both parties up to finalization, returning Alice's state and
+    /// Bob's double-blinded answer.
+    fn run() -> (PsiProtocol<DoubleBlindedState>, DoubleBlindedPointsMessage) {
+        let alice =
+            PsiProtocol::new(&[b"apple".to_vec(), b"banana".to_vec(), b"cherry".to_vec()]).unwrap();
+        let bob = PsiProtocol::new(&[b"banana".to_vec(), b"cherry".to_vec()]).unwrap();
+        let alice_msg = alice.message();
+        let (alice, _) = alice.compute(bob.message()).unwrap();
+        let (_, bob_double) = bob.compute(alice_msg).unwrap();
+        (alice, bob_double)
+    }
+
+    #[test]
+    fn test_sinks_match_finalize() {
+        let (alice, bob_double) = run();
+        let (_, expected) = alice.clone().finalize(bob_double.clone()).unwrap();
+
+        let mut ids = Vec::new();
+        let (_, count) = alice
+            .clone()
+            .finalize_into(bob_double.clone(), &mut ids)
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(ids, expected.intersection_hashes);
+
+        let mut sink = WriteSink::new(Vec::new());
+        alice
+            .clone()
+            .finalize_into(bob_double.clone(), &mut sink)
+            .unwrap();
+        assert_eq!(sink.count(), 2);
+        let expected_bytes: Vec<u8> = expected
+            .intersection_hashes
+            .iter()
+            .flat_map(|id| id.as_bytes().to_vec())
+            .collect();
+        assert_eq!(sink.into_inner(), expected_bytes);
+
+        let (mut sender, receiver) = std::sync::mpsc::channel();
+        alice.finalize_into(bob_double, &mut sender).unwrap();
+        drop(sender);
+        assert_eq!(
+            receiver.iter().collect::<Vec<_>>(),
+            expected.intersection_hashes
+        );
+    }
+
+    #[test]
+    fn test_finalize_with_reports_every_match() {
+        let (alice, bob_double) = run();
+        let (_, expected) = alice.clone().finalize(bob_double.clone()).unwrap();
+
+        let mut matches = Vec::new();
+        let (state, count) = alice
+            .finalize_with(bob_double, |m| matches.push(m))
+            .unwrap();
+        assert_eq!(count, 2);
+        assert!(state.double_blinded_map().is_empty());
+        for m in &matches {
+            assert_eq!(expected.double_blinded_map[&m.id], m.double_blinded);
+        }
+        let ids: Vec<ItemId> = matches.iter().map(|m| m.id).collect();
+        assert_eq!(ids, expected.intersection_hashes);
+        // Matches come in our message order
+        assert!(matches
+            .windows(2)
+            .all(|w| w[0].local_index < w[1].local_index));
+    }
+
+    #[test]
+    fn test_sink_error_stops_finalization() {
+        let (alice, bob_double) = run();
+        let mut seen = 0;
+        let mut sink = |_: ItemId, _: CompressedRistretto| {
+            seen += 1;
+            Err(PsiError::SinkFailed("full".to_string()))
+        };
+        assert_eq!(
+            alice.finalize_into(bob_double, &mut sink).unwrap_err(),
+            PsiError::SinkFailed("full".to_string())
+        );
+        assert_eq!(seen, 1);
+    }
+}
@@ -0,0 +1,145 @@
+//! Pulling items from external stores in batches.
+//!
+//! [`PsiProtocol::new`] needs every item in memory at once. An
+//! [`ItemSource`] hands them over a batch at a time instead (a page of a
+//! database query, a chunk of a file), and
+//! [`PsiProtocol::from_source`] blinds each batch before asking for the
+//! next, so only the 32-byte hashes and blinded points of the set are kept.
+//!
+//! `SqliteSource` (`sqlite` feature) is a reference implementation paging a
+//! column out of a SQLite table.
+
+use crate::config::PsiConfig;
+use crate::crypto::{blind_point, hash_item_with, hash_to_point_in, random_scalar};
+use crate::error::Result;
+use crate::protocol::PsiProtocol;
+use crate::state::PreparedState;
+use std::collections::BTreeMap;
+
+/// Source of protocol items, read in batches.
+pub trait ItemSource {
+    /// Next batch of items, or `None` once the source is exhausted.
+    ///
+    /// Batches may be of any size; an empty batch does not end the source.
+    ///
+    /// # Errors
+    /// Any error stops reading and is returned to the caller unchanged
+    fn next_batch(&mut self) -> Result<Option<Vec<Vec<u8>>>>;
+
+    /// Approximate total number of items, if the source knows it cheaply.
+    ///
+    /// Only a hint for progress reporting; duplicates are counted.
+    fn approximate_count(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl<S: ItemSource + ?Sized> ItemSource for &mut S {
+    fn next_batch(&mut self) -> Result<Option<Vec<Vec<u8>>>> {
+        (**self).next_batch()
+    }
+
+    fn approximate_count(&self) -> Option<usize> {
+        (**self).approximate_count()
+    }
+}
+
+impl PsiProtocol<PreparedState> {
+    /// Build a prepared protocol from the items of a source.
+    ///
+    /// Duplicates are blinded once. The local item limit is checked after
+    /// every batch, so an oversized source is rejected without being
+    /// drained.
+    ///
+    /// # Errors
+    /// Returns `PsiError::EmptyInput` if the source yields no item,
+    /// `PsiError::LimitExceeded` once it yields more unique items than the
+    /// configured local limit, plus any error of the source
+    ///
+    /// # Example
+    /// ```ignore
+    /// let mut users = SqliteSource::open("users.db", "users", "email")?;
+    /// let alice = PsiProtocol::from_source(&mut users, PsiConfig::default())?;
+    /// # Ok::<(), psi_protocol::PsiError>(())
+    /// ```
+    pub fn from_source<S: ItemSource + ?Sized>(source: &mut S, config: PsiConfig) -> Result<Self> {
+        let secret = random_scalar();
+        let mut blinded = BTreeMap::new();
+
+        while let Some(batch) = source.next_batch()? {
+            for item in batch {
+                let hash = hash_item_with(config.hash(), &item);
+                blinded.entry(hash).or_insert_with(|| {
+                    blind_point(&hash_to_point_in(config.domain(), &hash), &secret)
+                });
+            }
+            config.check_local_len(blinded.len())?;
+        }
+
+        Self::from_blinded(blinded.into_iter().collect(), secret, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{Limit, PsiError};
+    use crate::item_id::ItemId;
+
+    /// Source serving fixed batches.
+    struct Batches(std::vec::IntoIter<Vec<Vec<u8>>>, usize);
+
+    impl Batches {
+        fn new(batches: &[&[&[u8]]]) -> Self {
+            let batches: Vec<Vec<Vec<u8>>> = batches
+                .iter()
+                .map(|batch| batch.iter().map(|item| item.to_vec()).collect())
+                .collect();
+            let count = batches.iter().map(Vec::len).sum();
+            Self(batches.into_iter(), count)
+        }
+    }
+
+    impl ItemSource for Batches {
+        fn next_batch(&mut self) -> Result<Option<Vec<Vec<u8>>>> {
+            Ok(self.0.next())
+        }
+
+        fn approximate_count(&self) -> Option<usize> {
+            Some(self.1)
+        }
+    }
+
+    #[test]
+    fn test_from_source_runs_protocol() {
+        let mut source = Batches::new(&[&[b"apple", b"banana"], &[], &[b"apple", b"cherry"]]);
+        assert_eq!(source.approximate_count(), Some(4));
+        let alice = PsiProtocol::from_source(&mut source, PsiConfig::default()).unwrap();
+        assert_eq!(alice.message().len(), 3);
+
+        let bob = PsiProtocol::new(&[b"cherry".to_vec(), b"date".to_vec()]).unwrap();
+        let alice_msg = alice.message();
+        let (alice, _) = alice.compute(bob.message()).unwrap();
+        let (_, bob_double) = bob.compute(alice_msg).unwrap();
+        let (_, result) = alice.finalize(bob_double).unwrap();
+        assert_eq!(result.intersection_hashes, vec![ItemId::of(b"cherry")]);
+    }
+
+    #[test]
+    fn test_from_source_errors() {
+        assert_eq!(
+            PsiProtocol::from_source(&mut Batches::new(&[&[]]), PsiConfig::default()).unwrap_err(),
+            PsiError::EmptyInput
+        );
+        let config = PsiConfig::builder().max_local_items(2).build().unwrap();
+        let mut source = Batches::new(&[&[b"a", b"b"], &[b"c"], &[b"d"]]);
+        assert!(matches!(
+            PsiProtocol::from_source(&mut source, config),
+            Err(PsiError::LimitExceeded {
+                limit: Limit::LocalItems,
+                ..
+            })
+        ));
+        assert!(source.next_batch().unwrap().is_some(), "source was drained");
+    }
+}
@@ -0,0 +1,233 @@
+//! SQLite tables as an [`ItemSource`].
+//!
+//! [`SqliteSource`] pages one column of a table by `rowid`, so each batch is
+//! a short indexed query and the connection holds no statement open between
+//! batches; rows inserted while paging are read if they land after the
+//! cursor. `BLOB` values are used as is and `TEXT` values as their UTF-8
+//! bytes; `NULL` rows are skipped, and integer or real values are rejected
+//! rather than given an implicit encoding.
+//!
+//! Tables declared `WITHOUT ROWID` are not supported.
+//!
+//! Requires the `sqlite` feature.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::sqlite::SqliteSource;
+//!
+//! let mut users = SqliteSource::open("users.db", "users", "email")?;
+//! println!("reading ~{:?} rows", users.approximate_count());
+//! let alice = PsiProtocol::from_source(&mut users, PsiConfig::default())?;
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::error::{PsiError, Result};
+use crate::source::ItemSource;
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags};
+use std::path::Path;
+
+/// Default number of rows per batch.
+pub const DEFAULT_BATCH_SIZE: usize = 10_000;
+
+/// Pages the values of one column out of a SQLite table.
+#[derive(Debug)]
+pub struct SqliteSource {
+    connection: Connection,
+    query: String,
+    last_rowid: i64,
+    batch_size: usize,
+    count: usize,
+    done: bool,
+}
+
+fn source_error(error: rusqlite::Error) -> PsiError {
+    PsiError::SourceFailed(format!("SQLite: {}", error))
+}
+
+/// Quote an identifier for interpolation into SQL.
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+impl SqliteSource {
+    /// Open the database at `path` read-only and page `table.column`.
+    ///
+    /// # Errors
+    /// Returns `PsiError::SourceFailed` if the database cannot be opened or
+    /// has no such table or column
+    pub fn open(path: impl AsRef<Path>, table: &str, column: &str) -> Result<Self> {
+        let connection = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .map_err(source_error)?;
+        Self::new(connection, table, column)
+    }
+
+    /// Page `table.column` over an existing connection.
+    ///
+    /// The approximate count is read once, from `max(rowid)`.
+    ///
+    /// # Errors
+    /// Returns `PsiError::SourceFailed` if there is no such table or column
+    pub fn new(connection: Connection, table: &str, column: &str) -> Result<Self> {
+        // SQLite reads an unknown double-quoted column as a string literal,
+        // so check the schema instead of relying on the query failing
+        let exists: bool = connection
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+                (table, column),
+                |row| row.get(0),
+            )
+            .map_err(source_error)?;
+        if !exists {
+            return Err(PsiError::SourceFailed(format!(
+                "SQLite: no column {:?} in table {:?}",
+                column, table
+            )));
+        }
+        let table = quote(table);
+        let query = format!(
+            "SELECT rowid, {} FROM {} WHERE rowid > ?1 ORDER BY rowid LIMIT ?2",
+            quote(column),
+            table
+        );
+        let max_rowid: Option<i64> = connection
+            .query_row(&format!("SELECT max(rowid) FROM {}", table), [], |row| {
+                row.get(0)
+            })
+            .map_err(source_error)?;
+        Ok(Self {
+            connection,
+            query,
+            last_rowid: i64::MIN,
+            batch_size: DEFAULT_BATCH_SIZE,
+            count: max_rowid.map_or(0, |max| max.max(0) as usize),
+            done: false,
+        })
+    }
+
+    /// Set the number of rows fetched per batch (minimum 1).
+    pub fn batch_size(mut self, rows: usize) -> Self {
+        self.batch_size = rows.max(1);
+        self
+    }
+
+    /// Return the connection.
+    pub fn into_inner(self) -> Connection {
+        self.connection
+    }
+}
+
+impl ItemSource for SqliteSource {
+    /// # Errors
+    /// Returns `PsiError::SourceFailed` if the query fails or a value is
+    /// neither `BLOB`, `TEXT` nor `NULL`
+    fn next_batch(&mut self) -> Result<Option<Vec<Vec<u8>>>> {
+        if self.done {
+            return Ok(None);
+        }
+        let mut statement = self
+            .connection
+            .prepare_cached(&self.query)
+            .map_err(source_error)?;
+        let mut rows = statement
+            .query((self.last_rowid, self.batch_size as i64))
+            .map_err(source_error)?;
+
+        let mut batch = Vec::with_capacity(self.batch_size);
+        let mut read = 0;
+        while let Some(row) = rows.next().map_err(source_error)? {
+            read += 1;
+            self.last_rowid = row.get(0).map_err(source_error)?;
+            match row.get_ref(1).map_err(source_error)? {
+                ValueRef::Blob(bytes) | ValueRef::Text(bytes) => batch.push(bytes.to_vec()),
+                ValueRef::Null => {}
+                other => {
+                    return Err(PsiError::SourceFailed(format!(
+                        "Unsupported {} value at rowid {}",
+                        other.data_type(),
+                        self.last_rowid
+                    )))
+                }
+            }
+        }
+        if read < self.batch_size {
+            self.done = true;
+            if read == 0 {
+                return Ok(None);
+            }
+        }
+        Ok(Some(batch))
+    }
+
+    fn approximate_count(&self) -> Option<usize> {
+        Some(self.count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PsiConfig;
+    use crate::item_id::ItemId;
+    use crate::protocol::PsiProtocol;
+
+    fn users(rows: &[Option<&str>]) -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute("CREATE TABLE users (email)", [])
+            .unwrap();
+        for row in rows {
+            connection
+                .execute("INSERT INTO users (email) VALUES (?1)", [row])
+                .unwrap();
+        }
+        connection
+    }
+
+    #[test]
+    fn test_pages_table_into_protocol() {
+        let connection = users(&[Some("a"), None, Some("b"), Some("c"), Some("a"), Some("d")]);
+        let mut source = SqliteSource::new(connection, "users", "email")
+            .unwrap()
+            .batch_size(2);
+        assert_eq!(source.approximate_count(), Some(6));
+        let alice = PsiProtocol::from_source(&mut source, PsiConfig::default()).unwrap();
+        assert_eq!(alice.message().len(), 4);
+        assert_eq!(source.next_batch().unwrap(), None);
+
+        let bob = PsiProtocol::new(&[b"b".to_vec(), b"e".to_vec()]).unwrap();
+        let alice_msg = alice.message();
+        let (alice, _) = alice.compute(bob.message()).unwrap();
+        let (_, bob_double) = bob.compute(alice_msg).unwrap();
+        let (_, result) = alice.finalize(bob_double).unwrap();
+        assert_eq!(result.intersection_hashes, vec![ItemId::of(b"b")]);
+    }
+
+    #[test]
+    fn test_rejects_bad_tables_and_values() {
+        assert!(matches!(
+            SqliteSource::new(users(&[]), "users", "phone"),
+            Err(PsiError::SourceFailed(_))
+        ));
+        assert!(matches!(
+            SqliteSource::new(users(&[]), "users\"; DROP TABLE users; --", "email"),
+            Err(PsiError::SourceFailed(_))
+        ));
+        let mut empty = SqliteSource::new(users(&[]), "users", "email").unwrap();
+        assert_eq!(empty.approximate_count(), Some(0));
+        assert_eq!(empty.next_batch().unwrap(), None);
+
+        let connection = users(&[Some("a")]);
+        connection
+            .execute("INSERT INTO users (email) VALUES (42)", [])
+            .unwrap();
+        let mut source = SqliteSource::new(connection, "users", "email").unwrap();
+        assert!(matches!(
+            source.next_batch(),
+            Err(PsiError::SourceFailed(_))
+        ));
+    }
+}
@@ -1,8 +1,57 @@
 //! Protocol state types for the type-state pattern PSI implementation.
-
+//!
+//! All state types are `Send + Sync + Clone`. This is checked at compile
+//! time below, so moving a state into another thread or task is guaranteed
+//! to keep working across releases.
+//!
+//! States holding the secret scalar keep it in a `SecretScalar`, which is
+//! redacted from `Debug` output and zeroized when dropped. The set-derived
+//! material of each state (item hashes, blinded points, hash order and
+//! double-blinded points) is zeroized as well when the state is dropped,
+//! which happens at every transition, so it does not linger in freed memory
+//! until reused.
+//!
+//! Local items are kept in a single vector of `(hash, blinded point)` pairs
+//! sorted by hash, looked up by binary search. For million-item sets this
+//! is far more compact than hash maps and keeps lookups cache-friendly.
+
+use crate::config::PsiConfig;
+use crate::item_id::ItemId;
+use crate::messages::BlindedPointsMessage;
+use crate::psk::BLINDED_LABEL;
+use crate::secret::SecretScalar;
 use curve25519_dalek::ristretto::CompressedRistretto;
 use curve25519_dalek::Scalar;
 use std::collections::HashMap;
+use std::sync::Arc;
+use zeroize::Zeroize;
+
+/// Local items as `(hash, single-blinded point)` pairs, sorted by hash.
+pub(crate) type BlindedItems = Vec<([u8; 32], CompressedRistretto)>;
+
+/// Slots of an outgoing message in order: the hash of the item behind each
+/// point, `None` for a padding point.
+pub(crate) type MessageSlots = Vec<(Option<[u8; 32]>, CompressedRistretto)>;
+
+/// Overwrite local items with zeros and empty the vector.
+fn wipe_items(items: &mut BlindedItems) {
+    for (hash, point) in items.iter_mut() {
+        hash.zeroize();
+        point.zeroize();
+    }
+    items.clear();
+}
+
+/// Find the blinded point of `hash` in items sorted by hash.
+fn find_blinded<'a>(
+    items: &'a [([u8; 32], CompressedRistretto)],
+    hash: &[u8; 32],
+) -> Option<&'a CompressedRistretto> {
+    items
+        .binary_search_by_key(hash, |(item_hash, _)| *item_hash)
+        .ok()
+        .map(|index| &items[index].1)
+}
 
 /// Marker trait that all protocol states must implement.
 ///
@@ -15,97 +64,119 @@ pub trait PsiState {}
 /// This state exists after the protocol has been initialized with items
 /// and the points have been blinded. The blinded points are ready to be
 /// exchanged with a remote party.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PreparedState {
-    /// Secret scalar used for blinding
-    secret: Scalar,
-    /// Mapping from input hash to single-blinded point
-    hash_to_blinded: HashMap<[u8; 32], CompressedRistretto>,
-    /// Reverse mapping from blinded point to hash (for final result lookup)
-    blinded_to_hash: HashMap<CompressedRistretto, [u8; 32]>,
-    /// Ordered list of hashes (matches the order of blinded points in the message)
-    hash_order: Vec<[u8; 32]>,
+    /// Secret scalar used for blinding, redacted from `Debug` output
+    secret: SecretScalar,
+    /// Input hashes and their single-blinded points, sorted by hash
+    blinded_items: BlindedItems,
+    /// Ordered list of hashes (matches the order of blinded points in the message,
+    /// `None` marks a padding point)
+    hash_order: Vec<Option<[u8; 32]>>,
+    /// The outgoing message, built once; its points are in message order
+    /// (including padding)
+    message: Arc<BlindedPointsMessage>,
 }
 
 impl PreparedState {
-    /// Create a new PreparedState with the given secret and blinded mappings.
+    /// Create a new PreparedState with the given secret and blinded items.
+    ///
+    /// `blinded_items` must be sorted by hash. The hash order and the
+    /// message points are both taken from `slots`, so they always have the
+    /// same length and order. The message is authenticated and tagged
+    /// with the parameters of `config`.
     pub(crate) fn new(
         secret: Scalar,
-        hash_to_blinded: HashMap<[u8; 32], CompressedRistretto>,
-        blinded_to_hash: HashMap<CompressedRistretto, [u8; 32]>,
-        hash_order: Vec<[u8; 32]>,
+        blinded_items: BlindedItems,
+        slots: MessageSlots,
+        config: &PsiConfig,
     ) -> Self {
+        debug_assert!(blinded_items.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        let (hash_order, message_points) = slots.into_iter().unzip();
+        let mut message = BlindedPointsMessage::new(message_points);
+        message.authentication = config.authenticate(BLINDED_LABEL, &message.blinded_points);
+        message.parameters = Some(config.parameter_digest());
         Self {
-            secret,
-            hash_to_blinded,
-            blinded_to_hash,
+            secret: SecretScalar::new(secret),
+            blinded_items,
             hash_order,
+            message: Arc::new(message),
         }
     }
 
     /// Get the secret scalar (for testing purposes).
     #[cfg(test)]
     pub fn secret(&self) -> &Scalar {
-        &self.secret
+        self.secret.expose()
     }
 
-    /// Get the hash to blinded mapping (for testing purposes).
+    /// Get the single-blinded point of a hash (for testing purposes).
     #[cfg(test)]
-    pub fn hash_to_blinded(&self) -> &HashMap<[u8; 32], CompressedRistretto> {
-        &self.hash_to_blinded
+    pub fn blinded_point(&self, hash: &[u8; 32]) -> Option<&CompressedRistretto> {
+        find_blinded(&self.blinded_items, hash)
     }
 
-    /// Get a reference to the hash_to_blinded map.
-    pub(crate) fn blinded_map(&self) -> &HashMap<[u8; 32], CompressedRistretto> {
-        &self.hash_to_blinded
+    /// Get the blinded items, sorted by hash.
+    pub(crate) fn blinded_items(&self) -> &[([u8; 32], CompressedRistretto)] {
+        &self.blinded_items
     }
 
     /// Get the secret scalar.
     pub(crate) fn secret_scalar(&self) -> &Scalar {
-        &self.secret
-    }
-
-    /// Get the blinded_to_hash reverse mapping.
-    pub(crate) fn blinded_to_hash(&self) -> &HashMap<CompressedRistretto, [u8; 32]> {
-        &self.blinded_to_hash
+        self.secret.expose()
     }
 
     /// Get the ordered list of hashes.
-    pub(crate) fn hash_order(&self) -> &[[u8; 32]] {
+    pub(crate) fn hash_order(&self) -> &[Option<[u8; 32]>] {
         &self.hash_order
     }
+
+    /// Get the points of the outgoing message.
+    pub(crate) fn message_points(&self) -> &[CompressedRistretto] {
+        &self.message.blinded_points
+    }
+
+    /// Get the outgoing message.
+    pub(crate) fn message(&self) -> &Arc<BlindedPointsMessage> {
+        &self.message
+    }
 }
 
 impl PsiState for PreparedState {}
 
+impl Drop for PreparedState {
+    fn drop(&mut self) {
+        wipe_items(&mut self.blinded_items);
+        self.hash_order.zeroize();
+    }
+}
+
 /// Second state: During computation - contains remote data for intersection.
 ///
 /// This state exists internally during the computation phase when we have
 /// both local and remote data. It's used to compute the intersection.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct ComputingState {
-    /// Secret scalar used for blinding
-    secret: Scalar,
-    /// Mapping from input hash to single-blinded point (local)
-    hash_to_blinded: HashMap<[u8; 32], CompressedRistretto>,
-    /// Reverse mapping from blinded point to hash (local)
-    blinded_to_hash: HashMap<CompressedRistretto, [u8; 32]>,
+    /// Secret scalar used for blinding, redacted from `Debug` output
+    secret: SecretScalar,
+    /// Input hashes and their single-blinded points, sorted by hash (local)
+    blinded_items: BlindedItems,
     /// Remote blinded points (no hashes - we don't have them!)
     remote_blinded_points: Vec<CompressedRistretto>,
 }
 
+#[allow(dead_code)]
 impl ComputingState {
     /// Create a new ComputingState with local and remote data.
     pub(crate) fn new(
         secret: Scalar,
-        hash_to_blinded: HashMap<[u8; 32], CompressedRistretto>,
-        blinded_to_hash: HashMap<CompressedRistretto, [u8; 32]>,
+        blinded_items: BlindedItems,
         remote_blinded_points: Vec<CompressedRistretto>,
     ) -> Self {
         Self {
-            secret,
-            hash_to_blinded,
-            blinded_to_hash,
+            secret: SecretScalar::new(secret),
+            blinded_items,
             remote_blinded_points,
         }
     }
@@ -113,22 +184,17 @@ impl ComputingState {
     /// Get the secret scalar (for testing purposes).
     #[cfg(test)]
     pub fn secret(&self) -> &Scalar {
-        &self.secret
+        self.secret.expose()
     }
 
     /// Get the secret scalar.
     pub(crate) fn secret_scalar(&self) -> &Scalar {
-        &self.secret
+        self.secret.expose()
     }
 
-    /// Get the local blinded mapping.
-    pub(crate) fn blinded_map(&self) -> &HashMap<[u8; 32], CompressedRistretto> {
-        &self.hash_to_blinded
-    }
-
-    /// Get the blinded_to_hash reverse mapping.
-    pub(crate) fn blinded_to_hash(&self) -> &HashMap<CompressedRistretto, [u8; 32]> {
-        &self.blinded_to_hash
+    /// Get the single-blinded point of a local hash.
+    pub(crate) fn blinded_point(&self, hash: &[u8; 32]) -> Option<&CompressedRistretto> {
+        find_blinded(&self.blinded_items, hash)
     }
 
     /// Get the remote blinded points.
@@ -139,38 +205,40 @@ impl ComputingState {
 
 impl PsiState for ComputingState {}
 
+impl Drop for ComputingState {
+    fn drop(&mut self) {
+        wipe_items(&mut self.blinded_items);
+        self.remote_blinded_points.zeroize();
+    }
+}
+
 /// Third state: After double-blinding - ready for final exchange.
 ///
 /// This state exists after we've double-blinded the remote's single-blinded points.
 /// The double-blinded points are ready to be exchanged with the remote party for
 /// the final intersection computation.
-#[derive(Debug)]
+///
+/// Our single-blinded points are not needed to finalize and are not kept.
+#[derive(Debug, Clone)]
 pub struct DoubleBlindedState {
-    /// Secret scalar used for blinding
-    secret: Scalar,
-    /// Mapping from input hash to single-blinded point (local)
-    hash_to_blinded: HashMap<[u8; 32], CompressedRistretto>,
-    /// Reverse mapping from blinded point to hash (local)
-    blinded_to_hash: HashMap<CompressedRistretto, [u8; 32]>,
+    /// Secret scalar used for blinding, redacted from `Debug` output
+    secret: SecretScalar,
     /// Double-blinded points computed FROM remote's single-blinded points
     double_blinded_from_remote: Vec<CompressedRistretto>,
-    /// Ordered list of hashes (matches the order of blinded points in our message)
-    hash_order: Vec<[u8; 32]>,
+    /// Ordered list of hashes (matches the order of blinded points in our message,
+    /// `None` marks a padding point)
+    hash_order: Vec<Option<[u8; 32]>>,
 }
 
 impl DoubleBlindedState {
     /// Create a new DoubleBlindedState with local data and computed double-blinded points.
     pub(crate) fn new(
         secret: Scalar,
-        hash_to_blinded: HashMap<[u8; 32], CompressedRistretto>,
-        blinded_to_hash: HashMap<CompressedRistretto, [u8; 32]>,
         double_blinded_from_remote: Vec<CompressedRistretto>,
-        hash_order: Vec<[u8; 32]>,
+        hash_order: Vec<Option<[u8; 32]>>,
     ) -> Self {
         Self {
-            secret,
-            hash_to_blinded,
-            blinded_to_hash,
+            secret: SecretScalar::new(secret),
             double_blinded_from_remote,
             hash_order,
         }
@@ -179,22 +247,13 @@ impl DoubleBlindedState {
     /// Get the secret scalar (for testing purposes).
     #[cfg(test)]
     pub fn secret(&self) -> &Scalar {
-        &self.secret
+        self.secret.expose()
     }
 
     /// Get the secret scalar.
+    #[allow(dead_code)]
     pub(crate) fn secret_scalar(&self) -> &Scalar {
-        &self.secret
-    }
-
-    /// Get the local blinded mapping.
-    pub(crate) fn blinded_map(&self) -> &HashMap<[u8; 32], CompressedRistretto> {
-        &self.hash_to_blinded
-    }
-
-    /// Get the blinded_to_hash reverse mapping.
-    pub(crate) fn blinded_to_hash(&self) -> &HashMap<CompressedRistretto, [u8; 32]> {
-        &self.blinded_to_hash
+        self.secret.expose()
     }
 
     /// Get the double-blinded points computed from remote's single-blinded points.
@@ -203,27 +262,34 @@ impl DoubleBlindedState {
     }
 
     /// Get the ordered list of hashes.
-    pub(crate) fn hash_order(&self) -> &[[u8; 32]] {
+    pub(crate) fn hash_order(&self) -> &[Option<[u8; 32]>] {
         &self.hash_order
     }
 }
 
 impl PsiState for DoubleBlindedState {}
 
+impl Drop for DoubleBlindedState {
+    fn drop(&mut self) {
+        self.double_blinded_from_remote.zeroize();
+        self.hash_order.zeroize();
+    }
+}
+
 /// Final state: Complete - contains the intersection results.
 ///
 /// This state exists after the intersection has been computed.
 /// The secret is dropped for security (no longer needed).
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FinalState {
     /// Mapping from intersection hashes to their double-blinded point representations
-    hash_to_double_blinded: HashMap<[u8; 32], CompressedRistretto>,
+    hash_to_double_blinded: HashMap<ItemId, CompressedRistretto>,
 }
 
 impl FinalState {
     /// Create a new FinalState with the intersection results.
     pub(crate) fn new(
-        hash_to_double_blinded: HashMap<[u8; 32], CompressedRistretto>,
+        hash_to_double_blinded: HashMap<ItemId, CompressedRistretto>,
     ) -> Self {
         Self {
             hash_to_double_blinded,
@@ -232,18 +298,37 @@ impl FinalState {
 
     /// Get the hash to double-blinded mapping (for testing purposes).
     #[cfg(test)]
-    pub fn hash_to_double_blinded(&self) -> &HashMap<[u8; 32], CompressedRistretto> {
+    pub fn hash_to_double_blinded(&self) -> &HashMap<ItemId, CompressedRistretto> {
         &self.hash_to_double_blinded
     }
 
     /// Get the double-blinded mapping.
-    pub(crate) fn double_blinded_map(&self) -> &HashMap<[u8; 32], CompressedRistretto> {
+    pub(crate) fn double_blinded_map(&self) -> &HashMap<ItemId, CompressedRistretto> {
         &self.hash_to_double_blinded
     }
 }
 
 impl PsiState for FinalState {}
 
+impl Drop for FinalState {
+    fn drop(&mut self) {
+        // Keys cannot be overwritten in place; they are the ids also handed
+        // out in the `PsiResult`
+        for point in self.hash_to_double_blinded.values_mut() {
+            point.zeroize();
+        }
+    }
+}
+
+// Compile-time guarantee that every state can cross thread boundaries.
+const _: () = {
+    const fn assert_send_sync_clone<T: Send + Sync + Clone>() {}
+    assert_send_sync_clone::<PreparedState>();
+    assert_send_sync_clone::<ComputingState>();
+    assert_send_sync_clone::<DoubleBlindedState>();
+    assert_send_sync_clone::<FinalState>();
+};
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -252,28 +337,35 @@ mod tests {
     #[test]
     fn test_prepared_state_new() {
         let secret = random_scalar();
-        let hash_map = HashMap::new();
-        let blinded_map = HashMap::new();
-        let hash_order = vec![];
-        let state = PreparedState::new(secret, hash_map, blinded_map, hash_order);
-        assert!(!state.hash_to_blinded().contains_key(&[0u8; 32]));
+        let point = CompressedRistretto([7u8; 32]);
+        let items = vec![([1u8; 32], point), ([2u8; 32], point)];
+        let slots = vec![
+            (Some([2u8; 32]), point),
+            (Some([1u8; 32]), point),
+            (None, point),
+        ];
+        let state = PreparedState::new(secret, items, slots, &PsiConfig::default());
+        assert_eq!(state.hash_order().len(), state.message_points().len());
+        assert_eq!(state.hash_order()[2], None);
+        assert_eq!(state.blinded_point(&[1u8; 32]), Some(&point));
+        assert!(state.blinded_point(&[0u8; 32]).is_none());
     }
 
     #[test]
     fn test_computing_state_new() {
         let secret = random_scalar();
-        let local_map = HashMap::new();
-        let blinded_map = HashMap::new();
         let remote_points = vec![];
-        let state = ComputingState::new(secret, local_map, blinded_map, remote_points);
-        assert!(!state.blinded_map().contains_key(&[0u8; 32]));
+        let state = ComputingState::new(secret, vec![], remote_points);
+        assert!(state.blinded_point(&[0u8; 32]).is_none());
     }
 
     #[test]
     fn test_final_state_new() {
         let map = HashMap::new();
         let state = FinalState::new(map);
-        assert!(!state.hash_to_double_blinded().contains_key(&[0u8; 32]));
+        assert!(!state
+            .hash_to_double_blinded()
+            .contains_key(&ItemId::new([0u8; 32])));
     }
 
     #[test]
@@ -0,0 +1,94 @@
+//! Counters and timings of a protocol run.
+//!
+//! Every [`PsiProtocol`](crate::PsiProtocol) carries a [`PsiStats`] that
+//! each phase adds to and hands on to the next state, so the final state
+//! holds the figures of the whole run. Applications can log or alert on
+//! them without timing the calls themselves.
+//!
+//! # Example
+//! ```ignore
+//! let (alice, alice_double_msg) = alice.compute(bob_msg)?;
+//! let (alice, result) = alice.finalize(bob_double_msg)?;
+//! let stats = alice.stats();
+//! log::info!("psi: {} matches in {:?}", stats.matches, stats.total());
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use std::time::Duration;
+
+/// Bytes of one compressed point.
+pub(crate) const POINT_LEN: usize = 32;
+
+/// Figures of one protocol run, see [`PsiProtocol::stats`](crate::PsiProtocol::stats).
+///
+/// Phases that did not run yet are zero. Statistics are not persisted: a
+/// session restored from a [`SessionStore`](crate::SessionStore) starts
+/// from zero again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PsiStats {
+    /// Time spent hashing and blinding the local items.
+    pub prepare: Duration,
+    /// Time spent checking and double-blinding the remote's points.
+    pub compute: Duration,
+    /// Time spent matching the remote's double-blinded points.
+    pub finalize: Duration,
+    /// Distinct local items.
+    pub local_items: usize,
+    /// Points in the remote's blinded message, padding included.
+    pub remote_points: usize,
+    /// Bytes of points sent and received so far.
+    pub bytes_processed: usize,
+    /// Remote points that were not valid encodings; only lenient mode
+    /// accepts them, replaced with random points.
+    pub invalid_points: usize,
+    /// Items in the intersection.
+    pub matches: usize,
+}
+
+impl PsiStats {
+    /// Time spent in every phase so far.
+    pub fn total(&self) -> Duration {
+        self.prepare + self.compute + self.finalize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::items;
+    use crate::{PsiConfig, PsiProtocol};
+
+    #[test]
+    fn test_stats_accumulate_over_phases() {
+        let alice = PsiProtocol::new(&items(&["apple", "banana", "cherry"])).unwrap();
+        let bob = PsiProtocol::new(&items(&["banana", "cherry", "date", "fig"])).unwrap();
+        assert_eq!(alice.stats().local_items, 3);
+        assert_eq!(alice.stats().bytes_processed, 3 * 32);
+
+        let (alice_msg, bob_msg) = (alice.message(), bob.message());
+        let (alice, alice_double) = alice.compute(bob_msg).unwrap();
+        let (_, bob_double) = bob.compute(alice_msg).unwrap();
+        assert_eq!(alice.stats().remote_points, 4);
+        assert_eq!(alice.stats().finalize, std::time::Duration::ZERO);
+        drop(alice_double);
+
+        let (alice, _) = alice.finalize(bob_double).unwrap();
+        let stats = alice.stats();
+        assert_eq!(stats.matches, 2);
+        assert_eq!(stats.invalid_points, 0);
+        // Our message, their message, our answer and their answer
+        assert_eq!(stats.bytes_processed, (3 + 4 + 4 + 3) * 32);
+        assert!(stats.total() >= stats.prepare);
+    }
+
+    #[test]
+    fn test_stats_count_invalid_points_in_lenient_mode() {
+        let config = PsiConfig::builder().lenient(true).build().unwrap();
+        let alice = PsiProtocol::new_with_config(&items(&["apple"]), config).unwrap();
+        let bob = PsiProtocol::new(&items(&["apple", "banana"])).unwrap();
+
+        let mut bob_msg = bob.message();
+        bob_msg.blinded_points[0].0 = [0xff; 32];
+        let (alice, _) = alice.compute(bob_msg).unwrap();
+        assert_eq!(alice.stats().invalid_points, 1);
+    }
+}
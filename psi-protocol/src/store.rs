@@ -0,0 +1,493 @@
+//! Session state shared between server replicas.
+//!
+//! Behind a load balancer, the blinded message and the double-blinded
+//! answer of one session can reach different replicas. A [`SessionStore`]
+//! keeps each session's state under its id between the two, so whichever
+//! replica receives the next message loads the session, advances it and
+//! saves it back.
+//!
+//! [`PsiSession::to_state_bytes`] serializes the state only, not the
+//! configuration: every replica must restore sessions with the same
+//! [`PsiConfig`] they were started with. The bytes hold the session's
+//! secret scalar, so the store must be as trusted as the replicas
+//! themselves (private network, authenticated, encrypted at rest if
+//! persisted).
+//!
+//! [`MemoryStore`] keeps states in process, for tests and single-node
+//! deployments; `RedisStore` (`redis` feature) shares them through Redis.
+//!
+//! # Format
+//!
+//! ```text
+//! +-------------+-----------+--------------------+
+//! | version: u8 | state: u8 | state fields       |
+//! +-------------+-----------+--------------------+
+//! ```
+//!
+//! Lists are prefixed with a big-endian `u32` count, as in [`wire`](crate::wire).
+
+use crate::config::PsiConfig;
+use crate::error::{PsiError, Result};
+use crate::item_id::ItemId;
+use crate::protocol::PsiProtocol;
+use crate::session::PsiSession;
+use crate::state::{BlindedItems, DoubleBlindedState, FinalState, PreparedState};
+use crate::wire::{read_count, read_point_list, write_count};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::Scalar;
+use std::collections::HashMap;
+use zeroize::Zeroizing;
+
+/// Version of the session state format.
+pub const SESSION_STATE_VERSION: u8 = 1;
+
+const PREPARED: u8 = 0;
+const DOUBLE_BLINDED: u8 = 1;
+const FINAL: u8 = 2;
+
+/// Pluggable storage for session state, keyed by session id.
+///
+/// Implementors only move bytes; [`save`](Self::save) and
+/// [`load`](Self::load) do the (de)serialization.
+///
+/// # Example
+/// ```ignore
+/// // On any replica, for every incoming message of `session_id`
+/// let mut session = store
+///     .load(&session_id, config.clone())?
+///     .ok_or(PsiError::UnexpectedState { operation: "load", state: "unknown session" })?;
+/// let reply = session.on_blinded(msg)?;
+/// store.save(&session_id, &session)?;
+/// # Ok::<(), psi_protocol::PsiError>(())
+/// ```
+pub trait SessionStore {
+    /// Store `state` under `id`, replacing any previous state.
+    ///
+    /// # Errors
+    /// Returns `PsiError::StoreFailed` if the backend fails
+    fn put(&mut self, id: &str, state: &[u8]) -> Result<()>;
+
+    /// Fetch the state stored under `id`, if any.
+    ///
+    /// # Errors
+    /// Returns `PsiError::StoreFailed` if the backend fails
+    fn get(&mut self, id: &str) -> Result<Option<Zeroizing<Vec<u8>>>>;
+
+    /// Remove the state stored under `id`, if any.
+    ///
+    /// # Errors
+    /// Returns `PsiError::StoreFailed` if the backend fails
+    fn delete(&mut self, id: &str) -> Result<()>;
+
+    /// Serialize `session` and store it under `id`.
+    ///
+    /// # Errors
+    /// Same as [`PsiSession::to_state_bytes`] and [`put`](Self::put)
+    fn save(&mut self, id: &str, session: &PsiSession) -> Result<()> {
+        let state = session.to_state_bytes()?;
+        self.put(id, &state)
+    }
+
+    /// Fetch and restore the session stored under `id`.
+    ///
+    /// # Errors
+    /// Same as [`get`](Self::get) and [`PsiSession::from_state_bytes`]
+    fn load(&mut self, id: &str, config: PsiConfig) -> Result<Option<PsiSession>> {
+        self.get(id)?
+            .map(|state| PsiSession::from_state_bytes(&state, config))
+            .transpose()
+    }
+}
+
+/// In-process [`SessionStore`].
+#[derive(Default)]
+pub struct MemoryStore {
+    states: HashMap<String, Zeroizing<Vec<u8>>>,
+}
+
+impl MemoryStore {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of stored sessions.
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Returns true if no session is stored.
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+}
+
+impl std::fmt::Debug for MemoryStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryStore")
+            .field("len", &self.states.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn put(&mut self, id: &str, state: &[u8]) -> Result<()> {
+        self.states
+            .insert(id.to_string(), Zeroizing::new(state.to_vec()));
+        Ok(())
+    }
+
+    fn get(&mut self, id: &str) -> Result<Option<Zeroizing<Vec<u8>>>> {
+        Ok(self.states.get(id).cloned())
+    }
+
+    fn delete(&mut self, id: &str) -> Result<()> {
+        self.states.remove(id);
+        Ok(())
+    }
+}
+
+fn write_items(out: &mut Vec<u8>, items: &[([u8; 32], CompressedRistretto)]) {
+    write_count(out, items.len());
+    for (hash, point) in items {
+        out.extend_from_slice(hash);
+        out.extend_from_slice(point.as_bytes());
+    }
+}
+
+fn write_hash_order(out: &mut Vec<u8>, order: &[Option<[u8; 32]>]) {
+    write_count(out, order.len());
+    for slot in order {
+        out.push(slot.is_some() as u8);
+        out.extend_from_slice(&slot.unwrap_or_default());
+    }
+}
+
+fn write_points(out: &mut Vec<u8>, points: &[CompressedRistretto]) {
+    write_count(out, points.len());
+    for point in points {
+        out.extend_from_slice(point.as_bytes());
+    }
+}
+
+/// Cursor over serialized state.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(PsiError::InvalidEncoding(format!(
+                "Session state truncated: expected {} more bytes, found {}",
+                len,
+                self.0.len()
+            )));
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn array(&mut self) -> Result<[u8; 32]> {
+        let mut out = [0u8; 32];
+        out.copy_from_slice(self.take(32)?);
+        Ok(out)
+    }
+
+    /// Read a count of `record_len`-byte records, checked against the input.
+    fn count(&mut self, record_len: usize) -> Result<usize> {
+        let (count, rest) = read_count(self.0)?;
+        if rest.len() / record_len < count {
+            return Err(PsiError::InvalidEncoding(format!(
+                "Session state announces {} records, found {} bytes",
+                count,
+                rest.len()
+            )));
+        }
+        self.0 = rest;
+        Ok(count)
+    }
+
+    fn secret(&mut self) -> Result<Scalar> {
+        let bytes = Zeroizing::new(self.array()?);
+        Option::from(Scalar::from_canonical_bytes(*bytes)).ok_or_else(|| {
+            PsiError::InvalidEncoding("Non-canonical secret in session state".to_string())
+        })
+    }
+
+    fn items(&mut self) -> Result<BlindedItems> {
+        let count = self.count(64)?;
+        let items: BlindedItems = (0..count)
+            .map(|_| Ok((self.array()?, CompressedRistretto(self.array()?))))
+            .collect::<Result<_>>()?;
+        if !items.windows(2).all(|pair| pair[0].0 < pair[1].0) {
+            return Err(PsiError::InvalidEncoding(
+                "Session state items are not sorted by hash".to_string(),
+            ));
+        }
+        Ok(items)
+    }
+
+    fn hash_order(&mut self) -> Result<Vec<Option<[u8; 32]>>> {
+        let count = self.count(33)?;
+        (0..count)
+            .map(|_| {
+                let flag = self.byte()?;
+                let hash = self.array()?;
+                match flag {
+                    0 => Ok(None),
+                    1 => Ok(Some(hash)),
+                    other => Err(PsiError::InvalidEncoding(format!(
+                        "Invalid slot flag {} in session state",
+                        other
+                    ))),
+                }
+            })
+            .collect()
+    }
+
+    fn points(&mut self) -> Result<Vec<CompressedRistretto>> {
+        let (points, rest) = read_point_list(self.0)?;
+        self.0 = rest;
+        Ok(points)
+    }
+}
+
+impl PsiSession {
+    /// Serialize the session state, to resume it elsewhere with
+    /// [`from_state_bytes`](Self::from_state_bytes).
+    ///
+    /// The bytes contain the secret scalar and are zeroized on drop.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` if the session is aborted or
+    /// poisoned
+    pub fn to_state_bytes(&self) -> Result<Zeroizing<Vec<u8>>> {
+        let mut out = Zeroizing::new(Vec::with_capacity(self.state_len()));
+        out.push(SESSION_STATE_VERSION);
+        match self {
+            PsiSession::Prepared(protocol) => {
+                let state = protocol.state();
+                out.push(PREPARED);
+                out.extend_from_slice(state.secret_scalar().as_bytes());
+                write_items(&mut out, state.blinded_items());
+                write_hash_order(&mut out, state.hash_order());
+                write_points(&mut out, state.message_points());
+            }
+            PsiSession::DoubleBlinded(protocol) => {
+                let state = protocol.state();
+                out.push(DOUBLE_BLINDED);
+                out.extend_from_slice(state.secret_scalar().as_bytes());
+                // The state no longer keeps its blinded items; an empty list
+                // keeps the format unchanged
+                write_items(&mut out, &[]);
+                write_hash_order(&mut out, state.hash_order());
+                write_points(&mut out, state.double_blinded_from_remote());
+            }
+            PsiSession::Final(protocol) => {
+                out.push(FINAL);
+                let mut matches: Vec<_> = protocol
+                    .state()
+                    .double_blinded_map()
+                    .iter()
+                    .map(|(id, point)| (id.to_bytes(), *point))
+                    .collect();
+                matches.sort_unstable_by_key(|(id, _)| *id);
+                write_items(&mut out, &matches);
+            }
+            PsiSession::Aborted(_) | PsiSession::Poisoned => {
+                return Err(PsiError::UnexpectedState {
+                    operation: "to_state_bytes",
+                    state: self.state_name(),
+                })
+            }
+        }
+        Ok(out)
+    }
+
+    /// Exact length of [`to_state_bytes`](Self::to_state_bytes), so the
+    /// secret is never left behind by a reallocation.
+    fn state_len(&self) -> usize {
+        let list = |count: usize, record: usize| 4 + count * record;
+        2 + match self {
+            PsiSession::Prepared(protocol) => {
+                let state = protocol.state();
+                32 + list(state.blinded_items().len(), 64)
+                    + list(state.hash_order().len(), 33)
+                    + list(state.message_points().len(), 32)
+            }
+            PsiSession::DoubleBlinded(protocol) => {
+                let state = protocol.state();
+                32 + list(0, 64)
+                    + list(state.hash_order().len(), 33)
+                    + list(state.double_blinded_from_remote().len(), 32)
+            }
+            PsiSession::Final(protocol) => list(protocol.state().double_blinded_map().len(), 64),
+            PsiSession::Aborted(_) | PsiSession::Poisoned => 0,
+        }
+    }
+
+    /// Restore a session serialized with
+    /// [`to_state_bytes`](Self::to_state_bytes).
+    ///
+    /// `config` must be the configuration the session was started with.
+    ///
+    /// # Errors
+    /// Returns `PsiError::VersionMismatch` if the state has another format
+    /// version, or `PsiError::InvalidEncoding` if it is truncated, has
+    /// trailing bytes or is otherwise malformed
+    pub fn from_state_bytes(bytes: &[u8], config: PsiConfig) -> Result<Self> {
+        let mut reader = Reader(bytes);
+        let version = reader.byte()?;
+        if version != SESSION_STATE_VERSION {
+            return Err(PsiError::VersionMismatch {
+                expected: SESSION_STATE_VERSION,
+                actual: version,
+            });
+        }
+        let session = match reader.byte()? {
+            PREPARED => {
+                let secret = reader.secret()?;
+                let items = reader.items()?;
+                let hash_order = reader.hash_order()?;
+                let message_points = reader.points()?;
+                if hash_order.len() != message_points.len() {
+                    return Err(PsiError::InvalidEncoding(format!(
+                        "Session state has {} slots but {} message points",
+                        hash_order.len(),
+                        message_points.len()
+                    )));
+                }
+                let slots = hash_order.into_iter().zip(message_points).collect();
+                let state = PreparedState::new(secret, items, slots, &config);
+                PsiSession::Prepared(PsiProtocol::from_prepared(state, config))
+            }
+            DOUBLE_BLINDED => {
+                let secret = reader.secret()?;
+                // Items written by older releases are not needed to finalize
+                let _ = reader.items()?;
+                let hash_order = reader.hash_order()?;
+                let remote = reader.points()?;
+                let state = DoubleBlindedState::new(secret, remote, hash_order);
+                PsiSession::DoubleBlinded(PsiProtocol::from_parts(state, config))
+            }
+            FINAL => {
+                let matches = reader
+                    .items()?
+                    .into_iter()
+                    .map(|(id, point)| (ItemId::new(id), point))
+                    .collect();
+                PsiSession::Final(PsiProtocol::from_parts(FinalState::new(matches), config))
+            }
+            other => {
+                return Err(PsiError::InvalidEncoding(format!(
+                    "Unknown session state {}",
+                    other
+                )))
+            }
+        };
+        if !reader.0.is_empty() {
+            return Err(PsiError::InvalidEncoding(format!(
+                "{} trailing bytes after session state",
+                reader.0.len()
+            )));
+        }
+        Ok(session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::session;
+
+    #[test]
+    fn test_replicas_continue_session() {
+        let config = PsiConfig::default();
+        let mut store = MemoryStore::new();
+        let mut client = session(&["apple", "banana"]);
+
+        // Replica A answers the blinded message
+        let server = session(&["banana", "cherry"]);
+        let server_msg = server.message().unwrap();
+        store.save("s1", &server).unwrap();
+        let mut server = store.load("s1", config.clone()).unwrap().unwrap();
+        let server_double = server.on_blinded(client.message().unwrap()).unwrap();
+        store.save("s1", &server).unwrap();
+
+        // Replica B finalizes
+        let client_double = client.on_blinded(server_msg).unwrap();
+        let mut server = store.load("s1", config.clone()).unwrap().unwrap();
+        let server_result = server.on_double_blinded(client_double).unwrap();
+        let client_result = client.on_double_blinded(server_double).unwrap();
+        assert_eq!(
+            server_result.intersection_hashes,
+            client_result.intersection_hashes
+        );
+
+        store.save("s1", &server).unwrap();
+        let restored = store.load("s1", config).unwrap().unwrap();
+        assert!(restored.is_complete());
+        store.delete("s1").unwrap();
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let prepared = session(&["apple", "banana"]);
+        let bytes = prepared.to_state_bytes().unwrap();
+        let restored = PsiSession::from_state_bytes(&bytes, PsiConfig::default()).unwrap();
+        assert_eq!(restored.message().unwrap(), prepared.message().unwrap());
+        assert_eq!(*restored.to_state_bytes().unwrap(), *bytes);
+        assert_eq!(bytes.len(), prepared.state_len());
+    }
+
+    #[test]
+    fn test_double_blinded_state_keeps_no_items() {
+        let mut client = session(&["apple", "banana"]);
+        let server = session(&["banana"]);
+        let server_double = {
+            let mut server = server.clone();
+            server.on_blinded(client.message().unwrap()).unwrap()
+        };
+        client.on_blinded(server.message().unwrap()).unwrap();
+        let bytes = client.to_state_bytes().unwrap();
+        assert_eq!(bytes.len(), client.state_len());
+        assert_eq!(bytes[34..38], [0u8; 4]);
+
+        // States written with their items still restore
+        let mut old = bytes[..34].to_vec();
+        write_items(&mut old, &[([1u8; 32], CompressedRistretto([2u8; 32]))]);
+        old.extend_from_slice(&bytes[38..]);
+        let mut restored = PsiSession::from_state_bytes(&old, PsiConfig::default()).unwrap();
+        assert_eq!(*restored.to_state_bytes().unwrap(), *bytes);
+        assert_eq!(restored.on_double_blinded(server_double).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_rejects_malformed_state() {
+        let bytes = session(&["apple"]).to_state_bytes().unwrap();
+        for len in 0..bytes.len() {
+            assert!(PsiSession::from_state_bytes(&bytes[..len], PsiConfig::default()).is_err());
+        }
+        let mut trailing = bytes.to_vec();
+        trailing.push(0);
+        assert!(matches!(
+            PsiSession::from_state_bytes(&trailing, PsiConfig::default()),
+            Err(PsiError::InvalidEncoding(_))
+        ));
+        let mut version = bytes.to_vec();
+        version[0] = 9;
+        assert!(matches!(
+            PsiSession::from_state_bytes(&version, PsiConfig::default()),
+            Err(PsiError::VersionMismatch { .. })
+        ));
+        assert!(matches!(
+            PsiSession::Poisoned.to_state_bytes(),
+            Err(PsiError::UnexpectedState { .. })
+        ));
+    }
+}
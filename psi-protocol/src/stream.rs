@@ -0,0 +1,222 @@
+//! Lazy blinding of the local set.
+//!
+//! [`PsiProtocol::new`] hashes and blinds every item before the first point
+//! can be sent. [`BlindingStream`] does the same work one item at a time,
+//! so the caller can serialize and send each point while the rest are still
+//! being computed, overlapping CPU and network. Once the stream is
+//! exhausted, [`BlindingStream::finish`] returns the prepared protocol and
+//! the run continues as usual.
+//!
+//! Points are produced in input order, so the remote sees the local items
+//! in the order they were given. Shuffle the items first if that order
+//! carries information. Padding needs the final set size and is not
+//! supported.
+
+use crate::collections::FastSet;
+use crate::config::{Padding, PsiConfig};
+use crate::crypto::{blind_point, hash_item_with, hash_to_point_in, random_scalar};
+use crate::error::{PsiError, Result};
+use crate::item_id::ItemId;
+use crate::protocol::PsiProtocol;
+use crate::secret::SecretScalar;
+use crate::state::PreparedState;
+use curve25519_dalek::ristretto::CompressedRistretto;
+
+/// Iterator over the blinded points of the local set, computed lazily.
+///
+/// Yields one `(ItemId, CompressedRistretto)` pair per unique item. Only
+/// the point goes on the wire; send them in the order they are yielded.
+///
+/// # Example
+/// ```ignore
+/// use psi_protocol::PsiProtocol;
+///
+/// let mut stream = PsiProtocol::stream(&items)?;
+/// for (_, point) in &mut stream {
+///     send_point(point);
+/// }
+/// let alice = stream.finish()?;
+/// let (alice, double_msg) = alice.compute(bob_msg)?;
+/// # Ok::<(), psi_protocol::PsiError>(())
+/// ```
+pub struct BlindingStream<'a> {
+    items: std::slice::Iter<'a, Vec<u8>>,
+    secret: SecretScalar,
+    config: PsiConfig,
+    seen: FastSet<[u8; 32]>,
+    blinded: Vec<([u8; 32], CompressedRistretto)>,
+}
+
+impl PsiProtocol<PreparedState> {
+    /// Blind items lazily with the default configuration.
+    ///
+    /// # Errors
+    /// Same as [`stream_with_config`](Self::stream_with_config)
+    pub fn stream(items: &[Vec<u8>]) -> Result<BlindingStream<'_>> {
+        Self::stream_with_config(items, PsiConfig::default())
+    }
+
+    /// Blind items lazily with a custom configuration.
+    ///
+    /// The message order setting is ignored: points follow the input order.
+    /// Thread settings are ignored as well, since each item is blinded when
+    /// it is pulled from the stream.
+    ///
+    /// # Errors
+    /// Returns `PsiError::EmptyInput` if items is empty and the configuration
+    /// does not allow it,
+    /// `PsiError::LimitExceeded` if items exceeds the configured local limit,
+    /// or `PsiError::InvalidConfig` if the configuration uses padding
+    pub fn stream_with_config(items: &[Vec<u8>], config: PsiConfig) -> Result<BlindingStream<'_>> {
+        config.check_local_set(items.len())?;
+        if config.padding() != Padding::None {
+            return Err(PsiError::InvalidConfig(
+                "Padding is not supported when streaming blinded points".to_string(),
+            ));
+        }
+        Ok(BlindingStream {
+            items: items.iter(),
+            secret: SecretScalar::new(random_scalar()),
+            config,
+            seen: FastSet::with_capacity_and_hasher(items.len(), Default::default()),
+            blinded: Vec::with_capacity(items.len()),
+        })
+    }
+}
+
+impl BlindingStream<'_> {
+    /// Number of points yielded so far.
+    pub fn yielded(&self) -> usize {
+        self.blinded.len()
+    }
+
+    /// Turn the exhausted stream into a prepared protocol.
+    ///
+    /// The prepared protocol's [`message`](PsiProtocol::message) holds the
+    /// yielded points in the order they were yielded.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` if items are left in the stream,
+    /// since the remote would be missing their points
+    pub fn finish(mut self) -> Result<PsiProtocol<PreparedState>> {
+        if self.items.len() > 0 {
+            return Err(PsiError::UnexpectedState {
+                operation: "finish",
+                state: "streaming",
+            });
+        }
+        let blinded = std::mem::take(&mut self.blinded);
+        let slots = blinded
+            .iter()
+            .map(|(hash, point)| (Some(*hash), *point))
+            .collect();
+        let mut blinded_items = blinded;
+        blinded_items.sort_unstable_by_key(|(hash, _)| *hash);
+
+        let state = PreparedState::new(*self.secret.expose(), blinded_items, slots, &self.config);
+        Ok(PsiProtocol::from_prepared(state, self.config.clone()))
+    }
+}
+
+impl Iterator for BlindingStream<'_> {
+    type Item = (ItemId, CompressedRistretto);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for item in self.items.by_ref() {
+            let hash = hash_item_with(self.config.hash(), item);
+            // Duplicates are sent once, as in `PsiProtocol::new`
+            if !self.seen.insert(hash) {
+                continue;
+            }
+            let point = blind_point(
+                &hash_to_point_in(self.config.domain(), &hash),
+                self.secret.expose(),
+            );
+            self.blinded.push((hash, point));
+            return Some((ItemId::new(hash), point));
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.items.len()))
+    }
+}
+
+impl std::fmt::Debug for BlindingStream<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlindingStream")
+            .field("remaining", &self.items.len())
+            .field("yielded", &self.blinded.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items() -> Vec<Vec<u8>> {
+        vec![b"apple".to_vec(), b"banana".to_vec(), b"apple".to_vec()]
+    }
+
+    #[test]
+    fn test_stream_matches_message() {
+        let items = items();
+        let mut stream = PsiProtocol::stream(&items).unwrap();
+        let points: Vec<_> = stream.by_ref().map(|(_, point)| point).collect();
+        assert_eq!(points.len(), 2);
+
+        let alice = stream.finish().unwrap();
+        assert_eq!(alice.message().blinded_points, points);
+    }
+
+    #[test]
+    fn test_streamed_protocol_computes_intersection() {
+        let items = items();
+        let mut stream = PsiProtocol::stream(&items).unwrap();
+        let ids: Vec<_> = stream.by_ref().map(|(id, _)| id).collect();
+        assert_eq!(ids, vec![ItemId::of(b"apple"), ItemId::of(b"banana")]);
+        let alice = stream.finish().unwrap();
+        let bob = PsiProtocol::new(&[b"banana".to_vec(), b"cherry".to_vec()]).unwrap();
+
+        let alice_msg = alice.message();
+        let (alice, alice_double) = alice.compute(bob.message()).unwrap();
+        let (bob, bob_double) = bob.compute(alice_msg).unwrap();
+        let (_, alice_result) = alice.finalize(bob_double).unwrap();
+        let (_, bob_result) = bob.finalize(alice_double).unwrap();
+        assert_eq!(
+            alice_result.intersection_hashes,
+            vec![ItemId::of(b"banana")]
+        );
+        assert_eq!(alice_result, bob_result);
+    }
+
+    #[test]
+    fn test_finish_rejects_unsent_items() {
+        let items = items();
+        let mut stream = PsiProtocol::stream(&items).unwrap();
+        stream.next();
+        assert_eq!(stream.yielded(), 1);
+        assert!(matches!(
+            stream.finish(),
+            Err(PsiError::UnexpectedState { .. })
+        ));
+    }
+
+    #[test]
+    fn test_stream_rejects_padding_and_empty_input() {
+        let config = PsiConfig::builder()
+            .padding(Padding::ToSize(8))
+            .build()
+            .unwrap();
+        assert!(matches!(
+            PsiProtocol::stream_with_config(&items(), config),
+            Err(PsiError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            PsiProtocol::stream(&[]),
+            Err(PsiError::EmptyInput)
+        ));
+    }
+}
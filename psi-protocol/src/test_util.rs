@@ -0,0 +1,19 @@
+//! Fixtures shared by the unit tests.
+
+use crate::session::PsiSession;
+use std::ops::Range;
+
+/// Items `item-<i>` for every `i` in `range`.
+pub(crate) fn numbered(range: Range<u32>) -> Vec<Vec<u8>> {
+    range.map(|i| format!("item-{}", i).into_bytes()).collect()
+}
+
+/// Items from their names.
+pub(crate) fn items(names: &[&str]) -> Vec<Vec<u8>> {
+    names.iter().map(|name| name.as_bytes().to_vec()).collect()
+}
+
+/// Two-round session over the named items.
+pub(crate) fn session(names: &[&str]) -> PsiSession {
+    PsiSession::new(&items(names)).unwrap()
+}
@@ -0,0 +1,272 @@
+//! Time-bucketed PSI for correlating timestamped events.
+//!
+//! Two parties holding event logs (e.g. two SOC teams with IP addresses seen
+//! in their logs) often only care about matches inside the same time window.
+//! [`TimeBuckets`] maps a timestamp to a window index and encodes that index
+//! into the item bytes, and [`BucketedPsi`] runs one independent protocol per
+//! window, so each side only learns which items it shares with the other side
+//! *within the same window* and never the full timeline.
+//!
+//! Both parties must use the same bucket width and agree on the range of
+//! windows to compare. The set of non-empty windows is visible to the peer
+//! (one message per window); configure padding to hide per-window sizes.
+
+use crate::config::PsiConfig;
+use crate::error::{PsiError, Result};
+use crate::item_id::ItemId;
+use crate::messages::{BlindedPointsMessage, DoubleBlindedPointsMessage, PsiResult};
+use crate::protocol::PsiProtocol;
+use crate::state::{DoubleBlindedState, PreparedState, PsiState};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::time::Duration;
+
+/// Fixed-width time windows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeBuckets {
+    width_secs: u64,
+}
+
+impl TimeBuckets {
+    /// Create windows of the given width.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidConfig` if `width` is shorter than one second
+    pub fn new(width: Duration) -> Result<Self> {
+        if width.as_secs() == 0 {
+            return Err(PsiError::InvalidConfig(
+                "Bucket width must be at least one second".to_string(),
+            ));
+        }
+        Ok(Self {
+            width_secs: width.as_secs(),
+        })
+    }
+
+    /// One-hour windows.
+    pub fn hourly() -> Self {
+        Self { width_secs: 3600 }
+    }
+
+    /// Width of a window.
+    pub fn width(&self) -> Duration {
+        Duration::from_secs(self.width_secs)
+    }
+
+    /// Index of the window containing a Unix timestamp in seconds.
+    pub fn bucket_of(&self, timestamp: u64) -> u64 {
+        timestamp / self.width_secs
+    }
+
+    /// Encode an item together with its window index.
+    ///
+    /// The same item in two different windows gives two unrelated hashes.
+    pub fn encode(&self, bucket: u64, item: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(8 + item.len());
+        encoded.extend_from_slice(&bucket.to_be_bytes());
+        encoded.extend_from_slice(item);
+        encoded
+    }
+
+    /// Identifier of an item within a window, matching the bucket's
+    /// [`PsiResult::intersection_hashes`].
+    pub fn item_id(&self, bucket: u64, item: &[u8]) -> ItemId {
+        ItemId::of(&self.encode(bucket, item))
+    }
+
+    /// Group `(timestamp, item)` events by window, keeping the agreed windows.
+    ///
+    /// Returns the encoded items of every non-empty window in `windows`.
+    pub fn partition(
+        &self,
+        events: &[(u64, Vec<u8>)],
+        windows: Range<u64>,
+    ) -> BTreeMap<u64, Vec<Vec<u8>>> {
+        let mut partitioned: BTreeMap<u64, Vec<Vec<u8>>> = BTreeMap::new();
+        for (timestamp, item) in events {
+            let bucket = self.bucket_of(*timestamp);
+            if windows.contains(&bucket) {
+                partitioned
+                    .entry(bucket)
+                    .or_default()
+                    .push(self.encode(bucket, item));
+            }
+        }
+        partitioned
+    }
+}
+
+/// One protocol run per time window.
+///
+/// Mirrors [`PsiProtocol`]: `new` → `messages` → `compute` → `finalize`, with
+/// every message keyed by window index. Windows that only one side has are
+/// skipped, since they cannot intersect.
+///
+/// # Example
+/// ```ignore
+/// use psi_protocol::{BucketedPsi, PsiConfig, TimeBuckets};
+///
+/// let buckets = TimeBuckets::hourly();
+/// let windows = buckets.bucket_of(start)..buckets.bucket_of(end);
+/// let alice = BucketedPsi::new(&alice_events, buckets, windows, PsiConfig::default())?;
+///
+/// let (alice, alice_double) = alice.compute(bob_messages)?;
+/// let results = alice.finalize(bob_double)?;
+/// for (bucket, result) in results {
+///     println!("window {}: {} shared events", bucket, result.len());
+/// }
+/// # Ok::<(), psi_protocol::PsiError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct BucketedPsi<S: PsiState> {
+    runs: BTreeMap<u64, PsiProtocol<S>>,
+}
+
+impl<S: PsiState> BucketedPsi<S> {
+    /// Window indices with a run in progress.
+    pub fn buckets(&self) -> impl Iterator<Item = u64> + '_ {
+        self.runs.keys().copied()
+    }
+}
+
+impl BucketedPsi<PreparedState> {
+    /// Partition `(timestamp, item)` events and prepare one run per window.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::new_with_config`] for each window
+    pub fn new(
+        events: &[(u64, Vec<u8>)],
+        buckets: TimeBuckets,
+        windows: Range<u64>,
+        config: PsiConfig,
+    ) -> Result<Self> {
+        let runs = buckets
+            .partition(events, windows)
+            .into_iter()
+            .map(|(bucket, items)| {
+                PsiProtocol::new_with_config(&items, config.clone()).map(|run| (bucket, run))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { runs })
+    }
+
+    /// Blinded points messages to send, one per non-empty window.
+    pub fn messages(&self) -> BTreeMap<u64, BlindedPointsMessage> {
+        self.runs
+            .iter()
+            .map(|(bucket, run)| (*bucket, run.message()))
+            .collect()
+    }
+
+    /// Double-blind the remote's messages for every window both sides have.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::compute`] for each window
+    pub fn compute(
+        self,
+        mut remote: BTreeMap<u64, BlindedPointsMessage>,
+    ) -> Result<(
+        BucketedPsi<DoubleBlindedState>,
+        BTreeMap<u64, DoubleBlindedPointsMessage>,
+    )> {
+        let mut runs = BTreeMap::new();
+        let mut replies = BTreeMap::new();
+        for (bucket, run) in self.runs {
+            let Some(msg) = remote.remove(&bucket) else {
+                continue;
+            };
+            let (next, reply) = run.compute(msg)?;
+            runs.insert(bucket, next);
+            replies.insert(bucket, reply);
+        }
+        Ok((BucketedPsi { runs }, replies))
+    }
+}
+
+impl BucketedPsi<DoubleBlindedState> {
+    /// Compute the intersection of every window from the remote's replies.
+    ///
+    /// Windows the remote did not answer are left out of the result.
+    ///
+    /// # Errors
+    /// Same as [`PsiProtocol::finalize`] for each window
+    pub fn finalize(
+        self,
+        mut remote: BTreeMap<u64, DoubleBlindedPointsMessage>,
+    ) -> Result<BTreeMap<u64, PsiResult>> {
+        let mut results = BTreeMap::new();
+        for (bucket, run) in self.runs {
+            let Some(msg) = remote.remove(&bucket) else {
+                continue;
+            };
+            let (_, result) = run.finalize(msg)?;
+            results.insert(bucket, result);
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_buckets_encoding() {
+        let buckets = TimeBuckets::hourly();
+        assert_eq!(buckets.bucket_of(3599), 0);
+        assert_eq!(buckets.bucket_of(3600), 1);
+        assert_ne!(buckets.item_id(0, b"ip"), buckets.item_id(1, b"ip"));
+        assert!(matches!(
+            TimeBuckets::new(Duration::from_millis(10)),
+            Err(PsiError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_partition_keeps_agreed_windows() {
+        let buckets = TimeBuckets::new(Duration::from_secs(10)).unwrap();
+        let events = vec![(5, b"a".to_vec()), (15, b"b".to_vec()), (25, b"c".to_vec())];
+        let partitioned = buckets.partition(&events, 1..3);
+        assert_eq!(partitioned.keys().copied().collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(partitioned[&1], vec![buckets.encode(1, b"b")]);
+    }
+
+    #[test]
+    fn test_bucketed_psi_matches_within_windows_only() {
+        let buckets = TimeBuckets::hourly();
+        let alice_events = vec![
+            (100, b"10.0.0.1".to_vec()),
+            (4000, b"10.0.0.2".to_vec()),
+            (8000, b"10.0.0.3".to_vec()),
+        ];
+        // Same IPs, but 10.0.0.2 is seen in a different hour
+        let bob_events = vec![
+            (200, b"10.0.0.1".to_vec()),
+            (8000, b"10.0.0.2".to_vec()),
+            (9000, b"10.0.0.3".to_vec()),
+        ];
+
+        let config = PsiConfig::default();
+        let alice = BucketedPsi::new(&alice_events, buckets, 0..24, config.clone()).unwrap();
+        let bob = BucketedPsi::new(&bob_events, buckets, 0..24, config).unwrap();
+        assert_eq!(alice.buckets().collect::<Vec<_>>(), vec![0, 1, 2]);
+
+        let alice_messages = alice.messages();
+        let (alice, alice_double) = alice.compute(bob.messages()).unwrap();
+        let (bob, bob_double) = bob.compute(alice_messages).unwrap();
+        // Window 1 only exists on Alice's side and is skipped
+        assert_eq!(alice.buckets().collect::<Vec<_>>(), vec![0, 2]);
+
+        let alice_results = alice.finalize(bob_double).unwrap();
+        let bob_results = bob.finalize(alice_double).unwrap();
+        assert_eq!(
+            alice_results[&0].intersection_hashes,
+            vec![buckets.item_id(0, b"10.0.0.1")]
+        );
+        assert_eq!(
+            alice_results[&2].intersection_hashes,
+            vec![buckets.item_id(2, b"10.0.0.3")]
+        );
+        assert_eq!(bob_results.values().map(PsiResult::len).sum::<usize>(), 2);
+    }
+}
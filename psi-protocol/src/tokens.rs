@@ -0,0 +1,219 @@
+//! Opaque intersection tokens.
+//!
+//! A [`PsiResult`] names matched items by their [`ItemId`], the truncated
+//! hash of the item. For low-entropy items (phone numbers, short codes)
+//! anyone who sees that hash can recover the item by hashing candidates.
+//! [`TokenKey::tokenize`] replaces each hash with an [`OpaqueToken`], a MAC
+//! of the hash under a key that never leaves the local party, so the result
+//! can be handed downstream without exposing the items. Only the holder of
+//! the key can map tokens back with [`TokenizedResult::match_indices`].
+//!
+//! Use a fresh key per session so tokens from two sessions cannot be
+//! joined; keep it as long as tokens must be mapped back.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::TokenKey;
+//!
+//! let (_, result) = alice_intermediate.finalize(bob_double_msg)?;
+//! let key = TokenKey::random();
+//! let tokens = key.tokenize(result);
+//! publish(&tokens.tokens);
+//!
+//! // Later, with the same key
+//! let shared = tokens.match_items(&key, HashAlgorithm::default(), &alice_items);
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::config::HashAlgorithm;
+use crate::item_id::ItemId;
+use crate::messages::PsiResult;
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+use std::collections::HashSet;
+use zeroize::Zeroize;
+
+const TOKEN_TAG: &[u8] = b"psi-sync/opaque-token/v1";
+
+/// Session-scoped key turning item hashes into opaque tokens.
+///
+/// Never shared: tokens are only useful downstream because they cannot be
+/// recomputed without it. Redacted from `Debug` output and zeroized on drop.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TokenKey {
+    key: [u8; 32],
+}
+
+/// A salted stand-in for a matched item's hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OpaqueToken(pub [u8; 32]);
+
+/// A [`PsiResult`] with every item hash replaced by its token.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TokenizedResult {
+    /// Tokens of the elements in the intersection, in the result's order
+    pub tokens: Vec<OpaqueToken>,
+}
+
+impl TokenKey {
+    /// Create a key from stored bytes, to map tokens back later.
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { key }
+    }
+
+    /// Create a fresh key from the OS random number generator.
+    pub fn random() -> Self {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        Self { key }
+    }
+
+    /// Get the raw key bytes, to store them next to the tokens' owner.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.key
+    }
+
+    /// Token of an item identifier.
+    pub fn token(&self, id: &ItemId) -> OpaqueToken {
+        let mut input = Vec::with_capacity(TOKEN_TAG.len() + 32);
+        input.extend_from_slice(TOKEN_TAG);
+        input.extend_from_slice(id.as_bytes());
+        // HKDF-Extract is HMAC with the salt as key
+        let (tag, _) = Hkdf::<Sha256>::extract(Some(&self.key), &input);
+        OpaqueToken(tag.into())
+    }
+
+    /// Token of a raw item hashed with `algorithm`.
+    pub fn token_of(&self, algorithm: HashAlgorithm, item: &[u8]) -> OpaqueToken {
+        self.token(&ItemId::of_with(algorithm, item))
+    }
+
+    /// Replace the hashes of `result` with tokens.
+    ///
+    /// Consumes the result so the raw hashes and the double-blinded points
+    /// are not kept alongside the tokens.
+    pub fn tokenize(&self, result: PsiResult) -> TokenizedResult {
+        TokenizedResult {
+            tokens: result
+                .intersection_hashes
+                .iter()
+                .map(|id| self.token(id))
+                .collect(),
+        }
+    }
+}
+
+impl std::fmt::Debug for TokenKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenKey").finish_non_exhaustive()
+    }
+}
+
+impl Drop for TokenKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl OpaqueToken {
+    /// Get the raw token bytes.
+    pub const fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl TokenizedResult {
+    /// Returns the number of elements in the intersection.
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Returns true if the intersection is empty.
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Returns the caller's items that are in the intersection.
+    ///
+    /// Needs the key the result was tokenized with and the hash algorithm of
+    /// the run; see [`PsiResult::match_items`].
+    pub fn match_items<'a>(
+        &self,
+        key: &TokenKey,
+        algorithm: HashAlgorithm,
+        items: &'a [Vec<u8>],
+    ) -> Vec<&'a [u8]> {
+        self.match_indices(key, algorithm, items)
+            .into_iter()
+            .map(|index| items[index].as_slice())
+            .collect()
+    }
+
+    /// Returns the indices into `items` of the items in the intersection.
+    pub fn match_indices(
+        &self,
+        key: &TokenKey,
+        algorithm: HashAlgorithm,
+        items: &[Vec<u8>],
+    ) -> Vec<usize> {
+        let tokens: HashSet<&OpaqueToken> = self.tokens.iter().collect();
+        items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| tokens.contains(&key.token_of(algorithm, item)))
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local::run_local_psi;
+
+    #[test]
+    fn test_tokens_map_back_to_local_items() {
+        let alice = vec![b"555-0101".to_vec(), b"555-0102".to_vec()];
+        let bob = vec![b"555-0102".to_vec(), b"555-0103".to_vec()];
+        let (result, _) = run_local_psi(&alice, &bob).unwrap();
+
+        let key = TokenKey::random();
+        let tokens = key.tokenize(result.clone());
+        assert_eq!(tokens.len(), 1);
+        assert_ne!(
+            tokens.tokens[0].as_bytes(),
+            result.intersection_hashes[0].as_bytes()
+        );
+        assert_eq!(
+            tokens.match_items(&key, HashAlgorithm::default(), &alice),
+            vec![b"555-0102".as_slice()]
+        );
+    }
+
+    #[test]
+    fn test_tokens_are_session_scoped() {
+        let id = ItemId::of(b"555-0102");
+        let (first, second) = (TokenKey::random(), TokenKey::random());
+        assert_ne!(first.token(&id), second.token(&id));
+        assert_eq!(
+            first.token(&id),
+            TokenKey::new(*first.as_bytes()).token(&id)
+        );
+
+        let tokens = first.tokenize(PsiResult::new(vec![id], Default::default()));
+        let items = vec![b"555-0102".to_vec()];
+        assert!(tokens
+            .match_indices(&second, HashAlgorithm::default(), &items)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_debug_redacts_key() {
+        let key = TokenKey::new([7u8; 32]);
+        assert_eq!(format!("{key:?}"), "TokenKey { .. }");
+    }
+}
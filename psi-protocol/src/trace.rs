@@ -0,0 +1,199 @@
+//! Human-readable trace of a protocol run.
+//!
+//! Set a [`Tracer`] with [`PsiConfigBuilder::trace`](crate::PsiConfigBuilder::trace)
+//! and every phase reports what it did as a [`TraceEvent`]: how many points
+//! came in and went out, which code path ran, what was rejected and why.
+//! Points are shortened to their first bytes, enough to follow one across
+//! both parties' traces. This is meant for learning how the protocol works
+//! and for debugging integrations, not for production logs.
+//!
+//! Secrets never reach the tracer: it only sees counts, decisions and
+//! points that are sent or received anyway. Item hashes are left out too,
+//! since hashes of guessable items reveal them.
+//!
+//! Requires the `trace` feature.
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::trace::Tracer;
+//!
+//! let config = PsiConfig::builder().trace(Tracer::stderr()).build()?;
+//! let alice = PsiProtocol::new_with_config(&items, config)?;
+//! // psi [prepare] blind: items=3 padding=0 order=Shuffled sent=[1c5e09a4…, 7ab2c3d0…, +1 more]
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::error::Phase;
+use curve25519_dalek::ristretto::CompressedRistretto;
+use std::fmt;
+use std::sync::Arc;
+
+/// Number of leading bytes shown for each point.
+const POINT_PREFIX_LEN: usize = 4;
+
+/// Number of points shown for a list before summarizing the rest.
+const LISTED_POINTS: usize = 3;
+
+/// One step of a protocol run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// Phase the step belongs to.
+    pub phase: Phase,
+    /// Short name of the step, e.g. `double-blind`.
+    pub step: &'static str,
+    /// What the step saw and decided, as `(name, value)` pairs.
+    pub fields: Vec<(&'static str, String)>,
+}
+
+impl TraceEvent {
+    /// Value of the field called `name`, if present.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "psi [{}] {}:", self.phase, self.step)?;
+        for (name, value) in &self.fields {
+            write!(f, " {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Receiver of [`TraceEvent`]s, shared by every session built from a
+/// configuration.
+///
+/// Called synchronously from the protocol methods, so it should be quick.
+#[derive(Clone)]
+pub struct Tracer(Arc<dyn Fn(&TraceEvent) + Send + Sync>);
+
+impl Tracer {
+    /// Hand every event to `on_event`.
+    pub fn new(on_event: impl Fn(&TraceEvent) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(on_event))
+    }
+
+    /// Print every event to standard error.
+    pub fn stderr() -> Self {
+        Self::new(|event| eprintln!("{}", event))
+    }
+
+    pub(crate) fn emit(&self, event: &TraceEvent) {
+        (self.0)(event)
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Tracer")
+    }
+}
+
+/// Tracers compare equal when they are clones of each other.
+impl PartialEq for Tracer {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Tracer {}
+
+/// Shortened hex form of a point, e.g. `1c5e09a4…`.
+pub(crate) fn point(point: &CompressedRistretto) -> String {
+    let mut out = String::with_capacity(POINT_PREFIX_LEN * 2 + 3);
+    for byte in &point.as_bytes()[..POINT_PREFIX_LEN] {
+        out.push_str(&format!("{:02x}", byte));
+    }
+    out.push('…');
+    out
+}
+
+/// The first points of a list, e.g. `[1c5e09a4…, 7ab2c3d0…, +8 more]`.
+pub(crate) fn points(points: &[CompressedRistretto]) -> String {
+    let mut listed: Vec<String> = points.iter().take(LISTED_POINTS).map(point).collect();
+    if points.len() > LISTED_POINTS {
+        listed.push(format!("+{} more", points.len() - LISTED_POINTS));
+    }
+    format!("[{}]", listed.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PsiConfig;
+    use crate::protocol::PsiProtocol;
+    use std::sync::Mutex;
+
+    fn collect() -> (Tracer, Arc<Mutex<Vec<TraceEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        let tracer = Tracer::new(move |event| sink.lock().unwrap().push(event.clone()));
+        (tracer, events)
+    }
+
+    #[test]
+    fn test_trace_follows_every_phase() {
+        let (tracer, events) = collect();
+        let config = PsiConfig::builder().trace(tracer).build().unwrap();
+        let alice_items = vec![b"apple".to_vec(), b"banana".to_vec()];
+        let bob_items = vec![b"banana".to_vec(), b"cherry".to_vec()];
+        let alice = PsiProtocol::new_with_config(&alice_items, config).unwrap();
+        let bob = PsiProtocol::new(&bob_items).unwrap();
+
+        let alice_msg = alice.message();
+        let (alice, _) = alice.compute(bob.message()).unwrap();
+        let (_, bob_double) = bob.compute(alice_msg).unwrap();
+        let (_, result) = alice.finalize(bob_double).unwrap();
+        assert_eq!(result.intersection_hashes.len(), 1);
+
+        let events = events.lock().unwrap();
+        let steps: Vec<_> = events
+            .iter()
+            .map(|event| (event.phase, event.step))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                (Phase::Prepare, "blind"),
+                (Phase::Compute, "double-blind"),
+                (Phase::Finalize, "match"),
+            ]
+        );
+        assert_eq!(events[0].field("items"), Some("2"));
+        assert_eq!(events[1].field("invalid"), Some("0"));
+        assert_eq!(events[2].field("matches"), Some("1"));
+    }
+
+    #[test]
+    fn test_trace_reports_rejections_without_secrets() {
+        let (tracer, events) = collect();
+        let config = PsiConfig::builder().trace(tracer).build().unwrap();
+        let alice = PsiProtocol::new_with_config(&[b"apple".to_vec()], config).unwrap();
+        let secret = point(&CompressedRistretto(alice.state().secret().to_bytes()));
+        let secret = secret.trim_end_matches('…');
+        let mut bob_msg = PsiProtocol::new(&[b"apple".to_vec()]).unwrap().message();
+        bob_msg.blinded_points[0] = CompressedRistretto([0xff; 32]);
+        assert!(alice.compute(bob_msg).is_err());
+
+        let events = events.lock().unwrap();
+        let rejected = events.last().unwrap();
+        assert_eq!(rejected.step, "rejected");
+        assert!(rejected.to_string().contains("Invalid point"));
+        for event in events.iter() {
+            assert!(!event.to_string().contains(secret));
+        }
+    }
+
+    #[test]
+    fn test_point_lists_are_shortened() {
+        let list = vec![CompressedRistretto([0xab; 32]); 5];
+        assert_eq!(point(&list[0]), "abababab…");
+        assert_eq!(points(&list), "[abababab…, abababab…, abababab…, +2 more]");
+        assert_eq!(points(&[]), "[]");
+    }
+}
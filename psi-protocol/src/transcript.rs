@@ -0,0 +1,496 @@
+//! Session transcripts bound to a caller-supplied context.
+//!
+//! The blinded points carry no indication of the session they belong to, so
+//! on their own nothing stops an attacker from replaying or splicing
+//! messages between two sessions. A [`Transcript`] hashes a
+//! [`SessionContext`] (application id, channel binding token, peer ids)
+//! together with every message exchanged, and both parties finish by
+//! swapping a [`ConfirmationMessage`]. The intersection should only be
+//! trusted once the remote's confirmation verifies.
+//!
+//! The confirmation tag is keyed with the channel binding token and the
+//! optional resumption key. With a TLS
+//! exporter value as the token, an attacker who does not hold both TLS
+//! endpoints' secrets cannot compute the tag; a man-in-the-middle running
+//! two separate TLS connections sees two different tokens and fails
+//! confirmation on both sides. Without a secret token the tag only detects
+//! accidental mix-ups, not an active attacker.
+//!
+//! Transport security and protocol security meet through
+//! [`SessionContext::identities`]: each party's long-term public key (a
+//! libp2p `PeerId`, a TLS certificate hash) is hashed into the transcript,
+//! and [`PsiProtocol::finalize_verified`] refuses to produce a result unless
+//! the remote's claimed key is the one the transport authenticated, see
+//! [`PeerIdentity`].
+//!
+//! # Example
+//! ```ignore
+//! use psi_protocol::{SessionContext, Transcript};
+//!
+//! let context = SessionContext::new("inventory-sync")
+//!     .channel_binding(&tls_exporter)
+//!     .peers(b"alice", b"bob");
+//! let mut transcript = Transcript::new(&context);
+//! transcript.append_blinded(&alice_msg, &bob_msg);
+//! transcript.append_double_blinded(&alice_double_msg, &bob_double_msg);
+//! send(transcript.confirmation()?);
+//! transcript.verify(&receive())?;
+//! # Ok::<(), psi_protocol::PsiError>(())
+//! ```
+
+use crate::error::{PsiError, Result};
+use crate::messages::{
+    BlindedPointsMessage, ConfirmationMessage, DoubleBlindedPointsMessage, PsiResult,
+};
+use crate::protocol::PsiProtocol;
+use crate::state::{DoubleBlindedState, FinalState};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+
+const TRANSCRIPT_TAG: &[u8] = b"psi-sync/transcript/v1";
+const CONFIRM_FIRST: &[u8] = b"psi-sync/confirm/first";
+const CONFIRM_SECOND: &[u8] = b"psi-sync/confirm/second";
+
+/// Identity of the remote as authenticated by the transport.
+///
+/// Implement it on the transport's connection type, returning the remote's
+/// long-term public key (or its hash) in the same encoding that was passed
+/// to [`SessionContext::identities`]. Byte slices and arrays implement it
+/// directly for transports that expose the key as bytes.
+pub trait PeerIdentity {
+    /// The authenticated remote key, or `None` if the transport did not
+    /// authenticate the remote.
+    fn authenticated_peer(&self) -> Option<&[u8]>;
+}
+
+impl PeerIdentity for [u8] {
+    fn authenticated_peer(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+}
+
+impl PeerIdentity for Vec<u8> {
+    fn authenticated_peer(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+}
+
+impl<const N: usize> PeerIdentity for [u8; N] {
+    fn authenticated_peer(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+}
+
+/// Context a session is bound to.
+///
+/// Both parties must build the same context. Peer ids are order-independent,
+/// so each side can pass its own id first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionContext {
+    application: Vec<u8>,
+    channel_binding: Vec<u8>,
+    peers: Vec<Vec<u8>>,
+    local_identity: Vec<u8>,
+    remote_identity: Vec<u8>,
+    resumption: Vec<u8>,
+}
+
+impl SessionContext {
+    /// Start a context for an application.
+    pub fn new(application: impl AsRef<[u8]>) -> Self {
+        Self {
+            application: application.as_ref().to_vec(),
+            ..Self::default()
+        }
+    }
+
+    /// Bind the session to a channel, e.g. with a TLS exporter value.
+    ///
+    /// The token also keys the confirmation tag, see the module docs.
+    pub fn channel_binding(mut self, token: impl AsRef<[u8]>) -> Self {
+        self.channel_binding = token.as_ref().to_vec();
+        self
+    }
+
+    /// Key the confirmation with a secret carried over from an earlier
+    /// session, see [`SessionRatchet`](crate::SessionRatchet).
+    ///
+    /// Unlike the other fields it is not hashed into the transcript, only
+    /// used as key material.
+    pub fn resumption_key(mut self, key: impl AsRef<[u8]>) -> Self {
+        self.resumption = key.as_ref().to_vec();
+        self
+    }
+
+    /// Bind the session to the identities of both peers.
+    pub fn peers(mut self, local: impl AsRef<[u8]>, remote: impl AsRef<[u8]>) -> Self {
+        let mut peers = vec![local.as_ref().to_vec(), remote.as_ref().to_vec()];
+        peers.sort();
+        self.peers = peers;
+        self
+    }
+
+    /// Bind the session to the long-term public keys of both peers.
+    ///
+    /// Both keys are hashed into the transcript, and `remote` is the
+    /// identity [`PsiProtocol::finalize_verified`] checks against the
+    /// transport.
+    pub fn identities(mut self, local: impl AsRef<[u8]>, remote: impl AsRef<[u8]>) -> Self {
+        self.local_identity = local.as_ref().to_vec();
+        self.remote_identity = remote.as_ref().to_vec();
+        self
+    }
+}
+
+/// Which confirmation label each party uses, decided by the blinded messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    First,
+    Second,
+    /// The remote sent our own message back.
+    Reflected,
+}
+
+/// Running hash of a session's context and messages.
+///
+/// Messages are appended in pairs of (sent, received); both parties end up
+/// with the same hash regardless of which side they are on.
+#[derive(Clone)]
+pub struct Transcript {
+    hasher: Sha256,
+    key: Vec<u8>,
+    remote_identity: Vec<u8>,
+    role: Option<Role>,
+}
+
+impl Transcript {
+    /// Start a transcript bound to `context`.
+    pub fn new(context: &SessionContext) -> Self {
+        let mut hasher = Sha256::new().chain_update(TRANSCRIPT_TAG);
+        absorb(&mut hasher, &context.application);
+        absorb(&mut hasher, &context.channel_binding);
+        hasher.update((context.peers.len() as u64).to_be_bytes());
+        for peer in &context.peers {
+            absorb(&mut hasher, peer);
+        }
+        let (low, high) = if context.local_identity <= context.remote_identity {
+            (&context.local_identity, &context.remote_identity)
+        } else {
+            (&context.remote_identity, &context.local_identity)
+        };
+        absorb(&mut hasher, low);
+        absorb(&mut hasher, high);
+        let mut key = Vec::new();
+        for secret in [&context.channel_binding, &context.resumption] {
+            key.extend_from_slice(&(secret.len() as u64).to_be_bytes());
+            key.extend_from_slice(secret);
+        }
+        Self {
+            hasher,
+            key,
+            remote_identity: context.remote_identity.clone(),
+            role: None,
+        }
+    }
+
+    /// Append the exchanged blinded points messages.
+    pub fn append_blinded(&mut self, sent: &BlindedPointsMessage, received: &BlindedPointsMessage) {
+        let sent = digest_points(&sent.blinded_points);
+        let received = digest_points(&received.blinded_points);
+        self.role = Some(match sent.cmp(&received) {
+            std::cmp::Ordering::Less => Role::First,
+            std::cmp::Ordering::Greater => Role::Second,
+            std::cmp::Ordering::Equal => Role::Reflected,
+        });
+        self.append_pair(b"blinded", sent, received);
+    }
+
+    /// Append the exchanged double-blinded points messages.
+    pub fn append_double_blinded(
+        &mut self,
+        sent: &DoubleBlindedPointsMessage,
+        received: &DoubleBlindedPointsMessage,
+    ) {
+        let sent = digest_points(&sent.double_blinded_points);
+        let received = digest_points(&received.double_blinded_points);
+        self.append_pair(b"double-blinded", sent, received);
+    }
+
+    /// Our confirmation tag, to send to the remote.
+    ///
+    /// # Errors
+    /// Returns `PsiError::UnexpectedState` if the blinded messages were not
+    /// appended yet
+    pub fn confirmation(&self) -> Result<ConfirmationMessage> {
+        match self.role {
+            Some(Role::Second) => Ok(self.tag(CONFIRM_SECOND)),
+            Some(_) => Ok(self.tag(CONFIRM_FIRST)),
+            None => Err(self.unexpected("confirmation")),
+        }
+    }
+
+    /// Check the remote's confirmation tag.
+    ///
+    /// # Errors
+    /// Returns `PsiError::CryptoError` if the tag does not match our view of
+    /// the session or the remote reflected our own messages, and
+    /// `PsiError::UnexpectedState` if the blinded messages were not
+    /// appended yet
+    pub fn verify(&self, remote: &ConfirmationMessage) -> Result<()> {
+        let expected = match self.role {
+            Some(Role::First) => self.tag(CONFIRM_SECOND),
+            Some(Role::Second) => self.tag(CONFIRM_FIRST),
+            Some(Role::Reflected) => {
+                return Err(PsiError::CryptoError(
+                    "Remote sent our own blinded points back".to_string(),
+                ))
+            }
+            None => return Err(self.unexpected("verify")),
+        };
+        let diff = expected
+            .tag
+            .iter()
+            .zip(&remote.tag)
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        if diff != 0 {
+            return Err(PsiError::CryptoError(
+                "Transcript confirmation failed".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check that the transport authenticated the remote identity bound in
+    /// the context.
+    ///
+    /// # Errors
+    /// Returns `PsiError::CryptoError` if the context has no remote
+    /// identity, the transport authenticated none, or they differ
+    pub fn check_peer<P: PeerIdentity + ?Sized>(&self, transport: &P) -> Result<()> {
+        if self.remote_identity.is_empty() {
+            return Err(PsiError::CryptoError(
+                "No remote identity bound to the session".to_string(),
+            ));
+        }
+        let authenticated = transport.authenticated_peer().ok_or_else(|| {
+            PsiError::CryptoError("Transport did not authenticate the remote".to_string())
+        })?;
+        if authenticated != self.remote_identity.as_slice() {
+            return Err(PsiError::CryptoError(
+                "Remote identity does not match the authenticated peer".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn append_pair(&mut self, label: &[u8], sent: [u8; 32], received: [u8; 32]) {
+        let (low, high) = if sent <= received {
+            (sent, received)
+        } else {
+            (received, sent)
+        };
+        absorb(&mut self.hasher, label);
+        self.hasher.update(low);
+        self.hasher.update(high);
+    }
+
+    fn tag(&self, label: &[u8]) -> ConfirmationMessage {
+        let transcript_hash = self.hasher.clone().finalize();
+        let mut tag = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(&transcript_hash), &self.key)
+            .expand(label, &mut tag)
+            .expect("valid length");
+        ConfirmationMessage::new(tag)
+    }
+
+    fn unexpected(&self, operation: &'static str) -> PsiError {
+        PsiError::UnexpectedState {
+            operation,
+            state: "no blinded messages",
+        }
+    }
+}
+
+impl std::fmt::Debug for Transcript {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Transcript")
+            .field("role", &self.role)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Transcript {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl PsiProtocol<DoubleBlindedState> {
+    /// Finalize only if the session is bound to the authenticated peer.
+    ///
+    /// Checks, in order, that `transport` authenticated the remote identity
+    /// of the transcript's context and that the remote's confirmation
+    /// verifies, then finalizes as usual. Append both message pairs to the
+    /// transcript before calling.
+    ///
+    /// # Errors
+    /// Returns the errors of [`Transcript::check_peer`],
+    /// [`Transcript::verify`] and [`finalize`](Self::finalize)
+    ///
+    /// # Example
+    /// ```ignore
+    /// let context = SessionContext::new("inventory-sync")
+    ///     .identities(&my_peer_id, &claimed_peer_id);
+    /// // ...run the exchange and append both message pairs...
+    /// let (_, result) =
+    ///     alice.finalize_verified(bob_double_msg, &transcript, &bob_confirmation, &connection)?;
+    /// # Ok::<(), psi_protocol::PsiError>(())
+    /// ```
+    pub fn finalize_verified<P: PeerIdentity + ?Sized>(
+        self,
+        remote_msg: DoubleBlindedPointsMessage,
+        transcript: &Transcript,
+        confirmation: &ConfirmationMessage,
+        transport: &P,
+    ) -> Result<(PsiProtocol<FinalState>, PsiResult)> {
+        transcript.check_peer(transport)?;
+        transcript.verify(confirmation)?;
+        self.finalize(remote_msg)
+    }
+}
+
+/// Hash a length-prefixed field.
+fn absorb(hasher: &mut Sha256, field: &[u8]) {
+    hasher.update((field.len() as u64).to_be_bytes());
+    hasher.update(field);
+}
+
+/// Digest of a point list, in message order.
+fn digest_points(points: &[CompressedRistretto]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update((points.len() as u64).to_be_bytes());
+    for point in points {
+        hasher.update(point.as_bytes());
+    }
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the protocol and return both parties' transcripts.
+    fn run(
+        alice_context: &SessionContext,
+        bob_context: &SessionContext,
+    ) -> (Transcript, Transcript) {
+        let alice = PsiProtocol::new(&[b"apple".to_vec(), b"banana".to_vec()]).unwrap();
+        let bob = PsiProtocol::new(&[b"banana".to_vec(), b"cherry".to_vec()]).unwrap();
+        let (alice_msg, bob_msg) = (alice.message(), bob.message());
+        let (_, alice_double) = alice.compute(bob_msg.clone()).unwrap();
+        let (_, bob_double) = bob.compute(alice_msg.clone()).unwrap();
+
+        let mut alice_transcript = Transcript::new(alice_context);
+        alice_transcript.append_blinded(&alice_msg, &bob_msg);
+        alice_transcript.append_double_blinded(&alice_double, &bob_double);
+        let mut bob_transcript = Transcript::new(bob_context);
+        bob_transcript.append_blinded(&bob_msg, &alice_msg);
+        bob_transcript.append_double_blinded(&bob_double, &alice_double);
+        (alice_transcript, bob_transcript)
+    }
+
+    fn context() -> SessionContext {
+        SessionContext::new("test-app")
+            .channel_binding([7u8; 32])
+            .peers("alice", "bob")
+    }
+
+    #[test]
+    fn test_matching_contexts_confirm() {
+        // Peer order does not matter
+        let bob_context = SessionContext::new("test-app")
+            .channel_binding([7u8; 32])
+            .peers("bob", "alice");
+        let (alice, bob) = run(&context(), &bob_context);
+
+        let alice_tag = alice.confirmation().unwrap();
+        let bob_tag = bob.confirmation().unwrap();
+        assert_ne!(alice_tag, bob_tag);
+        bob.verify(&alice_tag).unwrap();
+        alice.verify(&bob_tag).unwrap();
+        // A reflected tag is rejected
+        assert!(alice.verify(&alice_tag).is_err());
+    }
+
+    #[test]
+    fn test_different_contexts_fail() {
+        for other in [
+            SessionContext::new("other-app")
+                .channel_binding([7u8; 32])
+                .peers("alice", "bob"),
+            context().channel_binding([8u8; 32]),
+            context().peers("alice", "mallory"),
+            context().resumption_key([1u8; 32]),
+        ] {
+            let (alice, bob) = run(&context(), &other);
+            assert!(matches!(
+                bob.verify(&alice.confirmation().unwrap()),
+                Err(PsiError::CryptoError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_reflected_and_missing_messages() {
+        let msg = PsiProtocol::new(&[b"apple".to_vec()]).unwrap().message();
+        let mut transcript = Transcript::new(&context());
+        assert!(matches!(
+            transcript.confirmation(),
+            Err(PsiError::UnexpectedState { .. })
+        ));
+
+        transcript.append_blinded(&msg, &msg);
+        let tag = transcript.confirmation().unwrap();
+        assert!(matches!(
+            transcript.verify(&tag),
+            Err(PsiError::CryptoError(_))
+        ));
+    }
+
+    #[test]
+    fn test_finalize_verified_checks_identity() {
+        let alice_context = SessionContext::new("test-app").identities("alice-key", "bob-key");
+        let bob_context = SessionContext::new("test-app").identities("bob-key", "alice-key");
+        let alice = PsiProtocol::new(&[b"apple".to_vec(), b"banana".to_vec()]).unwrap();
+        let bob = PsiProtocol::new(&[b"banana".to_vec()]).unwrap();
+        let (alice_msg, bob_msg) = (alice.message(), bob.message());
+        let (alice, alice_double) = alice.compute(bob_msg.clone()).unwrap();
+        let (_, bob_double) = bob.compute(alice_msg.clone()).unwrap();
+
+        let mut alice_transcript = Transcript::new(&alice_context);
+        alice_transcript.append_blinded(&alice_msg, &bob_msg);
+        alice_transcript.append_double_blinded(&alice_double, &bob_double);
+        let mut bob_transcript = Transcript::new(&bob_context);
+        bob_transcript.append_blinded(&bob_msg, &alice_msg);
+        bob_transcript.append_double_blinded(&bob_double, &alice_double);
+        let bob_tag = bob_transcript.confirmation().unwrap();
+
+        assert!(alice_transcript.check_peer(b"mallory-key").is_err());
+        assert!(Transcript::new(&context()).check_peer(b"bob-key").is_err());
+        let (_, result) = alice
+            .finalize_verified(bob_double, &alice_transcript, &bob_tag, b"bob-key")
+            .unwrap();
+        assert_eq!(result.intersection_hashes.len(), 1);
+    }
+
+    #[test]
+    fn test_identities_bind_transcript() {
+        let (alice, bob) = run(
+            &context().identities("alice-key", "bob-key"),
+            &context().identities("bob-key", "mallory-key"),
+        );
+        assert!(bob.verify(&alice.confirmation().unwrap()).is_err());
+    }
+}
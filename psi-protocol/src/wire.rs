@@ -0,0 +1,1150 @@
+//! Compact binary wire format for protocol messages.
+//!
+//! Every frame has the same layout:
+//!
+//! ```text
+//! +---------+------+-----------------+----------------------+
+//! | version | kind | count (u32, BE) | count * 32 bytes     |
+//! +---------+------+-----------------+----------------------+
+//! ```
+//!
+//! Message kinds carrying more than one list of points (such as the one-round
+//! response) append further `count | points` blocks after the first one. A
+//! cardinality announcement carries only the `count` field and no points; an
+//! abort carries its reason code in place of the count. An error report
+//! carries its report code in place of the count, followed by a fixed body
+//! whose unused fields are zero:
+//!
+//! ```text
+//! +---------------------+-------------------+--------------------+
+//! | phase or limit (u8) | first value (u64) | second value (u64) |
+//! +---------------------+-------------------+--------------------+
+//! ```
+//!
+//! A points message (blinded, double-blinded or one-round response) with a
+//! pre-shared key MAC sets the high bit of `kind` and ends with an
+//! authentication block:
+//!
+//! ```text
+//! +--------------------+----------+
+//! | key id (u32, BE)   | 32 bytes |
+//! +--------------------+----------+
+//! ```
+//!
+//! A points message carrying a [`ParameterDigest`] sets bit `0x40` of
+//! `kind` and has the 32-byte digest right after its points, before any
+//! authentication block.
+//!
+//! Every frame ends with a CRC32C (Castagnoli) checksum of all the bytes
+//! before it, as a big-endian `u32`. Decoding checks it before anything else
+//! past the version byte, so a frame corrupted by an unreliable transport
+//! fails with `PsiError::ChecksumMismatch` instead of an invalid point or a
+//! garbled count further down. The checksum only catches accidents; use a
+//! [`PreSharedKey`](crate::PreSharedKey) against tampering.
+//!
+//! Decoding never trusts `count` on its own: the remaining input must hold
+//! every announced point before anything is allocated, and no bytes may
+//! trail the last block, so the memory used by a decoded message is bounded
+//! by the size of the input buffer.
+//!
+//! Over a byte stream such as TCP, [`write_frame`] and [`read_frame`] prefix
+//! each frame with its big-endian `u32` length.
+
+use crate::config::PsiConfig;
+use crate::error::{AbortReason, ErrorReport, Limit, Phase, PsiError, Result};
+use crate::messages::{
+    AbortMessage, BlindedPointsMessage, CardinalityMessage, DoubleBlindedPointsMessage,
+    ErrorReportMessage, MessageMac, OneRoundResponseMessage, ParameterDigest,
+};
+use curve25519_dalek::ristretto::CompressedRistretto;
+use std::io::{self, Read, Write};
+
+/// Current version of the wire format.
+///
+/// Version 2 added the trailing checksum.
+pub const WIRE_VERSION: u8 = 2;
+
+/// Size of the fixed frame header in bytes.
+pub const HEADER_LEN: usize = 6;
+
+/// Size of the trailing checksum in bytes.
+pub const CHECKSUM_LEN: usize = 4;
+
+/// Size of a single encoded point in bytes.
+const POINT_LEN: usize = 32;
+
+/// Size of a point list's count prefix in bytes.
+const COUNT_LEN: usize = 4;
+
+/// Bit of the kind byte set when the frame ends with an authentication block.
+const AUTHENTICATED_FLAG: u8 = 0x80;
+
+/// Size of an authentication block in bytes.
+const MAC_LEN: usize = 4 + 32;
+
+/// Bit of the kind byte set when the frame carries a parameter digest.
+const PARAMETERS_FLAG: u8 = 0x40;
+
+/// Size of a parameter digest in bytes.
+const DIGEST_LEN: usize = 32;
+
+/// Size of an error report body in bytes.
+const REPORT_LEN: usize = 1 + 8 + 8;
+
+/// Largest frame [`read_frame`] accepts when the configuration sets no
+/// remote limit (256 MiB).
+pub const DEFAULT_MAX_FRAME_LEN: usize = 256 << 20;
+
+/// Kind of message carried by a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MessageKind {
+    /// A [`BlindedPointsMessage`].
+    Blinded = 1,
+    /// A [`DoubleBlindedPointsMessage`].
+    DoubleBlinded = 2,
+    /// A [`OneRoundResponseMessage`].
+    OneRoundResponse = 3,
+    /// A [`CardinalityMessage`].
+    Cardinality = 4,
+    /// An [`AbortMessage`].
+    Abort = 5,
+    /// An [`ErrorReportMessage`].
+    ErrorReport = 6,
+}
+
+impl MessageKind {
+    fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            1 => Ok(MessageKind::Blinded),
+            2 => Ok(MessageKind::DoubleBlinded),
+            3 => Ok(MessageKind::OneRoundResponse),
+            4 => Ok(MessageKind::Cardinality),
+            5 => Ok(MessageKind::Abort),
+            6 => Ok(MessageKind::ErrorReport),
+            other => Err(PsiError::InvalidEncoding(format!(
+                "Unknown message kind {}",
+                other
+            ))),
+        }
+    }
+
+    /// Number of point lists carried by this kind of message.
+    fn list_count(self) -> usize {
+        match self {
+            MessageKind::Blinded | MessageKind::DoubleBlinded => 1,
+            MessageKind::OneRoundResponse => 2,
+            MessageKind::Cardinality | MessageKind::Abort | MessageKind::ErrorReport => 0,
+        }
+    }
+}
+
+/// Any message that can be carried by the wire format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireMessage {
+    /// Single-blinded points (first exchange).
+    Blinded(BlindedPointsMessage),
+    /// Double-blinded points (second exchange).
+    DoubleBlinded(DoubleBlindedPointsMessage),
+    /// Response of the one-round variant.
+    OneRoundResponse(OneRoundResponseMessage),
+    /// Size announcement sent before the blinded points.
+    Cardinality(CardinalityMessage),
+    /// Termination of the session by the sender.
+    Abort(AbortMessage),
+    /// Why the sender rejected our last message.
+    ErrorReport(ErrorReportMessage),
+}
+
+impl WireMessage {
+    /// Returns the kind of this message.
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            WireMessage::Blinded(_) => MessageKind::Blinded,
+            WireMessage::DoubleBlinded(_) => MessageKind::DoubleBlinded,
+            WireMessage::OneRoundResponse(_) => MessageKind::OneRoundResponse,
+            WireMessage::Cardinality(_) => MessageKind::Cardinality,
+            WireMessage::Abort(_) => MessageKind::Abort,
+            WireMessage::ErrorReport(_) => MessageKind::ErrorReport,
+        }
+    }
+
+    fn point_lists(&self) -> Vec<&[CompressedRistretto]> {
+        match self {
+            WireMessage::Blinded(msg) => vec![&msg.blinded_points],
+            WireMessage::DoubleBlinded(msg) => vec![&msg.double_blinded_points],
+            WireMessage::OneRoundResponse(msg) => {
+                vec![&msg.blinded_points, &msg.double_blinded_points]
+            }
+            WireMessage::Cardinality(_) | WireMessage::Abort(_) | WireMessage::ErrorReport(_) => {
+                vec![]
+            }
+        }
+    }
+
+    fn authentication(&self) -> Option<&MessageMac> {
+        match self {
+            WireMessage::Blinded(msg) => msg.authentication.as_ref(),
+            WireMessage::DoubleBlinded(msg) => msg.authentication.as_ref(),
+            WireMessage::OneRoundResponse(msg) => msg.authentication.as_ref(),
+            _ => None,
+        }
+    }
+
+    fn parameters(&self) -> Option<&ParameterDigest> {
+        match self {
+            WireMessage::Blinded(msg) => msg.parameters.as_ref(),
+            WireMessage::DoubleBlinded(msg) => msg.parameters.as_ref(),
+            WireMessage::OneRoundResponse(msg) => msg.parameters.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// Encode a message into a frame.
+///
+/// # Panics
+/// Panics if a point list holds more than `u32::MAX` points.
+pub fn encode(msg: &WireMessage) -> Vec<u8> {
+    let lists = msg.point_lists();
+    let total: usize = lists.iter().map(|points| points.len()).sum();
+
+    let mac = msg.authentication();
+    let parameters = msg.parameters();
+
+    let mut out = Vec::with_capacity(
+        2 + lists.len() * COUNT_LEN + total * POINT_LEN + DIGEST_LEN + MAC_LEN + CHECKSUM_LEN,
+    );
+    out.push(WIRE_VERSION);
+    let mut kind = msg.kind() as u8;
+    if mac.is_some() {
+        kind |= AUTHENTICATED_FLAG;
+    }
+    if parameters.is_some() {
+        kind |= PARAMETERS_FLAG;
+    }
+    out.push(kind);
+    for points in lists {
+        write_count(&mut out, points.len());
+        for point in points {
+            out.extend_from_slice(point.as_bytes());
+        }
+    }
+    match msg {
+        WireMessage::Cardinality(msg) => write_count(&mut out, msg.point_count),
+        WireMessage::Abort(msg) => write_count(&mut out, usize::from(msg.reason.code())),
+        WireMessage::ErrorReport(msg) => write_report(&mut out, &msg.report),
+        _ => {}
+    }
+    if let Some(parameters) = parameters {
+        out.extend_from_slice(&parameters.0);
+    }
+    if let Some(mac) = mac {
+        out.extend_from_slice(&mac.key_id.to_be_bytes());
+        out.extend_from_slice(&mac.tag);
+    }
+    let checksum = crc32c(&out);
+    out.extend_from_slice(&checksum.to_be_bytes());
+    out
+}
+
+/// Append an error report's code and body.
+fn write_report(out: &mut Vec<u8>, report: &ErrorReport) {
+    let (code, tag, first, second) = match *report {
+        ErrorReport::InvalidPoint { phase, index } => (1, phase_code(phase), index, 0),
+        ErrorReport::LengthMismatch {
+            phase,
+            expected,
+            actual,
+        } => (2, phase_code(phase), expected, actual),
+        ErrorReport::LimitExceeded { limit, max, actual } => (3, limit_code(limit), max, actual),
+        ErrorReport::ParameterMismatch { phase } => (4, phase_code(phase), 0, 0),
+        ErrorReport::Unauthenticated => (5, 0, 0, 0),
+        ErrorReport::Malformed => (6, 0, 0, 0),
+        ErrorReport::StaleKey { current, actual } => (7, 0, current as usize, actual as usize),
+    };
+    write_count(out, code);
+    out.push(tag);
+    out.extend_from_slice(&(first as u64).to_be_bytes());
+    out.extend_from_slice(&(second as u64).to_be_bytes());
+}
+
+/// Read an error report body for the report `code`.
+fn read_report(code: usize, bytes: &[u8]) -> Result<(ErrorReport, &[u8])> {
+    if bytes.len() < REPORT_LEN {
+        return Err(PsiError::InvalidEncoding(format!(
+            "Frame too short for an error report: {} bytes",
+            bytes.len()
+        )));
+    }
+    let (body, rest) = bytes.split_at(REPORT_LEN);
+    let value = |bytes: &[u8]| -> Result<usize> {
+        let mut value = [0u8; 8];
+        value.copy_from_slice(bytes);
+        usize::try_from(u64::from_be_bytes(value))
+            .map_err(|_| PsiError::InvalidEncoding("Error report value out of range".to_string()))
+    };
+    let (tag, first, second) = (body[0], value(&body[1..9])?, value(&body[9..])?);
+    let epoch = |value: usize| {
+        u32::try_from(value)
+            .map_err(|_| PsiError::InvalidEncoding("Key epoch out of range".to_string()))
+    };
+    let report = match code {
+        1 => ErrorReport::InvalidPoint {
+            phase: phase_of(tag)?,
+            index: first,
+        },
+        2 => ErrorReport::LengthMismatch {
+            phase: phase_of(tag)?,
+            expected: first,
+            actual: second,
+        },
+        3 => ErrorReport::LimitExceeded {
+            limit: limit_of(tag)?,
+            max: first,
+            actual: second,
+        },
+        4 => ErrorReport::ParameterMismatch {
+            phase: phase_of(tag)?,
+        },
+        5 => ErrorReport::Unauthenticated,
+        6 => ErrorReport::Malformed,
+        7 => ErrorReport::StaleKey {
+            current: epoch(first)?,
+            actual: epoch(second)?,
+        },
+        other => {
+            return Err(PsiError::InvalidEncoding(format!(
+                "Unknown error report code {}",
+                other
+            )))
+        }
+    };
+    let unused_are_zero = match code {
+        1 => second == 0,
+        4 => first == 0 && second == 0,
+        5 | 6 => tag == 0 && first == 0 && second == 0,
+        7 => tag == 0,
+        _ => true,
+    };
+    if !unused_are_zero {
+        return Err(PsiError::InvalidEncoding(format!(
+            "Error report code {} has non-zero unused fields",
+            code
+        )));
+    }
+    Ok((report, rest))
+}
+
+fn phase_code(phase: Phase) -> u8 {
+    match phase {
+        Phase::Prepare => 0,
+        Phase::Compute => 1,
+        Phase::Finalize => 2,
+    }
+}
+
+fn phase_of(code: u8) -> Result<Phase> {
+    match code {
+        0 => Ok(Phase::Prepare),
+        1 => Ok(Phase::Compute),
+        2 => Ok(Phase::Finalize),
+        other => Err(PsiError::InvalidEncoding(format!(
+            "Unknown phase {}",
+            other
+        ))),
+    }
+}
+
+fn limit_code(limit: Limit) -> u8 {
+    match limit {
+        Limit::LocalItems => 0,
+        Limit::RemotePoints => 1,
+        Limit::ClientQueries => 2,
+        Limit::PeerItems => 3,
+    }
+}
+
+fn limit_of(code: u8) -> Result<Limit> {
+    match code {
+        0 => Ok(Limit::LocalItems),
+        1 => Ok(Limit::RemotePoints),
+        2 => Ok(Limit::ClientQueries),
+        3 => Ok(Limit::PeerItems),
+        other => Err(PsiError::InvalidEncoding(format!(
+            "Unknown limit {}",
+            other
+        ))),
+    }
+}
+
+/// Largest frame a blinded or double-blinded message of at most `points`
+/// points encodes to, every optional block included.
+///
+/// For transports that read a length prefix before the frame and want to
+/// refuse oversized frames up front.
+pub fn max_frame_len(points: usize) -> usize {
+    HEADER_LEN
+        .saturating_add(points.saturating_mul(POINT_LEN))
+        .saturating_add(DIGEST_LEN + MAC_LEN + CHECKSUM_LEN)
+}
+
+/// Lookup table of CRC32C, reflected polynomial `0x82F63B78`.
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82F6_3B78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC32C (Castagnoli) of `bytes`.
+fn crc32c(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, byte| {
+        CRC32C_TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Append a `count` field.
+pub(crate) fn write_count(out: &mut Vec<u8>, count: usize) {
+    let count = u32::try_from(count).expect("message exceeds u32::MAX points");
+    out.extend_from_slice(&count.to_be_bytes());
+}
+
+/// Read a `count` field, returning it and the rest of the input.
+pub(crate) fn read_count(bytes: &[u8]) -> Result<(usize, &[u8])> {
+    if bytes.len() < COUNT_LEN {
+        return Err(PsiError::InvalidEncoding(format!(
+            "Frame too short for a point count: {} bytes",
+            bytes.len()
+        )));
+    }
+    let (count_bytes, rest) = bytes.split_at(COUNT_LEN);
+    let count = u32::from_be_bytes([count_bytes[0], count_bytes[1], count_bytes[2], count_bytes[3]])
+        as usize;
+    Ok((count, rest))
+}
+
+/// Read one `count | points` block, returning the points and the rest of the input.
+pub(crate) fn read_point_list(bytes: &[u8]) -> Result<(Vec<CompressedRistretto>, &[u8])> {
+    let (count, rest) = read_count(bytes)?;
+
+    // Check the announced count against the input before allocating
+    if rest.len() / POINT_LEN < count {
+        return Err(PsiError::InvalidEncoding(format!(
+            "Expected {} points, found {} payload bytes",
+            count,
+            rest.len()
+        )));
+    }
+    let (payload, rest) = rest.split_at(count * POINT_LEN);
+
+    let points = payload
+        .chunks_exact(POINT_LEN)
+        .map(|chunk| {
+            let mut point = [0u8; POINT_LEN];
+            point.copy_from_slice(chunk);
+            CompressedRistretto(point)
+        })
+        .collect();
+    Ok((points, rest))
+}
+
+/// Decode a frame into a message.
+///
+/// # Errors
+/// Returns `PsiError::VersionMismatch` if the frame has an unknown version,
+/// `PsiError::ChecksumMismatch` if it was corrupted, or
+/// `PsiError::InvalidEncoding` if it is truncated, has trailing bytes or an
+/// unknown message kind.
+pub fn decode(bytes: &[u8]) -> Result<WireMessage> {
+    if bytes.len() < HEADER_LEN + CHECKSUM_LEN {
+        return Err(PsiError::InvalidEncoding(format!(
+            "Frame too short: {} bytes",
+            bytes.len()
+        )));
+    }
+    if bytes[0] != WIRE_VERSION {
+        return Err(PsiError::VersionMismatch {
+            expected: WIRE_VERSION,
+            actual: bytes[0],
+        });
+    }
+    let (bytes, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+    let expected = crc32c(bytes);
+    let actual = u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    if actual != expected {
+        return Err(PsiError::ChecksumMismatch { expected, actual });
+    }
+    let kind = MessageKind::from_byte(bytes[1] & !(AUTHENTICATED_FLAG | PARAMETERS_FLAG))?;
+    let authenticated = bytes[1] & AUTHENTICATED_FLAG != 0;
+    let with_parameters = bytes[1] & PARAMETERS_FLAG != 0;
+    if (authenticated || with_parameters) && kind.list_count() == 0 {
+        return Err(PsiError::InvalidEncoding(format!(
+            "{:?} messages cannot be authenticated or carry parameters",
+            kind
+        )));
+    }
+
+    let mut rest = &bytes[2..];
+    let mut lists = Vec::with_capacity(kind.list_count());
+    for _ in 0..kind.list_count() {
+        let (points, remaining) = read_point_list(rest)?;
+        lists.push(points);
+        rest = remaining;
+    }
+    let mut announced = 0;
+    if matches!(kind, MessageKind::Cardinality | MessageKind::Abort) {
+        let (count, remaining) = read_count(rest)?;
+        announced = count;
+        rest = remaining;
+    }
+    let mut report = None;
+    if kind == MessageKind::ErrorReport {
+        let (code, remaining) = read_count(rest)?;
+        let (decoded, remaining) = read_report(code, remaining)?;
+        report = Some(decoded);
+        rest = remaining;
+    }
+    let mut parameters = None;
+    if with_parameters {
+        if rest.len() < DIGEST_LEN {
+            return Err(PsiError::InvalidEncoding(format!(
+                "Frame too short for a parameter digest: {} bytes",
+                rest.len()
+            )));
+        }
+        let mut digest = [0u8; DIGEST_LEN];
+        digest.copy_from_slice(&rest[..DIGEST_LEN]);
+        parameters = Some(ParameterDigest(digest));
+        rest = &rest[DIGEST_LEN..];
+    }
+    let mut mac = None;
+    if authenticated {
+        if rest.len() < MAC_LEN {
+            return Err(PsiError::InvalidEncoding(format!(
+                "Frame too short for an authentication block: {} bytes",
+                rest.len()
+            )));
+        }
+        let (key_id, remaining) = read_count(rest)?;
+        let mut tag = [0u8; 32];
+        tag.copy_from_slice(&remaining[..32]);
+        mac = Some(MessageMac::new(key_id as u32, tag));
+        rest = &remaining[32..];
+    }
+    if !rest.is_empty() {
+        return Err(PsiError::InvalidEncoding(format!(
+            "{} trailing bytes after message",
+            rest.len()
+        )));
+    }
+
+    let mut lists = lists.into_iter();
+    let mut next = || lists.next().unwrap_or_default();
+    Ok(match kind {
+        MessageKind::Blinded => {
+            let mut msg = BlindedPointsMessage::new(next());
+            msg.authentication = mac;
+            msg.parameters = parameters;
+            WireMessage::Blinded(msg)
+        }
+        MessageKind::DoubleBlinded => {
+            let mut msg = DoubleBlindedPointsMessage::new(next());
+            msg.authentication = mac;
+            msg.parameters = parameters;
+            WireMessage::DoubleBlinded(msg)
+        }
+        MessageKind::OneRoundResponse => {
+            let blinded = next();
+            let double_blinded = next();
+            let mut msg = OneRoundResponseMessage::new(blinded, double_blinded);
+            msg.authentication = mac;
+            msg.parameters = parameters;
+            WireMessage::OneRoundResponse(msg)
+        }
+        MessageKind::Cardinality => WireMessage::Cardinality(CardinalityMessage::new(announced)),
+        MessageKind::Abort => {
+            let code = u8::try_from(announced).map_err(|_| {
+                PsiError::InvalidEncoding(format!("Abort reason code {} out of range", announced))
+            })?;
+            WireMessage::Abort(AbortMessage::new(AbortReason::from_code(code)))
+        }
+        MessageKind::ErrorReport => WireMessage::ErrorReport(ErrorReportMessage::new(
+            report.expect("error reports are read above"),
+        )),
+    })
+}
+
+impl BlindedPointsMessage {
+    /// Encode this message with the binary wire format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(&WireMessage::Blinded(self.clone()))
+    }
+
+    /// Decode a message from the binary wire format.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` if the frame is malformed or
+    /// carries a different kind of message.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match decode(bytes)? {
+            WireMessage::Blinded(msg) => Ok(msg),
+            other => Err(PsiError::InvalidEncoding(format!(
+                "Expected blinded points, found {:?}",
+                other.kind()
+            ))),
+        }
+    }
+}
+
+impl DoubleBlindedPointsMessage {
+    /// Encode this message with the binary wire format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(&WireMessage::DoubleBlinded(self.clone()))
+    }
+
+    /// Decode a message from the binary wire format.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` if the frame is malformed or
+    /// carries a different kind of message.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match decode(bytes)? {
+            WireMessage::DoubleBlinded(msg) => Ok(msg),
+            other => Err(PsiError::InvalidEncoding(format!(
+                "Expected double-blinded points, found {:?}",
+                other.kind()
+            ))),
+        }
+    }
+}
+
+impl CardinalityMessage {
+    /// Encode this message with the binary wire format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(&WireMessage::Cardinality(*self))
+    }
+
+    /// Decode a message from the binary wire format.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` if the frame is malformed or
+    /// carries a different kind of message.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match decode(bytes)? {
+            WireMessage::Cardinality(msg) => Ok(msg),
+            other => Err(PsiError::InvalidEncoding(format!(
+                "Expected cardinality, found {:?}",
+                other.kind()
+            ))),
+        }
+    }
+}
+
+impl AbortMessage {
+    /// Encode this message with the binary wire format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(&WireMessage::Abort(*self))
+    }
+
+    /// Decode a message from the binary wire format.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` if the frame is malformed or
+    /// carries a different kind of message.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match decode(bytes)? {
+            WireMessage::Abort(msg) => Ok(msg),
+            other => Err(PsiError::InvalidEncoding(format!(
+                "Expected abort, found {:?}",
+                other.kind()
+            ))),
+        }
+    }
+}
+
+impl ErrorReportMessage {
+    /// Encode this message with the binary wire format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(&WireMessage::ErrorReport(*self))
+    }
+
+    /// Decode a message from the binary wire format.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` if the frame is malformed or
+    /// carries a different kind of message.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match decode(bytes)? {
+            WireMessage::ErrorReport(msg) => Ok(msg),
+            other => Err(PsiError::InvalidEncoding(format!(
+                "Expected error report, found {:?}",
+                other.kind()
+            ))),
+        }
+    }
+}
+
+impl OneRoundResponseMessage {
+    /// Encode this message with the binary wire format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(&WireMessage::OneRoundResponse(self.clone()))
+    }
+
+    /// Decode a message from the binary wire format.
+    ///
+    /// # Errors
+    /// Returns `PsiError::InvalidEncoding` if the frame is malformed or
+    /// carries a different kind of message.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match decode(bytes)? {
+            WireMessage::OneRoundResponse(msg) => Ok(msg),
+            other => Err(PsiError::InvalidEncoding(format!(
+                "Expected one-round response, found {:?}",
+                other.kind()
+            ))),
+        }
+    }
+}
+
+/// Error of a session over a byte stream.
+#[derive(Debug)]
+pub enum FrameError {
+    /// The stream failed, or the peer announced a frame over the limit
+    Io(io::Error),
+    /// A frame did not decode, a message was rejected, or the peer aborted
+    Protocol(PsiError),
+    /// The peer sent another message than the one named
+    UnexpectedMessage(&'static str),
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::Io(error) => write!(f, "connection failed: {}", error),
+            FrameError::Protocol(error) => write!(f, "protocol failed: {}", error),
+            FrameError::UnexpectedMessage(expected) => {
+                write!(f, "peer sent an unexpected message, expected {}", expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<io::Error> for FrameError {
+    fn from(error: io::Error) -> Self {
+        FrameError::Io(error)
+    }
+}
+
+impl From<PsiError> for FrameError {
+    fn from(error: PsiError) -> Self {
+        FrameError::Protocol(error)
+    }
+}
+
+/// Write a message to a byte stream as a frame prefixed with its
+/// big-endian `u32` length.
+///
+/// # Errors
+/// Returns `io::ErrorKind::InvalidInput` if the frame does not fit the
+/// prefix, plus any error of the stream
+pub fn write_frame<W: Write>(stream: &mut W, msg: &WireMessage) -> io::Result<()> {
+    let frame = encode(msg);
+    let len = u32::try_from(frame.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too large"))?;
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+/// Read one length-prefixed frame from a byte stream.
+///
+/// A length the remote limit of `config` rules out (see [`max_frame_len`])
+/// is refused before the frame is read. An abort frame or error report
+/// from the peer ends the session with the peer's reason.
+///
+/// # Errors
+/// Returns `FrameError::Io` if the stream fails or the length is over the
+/// limit, and `FrameError::Protocol` if the frame does not decode or the
+/// peer aborted
+pub fn read_frame<R: Read>(
+    stream: &mut R,
+    config: &PsiConfig,
+) -> std::result::Result<WireMessage, FrameError> {
+    let max_len = config
+        .max_remote_items()
+        .map_or(DEFAULT_MAX_FRAME_LEN, max_frame_len);
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len {
+        return Err(FrameError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes exceeds the limit of {}", len, max_len),
+        )));
+    }
+    let mut frame = vec![0u8; len];
+    stream.read_exact(&mut frame)?;
+    match decode(&frame)? {
+        WireMessage::Abort(abort) => Err(FrameError::Protocol(abort.into_error())),
+        WireMessage::ErrorReport(report) => Err(FrameError::Protocol(report.into_error())),
+        msg => Ok(msg),
+    }
+}
+
+/// [`read_frame`], expecting blinded points.
+///
+/// # Errors
+/// Same as [`read_frame`], plus `FrameError::UnexpectedMessage` for any
+/// other message
+pub fn read_blinded<R: Read>(
+    stream: &mut R,
+    config: &PsiConfig,
+) -> std::result::Result<BlindedPointsMessage, FrameError> {
+    match read_frame(stream, config)? {
+        WireMessage::Blinded(msg) => Ok(msg),
+        _ => Err(FrameError::UnexpectedMessage("blinded points")),
+    }
+}
+
+/// [`read_frame`], expecting double-blinded points.
+///
+/// # Errors
+/// Same as [`read_frame`], plus `FrameError::UnexpectedMessage` for any
+/// other message
+pub fn read_double_blinded<R: Read>(
+    stream: &mut R,
+    config: &PsiConfig,
+) -> std::result::Result<DoubleBlindedPointsMessage, FrameError> {
+    match read_frame(stream, config)? {
+        WireMessage::DoubleBlinded(msg) => Ok(msg),
+        _ => Err(FrameError::UnexpectedMessage("double-blinded points")),
+    }
+}
+
+/// Tell the peer why the session stops before reporting a local error.
+///
+/// Errors caused by the peer's message are described in an error report
+/// ahead of the abort. Best effort: the error is reported even if neither
+/// can be sent.
+///
+/// # Errors
+/// Returns `FrameError::Protocol` with the error of `outcome`
+pub fn abort_on_error<W: Write, T>(
+    stream: &mut W,
+    outcome: Result<T>,
+) -> std::result::Result<T, FrameError> {
+    outcome.map_err(|error| {
+        if let Some(report) = ErrorReportMessage::for_error(&error) {
+            let _ = write_frame(stream, &WireMessage::ErrorReport(report));
+        }
+        let abort = AbortMessage::new(AbortReason::for_error(&error));
+        let _ = write_frame(stream, &WireMessage::Abort(abort));
+        FrameError::Protocol(error)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_points() -> Vec<CompressedRistretto> {
+        vec![CompressedRistretto([1u8; 32]), CompressedRistretto([2u8; 32])]
+    }
+
+    /// Replace the checksum of an edited frame, to reach the checks behind it.
+    fn reseal(bytes: &mut Vec<u8>) {
+        bytes.truncate(bytes.len() - CHECKSUM_LEN);
+        let checksum = crc32c(bytes);
+        bytes.extend_from_slice(&checksum.to_be_bytes());
+    }
+
+    #[test]
+    fn test_blinded_round_trip() {
+        let msg = BlindedPointsMessage::new(sample_points());
+        let bytes = msg.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 2 * POINT_LEN + CHECKSUM_LEN);
+        assert_eq!(BlindedPointsMessage::from_bytes(&bytes).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_cardinality_round_trip() {
+        let msg = CardinalityMessage::new(1_000_000);
+        let bytes = msg.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + CHECKSUM_LEN);
+        assert_eq!(CardinalityMessage::from_bytes(&bytes).unwrap(), msg);
+        assert!(BlindedPointsMessage::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_abort_round_trip() {
+        for reason in [AbortReason::Overloaded, AbortReason::Other(200)] {
+            let msg = AbortMessage::new(reason);
+            let bytes = msg.to_bytes();
+            assert_eq!(bytes.len(), HEADER_LEN + CHECKSUM_LEN);
+            assert_eq!(AbortMessage::from_bytes(&bytes).unwrap(), msg);
+        }
+
+        let mut bytes = AbortMessage::new(AbortReason::Cancelled).to_bytes();
+        bytes[2] = 1;
+        reseal(&mut bytes);
+        assert!(matches!(decode(&bytes), Err(PsiError::InvalidEncoding(_))));
+    }
+
+    #[test]
+    fn test_error_report_round_trip() {
+        let reports = [
+            ErrorReport::InvalidPoint {
+                phase: Phase::Compute,
+                index: 3,
+            },
+            ErrorReport::LengthMismatch {
+                phase: Phase::Finalize,
+                expected: 4,
+                actual: 2,
+            },
+            ErrorReport::LimitExceeded {
+                limit: Limit::RemotePoints,
+                max: 10,
+                actual: 1 << 20,
+            },
+            ErrorReport::ParameterMismatch {
+                phase: Phase::Compute,
+            },
+            ErrorReport::Unauthenticated,
+            ErrorReport::Malformed,
+            ErrorReport::StaleKey {
+                current: 4,
+                actual: u32::MAX,
+            },
+        ];
+        for report in reports {
+            let msg = ErrorReportMessage::new(report);
+            let bytes = msg.to_bytes();
+            assert_eq!(bytes.len(), HEADER_LEN + REPORT_LEN + CHECKSUM_LEN);
+            assert_eq!(ErrorReportMessage::from_bytes(&bytes).unwrap(), msg);
+            for len in 0..bytes.len() {
+                assert!(decode(&bytes[..len]).is_err(), "length {} accepted", len);
+            }
+        }
+
+        let mut bytes = ErrorReportMessage::new(ErrorReport::Malformed).to_bytes();
+        bytes[5] = 9;
+        reseal(&mut bytes);
+        assert!(matches!(decode(&bytes), Err(PsiError::InvalidEncoding(_))));
+    }
+
+    #[test]
+    fn test_error_report_rejects_unused_fields() {
+        let (tag, first, second) = (HEADER_LEN, HEADER_LEN + 8, HEADER_LEN + REPORT_LEN - 1);
+        let cases = [
+            (
+                ErrorReport::InvalidPoint {
+                    phase: Phase::Compute,
+                    index: 3,
+                },
+                second,
+            ),
+            (
+                ErrorReport::ParameterMismatch {
+                    phase: Phase::Compute,
+                },
+                first,
+            ),
+            (ErrorReport::Unauthenticated, tag),
+            (ErrorReport::Malformed, second),
+            (
+                ErrorReport::StaleKey {
+                    current: 4,
+                    actual: 5,
+                },
+                tag,
+            ),
+        ];
+        for (report, offset) in cases {
+            let mut bytes = ErrorReportMessage::new(report).to_bytes();
+            bytes[offset] = 1;
+            reseal(&mut bytes);
+            assert!(
+                matches!(decode(&bytes), Err(PsiError::InvalidEncoding(_))),
+                "{:?} accepted",
+                report
+            );
+        }
+    }
+
+    #[test]
+    fn test_oversized_frames_are_refused() {
+        let config = PsiConfig::builder().max_remote_items(1).build().unwrap();
+        let mut stream = Vec::new();
+        let msg = BlindedPointsMessage::new(vec![CompressedRistretto([1u8; 32]); 4]);
+        write_frame(&mut stream, &WireMessage::Blinded(msg.clone())).unwrap();
+        let error = read_frame(&mut stream.as_slice(), &config).unwrap_err();
+        assert!(
+            matches!(error, FrameError::Io(ref e) if e.kind() == io::ErrorKind::InvalidData)
+        );
+        assert_eq!(
+            read_blinded(&mut stream.as_slice(), &PsiConfig::default()).unwrap(),
+            msg
+        );
+    }
+
+    #[test]
+    fn test_double_blinded_round_trip() {
+        let msg = DoubleBlindedPointsMessage::new(sample_points());
+        let bytes = msg.to_bytes();
+        assert_eq!(DoubleBlindedPointsMessage::from_bytes(&bytes).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_one_round_response_round_trip() {
+        let msg = OneRoundResponseMessage::new(sample_points(), vec![CompressedRistretto([3u8; 32])]);
+        let bytes = msg.to_bytes();
+        assert_eq!(
+            bytes.len(),
+            2 + 2 * COUNT_LEN + 3 * POINT_LEN + CHECKSUM_LEN
+        );
+        assert_eq!(OneRoundResponseMessage::from_bytes(&bytes).unwrap(), msg);
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "length {} accepted", len);
+        }
+
+        let mut msg = msg;
+        msg.authentication = Some(MessageMac::new(7, [9u8; 32]));
+        msg.parameters = Some(ParameterDigest([5u8; 32]));
+        let bytes = msg.to_bytes();
+        assert_eq!(
+            bytes.len(),
+            2 + 2 * COUNT_LEN + 3 * POINT_LEN + DIGEST_LEN + MAC_LEN + CHECKSUM_LEN
+        );
+        assert_eq!(OneRoundResponseMessage::from_bytes(&bytes).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_authenticated_round_trip() {
+        let mut msg = DoubleBlindedPointsMessage::new(sample_points());
+        msg.authentication = Some(MessageMac::new(7, [9u8; 32]));
+        let bytes = msg.to_bytes();
+        assert_eq!(
+            bytes.len(),
+            HEADER_LEN + 2 * POINT_LEN + MAC_LEN + CHECKSUM_LEN
+        );
+        assert_eq!(DoubleBlindedPointsMessage::from_bytes(&bytes).unwrap(), msg);
+        for len in HEADER_LEN + 2 * POINT_LEN..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "length {} accepted", len);
+        }
+
+        let mut bytes = CardinalityMessage::new(3).to_bytes();
+        bytes[1] |= AUTHENTICATED_FLAG;
+        reseal(&mut bytes);
+        assert!(matches!(decode(&bytes), Err(PsiError::InvalidEncoding(_))));
+    }
+
+    #[test]
+    fn test_parameters_round_trip() {
+        let mut msg = BlindedPointsMessage::new(sample_points());
+        msg.parameters = Some(ParameterDigest([5u8; 32]));
+        msg.authentication = Some(MessageMac::new(7, [9u8; 32]));
+        let bytes = msg.to_bytes();
+        assert_eq!(
+            bytes.len(),
+            HEADER_LEN + 2 * POINT_LEN + DIGEST_LEN + MAC_LEN + CHECKSUM_LEN
+        );
+        assert_eq!(bytes.len(), max_frame_len(2));
+        assert_eq!(BlindedPointsMessage::from_bytes(&bytes).unwrap(), msg);
+        for len in HEADER_LEN + 2 * POINT_LEN..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "length {} accepted", len);
+        }
+
+        let mut bytes = CardinalityMessage::new(3).to_bytes();
+        bytes[1] |= PARAMETERS_FLAG;
+        reseal(&mut bytes);
+        assert!(matches!(decode(&bytes), Err(PsiError::InvalidEncoding(_))));
+    }
+
+    #[test]
+    fn test_empty_message_round_trip() {
+        let msg = BlindedPointsMessage::new(vec![]);
+        assert_eq!(BlindedPointsMessage::from_bytes(&msg.to_bytes()).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_decode_rejects_wrong_kind() {
+        let bytes = BlindedPointsMessage::new(sample_points()).to_bytes();
+        let result = DoubleBlindedPointsMessage::from_bytes(&bytes);
+        assert!(matches!(result, Err(PsiError::InvalidEncoding(_))));
+    }
+
+    #[test]
+    fn test_decode_rejects_truncated_input() {
+        let bytes = BlindedPointsMessage::new(sample_points()).to_bytes();
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "length {} accepted", len);
+        }
+    }
+
+    #[test]
+    fn test_decode_rejects_trailing_bytes() {
+        let mut bytes = BlindedPointsMessage::new(sample_points()).to_bytes();
+        bytes.push(0);
+        assert!(decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_huge_count_without_allocating() {
+        let mut bytes = vec![WIRE_VERSION, MessageKind::Blinded as u8];
+        bytes.extend_from_slice(&u32::MAX.to_be_bytes());
+        bytes.extend_from_slice(&crc32c(&bytes).to_be_bytes());
+        assert!(matches!(decode(&bytes), Err(PsiError::InvalidEncoding(_))));
+    }
+
+    #[test]
+    fn test_decode_rejects_unknown_version_and_kind() {
+        let mut bytes = BlindedPointsMessage::new(sample_points()).to_bytes();
+        bytes[0] = WIRE_VERSION + 1;
+        assert_eq!(
+            decode(&bytes),
+            Err(PsiError::VersionMismatch {
+                expected: WIRE_VERSION,
+                actual: WIRE_VERSION + 1
+            })
+        );
+
+        let mut bytes = BlindedPointsMessage::new(sample_points()).to_bytes();
+        bytes[1] = 0xff;
+        reseal(&mut bytes);
+        assert!(matches!(decode(&bytes), Err(PsiError::InvalidEncoding(_))));
+    }
+
+    #[test]
+    fn test_decode_detects_corruption() {
+        // Standard CRC32C check value
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+
+        let bytes = BlindedPointsMessage::new(sample_points()).to_bytes();
+        for index in 1..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted[index] ^= 0x10;
+            assert!(
+                matches!(decode(&corrupted), Err(PsiError::ChecksumMismatch { .. })),
+                "corruption at {} not detected",
+                index
+            );
+        }
+    }
+}
@@ -0,0 +1,365 @@
+//! Configuration from the command line and an optional TOML file.
+//!
+//! `--config <FILE>` reads the same settings from a file, so recurring jobs
+//! can be declared once; flags given alongside override the file and add to
+//! its peers. Peers in the file may have their own interval:
+//!
+//! ```toml
+//! dir = "/srv/shared"           # or: manifest = "/srv/items.txt"
+//! listen = "0.0.0.0:7878"
+//! socket = "/run/psi-syncd.sock"
+//! interval = 300
+//! timeout = 60
+//! max_remote_items = 100000
+//!
+//! [[peer]]
+//! address = "backup.internal:7878"
+//!
+//! [[peer]]
+//! address = "archive.internal:7878"
+//! interval = 3600
+//! ```
+//!
+//! Unknown keys are errors, so typos do not silently fall back to defaults.
+
+use crate::index::Source;
+use psi_protocol::PsiConfig;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml_edit::{Document, Item, Table};
+
+pub const USAGE: &str = "\
+usage: psi-syncd (--dir <PATH> | --manifest <FILE>) [options]
+       psi-syncd --config <FILE> [options]
+
+options:
+  --config <FILE>             read settings from this TOML file
+  --listen <ADDR>             answer peers on this address
+  --peer <HOST:PORT>          sync with this peer every round (repeatable)
+  --interval <SECS>           seconds between rounds [default: 300]
+  --socket <PATH>             serve the status as JSON on this Unix socket
+  --max-remote-items <N>      refuse peers announcing more items
+  --timeout <SECS>            network timeout per connection [default: 60]";
+
+/// Default time between two sync rounds.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Default read/write timeout on peer connections.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A peer synced with on a schedule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    /// `host:port` the peer listens on
+    pub address: String,
+    /// Time between two syncs with this peer
+    pub interval: Duration,
+}
+
+/// Everything the daemon needs to run.
+#[derive(Debug, Clone)]
+pub struct DaemonConfig {
+    pub source: Source,
+    pub listen: Option<SocketAddr>,
+    pub peers: Vec<Peer>,
+    /// Time between two rescans, and between syncs with peers without an
+    /// interval of their own
+    pub interval: Duration,
+    pub socket: Option<PathBuf>,
+    pub max_remote_items: Option<usize>,
+    pub timeout: Duration,
+}
+
+impl DaemonConfig {
+    /// Parse the command line (without the program name), reading the
+    /// `--config` file first if one is given.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut args: Vec<String> = args.into_iter().collect();
+        let mut settings = match args.iter().position(|arg| arg == "--config") {
+            Some(at) => {
+                let path = args.get(at + 1).ok_or("--config needs a value")?.clone();
+                args.drain(at..at + 2);
+                Settings::from_file(Path::new(&path))?
+            }
+            None => Settings::default(),
+        };
+
+        let mut source_flag = false;
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", flag));
+            match flag.as_str() {
+                "--dir" | "--manifest" if source_flag => {
+                    return Err("give only one of --dir and --manifest".to_string())
+                }
+                "--dir" => {
+                    settings.source = Some(Source::Dir(value()?.into()));
+                    source_flag = true;
+                }
+                "--manifest" => {
+                    settings.source = Some(Source::Manifest(value()?.into()));
+                    source_flag = true;
+                }
+                "--listen" => settings.listen = Some(parse_addr(&flag, &value()?)?),
+                "--peer" => settings.peers.push((value()?, None)),
+                "--interval" => {
+                    settings.interval = Some(Duration::from_secs(parse_number(&flag, &value()?)?))
+                }
+                "--socket" => settings.socket = Some(value()?.into()),
+                "--max-remote-items" => {
+                    settings.max_remote_items = Some(parse_number(&flag, &value()?)? as usize)
+                }
+                "--timeout" => {
+                    settings.timeout = Some(Duration::from_secs(parse_number(&flag, &value()?)?))
+                }
+                other => return Err(format!("unknown argument {}", other)),
+            }
+        }
+        settings.finish()
+    }
+
+    /// Protocol configuration shared by every session.
+    ///
+    /// Peers must agree on it, so only limits are configurable. A node with
+    /// an empty index still takes part, so fresh nodes can join.
+    pub fn psi_config(&self) -> PsiConfig {
+        let builder = PsiConfig::builder().domain("psi-syncd").allow_empty(true);
+        let builder = match self.max_remote_items {
+            Some(limit) => builder.max_remote_items(limit),
+            None => builder,
+        };
+        builder.build().expect("the daemon configuration is valid")
+    }
+}
+
+/// Settings gathered from the file and the command line.
+#[derive(Debug, Default)]
+struct Settings {
+    source: Option<Source>,
+    listen: Option<SocketAddr>,
+    /// Addresses and their own intervals, if any
+    peers: Vec<(String, Option<Duration>)>,
+    interval: Option<Duration>,
+    socket: Option<PathBuf>,
+    max_remote_items: Option<usize>,
+    timeout: Option<Duration>,
+}
+
+impl Settings {
+    fn from_file(path: &Path) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::from_toml(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn from_toml(text: &str) -> Result<Self, String> {
+        let document = Document::parse(text).map_err(|e| e.to_string())?;
+        let mut settings = Settings::default();
+        for (key, item) in document.as_table().iter() {
+            match key {
+                "dir" | "manifest" if settings.source.is_some() => {
+                    return Err("give only one of dir and manifest".to_string())
+                }
+                "dir" => settings.source = Some(Source::Dir(toml_str(key, item)?.into())),
+                "manifest" => settings.source = Some(Source::Manifest(toml_str(key, item)?.into())),
+                "listen" => settings.listen = Some(parse_addr(key, toml_str(key, item)?)?),
+                "socket" => settings.socket = Some(toml_str(key, item)?.into()),
+                "interval" => settings.interval = Some(toml_secs(key, item)?),
+                "timeout" => settings.timeout = Some(toml_secs(key, item)?),
+                "max_remote_items" => {
+                    settings.max_remote_items = Some(toml_number(key, item)? as usize)
+                }
+                "peer" => {
+                    let peers = item
+                        .as_array_of_tables()
+                        .ok_or("peer must be an array of tables ([[peer]])")?;
+                    for peer in peers {
+                        settings.peers.push(toml_peer(peer)?);
+                    }
+                }
+                "tls" => {
+                    return Err("TLS is not built in; terminate it in front of the daemon \
+                                (e.g. a tunnel or a proxy)"
+                        .to_string())
+                }
+                other => return Err(format!("unknown key {}", other)),
+            }
+        }
+        Ok(settings)
+    }
+
+    fn finish(self) -> Result<DaemonConfig, String> {
+        let source = self
+            .source
+            .ok_or("one of --dir and --manifest is required")?;
+        if self.listen.is_none() && self.peers.is_empty() {
+            return Err("nothing to do: give --listen, --peer or both".to_string());
+        }
+        let interval = self.interval.unwrap_or(DEFAULT_INTERVAL);
+        let peers = self
+            .peers
+            .into_iter()
+            .map(|(address, own)| Peer {
+                address,
+                interval: own.unwrap_or(interval),
+            })
+            .collect::<Vec<_>>();
+        if interval.is_zero() || peers.iter().any(|peer| peer.interval.is_zero()) {
+            return Err("--interval must be at least 1".to_string());
+        }
+        Ok(DaemonConfig {
+            source,
+            listen: self.listen,
+            peers,
+            interval,
+            socket: self.socket,
+            max_remote_items: self.max_remote_items,
+            timeout: self.timeout.unwrap_or(DEFAULT_TIMEOUT),
+        })
+    }
+}
+
+fn toml_peer(table: &Table) -> Result<(String, Option<Duration>), String> {
+    let mut address = None;
+    let mut interval = None;
+    for (key, item) in table.iter() {
+        match key {
+            "address" => address = Some(toml_str(key, item)?.to_string()),
+            "interval" => interval = Some(toml_secs(key, item)?),
+            other => return Err(format!("unknown key peer.{}", other)),
+        }
+    }
+    Ok((address.ok_or("every peer needs an address")?, interval))
+}
+
+fn toml_str<'a>(key: &str, item: &'a Item) -> Result<&'a str, String> {
+    item.as_str()
+        .ok_or_else(|| format!("{} must be a string", key))
+}
+
+fn toml_number(key: &str, item: &Item) -> Result<u64, String> {
+    item.as_integer()
+        .and_then(|value| u64::try_from(value).ok())
+        .ok_or_else(|| format!("{} must be a non-negative integer", key))
+}
+
+fn toml_secs(key: &str, item: &Item) -> Result<Duration, String> {
+    toml_number(key, item).map(Duration::from_secs)
+}
+
+fn parse_addr(flag: &str, value: &str) -> Result<SocketAddr, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid {} address {}", flag, value))
+}
+
+fn parse_number(flag: &str, value: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, got {}", flag, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<DaemonConfig, String> {
+        DaemonConfig::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_full_command_line() {
+        let config = parse(&[
+            "--dir",
+            "/srv/shared",
+            "--listen",
+            "127.0.0.1:7878",
+            "--peer",
+            "a:7878",
+            "--peer",
+            "b:7878",
+            "--interval",
+            "60",
+            "--max-remote-items",
+            "1000",
+        ])
+        .unwrap();
+        assert!(
+            matches!(config.source, Source::Dir(ref dir) if dir == &PathBuf::from("/srv/shared"))
+        );
+        let peers: Vec<&str> = config.peers.iter().map(|p| p.address.as_str()).collect();
+        assert_eq!(peers, vec!["a:7878", "b:7878"]);
+        assert_eq!(config.interval, Duration::from_secs(60));
+        assert_eq!(config.peers[0].interval, Duration::from_secs(60));
+        assert_eq!(config.psi_config().max_remote_items(), Some(1000));
+        assert_eq!(config.timeout, DEFAULT_TIMEOUT);
+    }
+
+    #[test]
+    fn test_reject_incomplete_command_lines() {
+        assert!(parse(&["--listen", "127.0.0.1:7878"]).is_err());
+        assert!(parse(&["--dir", "a", "--manifest", "b", "--peer", "x:1"]).is_err());
+        assert!(parse(&["--dir", "a"]).is_err());
+        assert!(parse(&["--dir", "a", "--peer"]).is_err());
+        assert!(parse(&["--dir", "a", "--peer", "x:1", "--interval", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_config_file_with_overrides() {
+        let path = std::env::temp_dir().join(format!("psi-syncd-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+manifest = "/srv/items.txt"
+listen = "0.0.0.0:7878"
+interval = 120
+max_remote_items = 5000
+
+[[peer]]
+address = "a:7878"
+
+[[peer]]
+address = "b:7878"
+interval = 3600
+"#,
+        )
+        .unwrap();
+        let config = parse(&[
+            "--config",
+            path.to_str().unwrap(),
+            "--dir",
+            "/srv/shared",
+            "--peer",
+            "c:7878",
+        ])
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(
+            matches!(config.source, Source::Dir(ref dir) if dir == &PathBuf::from("/srv/shared"))
+        );
+        assert_eq!(config.listen, Some("0.0.0.0:7878".parse().unwrap()));
+        let intervals: Vec<(&str, u64)> = config
+            .peers
+            .iter()
+            .map(|peer| (peer.address.as_str(), peer.interval.as_secs()))
+            .collect();
+        assert_eq!(
+            intervals,
+            vec![("a:7878", 120), ("b:7878", 3600), ("c:7878", 120)]
+        );
+        assert_eq!(config.psi_config().max_remote_items(), Some(5000));
+    }
+
+    #[test]
+    fn test_reject_invalid_config_files() {
+        assert!(Settings::from_toml("dir = 1").is_err());
+        assert!(Settings::from_toml("dir = \"a\"\nmanifest = \"b\"").is_err());
+        assert!(Settings::from_toml("intervall = 60").is_err());
+        assert!(Settings::from_toml("interval = -1").is_err());
+        assert!(Settings::from_toml("[[peer]]\ninterval = 60").is_err());
+        assert!(Settings::from_toml("[tls]\ncert = \"c.pem\"").is_err());
+        assert!(Settings::from_toml("dir = [").is_err());
+    }
+}
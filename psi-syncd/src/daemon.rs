@@ -0,0 +1,179 @@
+//! The daemon's threads: sync rounds, incoming peers and the status socket.
+
+use crate::config::DaemonConfig;
+use crate::exchange;
+use crate::index::ContentIndex;
+use crate::status::{Direction, PeerStatus, Status};
+use psi_protocol::wire::FrameError;
+use psi_protocol::{PsiConfig, PsiResult};
+use std::error::Error;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+
+/// State shared by every thread.
+struct Shared {
+    config: DaemonConfig,
+    psi_config: PsiConfig,
+    index: RwLock<ContentIndex>,
+    status: Mutex<Status>,
+}
+
+impl Shared {
+    /// Rescan the local content.
+    fn refresh(&self) {
+        let mut index = self.index.write().expect("index lock poisoned");
+        let error = index.refresh().err().map(|error| error.to_string());
+        if let Some(error) = &error {
+            eprintln!("psi-syncd: scan failed: {}", error);
+        }
+        self.status
+            .lock()
+            .expect("status lock poisoned")
+            .scanned(&index, error);
+    }
+
+    /// Run one session and record its outcome under `peer`.
+    fn sync(
+        &self,
+        peer: String,
+        direction: Direction,
+        session: impl FnOnce(&[Vec<u8>]) -> Result<PsiResult, FrameError>,
+    ) {
+        let items = self.index.read().expect("index lock poisoned").items();
+        let status = match session(&items) {
+            Ok(result) => {
+                let index = self.index.read().expect("index lock poisoned");
+                let status = PeerStatus::from_result(direction, &result, &index);
+                eprintln!(
+                    "psi-syncd: {}: {} shared, {} missing on peer",
+                    peer,
+                    status.shared,
+                    status.missing_on_peer.len()
+                );
+                status
+            }
+            Err(error) => {
+                eprintln!("psi-syncd: {}: {}", peer, error);
+                PeerStatus::failed(direction, error)
+            }
+        };
+        self.status
+            .lock()
+            .expect("status lock poisoned")
+            .peers
+            .insert(peer, status);
+    }
+
+    fn connect(&self, peer: &str) -> Result<TcpStream, FrameError> {
+        let addr = peer.to_socket_addrs()?.next().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "address did not resolve")
+        })?;
+        let stream = TcpStream::connect_timeout(&addr, self.config.timeout)?;
+        self.limit(&stream)?;
+        Ok(stream)
+    }
+
+    fn limit(&self, stream: &TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(self.config.timeout))?;
+        stream.set_write_timeout(Some(self.config.timeout))
+    }
+}
+
+/// Run the daemon until the process is stopped.
+pub fn run(config: DaemonConfig) -> Result<(), Box<dyn Error>> {
+    let shared = Arc::new(Shared {
+        psi_config: config.psi_config(),
+        index: RwLock::new(ContentIndex::new(config.source.clone())),
+        status: Mutex::new(Status::default()),
+        config,
+    });
+    shared.refresh();
+
+    if let Some(path) = &shared.config.socket {
+        serve_status(Arc::clone(&shared), path)?;
+    }
+    if let Some(addr) = shared.config.listen {
+        let listener = TcpListener::bind(addr)?;
+        eprintln!("psi-syncd: listening on {}", addr);
+        let shared = Arc::clone(&shared);
+        std::thread::spawn(move || accept_peers(shared, listener));
+    }
+
+    // Every peer is due at startup, then after its own interval
+    let mut due = vec![Instant::now(); shared.config.peers.len()];
+    loop {
+        for (peer, due) in shared.config.peers.iter().zip(&mut due) {
+            if *due > Instant::now() {
+                continue;
+            }
+            shared.sync(peer.address.clone(), Direction::Outgoing, |items| {
+                let mut stream = shared.connect(&peer.address)?;
+                exchange::initiate(&mut stream, items, &shared.psi_config)
+            });
+            *due = Instant::now() + peer.interval;
+        }
+        // Rescan at least every interval, even if no peer is due
+        let next = due
+            .iter()
+            .copied()
+            .chain([Instant::now() + shared.config.interval])
+            .min()
+            .expect("the chain is not empty");
+        std::thread::sleep(next.saturating_duration_since(Instant::now()));
+        shared.refresh();
+    }
+}
+
+/// Answer every incoming peer on its own thread.
+fn accept_peers(shared: Arc<Shared>, listener: TcpListener) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                eprintln!("psi-syncd: accept failed: {}", error);
+                continue;
+            }
+        };
+        let shared = Arc::clone(&shared);
+        std::thread::spawn(move || {
+            let peer = match stream.peer_addr() {
+                Ok(addr) => addr.ip().to_string(),
+                Err(_) => "unknown".to_string(),
+            };
+            shared.sync(peer, Direction::Incoming, |items| {
+                shared.limit(&stream)?;
+                exchange::respond(&mut stream, items, &shared.psi_config)
+            });
+        });
+    }
+}
+
+/// Write the status as JSON to every client of the Unix socket at `path`.
+#[cfg(unix)]
+fn serve_status(shared: Arc<Shared>, path: &std::path::Path) -> Result<(), Box<dyn Error>> {
+    use std::io::Write;
+    use std::os::unix::net::UnixListener;
+
+    // A socket left behind by a previous run would make bind fail
+    let _ = std::fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    std::thread::spawn(move || {
+        for mut client in listener.incoming().flatten() {
+            let json = shared
+                .status
+                .lock()
+                .expect("status lock poisoned")
+                .to_json();
+            if let Err(error) = client.write_all(json.as_bytes()) {
+                eprintln!("psi-syncd: status client: {}", error);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn serve_status(_shared: Arc<Shared>, _path: &std::path::Path) -> Result<(), Box<dyn Error>> {
+    Err("--socket needs Unix domain sockets".into())
+}
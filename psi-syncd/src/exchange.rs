@@ -0,0 +1,127 @@
+//! One PSI session over a byte stream.
+//!
+//! Messages are [`wire`](psi_protocol::wire) frames prefixed with their
+//! big-endian `u32` length, see
+//! [`write_frame`](psi_protocol::wire::write_frame). The initiator speaks first and the responder
+//! answers both of its messages at once, so neither side writes while the
+//! other is writing:
+//!
+//! ```text
+//! initiator                          responder
+//!     blinded  ------------------->
+//!              <-------------------  blinded, double-blinded
+//!     double-blinded  ------------>
+//! ```
+//!
+//! Both sides learn the intersection. A side whose `compute` rejects the
+//! peer's points sends an error report saying why, then an abort frame, in
+//! place of its double-blinded points; the peer fails with
+//! `PsiError::Rejected` (or `PsiError::Aborted` when there is nothing to
+//! report) instead of waiting on the connection.
+
+use psi_protocol::wire::{
+    abort_on_error, read_blinded, read_double_blinded, write_frame, FrameError, WireMessage,
+};
+use psi_protocol::{PsiConfig, PsiProtocol, PsiResult};
+use std::io::{Read, Write};
+
+/// Run a session as the side that connected.
+pub fn initiate<S: Read + Write>(
+    stream: &mut S,
+    items: &[Vec<u8>],
+    config: &PsiConfig,
+) -> Result<PsiResult, FrameError> {
+    let local = PsiProtocol::new_with_config(items, config.clone())?;
+    write_frame(stream, &WireMessage::Blinded(local.message()))?;
+
+    let remote_msg = read_blinded(stream, config)?;
+    let remote_double = read_double_blinded(stream, config)?;
+    let (local, double_msg) = abort_on_error(stream, local.compute(remote_msg))?;
+    write_frame(stream, &WireMessage::DoubleBlinded(double_msg))?;
+    let (_, result) = local.finalize(remote_double)?;
+    Ok(result)
+}
+
+/// Run a session as the side that accepted the connection.
+pub fn respond<S: Read + Write>(
+    stream: &mut S,
+    items: &[Vec<u8>],
+    config: &PsiConfig,
+) -> Result<PsiResult, FrameError> {
+    let local = PsiProtocol::new_with_config(items, config.clone())?;
+    let remote_msg = read_blinded(stream, config)?;
+    write_frame(stream, &WireMessage::Blinded(local.message()))?;
+    let (local, double_msg) = abort_on_error(stream, local.compute(remote_msg))?;
+    write_frame(stream, &WireMessage::DoubleBlinded(double_msg))?;
+
+    let remote_double = read_double_blinded(stream, config)?;
+    let (_, result) = local.finalize(remote_double)?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use psi_protocol::{ErrorReport, ItemId, Limit, PsiError};
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn test_both_sides_learn_the_intersection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let responder = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            respond(
+                &mut stream,
+                &[b"banana".to_vec(), b"cherry".to_vec()],
+                &PsiConfig::default(),
+            )
+            .unwrap()
+        });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let result = initiate(
+            &mut stream,
+            &[b"apple".to_vec(), b"banana".to_vec()],
+            &PsiConfig::default(),
+        )
+        .unwrap();
+        let remote_result = responder.join().unwrap();
+        assert_eq!(result.intersection_hashes, vec![ItemId::of(b"banana")]);
+        assert_eq!(
+            remote_result.intersection_hashes,
+            result.intersection_hashes
+        );
+    }
+
+    #[test]
+    fn test_rejected_points_abort_the_peer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let responder = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let config = PsiConfig::builder().max_remote_items(1).build().unwrap();
+            respond(&mut stream, &[b"banana".to_vec()], &config).unwrap_err()
+        });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let error = initiate(
+            &mut stream,
+            &[b"apple".to_vec(), b"banana".to_vec()],
+            &PsiConfig::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            FrameError::Protocol(PsiError::Rejected(ErrorReport::LimitExceeded {
+                limit: Limit::RemotePoints,
+                max: 1,
+                actual: 2
+            }))
+        ));
+        assert!(matches!(
+            responder.join().unwrap(),
+            FrameError::Protocol(PsiError::LimitExceeded { .. })
+        ));
+    }
+}
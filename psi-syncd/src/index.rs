@@ -0,0 +1,217 @@
+//! The local content index.
+
+use psi_protocol::ItemId;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Where the local items come from.
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// Every regular file below a directory; files with identical content
+    /// are one item.
+    Dir(PathBuf),
+    /// A text file with one item per non-empty line.
+    Manifest(PathBuf),
+}
+
+/// A file as of the last scan.
+#[derive(Debug, Clone)]
+struct FileEntry {
+    len: u64,
+    modified: Option<SystemTime>,
+    digest: [u8; 32],
+}
+
+/// Local items and the names they are known under.
+#[derive(Debug)]
+pub struct ContentIndex {
+    source: Source,
+    /// Directory mode: files by relative path, to skip rehashing
+    files: BTreeMap<String, FileEntry>,
+    /// Every item with the names it is known under (paths or lines)
+    items: HashMap<Vec<u8>, Vec<String>>,
+    /// Items by protocol id, to map results back
+    ids: HashMap<ItemId, Vec<u8>>,
+}
+
+impl ContentIndex {
+    /// Create an empty index; call [`refresh`](Self::refresh) to fill it.
+    pub fn new(source: Source) -> Self {
+        Self {
+            source,
+            files: BTreeMap::new(),
+            items: HashMap::new(),
+            ids: HashMap::new(),
+        }
+    }
+
+    /// Rescan the source, hashing only new or changed files.
+    pub fn refresh(&mut self) -> io::Result<()> {
+        let mut items: HashMap<Vec<u8>, Vec<String>> = HashMap::new();
+        match &self.source {
+            Source::Dir(root) => {
+                let mut files = BTreeMap::new();
+                scan_dir(root, root, &self.files, &mut files)?;
+                for (name, entry) in &files {
+                    items
+                        .entry(entry.digest.to_vec())
+                        .or_default()
+                        .push(name.clone());
+                }
+                self.files = files;
+            }
+            Source::Manifest(path) => {
+                for line in std::fs::read_to_string(path)?.lines() {
+                    let line = line.trim();
+                    if !line.is_empty() {
+                        items
+                            .entry(line.as_bytes().to_vec())
+                            .or_default()
+                            .push(line.to_string());
+                    }
+                }
+            }
+        }
+        self.ids = items
+            .keys()
+            .map(|item| (ItemId::of(item), item.clone()))
+            .collect();
+        self.items = items;
+        Ok(())
+    }
+
+    /// Number of distinct items.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// The distinct items, as protocol input.
+    pub fn items(&self) -> Vec<Vec<u8>> {
+        self.items.keys().cloned().collect()
+    }
+
+    /// Names of every item not in `shared`, sorted.
+    pub fn names_except(&self, shared: &[ItemId]) -> Vec<String> {
+        let shared: std::collections::HashSet<&Vec<u8>> =
+            shared.iter().filter_map(|id| self.ids.get(id)).collect();
+        let mut names: Vec<String> = self
+            .items
+            .iter()
+            .filter(|(item, _)| !shared.contains(item))
+            .flat_map(|(_, names)| names.iter().cloned())
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+/// Collect the regular files below `dir`, reusing digests from `previous`
+/// when size and modification time are unchanged.
+fn scan_dir(
+    root: &Path,
+    dir: &Path,
+    previous: &BTreeMap<String, FileEntry>,
+    files: &mut BTreeMap<String, FileEntry>,
+) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            scan_dir(root, &path, previous, files)?;
+            continue;
+        }
+        if !file_type.is_file() {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        let name = path
+            .strip_prefix(root)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .into_owned();
+        let (len, modified) = (metadata.len(), metadata.modified().ok());
+        let digest = match previous.get(&name) {
+            Some(known) if known.len == len && known.modified == modified && modified.is_some() => {
+                known.digest
+            }
+            _ => digest_file(&path)?,
+        };
+        files.insert(
+            name,
+            FileEntry {
+                len,
+                modified,
+                digest,
+            },
+        );
+    }
+    Ok(())
+}
+
+fn digest_file(path: &Path) -> io::Result<[u8; 32]> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A fresh directory under the system temp dir.
+    pub(crate) fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("psi-syncd-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_directory_items_follow_content() {
+        let dir = temp_dir("index");
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.txt"), b"same").unwrap();
+        std::fs::write(dir.join("sub").join("b.txt"), b"same").unwrap();
+        std::fs::write(dir.join("c.txt"), b"other").unwrap();
+
+        let mut index = ContentIndex::new(Source::Dir(dir.clone()));
+        index.refresh().unwrap();
+        assert_eq!(index.len(), 2);
+
+        let other = ItemId::of(&Sha256::digest(b"other"));
+        let mut expected = vec![
+            "a.txt".to_string(),
+            format!("sub{}b.txt", std::path::MAIN_SEPARATOR),
+        ];
+        expected.sort();
+        assert_eq!(index.names_except(&[other]), expected);
+
+        std::fs::write(dir.join("c.txt"), b"changed content").unwrap();
+        std::fs::remove_file(dir.join("a.txt")).unwrap();
+        index.refresh().unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.names_except(&[other]).len(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_manifest_items_are_lines() {
+        let dir = temp_dir("manifest");
+        let manifest = dir.join("items.txt");
+        std::fs::write(&manifest, "apple\n\nbanana\napple\n").unwrap();
+
+        let mut index = ContentIndex::new(Source::Manifest(manifest));
+        index.refresh().unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(
+            index.names_except(&[ItemId::of(b"apple")]),
+            vec!["banana".to_string()]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
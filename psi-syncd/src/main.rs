@@ -0,0 +1,50 @@
+//! `psi-syncd`: keep track of which local content configured peers share.
+//!
+//! The daemon indexes a directory (one item per distinct file content) or a
+//! manifest (one item per line), then periodically runs PSI against every
+//! configured peer over TCP and reconciles the result against the index:
+//! which local files the peer also has, and which it lacks. Peers run the
+//! daemon too and answer on their `--listen` address. The latest result per
+//! peer is served as JSON on a local Unix socket.
+//!
+//! The directory is rescanned before every round; only files whose size or
+//! modification time changed are hashed again.
+//!
+//! Run with:
+//! ```bash
+//! cargo run --bin psi-syncd -- --dir ./shared --listen 0.0.0.0:7878 \
+//!     --peer backup.internal:7878 --interval 300 --socket /tmp/psi-syncd.sock
+//! # then
+//! socat - UNIX-CONNECT:/tmp/psi-syncd.sock
+//! ```
+//!
+//! Recurring setups are better declared in a TOML file, with per-peer
+//! schedules (see the `config` module for the format):
+//! ```bash
+//! cargo run --bin psi-syncd -- --config /etc/psi-syncd.toml
+//! ```
+//!
+//! Connections are plain TCP; run the daemon behind TLS (e.g. a tunnel or a
+//! terminating proxy) anywhere the network is not trusted.
+
+mod config;
+mod daemon;
+mod exchange;
+mod index;
+mod status;
+
+use config::DaemonConfig;
+
+fn main() {
+    let config = match DaemonConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("psi-syncd: {}\n\n{}", error, config::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(error) = daemon::run(config) {
+        eprintln!("psi-syncd: {}", error);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,120 @@
+//! Latest sync result per peer, served as JSON.
+
+use crate::index::ContentIndex;
+use psi_protocol::PsiResult;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Which side opened the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Outgoing,
+    Incoming,
+}
+
+/// Outcome of the last session with one peer.
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerStatus {
+    /// Seconds since the Unix epoch
+    pub last_sync: u64,
+    pub direction: Direction,
+    /// Number of items both sides have
+    pub shared: usize,
+    /// Local names of the items the peer lacks
+    pub missing_on_peer: Vec<String>,
+    /// Set when the session failed; the other fields are then empty
+    pub error: Option<String>,
+}
+
+impl PeerStatus {
+    /// Reconcile a session's result against the local index.
+    pub fn from_result(direction: Direction, result: &PsiResult, index: &ContentIndex) -> Self {
+        Self {
+            last_sync: now(),
+            direction,
+            shared: result.intersection_hashes.len(),
+            missing_on_peer: index.names_except(&result.intersection_hashes),
+            error: None,
+        }
+    }
+
+    /// Record a failed session.
+    pub fn failed(direction: Direction, error: impl std::fmt::Display) -> Self {
+        Self {
+            last_sync: now(),
+            direction,
+            shared: 0,
+            missing_on_peer: Vec::new(),
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// Everything the status socket reports.
+#[derive(Debug, Default, Serialize)]
+pub struct Status {
+    /// Distinct local items at the last scan
+    pub local_items: usize,
+    /// Seconds since the Unix epoch
+    pub last_scan: u64,
+    /// Set when the last scan failed
+    pub scan_error: Option<String>,
+    /// Keyed by peer address
+    pub peers: BTreeMap<String, PeerStatus>,
+}
+
+impl Status {
+    /// Record a scan of the index.
+    pub fn scanned(&mut self, index: &ContentIndex, error: Option<String>) {
+        self.local_items = index.len();
+        self.last_scan = now();
+        self.scan_error = error;
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("status serializes")
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::tests::temp_dir;
+    use crate::index::Source;
+    use psi_protocol::ItemId;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_status_reports_missing_items() {
+        let dir = temp_dir("status");
+        let manifest = dir.join("items.txt");
+        std::fs::write(&manifest, "apple\nbanana\n").unwrap();
+        let mut index = ContentIndex::new(Source::Manifest(manifest));
+        index.refresh().unwrap();
+
+        let result = PsiResult::new(vec![ItemId::of(b"apple")], HashMap::new());
+        let mut status = Status::default();
+        status.scanned(&index, None);
+        status.peers.insert(
+            "peer:7878".to_string(),
+            PeerStatus::from_result(Direction::Outgoing, &result, &index),
+        );
+        let json: serde_json::Value = serde_json::from_str(&status.to_json()).unwrap();
+        assert_eq!(json["local_items"], 2);
+        assert_eq!(json["peers"]["peer:7878"]["shared"], 1);
+        assert_eq!(json["peers"]["peer:7878"]["direction"], "outgoing");
+        assert_eq!(
+            json["peers"]["peer:7878"]["missing_on_peer"],
+            serde_json::json!(["banana"])
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}